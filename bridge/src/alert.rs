@@ -0,0 +1,391 @@
+//! Alerts: conditions on the points a bridge receives that notify someone when they start and
+//! stop holding. Each alert watches the points of one source (or one of its points) and fires
+//! when a value goes above or below a threshold, changes faster than a rate, or stops arriving.
+//! An alert fires once per point until its condition clears, when a `resolved` event follows,
+//! unless `repeat` asks for a reminder while it holds:
+//!
+//! ```yaml
+//! alerts:
+//!   boiler-hot:
+//!     source: plc
+//!     point: temperature     # every point of the source when left out
+//!     above: 90              # and/or below:
+//!     rate: 2                # units per second, up or down
+//!     stale: 5m              # no value this long, or one marked stale
+//!     repeat: 1h             # once by default
+//!     notify:
+//!       - webhook: http://ops.local/hooks/edge
+//!       - sink: site         # a nats or mqtt sink
+//!         subject: alerts.{alert}.{point}
+//!       - exec: ./page.sh    # the event on stdin, as --exec hooks get it, run from the
+//!                            # bridge's directory
+//! ```
+//!
+//! Events are JSON objects with `alert`, `state` (`firing` or `resolved`), `source`, `point`,
+//! `reason`, the last `value`, `unit` and `quality` when there is one, and `time`. Conditions
+//! see points as they arrive, before routes convert or transform them.
+
+use edge_core::envelope::Envelope;
+use edge_core::exec::Exec;
+use edge_core::output::{self, Record};
+use edge_core::point::{Point, Quality};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use crate::sink::Sink;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Alert {
+    pub name: String,
+    pub source: String,
+    pub point: Option<String>,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+    // Units per second, either way.
+    pub rate: Option<f64>,
+    pub stale: Option<Duration>,
+    pub repeat: Option<Duration>,
+    pub notify: Vec<Notify>,
+}
+
+impl Alert {
+    pub fn matches(&self, source: &str, point: &str) -> bool {
+        self.source == source && self.point.as_deref().is_none_or(|p| p == point)
+    }
+
+    /// What holds for `point`, checked against the previous value for rates.
+    fn reason(&self, point: &Point, last: Option<(f64, Instant)>, now: Instant) -> Option<String> {
+        if self.stale.is_some() && point.quality == Quality::Stale {
+            return Some("marked stale".to_string());
+        }
+        let value = point.value.as_number()?;
+        if let Some(above) = self.above.filter(|above| value > *above) {
+            return Some(format!("above {above}"));
+        }
+        if let Some(below) = self.below.filter(|below| value < *below) {
+            return Some(format!("below {below}"));
+        }
+        if let (Some(rate), Some((previous, then))) = (self.rate, last) {
+            let seconds = now.duration_since(then).as_secs_f64();
+            let changing = (value - previous) / seconds;
+            if seconds > 0.0 && changing.abs() > rate {
+                return Some(format!("changing {changing:.3}/s, faster than {rate}/s"));
+            }
+        }
+        None
+    }
+}
+
+pub enum Notify {
+    Webhook(Webhook),
+    // A message on a NATS or MQTT sink, to the subject or topic with `{alert}`, `{source}`
+    // and `{point}` filled in.
+    Sink { sink: usize, name: String },
+    Exec(String),
+}
+
+/// An `http://` URL to POST events to.
+#[derive(Clone)]
+pub struct Webhook {
+    pub url: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Webhook {
+    pub fn parse(url: &str) -> Result<Webhook, String> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if url.starts_with("https://") => {
+                return Err("https isn't supported; use http:// or a sink".to_string())
+            }
+            None => return Err(format!("expected an http:// URL, found {url}")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse().map_err(|_| format!("invalid port in {url}"))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("no host in {url}"));
+        }
+        Ok(Webhook {
+            url: url.to_string(),
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    async fn post(&self, body: &str) -> Result<(), String> {
+        let request = async {
+            let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            let request = format!(
+                "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                self.path,
+                self.host,
+                self.port,
+                body.len()
+            );
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = Vec::new();
+            (&mut stream)
+                .take(64 * 1024)
+                .read_to_end(&mut response)
+                .await?;
+            Ok::<_, std::io::Error>(response)
+        };
+        let response = tokio::time::timeout(WEBHOOK_TIMEOUT, request)
+            .await
+            .map_err(|_| "timed out".to_string())?
+            .map_err(|err| err.to_string())?;
+        let response = String::from_utf8_lossy(&response);
+        match response.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format!(
+                "answered {}",
+                response.lines().next().unwrap_or("nothing")
+            )),
+        }
+    }
+}
+
+/// A point an alert watches.
+struct Watched {
+    point: Option<Point>,
+    // The last number and when it came, for rates; and when anything last came, for staleness.
+    last: Option<(f64, Instant)>,
+    seen: Instant,
+    // Why the alert is firing for the point, and when it last said so.
+    firing: Option<(String, Instant)>,
+}
+
+pub struct Event {
+    alert: usize,
+    point: String,
+    firing: bool,
+    reason: String,
+    last: Option<Point>,
+}
+
+/// The state of every alert, fed each point the bridge receives.
+pub struct Alerts {
+    alerts: Vec<Alert>,
+    watched: HashMap<(usize, String), Watched>,
+    execs: HashMap<String, Exec>,
+}
+
+impl Alerts {
+    pub fn new(alerts: Vec<Alert>) -> Alerts {
+        let now = Instant::now();
+        let mut watched = HashMap::new();
+        let mut execs = HashMap::new();
+        for (index, alert) in alerts.iter().enumerate() {
+            // A named point is watched from the start, so one that never comes goes stale.
+            if let Some(point) = &alert.point {
+                watched.insert(
+                    (index, point.clone()),
+                    Watched {
+                        point: None,
+                        last: None,
+                        seen: now,
+                        firing: None,
+                    },
+                );
+            }
+            for notify in &alert.notify {
+                if let Notify::Exec(command) = notify {
+                    execs
+                        .entry(command.clone())
+                        .or_insert_with(|| Exec::start(command));
+                }
+            }
+        }
+        Alerts {
+            alerts,
+            watched,
+            execs,
+        }
+    }
+
+    /// The events `point` starts or ends.
+    pub fn check(&mut self, point: &Point) -> Vec<Event> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        for (index, alert) in self.alerts.iter().enumerate() {
+            if !alert.matches(&point.source, &point.name) {
+                continue;
+            }
+            let watched = self
+                .watched
+                .entry((index, point.name.clone()))
+                .or_insert(Watched {
+                    point: None,
+                    last: None,
+                    seen: now,
+                    firing: None,
+                });
+            let reason = alert.reason(point, watched.last, now);
+            watched.point = Some(point.clone());
+            watched.seen = now;
+            if let Some(value) = point.value.as_number() {
+                watched.last = Some((value, now));
+            }
+            events.extend(transition(index, alert, &point.name, watched, reason, now));
+        }
+        events
+    }
+
+    /// Points that have gone quiet, and reminders for alerts still firing.
+    pub fn tick(&mut self) -> Vec<Event> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        for ((index, name), watched) in &mut self.watched {
+            let alert = &self.alerts[*index];
+            let reason = match alert.stale {
+                Some(stale) if now.duration_since(watched.seen) >= stale => Some(format!(
+                    "no value for {}",
+                    humantime::format_duration(Duration::from_secs(stale.as_secs().max(1)))
+                )),
+                _ => watched.firing.as_ref().map(|(reason, _)| reason.clone()),
+            };
+            events.extend(transition(*index, alert, name, watched, reason, now));
+        }
+        events
+    }
+
+    /// Sends `event` everywhere its alert says. Webhooks and commands run in the
+    /// background; sink messages are published before this returns.
+    pub async fn notify(&self, event: &Event, sinks: &mut [Sink]) {
+        let alert = &self.alerts[event.alert];
+        match event.firing {
+            true => log::warn!(
+                "Alert {} firing for {}.{}: {}",
+                alert.name,
+                alert.source,
+                event.point,
+                event.reason
+            ),
+            false => log::info!(
+                "Alert {} resolved for {}.{}",
+                alert.name,
+                alert.source,
+                event.point
+            ),
+        }
+        let record = self.record(event);
+        let body = output::json(&output::Value::Record(record.clone()));
+        for notify in &alert.notify {
+            match notify {
+                Notify::Webhook(webhook) => {
+                    let (webhook, body) = (webhook.clone(), body.clone());
+                    let name = alert.name.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = webhook.post(&body).await {
+                            log::warn!("Alert {name}: webhook {} failed: {err}", webhook.url);
+                        }
+                    });
+                }
+                Notify::Sink { sink, name } => {
+                    let sink = &mut sinks[*sink];
+                    let name = name
+                        .replace("{alert}", &alert.name)
+                        .replace("{source}", &alert.source)
+                        .replace("{point}", &event.point);
+                    if let Err(err) = sink.publish(&name, body.as_bytes()).await {
+                        log::warn!(
+                            "Alert {}: unable to publish to sink {}: {err}",
+                            alert.name,
+                            sink.name
+                        );
+                    }
+                }
+                Notify::Exec(command) => {
+                    let envelope = Envelope::new(&alert.source, &event.point, record.clone());
+                    self.execs[command].run(&envelope);
+                }
+            }
+        }
+    }
+
+    fn record(&self, event: &Event) -> Record {
+        let alert = &self.alerts[event.alert];
+        let mut record = Record::new()
+            .field("alert", alert.name.as_str())
+            .field("state", if event.firing { "firing" } else { "resolved" })
+            .field("source", alert.source.as_str())
+            .field("point", event.point.as_str())
+            .field("reason", event.reason.as_str());
+        if let Some(point) = &event.last {
+            record.push("value", &point.value);
+            if let Some(unit) = &point.unit {
+                record.push("unit", unit.as_str());
+            }
+            record.push("quality", point.quality.name());
+        }
+        record.push(
+            "time",
+            humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        );
+        record
+    }
+
+    /// Waits for the commands still queued.
+    pub async fn finish(self) {
+        for (_, exec) in self.execs {
+            exec.finish().await;
+        }
+    }
+}
+
+/// The event, if any, for `reason` now holding (or not) for a watched point.
+fn transition(
+    index: usize,
+    alert: &Alert,
+    point: &str,
+    watched: &mut Watched,
+    reason: Option<String>,
+    now: Instant,
+) -> Option<Event> {
+    let last = watched.point.clone();
+    let event = |firing, reason: String| Event {
+        alert: index,
+        point: point.to_string(),
+        firing,
+        reason,
+        last,
+    };
+    match (watched.firing.take(), reason) {
+        (None, Some(reason)) => {
+            watched.firing = Some((reason.clone(), now));
+            Some(event(true, reason))
+        }
+        (Some((previous, _)), None) => Some(event(false, previous)),
+        (Some((_, since)), Some(reason)) => match alert.repeat {
+            Some(repeat) if now.duration_since(since) >= repeat => {
+                watched.firing = Some((reason.clone(), now));
+                Some(event(true, reason))
+            }
+            _ => {
+                watched.firing = Some((reason, since));
+                None
+            }
+        },
+        (None, None) => None,
+    }
+}
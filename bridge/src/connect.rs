@@ -0,0 +1,140 @@
+//! Connections shared by sources and sinks.
+
+use async_nats::{Client, ConnectOptions};
+use edge_core::auth::Auth;
+use edge_core::buffer::Limits;
+use edge_core::cache::Cache;
+use edge_core::exit::{self, Code};
+use edge_core::mqtt::MqttOptions;
+use edge_core::pool::{Pool, Pooled};
+use edge_core::{stats, telemetry};
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio_modbus::client::Context;
+use tokio_modbus::slave::{Slave, SlaveContext};
+
+use crate::mapping::{ModbusConnection, MqttConnection, NatsConnection, RegisterKind};
+
+/// Raw readings by device, unit, kind, first register and count; bits are 0 or 1.
+pub type Readings = Cache<(SocketAddr, u8, RegisterKind, u16, u16), Vec<u16>>;
+
+/// A Modbus connection from the bridge's pool: sources and sinks on one device take turns on
+/// a single connection. Release it after a request that leaves it usable, exception answers
+/// included; dropping it closes it.
+pub type Modbus = Pooled<SocketAddr, Context>;
+
+/// A connection to the address in use, failing over to the secondary when it can't be reached;
+/// [`ModbusConnection::served_by`] says which one it is.
+pub async fn modbus(connection: &ModbusConnection) -> std::io::Result<Modbus> {
+    let Some(failover) = &connection.failover else {
+        return modbus_at(connection, &connection.address).await;
+    };
+    let endpoint = failover.active();
+    let (endpoint, result) = match modbus_at(connection, &endpoint).await {
+        Err(err) => match failover.failed(&endpoint, &err) {
+            Some(other) => {
+                let result = modbus_at(connection, &other).await;
+                (other, result)
+            }
+            None => (endpoint, Err(err)),
+        },
+        result => (endpoint, result),
+    };
+    match &result {
+        Ok(_) => failover.succeeded(&endpoint),
+        Err(err) => {
+            failover.failed(&endpoint, err);
+        }
+    }
+    result
+}
+
+async fn modbus_at(connection: &ModbusConnection, address: &str) -> std::io::Result<Modbus> {
+    static POOL: OnceLock<Pool<SocketAddr, Context>> = OnceLock::new();
+    let addr = edge_core::net::resolve(address, 502)?;
+    let mut context = POOL
+        .get_or_init(|| Pool::new(1))
+        .get(addr, || async {
+            let span = telemetry::span("modbus.connect")
+                .attribute("server.address", address)
+                .attribute("modbus.unit_id", connection.unit_id);
+            let result = tokio_modbus::client::tcp::connect(addr).await;
+            telemetry::finish(span, "bridge.connects", &[("protocol", "modbus")], &result);
+            if result.is_ok() {
+                stats::connected(&addr.to_string());
+            }
+            result
+        })
+        .await?;
+    context.set_slave(Slave(connection.unit_id));
+    Ok(context)
+}
+
+/// Readings sources on one device share when their `cache_ttl` allows.
+pub fn readings() -> &'static Readings {
+    static READINGS: OnceLock<Readings> = OnceLock::new();
+    READINGS.get_or_init(Readings::new)
+}
+
+/// Drops the readings a write of `count` registers or coils from `register` changes.
+pub fn written(context: &Modbus, unit_id: u8, kind: RegisterKind, register: u16, count: u16) {
+    let written = register..register.saturating_add(count);
+    readings().invalidate(|(device, unit, read_kind, first, read_count)| {
+        device == context.key()
+            && *unit == unit_id
+            && *read_kind == kind
+            && *first < written.end
+            && written.start < first.saturating_add(*read_count)
+    });
+}
+
+pub async fn nats(connection: &NatsConnection) -> Result<Client, exit::Error> {
+    nats_for(connection, Limits::default()).await
+}
+
+/// Connects for subscriptions that hold up to `limits.queue` messages each.
+pub async fn nats_for(connection: &NatsConnection, limits: Limits) -> Result<Client, exit::Error> {
+    // Credentials were checked when the mapping was loaded.
+    let options = match connection.credentials.resolve() {
+        Ok(Auth::UserPassword { username, password }) => {
+            ConnectOptions::with_user_and_password(username, password)
+        }
+        Ok(Auth::Token(token)) => ConnectOptions::with_token(token),
+        Ok(Auth::Creds(creds)) => ConnectOptions::with_credentials(&creds)
+            .map_err(|err| exit::Error::new(Code::Usage, format!("invalid creds file: {err}")))?,
+        Ok(Auth::NKey(seed)) => ConnectOptions::with_nkey(seed),
+        Ok(Auth::None) | Err(_) => ConnectOptions::new(),
+    };
+    let options = options.subscription_capacity(limits.queue);
+    let span = telemetry::span("nats.connect");
+    let result = options.connect(connection.address.as_str()).await;
+    telemetry::finish(span, "bridge.connects", &[("protocol", "nats")], &result);
+    result.map_err(|err| {
+        // Server -ERR lines come back as plain I/O errors; spot auth failures by text.
+        let code = if err.to_string().contains("authorization violation") {
+            Code::Auth
+        } else {
+            Code::from_io(&err)
+        };
+        exit::Error::new(code, err)
+    })
+}
+
+/// Options for a client named after the source or sink it serves; the port defaults to 1883.
+pub fn mqtt_options(name: &str, connection: &MqttConnection) -> MqttOptions {
+    let address = match connection.address.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => connection.address.clone(),
+        _ => format!("{}:1883", connection.address),
+    };
+    MqttOptions {
+        address,
+        client_id: connection
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("edge-bridge-{name}-{}", std::process::id())),
+        username: connection.username.clone(),
+        password: connection.password.clone(),
+        keep_alive: Duration::from_secs(60),
+    }
+}
@@ -0,0 +1,573 @@
+mod alert;
+mod connect;
+mod mapping;
+mod sink;
+mod source;
+mod transform;
+
+use clap::{Parser, Subcommand};
+use edge_core::buffer::{self, LimitArgs, Limits};
+use edge_core::completions::{self, Shell};
+use edge_core::config;
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::metrics::Metrics;
+use edge_core::mqtt::MqttClient;
+use edge_core::output::{Output, OutputArgs, Record};
+use edge_core::reload::Reload;
+use edge_core::shutdown::{self, Summary};
+use edge_core::stats::{Reporter, StatsArgs};
+use edge_core::telemetry;
+use edge_core::tsdb::Tsdb;
+use edge_core::OrExit;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alert::Alerts;
+use mapping::{Mapping, SinkKind, SourceKind, Target};
+
+pub use mapping::MappingError;
+use sink::Sink;
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    #[clap(flatten)]
+    output: OutputArgs,
+    /// Serve Prometheus metrics for every point and sink write on this address, e.g.
+    /// 0.0.0.0:9100.
+    #[clap(long, global = true, value_parser)]
+    metrics_listen: Option<SocketAddr>,
+    /// Send traces and metrics about connections, polls and sink writes to this OTLP/HTTP
+    /// collector, e.g. http://localhost:4318.
+    #[clap(long, global = true, env = "OTEL_EXPORTER_OTLP_ENDPOINT", action)]
+    otlp_endpoint: Option<String>,
+
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Move points from sources to sinks as described by a YAML mapping file, until stopped.
+    /// Changes to the file, or a SIGHUP, are applied as it runs: only the sources and sinks
+    /// whose entries changed are restarted.
+    Run {
+        #[clap(value_parser, env = "EDGE_BRIDGE_MAPPING")]
+        mapping: PathBuf,
+        #[clap(flatten)]
+        limits: LimitArgs,
+        #[clap(flatten)]
+        stats: StatsArgs,
+    },
+
+    /// Validate a mapping file and list its routes without connecting to anything.
+    Check {
+        #[clap(value_parser, env = "EDGE_BRIDGE_MAPPING")]
+        mapping: PathBuf,
+    },
+
+    /// Connect once to every Modbus, NATS, MQTT and database source and sink in a mapping and
+    /// exit 0 if they all answer, for container and systemd health probes.
+    Healthcheck {
+        #[clap(value_parser, env = "EDGE_BRIDGE_MAPPING")]
+        mapping: PathBuf,
+        /// Give up after this long, e.g. 2s; 5s by default.
+        #[clap(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+pub async fn run(cli: Args) {
+    let out = cli.output.load();
+    match cli.command {
+        Subcommands::Completions { shell } => completions::print::<Args>(shell, "bridge"),
+        Subcommands::GenMan { directory } => man::generate::<Args>("bridge", directory.as_deref()),
+        Subcommands::Check { mapping } => {
+            let mapping = mapping::load(&mapping).or_exit_with(Code::Usage, "Invalid mapping");
+            check(&mapping, &out);
+        }
+        Subcommands::Healthcheck { mapping, timeout } => {
+            let target = mapping.display().to_string();
+            let mapping = mapping::load(&mapping).or_exit_with(Code::Usage, "Invalid mapping");
+            health::check(&out, &target, timeout, healthcheck(mapping)).await;
+        }
+        Subcommands::Run {
+            mapping,
+            limits,
+            stats,
+        } => {
+            let path = mapping;
+            let mapping = mapping::load(&path).or_exit_with(Code::Usage, "Invalid mapping");
+            let limits = config::limits()
+                .or_exit_with(Code::Usage, "Unable to read the config")
+                .with_args(&limits);
+            let metrics = Metrics::new();
+            if let Some(listen) = cli.metrics_listen {
+                metrics
+                    .serve(listen)
+                    .await
+                    .or_exit(&format!("Unable to serve metrics on {listen}"));
+            }
+            if let Some(endpoint) = &cli.otlp_endpoint {
+                telemetry::init(endpoint, "bridge")
+                    .or_exit_with(Code::Usage, "Invalid OTLP endpoint");
+            }
+            bridge(&path, mapping, &out, &metrics, limits, stats.stats).await;
+            telemetry::shutdown();
+        }
+    }
+}
+
+/// Loads a mapping the way `run` would, without connecting to anything, for `edge config
+/// check`.
+pub fn validate(mapping: &Path) -> Result<(), MappingError> {
+    mapping::load(mapping).map(|_| ())
+}
+
+fn check(mapping: &Mapping, out: &Output) {
+    let records: Vec<Record> = mapping
+        .routes
+        .iter()
+        .map(|route| {
+            Record::new()
+                .field("source", route.source.as_str())
+                .field("point", route.point.as_deref())
+                .field("sink", mapping.sinks[route.sink].name.as_str())
+                .field("target", target_name(&route.target))
+                .field("unit", route.unit.as_deref())
+                .field(
+                    "transform",
+                    Some(route.transform.describe()).filter(|t| !t.is_empty()),
+                )
+                .field(
+                    "script",
+                    route
+                        .hook
+                        .as_ref()
+                        .map(|hook| hook.path.display().to_string()),
+                )
+        })
+        .collect();
+    out.records(&records, || {
+        for source in &mapping.sources {
+            let detail = match &source.kind {
+                SourceKind::Modbus { points, .. } => format!("{} points", points.len()),
+                SourceKind::Nats { subjects, .. } => subjects.join(", "),
+                SourceKind::Mqtt { topics, .. } => topics.join(", "),
+            };
+            println!("source {} ({}): {detail}", source.name, source.kind.name());
+        }
+        for sink in &mapping.sinks {
+            println!("sink {} ({})", sink.name, sink.kind.name());
+        }
+        for route in &mapping.routes {
+            let from = match &route.point {
+                Some(point) => format!("{}.{point}", route.source),
+                None => format!("{}.*", route.source),
+            };
+            let mut line = format!("route {from} -> {}", mapping.sinks[route.sink].name);
+            if let Some(target) = target_name(&route.target) {
+                line.push_str(&format!(" {target}"));
+            }
+            if let Some(hook) = &route.hook {
+                line.push_str(&format!(" script {}", hook.path.display()));
+            }
+            if let Some(unit) = &route.unit {
+                line.push_str(&format!(" in {unit}"));
+            }
+            if !route.transform.is_empty() {
+                line.push_str(&format!(" [{}]", route.transform.describe()));
+            }
+            println!("{line}");
+        }
+    });
+}
+
+/// Connects to each source and sink in turn, stopping at the first that doesn't answer.
+async fn healthcheck(mapping: Mapping) -> Result<String, exit::Error> {
+    fn failed(what: &str, name: &str, err: &(dyn std::error::Error + 'static)) -> exit::Error {
+        exit::Error::new(Code::of(err), format!("{what} {name}: {err}"))
+    }
+    let mut connections = 0;
+    for source in &mapping.sources {
+        match &source.kind {
+            SourceKind::Modbus { connection, .. } => {
+                connect::modbus(connection)
+                    .await
+                    .map_err(|err| failed("source", &source.name, &err))?;
+            }
+            SourceKind::Nats { connection, .. } => {
+                connect::nats(connection)
+                    .await
+                    .map_err(|err| failed("source", &source.name, &err))?;
+            }
+            SourceKind::Mqtt { connection, .. } => {
+                MqttClient::new(connect::mqtt_options(&source.name, connection))
+                    .connect_now()
+                    .await
+                    .map_err(|err| failed("source", &source.name, &*err))?;
+            }
+        }
+        connections += 1;
+    }
+    for sink in mapping.sinks {
+        match sink.kind {
+            SinkKind::Modbus(connection) => {
+                connect::modbus(&connection)
+                    .await
+                    .map_err(|err| failed("sink", &sink.name, &err))?;
+            }
+            SinkKind::Nats(connection) => {
+                connect::nats(&connection)
+                    .await
+                    .map_err(|err| failed("sink", &sink.name, &err))?;
+            }
+            SinkKind::Mqtt(connection) => {
+                MqttClient::new(connect::mqtt_options(&sink.name, &connection))
+                    .connect_now()
+                    .await
+                    .map_err(|err| failed("sink", &sink.name, &*err))?;
+            }
+            SinkKind::Tsdb(target) => {
+                Tsdb::open(target)
+                    .await
+                    .map_err(|err| failed("sink", &sink.name, &err))?
+                    .close()
+                    .await;
+            }
+            SinkKind::Stdout
+            | SinkKind::Websocket(_)
+            | SinkKind::Historian(_)
+            | SinkKind::File(_) => continue,
+        }
+        connections += 1;
+    }
+    Ok(format!("reached {connections} sources and sinks"))
+}
+
+fn target_name(target: &Target) -> Option<String> {
+    match target {
+        Target::Stdout | Target::Websocket | Target::Historian | Target::File | Target::Tsdb => {
+            None
+        }
+        Target::Name(name) => Some(name.clone()),
+        Target::Register { register, .. } => Some(format!("register {register}")),
+    }
+}
+
+async fn bridge(
+    path: &Path,
+    mapping: Mapping,
+    out: &Output,
+    metrics: &Metrics,
+    limits: Limits,
+    stats: Option<Duration>,
+) {
+    let Mapping {
+        sources,
+        sinks: defined,
+        mut routes,
+        alerts,
+    } = mapping;
+    let mut sinks = Vec::new();
+    for sink in defined {
+        let name = sink.name.clone();
+        match Sink::open(sink).await {
+            Ok(sink) => sinks.push(sink),
+            Err(err) => exit::fatal_error(format!("Unable to open sink {name}"), &err),
+        }
+    }
+
+    let (sender, mut points) = buffer::queue("bridge", limits);
+    let source_count = sources.len();
+    let mut running = source::Running::default();
+    running.update(sources, &sender);
+    log::info!(
+        "Bridging {source_count} sources to {} sinks over {} routes",
+        sinks.len(),
+        routes.len()
+    );
+
+    // Last value and quality sent per route and point, for deadbands.
+    let mut last_sent = HashMap::new();
+    let mut alerts = Alerts::new(alerts);
+    let mut reload = Reload::watch(path);
+    let mut ping = tokio::time::interval(Duration::from_secs(30));
+    let mut quiet = tokio::time::interval(Duration::from_secs(1));
+    let summary = Summary::new(&["points", "writes"]);
+    let _stats = Reporter::start(stats, &summary, vec![points.depth()]);
+    shutdown::listen();
+    loop {
+        let point = tokio::select! {
+            point = points.recv() => match point {
+                Some(point) => point,
+                None => break,
+            },
+            _ = shutdown::requested() => break,
+            _ = reload.changed() => {
+                let mapping = match mapping::load(path) {
+                    Ok(mapping) => mapping,
+                    Err(err) => {
+                        log::warn!("Keeping the mapping the bridge runs; the new one is invalid: {err}");
+                        continue;
+                    }
+                };
+                let Mapping {
+                    sources,
+                    sinks: defined,
+                    routes: new_routes,
+                    alerts: new_alerts,
+                } = mapping;
+                let reopened = match reopen(&mut sinks, defined).await {
+                    Ok(reopened) => reopened,
+                    Err(err) => {
+                        log::warn!("Keeping the mapping the bridge runs: {err}");
+                        continue;
+                    }
+                };
+                let restarted = running.update(sources, &sender);
+                routes = new_routes;
+                last_sent.clear();
+                std::mem::replace(&mut alerts, Alerts::new(new_alerts))
+                    .finish()
+                    .await;
+                log::info!(
+                    "Reloaded the mapping: {} sources started, {} restarted and {} stopped; \
+                     {} sinks opened, {} reopened and {} closed; {} routes",
+                    restarted.started,
+                    restarted.restarted,
+                    restarted.stopped,
+                    reopened.opened,
+                    reopened.reopened,
+                    reopened.closed,
+                    routes.len()
+                );
+                continue;
+            }
+            _ = ping.tick() => {
+                for sink in &mut sinks {
+                    sink.ping().await;
+                }
+                continue;
+            }
+            _ = quiet.tick() => {
+                // The bridge holds a sender for sources a reload starts, so the queue
+                // doesn't end when they all do.
+                if running.finished() {
+                    break;
+                }
+                for event in alerts.tick() {
+                    alerts.notify(&event, &mut sinks).await;
+                    summary.count("alerts");
+                }
+                continue;
+            }
+        };
+
+        telemetry::count("bridge.points", &[("source", &point.source)]);
+        summary.count("points");
+        if let Some(value) = point.value.as_number() {
+            metrics.gauge(
+                "bridge_point_value",
+                "Last value received per source point, before routing.",
+                &[("source", &point.source), ("point", &point.name)],
+                value,
+            );
+        }
+        for event in alerts.check(&point) {
+            alerts.notify(&event, &mut sinks).await;
+            summary.count("alerts");
+        }
+
+        for (index, route) in routes.iter().enumerate() {
+            if !route.matches(&point.source, &point.name) {
+                continue;
+            }
+            let hooked;
+            let point = match &route.hook {
+                Some(hook) => match hook.script.apply(point.to_record()) {
+                    Ok(Some(record)) => {
+                        hooked = point.changed(record);
+                        &hooked
+                    }
+                    Ok(None) => continue,
+                    Err(err) => {
+                        log::warn!(
+                            "Script {} failed on {}.{}: {err}",
+                            hook.path.display(),
+                            point.source,
+                            point.name
+                        );
+                        continue;
+                    }
+                },
+                None => &point,
+            };
+            let converted;
+            let point = match &route.unit {
+                Some(unit) => match point.converted(unit) {
+                    Ok(point) => {
+                        converted = point;
+                        &converted
+                    }
+                    Err(err) => {
+                        log::warn!(
+                            "Unable to convert {}.{} to {unit}: {err}",
+                            point.source,
+                            point.name
+                        );
+                        continue;
+                    }
+                },
+                None => point,
+            };
+            let value = route.transform.apply(&point.value);
+            let key = (index, point.name.clone());
+            // A change of quality goes through whatever the value did.
+            if let Some((last, quality)) = last_sent.get(&key) {
+                if *quality == point.quality && !route.transform.passes_deadband(&value, Some(last))
+                {
+                    continue;
+                }
+            }
+            let sink = &mut sinks[route.sink];
+            let span = telemetry::span("bridge.sink.write")
+                .attribute("bridge.sink", sink.name.as_str())
+                .attribute("bridge.source", point.source.as_str())
+                .attribute("bridge.point", point.name.as_str());
+            let result = sink.write(&route.target, point, &value, out).await;
+            telemetry::finish(span, "bridge.writes", &[("sink", &sink.name)], &result);
+            metrics.increment(
+                "bridge_writes_total",
+                "Sink writes by result.",
+                &[
+                    ("sink", &sink.name),
+                    ("result", if result.is_ok() { "ok" } else { "error" }),
+                ],
+            );
+            match result {
+                Ok(()) => {
+                    summary.count("writes");
+                    last_sent.insert(key, (value, point.quality));
+                }
+                Err(err) => {
+                    summary.error();
+                    log::warn!(
+                        "Unable to write {}.{} to sink {}: {err}",
+                        point.source,
+                        point.name,
+                        sink.name
+                    )
+                }
+            }
+        }
+    }
+    if !shutdown::is_requested() {
+        log::info!("All sources stopped");
+    }
+    alerts.finish().await;
+    for sink in sinks {
+        sink.close().await;
+    }
+    if points.dropped() > 0 {
+        summary.add("dropped", points.dropped());
+    }
+    summary.print();
+}
+
+/// What [`reopen`] did.
+#[derive(Default)]
+struct Reopened {
+    opened: usize,
+    reopened: usize,
+    closed: usize,
+}
+
+/// Replaces `sinks` with those of a reloaded mapping, in its order: unchanged sinks carry on,
+/// changed ones are closed and opened again and those gone are closed. Added sinks are opened
+/// first, so one that can't be opened leaves `sinks` as they were; a changed sink that can't be
+/// opened again stops the bridge, as it would at startup.
+async fn reopen(
+    sinks: &mut Vec<Sink>,
+    defined: Vec<mapping::Sink>,
+) -> Result<Reopened, exit::Error> {
+    enum Entry {
+        Added(Sink),
+        Existing(mapping::Sink),
+    }
+    let mut reopened = Reopened::default();
+    let mut entries = Vec::new();
+    for sink in defined {
+        if sinks.iter().any(|open| open.name == sink.name) {
+            entries.push(Entry::Existing(sink));
+            continue;
+        }
+        let name = sink.name.clone();
+        match Sink::open(sink).await {
+            Ok(sink) => {
+                entries.push(Entry::Added(sink));
+                reopened.opened += 1;
+            }
+            Err(err) => {
+                for entry in entries {
+                    if let Entry::Added(sink) = entry {
+                        sink.close().await;
+                    }
+                }
+                return Err(exit::Error::new(
+                    err.code,
+                    format!("unable to open sink {name}: {err}"),
+                ));
+            }
+        }
+    }
+
+    let mut old: Vec<Option<Sink>> = sinks.drain(..).map(Some).collect();
+    for entry in entries {
+        let sink = match entry {
+            Entry::Added(sink) => {
+                sinks.push(sink);
+                continue;
+            }
+            Entry::Existing(sink) => sink,
+        };
+        let open = old
+            .iter_mut()
+            .find(|open| open.as_ref().is_some_and(|open| open.name == sink.name))
+            .and_then(Option::take)
+            .expect("sinks that aren't added are open");
+        if open.is(&sink) {
+            sinks.push(open);
+            continue;
+        }
+        open.close().await;
+        let name = sink.name.clone();
+        match Sink::open(sink).await {
+            Ok(sink) => sinks.push(sink),
+            Err(err) => exit::fatal_error(format!("Unable to open sink {name} again"), &err),
+        }
+        reopened.reopened += 1;
+    }
+    for sink in old.into_iter().flatten() {
+        sink.close().await;
+        reopened.closed += 1;
+    }
+    Ok(reopened)
+}
@@ -0,0 +1,5 @@
+#[tokio::main]
+async fn main() {
+    bridge::run(edge_core::logging::parse("bridge")).await;
+    edge_core::expect::finish();
+}
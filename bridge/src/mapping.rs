@@ -0,0 +1,1313 @@
+//! The mapping file: named sources and sinks, and the routes between them.
+//!
+//! ```yaml
+//! sources:
+//!   plc:
+//!     type: modbus
+//!     address: 10.0.0.5:502
+//!     secondary: 10.0.0.6:502  # standby CPU, polled while the address doesn't answer
+//!     failback: 10m          # back to the address this long after failing over, or never
+//!     unit_id: 1
+//!     interval: 5s
+//!     cache_ttl: 2s          # share readings under 2s old with other sources on the device
+//!     points:
+//!       - name: temperature
+//!         register: 100
+//!         kind: holding      # holding, input, coil or discrete
+//!         type: i16          # u16, i16, u32, i32, u64, i64, f32, f64, bcd16, bcd32 or bool
+//!         order: abcd        # byte order of wider types: abcd (default), badc, cdab or dcba
+//!         scale: 0.1         # raw value multiplier
+//!         unit: °C
+//!         min: -40           # marked out-of-range below min or above max, after scaling
+//!         max: 150
+//!     stale_after: 1m        # marked stale when a value stays the same this long
+//!   sensors:
+//!     type: mqtt
+//!     address: broker.local:1883
+//!     topics: [sensors/+/humidity]  # envelopes and JSON points keep their time and quality
+//!     unit: "%"              # engineering unit of every value received
+//!     min: 0                 # as for Modbus points
+//!     max: 100
+//!     stale_after: 5m        # the last value is repeated as stale when none arrives this long
+//!
+//! sinks:
+//!   site:
+//!     type: nats
+//!     profile: site-a        # address and credentials from config.toml
+//!     creds: site-a.creds    # NATS 2.x .creds file, relative to this file; or nkey: SUA...
+//!     format: json           # value (default) or json
+//!   console:
+//!     type: stdout
+//!   dashboard:
+//!     type: websocket        # every point as JSON to the browsers connected
+//!     listen: 0.0.0.0:8481
+//!   history:
+//!     type: historian
+//!     path: history          # directory, relative to this file
+//!     store: parquet         # sqlite (default) or parquet
+//!     retention: 30d         # kept forever when left out
+//!     rollups:               # min/max/avg per interval, kept apart from the samples
+//!       - interval: 1m
+//!         retention: 1y
+//!       - interval: 1h       # kept forever when retention is left out
+//!   capture:
+//!     type: file             # every point as an envelope line, written in the background
+//!     path: points.ndjson    # appended to, relative to this file
+//!     flush: 1s              # written and synced this often, the default
+//!     queue: 1024            # lines held in memory while the disk is slow, the default
+//!     spill: spill           # then kept in this directory, relative to this file; dropped
+//!     spill_max: 256MiB      # without one, or past this much
+//!   influx:
+//!     type: influx
+//!     address: influx.local  # port 8086 by default
+//!     bucket: plant          # or database: for InfluxDB 1.x
+//!     org: edf               # 2.x only, with a token
+//!     token_file: token      # relative to this file; or token, or username and password
+//!     measurement: edge      # the default
+//!   timescale:
+//!     type: timescale
+//!     address: db.local      # port 5432 by default
+//!     database: plant
+//!     username: edge
+//!     password_file: /run/secrets/timescale  # or password
+//!     table: points          # the default, created when missing
+//!
+//! routes:
+//!   - source: plc
+//!     point: temperature     # every point of the source when left out
+//!     sink: site
+//!     subject: plant.{source}.{point}
+//!     unit: °F               # converted from the point's unit before the transform
+//!     transform: {round: 1, deadband: 0.2}
+//!   - source: sensors
+//!     sink: console
+//!     script: hooks/humidity.rhai  # relative to this file, runs before the transform
+//!
+//! alerts:                    # see the alert module
+//!   boiler-hot:
+//!     source: plc
+//!     point: temperature
+//!     above: 90
+//!     notify:
+//!       - sink: site
+//! ```
+
+use edge_core::auth::{Auth, Credentials};
+use edge_core::buffer;
+use edge_core::codec::Order;
+use edge_core::config::{self, ConfigError};
+use edge_core::failover::{Failback, Failover};
+use edge_core::file_sink;
+use edge_core::historian::{self, Rollup, Store};
+use edge_core::point::DataType;
+use edge_core::script::{Script, ScriptError};
+use edge_core::tsdb;
+use edge_core::units;
+use edge_core::yaml::{self, Value};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::alert::{Alert, Notify, Webhook};
+use crate::transform::Transform;
+
+#[derive(Debug)]
+pub enum MappingError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, yaml::ParseError),
+    Profile(String, ConfigError),
+    Script(String, ScriptError),
+    Invalid(String, String),
+    // One of the others, with the line of the file it's about.
+    At(PathBuf, usize, Box<MappingError>),
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MappingError::Read(path, err) => write!(f, "unable to read {}: {err}", path.display()),
+            MappingError::Parse(path, err) => {
+                write!(f, "{}:{}: {}", path.display(), err.line, err.message)
+            }
+            MappingError::Profile(path, err) => write!(f, "{path}: {err}"),
+            MappingError::Script(path, err) => write!(f, "{path}: {err}"),
+            MappingError::Invalid(path, message) => write!(f, "{path}: {message}"),
+            MappingError::At(file, line, err) => write!(f, "{}:{line}: {err}", file.display()),
+        }
+    }
+}
+
+impl std::error::Error for MappingError {}
+
+impl MappingError {
+    /// Points the error at the line of `file` its entry is on.
+    fn at(self, file: &Path, lines: &yaml::Lines) -> MappingError {
+        let path = match &self {
+            MappingError::Profile(path, _)
+            | MappingError::Script(path, _)
+            | MappingError::Invalid(path, _) => path,
+            _ => return self,
+        };
+        match lines.of(path.strip_prefix("mapping.").unwrap_or(path)) {
+            Some(line) => MappingError::At(file.to_path_buf(), line, Box::new(self)),
+            None => self,
+        }
+    }
+}
+
+pub struct Mapping {
+    pub sources: Vec<Source>,
+    pub sinks: Vec<Sink>,
+    pub routes: Vec<Route>,
+    pub alerts: Vec<Alert>,
+}
+
+pub struct Source {
+    pub name: String,
+    pub kind: SourceKind,
+    // Points polled unchanged, or not received, for this long are marked stale.
+    pub stale_after: Option<Duration>,
+    // The entry in the file, to tell whether a reload changed it.
+    pub definition: Value,
+}
+
+pub enum SourceKind {
+    Modbus {
+        connection: ModbusConnection,
+        interval: Duration,
+        points: Vec<ModbusPoint>,
+    },
+    Nats {
+        connection: NatsConnection,
+        subjects: Vec<String>,
+        unit: Option<String>,
+        range: Range,
+    },
+    Mqtt {
+        connection: MqttConnection,
+        topics: Vec<String>,
+        unit: Option<String>,
+        range: Range,
+    },
+}
+
+impl SourceKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SourceKind::Modbus { .. } => "modbus",
+            SourceKind::Nats { .. } => "nats",
+            SourceKind::Mqtt { .. } => "mqtt",
+        }
+    }
+}
+
+pub struct Sink {
+    pub name: String,
+    pub kind: SinkKind,
+    pub format: PayloadFormat,
+    // The entry in the file, to tell whether a reload changed it.
+    pub definition: Value,
+}
+
+pub enum SinkKind {
+    Modbus(ModbusConnection),
+    Nats(NatsConnection),
+    Mqtt(MqttConnection),
+    Stdout,
+    Websocket(SocketAddr),
+    Historian(historian::Options),
+    File(file_sink::Options),
+    Tsdb(tsdb::Target),
+}
+
+impl SinkKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SinkKind::Modbus(_) => "modbus",
+            SinkKind::Nats(_) => "nats",
+            SinkKind::Mqtt(_) => "mqtt",
+            SinkKind::Stdout => "stdout",
+            SinkKind::Websocket(_) => "websocket",
+            SinkKind::Historian(_) => "historian",
+            SinkKind::File(_) => "file",
+            SinkKind::Tsdb(tsdb::Target::Influx(_)) => "influx",
+            SinkKind::Tsdb(tsdb::Target::Timescale(_)) => "timescale",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PayloadFormat {
+    // The bare value, e.g. `21.5`.
+    Value,
+    // `{"source":..,"point":..,"value":..,"time":..}`
+    Json,
+}
+
+#[derive(Clone)]
+pub struct ModbusConnection {
+    pub address: String,
+    pub unit_id: u8,
+    // `address` and its secondary, when it has one; clones share which is in use.
+    pub failover: Option<Failover<String>>,
+    // How long readings stay good for other sources reading the same registers.
+    pub cache_ttl: Option<Duration>,
+}
+
+impl ModbusConnection {
+    /// The address that served the last connection, for logs.
+    pub fn served_by(&self) -> String {
+        self.failover
+            .as_ref()
+            .and_then(Failover::served)
+            .unwrap_or_else(|| self.address.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct NatsConnection {
+    pub address: String,
+    pub credentials: Credentials,
+}
+
+#[derive(Clone)]
+pub struct MqttConnection {
+    pub address: String,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ModbusPoint {
+    pub name: String,
+    pub register: u16,
+    pub kind: RegisterKind,
+    pub data_type: DataType,
+    pub order: Order,
+    pub unit: Option<String>,
+    // Applied to the raw value when read.
+    pub scale: Option<f64>,
+    pub range: Range,
+}
+
+/// Values a point is expected to stay within, after scaling; points outside are marked out of
+/// range.
+#[derive(Copy, Clone, Default)]
+pub struct Range {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl ModbusPoint {
+    /// `holding 100`, as read-register names registers.
+    pub fn address(&self) -> String {
+        format!("{} {}", self.kind.name(), self.register)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RegisterKind {
+    Holding,
+    Input,
+    Coil,
+    Discrete,
+}
+
+impl RegisterKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            RegisterKind::Holding => "holding",
+            RegisterKind::Input => "input",
+            RegisterKind::Coil => "coil",
+            RegisterKind::Discrete => "discrete",
+        }
+    }
+
+    pub fn is_bit(self) -> bool {
+        matches!(self, RegisterKind::Coil | RegisterKind::Discrete)
+    }
+}
+
+pub struct Route {
+    pub source: String,
+    pub point: Option<String>,
+    pub sink: usize,
+    pub target: Target,
+    // Unit to convert values to before the transform.
+    pub unit: Option<String>,
+    pub transform: Transform,
+    pub hook: Option<Hook>,
+}
+
+/// A script run on each point of a route; it can drop the point, change its name and value
+/// or add fields for JSON payloads.
+pub struct Hook {
+    pub path: PathBuf,
+    pub script: Script,
+}
+
+pub enum Target {
+    Stdout,
+    Websocket,
+    Historian,
+    File,
+    Tsdb,
+    // Subject or topic with `{source}` and `{point}` substituted.
+    Name(String),
+    Register {
+        register: u16,
+        kind: RegisterKind,
+        data_type: DataType,
+        order: Order,
+    },
+}
+
+impl Route {
+    pub fn matches(&self, source: &str, point: &str) -> bool {
+        self.source == source && self.point.as_deref().map(|p| p == point).unwrap_or(true)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Mapping, MappingError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| MappingError::Read(path.to_path_buf(), err))?;
+    let (document, lines) = yaml::parse_with_lines(&contents)
+        .map_err(|err| MappingError::Parse(path.to_path_buf(), err))?;
+    build(&document, path).map_err(|err| err.at(path, &lines))
+}
+
+fn build(document: &Value, path: &Path) -> Result<Mapping, MappingError> {
+    let root = Table::new("mapping".to_string(), document)?;
+    root.only(&["sources", "sinks", "routes", "alerts"])?;
+
+    let mut sources = Vec::new();
+    for (name, value) in root.entries("sources")? {
+        let table = Table::new(format!("sources.{name}"), value)?;
+        sources.push(Source {
+            name: name.clone(),
+            kind: source(&table, path)?,
+            stale_after: table.duration("stale_after")?,
+            definition: value.clone(),
+        });
+    }
+    let mut sinks = Vec::new();
+    for (name, value) in root.entries("sinks")? {
+        let table = Table::new(format!("sinks.{name}"), value)?;
+        sinks.push(sink(name, &table, path)?);
+    }
+
+    let mut routes = Vec::new();
+    for (index, value) in root.list("routes")?.iter().enumerate() {
+        let table = Table::new(format!("routes[{index}]"), value)?;
+        routes.push(route(&table, &sources, &sinks, path)?);
+    }
+    if routes.is_empty() {
+        return Err(MappingError::Invalid(
+            "routes".to_string(),
+            "at least one route is required".to_string(),
+        ));
+    }
+    let mut alerts = Vec::new();
+    for (name, value) in root.entries("alerts")? {
+        let table = Table::new(format!("alerts.{name}"), value)?;
+        alerts.push(alert(name, &table, &sources, &sinks)?);
+    }
+    Ok(Mapping {
+        sources,
+        sinks,
+        routes,
+        alerts,
+    })
+}
+
+fn source(table: &Table, mapping: &Path) -> Result<SourceKind, MappingError> {
+    match table.required_string("type")?.as_str() {
+        "modbus" => {
+            table.only(&[
+                "type",
+                "profile",
+                "address",
+                "secondary",
+                "failback",
+                "unit_id",
+                "interval",
+                "cache_ttl",
+                "stale_after",
+                "points",
+            ])?;
+            let mut points = Vec::new();
+            for (index, value) in table.list("points")?.iter().enumerate() {
+                let point = Table::new(format!("{}.points[{index}]", table.path), value)?;
+                points.push(modbus_point(&point)?);
+            }
+            if points.is_empty() {
+                return Err(table.invalid("points", "at least one point is required"));
+            }
+            if let Some(duplicate) = points
+                .iter()
+                .enumerate()
+                .find(|(index, point)| points[..*index].iter().any(|p| p.name == point.name))
+            {
+                return Err(table.invalid(
+                    "points",
+                    &format!("point {} defined twice", duplicate.1.name),
+                ));
+            }
+            Ok(SourceKind::Modbus {
+                connection: modbus_connection(table)?,
+                interval: table
+                    .duration("interval")?
+                    .unwrap_or(Duration::from_secs(1)),
+                points,
+            })
+        }
+        "nats" => {
+            table.only(&[
+                "type",
+                "profile",
+                "address",
+                "username",
+                "password",
+                "password_file",
+                "token",
+                "token_file",
+                "creds",
+                "nkey",
+                "subjects",
+                "unit",
+                "min",
+                "max",
+                "stale_after",
+            ])?;
+            Ok(SourceKind::Nats {
+                connection: nats_connection(table, mapping)?,
+                subjects: table.names("subjects")?,
+                unit: table.string("unit")?,
+                range: range(table)?,
+            })
+        }
+        "mqtt" => {
+            table.only(&[
+                "type",
+                "profile",
+                "address",
+                "client_id",
+                "username",
+                "password",
+                "password_file",
+                "topics",
+                "unit",
+                "min",
+                "max",
+                "stale_after",
+            ])?;
+            Ok(SourceKind::Mqtt {
+                connection: mqtt_connection(table, mapping)?,
+                topics: table.names("topics")?,
+                unit: table.string("unit")?,
+                range: range(table)?,
+            })
+        }
+        other => Err(table.invalid(
+            "type",
+            &format!("unknown source type {other}, expected modbus, nats or mqtt"),
+        )),
+    }
+}
+
+fn sink(name: &str, table: &Table, mapping: &Path) -> Result<Sink, MappingError> {
+    let kind = match table.required_string("type")?.as_str() {
+        "modbus" => {
+            table.only(&[
+                "type",
+                "profile",
+                "address",
+                "secondary",
+                "failback",
+                "unit_id",
+            ])?;
+            SinkKind::Modbus(modbus_connection(table)?)
+        }
+        "nats" => {
+            table.only(&[
+                "type",
+                "profile",
+                "address",
+                "username",
+                "password",
+                "password_file",
+                "token",
+                "token_file",
+                "creds",
+                "nkey",
+                "format",
+            ])?;
+            SinkKind::Nats(nats_connection(table, mapping)?)
+        }
+        "mqtt" => {
+            table.only(&[
+                "type",
+                "profile",
+                "address",
+                "client_id",
+                "username",
+                "password",
+                "password_file",
+                "format",
+            ])?;
+            SinkKind::Mqtt(mqtt_connection(table, mapping)?)
+        }
+        "stdout" => {
+            table.only(&["type"])?;
+            SinkKind::Stdout
+        }
+        "websocket" => {
+            table.only(&["type", "listen"])?;
+            let listen = table.required_string("listen")?;
+            SinkKind::Websocket(listen.parse().map_err(|_| {
+                table.invalid(
+                    "listen",
+                    &format!("invalid address {listen}, expected one like 0.0.0.0:8481"),
+                )
+            })?)
+        }
+        "historian" => {
+            table.only(&["type", "path", "store", "retention", "rollups"])?;
+            let store = match table.string("store")?.as_deref() {
+                None | Some("sqlite") => Store::Sqlite,
+                Some("parquet") => Store::Parquet,
+                Some(other) => {
+                    return Err(table.invalid(
+                        "store",
+                        &format!("unknown store {other}, expected sqlite or parquet"),
+                    ))
+                }
+            };
+            SinkKind::Historian(historian::Options {
+                directory: mapping
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(table.required_string("path")?),
+                store,
+                retention: table.duration("retention")?,
+                rollups: rollups(table)?,
+            })
+        }
+        "file" => {
+            table.only(&["type", "path", "flush", "queue", "spill", "spill_max"])?;
+            let directory = mapping.parent().unwrap_or_else(|| Path::new(""));
+            let defaults = file_sink::Options::new(directory.join(table.required_string("path")?));
+            let spill_max = match table.string("spill_max")? {
+                Some(size) => buffer::parse_size(&size).map_err(|err| table.invalid("spill_max", &err))?,
+                None => defaults.spill_max,
+            };
+            SinkKind::File(file_sink::Options {
+                flush: table.duration("flush")?.unwrap_or(defaults.flush),
+                queue: table.integer("queue")?.unwrap_or(defaults.queue).max(1),
+                spill: table.string("spill")?.map(|spill| directory.join(spill)),
+                spill_max,
+                ..defaults
+            })
+        }
+        "influx" => {
+            table.only(&[
+                "type",
+                "address",
+                "bucket",
+                "database",
+                "org",
+                "token",
+                "token_file",
+                "username",
+                "password",
+                "password_file",
+                "measurement",
+            ])?;
+            let database = match (table.string("bucket")?, table.string("database")?) {
+                (Some(bucket), None) => bucket,
+                (None, Some(database)) => database,
+                _ => return Err(table.invalid("bucket", "expected either bucket or database")),
+            };
+            let credentials = table.credentials(mapping)?;
+            if credentials.token.is_some() && credentials.password.is_some() {
+                return Err(table.invalid("token", "expected either token or password"));
+            }
+            SinkKind::Tsdb(tsdb::Target::Influx(tsdb::Influx {
+                address: table.required_string("address")?,
+                database,
+                org: table.string("org")?,
+                username: credentials.username,
+                password: credentials.token.or(credentials.password),
+                measurement: table
+                    .string("measurement")?
+                    .unwrap_or_else(|| "edge".to_string()),
+            }))
+        }
+        "timescale" => {
+            table.only(&[
+                "type",
+                "address",
+                "database",
+                "username",
+                "password",
+                "password_file",
+                "table",
+            ])?;
+            SinkKind::Tsdb(tsdb::Target::Timescale(tsdb::Timescale {
+                address: table.required_string("address")?,
+                database: table.required_string("database")?,
+                username: table.required_string("username")?,
+                password: table.credentials(mapping)?.password,
+                table: table.string("table")?.unwrap_or_else(|| "points".to_string()),
+            }))
+        }
+        other => {
+            return Err(table.invalid(
+                "type",
+                &format!(
+                    "unknown sink type {other}, expected modbus, nats, mqtt, stdout, websocket, historian, influx or timescale"
+                ),
+            ))
+        }
+    };
+    let format = match table.string("format")?.as_deref() {
+        None | Some("value") => PayloadFormat::Value,
+        Some("json") => PayloadFormat::Json,
+        Some(other) => {
+            return Err(table.invalid(
+                "format",
+                &format!("unknown format {other}, expected value or json"),
+            ))
+        }
+    };
+    Ok(Sink {
+        name: name.to_string(),
+        kind,
+        format,
+        definition: table.value.clone(),
+    })
+}
+
+/// A historian sink's rollups, each an interval and an optional retention.
+fn rollups(table: &Table) -> Result<Vec<Rollup>, MappingError> {
+    let mut rollups = Vec::new();
+    for (index, value) in table.list("rollups")?.iter().enumerate() {
+        let entry = Table::new(format!("{}.rollups[{index}]", table.path), value)?;
+        entry.only(&["interval", "retention"])?;
+        let interval = entry
+            .duration("interval")?
+            .ok_or_else(|| entry.invalid("interval", "is required"))?;
+        if interval.as_millis() == 0 {
+            return Err(entry.invalid("interval", "must be at least 1ms"));
+        }
+        rollups.push(Rollup {
+            interval,
+            retention: entry.duration("retention")?,
+        });
+    }
+    Ok(rollups)
+}
+
+fn route(
+    table: &Table,
+    sources: &[Source],
+    sinks: &[Sink],
+    mapping: &Path,
+) -> Result<Route, MappingError> {
+    table.only(&[
+        "source",
+        "point",
+        "sink",
+        "subject",
+        "topic",
+        "register",
+        "kind",
+        "type",
+        "unit",
+        "transform",
+        "script",
+    ])?;
+    let source_name = table.required_string("source")?;
+    let source = sources
+        .iter()
+        .find(|source| source.name == source_name)
+        .ok_or_else(|| table.invalid("source", &format!("no source named {source_name}")))?;
+    let point = table.string("point")?;
+    if let (Some(point), SourceKind::Modbus { points, .. }) = (&point, &source.kind) {
+        if !points.iter().any(|p| &p.name == point) {
+            return Err(table.invalid(
+                "point",
+                &format!("source {source_name} has no point named {point}"),
+            ));
+        }
+    }
+
+    let sink_name = table.required_string("sink")?;
+    let sink = sinks
+        .iter()
+        .position(|sink| sink.name == sink_name)
+        .ok_or_else(|| table.invalid("sink", &format!("no sink named {sink_name}")))?;
+
+    let allowed: &[&str] = match sinks[sink].kind {
+        SinkKind::Modbus(_) => &["register", "kind", "type", "order"],
+        SinkKind::Nats(_) => &["subject"],
+        SinkKind::Mqtt(_) => &["topic"],
+        SinkKind::Stdout
+        | SinkKind::Websocket(_)
+        | SinkKind::Historian(_)
+        | SinkKind::File(_)
+        | SinkKind::Tsdb(_) => &[],
+    };
+    for key in ["subject", "topic", "register", "kind", "type", "order"] {
+        if table.value.get(key).is_some() && !allowed.contains(&key) {
+            return Err(table.invalid(
+                key,
+                &format!("not used by {} sink {sink_name}", sinks[sink].kind.name()),
+            ));
+        }
+    }
+    let target = match sinks[sink].kind {
+        SinkKind::Modbus(_) => {
+            let kind = match table.string("kind")?.as_deref() {
+                None | Some("holding") => RegisterKind::Holding,
+                Some("coil") => RegisterKind::Coil,
+                Some(other) => {
+                    return Err(table.invalid(
+                        "kind",
+                        &format!("cannot write {other} registers, expected holding or coil"),
+                    ))
+                }
+            };
+            Target::Register {
+                register: table
+                    .integer("register")?
+                    .ok_or_else(|| table.invalid("register", "is required for modbus sinks"))?,
+                kind,
+                data_type: data_type(table, kind)?,
+                order: order(table)?,
+            }
+        }
+        SinkKind::Nats(_) => Target::Name(
+            table
+                .string("subject")?
+                .unwrap_or_else(|| "{source}.{point}".to_string()),
+        ),
+        SinkKind::Mqtt(_) => Target::Name(
+            table
+                .string("topic")?
+                .unwrap_or_else(|| "{source}/{point}".to_string()),
+        ),
+        SinkKind::Stdout => Target::Stdout,
+        SinkKind::Websocket(_) => Target::Websocket,
+        SinkKind::Historian(_) => Target::Historian,
+        SinkKind::File(_) => Target::File,
+        SinkKind::Tsdb(_) => Target::Tsdb,
+    };
+
+    let unit = table.string("unit")?;
+    if let Some(unit) = &unit {
+        let declared: Vec<&str> = match &source.kind {
+            SourceKind::Modbus { points, .. } => points
+                .iter()
+                .filter(|p| point.as_ref().is_none_or(|point| &p.name == point))
+                .filter_map(|p| p.unit.as_deref())
+                .collect(),
+            SourceKind::Nats { unit, .. } | SourceKind::Mqtt { unit, .. } => {
+                unit.as_deref().into_iter().collect()
+            }
+        };
+        if declared.is_empty() && !units::is_known(unit) {
+            return Err(table.invalid("unit", &units::Error::Unknown(unit.clone()).to_string()));
+        }
+        for from in declared {
+            units::can_convert(from, unit)
+                .map_err(|err| table.invalid("unit", &err.to_string()))?;
+        }
+    }
+    let transform = match table.value.get("transform") {
+        None | Some(Value::Null) => Transform::default(),
+        Some(value) => transform(&Table::new(format!("{}.transform", table.path), value)?)?,
+    };
+    let hook = match table.string("script")? {
+        None => None,
+        Some(script) => {
+            let path = mapping
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(script);
+            let script = Script::load(&path)
+                .map_err(|err| MappingError::Script(format!("{}.script", table.path), err))?;
+            Some(Hook { path, script })
+        }
+    };
+    Ok(Route {
+        source: source_name,
+        point,
+        sink,
+        target,
+        unit,
+        transform,
+        hook,
+    })
+}
+
+fn alert(
+    name: &str,
+    table: &Table,
+    sources: &[Source],
+    sinks: &[Sink],
+) -> Result<Alert, MappingError> {
+    table.only(&[
+        "source", "point", "above", "below", "rate", "stale", "repeat", "notify",
+    ])?;
+    let source_name = table.required_string("source")?;
+    let source = sources
+        .iter()
+        .find(|source| source.name == source_name)
+        .ok_or_else(|| table.invalid("source", &format!("no source named {source_name}")))?;
+    let point = table.string("point")?;
+    if let (Some(point), SourceKind::Modbus { points, .. }) = (&point, &source.kind) {
+        if !points.iter().any(|p| &p.name == point) {
+            return Err(table.invalid(
+                "point",
+                &format!("source {source_name} has no point named {point}"),
+            ));
+        }
+    }
+    let (above, below) = (table.float("above")?, table.float("below")?);
+    if let (Some(above), Some(below)) = (above, below) {
+        if below > above {
+            return Err(table.invalid("below", &format!("is over above {above}")));
+        }
+    }
+    let rate = table.float("rate")?;
+    if rate.is_some_and(|rate| rate <= 0.0) {
+        return Err(table.invalid("rate", "must be positive"));
+    }
+    let stale = table.duration("stale")?;
+    if above.is_none() && below.is_none() && rate.is_none() && stale.is_none() {
+        return Err(MappingError::Invalid(
+            table.path.clone(),
+            "expected at least one of above, below, rate or stale".to_string(),
+        ));
+    }
+
+    let mut notify = Vec::new();
+    for (index, value) in table.list("notify")?.iter().enumerate() {
+        let entry = Table::new(format!("{}.notify[{index}]", table.path), value)?;
+        notify.push(notifier(&entry, sinks)?);
+    }
+    if notify.is_empty() {
+        return Err(table.invalid("notify", "at least one is required"));
+    }
+    Ok(Alert {
+        name: name.to_string(),
+        source: source_name,
+        point,
+        above,
+        below,
+        rate,
+        stale,
+        repeat: table.duration("repeat")?,
+        notify,
+    })
+}
+
+fn notifier(table: &Table, sinks: &[Sink]) -> Result<Notify, MappingError> {
+    table.only(&["webhook", "sink", "subject", "topic", "exec"])?;
+    let kinds = ["webhook", "sink", "exec"];
+    match kinds.iter().filter(|key| table.get(key).is_some()).count() {
+        1 => {}
+        _ => {
+            return Err(MappingError::Invalid(
+                table.path.clone(),
+                "expected one of webhook, sink or exec".to_string(),
+            ))
+        }
+    }
+    if let Some(url) = table.string("webhook")? {
+        table.only(&["webhook"])?;
+        let webhook = Webhook::parse(&url).map_err(|err| table.invalid("webhook", &err))?;
+        return Ok(Notify::Webhook(webhook));
+    }
+    if let Some(command) = table.string("exec")? {
+        table.only(&["exec"])?;
+        return Ok(Notify::Exec(command));
+    }
+    let sink_name = table.required_string("sink")?;
+    let sink = sinks
+        .iter()
+        .position(|sink| sink.name == sink_name)
+        .ok_or_else(|| table.invalid("sink", &format!("no sink named {sink_name}")))?;
+    let (key, default) = match sinks[sink].kind {
+        SinkKind::Nats(_) => ("subject", "alerts.{alert}"),
+        SinkKind::Mqtt(_) => ("topic", "alerts/{alert}"),
+        _ => {
+            return Err(table.invalid(
+                "sink",
+                &format!(
+                    "alerts can't go to {} sink {sink_name}, only nats and mqtt",
+                    sinks[sink].kind.name()
+                ),
+            ))
+        }
+    };
+    for other in ["subject", "topic"] {
+        if other != key && table.get(other).is_some() {
+            return Err(table.invalid(
+                other,
+                &format!("not used by {} sink {sink_name}", sinks[sink].kind.name()),
+            ));
+        }
+    }
+    Ok(Notify::Sink {
+        sink,
+        name: table.string(key)?.unwrap_or_else(|| default.to_string()),
+    })
+}
+
+fn transform(table: &Table) -> Result<Transform, MappingError> {
+    table.only(&[
+        "scale", "offset", "min", "max", "round", "invert", "deadband",
+    ])?;
+    Ok(Transform {
+        scale: table.float("scale")?,
+        offset: table.float("offset")?,
+        min: table.float("min")?,
+        max: table.float("max")?,
+        round: table.integer("round")?,
+        invert: table.boolean("invert")?.unwrap_or(false),
+        deadband: table.float("deadband")?,
+    })
+}
+
+fn modbus_point(table: &Table) -> Result<ModbusPoint, MappingError> {
+    table.only(&[
+        "name", "register", "kind", "type", "order", "unit", "scale", "min", "max",
+    ])?;
+    let kind = match table.string("kind")?.as_deref() {
+        None | Some("holding") => RegisterKind::Holding,
+        Some("input") => RegisterKind::Input,
+        Some("coil") => RegisterKind::Coil,
+        Some("discrete") => RegisterKind::Discrete,
+        Some(other) => {
+            return Err(table.invalid(
+                "kind",
+                &format!("unknown kind {other}, expected holding, input, coil or discrete"),
+            ))
+        }
+    };
+    Ok(ModbusPoint {
+        name: table.required_string("name")?,
+        register: table
+            .integer("register")?
+            .ok_or_else(|| table.invalid("register", "is required"))?,
+        kind,
+        data_type: data_type(table, kind)?,
+        order: order(table)?,
+        unit: table.string("unit")?,
+        scale: table.float("scale")?,
+        range: range(table)?,
+    })
+}
+
+fn range(table: &Table) -> Result<Range, MappingError> {
+    let range = Range {
+        min: table.float("min")?,
+        max: table.float("max")?,
+    };
+    if let (Some(min), Some(max)) = (range.min, range.max) {
+        if min > max {
+            return Err(table.invalid("max", &format!("is below min {min}")));
+        }
+    }
+    Ok(range)
+}
+
+fn data_type(table: &Table, kind: RegisterKind) -> Result<DataType, MappingError> {
+    let data_type = match table.string("type")?.as_deref() {
+        None if kind.is_bit() => DataType::Bool,
+        None => DataType::U16,
+        Some(name) => DataType::parse(name).ok_or_else(|| {
+            table.invalid(
+                "type",
+                &format!("unknown type {name}, expected {}", DataType::NAMES),
+            )
+        })?,
+    };
+    if kind.is_bit() != (data_type == DataType::Bool) {
+        return Err(table.invalid("type", "coils and discrete inputs hold bool values only"));
+    }
+    Ok(data_type)
+}
+
+fn order(table: &Table) -> Result<Order, MappingError> {
+    match table.string("order")? {
+        None => Ok(Order::default()),
+        Some(name) => Order::parse(&name).ok_or_else(|| {
+            table.invalid(
+                "order",
+                &format!("unknown order {name}, expected {}", Order::NAMES),
+            )
+        }),
+    }
+}
+
+fn modbus_connection(table: &Table) -> Result<ModbusConnection, MappingError> {
+    let profile = table.profile()?;
+    let unit_id = match table.integer("unit_id")? {
+        Some(unit_id) => unit_id,
+        None => profile
+            .integer::<u8>("unit_id")
+            .map_err(|err| MappingError::Profile(table.path.clone(), err))?
+            .unwrap_or(1),
+    };
+    let address = table.address(&profile)?;
+    let secondary = match table.string("secondary")? {
+        Some(secondary) => Some(secondary),
+        None => profile
+            .secondary()
+            .map_err(|err| MappingError::Profile(table.path.clone(), err))?,
+    };
+    let failback = match table.string("failback")? {
+        Some(failback) => failback
+            .parse::<Failback>()
+            .map_err(|err| table.invalid("failback", &err))?,
+        None => profile
+            .failback()
+            .map_err(|err| MappingError::Profile(table.path.clone(), err))?,
+    };
+    Ok(ModbusConnection {
+        failover: secondary
+            .map(|secondary| Failover::new(address.clone(), secondary).failback(failback)),
+        address,
+        unit_id,
+        // Only sources take one.
+        cache_ttl: match table.duration("cache_ttl")? {
+            Some(ttl) => Some(ttl),
+            None => profile
+                .cache_ttl()
+                .map_err(|err| MappingError::Profile(table.path.clone(), err))?,
+        },
+    })
+}
+
+fn nats_connection(table: &Table, mapping: &Path) -> Result<NatsConnection, MappingError> {
+    let profile = table.profile()?;
+    let credentials = table
+        .credentials(mapping)?
+        .or_profile(&profile)
+        .map_err(|err| MappingError::Profile(table.path.clone(), err))?;
+    // Catch half-specified credentials and broken creds files now rather than when connecting.
+    let auth = credentials
+        .resolve()
+        .map_err(|err| table.invalid("username", &err.to_string()))?;
+    if let Auth::Creds(creds) = auth {
+        async_nats::ConnectOptions::with_credentials(&creds)
+            .map_err(|err| table.invalid("creds", &format!("invalid creds file: {err}")))?;
+    }
+    Ok(NatsConnection {
+        address: table.address(&profile)?,
+        credentials,
+    })
+}
+
+fn mqtt_connection(table: &Table, mapping: &Path) -> Result<MqttConnection, MappingError> {
+    let profile = table.profile()?;
+    let credentials = table
+        .credentials(mapping)?
+        .or_profile(&profile)
+        .map_err(|err| MappingError::Profile(table.path.clone(), err))?;
+    Ok(MqttConnection {
+        address: table.address(&profile)?,
+        client_id: table.string("client_id")?,
+        username: credentials.username,
+        password: credentials.password,
+    })
+}
+
+/// A mapping in the file, with the path used to point at it in errors.
+struct Table<'a> {
+    path: String,
+    value: &'a Value,
+}
+
+impl<'a> Table<'a> {
+    fn new(path: String, value: &'a Value) -> Result<Table<'a>, MappingError> {
+        match value {
+            Value::Map(_) => Ok(Table { path, value }),
+            other => Err(MappingError::Invalid(
+                path,
+                format!("expected a mapping, found {}", other.kind()),
+            )),
+        }
+    }
+
+    fn invalid(&self, key: &str, message: &str) -> MappingError {
+        MappingError::Invalid(format!("{}.{key}", self.path), message.to_string())
+    }
+
+    /// Rejects keys other than `allowed`, so a misspelt option isn't silently ignored.
+    fn only(&self, allowed: &[&str]) -> Result<(), MappingError> {
+        for (key, _) in self.value.as_map().unwrap_or_default() {
+            if !allowed.contains(&key.as_str()) {
+                return Err(self.invalid(key, "unknown key"));
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Value> {
+        match self.value.get(key) {
+            None | Some(Value::Null) => None,
+            Some(value) => Some(value),
+        }
+    }
+
+    fn expected(&self, key: &str, expected: &str, found: &Value) -> MappingError {
+        self.invalid(key, &format!("expected {expected}, found {}", found.kind()))
+    }
+
+    fn string(&self, key: &str) -> Result<Option<String>, MappingError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(other) => Err(self.expected(key, "a string", other)),
+        }
+    }
+
+    fn required_string(&self, key: &str) -> Result<String, MappingError> {
+        self.string(key)?
+            .ok_or_else(|| self.invalid(key, "is required"))
+    }
+
+    fn integer<T: TryFrom<i64>>(&self, key: &str) -> Result<Option<T>, MappingError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(value)) => T::try_from(*value)
+                .map(Some)
+                .map_err(|_| self.invalid(key, &format!("{value} is out of range"))),
+            Some(other) => Err(self.expected(key, "an integer", other)),
+        }
+    }
+
+    fn float(&self, key: &str) -> Result<Option<f64>, MappingError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => value
+                .as_float()
+                .map(Some)
+                .ok_or_else(|| self.expected(key, "a number", value)),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, MappingError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => value
+                .as_bool()
+                .map(Some)
+                .ok_or_else(|| self.expected(key, "true or false", value)),
+        }
+    }
+
+    /// `5s`, `250ms`, `1m 30s`, or a plain number of seconds.
+    fn duration(&self, key: &str) -> Result<Option<Duration>, MappingError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => humantime::parse_duration(value)
+                .map(Some)
+                .map_err(|err| self.invalid(key, &format!("invalid duration {value}: {err}"))),
+            Some(value) => match value.as_float() {
+                Some(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                    Ok(Some(Duration::from_secs_f64(seconds)))
+                }
+                _ => Err(self.expected(key, "a duration", value)),
+            },
+        }
+    }
+
+    fn list(&self, key: &str) -> Result<&'a [Value], MappingError> {
+        match self.get(key) {
+            None => Ok(&[]),
+            Some(Value::List(values)) => Ok(values),
+            Some(other) => Err(self.expected(key, "a list", other)),
+        }
+    }
+
+    fn entries(&self, key: &str) -> Result<&'a [(String, Value)], MappingError> {
+        match self.get(key) {
+            None => Ok(&[]),
+            Some(Value::Map(entries)) => Ok(entries),
+            Some(other) => Err(self.expected(key, "a mapping of names", other)),
+        }
+    }
+
+    /// A single subject/topic or a list of them; at least one is required.
+    fn names(&self, key: &str) -> Result<Vec<String>, MappingError> {
+        let names = match self.get(key) {
+            None => Vec::new(),
+            Some(Value::String(name)) => vec![name.clone()],
+            Some(Value::List(values)) => values
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| self.expected(key, "a list of strings", value))
+                })
+                .collect::<Result<_, _>>()?,
+            Some(other) => Err(self.expected(key, "a string or a list", other))?,
+        };
+        if names.is_empty() {
+            return Err(self.invalid(key, "at least one is required"));
+        }
+        Ok(names)
+    }
+
+    fn profile(&self) -> Result<config::Profile, MappingError> {
+        config::load_profile(self.string("profile")?.as_deref())
+            .map_err(|err| MappingError::Profile(self.path.clone(), err))
+    }
+
+    /// `username`, `password` and `token`, or the secrets in `password_file` and `token_file`
+    /// (relative to the mapping file). Only the keys a table allows can be set.
+    fn credentials(&self, mapping: &Path) -> Result<Credentials, MappingError> {
+        let directory = mapping.parent().unwrap_or_else(|| Path::new(""));
+        let path = |key| -> Result<Option<PathBuf>, MappingError> {
+            Ok(self.string(key)?.map(|file| directory.join(file)))
+        };
+        let (password_file, token_file) = (path("password_file")?, path("token_file")?);
+        let creds = path("creds")?;
+        for (key, direct, file) in [
+            ("password_file", "password", &password_file),
+            ("token_file", "token", &token_file),
+        ] {
+            if file.is_some() && self.get(direct).is_some() {
+                return Err(self.invalid(key, &format!("expected either {direct} or {key}")));
+            }
+        }
+        Credentials {
+            username: self.string("username")?,
+            password: self.string("password")?,
+            token: self.string("token")?,
+            creds: None,
+            nkey: self.string("nkey")?,
+        }
+        .or_files(password_file.as_deref(), token_file.as_deref())
+        .and_then(|credentials| credentials.or_creds_file(creds.as_deref()))
+        .map_err(|err| MappingError::Invalid(self.path.clone(), err.to_string()))
+    }
+
+    fn address(&self, profile: &config::Profile) -> Result<String, MappingError> {
+        match self.string("address")? {
+            Some(address) => Ok(address),
+            None => profile
+                .address()
+                .map_err(|err| MappingError::Profile(self.path.clone(), err))?
+                .ok_or_else(|| self.invalid("address", "is required without a profile")),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Sinks hold their connection for the life of the bridge; writes are made one at a time by
+//! the router.
+
+use async_nats::Client;
+use edge_core::audit;
+use edge_core::codec::Order;
+use edge_core::envelope::Envelope;
+use edge_core::exit::{self, Code};
+use edge_core::file_sink::FileSink;
+use edge_core::historian::Historian;
+use edge_core::mqtt::MqttClient;
+use edge_core::output::{self, Output};
+use edge_core::point::{DataType, Point, Value};
+use edge_core::retry::Retry;
+use edge_core::tsdb::Tsdb;
+use edge_core::websocket;
+use edge_core::writes::{self, Write};
+use edge_core::yaml;
+use tokio_modbus::client::{Context, Writer};
+
+use crate::connect;
+use crate::mapping::{self, PayloadFormat, RegisterKind, SinkKind, Target};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub struct Sink {
+    pub name: String,
+    format: PayloadFormat,
+    connection: Connection,
+    // The mapping entry it was opened from.
+    definition: yaml::Value,
+}
+
+enum Connection {
+    Modbus(mapping::ModbusConnection),
+    Nats(Client),
+    Mqtt(MqttClient),
+    Stdout,
+    Websocket(websocket::Server),
+    Historian(Historian),
+    File(FileSink),
+    Tsdb(Tsdb),
+}
+
+impl Sink {
+    /// Connects NATS and database sinks and binds WebSocket ones up front so a wrong address or
+    /// credentials fail at startup; Modbus and MQTT sinks connect on the first write and
+    /// reconnect as needed, Modbus ones sharing the connection with sources on the device.
+    pub async fn open(sink: mapping::Sink) -> Result<Sink, exit::Error> {
+        let connection = match sink.kind {
+            SinkKind::Modbus(connection) => Connection::Modbus(connection),
+            SinkKind::Nats(connection) => {
+                let what = format!("Sink {}: connecting to {}", sink.name, connection.address);
+                let client = Retry::default()
+                    .run(&what, || connect::nats(&connection))
+                    .await?;
+                Connection::Nats(client)
+            }
+            SinkKind::Mqtt(connection) => Connection::Mqtt(MqttClient::new(connect::mqtt_options(
+                &sink.name,
+                &connection,
+            ))),
+            SinkKind::Stdout => Connection::Stdout,
+            SinkKind::Websocket(listen) => {
+                Connection::Websocket(websocket::Server::serve(listen).await.map_err(|err| {
+                    exit::Error::new(
+                        Code::from_io(&err),
+                        format!("unable to listen on {listen}: {err}"),
+                    )
+                })?)
+            }
+            SinkKind::Historian(options) => Connection::Historian(
+                Historian::open(options).map_err(|err| exit::Error::new(Code::Failure, err))?,
+            ),
+            SinkKind::File(options) => {
+                let path = options.path.clone();
+                Connection::File(FileSink::open(options).map_err(|err| {
+                    exit::Error::new(
+                        Code::from_io(&err),
+                        format!("unable to open {}: {err}", path.display()),
+                    )
+                })?)
+            }
+            SinkKind::Tsdb(target) => Connection::Tsdb(Tsdb::open(target).await?),
+        };
+        Ok(Sink {
+            name: sink.name,
+            format: sink.format,
+            connection,
+            definition: sink.definition,
+        })
+    }
+
+    /// Whether `sink` is this one, unchanged.
+    pub fn is(&self, sink: &mapping::Sink) -> bool {
+        self.name == sink.name && self.definition == sink.definition
+    }
+
+    pub async fn write(
+        &mut self,
+        target: &Target,
+        point: &Point,
+        value: &Value,
+        out: &Output,
+    ) -> Result<(), Error> {
+        let point = Point {
+            value: value.clone(),
+            ..point.clone()
+        };
+        match (&mut self.connection, target) {
+            (Connection::Nats(client), Target::Name(template)) => {
+                let subject = expand(template, &point);
+                let payload = payload(self.format, &point);
+                let (write, value) = (Write::Nats(&subject), audit::payload(&payload));
+                writes::check(&write, &value)?;
+                let result = client.publish(subject.clone(), payload.into()).await;
+                audit::record(&write, &value, &result);
+                result?;
+            }
+            (Connection::Mqtt(client), Target::Name(template)) => {
+                let topic = expand(template, &point);
+                client
+                    .publish(&topic, &payload(self.format, &point))
+                    .await?;
+            }
+            (
+                Connection::Modbus(connection),
+                Target::Register {
+                    register,
+                    kind,
+                    data_type,
+                    order,
+                },
+            ) => {
+                // Stale or failed readings shouldn't drive a device.
+                if !point.quality.is_good() {
+                    return Err(format!("its quality is {}", point.quality.name()).into());
+                }
+                let write = Write::Modbus {
+                    device: edge_core::net::resolve(&connection.address, 502)?,
+                    kind: match kind {
+                        RegisterKind::Coil => "coil",
+                        _ => "holding",
+                    },
+                    register: *register,
+                    count: data_type.words(),
+                };
+                writes::check(&write, value)?;
+                let mut context = connect::modbus(connection).await?;
+                let result =
+                    write_register(&mut context, *register, *kind, *data_type, *order, value).await;
+                audit::record(&write, value, &result);
+                // Even a failed write may have reached the device.
+                let count = match kind {
+                    RegisterKind::Coil => 1,
+                    _ => data_type.words(),
+                };
+                connect::written(&context, connection.unit_id, *kind, *register, count);
+                // Keep the connection after exception responses, close it after anything else.
+                let usable = match &result {
+                    Ok(()) => true,
+                    Err(err) => matches!(
+                        err.downcast_ref::<std::io::Error>(),
+                        Some(io) if io.kind() == std::io::ErrorKind::Other
+                    ),
+                };
+                if usable {
+                    context.release();
+                }
+                result?;
+            }
+            (Connection::Stdout, _) => {
+                out.record(&point.to_record(), || {
+                    let unit = match &point.unit {
+                        Some(unit) => format!(" {unit}"),
+                        None => String::new(),
+                    };
+                    let quality = match point.quality.is_good() {
+                        true => String::new(),
+                        false => format!(" ({})", point.quality.name()),
+                    };
+                    println!(
+                        "{}.{} = {}{unit}{quality}",
+                        point.source, point.name, point.value
+                    )
+                });
+            }
+            (Connection::Websocket(server), _) => {
+                server.send(output::json(&output::Value::Record(point.to_record())))
+            }
+            (Connection::Historian(historian), _) => historian.record(&point)?,
+            // Queued, like database sinks.
+            (Connection::File(file), _) => file.write(&Envelope::from(&point).to_line()),
+            // Queued; the database is written in the background.
+            (Connection::Tsdb(tsdb), _) => tsdb.record(&point),
+            _ => unreachable!("targets are checked against their sink when loading the mapping"),
+        }
+        Ok(())
+    }
+
+    /// Publishes `payload` as it is to `name`, a subject or topic; only NATS and MQTT sinks
+    /// take messages like this.
+    pub async fn publish(&mut self, name: &str, payload: &[u8]) -> Result<(), Error> {
+        match &mut self.connection {
+            Connection::Nats(client) => {
+                let (write, value) = (Write::Nats(name), audit::payload(payload));
+                writes::check(&write, &value)?;
+                let result = client
+                    .publish(name.to_string(), payload.to_vec().into())
+                    .await;
+                audit::record(&write, &value, &result);
+                result?
+            }
+            Connection::Mqtt(client) => client.publish(name, payload).await?,
+            _ => unreachable!("alerts are checked against their sink when loading the mapping"),
+        }
+        Ok(())
+    }
+
+    /// Writes out what database and file sinks still have queued.
+    pub async fn close(self) {
+        match self.connection {
+            Connection::Tsdb(tsdb) => tsdb.close().await,
+            Connection::File(file) => file.close().await,
+            _ => {}
+        }
+    }
+
+    pub async fn ping(&mut self) {
+        if let Connection::Mqtt(client) = &mut self.connection {
+            if let Err(err) = client.ping().await {
+                log::warn!("Sink {}: MQTT ping failed: {err}", self.name);
+            }
+        }
+    }
+}
+
+fn payload(format: PayloadFormat, point: &Point) -> Vec<u8> {
+    match format {
+        PayloadFormat::Value => point.value.to_string().into_bytes(),
+        PayloadFormat::Json => output::json(&output::Value::Record(point.to_record())).into_bytes(),
+    }
+}
+
+fn expand(template: &str, point: &Point) -> String {
+    template
+        .replace("{source}", &point.source)
+        .replace("{point}", &point.name)
+}
+
+async fn write_register(
+    ctx: &mut Context,
+    register: u16,
+    kind: RegisterKind,
+    data_type: DataType,
+    order: Order,
+    value: &Value,
+) -> Result<(), Error> {
+    if kind == RegisterKind::Coil {
+        let bit = value
+            .as_bool()
+            .ok_or_else(|| format!("cannot write {value:?} to a coil"))?;
+        ctx.write_single_coil(register, bit).await?;
+        return Ok(());
+    }
+
+    let number = value
+        .as_number()
+        .ok_or_else(|| format!("cannot write {value:?} to a register"))?;
+    let words = data_type
+        .encode(number, order)
+        .map_err(|err| format!("cannot write to register {register}: {err}"))?;
+    if words.len() == 1 {
+        ctx.write_single_register(register, words[0]).await?;
+    } else {
+        ctx.write_multiple_registers(register, &words).await?;
+    }
+    Ok(())
+}
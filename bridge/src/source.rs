@@ -0,0 +1,545 @@
+//! Source tasks: each polls or subscribes on its own and sends points to the router. They
+//! reconnect on their own, so a device that drops off doesn't stop the rest of the bridge.
+//! [`Running`] keeps them by name, so a reloaded mapping only restarts the sources it changed.
+
+use edge_core::buffer;
+use edge_core::envelope::Envelope;
+use edge_core::mqtt::MqttClient;
+use edge_core::output;
+use edge_core::point::{Point, Quality, Value};
+use edge_core::retry::Retry;
+use edge_core::telemetry;
+use edge_core::yaml;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_modbus::client::{Context, Reader};
+
+use crate::connect;
+use crate::mapping::{
+    ModbusConnection, ModbusPoint, MqttConnection, NatsConnection, Range, RegisterKind, Source,
+    SourceKind,
+};
+
+// Sources reconnect for as long as the bridge runs, backing off up to a minute between tries.
+fn retry() -> Retry {
+    Retry {
+        delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(60),
+        ..Retry::default()
+    }
+    .forever()
+}
+
+/// The last point of each name a source sent, to mark points stale when they stop changing or
+/// arriving, and to repeat them as comm-fail when reading them fails.
+struct History {
+    stale_after: Option<Duration>,
+    last: HashMap<String, Last>,
+}
+
+struct Last {
+    point: Point,
+    // When the value last changed, or last arrived for subscriptions.
+    since: Instant,
+    // Already repeated as stale.
+    reported: bool,
+}
+
+impl History {
+    fn new(stale_after: Option<Duration>) -> History {
+        History {
+            stale_after,
+            last: HashMap::new(),
+        }
+    }
+
+    /// A polled point, marked stale when its value hasn't changed for too long.
+    fn polled(&mut self, mut point: Point) -> Point {
+        let now = Instant::now();
+        let since = match self.last.get(&point.name) {
+            Some(last) if last.point.value == point.value => last.since,
+            _ => now,
+        };
+        if point.quality.is_good()
+            && self
+                .stale_after
+                .is_some_and(|stale_after| now.duration_since(since) >= stale_after)
+        {
+            point.quality = Quality::Stale;
+        }
+        self.remember(point.clone(), since);
+        point
+    }
+
+    /// A point received on a subscription; it goes out unchanged.
+    fn received(&mut self, point: Point) -> Point {
+        self.remember(point.clone(), Instant::now());
+        point
+    }
+
+    fn remember(&mut self, point: Point, since: Instant) {
+        let last = Last {
+            point,
+            since,
+            reported: false,
+        };
+        self.last.insert(last.point.name.clone(), last);
+    }
+
+    /// The last value of `name` as of now, when reading it failed.
+    fn failed(&self, name: &str) -> Option<Point> {
+        self.last.get(name).map(|last| Point {
+            quality: Quality::CommFail,
+            time: SystemTime::now(),
+            ..last.point.clone()
+        })
+    }
+
+    /// Received points nothing has followed for too long, once each.
+    fn quiet(&mut self) -> Vec<Point> {
+        let Some(stale_after) = self.stale_after else {
+            return Vec::new();
+        };
+        self.last
+            .values_mut()
+            .filter(|last| !last.reported && last.since.elapsed() >= stale_after)
+            .map(|last| {
+                last.reported = true;
+                Point {
+                    quality: Quality::Stale,
+                    time: SystemTime::now(),
+                    ..last.point.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// How often to look for quiet points; never without `stale_after`.
+    async fn tick(ticker: &mut Option<tokio::time::Interval>) {
+        match ticker {
+            Some(ticker) => {
+                ticker.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    fn ticker(&self) -> Option<tokio::time::Interval> {
+        // Quiet points are caught within a tenth of `stale_after`.
+        self.stale_after.map(|stale_after| {
+            tokio::time::interval((stale_after / 10).max(Duration::from_millis(100)))
+        })
+    }
+}
+
+pub fn spawn(source: Source, points: buffer::Sender<Point>) -> JoinHandle<()> {
+    let name = source.name;
+    let history = History::new(source.stale_after);
+    match source.kind {
+        SourceKind::Modbus {
+            connection,
+            interval,
+            points: registers,
+        } => tokio::spawn(poll_modbus(
+            name, connection, interval, registers, history, points,
+        )),
+        SourceKind::Nats {
+            connection,
+            subjects,
+            unit,
+            range,
+        } => {
+            let received = Received { unit, range };
+            tokio::spawn(subscribe_nats(
+                name, connection, subjects, received, history, points,
+            ))
+        }
+        SourceKind::Mqtt {
+            connection,
+            topics,
+            unit,
+            range,
+        } => {
+            let received = Received { unit, range };
+            tokio::spawn(subscribe_mqtt(
+                name, connection, topics, received, history, points,
+            ))
+        }
+    }
+}
+
+/// The source tasks by name, with the definitions they were started from.
+#[derive(Default)]
+pub struct Running {
+    tasks: HashMap<String, (yaml::Value, JoinHandle<()>)>,
+}
+
+/// What [`Running::update`] did.
+#[derive(Default)]
+pub struct Changes {
+    pub started: usize,
+    pub restarted: usize,
+    pub stopped: usize,
+}
+
+impl Running {
+    /// Starts the sources that aren't running, restarts those whose definition changed and
+    /// stops those that are gone; the others carry on with their connections.
+    pub fn update(&mut self, sources: Vec<Source>, points: &buffer::Sender<Point>) -> Changes {
+        let mut changes = Changes::default();
+        let mut tasks = HashMap::new();
+        for source in sources {
+            let name = source.name.clone();
+            match self.tasks.remove(&name) {
+                Some((definition, task)) if definition == source.definition => {
+                    tasks.insert(name, (definition, task));
+                    continue;
+                }
+                Some((_, task)) => {
+                    task.abort();
+                    changes.restarted += 1;
+                }
+                None => changes.started += 1,
+            }
+            let definition = source.definition.clone();
+            tasks.insert(name, (definition, spawn(source, points.clone())));
+        }
+        for (_, (_, task)) in self.tasks.drain() {
+            task.abort();
+            changes.stopped += 1;
+        }
+        self.tasks = tasks;
+        changes
+    }
+
+    /// Whether every source has stopped by itself.
+    pub fn finished(&self) -> bool {
+        self.tasks.values().all(|(_, task)| task.is_finished())
+    }
+}
+
+/// What a subscribing source declares about every value it receives.
+struct Received {
+    unit: Option<String>,
+    range: Range,
+}
+
+impl Received {
+    /// Envelopes, and points another bridge publishes as JSON, bring their own value, unit,
+    /// quality and the time they were taken; anything else is a bare value received now.
+    fn point(&self, source: &str, address: &str, payload: &[u8]) -> Point {
+        let mut point = Point {
+            unit: self.unit.clone(),
+            ..Point::new(source, address, Value::parse(payload))
+        };
+        if let Some(record) = structured(payload) {
+            let envelope = Envelope::from_record(source, &record);
+            let timed = ["timestamp", "time"]
+                .iter()
+                .any(|name| record.get(name).is_some());
+            point.value = Value::from(&envelope.payload);
+            point.unit = point.unit.or(envelope.unit);
+            point.quality = envelope.quality;
+            point.source_time = envelope
+                .source_timestamp
+                .or(Some(envelope.timestamp).filter(|_| timed));
+        }
+        point.checked(self.range.min, self.range.max)
+    }
+}
+
+/// A payload that is a JSON object with a `payload` or a `value`.
+fn structured(payload: &[u8]) -> Option<output::Record> {
+    let text = std::str::from_utf8(payload).ok()?.trim();
+    if !text.starts_with('{') {
+        return None;
+    }
+    match edge_core::json::parse(text) {
+        Ok(output::Value::Record(record))
+            if record.get("payload").is_some() || record.get("value").is_some() =>
+        {
+            Some(record)
+        }
+        _ => None,
+    }
+}
+
+/// Sends the last value of each of `registers` as comm-fail; false when the router is gone.
+async fn failed<'a>(
+    history: &History,
+    registers: impl IntoIterator<Item = &'a ModbusPoint>,
+    points: &buffer::Sender<Point>,
+) -> bool {
+    for register in registers {
+        if let Some(point) = history.failed(&register.name) {
+            if points.send(point).await.is_err() {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+async fn poll_modbus(
+    name: String,
+    connection: ModbusConnection,
+    interval: Duration,
+    registers: Vec<ModbusPoint>,
+    mut history: History,
+    points: buffer::Sender<Point>,
+) {
+    let retry = retry();
+    let mut failures = 0;
+    let mut connected = false;
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut context = match connect::modbus(&connection).await {
+            Ok(context) => {
+                if !connected {
+                    log::info!("Source {name}: connected to {}", connection.served_by());
+                }
+                connected = true;
+                failures = 0;
+                context
+            }
+            Err(err) => {
+                log::warn!(
+                    "Source {name}: unable to connect to {}: {err}",
+                    connection.address
+                );
+                connected = false;
+                failures += 1;
+                if !failed(&history, &registers, &points).await {
+                    return;
+                }
+                // Polls that fall due meanwhile are skipped rather than bunched up.
+                tokio::time::sleep(retry.delay(failures).saturating_sub(interval)).await;
+                ticker.reset();
+                continue;
+            }
+        };
+
+        // Everything is read before anything is sent on, so a sink on the same device isn't
+        // kept waiting for the connection while the router waits for this source.
+        let poll = telemetry::span("bridge.poll").attribute("bridge.source", name.as_str());
+        let mut results = Vec::with_capacity(registers.len());
+        for register in &registers {
+            let span = poll
+                .child("modbus.read")
+                .attribute("bridge.point", register.name.as_str())
+                .attribute("modbus.register", register.register);
+            let result = read_point(&mut context, &connection, register).await;
+            telemetry::finish(span, "bridge.reads", &[("source", &name)], &result);
+            // Exception responses leave the connection usable; anything else doesn't.
+            let lost = matches!(&result, Err(err) if err.kind() != std::io::ErrorKind::Other);
+            results.push((register, result));
+            if lost {
+                connected = false;
+                break;
+            }
+        }
+        if connected {
+            context.release();
+        } else {
+            drop(context);
+        }
+
+        for (register, result) in results {
+            match result {
+                Ok(value) => {
+                    let point = Point {
+                        name: register.name.clone(),
+                        data_type: Some(register.data_type),
+                        unit: register.unit.clone(),
+                        ..Point::new(&name, &register.address(), value)
+                    }
+                    .scaled(register.scale)
+                    .checked(register.range.min, register.range.max);
+                    if points.send(history.polled(point)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Other => {
+                    log::warn!("Source {name}: reading {} failed: {err}", register.name);
+                }
+                Err(err) => {
+                    log::warn!(
+                        "Source {name}: reading {} failed, reconnecting: {err}",
+                        register.name
+                    );
+                }
+            }
+            if !failed(&history, [register], &points).await {
+                return;
+            }
+        }
+    }
+}
+
+/// Reads `point`, or takes a reading of it the connection's `cache_ttl` allows.
+async fn read_point(
+    ctx: &mut connect::Modbus,
+    connection: &ModbusConnection,
+    point: &ModbusPoint,
+) -> std::io::Result<Value> {
+    let count = match point.kind {
+        RegisterKind::Coil | RegisterKind::Discrete => 1,
+        RegisterKind::Holding | RegisterKind::Input => point.data_type.words(),
+    };
+    let key = (
+        *ctx.key(),
+        connection.unit_id,
+        point.kind,
+        point.register,
+        count,
+    );
+    let words = match connection.cache_ttl {
+        Some(ttl) => {
+            connect::readings()
+                .get(key, ttl, || {
+                    read_words(ctx, point.kind, point.register, count)
+                })
+                .await?
+        }
+        None => read_words(ctx, point.kind, point.register, count).await?,
+    };
+    match point.kind {
+        RegisterKind::Coil | RegisterKind::Discrete => {
+            Ok(Value::Bool(words.first().is_some_and(|bit| *bit != 0)))
+        }
+        RegisterKind::Holding | RegisterKind::Input => point
+            .data_type
+            .decode(&words, point.order)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+    }
+}
+
+async fn read_words(
+    ctx: &mut Context,
+    kind: RegisterKind,
+    register: u16,
+    count: u16,
+) -> std::io::Result<Vec<u16>> {
+    let bits = |bits: Vec<bool>| bits.into_iter().map(u16::from).collect();
+    match kind {
+        RegisterKind::Coil => ctx.read_coils(register, count).await.map(bits),
+        RegisterKind::Discrete => ctx.read_discrete_inputs(register, count).await.map(bits),
+        RegisterKind::Holding => ctx.read_holding_registers(register, count).await,
+        RegisterKind::Input => ctx.read_input_registers(register, count).await,
+    }
+}
+
+async fn subscribe_nats(
+    name: String,
+    connection: NatsConnection,
+    subjects: Vec<String>,
+    received: Received,
+    mut history: History,
+    points: buffer::Sender<Point>,
+) {
+    // The client reconnects by itself once connected; only the first connection is retried
+    // here.
+    let what = format!("Source {name}: connecting to {}", connection.address);
+    let limits = points.limits();
+    let client = match retry()
+        .run(&what, || connect::nats_for(&connection, limits))
+        .await
+    {
+        Ok(client) => client,
+        Err(err) => {
+            log::error!("{what} failed for good: {err}");
+            return;
+        }
+    };
+
+    let mut subscriptions = Vec::new();
+    for subject in subjects {
+        match client.subscribe(subject.clone()).await {
+            Ok(subscription) => subscriptions.push(subscription),
+            Err(err) => log::error!("Source {name}: unable to subscribe to {subject}: {err}"),
+        }
+    }
+    let mut messages = futures::stream::select_all(subscriptions);
+    let mut ticker = history.ticker();
+    loop {
+        let sent = tokio::select! {
+            message = messages.next() => match message {
+                Some(message) if !limits.allows(message.payload.len()) => {
+                    let size = message.payload.len();
+                    points.discard(&format!("{size} bytes is over the payload limit"));
+                    continue;
+                }
+                Some(message) => {
+                    let point = received.point(&name, &message.subject, &message.payload);
+                    vec![history.received(point)]
+                }
+                None => break,
+            },
+            _ = History::tick(&mut ticker) => history.quiet(),
+        };
+        for point in sent {
+            if points.send(point).await.is_err() {
+                return;
+            }
+        }
+    }
+    log::warn!("Source {name}: subscriptions ended");
+}
+
+async fn subscribe_mqtt(
+    name: String,
+    connection: MqttConnection,
+    topics: Vec<String>,
+    received: Received,
+    mut history: History,
+    points: buffer::Sender<Point>,
+) {
+    let retry = retry();
+    let mut client = MqttClient::new(connect::mqtt_options(&name, &connection));
+    let mut failures = 0;
+    let mut messages = loop {
+        match client.subscribe(&topics, points.limits()).await {
+            Ok(messages) => break messages,
+            Err(err) => {
+                log::warn!(
+                    "Source {name}: unable to subscribe on {}: {err}",
+                    connection.address
+                );
+                failures += 1;
+                tokio::time::sleep(retry.delay(failures)).await;
+            }
+        }
+    };
+
+    let mut ping = tokio::time::interval(client.keep_alive() / 2);
+    let mut ticker = history.ticker();
+    loop {
+        let sent = tokio::select! {
+            message = messages.recv() => {
+                let message = match message {
+                    Some(message) => message,
+                    None => return,
+                };
+                let point = received.point(&name, &message.topic, &message.payload);
+                vec![history.received(point)]
+            }
+            _ = History::tick(&mut ticker) => history.quiet(),
+            _ = ping.tick() => {
+                if let Err(err) = client.ping().await {
+                    log::warn!("Source {name}: MQTT connection lost: {err}");
+                }
+                continue;
+            }
+        };
+        for point in sent {
+            if points.send(point).await.is_err() {
+                return;
+            }
+        }
+    }
+}
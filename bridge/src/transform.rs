@@ -0,0 +1,91 @@
+//! Per-route value transforms, applied in the order they're listed here.
+
+use edge_core::point::Value;
+
+#[derive(Clone, Debug, Default)]
+pub struct Transform {
+    // Multiply, then add.
+    pub scale: Option<f64>,
+    pub offset: Option<f64>,
+    // Clamp into [min, max].
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    // Decimal places to keep.
+    pub round: Option<u32>,
+    // Flip booleans.
+    pub invert: bool,
+    // Skip numbers that moved less than this since the last one sent.
+    pub deadband: Option<f64>,
+}
+
+impl Transform {
+    pub fn is_empty(&self) -> bool {
+        self.scale.is_none()
+            && self.offset.is_none()
+            && self.min.is_none()
+            && self.max.is_none()
+            && self.round.is_none()
+            && !self.invert
+            && self.deadband.is_none()
+    }
+
+    /// The transformed value; text passes through untouched.
+    pub fn apply(&self, value: &Value) -> Value {
+        match value {
+            Value::Bool(value) => Value::Bool(*value != self.invert),
+            Value::Text(text) => Value::Text(text.clone()),
+            Value::Number(number) => {
+                let mut number = number * self.scale.unwrap_or(1.0) + self.offset.unwrap_or(0.0);
+                if let Some(min) = self.min {
+                    number = number.max(min);
+                }
+                if let Some(max) = self.max {
+                    number = number.min(max);
+                }
+                if let Some(places) = self.round {
+                    let factor = 10f64.powi(places as i32);
+                    number = (number * factor).round() / factor;
+                }
+                Value::Number(number)
+            }
+        }
+    }
+
+    /// Whether `value` moved far enough from `last` to be sent.
+    pub fn passes_deadband(&self, value: &Value, last: Option<&Value>) -> bool {
+        match (self.deadband, value, last) {
+            (Some(deadband), Value::Number(value), Some(Value::Number(last))) => {
+                (value - last).abs() >= deadband
+            }
+            (Some(_), value, Some(last)) => value != last,
+            _ => true,
+        }
+    }
+
+    /// A short description for `bridge check`, e.g. `scale 0.1, round 1`.
+    pub fn describe(&self) -> String {
+        let mut steps = Vec::new();
+        if let Some(scale) = self.scale {
+            steps.push(format!("scale {scale}"));
+        }
+        if let Some(offset) = self.offset {
+            steps.push(format!("offset {offset}"));
+        }
+        if let Some(min) = self.min {
+            steps.push(format!("min {min}"));
+        }
+        if let Some(max) = self.max {
+            steps.push(format!("max {max}"));
+        }
+        if let Some(round) = self.round {
+            steps.push(format!("round {round}"));
+        }
+        if self.invert {
+            steps.push("invert".to_string());
+        }
+        if let Some(deadband) = self.deadband {
+            steps.push(format!("deadband {deadband}"));
+        }
+        steps.join(", ")
+    }
+}
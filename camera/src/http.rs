@@ -0,0 +1,289 @@
+//! Minimal HTTP/1.1 and RTSP/1.0 request handling, which share the same message format.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use edge_core::exit::{self, Code};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::hash::{hex, md5};
+
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Sends one request on a fresh connection and reads the full response.
+///
+/// `request` must contain the request line and headers without the terminating blank line;
+/// `Content-Length` is added when there is a body.
+pub async fn exchange(
+    host_port: &str,
+    request: &str,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    tokio::time::timeout(timeout, exchange_inner(host_port, request, body))
+        .await
+        .map_err(|_| {
+            exit::Error::new(
+                Code::Timeout,
+                format!("no response from {host_port} within {}s", timeout.as_secs()),
+            )
+        })?
+}
+
+async fn exchange_inner(
+    host_port: &str,
+    request: &str,
+    body: &[u8],
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let mut stream = TcpStream::connect(host_port).await?;
+
+    let mut message = request.trim_end().to_string();
+    message.push_str("\r\n");
+    if !body.is_empty() {
+        message.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    message.push_str("\r\n");
+    log::trace!("Sending to {host_port}:\n{message}");
+    stream.write_all(message.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+    let mut parts = status_line.trim_end().splitn(3, ' ');
+    let _version = parts.next();
+    let status: u16 = parts
+        .next()
+        .ok_or_else(|| format!("malformed status line {status_line:?}"))?
+        .parse()?;
+    let reason = parts.next().unwrap_or("").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            // Repeated WWW-Authenticate headers: keep the first, which is usually Digest.
+            headers
+                .entry(name.trim().to_ascii_lowercase())
+                .or_insert_with(|| value.trim().to_string());
+        }
+    }
+
+    let body = if let Some(length) = headers.get("content-length") {
+        let mut body = vec![0u8; length.parse()?];
+        reader.read_exact(&mut body).await?;
+        body
+    } else if headers
+        .get("transfer-encoding")
+        .map(|te| te.eq_ignore_ascii_case("chunked"))
+        .unwrap_or(false)
+    {
+        read_chunked(&mut reader).await?
+    } else if request.starts_with("RTSP") || request.contains(" RTSP/1.0") {
+        // RTSP keeps the connection open; no length means no body.
+        Vec::new()
+    } else {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).await?;
+        body
+    };
+
+    Ok(Response {
+        status,
+        reason,
+        headers,
+        body,
+    })
+}
+
+async fn read_chunked(
+    reader: &mut BufReader<TcpStream>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line).await?;
+        let size_hex = size_line.trim().split(';').next().unwrap_or("0");
+        let size = usize::from_str_radix(size_hex, 16)?;
+        if size == 0 {
+            break;
+        }
+        let mut chunk = vec![0u8; size + 2];
+        reader.read_exact(&mut chunk).await?;
+        chunk.truncate(size);
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Builds an `Authorization` header value answering a `WWW-Authenticate` challenge.
+pub fn authorization(
+    challenge: &str,
+    method: &str,
+    uri: &str,
+    username: &str,
+    password: &str,
+) -> Option<String> {
+    let (scheme, params) = challenge.split_once(' ').unwrap_or((challenge, ""));
+    if scheme.eq_ignore_ascii_case("basic") {
+        let credentials = BASE64.encode(format!("{username}:{password}"));
+        return Some(format!("Basic {credentials}"));
+    }
+    if !scheme.eq_ignore_ascii_case("digest") {
+        return None;
+    }
+
+    let params = parse_challenge(params);
+    let realm = params.get("realm")?;
+    let nonce = params.get("nonce")?;
+    let ha1 = hex(&md5(format!("{username}:{realm}:{password}").as_bytes()));
+    let ha2 = hex(&md5(format!("{method}:{uri}").as_bytes()));
+
+    // qop may offer "auth,auth-int"; we only ever do auth.
+    let qop_auth = params
+        .get("qop")
+        .map(|qop| qop.split(',').any(|q| q.trim() == "auth"))
+        .unwrap_or(false);
+
+    let mut header = format!(
+        "Digest username=\"{username}\", realm=\"{realm}\", nonce=\"{nonce}\", uri=\"{uri}\""
+    );
+    if qop_auth {
+        let cnonce = hex(&md5(
+            format!("{nonce}{:?}", std::time::SystemTime::now()).as_bytes()
+        ));
+        let nc = "00000001";
+        let response = hex(&md5(
+            format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}").as_bytes()
+        ));
+        header.push_str(&format!(
+            ", qop=auth, nc={nc}, cnonce=\"{cnonce}\", response=\"{response}\""
+        ));
+    } else {
+        let response = hex(&md5(format!("{ha1}:{nonce}:{ha2}").as_bytes()));
+        header.push_str(&format!(", response=\"{response}\""));
+    }
+    if let Some(opaque) = params.get("opaque") {
+        header.push_str(&format!(", opaque=\"{opaque}\""));
+    }
+    header.push_str(", algorithm=MD5");
+    Some(header)
+}
+
+fn parse_challenge(params: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (key, after) = match rest.split_once('=') {
+            Some(split) => split,
+            None => break,
+        };
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+            match quoted.split_once('"') {
+                Some((value, after)) => (value, after),
+                None => (quoted, ""),
+            }
+        } else {
+            match after.split_once(',') {
+                Some((value, after)) => (value.trim(), after),
+                None => (after.trim(), ""),
+            }
+        };
+        result.insert(key, value.to_string());
+        rest = after.trim_start_matches([',', ' ']);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_and_bare_challenge_parameters() {
+        let params =
+            parse_challenge(r#"realm="cam, front", nonce="abc", qop="auth,auth-int", stale=FALSE"#);
+        assert_eq!(params["realm"], "cam, front");
+        assert_eq!(params["nonce"], "abc");
+        assert_eq!(params["qop"], "auth,auth-int");
+        assert_eq!(params["stale"], "FALSE");
+    }
+
+    #[test]
+    fn answers_basic_and_digest_challenges() {
+        assert_eq!(
+            authorization("Basic realm=\"cam\"", "GET", "/", "admin", "secret").unwrap(),
+            "Basic YWRtaW46c2VjcmV0"
+        );
+
+        // RFC 2617's example, without qop.
+        let challenge = r#"Digest realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+        let header = authorization(
+            challenge,
+            "GET",
+            "/dir/index.html",
+            "Mufasa",
+            "Circle Of Life",
+        )
+        .unwrap();
+        assert!(
+            header.contains(r#"response="670fd8c2df070c60b045671b8b24ff02""#),
+            "{header}"
+        );
+        assert!(
+            header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#),
+            "{header}"
+        );
+
+        let header = authorization(
+            &format!("{challenge}, qop=\"auth\""),
+            "GET",
+            "/dir/index.html",
+            "Mufasa",
+            "Circle Of Life",
+        )
+        .unwrap();
+        assert!(header.contains("qop=auth, nc=00000001"), "{header}");
+
+        assert_eq!(
+            authorization("Bearer realm=\"x\"", "GET", "/", "a", "b"),
+            None
+        );
+        assert_eq!(
+            authorization("Digest nonce=\"x\"", "GET", "/", "a", "b"),
+            None
+        );
+    }
+}
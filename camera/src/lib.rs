@@ -0,0 +1,399 @@
+mod hash;
+mod http;
+mod onvif;
+mod rtsp;
+
+use clap::{Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::{Output, OutputArgs, Record};
+use edge_core::secret;
+use edge_core::OrExit;
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
+
+use onvif::{Credentials, Device};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Authentication, used for both ONVIF and RTSP.
+    #[clap(short, long, env = "EDGE_CAMERA_USER", action)]
+    username: Option<String>,
+    #[clap(
+        short,
+        long,
+        env = "EDGE_CAMERA_PASSWORD",
+        hide_env_values = true,
+        action
+    )]
+    password: Option<String>,
+    /// Read the password from this file instead, e.g. a mounted secret.
+    #[clap(
+        long,
+        env = "EDGE_CAMERA_PASSWORD_FILE",
+        conflicts_with = "password",
+        action
+    )]
+    password_file: Option<PathBuf>,
+
+    /// Seconds to wait for answers.
+    #[clap(short, long, action)]
+    timeout: Option<u64>,
+    #[clap(flatten)]
+    output: OutputArgs,
+
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// WS-Discovery probe for ONVIF devices on the local network.
+    Discover,
+
+    /// Device information, capabilities, media profiles and their stream URIs.
+    Info {
+        /// Device service URL or just the camera's address.
+        #[clap(value_parser)]
+        device: String,
+        /// Also DESCRIBE every stream URI found.
+        #[clap(short, long, action)]
+        check_streams: bool,
+    },
+
+    /// Check that an RTSP stream answers DESCRIBE and print its SDP.
+    Describe {
+        #[clap(value_parser)]
+        url: String,
+    },
+
+    /// Exit 0 if an RTSP URL answers DESCRIBE, or a device (service URL or address) answers
+    /// GetDeviceInformation, for container and systemd health probes.
+    Healthcheck {
+        #[clap(value_parser)]
+        target: String,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+pub async fn run(mut cli: Args) {
+    if let Subcommands::Completions { shell } = cli.command {
+        completions::print::<Args>(shell, "camera");
+        return;
+    }
+    if let Subcommands::GenMan { directory } = &cli.command {
+        man::generate::<Args>("camera", directory.as_deref());
+        return;
+    }
+
+    if let Some(path) = &cli.password_file {
+        cli.password =
+            Some(secret::read_file(path).or_exit_with(Code::Usage, "Unable to read the password"));
+    }
+    let timeout = Duration::from_secs(cli.timeout.unwrap_or(3));
+    let out = cli.output.load();
+
+    match cli.command {
+        Subcommands::Completions { .. } | Subcommands::GenMan { .. } => {
+            unreachable!("handled above")
+        }
+        Subcommands::Discover => {
+            let devices = match onvif::discover(timeout).await {
+                Ok(devices) => devices,
+                Err(err) => exit::fatal_error("Discovery failed", &*err),
+            };
+            if devices.is_empty() {
+                log::warn!("No ONVIF devices answered within {}s", timeout.as_secs());
+            }
+            let records: Vec<Record> = devices
+                .iter()
+                .map(|device| {
+                    Record::new()
+                        .field("address", device.source.ip().to_string())
+                        .field("name", device.scope("name"))
+                        .field("hardware", device.scope("hardware"))
+                        .field("location", device.scope("location"))
+                        .field("types", device.types.as_str())
+                        .field("services", device.xaddrs.clone())
+                })
+                .collect();
+            out.records(&records, || {
+                for device in &devices {
+                    println!("{}", device.source.ip());
+                    if let Some(name) = device.scope("name") {
+                        println!("  Name: {name}");
+                    }
+                    if let Some(hardware) = device.scope("hardware") {
+                        println!("  Hardware: {hardware}");
+                    }
+                    if let Some(location) = device.scope("location") {
+                        println!("  Location: {location}");
+                    }
+                    println!("  Types: {}", device.types);
+                    for xaddr in &device.xaddrs {
+                        println!("  Service: {xaddr}");
+                    }
+                }
+            });
+        }
+        Subcommands::Info {
+            device,
+            check_streams,
+        } => {
+            let url = device_url(&device)
+                .or_exit_with(Code::Usage, &format!("Unable to parse device URL {device}"));
+            let device = Device::new(
+                url.clone(),
+                onvif_credentials(&cli.username, &cli.password),
+                timeout,
+            );
+            let stream_credentials = (cli.username.as_deref(), cli.password.as_deref());
+            if let Err(err) = info(
+                &device,
+                &url,
+                check_streams,
+                stream_credentials,
+                timeout,
+                &out,
+            )
+            .await
+            {
+                exit::fatal_error("Unable to query device", &*err);
+            }
+        }
+        Subcommands::Describe { url } => {
+            let url = Url::parse(&url)
+                .or_exit_with(Code::Usage, &format!("Unable to parse RTSP URL {url}"));
+            match rtsp::describe(
+                &url,
+                cli.username.as_deref(),
+                cli.password.as_deref(),
+                timeout,
+            )
+            .await
+            {
+                Ok(describe) if describe.status == 200 => {
+                    let record = Record::new()
+                        .field("status", describe.status)
+                        .field("reason", describe.reason.as_str())
+                        .field("server", describe.server.as_deref())
+                        .field("sdp", describe.sdp.as_str());
+                    out.record(&record, || {
+                        println!("{} {}", describe.status, describe.reason);
+                        if let Some(server) = &describe.server {
+                            println!("Server: {server}");
+                        }
+                        print!("{}", describe.sdp);
+                    });
+                }
+                Ok(describe) => {
+                    let code = match describe.status {
+                        401 | 403 => Code::Auth,
+                        _ => Code::Protocol,
+                    };
+                    exit::fatal_with(
+                        code,
+                        format!("DESCRIBE failed: {} {}", describe.status, describe.reason),
+                    );
+                }
+                Err(err) => exit::fatal_error("DESCRIBE failed", &*err),
+            }
+        }
+        Subcommands::Healthcheck { target } => {
+            if target.starts_with("rtsp://") {
+                let url = Url::parse(&target)
+                    .or_exit_with(Code::Usage, &format!("Unable to parse RTSP URL {target}"));
+                let (username, password) = (cli.username.as_deref(), cli.password.as_deref());
+                // Credentials in the URL stay out of the result.
+                let mut shown = url.clone();
+                let _ = shown.set_username("");
+                let _ = shown.set_password(None);
+                health::check(&out, shown.as_str(), None, async {
+                    let describe = rtsp::describe(&url, username, password, timeout).await?;
+                    let code = match describe.status {
+                        200 => return Ok(format!("answered DESCRIBE with {}", describe.status)),
+                        401 | 403 => Code::Auth,
+                        _ => Code::Protocol,
+                    };
+                    Err(Box::<dyn std::error::Error>::from(exit::Error::new(
+                        code,
+                        format!("DESCRIBE failed: {} {}", describe.status, describe.reason),
+                    )))
+                })
+                .await;
+            }
+            let url = device_url(&target)
+                .or_exit_with(Code::Usage, &format!("Unable to parse device URL {target}"));
+            let device = Device::new(
+                url,
+                onvif_credentials(&cli.username, &cli.password),
+                timeout,
+            );
+            health::check(&out, &target, None, async {
+                let information = device.device_information().await?;
+                Ok::<_, Box<dyn std::error::Error>>(format!(
+                    "answered GetDeviceInformation as {} {}",
+                    information.manufacturer, information.model
+                ))
+            })
+            .await;
+        }
+    }
+}
+
+fn onvif_credentials(username: &Option<String>, password: &Option<String>) -> Option<Credentials> {
+    match (username.clone(), password.clone()) {
+        (Some(username), Some(password)) => Some(Credentials { username, password }),
+        (None, None) => None,
+        _ => exit::fatal_with(Code::Usage, "Username and password must be given together"),
+    }
+}
+
+async fn info(
+    device: &Device,
+    url: &Url,
+    check_streams: bool,
+    credentials: (Option<&str>, Option<&str>),
+    timeout: Duration,
+    out: &Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let information = device.device_information().await?;
+    let capabilities = device.capabilities().await?;
+
+    // Cameras without a separate media service answer media calls on the device URL.
+    let media = match capabilities.services.get("Media") {
+        Some(xaddr) => Url::parse(xaddr)?,
+        None => url.clone(),
+    };
+
+    let mut profiles = Vec::new();
+    for profile in device.profiles(&media).await? {
+        let stream = device
+            .stream_uri(&media, &profile.token)
+            .await
+            .map_err(|err| err.to_string());
+        let describe = match &stream {
+            Ok(uri) if check_streams => Some(check_stream(uri, credentials, timeout).await),
+            _ => None,
+        };
+        profiles.push((profile, stream, describe));
+    }
+
+    let record = Record::new()
+        .field("manufacturer", information.manufacturer.as_str())
+        .field("model", information.model.as_str())
+        .field("firmware", information.firmware_version.as_str())
+        .field("serial_number", information.serial_number.as_str())
+        .field("hardware_id", information.hardware_id.as_str())
+        .field(
+            "services",
+            capabilities
+                .services
+                .iter()
+                .map(|(name, xaddr)| Record::new().field("name", name).field("xaddr", xaddr))
+                .collect::<Vec<_>>(),
+        )
+        .field(
+            "profiles",
+            profiles
+                .iter()
+                .map(|(profile, stream, describe)| {
+                    Record::new()
+                        .field("name", profile.name.as_str())
+                        .field("token", profile.token.as_str())
+                        .field("encoding", profile.encoding.as_deref())
+                        .field(
+                            "resolution",
+                            profile
+                                .resolution
+                                .as_ref()
+                                .map(|(width, height)| format!("{width}x{height}")),
+                        )
+                        .field("stream", stream.as_ref().ok())
+                        .field("error", stream.as_ref().err())
+                        .field("describe", describe.as_deref())
+                })
+                .collect::<Vec<_>>(),
+        );
+
+    out.record(&record, || {
+        println!("Manufacturer: {}", information.manufacturer);
+        println!("Model: {}", information.model);
+        println!("Firmware: {}", information.firmware_version);
+        println!("Serial number: {}", information.serial_number);
+        println!("Hardware ID: {}", information.hardware_id);
+
+        println!("Services:");
+        for (name, xaddr) in &capabilities.services {
+            println!("  {name}: {xaddr}");
+        }
+
+        println!("Profiles:");
+        for (profile, stream, describe) in &profiles {
+            println!("  {} ({})", profile.name, profile.token);
+            if let Some(encoding) = &profile.encoding {
+                println!("    Encoding: {encoding}");
+            }
+            if let Some((width, height)) = &profile.resolution {
+                println!("    Resolution: {width}x{height}");
+            }
+            match stream {
+                Ok(uri) => {
+                    println!("    Stream: {uri}");
+                    if let Some(describe) = describe {
+                        println!("    DESCRIBE: {describe}");
+                    }
+                }
+                Err(err) => println!("    Stream: unavailable ({err})"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn check_stream(
+    uri: &str,
+    (username, password): (Option<&str>, Option<&str>),
+    timeout: Duration,
+) -> String {
+    let url = match Url::parse(uri) {
+        Ok(url) => url,
+        Err(err) => return format!("invalid URI: {err}"),
+    };
+    match rtsp::describe(&url, username, password, timeout).await {
+        Ok(describe) => {
+            let media = describe
+                .sdp
+                .lines()
+                .filter(|line| line.starts_with("m="))
+                .count();
+            format!("{} {}, {media} media", describe.status, describe.reason)
+        }
+        Err(err) => format!("failed ({err})"),
+    }
+}
+
+/// Accepts a full device service URL or just a host, which gets the standard ONVIF path.
+fn device_url(device: &str) -> Result<Url, url::ParseError> {
+    if device.contains("://") {
+        Url::parse(device)
+    } else {
+        Url::parse(&format!("http://{device}/onvif/device_service"))
+    }
+}
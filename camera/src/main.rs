@@ -0,0 +1,5 @@
+#[tokio::main]
+async fn main() {
+    camera::run(edge_core::logging::parse("camera")).await;
+    edge_core::expect::finish();
+}
@@ -0,0 +1,398 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use edge_core::exit::{self, Code};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use url::Url;
+
+use crate::hash::sha1;
+use crate::http;
+
+const DISCOVERY_ADDRESS: &str = "239.255.255.250:3702";
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// A device that answered the WS-Discovery probe.
+pub struct DiscoveredDevice {
+    pub source: SocketAddr,
+    pub xaddrs: Vec<String>,
+    pub types: String,
+    pub scopes: Vec<String>,
+}
+
+impl DiscoveredDevice {
+    /// Value of an `onvif://www.onvif.org/<kind>/<value>` scope, e.g. name or hardware.
+    pub fn scope(&self, kind: &str) -> Option<String> {
+        let prefix = format!("onvif://www.onvif.org/{kind}/");
+        self.scopes
+            .iter()
+            .find_map(|scope| scope.strip_prefix(&prefix))
+            .map(percent_decode)
+    }
+}
+
+pub async fn discover(
+    timeout: Duration,
+) -> Result<Vec<DiscoveredDevice>, Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let message_id = uuid_like();
+    let probe = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:a="http://schemas.xmlsoap.org/ws/2004/08/addressing" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" xmlns:dn="http://www.onvif.org/ver10/network/wsdl">
+<s:Header>
+<a:Action s:mustUnderstand="1">http://schemas.xmlsoap.org/ws/2005/04/discovery/Probe</a:Action>
+<a:MessageID>uuid:{message_id}</a:MessageID>
+<a:To s:mustUnderstand="1">urn:schemas-xmlsoap-org:ws:2005:04:discovery</a:To>
+</s:Header>
+<s:Body><d:Probe><d:Types>dn:NetworkVideoTransmitter</d:Types></d:Probe></s:Body>
+</s:Envelope>"#
+    );
+    socket.send_to(probe.as_bytes(), DISCOVERY_ADDRESS).await?;
+
+    let mut devices: BTreeMap<String, DiscoveredDevice> = BTreeMap::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut buffer = vec![0u8; 65535];
+    loop {
+        let received = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await;
+        let (len, source) = match received {
+            Ok(result) => result?,
+            Err(_) => break,
+        };
+        let response = String::from_utf8_lossy(&buffer[..len]);
+        if let Some((key, device)) = probe_match(&response, source) {
+            devices.entry(key).or_insert(device);
+        }
+    }
+    Ok(devices.into_values().collect())
+}
+
+/// The device in a ProbeMatch answer, keyed on its endpoint address: devices often answer
+/// once per interface or repeat the match.
+fn probe_match(response: &str, source: SocketAddr) -> Option<(String, DiscoveredDevice)> {
+    if !response.contains("ProbeMatch") {
+        return None;
+    }
+    let words = |name| {
+        element_text(response, name)
+            .map(|text| text.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    };
+    let device = DiscoveredDevice {
+        source,
+        xaddrs: words("XAddrs"),
+        types: element_text(response, "Types").unwrap_or_default(),
+        scopes: words("Scopes"),
+    };
+    let key = element_text(response, "Address").unwrap_or_else(|| source.to_string());
+    Some((key, device))
+}
+
+pub struct DeviceInformation {
+    pub manufacturer: String,
+    pub model: String,
+    pub firmware_version: String,
+    pub serial_number: String,
+    pub hardware_id: String,
+}
+
+pub struct Capabilities {
+    /// Service name (Media, PTZ, Events, ...) to its XAddr.
+    pub services: BTreeMap<String, String>,
+}
+
+pub struct Profile {
+    pub token: String,
+    pub name: String,
+    pub encoding: Option<String>,
+    pub resolution: Option<(String, String)>,
+}
+
+pub struct Device {
+    url: Url,
+    credentials: Option<Credentials>,
+    timeout: Duration,
+}
+
+impl Device {
+    pub fn new(url: Url, credentials: Option<Credentials>, timeout: Duration) -> Device {
+        Device {
+            url,
+            credentials,
+            timeout,
+        }
+    }
+
+    pub async fn device_information(
+        &self,
+    ) -> Result<DeviceInformation, Box<dyn std::error::Error>> {
+        let body = r#"<GetDeviceInformation xmlns="http://www.onvif.org/ver10/device/wsdl"/>"#;
+        let response = self.call(&self.url, body).await?;
+        let field = |name| element_text(&response, name).unwrap_or_default();
+        Ok(DeviceInformation {
+            manufacturer: field("Manufacturer"),
+            model: field("Model"),
+            firmware_version: field("FirmwareVersion"),
+            serial_number: field("SerialNumber"),
+            hardware_id: field("HardwareId"),
+        })
+    }
+
+    pub async fn capabilities(&self) -> Result<Capabilities, Box<dyn std::error::Error>> {
+        let body = r#"<GetCapabilities xmlns="http://www.onvif.org/ver10/device/wsdl"><Category>All</Category></GetCapabilities>"#;
+        let response = self.call(&self.url, body).await?;
+
+        // <tt:Media><tt:XAddr>http://...</tt:XAddr>...
+        let pattern =
+            Regex::new(r"(?s)<(?:\w+:)?(\w+)>\s*<(?:\w+:)?XAddr>([^<]+)</(?:\w+:)?XAddr>")?;
+        let services = pattern
+            .captures_iter(&response)
+            .map(|c| (c[1].to_string(), c[2].trim().to_string()))
+            .collect();
+        Ok(Capabilities { services })
+    }
+
+    pub async fn profiles(&self, media: &Url) -> Result<Vec<Profile>, Box<dyn std::error::Error>> {
+        let body = r#"<GetProfiles xmlns="http://www.onvif.org/ver10/media/wsdl"/>"#;
+        let response = self.call(media, body).await?;
+
+        let profile_pattern = Regex::new(
+            r#"(?s)<(?:\w+:)?Profiles[^>]*token="([^"]+)"[^>]*>(.*?)</(?:\w+:)?Profiles>"#,
+        )?;
+        let mut profiles = Vec::new();
+        for capture in profile_pattern.captures_iter(&response) {
+            let profile = &capture[2];
+            let video = element_text(profile, "VideoEncoderConfiguration");
+            let resolution = match (
+                element_text(profile, "Width"),
+                element_text(profile, "Height"),
+            ) {
+                (Some(width), Some(height)) => Some((width, height)),
+                _ => None,
+            };
+            profiles.push(Profile {
+                token: capture[1].to_string(),
+                name: element_text(profile, "Name").unwrap_or_default(),
+                encoding: video.and(element_text(profile, "Encoding")),
+                resolution,
+            });
+        }
+        Ok(profiles)
+    }
+
+    pub async fn stream_uri(
+        &self,
+        media: &Url,
+        profile_token: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let body = format!(
+            r#"<GetStreamUri xmlns="http://www.onvif.org/ver10/media/wsdl"><StreamSetup><Stream xmlns="http://www.onvif.org/ver10/schema">RTP-Unicast</Stream><Transport xmlns="http://www.onvif.org/ver10/schema"><Protocol>RTSP</Protocol></Transport></StreamSetup><ProfileToken>{profile_token}</ProfileToken></GetStreamUri>"#
+        );
+        let response = self.call(media, &body).await?;
+        element_text(&response, "Uri")
+            .map(|uri| uri.replace("&amp;", "&"))
+            .ok_or_else(|| "GetStreamUri response without Uri".into())
+    }
+
+    async fn call(&self, url: &Url, body: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let envelope = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Header>{}</s:Header><s:Body>{}</s:Body></s:Envelope>"#,
+            self.security_header(),
+            body
+        );
+
+        let host = url.host_str().ok_or("device URL without host")?;
+        let host_port = format!("{host}:{}", url.port_or_known_default().unwrap_or(80));
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        let base_request = format!(
+            "POST {path} HTTP/1.1\r\nHost: {host_port}\r\nContent-Type: application/soap+xml; charset=utf-8\r\nConnection: close"
+        );
+
+        let mut response =
+            http::exchange(&host_port, &base_request, envelope.as_bytes(), self.timeout).await?;
+
+        // Some devices want HTTP digest on top of (or instead of) WS-Security.
+        if response.status == 401 {
+            if let (Some(credentials), Some(challenge)) =
+                (&self.credentials, response.header("www-authenticate"))
+            {
+                if let Some(auth) = http::authorization(
+                    challenge,
+                    "POST",
+                    &path,
+                    &credentials.username,
+                    &credentials.password,
+                ) {
+                    let request = format!("{base_request}\r\nAuthorization: {auth}");
+                    response =
+                        http::exchange(&host_port, &request, envelope.as_bytes(), self.timeout)
+                            .await?;
+                }
+            }
+        }
+
+        let text = response.body_text();
+        if response.status != 200 {
+            let reason = element_text(&text, "Text").unwrap_or(response.reason);
+            let code = match response.status {
+                401 | 403 => Code::Auth,
+                _ => Code::Protocol,
+            };
+            let message = format!("HTTP {} from {url}: {reason}", response.status);
+            return Err(exit::Error::new(code, message).into());
+        }
+        Ok(text)
+    }
+
+    /// WS-Security UsernameToken with a password digest.
+    fn security_header(&self) -> String {
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return String::new(),
+        };
+
+        let created = rfc3339_now();
+        let nonce = sha1(format!("{:?}{}", SystemTime::now(), std::process::id()).as_bytes());
+        let mut digest_input = nonce[..16].to_vec();
+        digest_input.extend_from_slice(created.as_bytes());
+        digest_input.extend_from_slice(credentials.password.as_bytes());
+        let digest = BASE64.encode(sha1(&digest_input));
+
+        format!(
+            r#"<wsse:Security xmlns:wsse="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd" xmlns:wsu="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd"><wsse:UsernameToken><wsse:Username>{}</wsse:Username><wsse:Password Type="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordDigest">{}</wsse:Password><wsse:Nonce EncodingType="http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-soap-message-security-1.0#Base64Binary">{}</wsse:Nonce><wsu:Created>{}</wsu:Created></wsse:UsernameToken></wsse:Security>"#,
+            credentials.username,
+            digest,
+            BASE64.encode(&nonce[..16]),
+            created
+        )
+    }
+}
+
+/// Text content of the first element with this local name, whatever its namespace prefix.
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    let pattern = format!(r"(?s)<(?:\w+:)?{name}(?:\s[^>]*)?>(.*?)</(?:\w+:)?{name}>");
+    let regex = Regex::new(&pattern).ok()?;
+    regex.captures(xml).map(|c| c[1].trim().to_string())
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let digits = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(digits, 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn uuid_like() -> String {
+    let hash = sha1(format!("{:?}{}", SystemTime::now(), std::process::id()).as_bytes());
+    let h = crate::hash::hex(&hash[..16]);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &h[0..8],
+        &h[8..12],
+        &h[12..16],
+        &h[16..20],
+        &h[20..32]
+    )
+}
+
+fn rfc3339_now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Civil from days, Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE_MATCH: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:wsa="http://schemas.xmlsoap.org/ws/2004/08/addressing" xmlns:d="http://schemas.xmlsoap.org/ws/2005/04/discovery" xmlns:dn="http://www.onvif.org/ver10/network/wsdl">
+<SOAP-ENV:Body><d:ProbeMatches><d:ProbeMatch>
+<wsa:EndpointReference><wsa:Address>urn:uuid:2419d68a-2dd2-21b2-a205-ec6e8a2d4f8b</wsa:Address></wsa:EndpointReference>
+<d:Types>dn:NetworkVideoTransmitter</d:Types>
+<d:Scopes>onvif://www.onvif.org/type/video_encoder onvif://www.onvif.org/name/Gate%20Camera onvif://www.onvif.org/hardware/DS-2CD2043G2</d:Scopes>
+<d:XAddrs>http://192.0.2.10/onvif/device_service http://[fe80::1]/onvif/device_service</d:XAddrs>
+<d:MetadataVersion>1</d:MetadataVersion>
+</d:ProbeMatch></d:ProbeMatches></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+
+    #[test]
+    fn reads_devices_from_probe_matches() {
+        let source = "192.0.2.10:3702".parse().unwrap();
+        let (key, device) = probe_match(PROBE_MATCH, source).unwrap();
+        assert_eq!(key, "urn:uuid:2419d68a-2dd2-21b2-a205-ec6e8a2d4f8b");
+        assert_eq!(device.types, "dn:NetworkVideoTransmitter");
+        assert_eq!(
+            device.xaddrs,
+            [
+                "http://192.0.2.10/onvif/device_service",
+                "http://[fe80::1]/onvif/device_service"
+            ]
+        );
+        assert_eq!(device.scope("name").as_deref(), Some("Gate Camera"));
+        assert_eq!(device.scope("hardware").as_deref(), Some("DS-2CD2043G2"));
+        assert_eq!(device.scope("location"), None);
+
+        // Without an endpoint address, the sender stands for the device.
+        let response = PROBE_MATCH.replace("wsa:Address", "wsa:Other");
+        let (key, _) = probe_match(&response, source).unwrap();
+        assert_eq!(key, "192.0.2.10:3702");
+
+        let probe = r#"<s:Envelope><s:Body><d:Probe/></s:Body></s:Envelope>"#;
+        assert!(probe_match(probe, source).is_none());
+    }
+
+    #[test]
+    fn finds_elements_whatever_their_prefix() {
+        let xml = r#"<tds:Manufacturer>ACME</tds:Manufacturer><Model attr="1"> X1 </Model>"#;
+        assert_eq!(element_text(xml, "Manufacturer").as_deref(), Some("ACME"));
+        assert_eq!(element_text(xml, "Model").as_deref(), Some("X1"));
+        assert_eq!(element_text(xml, "ModelName"), None);
+    }
+
+    #[test]
+    fn percent_decodes_scopes() {
+        assert_eq!(percent_decode("Gate%20Camera%2F1"), "Gate Camera/1");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%a\u{e9}"), "%zz%a\u{e9}");
+    }
+}
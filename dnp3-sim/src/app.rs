@@ -0,0 +1,487 @@
+//! DNP3 application layer: the point database and request handling.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AC_FIR: u8 = 0x80;
+const AC_FIN: u8 = 0x40;
+const AC_CON: u8 = 0x20;
+
+const FC_CONFIRM: u8 = 0;
+const FC_READ: u8 = 1;
+const FC_WRITE: u8 = 2;
+const FC_COLD_RESTART: u8 = 13;
+const FC_WARM_RESTART: u8 = 14;
+const FC_ENABLE_UNSOLICITED: u8 = 20;
+const FC_DISABLE_UNSOLICITED: u8 = 21;
+const FC_DELAY_MEASURE: u8 = 23;
+const FC_RESPONSE: u8 = 129;
+
+// IIN1
+const IIN1_CLASS1_EVENTS: u8 = 0x02;
+const IIN1_CLASS2_EVENTS: u8 = 0x04;
+const IIN1_DEVICE_RESTART: u8 = 0x80;
+// IIN2
+const IIN2_NO_FUNC_CODE_SUPPORT: u8 = 0x01;
+const IIN2_OBJECT_UNKNOWN: u8 = 0x02;
+const IIN2_PARAMETER_ERROR: u8 = 0x04;
+
+const FLAG_ONLINE: u8 = 0x01;
+const FLAG_STATE: u8 = 0x80;
+
+/// Leaves room for the response header in a 2048 byte fragment.
+const MAX_EVENT_BYTES: usize = 1800;
+
+pub struct AnalogPoint {
+    pub value: i32,
+    /// Last value reported as an event, for the deadband check.
+    reported: i32,
+}
+
+pub enum Event {
+    Binary { index: u16, value: bool, time: u64 },
+    Analog { index: u16, value: i32, time: u64 },
+}
+
+impl Event {
+    fn is_binary(&self) -> bool {
+        matches!(self, Event::Binary { .. })
+    }
+}
+
+pub struct Database {
+    pub binaries: Vec<bool>,
+    pub analogs: Vec<AnalogPoint>,
+    pub deadband: i32,
+    events: VecDeque<Event>,
+    max_events: usize,
+    restart: bool,
+}
+
+/// Events sent in a response that are only removed once the master confirms it.
+pub struct PendingConfirm {
+    sequence: u8,
+    binaries: usize,
+    analogs: usize,
+}
+
+impl Database {
+    pub fn new(binaries: usize, analogs: usize, deadband: i32, max_events: usize) -> Database {
+        Database {
+            binaries: vec![false; binaries],
+            analogs: (0..analogs)
+                .map(|_| AnalogPoint {
+                    value: 0,
+                    reported: 0,
+                })
+                .collect(),
+            deadband,
+            events: VecDeque::new(),
+            max_events,
+            restart: true,
+        }
+    }
+
+    pub fn set_binary(&mut self, index: usize, value: bool) {
+        if let Some(point) = self.binaries.get_mut(index) {
+            if *point != value {
+                *point = value;
+                self.push_event(Event::Binary {
+                    index: index as u16,
+                    value,
+                    time: now_millis(),
+                });
+            }
+        }
+    }
+
+    pub fn set_analog(&mut self, index: usize, value: i32) {
+        let deadband = self.deadband;
+        if let Some(point) = self.analogs.get_mut(index) {
+            point.value = value;
+            if (i64::from(value) - i64::from(point.reported)).abs() > i64::from(deadband) {
+                point.reported = value;
+                self.push_event(Event::Analog {
+                    index: index as u16,
+                    value,
+                    time: now_millis(),
+                });
+            }
+        }
+    }
+
+    fn push_event(&mut self, event: Event) {
+        if self.events.len() >= self.max_events {
+            log::warn!("Event buffer full, dropping oldest event");
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn event_count(&self) -> usize {
+        self.events.len()
+    }
+
+    fn iin(&self) -> [u8; 2] {
+        let mut iin1 = 0;
+        if self.restart {
+            iin1 |= IIN1_DEVICE_RESTART;
+        }
+        if self.events.iter().any(Event::is_binary) {
+            iin1 |= IIN1_CLASS1_EVENTS;
+        }
+        if self.events.iter().any(|e| !e.is_binary()) {
+            iin1 |= IIN1_CLASS2_EVENTS;
+        }
+        [iin1, 0]
+    }
+
+    /// Handles one request fragment, returning the response fragment if one is due.
+    pub fn handle(
+        &mut self,
+        request: &[u8],
+        pending: &mut Option<PendingConfirm>,
+    ) -> Option<Vec<u8>> {
+        if request.len() < 2 {
+            return None;
+        }
+        let (control, function) = (request[0], request[1]);
+        let sequence = control & 0x0f;
+        let objects = &request[2..];
+
+        if function == FC_CONFIRM {
+            if let Some(confirmed) = pending.take() {
+                if confirmed.sequence == sequence {
+                    self.clear_confirmed(&confirmed);
+                } else {
+                    log::debug!("Confirm for sequence {sequence} does not match pending");
+                    *pending = Some(confirmed);
+                }
+            }
+            return None;
+        }
+
+        let mut iin2 = 0;
+        let mut body = Vec::new();
+        let mut confirm = None;
+        match function {
+            FC_READ => match self.read(objects, &mut body) {
+                Ok(Some((binaries, analogs))) => {
+                    confirm = Some(PendingConfirm {
+                        sequence,
+                        binaries,
+                        analogs,
+                    })
+                }
+                Ok(None) => {}
+                Err(flags) => iin2 |= flags,
+            },
+            FC_WRITE => {
+                if let Err(flags) = self.write(objects) {
+                    iin2 |= flags;
+                }
+            }
+            FC_COLD_RESTART | FC_WARM_RESTART => {
+                // Time object g52v2: the master may poll again after 1 second.
+                body.extend_from_slice(&[52, 2, 0x07, 1]);
+                body.extend_from_slice(&1000u16.to_le_bytes());
+            }
+            FC_DELAY_MEASURE => {
+                body.extend_from_slice(&[52, 2, 0x07, 1]);
+                body.extend_from_slice(&0u16.to_le_bytes());
+            }
+            FC_ENABLE_UNSOLICITED | FC_DISABLE_UNSOLICITED => {
+                log::info!(
+                    "Ignoring unsolicited reporting request, the simulator only responds to polls"
+                );
+            }
+            other => {
+                log::info!("Function code {other} not supported");
+                iin2 |= IIN2_NO_FUNC_CODE_SUPPORT;
+            }
+        }
+
+        let mut control = AC_FIR | AC_FIN | sequence;
+        if confirm.is_some() {
+            control |= AC_CON;
+        }
+        *pending = confirm;
+
+        let iin = self.iin();
+        let mut response = vec![control, FC_RESPONSE, iin[0], iin[1] | iin2];
+        response.extend_from_slice(&body);
+        Some(response)
+    }
+
+    /// Returns how many binary and analog events went into the response.
+    fn read(&self, mut objects: &[u8], body: &mut Vec<u8>) -> Result<Option<(usize, usize)>, u8> {
+        let mut events = None;
+        while !objects.is_empty() {
+            let (group, variation, range, rest) = parse_header(objects)?;
+            objects = rest;
+
+            match (group, variation) {
+                // Class 0: all static data.
+                (60, 1) => {
+                    self.write_binaries(body, 0, self.binaries.len())?;
+                    self.write_analogs(body, 0, self.analogs.len())?;
+                }
+                // Classes 1, 2 and 3. Binaries report as class 1, analogs as class 2.
+                (60, 2..=4) => {
+                    let include_binaries = variation == 2;
+                    let include_analogs = variation == 3;
+                    let (b, a) = events.unwrap_or((0, 0));
+                    let (nb, na) = self.write_events(body, include_binaries, include_analogs);
+                    events = Some((b + nb, a + na));
+                }
+                (1, 0) | (1, 2) => {
+                    let (start, end) = range.resolve(self.binaries.len())?;
+                    self.write_binaries(body, start, end)?;
+                }
+                (30, 0) | (30, 1) => {
+                    let (start, end) = range.resolve(self.analogs.len())?;
+                    self.write_analogs(body, start, end)?;
+                }
+                (2, 0) | (2, 2) => {
+                    let (b, a) = events.unwrap_or((0, 0));
+                    let (nb, _) = self.write_events(body, true, false);
+                    events = Some((b + nb, a));
+                }
+                (32, 0) | (32, 3) => {
+                    let (b, a) = events.unwrap_or((0, 0));
+                    let (_, na) = self.write_events(body, false, true);
+                    events = Some((b, a + na));
+                }
+                _ => {
+                    log::info!("Read of unsupported object g{group}v{variation}");
+                    return Err(IIN2_OBJECT_UNKNOWN);
+                }
+            }
+        }
+        Ok(events.filter(|(b, a)| b + a > 0))
+    }
+
+    fn write(&mut self, mut objects: &[u8]) -> Result<(), u8> {
+        while !objects.is_empty() {
+            let (group, variation, range, rest) = parse_header(objects)?;
+            match (group, variation) {
+                // Internal indications: the master clears the restart bit (index 7).
+                (80, 1) => {
+                    let (start, end) = range.resolve(16)?;
+                    let count = end - start;
+                    let bytes = count.div_ceil(8);
+                    let values = rest.get(..bytes).ok_or(IIN2_PARAMETER_ERROR)?;
+                    for (i, index) in (start..end).enumerate() {
+                        let set = values[i / 8] & (1 << (i % 8)) != 0;
+                        if index == 7 && !set {
+                            log::info!("Master cleared the device restart bit");
+                            self.restart = false;
+                        }
+                    }
+                    objects = &rest[bytes..];
+                }
+                // Time and date: accepted, the simulator always uses its own clock.
+                (50, 1) => {
+                    objects = rest.get(6..).ok_or(IIN2_PARAMETER_ERROR)?;
+                }
+                _ => {
+                    log::info!("Write of unsupported object g{group}v{variation}");
+                    return Err(IIN2_OBJECT_UNKNOWN);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_binaries(&self, body: &mut Vec<u8>, start: usize, end: usize) -> Result<(), u8> {
+        if start >= end {
+            return Ok(());
+        }
+        // g1v2: binary input with flags, qualifier 0x01 (2 byte start/stop).
+        body.extend_from_slice(&[1, 2, 0x01]);
+        body.extend_from_slice(&(start as u16).to_le_bytes());
+        body.extend_from_slice(&((end - 1) as u16).to_le_bytes());
+        for value in &self.binaries[start..end] {
+            body.push(FLAG_ONLINE | if *value { FLAG_STATE } else { 0 });
+        }
+        Ok(())
+    }
+
+    fn write_analogs(&self, body: &mut Vec<u8>, start: usize, end: usize) -> Result<(), u8> {
+        if start >= end {
+            return Ok(());
+        }
+        // g30v1: 32-bit analog input with flags.
+        body.extend_from_slice(&[30, 1, 0x01]);
+        body.extend_from_slice(&(start as u16).to_le_bytes());
+        body.extend_from_slice(&((end - 1) as u16).to_le_bytes());
+        for point in &self.analogs[start..end] {
+            body.push(FLAG_ONLINE);
+            body.extend_from_slice(&point.value.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_events(&self, body: &mut Vec<u8>, binaries: bool, analogs: bool) -> (usize, usize) {
+        let mut budget = MAX_EVENT_BYTES.saturating_sub(body.len());
+        let mut binary_events = Vec::new();
+        let mut analog_events = Vec::new();
+
+        for event in &self.events {
+            match event {
+                Event::Binary { index, value, time } if binaries => {
+                    // index(2) + flags(1) + time(6)
+                    if budget < 9 {
+                        break;
+                    }
+                    budget -= 9;
+                    let mut encoded = index.to_le_bytes().to_vec();
+                    encoded.push(FLAG_ONLINE | if *value { FLAG_STATE } else { 0 });
+                    encoded.extend_from_slice(&time.to_le_bytes()[..6]);
+                    binary_events.push(encoded);
+                }
+                Event::Analog { index, value, time } if analogs => {
+                    // index(2) + flags(1) + value(4) + time(6)
+                    if budget < 13 {
+                        break;
+                    }
+                    budget -= 13;
+                    let mut encoded = index.to_le_bytes().to_vec();
+                    encoded.push(FLAG_ONLINE);
+                    encoded.extend_from_slice(&value.to_le_bytes());
+                    encoded.extend_from_slice(&time.to_le_bytes()[..6]);
+                    analog_events.push(encoded);
+                }
+                _ => {}
+            }
+        }
+
+        // g2v2 binary input event with absolute time, g32v3 analog event with time,
+        // both with qualifier 0x28 (2 byte count, 2 byte index prefix).
+        for (group, variation, events) in [(2, 2, &binary_events), (32, 3, &analog_events)] {
+            if events.is_empty() {
+                continue;
+            }
+            body.extend_from_slice(&[group, variation, 0x28]);
+            body.extend_from_slice(&(events.len() as u16).to_le_bytes());
+            for event in events.iter() {
+                body.extend_from_slice(event);
+            }
+        }
+        (binary_events.len(), analog_events.len())
+    }
+
+    fn clear_confirmed(&mut self, confirmed: &PendingConfirm) {
+        let (mut binaries, mut analogs) = (confirmed.binaries, confirmed.analogs);
+        self.events.retain(|event| match event {
+            Event::Binary { .. } if binaries > 0 => {
+                binaries -= 1;
+                false
+            }
+            Event::Analog { .. } if analogs > 0 => {
+                analogs -= 1;
+                false
+            }
+            _ => true,
+        });
+        log::debug!(
+            "Master confirmed {} binary and {} analog events",
+            confirmed.binaries,
+            confirmed.analogs
+        );
+    }
+}
+
+enum Range {
+    All,
+    StartStop(usize, usize),
+    Count(usize),
+}
+
+impl Range {
+    /// Converts to a half open index range, checked against the number of points.
+    fn resolve(&self, points: usize) -> Result<(usize, usize), u8> {
+        match *self {
+            Range::All => Ok((0, points)),
+            Range::StartStop(start, stop) if start <= stop && stop < points => {
+                Ok((start, stop + 1))
+            }
+            Range::Count(count) if count <= points => Ok((0, count)),
+            _ => Err(IIN2_PARAMETER_ERROR),
+        }
+    }
+}
+
+fn parse_header(objects: &[u8]) -> Result<(u8, u8, Range, &[u8]), u8> {
+    if objects.len() < 3 {
+        return Err(IIN2_PARAMETER_ERROR);
+    }
+    let (group, variation, qualifier) = (objects[0], objects[1], objects[2]);
+    let rest = &objects[3..];
+    let byte = |i: usize| {
+        rest.get(i)
+            .copied()
+            .map(usize::from)
+            .ok_or(IIN2_PARAMETER_ERROR)
+    };
+
+    let (range, rest) = match qualifier {
+        0x06 => (Range::All, rest),
+        0x00 => (Range::StartStop(byte(0)?, byte(1)?), &rest[2..]),
+        0x01 => (
+            Range::StartStop(byte(0)? | byte(1)? << 8, byte(2)? | byte(3)? << 8),
+            &rest[4..],
+        ),
+        0x07 => (Range::Count(byte(0)?), &rest[1..]),
+        0x08 => (Range::Count(byte(0)? | byte(1)? << 8), &rest[2..]),
+        other => {
+            log::info!("Unsupported qualifier {other:#04x} for g{group}v{variation}");
+            return Err(IIN2_PARAMETER_ERROR);
+        }
+    };
+    Ok((group, variation, range, rest))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_object_headers() {
+        let (group, variation, range, rest) = parse_header(&[30, 1, 0x00, 2, 5, 0xaa]).unwrap();
+        assert_eq!((group, variation, rest), (30, 1, &[0xaa][..]));
+        assert_eq!(range.resolve(10), Ok((2, 6)));
+        assert_eq!(range.resolve(5), Err(IIN2_PARAMETER_ERROR));
+
+        let (_, _, range, _) = parse_header(&[1, 2, 0x01, 0x00, 0x01, 0x01, 0x01]).unwrap();
+        assert_eq!(range.resolve(300), Ok((256, 258)));
+        let (_, _, range, _) = parse_header(&[60, 1, 0x06]).unwrap();
+        assert_eq!(range.resolve(3), Ok((0, 3)));
+
+        assert!(parse_header(&[30, 1]).is_err());
+        assert!(parse_header(&[30, 1, 0x00, 2]).is_err());
+        assert!(parse_header(&[30, 1, 0x17]).is_err());
+    }
+
+    #[test]
+    fn reports_analog_changes_beyond_the_deadband() {
+        let mut database = Database::new(1, 1, 10, 2);
+        database.set_analog(0, 10);
+        assert_eq!(database.event_count(), 0);
+        database.set_analog(0, 11);
+        assert_eq!(database.event_count(), 1);
+        database.set_binary(0, true);
+        database.set_binary(0, true);
+        assert_eq!(database.event_count(), 2);
+        // A full buffer drops its oldest event.
+        database.set_binary(0, false);
+        assert_eq!(database.event_count(), 2);
+    }
+}
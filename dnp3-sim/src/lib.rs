@@ -0,0 +1,270 @@
+mod app;
+pub mod link;
+
+use clap::{Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::Output;
+use rand::Rng;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use app::{Database, PendingConfirm};
+use link::{Reassembly, CTRL_DIR, CTRL_PRM};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Address to accept master connections on.
+    #[clap(short, long, env = "EDGE_DNP3_LISTEN", action)]
+    listen: Option<String>,
+
+    /// Link layer addresses.
+    #[clap(short, long, action)]
+    outstation_address: Option<u16>,
+
+    // Points
+    #[clap(short, long, action)]
+    binaries: Option<usize>,
+    #[clap(short, long, action)]
+    analogs: Option<usize>,
+    /// Initial analog value; each point starts here and random-walks from it.
+    #[clap(long, action)]
+    initial_analog: Option<i32>,
+    /// Analog changes larger than this generate an event.
+    #[clap(short, long, action)]
+    deadband: Option<i32>,
+
+    // Event generation
+    /// Milliseconds between simulated changes, 0 to keep all values static.
+    #[clap(short, long, action)]
+    interval: Option<u64>,
+    /// Largest step of the analog random walk.
+    #[clap(short, long, action)]
+    step: Option<i32>,
+    /// Events kept until the master reads and confirms them.
+    #[clap(short, long, action)]
+    max_events: Option<usize>,
+
+    #[clap(subcommand)]
+    command: Option<Subcommands>,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Ask the simulator running on --listen for its link status and exit 0 if it answers, for
+    /// container and systemd health probes.
+    Healthcheck {
+        /// Give up after this long, e.g. 2s; 5s by default.
+        #[clap(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+pub async fn run(cli: Args) {
+    if let Some(Subcommands::Completions { shell }) = cli.command {
+        completions::print::<Args>(shell, "dnp3-sim");
+        return;
+    }
+    if let Some(Subcommands::GenMan { directory }) = &cli.command {
+        man::generate::<Args>("dnp3-sim", directory.as_deref());
+        return;
+    }
+
+    let listen = cli.listen.as_deref().unwrap_or("0.0.0.0:20000");
+    let listen = match listen.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(err) => exit::fatal_with(
+            Code::Usage,
+            format!("Unable to parse listen address {listen}: {err}"),
+        ),
+    };
+    let outstation_address = cli.outstation_address.unwrap_or(10);
+    if let Some(Subcommands::Healthcheck { timeout }) = cli.command {
+        let mut target = listen;
+        if target.ip().is_unspecified() {
+            target.set_ip([127, 0, 0, 1].into());
+        }
+        health::check(&Output::new(None), &target.to_string(), timeout, async {
+            match link_status(target, outstation_address).await {
+                Ok(()) => Ok(format!(
+                    "outstation {outstation_address} answered REQUEST LINK STATUS"
+                )),
+                Err(err) => Err(err as Box<dyn std::error::Error>),
+            }
+        })
+        .await;
+    }
+
+    let mut database = Database::new(
+        cli.binaries.unwrap_or(8),
+        cli.analogs.unwrap_or(8),
+        cli.deadband.unwrap_or(0),
+        cli.max_events.unwrap_or(1000),
+    );
+    let initial_analog = cli.initial_analog.unwrap_or(0);
+    for point in database.analogs.iter_mut() {
+        point.value = initial_analog;
+    }
+    let database = Arc::new(Mutex::new(database));
+
+    let interval = cli.interval.unwrap_or(1000);
+    if interval > 0 {
+        let database = database.clone();
+        let step = cli.step.unwrap_or(10).max(1);
+        tokio::spawn(generate_events(
+            database,
+            Duration::from_millis(interval),
+            step,
+        ));
+    }
+
+    let listener = match TcpListener::bind(listen).await {
+        Ok(listener) => listener,
+        Err(err) => exit::fatal_error(format!("Unable to bind {listen}"), &err),
+    };
+    log::info!("DNP3 outstation {outstation_address} listening on {listen}");
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log::warn!("Accept failed: {err}");
+                continue;
+            }
+        };
+        log::info!("Master connected from {peer}");
+        let database = database.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve(stream, database, outstation_address).await {
+                log::info!("Master {peer} disconnected: {err}");
+            }
+        });
+    }
+}
+
+/// Link status round trip with the outstation at `address`, as master 1.
+async fn link_status(
+    address: SocketAddr,
+    outstation: u16,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut stream = TcpStream::connect(address).await?;
+    let request = link::encode_frame(
+        CTRL_DIR | CTRL_PRM | link::FC_REQUEST_LINK_STATUS,
+        outstation,
+        1,
+        &[],
+    );
+    stream.write_all(&request).await?;
+    loop {
+        let frame = link::read_frame(&mut stream).await?;
+        if frame.control & CTRL_PRM == 0 && frame.source == outstation {
+            return match frame.function() {
+                link::FC_LINK_STATUS => Ok(()),
+                function => Err(exit::Error::new(
+                    Code::Protocol,
+                    format!("answered link function {function} instead of LINK STATUS"),
+                )
+                .into()),
+            };
+        }
+    }
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    database: Arc<Mutex<Database>>,
+    our_address: u16,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut reassembly = Reassembly::default();
+    let mut pending: Option<PendingConfirm> = None;
+    let mut transport_sequence = 0u8;
+
+    loop {
+        let frame = link::read_frame(&mut stream).await?;
+        // Broadcast addresses are 0xfffd-0xffff; we never answer those.
+        let broadcast = frame.destination >= 0xfffd;
+        if frame.destination != our_address && !broadcast {
+            log::debug!("Ignoring frame for address {}", frame.destination);
+            continue;
+        }
+        if frame.control & CTRL_DIR == 0 || frame.control & CTRL_PRM == 0 {
+            log::debug!("Ignoring non-primary frame from {}", frame.source);
+            continue;
+        }
+
+        if !broadcast {
+            if let Some(response) = link::link_response(&frame, our_address) {
+                stream.write_all(&response).await?;
+            }
+        }
+        if frame.function() != link::FC_CONFIRMED_USER_DATA
+            && frame.function() != link::FC_UNCONFIRMED_USER_DATA
+        {
+            continue;
+        }
+
+        let fragment = match reassembly.push(&frame.data) {
+            Some(fragment) => fragment,
+            None => continue,
+        };
+        log::trace!("Request {:02x?}", fragment);
+
+        let response = {
+            let mut database = database.lock().unwrap();
+            database.handle(&fragment, &mut pending)
+        };
+        if let (Some(response), false) = (response, broadcast) {
+            log::trace!("Response {:02x?}", response);
+            let frames = link::encode_apdu(
+                &response,
+                frame.source,
+                our_address,
+                &mut transport_sequence,
+            );
+            stream.write_all(&frames).await?;
+        }
+    }
+}
+
+async fn generate_events(database: Arc<Mutex<Database>>, interval: Duration, step: i32) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut rng = rand::thread_rng();
+        let mut database = database.lock().unwrap();
+
+        if !database.binaries.is_empty() && rng.gen_bool(0.3) {
+            let index = rng.gen_range(0..database.binaries.len());
+            let value = !database.binaries[index];
+            log::debug!("Binary {index} -> {value}");
+            database.set_binary(index, value);
+        }
+        for index in 0..database.analogs.len() {
+            let value = database.analogs[index]
+                .value
+                .saturating_add(rng.gen_range(-step..=step));
+            database.set_analog(index, value);
+        }
+        log::trace!("{} events buffered", database.event_count());
+    }
+}
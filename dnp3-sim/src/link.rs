@@ -0,0 +1,276 @@
+//! DNP3 data link and transport layer framing.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const START: [u8; 2] = [0x05, 0x64];
+const BLOCK_SIZE: usize = 16;
+/// Maximum user data in one link frame, including the transport header.
+const MAX_USER_DATA: usize = 250;
+
+pub const CTRL_DIR: u8 = 0x80;
+pub const CTRL_PRM: u8 = 0x40;
+
+// Primary function codes, master to outstation.
+pub const FC_RESET_LINK_STATES: u8 = 0;
+pub const FC_TEST_LINK_STATES: u8 = 2;
+pub const FC_CONFIRMED_USER_DATA: u8 = 3;
+pub const FC_UNCONFIRMED_USER_DATA: u8 = 4;
+pub const FC_REQUEST_LINK_STATUS: u8 = 9;
+
+// Secondary function codes, outstation to master.
+const FC_ACK: u8 = 0;
+const FC_NOT_SUPPORTED: u8 = 15;
+pub const FC_LINK_STATUS: u8 = 11;
+
+const TRANSPORT_FIN: u8 = 0x80;
+const TRANSPORT_FIR: u8 = 0x40;
+
+pub struct Frame {
+    pub control: u8,
+    pub destination: u16,
+    pub source: u16,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn function(&self) -> u8 {
+        self.control & 0x0f
+    }
+}
+
+/// Reads one link frame, validating the header and block CRCs.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Frame, Box<dyn std::error::Error + Send + Sync>> {
+    // Resynchronise on the start bytes in case the master sent garbage.
+    let mut previous = 0u8;
+    loop {
+        let byte = reader.read_u8().await?;
+        if previous == START[0] && byte == START[1] {
+            break;
+        }
+        previous = byte;
+    }
+
+    let mut header = [0u8; 10];
+    header[..2].copy_from_slice(&START);
+    reader.read_exact(&mut header[2..]).await?;
+    if crc(&header[..8]).to_le_bytes() != header[8..10] {
+        return Err("link header CRC mismatch".into());
+    }
+
+    let length = header[2] as usize;
+    if length < 5 {
+        return Err(format!("invalid link frame length {length}").into());
+    }
+    let mut remaining = length - 5;
+    let mut data = Vec::with_capacity(remaining);
+    while remaining > 0 {
+        let block_len = remaining.min(BLOCK_SIZE);
+        let mut block = vec![0u8; block_len + 2];
+        reader.read_exact(&mut block).await?;
+        if crc(&block[..block_len]).to_le_bytes() != block[block_len..] {
+            return Err("link data block CRC mismatch".into());
+        }
+        data.extend_from_slice(&block[..block_len]);
+        remaining -= block_len;
+    }
+
+    Ok(Frame {
+        control: header[3],
+        destination: u16::from_le_bytes([header[4], header[5]]),
+        source: u16::from_le_bytes([header[6], header[7]]),
+        data,
+    })
+}
+
+pub fn encode_frame(control: u8, destination: u16, source: u16, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(10 + data.len() + 2 * data.len().div_ceil(BLOCK_SIZE));
+    frame.extend_from_slice(&START);
+    frame.push((5 + data.len()) as u8);
+    frame.push(control);
+    frame.extend_from_slice(&destination.to_le_bytes());
+    frame.extend_from_slice(&source.to_le_bytes());
+    let header_crc = crc(&frame);
+    frame.extend_from_slice(&header_crc.to_le_bytes());
+
+    for block in data.chunks(BLOCK_SIZE) {
+        frame.extend_from_slice(block);
+        frame.extend_from_slice(&crc(block).to_le_bytes());
+    }
+    frame
+}
+
+/// Secondary frame answering a link layer service request, if it needs one.
+pub fn link_response(request: &Frame, our_address: u16) -> Option<Vec<u8>> {
+    let function = match request.function() {
+        FC_RESET_LINK_STATES | FC_TEST_LINK_STATES | FC_CONFIRMED_USER_DATA => FC_ACK,
+        FC_REQUEST_LINK_STATUS => FC_LINK_STATUS,
+        FC_UNCONFIRMED_USER_DATA => return None,
+        _ => FC_NOT_SUPPORTED,
+    };
+    Some(encode_frame(function, request.source, our_address, &[]))
+}
+
+/// Splits an application fragment into transport segments wrapped in link frames.
+pub fn encode_apdu(apdu: &[u8], destination: u16, source: u16, sequence: &mut u8) -> Vec<u8> {
+    let mut out = Vec::new();
+    let chunks: Vec<&[u8]> = apdu.chunks(MAX_USER_DATA - 1).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut header = *sequence & 0x3f;
+        if i == 0 {
+            header |= TRANSPORT_FIR;
+        }
+        if i == chunks.len() - 1 {
+            header |= TRANSPORT_FIN;
+        }
+        *sequence = sequence.wrapping_add(1) & 0x3f;
+
+        let mut data = Vec::with_capacity(chunk.len() + 1);
+        data.push(header);
+        data.extend_from_slice(chunk);
+        out.extend(encode_frame(
+            CTRL_PRM | FC_UNCONFIRMED_USER_DATA,
+            destination,
+            source,
+            &data,
+        ));
+    }
+    out
+}
+
+/// Reassembles transport segments into application fragments.
+#[derive(Default)]
+pub struct Reassembly {
+    buffer: Vec<u8>,
+    in_progress: bool,
+}
+
+impl Reassembly {
+    pub fn push(&mut self, segment: &[u8]) -> Option<Vec<u8>> {
+        let (&header, payload) = segment.split_first()?;
+        if header & TRANSPORT_FIR != 0 {
+            self.buffer.clear();
+            self.in_progress = true;
+        } else if !self.in_progress {
+            log::debug!("Dropping transport segment without a first segment");
+            return None;
+        }
+        self.buffer.extend_from_slice(payload);
+
+        if header & TRANSPORT_FIN != 0 {
+            self.in_progress = false;
+            return Some(std::mem::take(&mut self.buffer));
+        }
+        None
+    }
+}
+
+/// CRC-16/DNP: reflected polynomial 0x3D65, complemented, transmitted little endian.
+fn crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xa6bc;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_dnp_crc() {
+        assert_eq!(crc(b"123456789"), 0xea82);
+        // Reset link states from master 1024 to outstation 1, as captured on the wire.
+        assert_eq!(
+            encode_frame(CTRL_DIR | CTRL_PRM | FC_RESET_LINK_STATES, 1, 1024, &[]),
+            [0x05, 0x64, 0x05, 0xc0, 0x01, 0x00, 0x00, 0x04, 0xe9, 0x21]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_encoded_frames() {
+        let data: Vec<u8> = (0..40).collect();
+        let mut wire = vec![0xff, 0x05, 0x00];
+        wire.extend(encode_frame(
+            CTRL_PRM | FC_UNCONFIRMED_USER_DATA,
+            10,
+            3,
+            &data,
+        ));
+        // Three blocks of 16, 16 and 8 bytes, each with its CRC.
+        assert_eq!(wire.len(), 3 + 10 + 40 + 3 * 2);
+
+        let frame = read_frame(&mut wire.as_slice()).await.unwrap();
+        assert_eq!(frame.function(), FC_UNCONFIRMED_USER_DATA);
+        assert_eq!((frame.destination, frame.source), (10, 3));
+        assert_eq!(frame.data, data);
+    }
+
+    #[tokio::test]
+    async fn refuses_frames_with_a_bad_crc() {
+        let frame = encode_frame(CTRL_PRM | FC_UNCONFIRMED_USER_DATA, 10, 3, &[1, 2, 3]);
+
+        let mut header = frame.clone();
+        header[4] ^= 1;
+        let err = read_frame(&mut header.as_slice()).await.err().unwrap();
+        assert!(err.to_string().contains("header CRC"), "{err}");
+
+        let mut block = frame;
+        block[10] ^= 1;
+        let err = read_frame(&mut block.as_slice()).await.err().unwrap();
+        assert!(err.to_string().contains("block CRC"), "{err}");
+    }
+
+    #[test]
+    fn answers_link_services() {
+        let request = |function| Frame {
+            control: CTRL_DIR | CTRL_PRM | function,
+            destination: 1,
+            source: 1024,
+            data: Vec::new(),
+        };
+        let status = link_response(&request(FC_REQUEST_LINK_STATUS), 1).unwrap();
+        assert_eq!(status[3], FC_LINK_STATUS);
+        assert_eq!(status[4..8], [0x00, 0x04, 0x01, 0x00]);
+        assert_eq!(
+            link_response(&request(FC_RESET_LINK_STATES), 1).unwrap()[3],
+            FC_ACK
+        );
+        assert_eq!(link_response(&request(7), 1).unwrap()[3], FC_NOT_SUPPORTED);
+        assert!(link_response(&request(FC_UNCONFIRMED_USER_DATA), 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn splits_and_reassembles_fragments() {
+        let apdu: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let mut sequence = 62;
+        let wire = encode_apdu(&apdu, 1024, 1, &mut sequence);
+        assert_eq!(sequence, 1);
+
+        let mut reader = wire.as_slice();
+        let mut reassembly = Reassembly::default();
+        let mut segments = Vec::new();
+        let fragment = loop {
+            let frame = read_frame(&mut reader).await.unwrap();
+            segments.push(frame.data[0]);
+            if let Some(fragment) = reassembly.push(&frame.data) {
+                break fragment;
+            }
+        };
+        assert_eq!(fragment, apdu);
+        assert_eq!(segments, [0x40 | 62, 63, 0x80]);
+        assert!(reader.is_empty());
+
+        // A segment that isn't the first of a fragment is dropped.
+        assert!(Reassembly::default().push(&[0x80, 1, 2]).is_none());
+    }
+}
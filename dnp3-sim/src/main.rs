@@ -0,0 +1,5 @@
+#[tokio::main]
+async fn main() {
+    dnp3_sim::run(edge_core::logging::parse("dnp3-sim")).await;
+    edge_core::expect::finish();
+}
@@ -0,0 +1,459 @@
+//! `edge agent`: runs diagnostics on a gateway when asked over NATS, so an engineer at the
+//! central site doesn't need SSH to it:
+//!
+//! ```text
+//! $ edge agent nats://hub:4222                 # on the gateway, as a service
+//! $ nats req edge.agent.gw-7 'modbus 10.0.0.5 read-register -r 40070 -k holding --output json'
+//! {"command":"modbus 10.0.0.5 read-register ...","status":0,"stdout":"...","stderr":""}
+//! ```
+//!
+//! A request is one command line, split the way `edge run` splits a script line, and only
+//! runs when its tool and subcommand are allowed: reading registers, sampling a subject and
+//! health checks unless `--allow` says otherwise. So are its options: those of the defaults
+//! that only shape what's read and printed, plus any `--allow` names, so nothing a request
+//! sends can write files, read local secrets, listen on ports, send data elsewhere or run
+//! commands unless the agent was started to let it. The reply is a JSON record with the exit
+//! status and what the command printed. Requests go in the [`edge_core::audit`] log, and so do
+//! the writes their commands make.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueSource;
+use edge_core::audit;
+use edge_core::config;
+use edge_core::exit::{self, Code};
+use edge_core::output::{self, Record, Value};
+use edge_core::OrExit;
+use futures::StreamExt;
+use nats::client::{self, Client, Options};
+use tokio::sync::Semaphore;
+
+use crate::batch;
+
+const DEFAULT_ALLOWED: [(&str, &str); 4] = [
+    ("modbus", "read-register"),
+    ("modbus", "healthcheck"),
+    ("nats", "subscribe"),
+    ("nats", "healthcheck"),
+];
+
+// The options, by long name, and positional arguments, by name, a request may give a tool
+// before its subcommand. Anything reading files such as profiles, password files and
+// certificates is left out, and so is anything writing them, listening on a port or sending
+// data elsewhere.
+const TOOL_OPTIONS: [(&str, &[&str]); 2] = [
+    (
+        "modbus",
+        &[
+            "address",
+            "secondary",
+            "failback",
+            "output",
+            "timestamps",
+            "retries",
+            "retry-delay",
+            "max-rate",
+            "dry-run",
+            "parallel",
+        ],
+    ),
+    (
+        "nats",
+        &[
+            "address",
+            "secondary",
+            "username",
+            "password",
+            "token",
+            "nkey",
+            "tls",
+            "max-reconnects",
+            "ping-interval",
+            "send-buffer",
+            "verbose",
+            "output",
+            "timestamps",
+            "retries",
+            "retry-delay",
+            "max-rate",
+            "dry-run",
+        ],
+    ),
+];
+
+// The same for the subcommands the agent knows, those that only read; others get only what
+// `--allow` lists. Options a subcommand flattens in, such as `--file` and `--file-spill` from
+// `edge_core::file_sink::FileArgs`, stay out until they're listed here.
+const SUBCOMMAND_OPTIONS: [(&str, &str, &[&str]); 4] = [
+    (
+        "modbus",
+        "read-register",
+        &[
+            "register",
+            "kind",
+            "unit-id",
+            "count",
+            "presentation",
+            "watch",
+            "interval",
+            "reads",
+            "duration",
+            "stats",
+        ],
+    ),
+    (
+        "modbus",
+        "healthcheck",
+        &["register", "kind", "unit-id", "timeout"],
+    ),
+    (
+        "nats",
+        "subscribe",
+        &[
+            "subject",
+            "watch",
+            "count",
+            "duration",
+            "timeout",
+            "stream",
+            "consumer",
+            "batch",
+            "encoding",
+            "grep",
+            "jsonpath",
+            "invert",
+            "queue",
+            "max-payload",
+            "overflow",
+            "stats",
+        ],
+    ),
+    ("nats", "healthcheck", &["timeout"]),
+];
+
+// Commands running at once; further requests wait for one to finish.
+const CONCURRENT: usize = 4;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// NATS server to take requests from, optional when the profile has one.
+    #[clap(value_parser, env = "EDGE_NATS_URL")]
+    address: Option<String>,
+    /// Named profile for the server's address, credentials and TLS.
+    #[clap(long, env = "EDGE_PROFILE", action)]
+    profile: Option<String>,
+    /// Subject to take requests on; edge.agent.<hostname> by default.
+    #[clap(short, long, action)]
+    subject: Option<String>,
+    /// Allow a tool's subcommand, with the options after a second colon, e.g. --allow
+    /// nats:request:subject,message,timeout; may be repeated and replaces the default of
+    /// modbus:read-register, modbus:healthcheck, nats:subscribe and nats:healthcheck. Those
+    /// take their read-only options without listing them.
+    #[clap(
+        long = "allow",
+        value_name = "TOOL:SUBCOMMAND[:OPTION,...]",
+        value_parser = parse_allow,
+        action
+    )]
+    allowed: Vec<Allowed>,
+    /// Kill a command that hasn't finished after this long (default 30s).
+    #[clap(long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+}
+
+struct Agent {
+    edge: PathBuf,
+    command: clap::Command<'static>,
+    allowed: Vec<Allowed>,
+    timeout: Duration,
+}
+
+/// A subcommand requests may run, with the options they may give it beyond the tool's.
+#[derive(Clone, Debug)]
+struct Allowed {
+    tool: String,
+    subcommand: String,
+    options: Vec<String>,
+}
+
+impl Allowed {
+    fn new(tool: &str, subcommand: &str, options: &[&str]) -> Allowed {
+        let for_tool = TOOL_OPTIONS
+            .iter()
+            .filter(|(known, _)| *known == tool)
+            .flat_map(|(_, options)| options.iter());
+        let for_subcommand = SUBCOMMAND_OPTIONS
+            .iter()
+            .filter(|(known_tool, known, _)| *known_tool == tool && *known == subcommand)
+            .flat_map(|(_, _, options)| options.iter());
+        Allowed {
+            tool: tool.to_string(),
+            subcommand: subcommand.to_string(),
+            options: for_tool
+                .chain(for_subcommand)
+                .chain(options)
+                .map(|option| option.to_string())
+                .collect(),
+        }
+    }
+
+    /// Whether a request may give the argument `name`, an option's long name or a positional
+    /// argument's, anywhere on the command line.
+    fn permits(&self, name: &str) -> bool {
+        name == "log-level" || self.options.iter().any(|option| option == name)
+    }
+}
+
+/// Serves requests until the connection is lost. `command` is edge's own, for checking a
+/// request parses before anything runs.
+pub async fn run(args: Args, command: clap::Command<'static>) {
+    let profile = config::load_profile(args.profile.as_deref())
+        .or_exit_with(Code::Usage, "Unable to load profile");
+    let address = match args.address {
+        Some(address) => address,
+        None => profile
+            .address()
+            .or_exit_with(Code::Usage, "Unable to read profile")
+            .or_exit_with(
+                Code::Usage,
+                "No address given on the command line or in the profile.",
+            ),
+    };
+    let options = Options::new(address)
+        .with_profile(&profile)
+        .or_exit_with(Code::Usage, "Unable to read profile");
+    let subject = args.subject.unwrap_or_else(|| {
+        let hostname = edge_core::net::hostname();
+        if hostname.is_empty() {
+            exit::fatal_with(
+                Code::Usage,
+                "No hostname to name the subject after; pass --subject",
+            );
+        }
+        format!("edge.agent.{hostname}")
+    });
+    let allowed = match args.allowed.is_empty() {
+        true => DEFAULT_ALLOWED
+            .iter()
+            .map(|(tool, subcommand)| Allowed::new(tool, subcommand, &[]))
+            .collect(),
+        false => args.allowed,
+    };
+    let agent = Arc::new(Agent {
+        edge: std::env::current_exe()
+            .unwrap_or_else(|err| exit::fatal_error("Unable to find the edge binary", &err)),
+        command,
+        allowed,
+        timeout: args.timeout.unwrap_or(Duration::from_secs(30)),
+    });
+
+    let connection = match client::connect(&options).await {
+        Ok(connection) => connection,
+        Err(err) => exit::fatal_error("Unable to connect to remote", err.as_ref()),
+    };
+    let mut requests = connection
+        .subscribe(subject.clone())
+        .await
+        .unwrap_or_else(|err| {
+            exit::fatal_with(Code::Subscribe, format!("Unable to subscribe: {err}"))
+        });
+    connection.flush().await.unwrap_or_else(|err| {
+        exit::fatal_with(Code::Connection, format!("Unable to flush: {err}"))
+    });
+    log::info!("Taking requests on {subject}");
+
+    let running = Arc::new(Semaphore::new(CONCURRENT));
+    while let Some(message) = requests.next().await {
+        let Some(reply) = message.reply else {
+            log::warn!(
+                "Ignoring a request on {} without a reply subject",
+                message.subject
+            );
+            continue;
+        };
+        let permit = running
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let agent = agent.clone();
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let line = String::from_utf8_lossy(&message.payload).into_owned();
+            let client = format!("agent request on {}", message.subject);
+            let record = agent.handle(&line, &client).await;
+            audit::record_for(Some(&client), "command", line.trim(), &outcome(&record));
+            drop(permit);
+            answer(&connection, &reply, record).await;
+        });
+    }
+    exit::fatal_with(Code::Connection, "Lost the connection to the server");
+}
+
+impl Agent {
+    /// Runs the command in `line` for `client`, whose writes the audit log puts down to them.
+    async fn handle(&self, line: &str, client: &str) -> Record {
+        let record = Record::new().field("command", line.trim());
+        let words = match batch::split(line, &HashMap::new()) {
+            Ok(words) => words,
+            Err(err) => return refused(record, err),
+        };
+        if let Err(err) = self.check(&words) {
+            log::warn!("Refused `{}`: {err}", line.trim());
+            return refused(record, err);
+        }
+        if let Err(err) = audit::ready() {
+            return refused(record, err);
+        }
+        log::info!("Running `{}`", line.trim());
+        match run_command(&self.edge, &words, self.timeout, client).await {
+            Ok((status, stdout, stderr)) => record
+                .field("status", status)
+                .field("stdout", stdout)
+                .field("stderr", stderr),
+            Err(err) => record
+                .field("status", err.code.status())
+                .field("error", err.to_string()),
+        }
+    }
+
+    /// Whether `words` are an allowed subcommand that parses, with only allowed options.
+    fn check(&self, words: &[String]) -> Result<(), String> {
+        let parsed = self
+            .command
+            .clone()
+            .try_get_matches_from(std::iter::once("edge").chain(words.iter().map(String::as_str)))
+            .map_err(|err| {
+                let message = err.to_string();
+                let first = message.lines().next().unwrap_or_default();
+                first.trim_start_matches("error: ").to_string()
+            })?;
+        let Some((tool, matches)) = parsed.subcommand() else {
+            return Err("expected a tool".to_string());
+        };
+        let Some(subcommand) = matches.subcommand_name() else {
+            return Err(format!("expected a {tool} subcommand"));
+        };
+        let Some(allowed) = self
+            .allowed
+            .iter()
+            .find(|allowed| allowed.tool == tool && allowed.subcommand == subcommand)
+        else {
+            return Err(format!("{tool} {subcommand} isn't allowed"));
+        };
+        // Every level of the command line, from edge's own options down, with what was given
+        // there.
+        let mut levels = vec![(&self.command, &parsed)];
+        while let Some((command, matches)) = levels.last().copied() {
+            let Some((name, below)) = matches.subcommand() else {
+                break;
+            };
+            let command = command
+                .find_subcommand(name)
+                .expect("clap matched a subcommand it knows");
+            levels.push((command, below));
+        }
+        for (command, matches) in levels {
+            for arg in command.get_arguments() {
+                let name = arg.get_long().unwrap_or_else(|| arg.get_id());
+                // Help and version aren't in the matches of every level.
+                let given = matches!(matches.try_contains_id(arg.get_id()), Ok(true))
+                    && matches.value_source(arg.get_id()) == Some(ValueSource::CommandLine);
+                if given && !allowed.permits(name) {
+                    let option = match arg.get_long() {
+                        Some(long) => format!("--{long}"),
+                        None => format!("<{}>", name.to_uppercase()),
+                    };
+                    return Err(format!("{option} isn't allowed from the agent"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `edge` with `words`, returning its exit status and output.
+async fn run_command(
+    edge: &Path,
+    words: &[String],
+    timeout: Duration,
+    client: &str,
+) -> Result<(i32, String, String), exit::Error> {
+    let child = tokio::process::Command::new(edge)
+        .args(words)
+        .env(audit::CLIENT, client)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| exit::Error::new(Code::Failure, format!("Unable to start edge: {err}")))?;
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            exit::Error::new(
+                Code::Timeout,
+                format!("Killed after {}", humantime::format_duration(timeout)),
+            )
+        })?
+        .map_err(|err| exit::Error::new(Code::Failure, format!("Unable to run edge: {err}")))?;
+    Ok((
+        output.status.code().unwrap_or(Code::Failure.status()),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+/// How a request went, for the audit log.
+fn outcome(record: &Record) -> Result<(), String> {
+    match (record.get("status"), record.get("error")) {
+        (_, Some(Value::String(error))) => Err(error.clone()),
+        (Some(Value::Integer(0)) | None, _) => Ok(()),
+        (Some(Value::Integer(status)), _) => Err(format!("exit status {status}")),
+        (Some(_), _) => Err("failed".to_string()),
+    }
+}
+
+fn refused(record: Record, reason: String) -> Record {
+    record
+        .field("status", Code::Usage.status())
+        .field("error", reason)
+}
+
+async fn answer(connection: &Client, reply: &str, record: Record) {
+    let payload = output::json(&Value::Record(record)).into_bytes();
+    // Not through `client::publish`: replies go to whoever asked, which `[writes]` and
+    // `--read-only` don't cover.
+    let answered = match connection.publish(reply.to_string(), payload.into()).await {
+        Ok(()) => connection.flush().await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    if let Err(err) = answered {
+        log::error!("Unable to answer on {reply}: {err}");
+    }
+}
+
+/// `tool:subcommand`, optionally with `:option,...`, for `--allow`.
+fn parse_allow(value: &str) -> Result<Allowed, String> {
+    let mut parts = value.splitn(3, ':');
+    let (tool, subcommand) = (parts.next().unwrap_or_default(), parts.next());
+    let options: Vec<&str> = match parts.next() {
+        Some(options) => options
+            .split(',')
+            .map(|option| option.trim().trim_start_matches("--"))
+            .filter(|option| !option.is_empty())
+            .collect(),
+        None => Vec::new(),
+    };
+    match subcommand {
+        Some(subcommand) if !tool.is_empty() && !subcommand.is_empty() => {
+            Ok(Allowed::new(tool, subcommand, &options))
+        }
+        _ => Err(format!(
+            "expected TOOL:SUBCOMMAND[:OPTION,...], got `{value}`"
+        )),
+    }
+}
@@ -0,0 +1,544 @@
+//! `edge api`: serves register reads and writes and subject samples over HTTP, so a local HMI
+//! or script can use the tools' Modbus and NATS clients without starting a process per call:
+//!
+//! ```text
+//! $ EDGE_API_TOKEN=s3cret edge api --api-listen 127.0.0.1:8480 --nats nats://hub:4222
+//! $ curl -H 'Authorization: Bearer s3cret' \
+//!       'http://127.0.0.1:8480/modbus/10.0.0.5/holding/40070?count=2&unit=3'
+//! {"address":"10.0.0.5","unit_id":3,"kind":"holding","register":40070,"values":[231,0]}
+//! $ curl -X PUT -H 'Authorization: Bearer s3cret' -d '{"value":1}' \
+//!       http://127.0.0.1:8480/modbus/10.0.0.5/holding/40236
+//! $ curl -H 'Authorization: Bearer s3cret' \
+//!       'http://127.0.0.1:8480/nats/plant.temperature?count=5&timeout=10s'
+//! {"subject":"plant.temperature","messages":[{"subject":"plant.temperature","payload":21.5}, ...]}
+//! ```
+//!
+//! Every request needs the token as a bearer token. `GET /modbus/ADDRESS/KIND/REGISTER` reads
+//! `count` registers (1 by default) of unit `unit` (the profile's, or 1); `PUT` on a holding
+//! register writes the JSON body's `value`. `GET /nats/SUBJECT` waits for `count` messages (1
+//! by default) for up to `timeout` (5s) and answers with those that came; payloads that are
+//! JSON are embedded as they are, others as text. Answers are JSON; failures are an object
+//! with `error` and the `status` the CLI would exit with.
+//!
+//! With `--cache-ttl` (or the profile's `cache_ttl`), reads of registers read less than that
+//! long ago are answered from memory, so callers polling a slow serial gateway share its
+//! answers; a write through the API drops the values it changes.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use edge_core::audit;
+use edge_core::config;
+use edge_core::exit::{self, Code};
+use edge_core::json;
+use edge_core::output::{self, Record, Value};
+use edge_core::retry::Retry;
+use edge_core::writes::{self, Write};
+use edge_core::OrExit;
+use futures::StreamExt;
+use modbus::client::{self as modbus_client, Device, Read, RegisterKind};
+use nats::client::{self as nats_client, Client, Options};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8480";
+
+// Requests larger than this, headers and body together, are refused.
+const MAX_REQUEST: usize = 64 * 1024;
+
+// How long a client gets to send its whole request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_SAMPLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A sample waits at most this long, whatever it asks for.
+const MAX_SAMPLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Address to serve the API on (default 127.0.0.1:8480).
+    #[clap(long, value_parser)]
+    api_listen: Option<SocketAddr>,
+    /// Token requests must send as `Authorization: Bearer TOKEN`.
+    #[clap(long, env = "EDGE_API_TOKEN", hide_env_values = true, action)]
+    token: Option<String>,
+    /// NATS server for /nats requests, which answer 404 without one.
+    #[clap(long, env = "EDGE_NATS_URL", action)]
+    nats: Option<String>,
+    /// Named profile for the NATS server's credentials and TLS, and the Modbus unit, retries
+    /// and cache TTL.
+    #[clap(long, env = "EDGE_PROFILE", action)]
+    profile: Option<String>,
+    /// Answer reads of registers read less than this long ago from memory, e.g. 2s.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    cache_ttl: Option<Duration>,
+}
+
+struct Api {
+    token: String,
+    nats: Option<Client>,
+    unit_id: u8,
+    retry: Retry,
+    cache_ttl: Option<Duration>,
+}
+
+/// A failed request: the HTTP status and what to say about it.
+struct Failure {
+    http: &'static str,
+    code: Code,
+    message: String,
+}
+
+impl Failure {
+    fn new(http: &'static str, code: Code, message: impl Into<String>) -> Failure {
+        Failure {
+            http,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Failure {
+        Failure::new("400 Bad Request", Code::Usage, message)
+    }
+
+    fn not_found(message: impl Into<String>) -> Failure {
+        Failure::new("404 Not Found", Code::Usage, message)
+    }
+
+    /// A failure talking to a device or server, by the exit code it carries.
+    fn upstream(err: &(dyn std::error::Error + 'static)) -> Failure {
+        let code = exit::Code::of(err);
+        let http = match code {
+            Code::Timeout => "504 Gateway Timeout",
+            Code::Usage => "400 Bad Request",
+            _ => "502 Bad Gateway",
+        };
+        Failure::new(http, code, err.to_string())
+    }
+}
+
+struct Request {
+    // Who sent it, for the audit log.
+    client: String,
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn parsed<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, Failure> {
+        self.query(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Failure::bad_request(format!("invalid {name} `{value}`")))
+            })
+            .transpose()
+    }
+}
+
+/// Serves the API until stopped.
+pub async fn run(args: Args) {
+    let token = args
+        .token
+        .filter(|token| !token.is_empty())
+        .or_exit_with(Code::Usage, "An API token is required: set EDGE_API_TOKEN");
+    let profile = config::load_profile(args.profile.as_deref())
+        .or_exit_with(Code::Usage, "Unable to load profile");
+    let nats = match args.nats {
+        Some(address) => {
+            let options = Options::new(address)
+                .with_profile(&profile)
+                .or_exit_with(Code::Usage, "Unable to read profile");
+            match nats_client::connect(&options).await {
+                Ok(connection) => Some(connection),
+                Err(err) => exit::fatal_error("Unable to connect to NATS", err.as_ref()),
+            }
+        }
+        None => None,
+    };
+    let api = Arc::new(Api {
+        token,
+        nats,
+        unit_id: profile
+            .integer::<u8>("unit_id")
+            .or_exit_with(Code::Usage, "Unable to read profile")
+            .unwrap_or(1),
+        retry: profile
+            .retry()
+            .or_exit_with(Code::Usage, "Unable to read profile"),
+        cache_ttl: match args.cache_ttl {
+            Some(ttl) => Some(ttl),
+            None => profile
+                .cache_ttl()
+                .or_exit_with(Code::Usage, "Unable to read profile"),
+        },
+    });
+
+    let listen = args
+        .api_listen
+        .unwrap_or_else(|| DEFAULT_LISTEN.parse().expect("a valid address"));
+    let listener = TcpListener::bind(listen)
+        .await
+        .or_exit(&format!("Unable to listen on {listen}"));
+    log::info!("Serving the API on http://{listen}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let api = api.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = api.serve(stream, peer).await {
+                        log::debug!("API request from {peer} failed: {err}");
+                    }
+                });
+            }
+            Err(err) => log::warn!("Unable to accept an API connection: {err}"),
+        }
+    }
+}
+
+impl Api {
+    async fn serve(&self, mut stream: TcpStream, peer: SocketAddr) -> std::io::Result<()> {
+        let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream, peer));
+        let request = match request.await {
+            Ok(request) => request?,
+            Err(_) => Err(Failure::new(
+                "408 Request Timeout",
+                Code::Timeout,
+                "the request took too long to arrive",
+            )),
+        };
+        let (status, body) = match request {
+            Ok(request) => {
+                let (status, body) = match self.handle(&request).await {
+                    Ok(record) => ("200 OK", record),
+                    Err(failure) => {
+                        log::warn!("{} {}: {}", request.method, request.path, failure.message);
+                        error(&failure)
+                    }
+                };
+                log::info!("{} {}: {status}", request.method, request.path);
+                (status, body)
+            }
+            Err(failure) => error(&failure),
+        };
+        let body = output::json(&Value::Record(body));
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    async fn handle(&self, request: &Request) -> Result<Record, Failure> {
+        if !request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| same(token.trim().as_bytes(), self.token.as_bytes()))
+        {
+            return Err(Failure::new(
+                "401 Unauthorized",
+                Code::Auth,
+                "expected the API token as `Authorization: Bearer TOKEN`",
+            ));
+        }
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["modbus", address, kind, register]) => {
+                self.read(request, address, kind, register).await
+            }
+            ("PUT", ["modbus", address, kind, register]) => {
+                self.write(request, address, kind, register).await
+            }
+            ("GET", ["nats", subject]) => self.sample(request, subject).await,
+            (_, ["modbus", _, _, _] | ["nats", _]) => Err(Failure::new(
+                "405 Method Not Allowed",
+                Code::Usage,
+                format!("{} isn't supported here", request.method),
+            )),
+            _ => Err(Failure::not_found(
+                "expected /modbus/ADDRESS/KIND/REGISTER or /nats/SUBJECT",
+            )),
+        }
+    }
+
+    fn device(&self, request: &Request, address: &str) -> Result<Device, Failure> {
+        let device =
+            Device::resolve(address).map_err(|err| Failure::bad_request(err.to_string()))?;
+        let unit_id = request.parsed("unit")?.unwrap_or(self.unit_id);
+        Ok(device
+            .unit_id(unit_id)
+            .retry(self.retry)
+            .cache_ttl(self.cache_ttl))
+    }
+
+    async fn read(
+        &self,
+        request: &Request,
+        address: &str,
+        kind: &str,
+        register: &str,
+    ) -> Result<Record, Failure> {
+        let kind = match kind {
+            "holding" => RegisterKind::Holding,
+            "input" => RegisterKind::Input,
+            _ => {
+                return Err(Failure::not_found(format!(
+                    "unknown register kind {kind}, expected holding or input"
+                )))
+            }
+        };
+        let register = number(register)?;
+        let count = request.parsed("count")?.unwrap_or(1);
+        let device = self.device(request, address)?;
+        let values =
+            modbus_client::read_registers(&device, &Read::new(kind, register).count(count))
+                .await
+                .map_err(|err| Failure::upstream(&*err))?;
+        Ok(Record::new()
+            .field("address", address)
+            .field("unit_id", device.unit_id)
+            .field("kind", kind.name())
+            .field("register", register)
+            .field("values", values))
+    }
+
+    async fn write(
+        &self,
+        request: &Request,
+        address: &str,
+        kind: &str,
+        register: &str,
+    ) -> Result<Record, Failure> {
+        if kind != "holding" {
+            return Err(Failure::bad_request(
+                "only holding registers can be written",
+            ));
+        }
+        let register = number(register)?;
+        let body = std::str::from_utf8(&request.body)
+            .map_err(|_| Failure::bad_request("the body isn't UTF-8"))?;
+        let value = match json::parse(body) {
+            Ok(Value::Record(record)) => match record.get("value") {
+                Some(Value::Integer(value)) => u16::try_from(*value).ok(),
+                Some(Value::Unsigned(value)) => u16::try_from(*value).ok(),
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| {
+            Failure::bad_request("expected a body like {\"value\": 1} from 0 to 65535")
+        })?;
+        let device = self.device(request, address)?;
+        let write = Write::Modbus {
+            device: device.address,
+            kind: "holding",
+            register,
+            count: 1,
+        };
+        let client = Some(request.client.as_str());
+        writes::check_for(client, &write, value)
+            .map_err(|err| Failure::new("403 Forbidden", err.code, err.to_string()))?;
+        let result = modbus_client::write_register(&device, register, value).await;
+        audit::record_for(client, &write, value, &result);
+        result.map_err(|err| Failure::upstream(&*err))?;
+        log::info!(
+            "Wrote {value} to holding register {register} of {address} unit {}",
+            device.unit_id
+        );
+        Ok(Record::new()
+            .field("address", address)
+            .field("unit_id", device.unit_id)
+            .field("register", register)
+            .field("value", value))
+    }
+
+    async fn sample(&self, request: &Request, subject: &str) -> Result<Record, Failure> {
+        let connection = self
+            .nats
+            .as_ref()
+            .ok_or_else(|| Failure::not_found("no NATS server; start the API with --nats"))?;
+        let count: usize = request.parsed("count")?.unwrap_or(1);
+        let timeout = match request.query("timeout") {
+            Some(timeout) => humantime::parse_duration(timeout)
+                .map_err(|_| Failure::bad_request(format!("invalid timeout `{timeout}`")))?,
+            None => DEFAULT_SAMPLE_TIMEOUT,
+        }
+        .min(MAX_SAMPLE_TIMEOUT);
+        let mut subscription = connection
+            .subscribe(subject.to_string())
+            .await
+            .map_err(|err| Failure::new("502 Bad Gateway", Code::Protocol, err.to_string()))?;
+        let mut messages = Vec::new();
+        let _ = tokio::time::timeout(timeout, async {
+            while messages.len() < count {
+                match subscription.next().await {
+                    Some(message) => messages.push(message),
+                    None => break,
+                }
+            }
+        })
+        .await;
+        let _ = subscription.unsubscribe().await;
+        let messages: Vec<Value> = messages
+            .into_iter()
+            .map(|message| {
+                let text = String::from_utf8_lossy(&message.payload);
+                let payload = json::parse(&text).unwrap_or_else(|_| Value::from(&*text));
+                Value::Record(
+                    Record::new()
+                        .field("subject", message.subject)
+                        .field("payload", payload),
+                )
+            })
+            .collect();
+        Ok(Record::new()
+            .field("subject", subject)
+            .field("messages", Value::List(messages)))
+    }
+}
+
+fn number(register: &str) -> Result<u16, Failure> {
+    register
+        .parse()
+        .map_err(|_| Failure::bad_request(format!("invalid register `{register}`")))
+}
+
+fn error(failure: &Failure) -> (&'static str, Record) {
+    (
+        failure.http,
+        Record::new()
+            .field("error", failure.message.as_str())
+            .field("status", failure.code.status()),
+    )
+}
+
+/// Whether the token matches, taking as long whichever byte differs.
+fn same(given: &[u8], token: &[u8]) -> bool {
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+/// Reads one request; the inner error is a request that can't be answered normally.
+async fn read_request(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+) -> std::io::Result<Result<Request, Failure>> {
+    let too_large = || {
+        Failure::new(
+            "413 Payload Too Large",
+            Code::Usage,
+            "the request is too large",
+        )
+    };
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let end = loop {
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if data.len() > MAX_REQUEST {
+            return Ok(Err(too_large()));
+        }
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(Err(Failure::bad_request("incomplete request")));
+        }
+        data.extend_from_slice(&buffer[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut authorization = None;
+    let mut length = 0;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = match value.parse() {
+                Ok(length) => length,
+                Err(_) => return Ok(Err(Failure::bad_request("invalid Content-Length"))),
+            };
+        }
+    }
+    // Checked without adding, as a Content-Length near usize::MAX would overflow.
+    if length > MAX_REQUEST.saturating_sub(end + 4) {
+        return Ok(Err(too_large()));
+    }
+    let mut body = data.split_off(end + 4);
+    while body.len() < length {
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(Err(Failure::bad_request("incomplete body")));
+        }
+        body.extend_from_slice(&buffer[..n]);
+    }
+    body.truncate(length);
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                decode(&key.replace('+', " ")),
+                decode(&value.replace('+', " ")),
+            )
+        })
+        .collect();
+    Ok(Ok(Request {
+        client: format!("api {peer}"),
+        method: method.to_string(),
+        path: decode(path),
+        query,
+        authorization,
+        body,
+    }))
+}
+
+/// `text` with its `%XX` escapes replaced.
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| text.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                index += 3;
+            }
+            None => {
+                out.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
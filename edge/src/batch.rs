@@ -0,0 +1,304 @@
+//! `edge run <script>`: tool commands from a file, one per line, run in order as if typed after
+//! `edge`. A commissioning checklist then looks like:
+//!
+//! ```text
+//! # inverter 3
+//! set device = 10.0.0.5
+//! modbus ${device} healthcheck
+//! modbus ${device} read-register -r 40070 -k holding -c 2
+//! - nats nats://broker:4222 publish -s site.commissioning -m "inverter 3 up"
+//! sleep 2s
+//! on-error continue
+//! modbus ${device} write-register -a 40236 -v 1
+//! modbus ${device} read-register -r 40236 -k holding
+//! ```
+//!
+//! Words are split like a shell would: quotes keep spaces, `\` escapes one character and
+//! `${name}` expands outside single quotes. `--var name=value` on the command line wins over
+//! `set` in the script.
+//!
+//! A failed step stops the script and its exit status becomes the script's, unless
+//! `on-error continue` is in effect: then the rest still runs and the script exits with the
+//! first failure's status at the end. A step starting with `-` may fail without affecting
+//! the outcome at all.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use edge_core::exit::{self, Code};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum OnError {
+    Stop,
+    Continue,
+    // `-` steps: failures are reported but don't count.
+    Ignore,
+}
+
+enum Action {
+    Run(Vec<String>),
+    Sleep(Duration),
+}
+
+pub struct Step {
+    line: usize,
+    // The line with variables expanded, for progress and the summary.
+    text: String,
+    action: Action,
+    on_error: OnError,
+}
+
+/// Parses a script, expanding variables and checking every command names one of `tools`, so
+/// a typo on the last line is found before the first one runs.
+pub fn parse(
+    source: &str,
+    vars: &[(String, String)],
+    tools: &[&str],
+) -> Result<Vec<Step>, exit::Error> {
+    let mut values: HashMap<String, String> = vars.iter().cloned().collect();
+    let fixed: HashSet<String> = vars.iter().map(|(name, _)| name.clone()).collect();
+    let mut on_error = OnError::Stop;
+    let mut steps = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let invalid = |message: String| {
+            exit::Error::new(Code::Usage, format!("line {line_number}: {message}"))
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (ignored, line) = match line.strip_prefix('-') {
+            Some(rest) if rest.starts_with(char::is_whitespace) => (true, rest.trim_start()),
+            _ => (false, line),
+        };
+        let words = split(line, &values).map_err(invalid)?;
+        let Some(first) = words.first() else {
+            continue;
+        };
+        let action = match first.as_str() {
+            "set" => {
+                let rest = line["set".len()..].trim_start();
+                let Some((name, value)) = rest.split_once('=') else {
+                    return Err(invalid("expected `set name = value`".to_string()));
+                };
+                let name = name.trim();
+                if !is_name(name) {
+                    return Err(invalid(format!("invalid variable name `{name}`")));
+                }
+                let value = split(value, &values).map_err(invalid)?.join(" ");
+                if !fixed.contains(name) {
+                    values.insert(name.to_string(), value);
+                }
+                continue;
+            }
+            "on-error" => {
+                on_error = match words.get(1).map(String::as_str) {
+                    Some("stop") if words.len() == 2 => OnError::Stop,
+                    Some("continue") if words.len() == 2 => OnError::Continue,
+                    _ => {
+                        return Err(invalid(
+                            "expected `on-error stop` or `on-error continue`".to_string(),
+                        ))
+                    }
+                };
+                continue;
+            }
+            "sleep" => match words.get(1).map(|word| humantime::parse_duration(word)) {
+                Some(Ok(duration)) if words.len() == 2 => Action::Sleep(duration),
+                _ => {
+                    return Err(invalid(
+                        "expected `sleep <duration>`, e.g. `sleep 2s`".to_string(),
+                    ))
+                }
+            },
+            tool if tools.contains(&tool) => Action::Run(words.clone()),
+            other => return Err(invalid(format!("unknown tool `{other}`"))),
+        };
+        steps.push(Step {
+            line: line_number,
+            text: words.join(" "),
+            action,
+            on_error: if ignored { OnError::Ignore } else { on_error },
+        });
+    }
+    Ok(steps)
+}
+
+/// Runs `steps` with `edge`, passing `global` before each tool, and exits with the script's
+/// status. Progress and the summary go to stderr; stdout is left to the tools.
+pub async fn run(steps: Vec<Step>, global: &[String]) -> ! {
+    let edge = std::env::current_exe()
+        .unwrap_or_else(|err| exit::fatal_error("Unable to find the edge binary", &err));
+    let total = steps.len();
+    let mut failed: Vec<(&Step, Code)> = Vec::new();
+    let mut ok = 0;
+    for (index, step) in steps.iter().enumerate() {
+        eprintln!("[{}/{total}] {}", index + 1, step.text);
+        let status = match &step.action {
+            Action::Sleep(duration) => {
+                tokio::time::sleep(*duration).await;
+                None
+            }
+            Action::Run(words) => {
+                match tokio::process::Command::new(&edge)
+                    .args(global)
+                    .args(words)
+                    .status()
+                    .await
+                {
+                    Ok(status) if status.success() => None,
+                    Ok(status) => Some(status.code().map_or(Code::Failure, Code::from_status)),
+                    Err(err) => {
+                        log::error!("Unable to start step {}: {err}", step.line);
+                        Some(Code::Failure)
+                    }
+                }
+            }
+        };
+        match status {
+            None => ok += 1,
+            Some(code) => {
+                eprintln!(
+                    "[{}/{total}] failed: {} (line {}, exit status {})",
+                    index + 1,
+                    code.description(),
+                    step.line,
+                    code.status()
+                );
+                failed.push((step, code));
+                if step.on_error == OnError::Stop {
+                    break;
+                }
+            }
+        }
+    }
+
+    match total - ok - failed.len() {
+        0 => eprintln!("{ok} of {total} steps succeeded"),
+        not_run => eprintln!("{ok} of {total} steps succeeded, {not_run} not run"),
+    }
+    for (step, code) in &failed {
+        let note = if step.on_error == OnError::Ignore {
+            " (ignored)"
+        } else {
+            ""
+        };
+        eprintln!(
+            "  line {}: {}: {}{note}",
+            step.line,
+            code.description(),
+            step.text
+        );
+    }
+    let outcome = failed
+        .iter()
+        .find(|(step, _)| step.on_error != OnError::Ignore)
+        .map(|(_, code)| *code);
+    std::process::exit(outcome.map_or(0, Code::status));
+}
+
+/// `name=value` for `--var`.
+pub fn parse_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if is_name(name) => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected name=value, got `{value}`")),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Shell-like words: whitespace separates, quotes group, `\` escapes, `${name}` expands
+/// outside single quotes and `#` at the start of a word comments out the rest.
+pub(crate) fn split(line: &str, values: &HashMap<String, String>) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // A word is started by quotes even when they're empty, e.g. `-m ""`.
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '#' if !in_word => break,
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some('$') if chars.peek() == Some(&'{') => {
+                            word.push_str(&expand(&mut chars, values)?)
+                        }
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => return Err("`\\` at the end of the line".to_string()),
+                }
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                in_word = true;
+                word.push_str(&expand(&mut chars, values)?);
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// The value of `{name}` right after a `$`.
+fn expand(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    values: &HashMap<String, String>,
+) -> Result<String, String> {
+    chars.next();
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) => name.push(c),
+            None => return Err("unterminated `${`".to_string()),
+        }
+    }
+    values
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("`{name}` isn't set"))
+}
@@ -0,0 +1,86 @@
+//! `edge config check`: catches mistakes in the config file and bridge mappings before a
+//! daemon starts polling a live plant with them:
+//!
+//! ```text
+//! $ edge config check bridge.yaml
+//! /home/edge/.config/edge_tools/config.toml:14: profiles.site-a.retries: must be an integer from 0 to 4294967295
+//! bridge.yaml:23: sources.plc.points[1].type: unknown type f23, expected u16, i16, u32, i32, u64, i64, f32, f64, bcd16, bcd32 or bool
+//! ```
+//!
+//! The config file is always checked, every key of it; a mapping is loaded the way `bridge
+//! run` loads it and stops at its first mistake.
+
+use std::path::{Path, PathBuf};
+
+use edge_core::config;
+use edge_core::exit::{self, Code};
+use edge_core::OrExit;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Check the config file's profiles, and these bridge mapping files if given, and exit 2 if
+    /// anything is wrong.
+    Check {
+        #[clap(value_parser)]
+        mappings: Vec<PathBuf>,
+    },
+}
+
+pub fn run(args: Args) {
+    let Command::Check { mappings } = args.command;
+    let config = config::default_path().or_exit_with(Code::Usage, "No config file");
+    let mut problems = 0;
+    if config.exists() || mappings.is_empty() {
+        problems += check_config(&config);
+    }
+    for mapping in &mappings {
+        problems += check_mapping(mapping);
+    }
+    match problems {
+        0 => println!("No problems found"),
+        1 => exit::fatal_with(Code::Usage, "Found 1 problem"),
+        _ => exit::fatal_with(Code::Usage, format!("Found {problems} problems")),
+    }
+}
+
+#[cfg(feature = "bridge")]
+fn check_mapping(path: &Path) -> usize {
+    match bridge::validate(path) {
+        Ok(()) => 0,
+        Err(err) => {
+            println!("{err}");
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "bridge"))]
+fn check_mapping(path: &Path) -> usize {
+    println!("{}: this edge was built without the bridge", path.display());
+    1
+}
+
+fn check_config(path: &Path) -> usize {
+    let contents = std::fs::read_to_string(path)
+        .or_exit_with(Code::Usage, &format!("Unable to read {}", path.display()));
+    let problems = match config::check(&contents) {
+        Ok(problems) => problems,
+        Err(err) => {
+            println!("{}:{}: {}", path.display(), err.line, err.message);
+            return 1;
+        }
+    };
+    for problem in &problems {
+        match problem.line {
+            Some(line) => println!("{}:{line}: {}", path.display(), problem.message),
+            None => println!("{}: {}", path.display(), problem.message),
+        }
+    }
+    problems.len()
+}
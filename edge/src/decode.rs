@@ -0,0 +1,363 @@
+//! `edge decode`: runs stored payloads through the decoders the tools use live, for looking at
+//! what a device or broker sent after the fact, without connecting to anything:
+//!
+//! ```text
+//! $ edge decode response.hex --type f32 --order cdab
+//! response.hex 229.8 0.51
+//! $ edge decode capture.ndjson --codec 'sparkplug-codec' --output json
+//! {"file":"capture.ndjson","line":1,"subject":"spBv1.0/plant/DDATA/gw/meter","size":61,"value":{...}}
+//! ```
+//!
+//! A file holds one payload as a hex dump (plain hex, `xxd` or `hexdump -C`), as base64 or as
+//! raw bytes, or one per line as NDJSON like `nats subscribe --output json` or envelopes
+//! print, with the payload in `payload`. The format is told from the extension (`.hex`,
+//! `.b64`, `.ndjson`, `.bin`) or else from the contents, unless `--format` says. Payloads
+//! are decoded as Modbus register values with `--type`, through a codec plugin with `--codec`
+//! (formats such as protobuf, CBOR or Sparkplug), or otherwise as JSON or text.
+
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use edge_core::codec::{self, Order};
+use edge_core::exit::{self, Code};
+use edge_core::output::{self, OutputArgs, Record, Value};
+use edge_core::plugin::Codec;
+use edge_core::point::DataType;
+use edge_core::OrExit;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Files to decode; stdin when none are given.
+    #[clap(value_parser)]
+    files: Vec<PathBuf>,
+    /// How payloads are stored: hex, base64, ndjson or raw; told from each file otherwise.
+    #[clap(long, value_enum)]
+    format: Option<Stored>,
+    /// Decode payloads as big-endian Modbus registers holding values of this type: u16, i16,
+    /// u32, i32, u64, i64, f32, f64, bcd16 or bcd32.
+    #[clap(short = 't', long = "type", value_parser = parse_type, conflicts_with = "codec")]
+    data_type: Option<codec::Type>,
+    /// Word and byte order of wider types: abcd (default), badc, cdab or dcba.
+    #[clap(long, value_parser = parse_order, requires = "data-type")]
+    order: Option<Order>,
+    /// Decode payloads with this codec plugin command, as nats subscribe --codec does.
+    #[clap(long, action)]
+    codec: Option<String>,
+    /// Subject passed to the codec for payloads without one, e.g. from a hex dump.
+    #[clap(short, long, action)]
+    subject: Option<String>,
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Stored {
+    Hex,
+    Base64,
+    Ndjson,
+    Raw,
+}
+
+/// A payload and where it was found.
+struct Payload {
+    file: String,
+    // For NDJSON, the line it was on.
+    line: Option<usize>,
+    subject: Option<String>,
+    bytes: Vec<u8>,
+}
+
+enum Decoder {
+    Registers(codec::Type, Order),
+    Plugin(Box<Codec>),
+    Plain,
+}
+
+pub async fn run(args: Args) {
+    let out = args.output.load();
+    let decoder = match (args.data_type, &args.codec) {
+        (Some(ty), _) => Decoder::Registers(ty, args.order.unwrap_or_default()),
+        (None, Some(command)) => Decoder::Plugin(Box::new(
+            Codec::spawn(command).or_exit_with(Code::Usage, "Invalid --codec"),
+        )),
+        (None, None) => Decoder::Plain,
+    };
+
+    let mut payloads = Vec::new();
+    if args.files.is_empty() {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)
+            .unwrap_or_else(|err| exit::fatal_error("Unable to read stdin", &err));
+        payloads.extend(
+            read("-", None, &bytes, args.format).or_exit_with(Code::Usage, "Unable to read stdin"),
+        );
+    }
+    for file in &args.files {
+        let bytes = std::fs::read(file)
+            .or_exit_with(Code::Usage, &format!("Unable to read {}", file.display()));
+        let name = file.display().to_string();
+        payloads.extend(
+            read(&name, Some(file), &bytes, args.format)
+                .or_exit_with(Code::Usage, &format!("Unable to read {name}")),
+        );
+    }
+
+    let mut failed = 0;
+    for payload in &payloads {
+        let subject = payload
+            .subject
+            .as_deref()
+            .or(args.subject.as_deref())
+            .unwrap_or_default();
+        let value = match decode(&decoder, subject, &payload.bytes).await {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(err) => {
+                log::warn!("Unable to decode {}: {err}", payload.location());
+                failed += 1;
+                continue;
+            }
+        };
+        let record = Record::new()
+            .field("file", payload.file.as_str())
+            .field("line", payload.line)
+            .field("subject", payload.subject.as_deref())
+            .field("size", payload.bytes.len())
+            .field("value", value.clone());
+        out.record(&record, || {
+            let value = match &value {
+                Value::List(values) => values
+                    .iter()
+                    .map(output::json)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                Value::String(text) => text.clone(),
+                other => output::json(other),
+            };
+            match &payload.subject {
+                Some(subject) => println!("{} {subject} {value}", payload.location()),
+                None => println!("{} {value}", payload.location()),
+            }
+        });
+    }
+    if failed > 0 {
+        exit::fatal_with(
+            Code::Failure,
+            format!("Unable to decode {failed} of {} payloads", payloads.len()),
+        );
+    }
+}
+
+impl Payload {
+    fn location(&self) -> String {
+        match self.line {
+            Some(line) => format!("{}:{line}", self.file),
+            None => self.file.clone(),
+        }
+    }
+}
+
+/// The payloads stored in a file.
+fn read(
+    name: &str,
+    path: Option<&Path>,
+    bytes: &[u8],
+    format: Option<Stored>,
+) -> Result<Vec<Payload>, String> {
+    let format = format.unwrap_or_else(|| guess(path, bytes));
+    let one = |bytes: Vec<u8>| {
+        vec![Payload {
+            file: name.to_string(),
+            line: None,
+            subject: None,
+            bytes,
+        }]
+    };
+    match format {
+        Stored::Raw => Ok(one(bytes.to_vec())),
+        Stored::Hex => Ok(one(hex(text(bytes)?)?)),
+        Stored::Base64 => {
+            let text: String = text(bytes)?.split_whitespace().collect();
+            Ok(one(BASE64
+                .decode(text)
+                .map_err(|err| format!("invalid base64: {err}"))?))
+        }
+        Stored::Ndjson => {
+            let mut payloads = Vec::new();
+            for (index, line) in text(bytes)?.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = match edge_core::json::parse(line) {
+                    Ok(Value::Record(record)) => record,
+                    Ok(_) => return Err(format!("line {}: expected a JSON object", index + 1)),
+                    Err(err) => return Err(format!("line {}: invalid JSON {err}", index + 1)),
+                };
+                let bytes = match record.get("payload") {
+                    Some(Value::String(text)) => text.as_bytes().to_vec(),
+                    Some(other) => output::json(other).into_bytes(),
+                    None => return Err(format!("line {}: no `payload`", index + 1)),
+                };
+                let subject = ["subject", "topic"]
+                    .iter()
+                    .find_map(|name| match record.get(name) {
+                        Some(Value::String(subject)) => Some(subject.clone()),
+                        _ => None,
+                    });
+                payloads.push(Payload {
+                    file: name.to_string(),
+                    line: Some(index + 1),
+                    subject,
+                    bytes,
+                });
+            }
+            Ok(payloads)
+        }
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(bytes).map_err(|_| "expected text, not binary data".to_string())
+}
+
+/// The format a file is stored in, by its extension or else its contents.
+fn guess(path: Option<&Path>, bytes: &[u8]) -> Stored {
+    let extension = path
+        .and_then(Path::extension)
+        .and_then(|extension| extension.to_str());
+    match extension {
+        Some("hex" | "xxd") => return Stored::Hex,
+        Some("b64" | "base64") => return Stored::Base64,
+        Some("ndjson" | "jsonl") => return Stored::Ndjson,
+        Some("bin" | "raw") => return Stored::Raw,
+        _ => {}
+    }
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return Stored::Raw;
+    };
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    if lines.peek().is_some() && lines.all(|line| line.trim_start().starts_with('{')) {
+        Stored::Ndjson
+    } else if hex(text).is_ok() {
+        Stored::Hex
+    } else if BASE64
+        .decode(text.split_whitespace().collect::<String>())
+        .is_ok()
+    {
+        Stored::Base64
+    } else {
+        Stored::Raw
+    }
+}
+
+/// The bytes of a hex dump: plain hex, optionally `0x`-prefixed and spaced, or the lines of
+/// `xxd` and `hexdump -C` with their offsets and text columns.
+fn hex(dump: &str) -> Result<Vec<u8>, String> {
+    let first = dump
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default();
+    let mut words = first.split_whitespace();
+    // `xxd` ends offsets with a colon; `hexdump -C` gives them eight digits, then bytes.
+    let dumped = match (words.next(), words.next()) {
+        (Some(offset), _) if offset.ends_with(':') => true,
+        (Some(offset), Some(byte)) => offset.len() == 8 && byte.len() == 2,
+        _ => false,
+    };
+    let mut bytes = Vec::new();
+    for line in dump.lines() {
+        let line = line.split('|').next().unwrap_or_default();
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if dumped && !words.is_empty() {
+            words.remove(0);
+        }
+        let start = bytes.len();
+        for word in words {
+            let digits = word.trim_start_matches("0x").trim_end_matches(',');
+            let valid = !digits.is_empty()
+                && digits.len() % 2 == 0
+                && digits.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                // The text column of a dump, which may be words of its own.
+                if dumped && bytes.len() > start {
+                    break;
+                }
+                return Err(format!("`{word}` isn't hex"));
+            }
+            for pair in digits.as_bytes().chunks(2) {
+                let pair = std::str::from_utf8(pair).unwrap_or_default();
+                bytes.push(u8::from_str_radix(pair, 16).map_err(|err| err.to_string())?);
+            }
+            // A dump line holds 16 bytes; anything after them is its text column.
+            if dumped && bytes.len() - start == 16 {
+                break;
+            }
+        }
+    }
+    if bytes.is_empty() {
+        return Err("no hex bytes".to_string());
+    }
+    Ok(bytes)
+}
+
+async fn decode(decoder: &Decoder, subject: &str, bytes: &[u8]) -> Result<Option<Value>, String> {
+    match decoder {
+        Decoder::Registers(ty, order) => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(format!(
+                    "{} bytes aren't a whole number of registers",
+                    bytes.len()
+                ));
+            }
+            let words: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            let data_type = DataType::from(*ty);
+            let values = words
+                .chunks(ty.words())
+                .map(|chunk| {
+                    data_type
+                        .decode(chunk, *order)
+                        .map(|value| Value::from(&value))
+                        .map_err(|err| err.to_string())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Some(Value::List(values)))
+        }
+        Decoder::Plugin(codec) => codec
+            .decode(subject, bytes)
+            .await
+            .map_err(|err| err.to_string()),
+        Decoder::Plain => Ok(Some(match std::str::from_utf8(bytes) {
+            Ok(text) => match edge_core::json::parse(text) {
+                Ok(value) => value,
+                Err(_) => Value::String(text.to_string()),
+            },
+            Err(_) => Value::String(
+                bytes
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+        })),
+    }
+}
+
+fn parse_type(name: &str) -> Result<codec::Type, String> {
+    codec::Type::parse(name).ok_or_else(|| {
+        format!(
+            "unknown type {name}, expected u16, i16, u32, i32, u64, i64, f32, f64, bcd16 or bcd32"
+        )
+    })
+}
+
+fn parse_order(name: &str) -> Result<Order, String> {
+    Order::parse(name).ok_or_else(|| format!("unknown order {name}, expected {}", Order::NAMES))
+}
@@ -0,0 +1,219 @@
+#[cfg(feature = "agent")]
+mod agent;
+#[cfg(feature = "api")]
+mod api;
+mod batch;
+mod config;
+mod decode;
+mod doctor;
+#[cfg(feature = "scan")]
+mod scan;
+mod schedule;
+mod secret;
+mod service;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::Code;
+use edge_core::logging::{self, LogFormat};
+use edge_core::man;
+use edge_core::OrExit;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[clap(author, version, about = "All edge tools in one binary", long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+struct Args {
+    /// Log filter applied to every tool (error, warn, info, debug, trace or env_logger
+    /// directives). RUST_LOG takes precedence when set.
+    #[clap(long, global = true, action)]
+    log_level: Option<String>,
+
+    #[clap(subcommand)]
+    tool: Tools,
+}
+
+// Only one tool's arguments are ever held, so boxing the big ones would buy nothing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Tools {
+    #[cfg(feature = "bridge")]
+    #[clap(about = "Protocol gateway driven by a YAML mapping file")]
+    Bridge(bridge::Args),
+    #[cfg(feature = "camera")]
+    #[clap(about = "ONVIF discovery and RTSP stream checks")]
+    Camera(camera::Args),
+    #[cfg(feature = "dnp3-sim")]
+    #[clap(about = "DNP3 outstation simulator")]
+    Dnp3Sim(dnp3_sim::Args),
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    #[clap(about = "GPIO lines through the character device API")]
+    Gpio(gpio::Args),
+    #[cfg(feature = "hart")]
+    #[clap(about = "HART-IP gateway client")]
+    Hart(hart::Args),
+    #[cfg(feature = "iec62056")]
+    #[clap(about = "IEC 62056-21 optical meter reader")]
+    Iec62056(iec62056::Args),
+    #[cfg(feature = "modbus")]
+    #[clap(about = "Modbus TCP client and capture analysis")]
+    Modbus(modbus::Args),
+    #[cfg(feature = "nats")]
+    #[clap(about = "NATS client")]
+    Nats(nats::Args),
+    #[cfg(all(feature = "sensors", target_os = "linux"))]
+    #[clap(about = "I2C and 1-Wire sensors")]
+    Sensors(sensors::Args),
+    #[cfg(all(feature = "spi", target_os = "linux"))]
+    #[clap(about = "Raw spidev transfers")]
+    Spi(spi::Args),
+    #[cfg(feature = "syslog")]
+    #[clap(about = "Syslog listener and forwarder")]
+    Syslog(syslog::Args),
+    #[clap(about = "Run tool commands from a script, one per line")]
+    Run {
+        #[clap(value_parser)]
+        script: PathBuf,
+        /// Set a script variable, e.g. --var device=10.0.0.5; wins over `set` in the script.
+        #[clap(long = "var", value_name = "NAME=VALUE", value_parser = batch::parse_var, action)]
+        vars: Vec<(String, String)>,
+    },
+    #[cfg(feature = "scan")]
+    #[clap(about = "Find Modbus, NATS, MQTT, OPC UA and DNP3 endpoints on a subnet")]
+    Scan(scan::Args),
+    #[clap(about = "Check the config file and bridge mappings for mistakes")]
+    Config(config::Args),
+    #[clap(about = "Check serial ports, routes and DNS to configured targets, the clock and disk")]
+    Doctor(doctor::Args),
+    #[clap(about = "Keys and encrypted values for captures and profile secrets")]
+    Secret(secret::Args),
+    #[cfg(feature = "agent")]
+    #[clap(about = "Run allowed diagnostics requested over NATS")]
+    Agent(agent::Args),
+    #[cfg(feature = "api")]
+    #[clap(about = "Serve Modbus reads and writes and NATS samples over HTTP and JSON")]
+    Api(api::Args),
+    #[clap(about = "Decode stored payloads offline: hex dumps, base64 and NDJSON captures")]
+    Decode(decode::Args),
+    #[clap(about = "Run a bridge, the agent or another long-running command as a Windows service")]
+    Service(service::Args),
+    #[clap(about = "Run the recurring jobs in the config file until stopped")]
+    Schedule(schedule::Args),
+    #[clap(about = "Print a completion script for bash, zsh or fish")]
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+    /// Write man pages for every tool and subcommand into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let mut command = logging::command::<Args>();
+    let matches = command.get_matches_mut();
+    let cli =
+        Args::from_arg_matches(&matches).unwrap_or_else(|err| err.format(&mut command).exit());
+
+    let level = cli.log_level.as_deref().unwrap_or("error");
+    let (tool, subcommand) = match matches.subcommand() {
+        Some((tool, matches)) => (tool, matches.subcommand_name()),
+        None => ("edge", None),
+    };
+    let log_format = logging::format(&matches);
+    logging::init(tool, subcommand, log_format, level);
+    edge_core::expect::install(&matches);
+    edge_core::writes::install(&matches);
+
+    // The service manager starts `edge service run -- <command>`; from here on it's that command.
+    let (cli, service) = match cli.tool {
+        Tools::Service(args) => match service::run(args, logging::command::<Args>()) {
+            Some((cli, service)) => (cli, Some(service)),
+            None => return,
+        },
+        _ => (cli, None),
+    };
+
+    match cli.tool {
+        #[cfg(feature = "bridge")]
+        Tools::Bridge(args) => bridge::run(args).await,
+        #[cfg(feature = "camera")]
+        Tools::Camera(args) => camera::run(args).await,
+        #[cfg(feature = "dnp3-sim")]
+        Tools::Dnp3Sim(args) => dnp3_sim::run(args).await,
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        Tools::Gpio(args) => gpio::run(args),
+        #[cfg(feature = "hart")]
+        Tools::Hart(args) => hart::run(args).await,
+        #[cfg(feature = "iec62056")]
+        Tools::Iec62056(args) => iec62056::run(args).await,
+        #[cfg(feature = "modbus")]
+        Tools::Modbus(args) => modbus::run(args).await,
+        #[cfg(feature = "nats")]
+        Tools::Nats(args) => nats::run(args).await,
+        #[cfg(all(feature = "sensors", target_os = "linux"))]
+        Tools::Sensors(args) => sensors::run(args),
+        #[cfg(all(feature = "spi", target_os = "linux"))]
+        Tools::Spi(args) => spi::run(args),
+        #[cfg(feature = "syslog")]
+        Tools::Syslog(args) => syslog::run(args).await,
+        Tools::Run { script, vars } => {
+            let source = std::fs::read_to_string(&script)
+                .or_exit_with(Code::Usage, &format!("Unable to read {}", script.display()));
+            let command = Args::command();
+            let tools: Vec<&str> = command
+                .get_subcommands()
+                .map(|tool| tool.get_name())
+                .filter(|name| {
+                    !matches!(
+                        *name,
+                        "run"
+                            | "scan"
+                            | "config"
+                            | "doctor"
+                            | "secret"
+                            | "agent"
+                            | "api"
+                            | "decode"
+                            | "service"
+                            | "schedule"
+                            | "completions"
+                            | "gen-man"
+                    )
+                })
+                .collect();
+            let steps = batch::parse(&source, &vars, &tools)
+                .or_exit_with(Code::Usage, &format!("Invalid script {}", script.display()));
+            // Steps log the way this run was asked to.
+            let mut global = Vec::new();
+            if let Some(level) = &cli.log_level {
+                global.extend(["--log-level".to_string(), level.clone()]);
+            }
+            if log_format == LogFormat::Json {
+                global.extend(["--log-format".to_string(), "json".to_string()]);
+            }
+            batch::run(steps, &global).await
+        }
+        #[cfg(feature = "scan")]
+        Tools::Scan(args) => scan::run(args).await,
+        Tools::Config(args) => config::run(args),
+        Tools::Doctor(args) => doctor::run(args).await,
+        Tools::Secret(args) => secret::run(args),
+        #[cfg(feature = "agent")]
+        Tools::Agent(args) => agent::run(args, logging::command::<Args>()).await,
+        #[cfg(feature = "api")]
+        Tools::Api(args) => api::run(args).await,
+        Tools::Decode(args) => decode::run(args).await,
+        Tools::Service(_) => unreachable!("handled above"),
+        Tools::Schedule(args) => schedule::run(args, logging::command::<Args>()).await,
+        Tools::Completions { shell } => completions::print::<Args>(shell, "edge"),
+        Tools::GenMan { directory } => man::generate::<Args>("edge", directory.as_deref()),
+    }
+    if let Some(service) = service {
+        service.stopped();
+    }
+    edge_core::expect::finish();
+}
@@ -0,0 +1,544 @@
+//! `edge scan <targets>`: an inventory of the devices and brokers on a new site. Every address
+//! in a subnet is tried on the usual edge protocol ports, and whatever answers is asked who it
+//! is in its own protocol:
+//!
+//! ```text
+//! $ edge scan 10.0.0.0/24
+//! 10.0.0.5:502      modbus   Schneider Electric PM5560 v2.1.4
+//! 10.0.0.9:20000    dnp3     DNP3 outstation 10
+//! 10.0.0.20:1883    mqtt     MQTT broker, anonymous access allowed
+//! 10.0.0.20:4222    nats     NATS server hub-1 2.10.4
+//! 10.0.0.31:4840    opc-ua   OPC UA server, protocol version 0
+//! Found 5 endpoints on 4 of 254 hosts in 1.3s
+//! ```
+//!
+//! Modbus devices are asked for their device identification, NATS servers for their INFO,
+//! MQTT brokers whether they take an anonymous CONNECT, OPC UA servers for a HEL/ACK and DNP3
+//! outstations at the common link addresses for their link status. Other ports given with
+//! `--ports` are reported with the first line they send, if any. A port that accepts the
+//! connection but doesn't answer in its protocol is listed as `unknown`, so nothing open goes
+//! unnoticed.
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use dnp3_sim::link;
+use edge_core::exit::Code;
+use edge_core::output::{OutputArgs, Record, Value};
+use edge_core::OrExit;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+const DEFAULT_PORTS: [u16; 5] = [502, 4222, 1883, 4840, 20000];
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_PARALLEL: usize = 256;
+// A /16: more than that is rarely one site and takes a long time to sweep.
+const MAX_HOSTS: usize = 65_536;
+// Link addresses outstations most often ship with; DNP3 devices ignore frames for others.
+const DNP3_OUTSTATIONS: [u16; 3] = [1, 10, 1024];
+const DNP3_MASTER: u16 = 1;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// What to sweep: a subnet like 10.0.0.0/24, an address or hostname, or a comma list of them.
+    #[clap(value_parser)]
+    targets: String,
+    /// Ports to try, 502,4222,1883,4840,20000 by default. PORT=PROTOCOL speaks a protocol on a
+    /// port other than its usual one, e.g. 5020=modbus.
+    #[clap(short, long, value_delimiter = ',', value_parser = parse_port)]
+    ports: Vec<(u16, Protocol)>,
+    /// Give up on a port after this long, e.g. 2s; 500ms by default.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+    /// Connections to try at once; 256 by default.
+    #[clap(long, action)]
+    parallel: Option<usize>,
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Protocol {
+    Modbus,
+    Nats,
+    Mqtt,
+    OpcUa,
+    Dnp3,
+    /// Just connect and report the banner, if any.
+    Tcp,
+}
+
+impl Protocol {
+    fn of_port(port: u16) -> Protocol {
+        match port {
+            502 => Protocol::Modbus,
+            4222 => Protocol::Nats,
+            1883 => Protocol::Mqtt,
+            4840 => Protocol::OpcUa,
+            20000 => Protocol::Dnp3,
+            _ => Protocol::Tcp,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Protocol::Modbus => "modbus",
+            Protocol::Nats => "nats",
+            Protocol::Mqtt => "mqtt",
+            Protocol::OpcUa => "opc-ua",
+            Protocol::Dnp3 => "dnp3",
+            Protocol::Tcp => "tcp",
+        }
+    }
+}
+
+/// `502` or `5020=modbus`.
+fn parse_port(value: &str) -> Result<(u16, Protocol), String> {
+    let (port, protocol) = match value.split_once('=') {
+        Some((port, protocol)) => (port, Some(protocol)),
+        None => (value, None),
+    };
+    let port: u16 = port
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid port {port}"))?;
+    let protocol = match protocol {
+        Some(protocol) => Protocol::from_str(protocol.trim(), true).map_err(|_| {
+            format!("unknown protocol {protocol}, expected modbus, nats, mqtt, opc-ua, dnp3 or tcp")
+        })?,
+        None => Protocol::of_port(port),
+    };
+    Ok((port, protocol))
+}
+
+/// What answered on one port.
+struct Endpoint {
+    address: SocketAddr,
+    protocol: &'static str,
+    identity: String,
+    details: Record,
+}
+
+impl Endpoint {
+    fn record(&self) -> Record {
+        let mut record = Record::new()
+            .field("address", self.address.ip().to_string())
+            .field("port", self.address.port())
+            .field("protocol", self.protocol)
+            .field("identity", self.identity.as_str());
+        for (name, value) in self.details.fields() {
+            record.push(name, value.clone());
+        }
+        record
+    }
+}
+
+pub async fn run(args: Args) {
+    let hosts = hosts(&args.targets).or_exit_with(Code::Usage, "Invalid scan targets");
+    let ports = match args.ports.is_empty() {
+        true => DEFAULT_PORTS
+            .iter()
+            .map(|&port| (port, Protocol::of_port(port)))
+            .collect(),
+        false => args.ports,
+    };
+    let timeout = args.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let parallel = Arc::new(Semaphore::new(
+        args.parallel.unwrap_or(DEFAULT_PARALLEL).max(1),
+    ));
+    log::info!(
+        "Scanning {} hosts on ports {}",
+        hosts.len(),
+        ports
+            .iter()
+            .map(|(port, _)| port.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let start = Instant::now();
+    let mut probes = JoinSet::new();
+    for &host in &hosts {
+        for &(port, protocol) in &ports {
+            let parallel = parallel.clone();
+            probes.spawn(async move {
+                let _permit = parallel.acquire_owned().await.ok()?;
+                probe(SocketAddr::new(host, port), protocol, timeout).await
+            });
+        }
+    }
+    let mut endpoints = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        if let Ok(Some(endpoint)) = result {
+            log::debug!("{} answered as {}", endpoint.address, endpoint.protocol);
+            endpoints.push(endpoint);
+        }
+    }
+    endpoints.sort_by_key(|endpoint| endpoint.address);
+
+    let records: Vec<Record> = endpoints.iter().map(Endpoint::record).collect();
+    args.output.load().records(&records, || {
+        for endpoint in &endpoints {
+            println!(
+                "{:<17} {:<8} {}",
+                endpoint.address.to_string(),
+                endpoint.protocol,
+                endpoint.identity
+            );
+        }
+    });
+    let mut found: Vec<IpAddr> = endpoints
+        .iter()
+        .map(|endpoint| endpoint.address.ip())
+        .collect();
+    found.dedup();
+    eprintln!(
+        "Found {} on {} of {} in {:.1}s",
+        plural(endpoints.len(), "endpoint"),
+        found.len(),
+        plural(hosts.len(), "host"),
+        start.elapsed().as_secs_f64()
+    );
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        count => format!("{count} {noun}s"),
+    }
+}
+
+/// The addresses `targets` names, in order and without repeats.
+fn hosts(targets: &str) -> Result<Vec<IpAddr>, String> {
+    let mut hosts: Vec<IpAddr> = Vec::new();
+    let mut seen = HashSet::new();
+    for target in targets.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let found = match target.split_once('/') {
+            Some((network, prefix)) => {
+                let network: Ipv4Addr = network
+                    .parse()
+                    .map_err(|_| format!("{target} isn't an IPv4 subnet"))?;
+                let prefix: u32 = match prefix.parse() {
+                    Ok(prefix) if prefix <= 32 => prefix,
+                    _ => return Err(format!("invalid prefix length in {target}")),
+                };
+                if 32 - prefix > MAX_HOSTS.trailing_zeros() {
+                    return Err(format!(
+                        "{target} is too large to scan; split it into /16 or smaller"
+                    ));
+                }
+                subnet(network, prefix)
+            }
+            None => match target.parse::<IpAddr>() {
+                Ok(ip) => vec![ip],
+                Err(_) => vec![edge_core::net::resolve(target, 0)
+                    .map_err(|err| format!("unable to resolve {target}: {err}"))?
+                    .ip()],
+            },
+        };
+        for host in found {
+            if seen.insert(host) {
+                hosts.push(host);
+            }
+        }
+        if hosts.len() > MAX_HOSTS {
+            return Err(format!("more than {MAX_HOSTS} hosts"));
+        }
+    }
+    if hosts.is_empty() {
+        return Err(format!("no hosts in {targets}"));
+    }
+    Ok(hosts)
+}
+
+/// The host addresses of a subnet: all but the network and broadcast addresses, except in /31
+/// and /32 which have none.
+fn subnet(network: Ipv4Addr, prefix: u32) -> Vec<IpAddr> {
+    let size = 1u64 << (32 - prefix);
+    let first = u32::from(network) as u64 & !(size - 1);
+    let range = match size {
+        1 | 2 => first..first + size,
+        _ => first + 1..first + size - 1,
+    };
+    range
+        .map(|address| IpAddr::V4(Ipv4Addr::from(address as u32)))
+        .collect()
+}
+
+/// Connects to `address` and asks who's there; `None` when nothing listens.
+async fn probe(address: SocketAddr, protocol: Protocol, timeout: Duration) -> Option<Endpoint> {
+    let mut stream = match tokio::time::timeout(timeout, TcpStream::connect(address)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            log::trace!("{address}: {err}");
+            return None;
+        }
+        Err(_) => return None,
+    };
+    let answer = match tokio::time::timeout(timeout, identify(&mut stream, protocol)).await {
+        Ok(answer) => answer,
+        Err(_) => Err("timed out".into()),
+    };
+    Some(match answer {
+        Ok((identity, details)) => Endpoint {
+            address,
+            protocol: protocol.name(),
+            identity,
+            details,
+        },
+        Err(err) => Endpoint {
+            address,
+            protocol: "unknown",
+            identity: format!("open, but no {} answer: {err}", protocol.name()),
+            details: Record::new(),
+        },
+    })
+}
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+async fn identify(stream: &mut TcpStream, protocol: Protocol) -> Result<(String, Record), Error> {
+    match protocol {
+        Protocol::Modbus => modbus(stream).await,
+        Protocol::Nats => nats(stream).await,
+        Protocol::Mqtt => mqtt(stream).await,
+        Protocol::OpcUa => opc_ua(stream).await,
+        Protocol::Dnp3 => dnp3(stream).await,
+        Protocol::Tcp => banner(stream).await,
+    }
+}
+
+/// Read Device Identification (function 43, MEI 14), basic objects, to unit 1.
+async fn modbus(stream: &mut TcpStream) -> Result<(String, Record), Error> {
+    const UNIT: u8 = 1;
+    let transaction = 0x5343u16.to_be_bytes();
+    let request = [
+        transaction[0],
+        transaction[1],
+        0,
+        0,
+        0,
+        5,
+        UNIT,
+        0x2b,
+        0x0e,
+        0x01,
+        0x00,
+    ];
+    stream.write_all(&request).await?;
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).await?;
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if header[..2] != transaction || header[2..4] != [0, 0] || !(2..=254).contains(&length) {
+        return Err("not a Modbus TCP response".into());
+    }
+    let mut pdu = vec![0u8; length - 1];
+    stream.read_exact(&mut pdu).await?;
+    let details = Record::new().field("unit_id", header[6]);
+    match pdu.as_slice() {
+        [0xab, code, ..] => Ok((
+            format!("Modbus device without device identification (exception {code})"),
+            details,
+        )),
+        [0x2b, 0x0e, _, _, _, _, count, objects @ ..] => {
+            let mut details = details;
+            let mut identity = Vec::new();
+            let mut rest = objects;
+            for _ in 0..*count {
+                let [id, length, tail @ ..] = rest else {
+                    break;
+                };
+                let length = (*length as usize).min(tail.len());
+                let text = String::from_utf8_lossy(&tail[..length]).trim().to_string();
+                let name = match id {
+                    0 => "vendor",
+                    1 => "product_code",
+                    2 => "revision",
+                    _ => "",
+                };
+                if !name.is_empty() && !text.is_empty() {
+                    identity.push(match id {
+                        2 if !text.starts_with(['v', 'V']) => format!("v{text}"),
+                        _ => text.clone(),
+                    });
+                    details.push(name, text);
+                }
+                rest = &tail[length..];
+            }
+            match identity.is_empty() {
+                true => Ok(("Modbus device".to_string(), details)),
+                false => Ok((identity.join(" "), details)),
+            }
+        }
+        _ => Err(format!("unexpected Modbus response {pdu:02x?}").into()),
+    }
+}
+
+/// NATS servers greet every client with an INFO line.
+async fn nats(stream: &mut TcpStream) -> Result<(String, Record), Error> {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await?;
+    let info = line
+        .strip_prefix("INFO ")
+        .ok_or("no INFO from the server")?;
+    let Ok(Value::Record(info)) = edge_core::json::parse(info.trim()) else {
+        return Err("invalid INFO from the server".into());
+    };
+    let text = |name: &str| match info.get(name) {
+        Some(Value::String(text)) => text.clone(),
+        _ => String::new(),
+    };
+    let mut details = Record::new();
+    for name in [
+        "server_name",
+        "version",
+        "server_id",
+        "auth_required",
+        "tls_required",
+        "jetstream",
+    ] {
+        if let Some(value) = info.get(name) {
+            details.push(name, value.clone());
+        }
+    }
+    let identity = [
+        "NATS server".to_string(),
+        text("server_name"),
+        text("version"),
+    ]
+    .into_iter()
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ");
+    Ok((identity, details))
+}
+
+/// An anonymous MQTT 3.1.1 CONNECT, to see whether the broker takes it.
+async fn mqtt(stream: &mut TcpStream) -> Result<(String, Record), Error> {
+    let client_id = format!("edge-scan-{}", std::process::id());
+    let mut body = vec![0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 10];
+    body.extend_from_slice(&(client_id.len() as u16).to_be_bytes());
+    body.extend_from_slice(client_id.as_bytes());
+    let mut connect = vec![0x10, body.len() as u8];
+    connect.extend(body);
+    stream.write_all(&connect).await?;
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack).await?;
+    if connack[..2] != [0x20, 2] {
+        return Err("no CONNACK from the broker".into());
+    }
+    let code = connack[3];
+    if code == 0 {
+        // Leave politely rather than as a dropped client.
+        let _ = stream.write_all(&[0xe0, 0]).await;
+    }
+    let identity = match code {
+        0 => "MQTT broker, anonymous access allowed".to_string(),
+        4 | 5 => "MQTT broker, login required".to_string(),
+        code => format!("MQTT broker, refused the connection (return code {code})"),
+    };
+    let details = Record::new()
+        .field("return_code", code)
+        .field("anonymous", code == 0);
+    Ok((identity, details))
+}
+
+/// An OPC UA binary HEL message; servers answer with ACK, or ERR when they don't like it.
+async fn opc_ua(stream: &mut TcpStream) -> Result<(String, Record), Error> {
+    let endpoint = format!("opc.tcp://{}", stream.peer_addr()?);
+    let mut body = Vec::new();
+    for field in [0u32, 65_536, 65_536, 0, 0] {
+        body.extend_from_slice(&field.to_le_bytes());
+    }
+    body.extend_from_slice(&(endpoint.len() as i32).to_le_bytes());
+    body.extend_from_slice(endpoint.as_bytes());
+    let mut hello = b"HELF".to_vec();
+    hello.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+    hello.extend(body);
+    stream.write_all(&hello).await?;
+
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await?;
+    let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if !(8..=65_536).contains(&size) {
+        return Err("not an OPC UA message".into());
+    }
+    let mut body = vec![0u8; size - 8];
+    stream.read_exact(&mut body).await?;
+    let number = |index: usize| {
+        body.get(index * 4..index * 4 + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    match &header[..4] {
+        b"ACKF" => {
+            let version = number(0).ok_or("short ACK")?;
+            let details = Record::new()
+                .field("protocol_version", version)
+                .field("max_message_size", number(3).unwrap_or(0));
+            Ok((
+                format!("OPC UA server, protocol version {version}"),
+                details,
+            ))
+        }
+        b"ERRF" => {
+            let status = number(0).ok_or("short ERR")?;
+            Ok((
+                format!("OPC UA server, refused the hello (status {status:#010x})"),
+                Record::new().field("status", status),
+            ))
+        }
+        other => Err(format!(
+            "unexpected OPC UA message {}",
+            String::from_utf8_lossy(other)
+        )
+        .into()),
+    }
+}
+
+/// REQUEST LINK STATUS to each of the usual outstation addresses; the first to answer is it.
+async fn dnp3(stream: &mut TcpStream) -> Result<(String, Record), Error> {
+    for outstation in DNP3_OUTSTATIONS {
+        let request = link::encode_frame(
+            link::CTRL_DIR | link::CTRL_PRM | link::FC_REQUEST_LINK_STATUS,
+            outstation,
+            DNP3_MASTER,
+            &[],
+        );
+        stream.write_all(&request).await?;
+    }
+    loop {
+        let frame = link::read_frame(stream).await?;
+        if frame.control & link::CTRL_PRM == 0 {
+            return Ok((
+                format!("DNP3 outstation {}", frame.source),
+                Record::new().field("outstation", frame.source),
+            ));
+        }
+    }
+}
+
+/// Whatever the service says first, e.g. an SSH or SMTP greeting.
+async fn banner(stream: &mut TcpStream) -> Result<(String, Record), Error> {
+    let mut buffer = [0u8; 256];
+    let read = tokio::time::timeout(Duration::from_millis(200), stream.read(&mut buffer)).await;
+    let text = match read {
+        Ok(Ok(read)) => String::from_utf8_lossy(&buffer[..read])
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect(),
+        _ => String::new(),
+    };
+    match text.is_empty() {
+        true => Ok(("open".to_string(), Record::new())),
+        false => Ok((text.clone(), Record::new().field("banner", text))),
+    }
+}
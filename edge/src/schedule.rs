@@ -0,0 +1,248 @@
+//! `edge schedule`: runs the config file's `[jobs.NAME]` tables until stopped, so a gateway
+//! needs one service rather than a crontab of CLI calls:
+//!
+//! ```text
+//! $ edge schedule --list
+//! plant-poll   every 5m     next 2026-10-14T10:05:00Z  modbus 10.0.0.5 read-register -r 40070
+//! rotate-logs  30 2 * * *   next 2026-10-15T02:30:00Z  logrotate /etc/logrotate.d/edge
+//! $ edge schedule           # as a systemd unit or `edge service install -- schedule`
+//! ```
+//!
+//! `run` jobs are edge command lines, checked before anything starts; `shell` jobs go through
+//! `sh -c` (`cmd /C` on Windows). What a job prints goes to the scheduler's own stdout and
+//! stderr. An `every` job first runs one interval after the scheduler starts. A job still
+//! running when it's due again isn't started twice: the run it missed is skipped. A job past
+//! its `timeout`, or still running when the scheduler stops, is killed; that's the process the
+//! job started, not what it started in turn, so a long `shell` job should `exec` its command.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use edge_core::config::{self, Command, Job};
+use edge_core::exit::{self, Code};
+use edge_core::plugin;
+use edge_core::shutdown::{self, Summary};
+use edge_core::OrExit;
+
+use crate::batch;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only run this job; may be repeated. Every job in the config by default.
+    #[clap(long = "job", value_name = "NAME", action)]
+    jobs: Vec<String>,
+    /// Print each job and when it's next due, then exit.
+    #[clap(long, action)]
+    list: bool,
+    /// Run each job once now, one after another, and exit with the first failure's status.
+    #[clap(long, action)]
+    once: bool,
+}
+
+/// A job ready to run: edge command lines are split once, up front.
+struct Scheduled {
+    job: Job,
+    words: Vec<String>,
+}
+
+/// Runs the jobs until stopped. `command` is edge's own, for checking `run` lines parse.
+pub async fn run(args: Args, command: clap::Command<'static>) {
+    let jobs = config::jobs().or_exit_with(Code::Usage, "Unable to read the jobs");
+    if let Some(name) = args
+        .jobs
+        .iter()
+        .find(|name| !jobs.iter().any(|job| &job.name == *name))
+    {
+        exit::fatal_with(Code::Usage, format!("No job named {name} in the config"));
+    }
+    let jobs: Vec<Scheduled> = jobs
+        .into_iter()
+        .filter(|job| args.jobs.is_empty() || args.jobs.contains(&job.name))
+        .map(|job| {
+            let words = check(&job, &command).unwrap_or_else(|err| {
+                exit::fatal_with(Code::Usage, format!("Invalid job {}: {err}", job.name))
+            });
+            Scheduled { job, words }
+        })
+        .collect();
+    if jobs.is_empty() {
+        exit::fatal_with(
+            Code::Usage,
+            "No jobs to run; add [jobs.NAME] tables to the config",
+        );
+    }
+    let edge = std::env::current_exe().or_exit("Unable to find the edge executable");
+
+    if args.list {
+        list(&jobs);
+    } else if args.once {
+        once(&edge, &jobs).await;
+    } else {
+        daemon(edge, jobs).await;
+    }
+}
+
+/// The words of a `run` job, which must parse as an edge command that isn't another
+/// scheduler or a service.
+fn check(job: &Job, command: &clap::Command<'static>) -> Result<Vec<String>, String> {
+    let Command::Edge(line) = &job.command else {
+        return Ok(Vec::new());
+    };
+    let words = batch::split(line, &Default::default())?;
+    let matches = command
+        .clone()
+        .try_get_matches_from(std::iter::once("edge").chain(words.iter().map(String::as_str)))
+        .map_err(|err| {
+            let message = err.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            first.trim_start_matches("error: ").to_string()
+        })?;
+    match matches.subcommand_name() {
+        Some(tool @ ("schedule" | "service")) => Err(format!("a job can't run `edge {tool}`")),
+        _ => Ok(words),
+    }
+}
+
+fn list(jobs: &[Scheduled]) {
+    let now = SystemTime::now();
+    let rows: Vec<[String; 4]> = jobs
+        .iter()
+        .map(|Scheduled { job, .. }| {
+            let next = match job.schedule.next(now, now) {
+                Some(next) => format!("next {}", humantime::format_rfc3339_seconds(next)),
+                None => "never due".to_string(),
+            };
+            [
+                job.name.clone(),
+                job.schedule.to_string(),
+                next,
+                describe(&job.command).to_string(),
+            ]
+        })
+        .collect();
+    let width = |column: usize| rows.iter().map(|row| row[column].len()).max().unwrap_or(0);
+    let (name, schedule, next) = (width(0), width(1), width(2));
+    for [a, b, c, d] in rows {
+        println!("{a:name$}  {b:schedule$}  {c:next$}  {d}");
+    }
+}
+
+async fn once(edge: &Path, jobs: &[Scheduled]) {
+    let mut failed = None;
+    for scheduled in jobs {
+        if let Err(code) = run_job(edge, scheduled).await {
+            failed = failed.or(Some(code));
+        }
+    }
+    if let Some(code) = failed {
+        std::process::exit(code.status());
+    }
+}
+
+async fn daemon(edge: PathBuf, jobs: Vec<Scheduled>) {
+    let edge = Arc::new(edge);
+    let summary = Arc::new(Summary::new(&["runs"]));
+    let start = SystemTime::now();
+    shutdown::listen();
+    log::info!("Scheduling {} jobs", jobs.len());
+    let tasks: Vec<_> = jobs
+        .into_iter()
+        .map(|scheduled| {
+            let (edge, summary) = (edge.clone(), summary.clone());
+            tokio::spawn(async move {
+                loop {
+                    let now = SystemTime::now();
+                    let Some(next) = scheduled.job.schedule.next(start, now) else {
+                        log::warn!(
+                            "Job {} is never due ({})",
+                            scheduled.job.name,
+                            scheduled.job.schedule
+                        );
+                        return;
+                    };
+                    tokio::time::sleep(next.duration_since(now).unwrap_or_default()).await;
+                    summary.count("runs");
+                    if run_job(&edge, &scheduled).await.is_err() {
+                        summary.error();
+                    }
+                }
+            })
+        })
+        .collect();
+    shutdown::requested().await;
+    // Dropping a job's child process kills it.
+    for task in &tasks {
+        task.abort();
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+    summary.print();
+}
+
+/// Runs a job once, logging how it went; the error is the status it failed with.
+async fn run_job(edge: &Path, scheduled: &Scheduled) -> Result<(), Code> {
+    let Scheduled { job, words } = scheduled;
+    let mut child = match &job.command {
+        Command::Edge(_) => {
+            let mut child = tokio::process::Command::new(edge);
+            child.args(words);
+            child
+        }
+        Command::Shell(line) => plugin::shell(line),
+    };
+    log::info!("Job {}: running `{}`", job.name, describe(&job.command));
+    let started = Instant::now();
+    let status = async {
+        let mut child = child
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| (Code::Failure, format!("unable to start it: {err}")))?;
+        let status = match job.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, child.wait())
+                .await
+                .map_err(|_| {
+                    (
+                        Code::Timeout,
+                        format!("killed after {}", humantime::format_duration(timeout)),
+                    )
+                })?,
+            None => child.wait().await,
+        };
+        status.map_err(|err| (Code::Failure, err.to_string()))
+    }
+    .await;
+    let elapsed = Duration::from_millis(started.elapsed().as_millis() as u64);
+    let failure = match status {
+        Ok(status) if status.success() => {
+            log::info!(
+                "Job {} finished in {}",
+                job.name,
+                humantime::format_duration(elapsed)
+            );
+            return Ok(());
+        }
+        Ok(status) => match (&job.command, status.code()) {
+            (Command::Edge(_), Some(status)) => {
+                let code = Code::from_status(status);
+                (
+                    code,
+                    format!("exit status {status}, {}", code.description()),
+                )
+            }
+            _ => (Code::Failure, status.to_string()),
+        },
+        Err(failure) => failure,
+    };
+    log::warn!("Job {} failed: {}", job.name, failure.1);
+    Err(failure.0)
+}
+
+fn describe(command: &Command) -> &str {
+    match command {
+        Command::Edge(line) | Command::Shell(line) => line,
+    }
+}
@@ -0,0 +1,144 @@
+//! `edge secret`: keys and encrypted values for the config's `[encryption]` table:
+//!
+//! ```text
+//! $ edge secret keygen                         # writes identity.txt, prints its recipient
+//! age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p
+//! $ echo hunter2 | edge secret encrypt         # for a profile's password or token
+//! age:YWdlLWVuY3J5cHRpb24ub3JnL3YxCi0+IFgyNTUxOSB...
+//! $ edge secret decrypt site.pcapng.age > site.pcapng
+//! ```
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use edge_core::age::{self, Identity, Recipient};
+use edge_core::config::{self, Encryption};
+use edge_core::exit::{self, Code};
+use edge_core::OrExit;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Create an identity for decrypting captures and secrets and print its recipient, the
+    /// public key to list under [encryption] recipients.
+    Keygen {
+        /// Where to write the identity; the config's identity file by default.
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Encrypt a password or token read from stdin into an age: value for a profile.
+    Encrypt {
+        /// Encrypt to this age1... recipient instead of the configured ones; may be repeated.
+        #[clap(short, long = "recipient", value_parser = Recipient::parse, action)]
+        recipients: Vec<Recipient>,
+    },
+    /// Decrypt an age file, such as an encrypted capture, with the configured identity.
+    Decrypt {
+        #[clap(value_parser)]
+        file: PathBuf,
+        /// Write the plaintext here instead of stdout.
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn run(args: Args) {
+    match args.command {
+        Command::Keygen { output } => {
+            let path = output.unwrap_or_else(|| configured().identity);
+            keygen(&path).or_exit(&format!("Unable to write {}", path.display()));
+        }
+        Command::Encrypt { recipients } => {
+            let recipients = match recipients.is_empty() {
+                true => recipients_or_own(&configured()),
+                false => recipients,
+            };
+            let mut secret = String::new();
+            std::io::stdin()
+                .read_to_string(&mut secret)
+                .or_exit("Unable to read the secret");
+            let secret = secret.trim_end_matches(['\r', '\n']);
+            if secret.is_empty() {
+                exit::fatal_with(Code::Usage, "Nothing to encrypt on stdin");
+            }
+            let value = config::encrypt_secret(secret, &recipients).or_exit("Unable to encrypt");
+            println!("{value}");
+        }
+        Command::Decrypt { file, output } => {
+            let identities = configured()
+                .identities()
+                .or_exit_with(Code::Usage, "Invalid configuration");
+            let data = std::fs::read(&file).or_exit(&format!("Unable to read {}", file.display()));
+            let plain = age::decrypt(&data, &identities)
+                .or_exit_with(Code::Auth, &format!("Unable to open {}", file.display()));
+            match output {
+                Some(path) => std::fs::write(&path, plain)
+                    .or_exit(&format!("Unable to write {}", path.display())),
+                None => std::io::stdout()
+                    .write_all(&plain)
+                    .or_exit("Unable to write the plaintext"),
+            }
+        }
+    }
+}
+
+fn configured() -> Encryption {
+    config::encryption().or_exit_with(Code::Usage, "Invalid configuration")
+}
+
+/// The configured recipients, or the configured identity's own when there are none.
+fn recipients_or_own(encryption: &Encryption) -> Vec<Recipient> {
+    if !encryption.recipients.is_empty() {
+        return encryption.recipients.clone();
+    }
+    encryption
+        .identities()
+        .or_exit_with(
+            Code::Usage,
+            "No [encryption] recipients and no identity to encrypt to",
+        )
+        .iter()
+        .map(Identity::recipient)
+        .collect()
+}
+
+/// Writes a new identity only the owner can read, the way `age-keygen` lays it out.
+fn keygen(path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        exit::fatal_with(
+            Code::Usage,
+            format!("{} already exists; not replacing it", path.display()),
+        );
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let identity = Identity::generate();
+    let recipient = identity.recipient();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    write!(
+        file,
+        "# created: {}\n# public key: {recipient}\n{}\n",
+        humantime::format_rfc3339_seconds(SystemTime::now()),
+        identity.to_secret_string()
+    )?;
+    eprintln!("Wrote {}", path.display());
+    println!("{recipient}");
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! `edge service`: runs a long-lived command, such as a bridge or the agent, as a Windows service
+//! that starts with the machine:
+//!
+//! ```text
+//! > edge service install --name edge-bridge -- bridge run plant.yaml
+//! > sc start edge-bridge
+//! > edge service uninstall --name edge-bridge
+//! ```
+//!
+//! The service manager starts `edge service run` with the command, in the directory it was
+//! installed from and with the config file the installing user had, so relative paths and
+//! profiles mean the same as in the console. Stopping the service stops the command the way
+//! Ctrl-C does, once sinks are flushed; a command that doesn't stop on its own within 20 seconds
+//! is ended. A service has no console, so what the command prints is lost: it should write
+//! where it's read, through a bridge's sinks or the agent's replies. On Linux, run the command
+//! from a systemd unit instead.
+
+#[cfg(windows)]
+mod scm;
+
+use std::path::PathBuf;
+
+use clap::FromArgMatches;
+use edge_core::exit::{self, Code};
+use edge_core::OrExit;
+
+#[cfg(windows)]
+pub use scm::Running;
+
+/// A service started by the service manager; there are none off Windows.
+#[cfg(not(windows))]
+pub enum Running {}
+
+#[cfg(not(windows))]
+impl Running {
+    pub fn stopped(self) {
+        match self {}
+    }
+}
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Register a service that runs the command after `--`, e.g. `-- bridge run plant.yaml`.
+    /// Needs an administrator prompt.
+    Install {
+        /// Service name, for `sc start` and the Services console.
+        #[clap(long, value_parser)]
+        name: String,
+        /// Name shown in the Services console; the service name by default.
+        #[clap(long, value_parser)]
+        display_name: Option<String>,
+        /// Start the service only when asked rather than with the machine.
+        #[clap(long, action)]
+        manual: bool,
+        #[clap(value_parser, required = true, last = true)]
+        command: Vec<String>,
+    },
+    /// Stop and remove a service registered with install.
+    Uninstall {
+        #[clap(long, value_parser)]
+        name: String,
+    },
+    /// What the service manager starts; runs the command as the named service.
+    #[clap(hide = true)]
+    Run {
+        #[clap(long, value_parser)]
+        name: String,
+        /// Directory to run the command in.
+        #[clap(long, value_parser)]
+        directory: Option<PathBuf>,
+        /// Config file for the command, as EDGE_CONFIG.
+        #[clap(long, value_parser)]
+        config: Option<PathBuf>,
+        #[clap(value_parser, required = true, last = true)]
+        command: Vec<String>,
+    },
+}
+
+/// Installs or removes a service, or for `service run` reports the service started and returns
+/// the command to run in it.
+pub fn run(args: Args, command: clap::Command<'static>) -> Option<(crate::Args, Running)> {
+    match args.command {
+        Command::Install {
+            name,
+            display_name,
+            manual,
+            command: words,
+        } => {
+            parse(command, &words);
+            install(&name, display_name.as_deref(), !manual, &words);
+            None
+        }
+        Command::Uninstall { name } => {
+            uninstall(&name);
+            None
+        }
+        Command::Run {
+            name,
+            directory,
+            config,
+            command: words,
+        } => {
+            let cli = parse(command, &words);
+            if let Some(directory) = &directory {
+                std::env::set_current_dir(directory)
+                    .or_exit(&format!("Unable to change to {}", directory.display()));
+            }
+            if let Some(config) = &config {
+                std::env::set_var("EDGE_CONFIG", config);
+            }
+            start(&name).map(|service| (cli, service))
+        }
+    }
+}
+
+/// `words` as an `edge` command line, refusing what can't run as a service.
+fn parse(command: clap::Command<'static>, words: &[String]) -> crate::Args {
+    let matches = command
+        .try_get_matches_from(std::iter::once("edge").chain(words.iter().map(String::as_str)))
+        .map_err(|err| {
+            let message = err.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            first.trim_start_matches("error: ").to_string()
+        })
+        .or_exit_with(Code::Usage, "Invalid service command");
+    let cli = crate::Args::from_arg_matches(&matches)
+        .or_exit_with(Code::Usage, "Invalid service command");
+    if let crate::Tools::Service(_) = cli.tool {
+        exit::fatal_with(
+            Code::Usage,
+            "Invalid service command: a service can't run `edge service`",
+        );
+    }
+    cli
+}
+
+#[cfg(windows)]
+fn install(name: &str, display_name: Option<&str>, automatic: bool, words: &[String]) {
+    let edge = std::env::current_exe().or_exit("Unable to find the edge executable");
+    let directory = std::env::current_dir().or_exit("Unable to read the current directory");
+    let mut line = vec![
+        edge.to_string_lossy().into_owned(),
+        "service".to_string(),
+        "run".to_string(),
+        "--name".to_string(),
+        name.to_string(),
+        "--directory".to_string(),
+        directory.to_string_lossy().into_owned(),
+    ];
+    if let Some(config) = edge_core::config::default_path()
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| std::fs::canonicalize(path).ok())
+    {
+        line.extend([
+            "--config".to_string(),
+            config.to_string_lossy().into_owned(),
+        ]);
+    }
+    line.push("--".to_string());
+    line.extend(words.iter().cloned());
+    let description = format!("edge {}", words.join(" "));
+    scm::install(
+        name,
+        display_name.unwrap_or(name),
+        &description,
+        &scm::command_line(&line),
+        automatic,
+    )
+    .or_exit(&format!("Unable to install service {name}"));
+    println!("Installed service {name}; start it with `sc start {name}`");
+}
+
+#[cfg(windows)]
+fn uninstall(name: &str) {
+    scm::uninstall(name).or_exit(&format!("Unable to remove service {name}"));
+    println!("Removed service {name}");
+}
+
+#[cfg(windows)]
+fn start(name: &str) -> Option<Running> {
+    let service = scm::Running::start(name).or_exit_with(
+        Code::Usage,
+        "Unable to reach the service manager; `edge service run` is only for services",
+    );
+    Some(service)
+}
+
+#[cfg(not(windows))]
+fn install(_: &str, _: Option<&str>, _: bool, words: &[String]) {
+    unavailable(words)
+}
+
+#[cfg(not(windows))]
+fn uninstall(_: &str) {
+    unavailable(&[])
+}
+
+#[cfg(not(windows))]
+fn start(_: &str) -> Option<Running> {
+    unavailable(&[])
+}
+
+#[cfg(not(windows))]
+fn unavailable(words: &[String]) -> ! {
+    let example = match words {
+        [] => "edge".to_string(),
+        words => format!("`edge {}`", words.join(" ")),
+    };
+    exit::fatal_with(
+        Code::Usage,
+        format!("Services are only for Windows; run {example} from a systemd unit instead"),
+    )
+}
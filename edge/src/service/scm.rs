@@ -0,0 +1,357 @@
+//! Just enough of the Windows service control manager API for `edge service`: registering and
+//! removing a service, and the dispatcher handshake a service process owes the manager.
+
+use std::ffi::{c_void, OsStr};
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+use std::sync::{mpsc, Condvar, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use edge_core::shutdown;
+
+// SC_HANDLE and SERVICE_STATUS_HANDLE, both pointer-sized.
+type RawHandle = isize;
+
+const SC_MANAGER_CONNECT: u32 = 0x0001;
+const SC_MANAGER_CREATE_SERVICE: u32 = 0x0002;
+const SERVICE_CHANGE_CONFIG: u32 = 0x0002;
+const SERVICE_QUERY_STATUS: u32 = 0x0004;
+const SERVICE_STOP: u32 = 0x0020;
+const DELETE: u32 = 0x0001_0000;
+
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+const SERVICE_AUTO_START: u32 = 2;
+const SERVICE_DEMAND_START: u32 = 3;
+const SERVICE_ERROR_NORMAL: u32 = 1;
+const SERVICE_CONFIG_DESCRIPTION: u32 = 1;
+
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+const SERVICE_ACCEPT_STOP: u32 = 0x1;
+const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+const SERVICE_CONTROL_STOP: u32 = 1;
+const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+
+const NO_ERROR: u32 = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+const ERROR_SERVICE_NOT_ACTIVE: i32 = 1062;
+
+// How long a stopping command has to finish before the process ends anyway.
+const STOP_WAIT: Duration = Duration::from_secs(20);
+
+#[repr(C)]
+#[derive(Default)]
+struct ServiceStatus {
+    service_type: u32,
+    current_state: u32,
+    controls_accepted: u32,
+    win32_exit_code: u32,
+    service_specific_exit_code: u32,
+    check_point: u32,
+    wait_hint: u32,
+}
+
+#[repr(C)]
+struct TableEntry {
+    name: *mut u16,
+    main: Option<unsafe extern "system" fn(u32, *mut *mut u16)>,
+}
+
+#[repr(C)]
+struct Description {
+    description: *mut u16,
+}
+
+type Handler = unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn OpenSCManagerW(machine: *const u16, database: *const u16, access: u32) -> RawHandle;
+    fn CreateServiceW(
+        manager: RawHandle,
+        name: *const u16,
+        display_name: *const u16,
+        access: u32,
+        service_type: u32,
+        start_type: u32,
+        error_control: u32,
+        binary_path: *const u16,
+        load_order_group: *const u16,
+        tag_id: *mut u32,
+        dependencies: *const u16,
+        account: *const u16,
+        password: *const u16,
+    ) -> RawHandle;
+    fn OpenServiceW(manager: RawHandle, name: *const u16, access: u32) -> RawHandle;
+    fn ChangeServiceConfig2W(service: RawHandle, level: u32, info: *const c_void) -> i32;
+    fn ControlService(service: RawHandle, control: u32, status: *mut ServiceStatus) -> i32;
+    fn DeleteService(service: RawHandle) -> i32;
+    fn CloseServiceHandle(handle: RawHandle) -> i32;
+    fn StartServiceCtrlDispatcherW(table: *const TableEntry) -> i32;
+    fn RegisterServiceCtrlHandlerExW(
+        name: *const u16,
+        handler: Handler,
+        context: *mut c_void,
+    ) -> RawHandle;
+    fn SetServiceStatus(status: RawHandle, service_status: *const ServiceStatus) -> i32;
+}
+
+/// A manager or service handle, closed on drop.
+struct Handle(RawHandle);
+
+impl Handle {
+    fn new(raw: RawHandle) -> io::Result<Handle> {
+        match raw {
+            0 => Err(io::Error::last_os_error()),
+            raw => Ok(Handle(raw)),
+        }
+    }
+
+    fn manager(access: u32) -> io::Result<Handle> {
+        Handle::new(unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) })
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+fn wide(value: &str) -> Vec<u16> {
+    OsStr::new(value).encode_wide().chain([0]).collect()
+}
+
+fn check(result: i32) -> io::Result<()> {
+    match result {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// `words` quoted into one Windows command line, the way programs split it back up.
+pub fn command_line(words: &[String]) -> String {
+    let quoted: Vec<String> = words.iter().map(|word| quote(word)).collect();
+    quoted.join(" ")
+}
+
+fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains([' ', '\t', '"']) {
+        return word.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in word.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            // Backslashes before a quote are escaped, and so is the quote.
+            '"' => quoted.extend(std::iter::repeat('\\').take(backslashes * 2 + 1)),
+            _ => quoted.extend(std::iter::repeat('\\').take(backslashes)),
+        }
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.extend(std::iter::repeat('\\').take(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+pub fn install(
+    name: &str,
+    display_name: &str,
+    description: &str,
+    command_line: &str,
+    automatic: bool,
+) -> io::Result<()> {
+    let manager = Handle::manager(SC_MANAGER_CREATE_SERVICE)?;
+    let (name, display_name, command_line) = (wide(name), wide(display_name), wide(command_line));
+    let start = match automatic {
+        true => SERVICE_AUTO_START,
+        false => SERVICE_DEMAND_START,
+    };
+    let service = Handle::new(unsafe {
+        CreateServiceW(
+            manager.0,
+            name.as_ptr(),
+            display_name.as_ptr(),
+            SERVICE_CHANGE_CONFIG,
+            SERVICE_WIN32_OWN_PROCESS,
+            start,
+            SERVICE_ERROR_NORMAL,
+            command_line.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            // LocalSystem.
+            ptr::null(),
+            ptr::null(),
+        )
+    })?;
+    let mut description = wide(description);
+    let info = Description {
+        description: description.as_mut_ptr(),
+    };
+    // The service works without one; the console just shows an empty description.
+    if let Err(err) = check(unsafe {
+        ChangeServiceConfig2W(
+            service.0,
+            SERVICE_CONFIG_DESCRIPTION,
+            &info as *const Description as *const c_void,
+        )
+    }) {
+        log::warn!("Unable to set the service description: {err}");
+    }
+    Ok(())
+}
+
+/// Stops the service if it's running and removes it.
+pub fn uninstall(name: &str) -> io::Result<()> {
+    let manager = Handle::manager(SC_MANAGER_CONNECT)?;
+    let name = wide(name);
+    let service = Handle::new(unsafe {
+        OpenServiceW(
+            manager.0,
+            name.as_ptr(),
+            SERVICE_STOP | SERVICE_QUERY_STATUS | DELETE,
+        )
+    })?;
+    let mut status = ServiceStatus::default();
+    if let Err(err) = check(unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) })
+    {
+        if err.raw_os_error() != Some(ERROR_SERVICE_NOT_ACTIVE) {
+            return Err(err);
+        }
+    }
+    // Removal completes once the service has stopped and its handles are closed.
+    check(unsafe { DeleteService(service.0) })
+}
+
+static NAME: OnceLock<Vec<u16>> = OnceLock::new();
+static STATUS: OnceLock<RawHandle> = OnceLock::new();
+static STARTED: Mutex<Option<mpsc::Sender<io::Result<()>>>> = Mutex::new(None);
+// Set once the command has finished.
+static FINISHED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+/// The process running as a service. The command the service runs goes on in the caller while
+/// the manager's dispatcher runs in the background.
+pub struct Running {
+    dispatcher: JoinHandle<()>,
+}
+
+impl Running {
+    /// Connects to the service manager, which only works in a process it started, and reports
+    /// the service as running.
+    pub fn start(name: &str) -> io::Result<Running> {
+        let _ = NAME.set(wide(name));
+        let (sender, receiver) = mpsc::channel();
+        *STARTED.lock().unwrap_or_else(|err| err.into_inner()) = Some(sender);
+        let dispatcher = std::thread::spawn(|| {
+            let table = [
+                TableEntry {
+                    name: NAME.get().expect("service name set").as_ptr() as *mut u16,
+                    main: Some(service_main),
+                },
+                TableEntry {
+                    name: ptr::null_mut(),
+                    main: None,
+                },
+            ];
+            // Returns once the service has stopped.
+            if let Err(err) = check(unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }) {
+                started(Err(err));
+            }
+        });
+        match receiver.recv() {
+            Ok(result) => result.map(|()| Running { dispatcher }),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the dispatcher ended without starting the service",
+            )),
+        }
+    }
+
+    /// Reports the service stopped after its command finished.
+    pub fn stopped(self) {
+        let (finished, condvar) = &FINISHED;
+        *finished.lock().unwrap_or_else(|err| err.into_inner()) = true;
+        condvar.notify_all();
+        let _ = self.dispatcher.join();
+    }
+}
+
+fn started(result: io::Result<()>) {
+    if let Some(sender) = STARTED.lock().unwrap_or_else(|err| err.into_inner()).take() {
+        let _ = sender.send(result);
+    }
+}
+
+fn set_state(state: u32) {
+    let Some(handle) = STATUS.get() else {
+        return;
+    };
+    let status = ServiceStatus {
+        service_type: SERVICE_WIN32_OWN_PROCESS,
+        current_state: state,
+        controls_accepted: match state {
+            SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+            _ => 0,
+        },
+        win32_exit_code: NO_ERROR,
+        service_specific_exit_code: 0,
+        check_point: 0,
+        wait_hint: match state {
+            SERVICE_STOP_PENDING => STOP_WAIT.as_millis() as u32,
+            _ => 0,
+        },
+    };
+    if let Err(err) = check(unsafe { SetServiceStatus(*handle, &status) }) {
+        log::warn!("Unable to report the service state: {err}");
+    }
+}
+
+unsafe extern "system" fn service_main(_: u32, _: *mut *mut u16) {
+    let name = NAME.get().expect("service name set");
+    let handle = RegisterServiceCtrlHandlerExW(name.as_ptr(), handler, ptr::null_mut());
+    if handle == 0 {
+        started(Err(io::Error::last_os_error()));
+        return;
+    }
+    let _ = STATUS.set(handle);
+    set_state(SERVICE_RUNNING);
+    started(Ok(()));
+    // The service ends when this returns, so it waits for the command.
+    let (finished, condvar) = &FINISHED;
+    let mut done = finished.lock().unwrap_or_else(|err| err.into_inner());
+    while !*done {
+        done = condvar.wait(done).unwrap_or_else(|err| err.into_inner());
+    }
+    set_state(SERVICE_STOPPED);
+}
+
+unsafe extern "system" fn handler(control: u32, _: u32, _: *mut c_void, _: *mut c_void) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            set_state(SERVICE_STOP_PENDING);
+            if !shutdown::is_requested() {
+                shutdown::request();
+            }
+            // Not every command watches for a stop.
+            std::thread::spawn(|| {
+                std::thread::sleep(STOP_WAIT);
+                log::warn!("The command didn't stop within {STOP_WAIT:?}; ending it");
+                set_state(SERVICE_STOPPED);
+                std::process::exit(0);
+            });
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
@@ -0,0 +1,183 @@
+//! `edge agent` answering requests from a NATS server.
+
+#![cfg(all(feature = "agent", feature = "modbus"))]
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use simulators::{ModbusSimulator, NatsSimulator};
+use tokio::process::{Child, Command};
+
+fn agent(server: &NatsSimulator) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["agent", &server.url(), "--subject", "edge.agent.test"])
+        .env_clear()
+        .env("EDGE_CONFIG", "/nonexistent/config.toml")
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("edge runs")
+}
+
+/// The agent's answer to the request that went out with `reply`.
+fn reply(server: &NatsSimulator, subject: &str) -> String {
+    let messages = server.messages();
+    let message = messages
+        .iter()
+        .find(|message| message.subject == subject)
+        .expect("a reply");
+    String::from_utf8(message.payload.clone()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_allowed_commands_and_refuses_the_rest() {
+    let server = NatsSimulator::start().await.unwrap();
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(40070, &[1234]);
+    let mut agent = agent(&server);
+    assert!(
+        server
+            .wait_for_subscription("edge.agent.test", Duration::from_secs(10))
+            .await
+    );
+
+    let address = device.address();
+    server.request(
+        "edge.agent.test",
+        "_INBOX.read",
+        format!("modbus {address} --output json read-register -r 40070 -k holding").as_bytes(),
+    );
+    server.request(
+        "edge.agent.test",
+        "_INBOX.write",
+        format!("modbus {address} write-register -a 40070 -v 1").as_bytes(),
+    );
+    assert!(server.wait_for_messages(2, Duration::from_secs(10)).await);
+    agent.kill().await.unwrap();
+
+    let read = reply(&server, "_INBOX.read");
+    assert!(read.contains(r#""status":0"#), "{read}");
+    assert!(read.contains("1234"), "{read}");
+    let write = reply(&server, "_INBOX.write");
+    assert!(write.contains(r#""status":2"#), "{write}");
+    assert!(
+        write.contains("modbus write-register isn't allowed"),
+        "{write}"
+    );
+    assert_eq!(device.holding(40070), 1234);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_options_it_does_not_know_to_be_safe() {
+    let server = NatsSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("edge-agent-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let marker = directory.join("marker");
+    let mut agent = agent(&server);
+    assert!(
+        server
+            .wait_for_subscription("edge.agent.test", Duration::from_secs(10))
+            .await
+    );
+
+    let url = server.url();
+    let subscribe = format!("nats {url} subscribe -s x.y --duration 100ms");
+    let marker = marker.display();
+    let requests = [
+        (
+            "codec",
+            format!("{subscribe} --codec 'touch {marker}'"),
+            "--codec",
+        ),
+        (
+            "capture",
+            format!("{subscribe} --capture {marker}"),
+            "--capture",
+        ),
+        (
+            "password",
+            format!("nats {url} --password-file {marker} subscribe -s x.y"),
+            "--password-file",
+        ),
+        (
+            "profile",
+            format!("nats {url} --profile site subscribe -s x.y"),
+            "--profile",
+        ),
+        (
+            "config",
+            format!("{subscribe} --config {marker}"),
+            "--config",
+        ),
+    ];
+    for (name, request, _) in &requests {
+        server.request(
+            "edge.agent.test",
+            &format!("_INBOX.{name}"),
+            request.as_bytes(),
+        );
+    }
+    server.request(
+        "edge.agent.test",
+        "_INBOX.allowed",
+        format!("nats {url} --output json subscribe -s x.y --duration 100ms --timestamps")
+            .as_bytes(),
+    );
+    assert!(
+        server
+            .wait_for_messages(requests.len() + 1, Duration::from_secs(10))
+            .await
+    );
+    agent.kill().await.unwrap();
+
+    for (name, _, option) in &requests {
+        let refused = reply(&server, &format!("_INBOX.{name}"));
+        assert!(refused.contains(r#""status":2"#), "{refused}");
+        assert!(refused.contains(option), "{refused}");
+    }
+    let allowed = reply(&server, "_INBOX.allowed");
+    assert!(allowed.contains(r#""status":0"#), "{allowed}");
+    assert!(!directory.join("marker").exists());
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_writing_subscribed_messages_to_files() {
+    let server = NatsSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("edge-agent-file-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mut agent = agent(&server);
+    assert!(
+        server
+            .wait_for_subscription("edge.agent.test", Duration::from_secs(10))
+            .await
+    );
+
+    let subscribe = format!("nats {} subscribe -s x.y --duration 100ms", server.url());
+    let file = directory.join("messages");
+    server.request(
+        "edge.agent.test",
+        "_INBOX.file",
+        format!("{subscribe} --file {}", file.display()).as_bytes(),
+    );
+    server.request(
+        "edge.agent.test",
+        "_INBOX.spill",
+        format!("{subscribe} --file-spill {}", directory.display()).as_bytes(),
+    );
+    assert!(server.wait_for_messages(2, Duration::from_secs(10)).await);
+    agent.kill().await.unwrap();
+
+    let file_reply = reply(&server, "_INBOX.file");
+    assert!(file_reply.contains(r#""status":2"#), "{file_reply}");
+    assert!(file_reply.contains("--file isn't allowed"), "{file_reply}");
+    let spill_reply = reply(&server, "_INBOX.spill");
+    assert!(spill_reply.contains(r#""status":2"#), "{spill_reply}");
+    assert!(
+        spill_reply.contains("--file-spill isn't allowed"),
+        "{spill_reply}"
+    );
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,129 @@
+//! `edge api` serving register reads and writes and subject samples over HTTP.
+
+#![cfg(feature = "api")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use simulators::{ModbusSimulator, NatsSimulator};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+/// Starts `edge api` against `nats` with the token s3cret, once it listens.
+async fn start(nats: &str) -> (Child, SocketAddr) {
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let api = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["api", "--api-listen", &listen.to_string()])
+        .args(["--nats", nats])
+        .env_clear()
+        .env("EDGE_CONFIG", "/nonexistent/config.toml")
+        .env("EDGE_API_TOKEN", "s3cret")
+        .kill_on_drop(true)
+        .spawn()
+        .expect("edge runs");
+    let started = tokio::time::timeout(Duration::from_secs(10), async {
+        while TcpStream::connect(listen).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(started.is_ok(), "the API never listened");
+    (api, listen)
+}
+
+/// Sends one request and returns the status line and the body.
+async fn request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    token: &str,
+    body: &str,
+) -> (String, String) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {address}\r\nAuthorization: Bearer {token}\r\n\
+         Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_writes_and_samples_with_the_token() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[7, 8]);
+    let server = NatsSimulator::start().await.unwrap();
+    let (_api, listen) = start(&server.url()).await;
+
+    let registers = format!("/modbus/{}/holding/100", device.address());
+    let (status, body) = request(listen, "GET", &registers, "wrong", "").await;
+    assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+    assert!(body.contains("\"status\":4"), "{body}");
+
+    let (status, body) =
+        request(listen, "GET", &format!("{registers}?count=2"), "s3cret", "").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(
+        body,
+        format!(
+            "{{\"address\":\"{}\",\"unit_id\":1,\"kind\":\"holding\",\"register\":100,\
+             \"values\":[7,8]}}",
+            device.address()
+        )
+    );
+
+    let (status, _) = request(listen, "PUT", &registers, "s3cret", "{\"value\": 42}").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(device.holding(100), 42);
+
+    let sample = tokio::spawn(request(
+        listen,
+        "GET",
+        "/nats/plant.temperature?count=2&timeout=5s",
+        "s3cret",
+        "",
+    ));
+    assert!(
+        server
+            .wait_for_subscription("plant.temperature", Duration::from_secs(5))
+            .await
+    );
+    server.publish("plant.temperature", b"21.5");
+    server.publish("plant.temperature", b"warm");
+    let (status, body) = sample.await.unwrap();
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(
+        body,
+        "{\"subject\":\"plant.temperature\",\"messages\":[\
+         {\"subject\":\"plant.temperature\",\"payload\":21.5},\
+         {\"subject\":\"plant.temperature\",\"payload\":\"warm\"}]}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_bodies_too_large_to_add_up() {
+    let server = NatsSimulator::start().await.unwrap();
+    let (_api, listen) = start(&server.url()).await;
+
+    for length in [(64 * 1024).to_string(), usize::MAX.to_string()] {
+        let mut stream = TcpStream::connect(listen).await.unwrap();
+        let request = format!("PUT /nats/a HTTP/1.1\r\nContent-Length: {length}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        let read =
+            tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response));
+        read.await.expect("an answer without the body").unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 413 Payload Too Large"),
+            "{response}"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! `edge bridge run` marking the quality of the points it polls, and keeping the time of the
+//! ones it receives.
+
+#![cfg(feature = "bridge")]
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use simulators::{ModbusSimulator, NatsSimulator};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn marks_points_out_of_range_and_stale() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[500]);
+    device.set_holding(101, &[20]);
+    let directory = std::env::temp_dir().join(format!("edge-bridge-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mapping = directory.join("bridge.yaml");
+    std::fs::write(
+        &mapping,
+        format!(
+            "sources:\n  plc:\n    type: modbus\n    address: {}\n    interval: 50ms\n    \
+             stale_after: 300ms\n    points:\n      \
+             - name: pressure\n        register: 100\n        max: 100\n      \
+             - name: temperature\n        register: 101\n\
+             sinks:\n  console:\n    type: stdout\n\
+             routes:\n  - source: plc\n    point: temperature\n    sink: console\n  \
+             - source: plc\n    point: pressure\n    sink: console\n    \
+             transform: {{deadband: 1}}\n",
+            device.address()
+        ),
+    )
+    .unwrap();
+
+    let mut bridge = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["bridge", "run", mapping.to_str().unwrap()])
+        .env_clear()
+        .env("EDGE_CONFIG", directory.join("config.toml"))
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("edge runs");
+    let mut lines = BufReader::new(bridge.stdout.take().unwrap()).lines();
+    let mut seen = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Ok(Some(line)) = lines.next_line().await {
+            let stale = line == "plc.temperature = 20 (stale)";
+            seen.push(line);
+            if stale {
+                break;
+            }
+        }
+    })
+    .await;
+    bridge.kill().await.unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(read.is_ok(), "{seen:?}");
+    assert_eq!(seen[0], "plc.pressure = 500 (out-of-range)", "{seen:?}");
+    assert_eq!(seen[1], "plc.temperature = 20", "{seen:?}");
+    // The deadband holds back the unchanged pressure, and the temperature was polled good
+    // before going stale.
+    assert!(
+        !seen[1..]
+            .iter()
+            .any(|line| line.starts_with("plc.pressure")),
+        "{seen:?}"
+    );
+    assert!(seen.len() >= 3, "{seen:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_source_timestamps_apart_from_receive_time() {
+    let server = NatsSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("edge-bridge-time-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mapping = directory.join("bridge.yaml");
+    std::fs::write(
+        &mapping,
+        format!(
+            "sources:\n  meters:\n    type: nats\n    address: {}\n    subjects: [meter.>]\n\
+             sinks:\n  console:\n    type: stdout\n\
+             routes:\n  - source: meters\n    sink: console\n",
+            server.url()
+        ),
+    )
+    .unwrap();
+
+    let mut bridge = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args([
+            "bridge",
+            "--output",
+            "json",
+            "run",
+            mapping.to_str().unwrap(),
+        ])
+        .env_clear()
+        .env("EDGE_CONFIG", directory.join("config.toml"))
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("edge runs");
+    assert!(
+        server
+            .wait_for_subscription("meter.>", Duration::from_secs(10))
+            .await
+    );
+    server.publish(
+        "meter.energy",
+        br#"{"source":"meter","topic":"energy","payload":1520,"unit":"kWh","timestamp":"2026-10-14T08:00:00.000Z","quality":"good"}"#,
+    );
+    let mut lines = BufReader::new(bridge.stdout.take().unwrap()).lines();
+    let line = tokio::time::timeout(Duration::from_secs(10), lines.next_line())
+        .await
+        .expect("a point")
+        .unwrap()
+        .unwrap();
+    bridge.kill().await.unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(
+        line.contains(r#""point":"meter.energy","value":1520,"unit":"kWh""#),
+        "{line}"
+    );
+    assert!(
+        line.contains(r#""source_time":"2026-10-14T08:00:00.000Z""#),
+        "{line}"
+    );
+    assert!(
+        !line.contains(r#""time":"2026-10-14T08:00:00.000Z""#),
+        "{line}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn alerts_once_and_again_when_resolved() {
+    let server = NatsSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("edge-bridge-alert-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mapping = directory.join("bridge.yaml");
+    std::fs::write(
+        &mapping,
+        format!(
+            "sources:\n  boiler:\n    type: nats\n    address: {0}\n    subjects: [boiler.>]\n\
+             sinks:\n  console:\n    type: stdout\n  site:\n    type: nats\n    address: {0}\n\
+             routes:\n  - source: boiler\n    sink: console\n\
+             alerts:\n  hot:\n    source: boiler\n    above: 90\n    notify:\n      \
+             - sink: site\n        subject: alerts.{{alert}}\n",
+            server.url()
+        ),
+    )
+    .unwrap();
+
+    let mut bridge = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["bridge", "run", mapping.to_str().unwrap()])
+        .env_clear()
+        .env("EDGE_CONFIG", directory.join("config.toml"))
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("edge runs");
+    assert!(
+        server
+            .wait_for_subscription("boiler.>", Duration::from_secs(10))
+            .await
+    );
+    for value in [b"95", b"97", b"80"] {
+        server.publish("boiler.temperature", value);
+    }
+    let sent = server.wait_for_messages(2, Duration::from_secs(10)).await;
+    bridge.kill().await.unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let messages = server.messages();
+    assert!(sent, "{} alerts", messages.len());
+    assert_eq!(messages.len(), 2);
+    assert!(messages
+        .iter()
+        .all(|message| message.subject == "alerts.hot"));
+    let payloads: Vec<String> = messages
+        .iter()
+        .map(|message| String::from_utf8_lossy(&message.payload).into_owned())
+        .collect();
+    assert!(
+        payloads[0].contains(r#""state":"firing","source":"boiler","point":"boiler.temperature","reason":"above 90","value":95"#),
+        "{payloads:?}"
+    );
+    assert!(
+        payloads[1].contains(r#""state":"resolved""#) && payloads[1].contains(r#""value":80"#),
+        "{payloads:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_points_to_websocket_clients() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let directory = std::env::temp_dir().join(format!("edge-bridge-ws-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mapping = directory.join("bridge.yaml");
+    std::fs::write(
+        &mapping,
+        format!(
+            "sources:\n  plc:\n    type: modbus\n    address: {}\n    interval: 50ms\n    \
+             points:\n      - name: temperature\n        register: 100\n        scale: 0.1\n\
+             sinks:\n  dashboard:\n    type: websocket\n    listen: {listen}\n\
+             routes:\n  - source: plc\n    sink: dashboard\n",
+            device.address()
+        ),
+    )
+    .unwrap();
+
+    let mut bridge = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["bridge", "run", mapping.to_str().unwrap()])
+        .env_clear()
+        .env("EDGE_CONFIG", directory.join("config.toml"))
+        .kill_on_drop(true)
+        .spawn()
+        .expect("edge runs");
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut stream = loop {
+            match TcpStream::connect(listen).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        // The key and accept value from RFC 6455.
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        let length = match head[1] & 0x7f {
+            126 => stream.read_u16().await.unwrap() as usize,
+            length => length as usize,
+        };
+        let mut event = vec![0u8; length];
+        stream.read_exact(&mut event).await.unwrap();
+        (
+            String::from_utf8(response).unwrap(),
+            head[0],
+            String::from_utf8(event).unwrap(),
+        )
+    })
+    .await;
+    bridge.kill().await.unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let (response, opcode, event) = received.expect("an event");
+    assert!(
+        response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{response}"
+    );
+    assert!(
+        response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+        "{response}"
+    );
+    // A final text frame.
+    assert_eq!(opcode, 0x81);
+    assert!(
+        event.starts_with(
+            r#"{"source":"plc","point":"temperature","address":"holding 100","value":21.5,"#
+        ),
+        "{event}"
+    );
+}
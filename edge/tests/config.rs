@@ -0,0 +1,91 @@
+//! `edge config check` on a config file and a bridge mapping with mistakes in them.
+
+#![cfg(feature = "bridge")]
+
+use tokio::process::Command;
+
+#[tokio::test]
+async fn points_at_the_lines_with_mistakes() {
+    let directory = std::env::temp_dir().join(format!("edge-config-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let config = directory.join("config.toml");
+    let mapping = directory.join("bridge.yaml");
+    std::fs::write(
+        &config,
+        "[profiles.site]\naddress = \"10.0.0.5:502\"\nretries = \"three\"\nadress = \"x\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &mapping,
+        "sources:\n  plc:\n    type: modbus\n    address: 10.0.0.5:502\n    points:\n      \
+         - name: temperature\n        register: 100\n        type: f23\n\
+         sinks:\n  console:\n    type: stdout\n\
+         routes:\n  - source: plc\n    sink: console\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["config", "check", mapping.to_str().unwrap()])
+        .env_clear()
+        .env("EDGE_CONFIG", &config)
+        .output()
+        .await
+        .expect("edge runs");
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            format!(
+                "{}:3: profiles.site.retries: must be an integer from 0 to 4294967295",
+                config.display()
+            ),
+            format!("{}:4: profiles.site.adress: unknown key", config.display()),
+            format!(
+                "{}:8: sources.plc.points[0].type: unknown type f23, expected u16, i16, u32, i32, u64, i64, f32, f64, bcd16, bcd32 or bool",
+                mapping.display()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn refuses_routes_to_units_of_another_quantity() {
+    let directory = std::env::temp_dir().join(format!("edge-units-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mapping = directory.join("bridge.yaml");
+    std::fs::write(
+        &mapping,
+        "sources:\n  plc:\n    type: modbus\n    address: 10.0.0.5:502\n    points:\n      \
+         - name: temperature\n        register: 100\n        type: f32\n        unit: °C\n\
+         sinks:\n  console:\n    type: stdout\n\
+         routes:\n  - source: plc\n    sink: console\n    unit: °F\n\
+         \x20 - source: plc\n    sink: console\n    unit: kWh\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["config", "check", mapping.to_str().unwrap()])
+        .env_clear()
+        .env("EDGE_CONFIG", directory.join("config.toml"))
+        .output()
+        .await
+        .expect("edge runs");
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().next(),
+        Some(
+            format!(
+                "{}:19: routes[1].unit: cannot convert °C to kWh",
+                mapping.display()
+            )
+            .as_str()
+        )
+    );
+}
@@ -0,0 +1,59 @@
+//! `edge decode` on stored payloads.
+
+use tokio::process::Command;
+
+#[tokio::test]
+async fn decodes_hex_dumps_and_ndjson_captures() {
+    let directory = std::env::temp_dir().join(format!("edge-decode-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let dump = directory.join("response.hex");
+    let capture = directory.join("capture.ndjson");
+    // Two f32 values of two registers each, as `hexdump -C` prints them.
+    std::fs::write(
+        &dump,
+        "00000000  43 66 cc cd 3f 00 00 00                           |Cf..?...|\n00000008\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &capture,
+        "{\"subject\":\"plant.meter\",\"payload\":\"{\\\"power\\\":1.5}\"}\n\n\
+         {\"subject\":\"plant.state\",\"payload\":\"running\"}\n",
+    )
+    .unwrap();
+
+    let decode = |args: Vec<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_edge"));
+        command.arg("decode").args(args).env_clear();
+        command
+    };
+    let registers = decode(vec![dump.to_str().unwrap(), "--type", "f32"])
+        .output()
+        .await
+        .expect("edge runs");
+    let messages = decode(vec![capture.to_str().unwrap(), "--output", "json"])
+        .output()
+        .await
+        .expect("edge runs");
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(registers.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&registers.stdout),
+        format!("{} 230.8000030517578 0.5\n", dump.display())
+    );
+    assert!(messages.status.success());
+    let stdout = String::from_utf8_lossy(&messages.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let file = capture.display();
+    assert_eq!(
+        lines,
+        [
+            format!(
+                r#"{{"file":"{file}","line":1,"subject":"plant.meter","size":13,"value":{{"power":1.5}}}}"#
+            ),
+            format!(
+                r#"{{"file":"{file}","line":3,"subject":"plant.state","size":7,"value":"running"}}"#
+            ),
+        ]
+    );
+}
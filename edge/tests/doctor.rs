@@ -0,0 +1,44 @@
+//! `edge doctor` against the targets of a config file, one listening and one not.
+
+use tokio::net::TcpListener;
+use tokio::process::Command;
+
+#[tokio::test]
+async fn reports_which_targets_answer() {
+    let listening = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (up, down) = (
+        listening.local_addr().unwrap(),
+        closed.local_addr().unwrap(),
+    );
+    drop(closed);
+    let directory = std::env::temp_dir().join(format!("edge-doctor-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let config = directory.join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[profiles.plc]\naddress = \"{up}\"\n\n\
+             [profiles.hub]\naddress = \"nats://edge:secret@{down}\"\n"
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .arg("doctor")
+        .env_clear()
+        .env("EDGE_CONFIG", &config)
+        .current_dir(&directory)
+        .output()
+        .await
+        .expect("edge runs");
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("ok       route   plc {up}: connected")));
+    assert!(stdout.contains(&format!(
+        "problem  route   hub {down}: the host answered, but nothing listens on the port"
+    )));
+    assert!(!stdout.contains("secret"));
+}
@@ -0,0 +1,33 @@
+//! `edge scan` against in-process devices.
+
+#![cfg(feature = "scan")]
+
+use simulators::{ModbusSimulator, NatsSimulator};
+use tokio::process::Command;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn finds_a_modbus_device_and_a_nats_server() {
+    let device = ModbusSimulator::start().await.unwrap();
+    let broker = NatsSimulator::start().await.unwrap();
+    let ports = format!(
+        "{}=modbus,{}=nats",
+        device.address().port(),
+        broker.address().port()
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["scan", "127.0.0.1", "--ports", &ports, "--output", "json"])
+        .env_clear()
+        .output()
+        .await
+        .expect("edge runs");
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!(
+        "{{\"address\":\"127.0.0.1\",\"port\":{},\"protocol\":\"modbus\"",
+        device.address().port()
+    )));
+    assert!(stdout.contains("\"identity\":\"NATS server simulator 2.9.0\""));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Found 2 endpoints on 1 of 1 host"));
+}
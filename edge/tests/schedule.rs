@@ -0,0 +1,69 @@
+//! `edge schedule --once` running the jobs in a config file.
+
+#![cfg(unix)]
+
+use tokio::process::Command;
+
+#[tokio::test]
+async fn once_runs_every_job_and_exits_with_the_first_failure() {
+    let directory = std::env::temp_dir().join(format!("edge-schedule-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let config = directory.join("config.toml");
+    std::fs::write(
+        &config,
+        "[jobs.check]\ncron = \"@daily\"\nrun = \"config check /nonexistent/bridge.yaml\"\n\n\
+         [jobs.touch]\nevery = \"1h\"\nshell = \"echo ran > ran\"\n\n\
+         [jobs.slow]\nevery = \"1h\"\nshell = \"exec sleep 30\"\ntimeout = \"200ms\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["--log-level", "warn", "schedule", "--once"])
+        .env_clear()
+        .env("EDGE_CONFIG", &config)
+        .current_dir(&directory)
+        .output()
+        .await
+        .expect("edge runs");
+    let ran = std::fs::read_to_string(directory.join("ran"));
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    // Jobs run by name; `check` fails first with a usage error, and the rest still run.
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(ran.unwrap(), "ran\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Job check failed: exit status 2, invalid arguments or configuration"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("Job slow failed: killed after 200ms"),
+        "{stderr}"
+    );
+}
+
+#[tokio::test]
+async fn refuses_jobs_that_are_not_edge_commands() {
+    let directory = std::env::temp_dir().join(format!("edge-jobs-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let config = directory.join("config.toml");
+    std::fs::write(
+        &config,
+        "[jobs.poll]\nevery = \"5m\"\nrun = \"modbus --bogus\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(["schedule", "--list"])
+        .env_clear()
+        .env("EDGE_CONFIG", &config)
+        .output()
+        .await
+        .expect("edge runs");
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid job poll: "), "{stderr}");
+}
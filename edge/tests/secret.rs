@@ -0,0 +1,148 @@
+//! `edge secret` keys and values, used by a profile and an encrypted capture.
+
+#![cfg(all(feature = "modbus", feature = "nats"))]
+
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+use simulators::{ModbusSimulator, NatsSimulator};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+async fn edge(config: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(args)
+        .env_clear()
+        .env("EDGE_CONFIG", config)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("edge runs");
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin.as_bytes()).await.unwrap();
+    drop(input);
+    child.wait_with_output().await.unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decrypts_profile_passwords_and_captures() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_user("edge", "secret");
+    let device = ModbusSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("edge-secret-{}", std::process::id()));
+    let config = directory.join("config.toml");
+    let capture = directory.join("capture.pcapng.zst.age");
+
+    let recipient = stdout(&edge(&config, &["secret", "keygen"], "").await);
+    let password = stdout(&edge(&config, &["secret", "encrypt"], "secret\n").await);
+    std::fs::write(
+        &config,
+        format!(
+            "[encryption]\nrecipients = [\"{recipient}\"]\n\n\
+             [profiles.site]\nusername = \"edge\"\npassword = \"{password}\"\n"
+        ),
+    )
+    .unwrap();
+    let url = server.url();
+    let published = edge(
+        &config,
+        &[
+            "nats",
+            &url,
+            "--profile",
+            "site",
+            "publish",
+            "-s",
+            "a",
+            "-m",
+            "b",
+        ],
+        "",
+    )
+    .await;
+    let address = device.address().to_string();
+    let capture_path = capture.to_str().unwrap();
+    let read = edge(
+        &config,
+        &[
+            "modbus",
+            &address,
+            "--capture",
+            capture_path,
+            "read-register",
+            "-r",
+            "1",
+            "-k",
+            "holding",
+        ],
+        "",
+    )
+    .await;
+    let port = device.address().port().to_string();
+    let analysis = edge(
+        &config,
+        &[
+            "modbus",
+            "--output",
+            "json",
+            "analyze-pcap",
+            capture_path,
+            "--port",
+            &port,
+        ],
+        "",
+    )
+    .await;
+    let header = std::fs::read(&capture).unwrap()[..22].to_vec();
+    // A read that fails still leaves a capture that decrypts.
+    device.fail(2, 2);
+    let failed = directory.join("failed.pcapng.age");
+    let failed_path = failed.to_str().unwrap();
+    let failed_read = edge(
+        &config,
+        &[
+            "modbus",
+            &address,
+            "--capture",
+            failed_path,
+            "read-register",
+            "-r",
+            "2",
+            "-k",
+            "holding",
+        ],
+        "",
+    )
+    .await;
+    let failed_analysis = edge(
+        &config,
+        &[
+            "modbus",
+            "--output",
+            "json",
+            "analyze-pcap",
+            failed_path,
+            "--port",
+            &port,
+        ],
+        "",
+    )
+    .await;
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(recipient.starts_with("age1"));
+    assert!(password.starts_with("age:"));
+    assert_eq!(published.status.code(), Some(0));
+    assert_eq!(server.messages().len(), 1);
+    assert_eq!(read.status.code(), Some(0));
+    assert_eq!(header, b"age-encryption.org/v1\n");
+    assert!(stdout(&analysis).contains(r#""requests":1,"responses":1"#));
+    assert_eq!(failed_read.status.code(), Some(5));
+    assert_eq!(failed_analysis.status.code(), Some(0));
+    assert!(stdout(&failed_analysis).contains(r#""requests":1,"responses":1"#));
+}
@@ -0,0 +1,60 @@
+//! `edge service` off Windows: the command is still checked, then refused with a pointer to
+//! systemd.
+
+#![cfg(feature = "bridge")]
+
+use std::process::Command;
+
+fn edge(args: &[&str]) -> (i32, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_edge"))
+        .args(args)
+        .env_clear()
+        .output()
+        .expect("edge runs");
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    (output.status.code().unwrap_or(-1), stderr)
+}
+
+#[test]
+fn checks_the_command_and_points_at_systemd() {
+    let (status, stderr) = edge(&[
+        "service",
+        "install",
+        "--name",
+        "edge-bridge",
+        "--",
+        "bridge",
+    ]);
+    assert_eq!(status, 2, "{stderr}");
+    assert!(stderr.contains("Invalid service command"), "{stderr}");
+
+    let (status, stderr) = edge(&[
+        "service",
+        "install",
+        "--name",
+        "edge-bridge",
+        "--",
+        "service",
+        "uninstall",
+        "--name",
+        "x",
+    ]);
+    assert_eq!(status, 2, "{stderr}");
+    assert!(stderr.contains("can't run `edge service`"), "{stderr}");
+
+    let (status, stderr) = edge(&[
+        "service",
+        "install",
+        "--name",
+        "edge-bridge",
+        "--",
+        "bridge",
+        "run",
+        "plant.yaml",
+    ]);
+    assert_eq!(status, 2, "{stderr}");
+    assert!(
+        stderr.contains("run `edge bridge run plant.yaml` from a systemd unit"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,714 @@
+//! [age](https://age-encryption.org/v1) encryption to X25519 recipients, for captures and
+//! profile secrets on gateways anyone can walk up to. Files are interchangeable with the `age`
+//! tools, so a capture encrypted on site can be decrypted on a laptop with either:
+//!
+//! ```text
+//! $ edge secret keygen                        # on the laptop: prints age1...
+//! $ modbus 10.0.0.5 --capture plant.pcapng.age read-register -r 100 -k holding -w
+//! $ age -d -i ~/.config/edge_tools/identity.txt plant.pcapng.age > plant.pcapng
+//! ```
+//!
+//! Only X25519 stanzas are understood; passphrase (scrypt) files have to go through `age`.
+//!
+//! age marks the last chunk of a file so cutting one short can't pass for a shorter file.
+//! That cuts both ways: a file whose [`Writer`] never finished, e.g. a `.age` capture on a
+//! gateway that lost power, has no last chunk and doesn't decrypt at all, neither with these
+//! tools nor with `age`. Nothing of it can be recovered, so anything that has to survive a
+//! cut should go to a plain file.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD_NO_PAD as BASE64;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hkdf, hmac};
+
+const VERSION: &str = "age-encryption.org/v1";
+const X25519_INFO: &[u8] = b"age-encryption.org/v1/X25519";
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+// Plaintext per payload chunk; each is sealed with its own nonce and tag.
+const CHUNK: usize = 64 * 1024;
+const TAG: usize = 16;
+// Stanza bodies are base64 wrapped at this many columns.
+const COLUMNS: usize = 64;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unable to decrypt: {}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+fn invalid<T>(message: &str) -> Result<T, Error> {
+    Err(Error(message.to_string()))
+}
+
+/// Whether the file should be encrypted: its name ends in `.age`.
+pub fn is_age(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "age")
+}
+
+/// Whether `data` starts with an age header.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(VERSION.as_bytes()) && data.get(VERSION.len()) == Some(&b'\n')
+}
+
+/// A public key files are encrypted to, written `age1...`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recipient([u8; 32]);
+
+impl Recipient {
+    pub fn parse(text: &str) -> Result<Recipient, String> {
+        match bech32::decode(text.trim()) {
+            Some((hrp, key)) if hrp == RECIPIENT_HRP && key.len() == 32 => {
+                Ok(Recipient(key.try_into().expect("32 bytes")))
+            }
+            _ => Err(format!("{text} is not an age1... recipient")),
+        }
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", bech32::encode(RECIPIENT_HRP, &self.0))
+    }
+}
+
+/// A private key, written `AGE-SECRET-KEY-1...`.
+pub struct Identity([u8; 32]);
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity(random())
+    }
+
+    pub fn parse(text: &str) -> Result<Identity, String> {
+        match bech32::decode(text.trim()) {
+            Some((hrp, key)) if hrp == IDENTITY_HRP && key.len() == 32 => {
+                Ok(Identity(key.try_into().expect("32 bytes")))
+            }
+            _ => Err("not an AGE-SECRET-KEY-1... identity".to_string()),
+        }
+    }
+
+    /// Every identity in an identity file as `age-keygen` writes them, skipping comments.
+    pub fn parse_file(text: &str) -> Result<Vec<Identity>, String> {
+        let identities = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Identity::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        match identities.is_empty() {
+            true => Err("no identities".to_string()),
+            false => Ok(identities),
+        }
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(x25519::base(&self.0))
+    }
+
+    /// The identity as written in an identity file.
+    pub fn to_secret_string(&self) -> String {
+        bech32::encode(IDENTITY_HRP, &self.0).to_uppercase()
+    }
+
+    /// The file key from an X25519 stanza, if it was wrapped for this identity.
+    fn unwrap(&self, share: &[u8; 32], body: &[u8]) -> Option<[u8; 16]> {
+        let shared = x25519::scalarmult(&self.0, share);
+        if shared == [0; 32] {
+            return None;
+        }
+        let recipient = self.recipient();
+        let mut salt = share.to_vec();
+        salt.extend_from_slice(&recipient.0);
+        let key = derive::<32>(&shared, &salt, X25519_INFO);
+        let mut body = body.to_vec();
+        let opened = seal_key(&key).open_in_place(nonce(0, false), Aad::empty(), &mut body);
+        opened.ok()?.try_into().ok()
+    }
+}
+
+/// Encrypts `data` to every recipient in one go.
+pub fn encrypt(data: &[u8], recipients: &[Recipient]) -> io::Result<Vec<u8>> {
+    let mut writer = Writer::new(Vec::new(), recipients)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+    Ok(writer.inner.take().expect("not finished twice"))
+}
+
+/// Decrypts a whole file with whichever of `identities` it was encrypted to.
+pub fn decrypt(data: &[u8], identities: &[Identity]) -> Result<Vec<u8>, Error> {
+    if !is_encrypted(data) {
+        return invalid("not an age file");
+    }
+    let mut lines = Lines { data, offset: 0 };
+    lines.next()?;
+    let mut file_key = None;
+    let mac_start = loop {
+        let start = lines.offset;
+        let line = lines.next()?;
+        if line.starts_with("---") {
+            break start;
+        }
+        let arguments: Vec<&str> = match line.strip_prefix("-> ") {
+            Some(stanza) => stanza.split(' ').collect(),
+            None => return invalid("malformed header"),
+        };
+        let mut body = String::new();
+        loop {
+            let line = lines.next()?;
+            body.push_str(line);
+            if line.len() < COLUMNS {
+                break;
+            }
+        }
+        let body = BASE64
+            .decode(&body)
+            .or_else(|_| invalid("malformed stanza"))?;
+        if let ["X25519", share] = arguments[..] {
+            let share: [u8; 32] = match BASE64.decode(share).map(<[u8; 32]>::try_from) {
+                Ok(Ok(share)) => share,
+                _ => return invalid("malformed X25519 stanza"),
+            };
+            if file_key.is_none() {
+                file_key = identities
+                    .iter()
+                    .find_map(|identity| identity.unwrap(&share, &body));
+            }
+        }
+    };
+    let file_key = match file_key {
+        Some(file_key) => file_key,
+        None => return invalid("none of the identities can open it"),
+    };
+    let mac_line = &data[mac_start..lines.offset - 1];
+    let mac = match std::str::from_utf8(&mac_line[3..]).map(|mac| BASE64.decode(mac.trim())) {
+        Ok(Ok(mac)) => mac,
+        _ => return invalid("malformed header MAC"),
+    };
+    let header_key = hmac::Key::new(hmac::HMAC_SHA256, &derive::<32>(&file_key, &[], b"header"));
+    if hmac::verify(&header_key, &data[..mac_start + 3], &mac).is_err() {
+        return invalid("the header was modified");
+    }
+
+    let payload = &data[lines.offset..];
+    if payload.len() < 16 + TAG {
+        return invalid("truncated payload");
+    }
+    let key = seal_key(&derive::<32>(&file_key, &payload[..16], b"payload"));
+    let chunks: Vec<&[u8]> = payload[16..].chunks(CHUNK + TAG).collect();
+    let mut out = Vec::with_capacity(payload.len());
+    for (counter, chunk) in chunks.iter().enumerate() {
+        let last = counter + 1 == chunks.len();
+        let mut chunk = chunk.to_vec();
+        match key.open_in_place(nonce(counter as u64, last), Aad::empty(), &mut chunk) {
+            Ok(plain) if !plain.is_empty() || counter == 0 => out.extend_from_slice(plain),
+            _ => return invalid("the payload was modified or truncated"),
+        }
+    }
+    Ok(out)
+}
+
+/// Encrypts what's written in 64 KiB chunks. The last chunk is marked as such, so the file is
+/// only complete once [`Writer::finish`] has run (or the writer is dropped); flushing writes
+/// out every full chunk, but a file cut off before the end is unreadable as a whole.
+pub struct Writer<W: Write> {
+    inner: Option<W>,
+    key: LessSafeKey,
+    counter: u64,
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the header for `recipients`, who can each decrypt the file on their own.
+    pub fn new(mut inner: W, recipients: &[Recipient]) -> io::Result<Writer<W>> {
+        if recipients.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no recipients to encrypt to",
+            ));
+        }
+        let file_key: [u8; 16] = random();
+        let mut header = format!("{VERSION}\n");
+        for recipient in recipients {
+            let ephemeral: [u8; 32] = random();
+            let share = x25519::base(&ephemeral);
+            let shared = x25519::scalarmult(&ephemeral, &recipient.0);
+            let mut salt = share.to_vec();
+            salt.extend_from_slice(&recipient.0);
+            let key = derive::<32>(&shared, &salt, X25519_INFO);
+            let mut body = file_key.to_vec();
+            seal_key(&key)
+                .seal_in_place_append_tag(nonce(0, false), Aad::empty(), &mut body)
+                .map_err(|_| io::Error::other("unable to wrap the file key"))?;
+            header.push_str(&format!("-> X25519 {}\n", BASE64.encode(share)));
+            header.push_str(&wrap(&BASE64.encode(body)));
+        }
+        header.push_str("---");
+        let header_key =
+            hmac::Key::new(hmac::HMAC_SHA256, &derive::<32>(&file_key, &[], b"header"));
+        let mac = hmac::sign(&header_key, header.as_bytes());
+        header.push_str(&format!(" {}\n", BASE64.encode(mac.as_ref())));
+
+        let payload_nonce: [u8; 16] = random();
+        inner.write_all(header.as_bytes())?;
+        inner.write_all(&payload_nonce)?;
+        Ok(Writer {
+            inner: Some(inner),
+            key: seal_key(&derive::<32>(&file_key, &payload_nonce, b"payload")),
+            counter: 0,
+            pending: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// Writes the last chunk. Nothing can be written after.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished || self.inner.is_none() {
+            return Ok(());
+        }
+        let last = std::mem::take(&mut self.pending);
+        self.write_chunk(last, true)?;
+        self.finished = true;
+        self.flush()
+    }
+
+    fn write_chunk(&mut self, mut chunk: Vec<u8>, last: bool) -> io::Result<()> {
+        let inner = match self.inner.as_mut() {
+            Some(inner) if !self.finished => inner,
+            _ => return Err(io::Error::other("the encrypted file is already finished")),
+        };
+        self.key
+            .seal_in_place_append_tag(nonce(self.counter, last), Aad::empty(), &mut chunk)
+            .map_err(|_| io::Error::other("unable to encrypt"))?;
+        inner.write_all(&chunk)?;
+        self.counter += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        // Only a chunk with more after it is known not to be the last.
+        while self.pending.len() > CHUNK {
+            let rest = self.pending.split_off(CHUNK);
+            let chunk = std::mem::replace(&mut self.pending, rest);
+            self.write_chunk(chunk, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for Writer<W> {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            log::warn!("Unable to write the end of an encrypted file: {err}");
+        }
+    }
+}
+
+/// Header lines, each without its newline.
+struct Lines<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn next(&mut self) -> Result<&'a str, Error> {
+        let rest = &self.data[self.offset..];
+        let end = match rest.iter().position(|&byte| byte == b'\n') {
+            Some(end) => end,
+            None => return invalid("truncated header"),
+        };
+        self.offset += end + 1;
+        std::str::from_utf8(&rest[..end]).or_else(|_| invalid("malformed header"))
+    }
+}
+
+fn wrap(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.as_bytes().chunks(COLUMNS) {
+        out.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        out.push('\n');
+    }
+    // A body that fills its last line ends with an empty one.
+    if text.len().is_multiple_of(COLUMNS) {
+        out.push('\n');
+    }
+    out
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system random number generator works");
+    bytes
+}
+
+fn nonce(counter: u64, last: bool) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn seal_key(key: &[u8; 32]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("32 byte key"))
+}
+
+struct Length(usize);
+
+impl hkdf::KeyType for Length {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// HKDF-SHA256.
+fn derive<const N: usize>(secret: &[u8], salt: &[u8], info: &[u8]) -> [u8; N] {
+    let mut out = [0u8; N];
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(secret)
+        .expand(&[info], Length(N))
+        .and_then(|okm| okm.fill(&mut out))
+        .expect("a short HKDF output");
+    out
+}
+
+/// The curve25519 Diffie-Hellman function (RFC 7748), which ring only offers for ephemeral
+/// keys, on curve25519-dalek's Montgomery ladder.
+mod x25519 {
+    use curve25519_dalek::constants::X25519_BASEPOINT;
+    use curve25519_dalek::montgomery::MontgomeryPoint;
+    use curve25519_dalek::scalar::Scalar;
+
+    /// The scalar with RFC 7748's clamping; `from_bits` keeps it unreduced, as X25519 needs.
+    fn clamp(scalar: &[u8; 32]) -> Scalar {
+        let mut k = *scalar;
+        k[0] &= 248;
+        k[31] &= 127;
+        k[31] |= 64;
+        Scalar::from_bits(k)
+    }
+
+    pub fn scalarmult(scalar: &[u8; 32], point: &[u8; 32]) -> [u8; 32] {
+        (MontgomeryPoint(*point) * clamp(scalar)).to_bytes()
+    }
+
+    /// The public key for a private one.
+    pub fn base(scalar: &[u8; 32]) -> [u8; 32] {
+        (X25519_BASEPOINT * clamp(scalar)).to_bytes()
+    }
+}
+
+/// Bech32 (BIP 173), which age writes its keys in, without the 90 character limit.
+mod bech32 {
+    const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+    fn polymod(values: &[u8]) -> u32 {
+        const GENERATOR: [u32; 5] = [
+            0x3b6a_57b2,
+            0x2650_8e6d,
+            0x1ea1_19fa,
+            0x3d42_33dd,
+            0x2a14_62b3,
+        ];
+        let mut check = 1u32;
+        for &value in values {
+            let top = check >> 25;
+            check = ((check & 0x1ff_ffff) << 5) ^ u32::from(value);
+            for (i, generator) in GENERATOR.iter().enumerate() {
+                if (top >> i) & 1 == 1 {
+                    check ^= generator;
+                }
+            }
+        }
+        check
+    }
+
+    fn expand(hrp: &str) -> Vec<u8> {
+        let mut values: Vec<u8> = hrp.bytes().map(|byte| byte >> 5).collect();
+        values.push(0);
+        values.extend(hrp.bytes().map(|byte| byte & 31));
+        values
+    }
+
+    /// Regroups bits, e.g. bytes into 5 bit values; None if `pad` is off and bits are left.
+    fn regroup(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+        let mut acc = 0u32;
+        let mut bits = 0;
+        let mut out = Vec::new();
+        for &value in data {
+            acc = (acc << from) | u32::from(value);
+            bits += from;
+            while bits >= to {
+                bits -= to;
+                out.push(((acc >> bits) & ((1 << to) - 1)) as u8);
+            }
+        }
+        if pad && bits > 0 {
+            out.push(((acc << (to - bits)) & ((1 << to) - 1)) as u8);
+        } else if !pad && (bits >= from || (acc & ((1 << bits) - 1)) != 0) {
+            return None;
+        }
+        Some(out)
+    }
+
+    pub fn encode(hrp: &str, data: &[u8]) -> String {
+        let data = regroup(data, 8, 5, true).expect("padded");
+        let mut values = expand(hrp);
+        values.extend_from_slice(&data);
+        values.extend_from_slice(&[0; 6]);
+        let check = polymod(&values) ^ 1;
+        let mut out = format!("{hrp}1");
+        for value in data {
+            out.push(char::from(CHARSET[usize::from(value)]));
+        }
+        for i in 0..6 {
+            out.push(char::from(
+                CHARSET[((check >> (5 * (5 - i))) & 31) as usize],
+            ));
+        }
+        out
+    }
+
+    /// The human readable part, lowercased, and the data.
+    pub fn decode(text: &str) -> Option<(String, Vec<u8>)> {
+        if text.to_lowercase() != text && text.to_uppercase() != text {
+            return None;
+        }
+        let text = text.to_lowercase();
+        let (hrp, data) = text.rsplit_once('1')?;
+        if hrp.is_empty() || data.len() < 6 {
+            return None;
+        }
+        let values = data
+            .bytes()
+            .map(|byte| CHARSET.iter().position(|&c| c == byte).map(|at| at as u8))
+            .collect::<Option<Vec<u8>>>()?;
+        let mut check = expand(hrp);
+        check.extend_from_slice(&values);
+        if polymod(&check) != 1 {
+            return None;
+        }
+        let data = regroup(&values[..values.len() - 6], 5, 8, false)?;
+        Some((hrp.to_string(), data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes<const N: usize>(text: &str) -> [u8; N] {
+        let bytes: Vec<u8> = (0..text.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&text[at..at + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    // RFC 7748 section 5.2.
+    #[test]
+    fn scalar_multiplication_matches_rfc_7748() {
+        let vectors = [
+            (
+                "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+                "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+                "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+            ),
+            (
+                "4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d",
+                "e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493",
+                "95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957",
+            ),
+        ];
+        for (scalar, point, product) in vectors {
+            assert_eq!(
+                x25519::scalarmult(&bytes(scalar), &bytes(point)),
+                bytes::<32>(product)
+            );
+        }
+    }
+
+    // RFC 7748 section 5.2: k and u start at 9, then k becomes X25519(k, u) and u the old k.
+    #[test]
+    fn iterated_scalar_multiplication_matches_rfc_7748() {
+        let mut scalar = [0; 32];
+        scalar[0] = 9;
+        let mut point = scalar;
+        for iteration in 1..=1000 {
+            let product = x25519::scalarmult(&scalar, &point);
+            point = scalar;
+            scalar = product;
+            if iteration == 1 {
+                assert_eq!(
+                    scalar,
+                    bytes::<32>("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079")
+                );
+            }
+        }
+        assert_eq!(
+            scalar,
+            bytes::<32>("684cf59ba83309552800ef566f2f4d3c1c3887c49360e3875f2eb94d99532c51")
+        );
+    }
+
+    // RFC 7748 section 6.1.
+    #[test]
+    fn diffie_hellman_matches_rfc_7748() {
+        let alice = bytes("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = bytes("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_public = x25519::base(&alice);
+        let bob_public = x25519::base(&bob);
+        assert_eq!(
+            alice_public,
+            bytes::<32>("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            bob_public,
+            bytes::<32>("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared =
+            bytes::<32>("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(x25519::scalarmult(&alice, &bob_public), shared);
+        assert_eq!(x25519::scalarmult(&bob, &alice_public), shared);
+        assert_eq!(
+            Identity(alice).recipient().to_string(),
+            "age1s5s0qzvfxzn4gayt0hwtg0hhtgxm7wsdycup4a8t5j5ca25mfe4qt4hs7q"
+        );
+    }
+
+    // BIP 173's valid strings, which round trip, and invalid ones.
+    #[test]
+    fn bech32_matches_bip_173() {
+        let valid = [
+            "a12uel5l",
+            "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+            "11qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqc8247j",
+            "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w",
+            "?1ezyfcl",
+        ];
+        for text in valid {
+            let (hrp, data) = bech32::decode(text).unwrap_or_else(|| panic!("{text}"));
+            assert_eq!(bech32::encode(&hrp, &data), text);
+            assert_eq!(bech32::decode(&text.to_uppercase()), Some((hrp, data)));
+        }
+        let invalid = [
+            "pzry9x8gf2tvdw0s3jn54khce6mua7l",
+            "1pzry9x8gf2tvdw0s3jn54khce6mua7l",
+            "x1b4n0q5v",
+            "li1dgmt3",
+            "A1G7SGD8",
+            "10a06t8",
+            "1qzzfhee",
+            "A12UEl5L",
+        ];
+        for text in invalid {
+            assert_eq!(bech32::decode(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn keys_are_written_as_age_writes_them() {
+        let identity = Identity(std::array::from_fn(|at| at as u8 + 1));
+        let secret = "AGE-SECRET-KEY-1QYPQXPQ9QCRSSZG2PVXQ6RS0ZQG3YYC5Z5TPWXQERGD3C8G7RUSQGPQYEE";
+        assert_eq!(identity.to_secret_string(), secret);
+        assert_eq!(Identity::parse(secret).unwrap().0, identity.0);
+        let recipient = "age1q73he0q5yzfu3d64msd3p6rvksnrwjk3d2598mgtmlqt9wrdr37q2vrn72";
+        assert_eq!(identity.recipient().to_string(), recipient);
+        assert_eq!(Recipient::parse(recipient).unwrap(), identity.recipient());
+        assert!(Recipient::parse(secret).is_err());
+    }
+
+    // Written by a separate implementation of the age spec from fixed keys and nonces: the
+    // identity above, file key "YELLOW SUBMARINE".
+    const KNOWN_ANSWER: &str = "age-encryption.org/v1
+-> X25519 VxR2nRFr92Q2rnS8eT0sMK0ZA8WaxSc4BcfiaYtBDDY
+pFpNc5uoaT3eVkPu4b4H8k7kzeTHxU6u170aZzYll/s
+--- 4D1izi71qyL0+9t794HQTzoWaNw1GmdjpN9vATyxCG4
+";
+    const KNOWN_PAYLOAD: &str = "c8c9cacbcccdcecfd0d1d2d3d4d5d6d718c573cca5d217e79b29c2954fbb519f33d8e48a3b6c46b945946cf600907abfdcbb10a045e9f9f708d33ea4";
+
+    fn known_answer() -> Vec<u8> {
+        let mut data = KNOWN_ANSWER.as_bytes().to_vec();
+        data.extend(bytes::<60>(KNOWN_PAYLOAD));
+        data
+    }
+
+    #[test]
+    fn decrypts_a_known_answer() {
+        let identity = Identity(std::array::from_fn(|at| at as u8 + 1));
+        assert_eq!(
+            decrypt(&known_answer(), &[identity]).unwrap(),
+            b"edge_tools age known answer\n"
+        );
+    }
+
+    #[test]
+    fn refuses_the_wrong_identity_and_tampering() {
+        let identity = || Identity(std::array::from_fn(|at| at as u8 + 1));
+        assert!(decrypt(&known_answer(), &[Identity([7; 32])]).is_err());
+        let mut header = known_answer();
+        header[30] ^= 1;
+        assert!(decrypt(&header, &[identity()]).is_err());
+        let mut payload = known_answer();
+        *payload.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&payload, &[identity()]).is_err());
+    }
+
+    #[test]
+    fn round_trips_to_every_recipient() {
+        let identities = [Identity::generate(), Identity::generate()];
+        let recipients: Vec<Recipient> = identities.iter().map(Identity::recipient).collect();
+        for size in [0, 1, CHUNK, CHUNK + 1, 3 * CHUNK + 17] {
+            let data: Vec<u8> = (0..size).map(|at| (at % 251) as u8).collect();
+            let encrypted = encrypt(&data, &recipients).unwrap();
+            assert!(is_encrypted(&encrypted));
+            for identity in &identities {
+                assert_eq!(
+                    decrypt(&encrypted, std::slice::from_ref(identity)).unwrap(),
+                    data
+                );
+            }
+        }
+    }
+
+    // What a capture on a gateway that lost power leaves behind.
+    #[test]
+    fn unfinished_files_do_not_decrypt() {
+        let identity = Identity::generate();
+        let mut writer = Writer::new(Vec::new(), &[identity.recipient()]).unwrap();
+        writer.write_all(&vec![1; 2 * CHUNK + 1]).unwrap();
+        writer.flush().unwrap();
+        let cut = writer.inner.take().unwrap();
+        assert!(decrypt(&cut, &[identity]).is_err());
+    }
+}
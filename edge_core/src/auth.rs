@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// Credentials as given on the command line, before deciding which scheme they describe.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    // The contents of a NATS .creds file: a user JWT and the NKey seed signing for it.
+    pub creds: Option<String>,
+    // An NKey seed, e.g. SUAM...
+    pub nkey: Option<String>,
+}
+
+pub enum Auth {
+    None,
+    UserPassword { username: String, password: String },
+    Token(String),
+    Creds(String),
+    NKey(String),
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingPassword,
+    MissingUsername,
+    Ambiguous,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingPassword => write!(f, "Username but no password specified."),
+            AuthError::MissingUsername => write!(f, "Password but no username specified"),
+            AuthError::Ambiguous => write!(
+                f,
+                "More than one of username and password, token, creds and nkey specified. \
+                 Can't decide which to use."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl Credentials {
+    pub fn resolve(&self) -> Result<Auth, AuthError> {
+        let others = self.username.is_some() || self.password.is_some() || self.token.is_some();
+        match (self.creds.as_ref(), self.nkey.as_ref()) {
+            (Some(_), Some(_)) => return Err(AuthError::Ambiguous),
+            (Some(_), None) | (None, Some(_)) if others => return Err(AuthError::Ambiguous),
+            (Some(creds), None) => return Ok(Auth::Creds(creds.clone())),
+            (None, Some(seed)) => return Ok(Auth::NKey(seed.clone())),
+            (None, None) => {}
+        }
+        match (
+            self.username.as_ref(),
+            self.password.as_ref(),
+            self.token.as_ref(),
+        ) {
+            (Some(username), Some(password), None) => Ok(Auth::UserPassword {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            (Some(_), None, _) => Err(AuthError::MissingPassword),
+            (None, Some(_), _) => Err(AuthError::MissingUsername),
+            (None, None, Some(token)) => Ok(Auth::Token(token.clone())),
+            (Some(_), Some(_), Some(_)) => Err(AuthError::Ambiguous),
+            (None, None, None) => Ok(Auth::None),
+        }
+    }
+}
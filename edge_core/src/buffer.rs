@@ -0,0 +1,354 @@
+//! Bounded in-memory queues for values that arrive faster than they're handled, so a burst on a
+//! busy subject can't use up a small gateway's memory. At most `queue` values wait, and
+//! messages over `max_payload` bytes are dropped as they arrive. A full queue either makes the
+//! receiving side wait (`block`, the default) or drops its oldest value to make room
+//! (`drop-oldest`, for when only recent readings matter). The defaults come from the config
+//! file's `[limits]` table, and the `--queue`, `--max-payload` and `--overflow` options win over
+//! it:
+//!
+//! ```toml
+//! [limits]
+//! queue = 256
+//! max_payload = "64KiB"
+//! overflow = "drop-oldest"
+//! ```
+//!
+//! A NATS subscription that blocks holds up to another `queue` messages in the client, which
+//! then drops new ones as a slow consumer.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+const DEFAULT_QUEUE: usize = 1024;
+
+// Drops are reported at most this often.
+const WARN_EVERY: Duration = Duration::from_secs(10);
+
+/// What a full queue does with one more value.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    #[default]
+    Block,
+    DropOldest,
+}
+
+impl Overflow {
+    pub const NAMES: &'static str = "block or drop-oldest";
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Overflow, String> {
+        match value {
+            "block" => Ok(Overflow::Block),
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            _ => Err(format!("expected {}, got `{value}`", Overflow::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overflow::Block => write!(f, "block"),
+            Overflow::DropOldest => write!(f, "drop-oldest"),
+        }
+    }
+}
+
+/// A size in bytes, written like `65536`, `64KiB`, `64k` or `1MB`; k and M are powers of 1024
+/// whichever way they're spelt.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let invalid = || format!("expected a size such as 64KiB or 1MB, got `{value}`");
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: usize = number.parse().map_err(|_| invalid())?;
+    let factor = match unit.trim() {
+        "" | "B" => 1,
+        "k" | "K" | "KB" | "KiB" => 1 << 10,
+        "M" | "MB" | "MiB" => 1 << 20,
+        "G" | "GB" | "GiB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    number.checked_mul(factor).ok_or_else(invalid)
+}
+
+/// How much may wait in memory, and what happens past that.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub queue: usize,
+    // Bytes; messages of any size are taken without one.
+    pub max_payload: Option<usize>,
+    pub overflow: Overflow,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            queue: DEFAULT_QUEUE,
+            max_payload: None,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+impl Limits {
+    /// These limits with whatever the command line sets instead.
+    pub fn with_args(self, args: &LimitArgs) -> Limits {
+        Limits {
+            queue: args.queue.unwrap_or(self.queue).max(1),
+            max_payload: args.max_payload.or(self.max_payload),
+            overflow: args.overflow.unwrap_or(self.overflow),
+        }
+    }
+
+    /// Whether a message of `size` bytes is small enough to take.
+    pub fn allows(&self, size: usize) -> bool {
+        self.max_payload.is_none_or(|max| size <= max)
+    }
+}
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct LimitArgs {
+    /// Hold at most this many messages in memory; the config's [limits] queue, or 1024.
+    #[clap(long, action)]
+    pub queue: Option<usize>,
+    /// Drop messages larger than this, e.g. 64KiB; the config's [limits] max_payload, or no
+    /// limit.
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_payload: Option<usize>,
+    /// When the queue is full: block (default) or drop-oldest.
+    #[clap(long, action)]
+    pub overflow: Option<Overflow>,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiving: bool,
+    dropped: u64,
+    warned: Option<Instant>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    limits: Limits,
+    // What the queue holds values for, e.g. `nats subscribe`, in warnings.
+    what: String,
+    items: Notify,
+    space: Notify,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// A bounded queue for `what` by `limits`, with as many senders as clones of the first.
+pub fn queue<T>(what: &str, limits: Limits) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            senders: 1,
+            receiving: true,
+            dropped: 0,
+            warned: None,
+        }),
+        limits,
+        what: what.to_string(),
+        items: Notify::new(),
+        space: Notify::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiver went away; the value is handed back.
+#[derive(Debug)]
+pub struct Closed<T>(pub T);
+
+impl<T> Sender<T> {
+    /// Queues `value`, waiting for room or dropping the oldest value as the limits say.
+    pub async fn send(&self, mut value: T) -> Result<(), Closed<T>> {
+        let shared = &*self.shared;
+        loop {
+            // Registered before looking, so room made in between isn't missed.
+            let room = shared.space.notified();
+            // The value comes back when there's no room for it yet.
+            let waiting = {
+                let mut state = shared.lock();
+                if !state.receiving {
+                    return Err(Closed(value));
+                }
+                let full = state.items.len() >= shared.limits.queue;
+                if full && shared.limits.overflow == Overflow::Block {
+                    Some(value)
+                } else {
+                    if full {
+                        state.items.pop_front();
+                        dropped(shared, &mut state, "the queue is full");
+                    }
+                    state.items.push_back(value);
+                    None
+                }
+            };
+            match waiting {
+                Some(back) => {
+                    value = back;
+                    room.await;
+                }
+                None => {
+                    shared.items.notify_one();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Counts a value dropped without being queued, such as a message over `max_payload`.
+    pub fn discard(&self, why: &str) {
+        let shared = &*self.shared;
+        dropped(shared, &mut shared.lock(), why);
+    }
+
+    /// The limits the queue was made with.
+    pub fn limits(&self) -> Limits {
+        self.shared.limits
+    }
+}
+
+fn dropped<T>(shared: &Shared<T>, state: &mut State<T>, why: &str) {
+    state.dropped += 1;
+    let now = Instant::now();
+    if state
+        .warned
+        .is_none_or(|warned| now.duration_since(warned) >= WARN_EVERY)
+    {
+        state.warned = Some(now);
+        log::warn!(
+            "{}: dropped a message, {why} ({} dropped so far)",
+            shared.what,
+            state.dropped
+        );
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.items.notify_one();
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// The oldest value waiting, or None once every sender is gone and nothing is left.
+    pub async fn recv(&mut self) -> Option<T> {
+        let shared = &*self.shared;
+        loop {
+            let arrived = shared.items.notified();
+            {
+                let mut state = shared.lock();
+                if let Some(value) = state.items.pop_front() {
+                    drop(state);
+                    shared.space.notify_one();
+                    return Some(value);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            arrived.await;
+        }
+    }
+
+    /// Values dropped so far, for overflow or size.
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+}
+
+impl<T: Send + 'static> Receiver<T> {
+    /// A view of how full the queue is, for `--stats`; it doesn't keep the queue open.
+    pub fn depth(&self) -> Depth {
+        let shared = Arc::downgrade(&self.shared);
+        Depth::new(&self.shared.what, self.shared.limits.queue, move || {
+            let shared = Weak::upgrade(&shared)?;
+            let state = shared.lock();
+            Some((state.items.len(), state.dropped))
+        })
+    }
+}
+
+/// How many values wait in a queue and how many it dropped, from [`Receiver::depth`].
+pub struct Depth {
+    what: String,
+    limit: usize,
+    state: Box<dyn Fn() -> Option<(usize, u64)> + Send + Sync>,
+}
+
+impl Depth {
+    /// For a queue of at most `limit` values that `state` reports on.
+    pub(crate) fn new(
+        what: &str,
+        limit: usize,
+        state: impl Fn() -> Option<(usize, u64)> + Send + Sync + 'static,
+    ) -> Depth {
+        Depth {
+            what: what.to_string(),
+            limit,
+            state: Box::new(state),
+        }
+    }
+
+    /// What the queue holds values for.
+    pub fn what(&self) -> &str {
+        &self.what
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Values waiting and dropped so far, or None once the queue is gone.
+    pub fn get(&self) -> Option<(usize, u64)> {
+        (self.state)()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiving = false;
+        self.shared.space.notify_waiters();
+    }
+}
@@ -0,0 +1,60 @@
+//! Values read recently, so consumers that poll the same point — `edge api` callers, bridge
+//! sources, a dashboard — share one transaction with a slow device rather than queueing one
+//! each. A value is served from memory until it's older than the caller's TTL; callers that
+//! ask for a point while it's being read wait for that read instead of starting another.
+//! Errors aren't kept, and writes drop what they overwrite with [`Cache::invalidate`].
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Slot<V> = Arc<tokio::sync::Mutex<Option<(V, Instant)>>>;
+
+pub struct Cache<K, V> {
+    slots: Mutex<HashMap<K, Slot<V>>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Default for Cache<K, V> {
+    fn default() -> Cache<K, V> {
+        Cache::new()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Cache<K, V> {
+    pub fn new() -> Cache<K, V> {
+        Cache {
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The value for `key` if it was read less than `ttl` ago, or a new one from `read`.
+    pub async fn get<F, Fut, E>(&self, key: K, ttl: Duration, read: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let slot = self.lock().entry(key).or_default().clone();
+        let mut slot = slot.lock().await;
+        if let Some((value, read_at)) = &*slot {
+            if read_at.elapsed() < ttl {
+                return Ok(value.clone());
+            }
+        }
+        let value = read().await?;
+        *slot = Some((value.clone(), Instant::now()));
+        Ok(value)
+    }
+
+    /// Drops the values of the keys `stale` picks, so the next read of them goes to the device.
+    pub fn invalidate(&self, stale: impl Fn(&K) -> bool) {
+        // A value being read now is replaced when the read is done.
+        self.lock()
+            .retain(|key, slot| !stale(key) || slot.try_lock().is_err());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<K, Slot<V>>> {
+        self.slots.lock().expect("cache poisoned")
+    }
+}
@@ -0,0 +1,440 @@
+//! Records the traffic a tool exchanges with a device or server into a pcapng file, for
+//! `--capture`. The tool talks to a local relay which forwards to the real address; every
+//! chunk passed along is written as a TCP segment between the real endpoints, with a
+//! handshake and teardown so Wireshark follows and reassembles each connection.
+//!
+//! A path ending in `.zst` is compressed with zstd (see [`crate::zstd`]). Plain captures are
+//! flushed after every packet; compressed ones about once a second, so an interrupted tool
+//! loses at most the last second of traffic. One ending in `.age`, e.g. `site.pcapng.zst.age`,
+//! is encrypted to the recipients in the config's `[encryption]` table (see [`crate::age`]);
+//! the file is only complete once the tool has finished, which a tool exiting on an error
+//! still does. One cut off before then, by a power loss or a kill -9, can't be decrypted at
+//! all, not even the traffic before the cut.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{age, config, exit, zstd};
+
+// Packets start at the IP header.
+const LINKTYPE_RAW: u16 = 101;
+// Stay under the 64 KiB IPv4 total length.
+const MAX_SEGMENT: usize = 65_000;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+// How often a compressed capture ends a zstd frame.
+const COMPRESSED_FLUSH: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct Capture {
+    file: Arc<Mutex<Sink>>,
+    // When a relay last passed data along.
+    last_activity: Arc<Mutex<Instant>>,
+}
+
+impl Capture {
+    /// Creates `path` and writes the section and interface headers; `tool` is recorded as the
+    /// capturing application.
+    pub fn create(path: &Path, tool: &str) -> io::Result<Capture> {
+        let encrypted = age::is_age(path);
+        let compressed = match encrypted {
+            true => zstd::is_zst(&path.with_extension("")),
+            false => zstd::is_zst(path),
+        };
+        let recipients = match encrypted {
+            true => config::encryption().map_err(io::Error::other)?.recipients,
+            false => Vec::new(),
+        };
+        if encrypted && recipients.is_empty() {
+            return Err(io::Error::other(
+                "no recipients in the config's [encryption] table to encrypt it to",
+            ));
+        }
+        let file = BufWriter::new(File::create(path)?);
+        let out: Box<dyn Write + Send> = match (encrypted, compressed) {
+            (true, true) => Box::new(zstd::Writer::new(age::Writer::new(file, &recipients)?)),
+            (true, false) => Box::new(age::Writer::new(file, &recipients)?),
+            (false, true) => Box::new(zstd::Writer::new(file)),
+            (false, false) => Box::new(file),
+        };
+        let mut file = Sink {
+            out,
+            compressed,
+            encrypted,
+            flushed: Instant::now(),
+        };
+
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1a2b_3c4du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not known up front.
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        let application = format!("{tool} {}", env!("CARGO_PKG_VERSION"));
+        push_option(&mut shb, 4, application.as_bytes());
+        push_option(&mut shb, 0, &[]);
+        write_block(&mut file.out, 0x0a0d_0d0a, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut idb, 2, b"relay");
+        push_option(&mut idb, 0, &[]);
+        write_block(&mut file.out, 0x0000_0001, &idb)?;
+        file.out.flush()?;
+
+        let file = Arc::new(Mutex::new(file));
+        let finishing = Arc::downgrade(&file);
+        exit::at_exit(move || {
+            if let Some(file) = finishing.upgrade() {
+                finish(&file);
+            }
+        });
+        Ok(Capture {
+            file,
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+        })
+    }
+
+    /// Waits (at most two seconds) until the relays have been idle for a moment, so data the
+    /// tool wrote just before finishing still reaches the server and the file. An encrypted
+    /// capture is finished and records nothing after.
+    pub async fn settle(&self) {
+        let idle = Duration::from_millis(100);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            let last = match self.last_activity.lock() {
+                Ok(last) => *last,
+                Err(poisoned) => *poisoned.into_inner(),
+            };
+            if last.elapsed() >= idle {
+                break;
+            }
+            tokio::time::sleep(idle).await;
+        }
+        finish(&self.file);
+    }
+
+    /// Starts a relay on a local port that records and forwards every connection to
+    /// `upstream`, and returns the address the tool should connect to instead.
+    pub async fn relay(&self, upstream: SocketAddr) -> io::Result<SocketAddr> {
+        let bind: IpAddr = if upstream.is_ipv6() {
+            IpAddr::from([0u16, 0, 0, 0, 0, 0, 0, 1])
+        } else {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        };
+        let listener = TcpListener::bind((bind, 0)).await?;
+        let local = listener.local_addr()?;
+        log::debug!("Capturing traffic to {upstream} through {local}");
+
+        let capture = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let inbound = match listener.accept().await {
+                    Ok((inbound, _)) => inbound,
+                    Err(err) => {
+                        log::warn!("Capture relay accept failed: {err}");
+                        continue;
+                    }
+                };
+                let capture = capture.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = capture.forward(inbound, upstream).await {
+                        log::warn!("Capture relay to {upstream} failed: {err}");
+                    }
+                });
+            }
+        });
+        Ok(local)
+    }
+
+    async fn forward(&self, inbound: TcpStream, upstream: SocketAddr) -> io::Result<()> {
+        let outbound = match TcpStream::connect(upstream).await {
+            Ok(outbound) => outbound,
+            Err(err) => {
+                // Reset the tool's connection so it fails the way a direct connection would.
+                inbound.set_zero_linger()?;
+                return Err(err);
+            }
+        };
+        let client = outbound.local_addr()?;
+        let flow = Arc::new(Mutex::new(Flow::new(client, upstream)));
+        self.record(&flow, Direction::ToServer, SYN, &[]);
+        self.record(&flow, Direction::ToClient, SYN | ACK, &[]);
+        self.record(&flow, Direction::ToServer, ACK, &[]);
+
+        let (client_read, client_write) = inbound.into_split();
+        let (server_read, server_write) = outbound.into_split();
+        let to_server = self.pipe(client_read, server_write, flow.clone(), Direction::ToServer);
+        let to_client = self.pipe(server_read, client_write, flow, Direction::ToClient);
+        let (to_server, to_client) = tokio::join!(to_server, to_client);
+        to_server.and(to_client)
+    }
+
+    async fn pipe(
+        &self,
+        mut from: OwnedReadHalf,
+        mut to: OwnedWriteHalf,
+        flow: Arc<Mutex<Flow>>,
+        direction: Direction,
+    ) -> io::Result<()> {
+        let mut buffer = vec![0u8; 16 * 1024];
+        loop {
+            let n = match from.read(&mut buffer).await {
+                Ok(n) => n,
+                Err(err) => {
+                    self.record(&flow, direction, FIN | ACK, &[]);
+                    return Err(err);
+                }
+            };
+            if n == 0 {
+                self.record(&flow, direction, FIN | ACK, &[]);
+                // Pass the half-close on so request/response tools see end of stream.
+                return to.shutdown().await;
+            }
+            self.record(&flow, direction, PSH | ACK, &buffer[..n]);
+            to.write_all(&buffer[..n]).await?;
+            if let Ok(mut last) = self.last_activity.lock() {
+                *last = Instant::now();
+            }
+        }
+    }
+
+    /// Writes one direction's data as segments, logging rather than failing the connection if
+    /// the capture file can't be written.
+    fn record(&self, flow: &Mutex<Flow>, direction: Direction, flags: u8, data: &[u8]) {
+        let mut flow = match flow.lock() {
+            Ok(flow) => flow,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(MAX_SEGMENT).collect()
+        };
+        for chunk in chunks {
+            let packet = flow.segment(direction, flags, chunk);
+            if let Err(err) = self.write_packet(&packet) {
+                log::warn!("Unable to write capture: {err}");
+            }
+        }
+    }
+
+    fn write_packet(&self, packet: &[u8]) -> io::Result<()> {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros() as u64)
+            .unwrap_or(0);
+        let mut epb = Vec::with_capacity(packet.len() + 24);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(packet);
+        pad(&mut epb);
+
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        write_block(&mut file.out, 0x0000_0006, &epb)?;
+        // Flushed so an interrupted tool still leaves a readable file.
+        if !file.compressed || file.flushed.elapsed() >= COMPRESSED_FLUSH {
+            file.flushed = Instant::now();
+            file.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes out what `file` holds and, if it's encrypted, its last chunk, after which it
+/// records nothing.
+fn finish(file: &Mutex<Sink>) {
+    let mut file = match file.lock() {
+        Ok(file) => file,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Err(err) = file.out.flush() {
+        log::warn!("Unable to write capture: {err}");
+    }
+    if file.encrypted {
+        // Dropping the writer writes the last chunk.
+        file.out = Box::new(io::sink());
+    }
+}
+
+/// The capture file, compressed or not.
+struct Sink {
+    out: Box<dyn Write + Send>,
+    compressed: bool,
+    encrypted: bool,
+    flushed: Instant,
+}
+
+/// A capture as written, decrypted with the configured identity and decompressed as needed.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = std::fs::read(path)?;
+    if age::is_encrypted(&data) {
+        let identities = config::encryption()
+            .and_then(|encryption| encryption.identities())
+            .map_err(io::Error::other)?;
+        data = age::decrypt(&data, &identities)?;
+    }
+    if zstd::is_compressed(&data) {
+        data = zstd::decompress(&data)?;
+    }
+    Ok(data)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// Sequence numbers for one relayed connection.
+struct Flow {
+    client: SocketAddr,
+    server: SocketAddr,
+    // Next sequence number each side sends.
+    client_seq: u32,
+    server_seq: u32,
+}
+
+impl Flow {
+    fn new(client: SocketAddr, server: SocketAddr) -> Flow {
+        Flow {
+            client,
+            server,
+            client_seq: 1_000,
+            server_seq: 2_000,
+        }
+    }
+
+    fn segment(&mut self, direction: Direction, flags: u8, data: &[u8]) -> Vec<u8> {
+        let (source, destination, seq, ack) = match direction {
+            Direction::ToServer => (self.client, self.server, self.client_seq, self.server_seq),
+            Direction::ToClient => (self.server, self.client, self.server_seq, self.client_seq),
+        };
+        // SYN and FIN take up a sequence number of their own.
+        let advance = data.len() as u32 + u32::from(flags & (SYN | FIN) != 0);
+        match direction {
+            Direction::ToServer => self.client_seq = seq.wrapping_add(advance),
+            Direction::ToClient => self.server_seq = seq.wrapping_add(advance),
+        }
+        let ack = if flags & ACK != 0 { ack } else { 0 };
+
+        let mut tcp = Vec::with_capacity(20 + data.len());
+        tcp.extend_from_slice(&source.port().to_be_bytes());
+        tcp.extend_from_slice(&destination.port().to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.push(5 << 4);
+        tcp.push(flags);
+        tcp.extend_from_slice(&65_535u16.to_be_bytes());
+        tcp.extend_from_slice(&[0, 0, 0, 0]);
+        tcp.extend_from_slice(data);
+        ip_packet(source.ip(), destination.ip(), tcp)
+    }
+}
+
+/// Wraps a TCP segment in an IPv4 or IPv6 header, filling in both checksums. Mixed address
+/// families (a v4-mapped upstream) are written as IPv6.
+fn ip_packet(source: IpAddr, destination: IpAddr, mut tcp: Vec<u8>) -> Vec<u8> {
+    let length = tcp.len();
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let mut pseudo = Vec::with_capacity(12 + length);
+            pseudo.extend_from_slice(&source.octets());
+            pseudo.extend_from_slice(&destination.octets());
+            pseudo.extend_from_slice(&[0, 6]);
+            pseudo.extend_from_slice(&(length as u16).to_be_bytes());
+            pseudo.extend_from_slice(&tcp);
+            tcp[16..18].copy_from_slice(&checksum(&pseudo).to_be_bytes());
+
+            let mut packet = Vec::with_capacity(20 + length);
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + length) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            let header = checksum(&packet[..20]);
+            packet[10..12].copy_from_slice(&header.to_be_bytes());
+            packet.extend_from_slice(&tcp);
+            packet
+        }
+        (source, destination) => {
+            let source = to_ipv6(source).octets();
+            let destination = to_ipv6(destination).octets();
+            let mut pseudo = Vec::with_capacity(40 + length);
+            pseudo.extend_from_slice(&source);
+            pseudo.extend_from_slice(&destination);
+            pseudo.extend_from_slice(&(length as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 6]);
+            pseudo.extend_from_slice(&tcp);
+            tcp[16..18].copy_from_slice(&checksum(&pseudo).to_be_bytes());
+
+            let mut packet = Vec::with_capacity(40 + length);
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(length as u16).to_be_bytes());
+            packet.extend_from_slice(&[6, 64]);
+            packet.extend_from_slice(&source);
+            packet.extend_from_slice(&destination);
+            packet.extend_from_slice(&tcp);
+            packet
+        }
+    }
+}
+
+fn to_ipv6(address: IpAddr) -> std::net::Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address,
+    }
+}
+
+/// The Internet checksum (RFC 1071).
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn push_option(block: &mut Vec<u8>, code: u16, value: &[u8]) {
+    block.extend_from_slice(&code.to_le_bytes());
+    block.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    block.extend_from_slice(value);
+    pad(block);
+}
+
+fn pad(block: &mut Vec<u8>) {
+    while !block.len().is_multiple_of(4) {
+        block.push(0);
+    }
+}
+
+fn write_block(out: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total = (body.len() + 12) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total.to_le_bytes())
+}
@@ -0,0 +1,564 @@
+//! Raw 16-bit words to values and back, the one way every tool does it: integer widths up to
+//! 64 bits, IEEE floats, packed BCD, bitfields and scaling, in any of the byte orders devices
+//! lay multi-word values out in.
+//!
+//! Orders are named after where the bytes of a big-endian `ABCD` value end up in the
+//! registers: `abcd` is high word first (the Modbus convention), `cdab` swaps the words,
+//! `badc` swaps the bytes within each word and `dcba` is little-endian throughout. Wider and
+//! narrower values follow the same pattern.
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Abcd,
+    Badc,
+    Cdab,
+    Dcba,
+}
+
+impl Order {
+    pub const NAMES: &'static str = "abcd, badc, cdab or dcba";
+
+    pub fn parse(name: &str) -> Option<Order> {
+        match name {
+            "abcd" => Some(Order::Abcd),
+            "badc" => Some(Order::Badc),
+            "cdab" => Some(Order::Cdab),
+            "dcba" => Some(Order::Dcba),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Abcd => "abcd",
+            Order::Badc => "badc",
+            Order::Cdab => "cdab",
+            Order::Dcba => "dcba",
+        }
+    }
+
+    fn swaps_words(self) -> bool {
+        matches!(self, Order::Cdab | Order::Dcba)
+    }
+
+    fn swaps_bytes(self) -> bool {
+        matches!(self, Order::Badc | Order::Dcba)
+    }
+}
+
+/// How a number is encoded in its words.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    // Packed decimal digits, four to a word.
+    Bcd16,
+    Bcd32,
+}
+
+impl Type {
+    pub fn parse(name: &str) -> Option<Type> {
+        match name {
+            "u16" => Some(Type::U16),
+            "i16" => Some(Type::I16),
+            "u32" => Some(Type::U32),
+            "i32" => Some(Type::I32),
+            "u64" => Some(Type::U64),
+            "i64" => Some(Type::I64),
+            "f32" => Some(Type::F32),
+            "f64" => Some(Type::F64),
+            "bcd16" => Some(Type::Bcd16),
+            "bcd32" => Some(Type::Bcd32),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::U16 => "u16",
+            Type::I16 => "i16",
+            Type::U32 => "u32",
+            Type::I32 => "i32",
+            Type::U64 => "u64",
+            Type::I64 => "i64",
+            Type::F32 => "f32",
+            Type::F64 => "f64",
+            Type::Bcd16 => "bcd16",
+            Type::Bcd32 => "bcd32",
+        }
+    }
+
+    /// Registers a value takes.
+    pub fn words(self) -> usize {
+        match self {
+            Type::U16 | Type::I16 | Type::Bcd16 => 1,
+            Type::U32 | Type::I32 | Type::F32 | Type::Bcd32 => 2,
+            Type::U64 | Type::I64 | Type::F64 => 4,
+        }
+    }
+
+    /// The smallest and largest values the type holds exactly; floats hold anything.
+    fn range(self) -> Option<(f64, f64)> {
+        match self {
+            Type::U16 => Some((0.0, u16::MAX as f64)),
+            Type::I16 => Some((i16::MIN as f64, i16::MAX as f64)),
+            Type::U32 => Some((0.0, u32::MAX as f64)),
+            Type::I32 => Some((i32::MIN as f64, i32::MAX as f64)),
+            Type::U64 => Some((0.0, u64::MAX as f64)),
+            Type::I64 => Some((i64::MIN as f64, i64::MAX as f64)),
+            Type::Bcd16 => Some((0.0, 9_999.0)),
+            Type::Bcd32 => Some((0.0, 99_999_999.0)),
+            Type::F32 | Type::F64 => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // Fewer words than the type needs.
+    TooShort { needed: usize, got: usize },
+    // A nibble above 9 in a BCD value.
+    InvalidBcd(u64),
+    OutOfRange { value: f64, ty: Type },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooShort { needed, got } => {
+                write!(f, "needs {needed} registers, got {got}")
+            }
+            Error::InvalidBcd(raw) => write!(f, "{raw:#x} isn't a BCD value"),
+            Error::OutOfRange { value, ty } => write!(f, "{value} doesn't fit in {}", ty.name()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The big-endian bytes of the value `words` hold in `order`.
+pub fn to_bytes(words: &[u16], order: Order) -> Vec<u8> {
+    let mut words = words.to_vec();
+    if order.swaps_words() {
+        words.reverse();
+    }
+    words
+        .into_iter()
+        .map(|word| match order.swaps_bytes() {
+            true => word.swap_bytes(),
+            false => word,
+        })
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// The words that hold big-endian `bytes` in `order`; an odd last byte is padded with zero.
+pub fn from_bytes(bytes: &[u8], order: Order) -> Vec<u16> {
+    let mut words: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+        .map(|word| match order.swaps_bytes() {
+            true => word.swap_bytes(),
+            false => word,
+        })
+        .collect();
+    if order.swaps_words() {
+        words.reverse();
+    }
+    words
+}
+
+/// The number at the start of `words`. Extra words after the value are ignored.
+pub fn decode(words: &[u16], ty: Type, order: Order) -> Result<f64, Error> {
+    let raw = raw(words, ty.words(), order)?;
+    Ok(match ty {
+        Type::U16 => f64::from(raw as u16),
+        Type::I16 => f64::from(raw as u16 as i16),
+        Type::U32 => f64::from(raw as u32),
+        Type::I32 => f64::from(raw as u32 as i32),
+        Type::U64 => raw as f64,
+        Type::I64 => raw as i64 as f64,
+        Type::F32 => f64::from(f32::from_bits(raw as u32)),
+        Type::F64 => f64::from_bits(raw),
+        Type::Bcd16 | Type::Bcd32 => from_bcd(raw)? as f64,
+    })
+}
+
+/// The words for `number`, rounded to the nearest integer for integer types.
+pub fn encode(number: f64, ty: Type, order: Order) -> Result<Vec<u16>, Error> {
+    let out_of_range = || Error::OutOfRange { value: number, ty };
+    let integer = match ty.range() {
+        Some((min, max)) => {
+            let rounded = number.round();
+            if !(min..=max).contains(&rounded) {
+                return Err(out_of_range());
+            }
+            rounded
+        }
+        None => number,
+    };
+    let raw = match ty {
+        Type::U16 | Type::U32 | Type::U64 => integer as u64,
+        Type::I16 => integer as i16 as u16 as u64,
+        Type::I32 => integer as i32 as u32 as u64,
+        Type::I64 => integer as i64 as u64,
+        Type::F32 => u64::from((number as f32).to_bits()),
+        Type::F64 => number.to_bits(),
+        Type::Bcd16 | Type::Bcd32 => to_bcd(integer as u64),
+    };
+    let bytes = raw.to_be_bytes();
+    Ok(from_bytes(&bytes[8 - 2 * ty.words()..], order))
+}
+
+/// The first `count` words of `words` as one unsigned integer.
+pub fn raw(words: &[u16], count: usize, order: Order) -> Result<u64, Error> {
+    if words.len() < count {
+        return Err(Error::TooShort {
+            needed: count,
+            got: words.len(),
+        });
+    }
+    Ok(to_bytes(&words[..count.min(4)], order)
+        .into_iter()
+        .fold(0, |value, byte| (value << 8) | u64::from(byte)))
+}
+
+/// The number packed BCD digits `raw` holds, four bits to a digit.
+pub fn from_bcd(raw: u64) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..16).rev() {
+        let digit = (raw >> (shift * 4)) & 0xf;
+        if digit > 9 {
+            return Err(Error::InvalidBcd(raw));
+        }
+        value = value * 10 + digit;
+    }
+    Ok(value)
+}
+
+/// `value` as packed BCD digits; digits beyond the sixteenth are dropped.
+pub fn to_bcd(mut value: u64) -> u64 {
+    let mut raw = 0;
+    for shift in 0..16 {
+        raw |= (value % 10) << (shift * 4);
+        value /= 10;
+    }
+    raw
+}
+
+/// `width` bits of `raw` starting at bit `offset`, counting from the least significant.
+pub fn bits(raw: u64, offset: u32, width: u32) -> u64 {
+    raw.checked_shr(offset).unwrap_or(0) & mask(width)
+}
+
+/// `raw` with `width` bits at `offset` replaced by the low bits of `value`.
+pub fn with_bits(raw: u64, offset: u32, width: u32, value: u64) -> u64 {
+    let mask = mask(width).checked_shl(offset).unwrap_or(0);
+    (raw & !mask) | (value.checked_shl(offset).unwrap_or(0) & mask)
+}
+
+fn mask(width: u32) -> u64 {
+    match width {
+        0 => 0,
+        64.. => u64::MAX,
+        width => (1 << width) - 1,
+    }
+}
+
+/// A linear conversion from raw to engineering values: `raw * factor + offset`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale {
+            factor: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl Scale {
+    pub fn factor(factor: f64) -> Scale {
+        Scale {
+            factor,
+            ..Scale::default()
+        }
+    }
+
+    /// `10^exponent`, as SunSpec scale factors give it. `-32768` means the device doesn't
+    /// have one.
+    pub fn power_of_ten(exponent: i16) -> Option<Scale> {
+        (exponent != i16::MIN).then(|| Scale::factor(10f64.powi(i32::from(exponent))))
+    }
+
+    pub fn apply(self, raw: f64) -> f64 {
+        raw * self.factor + self.offset
+    }
+
+    /// The raw value that [`Scale::apply`] turns into `value`, for writing it back.
+    pub fn remove(self, value: f64) -> f64 {
+        (value - self.offset) / self.factor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [Order; 4] = [Order::Abcd, Order::Badc, Order::Cdab, Order::Dcba];
+    const TYPES: [Type; 10] = [
+        Type::U16,
+        Type::I16,
+        Type::U32,
+        Type::I32,
+        Type::U64,
+        Type::I64,
+        Type::F32,
+        Type::F64,
+        Type::Bcd16,
+        Type::Bcd32,
+    ];
+
+    #[test]
+    fn orders_lay_out_bytes() {
+        let bytes = [0xa1, 0xb2, 0xc3, 0xd4];
+        assert_eq!(from_bytes(&bytes, Order::Abcd), [0xa1b2, 0xc3d4]);
+        assert_eq!(from_bytes(&bytes, Order::Badc), [0xb2a1, 0xd4c3]);
+        assert_eq!(from_bytes(&bytes, Order::Cdab), [0xc3d4, 0xa1b2]);
+        assert_eq!(from_bytes(&bytes, Order::Dcba), [0xd4c3, 0xb2a1]);
+        for order in ORDERS {
+            assert_eq!(to_bytes(&from_bytes(&bytes, order), order), bytes);
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_orders_only_swap_bytes() {
+        assert_eq!(decode(&[0x1234], Type::U16, Order::Abcd), Ok(4660.0));
+        assert_eq!(decode(&[0x1234], Type::U16, Order::Cdab), Ok(4660.0));
+        assert_eq!(decode(&[0x3412], Type::U16, Order::Badc), Ok(4660.0));
+        assert_eq!(decode(&[0x3412], Type::U16, Order::Dcba), Ok(4660.0));
+    }
+
+    #[test]
+    fn decodes_integers() {
+        assert_eq!(decode(&[0xffff], Type::U16, Order::Abcd), Ok(65535.0));
+        assert_eq!(decode(&[0xffff], Type::I16, Order::Abcd), Ok(-1.0));
+        assert_eq!(decode(&[0x8000], Type::I16, Order::Abcd), Ok(-32768.0));
+        assert_eq!(
+            decode(&[0x0001, 0x0000], Type::U32, Order::Abcd),
+            Ok(65536.0)
+        );
+        assert_eq!(
+            decode(&[0x0000, 0x0001], Type::U32, Order::Cdab),
+            Ok(65536.0)
+        );
+        assert_eq!(decode(&[0xffff, 0xfffe], Type::I32, Order::Abcd), Ok(-2.0));
+        assert_eq!(
+            decode(&[0x0000, 0x0000, 0x0001, 0x0000], Type::U64, Order::Abcd),
+            Ok(65536.0)
+        );
+        assert_eq!(
+            decode(&[0xffff, 0xffff, 0xffff, 0xff00], Type::I64, Order::Abcd),
+            Ok(-256.0)
+        );
+        assert_eq!(
+            decode(&[0x00ff, 0xffff, 0xffff, 0xffff], Type::I64, Order::Dcba),
+            Ok(-256.0)
+        );
+    }
+
+    #[test]
+    fn decodes_floats() {
+        assert_eq!(decode(&[0x4148, 0x0000], Type::F32, Order::Abcd), Ok(12.5));
+        assert_eq!(decode(&[0x0000, 0x4148], Type::F32, Order::Cdab), Ok(12.5));
+        assert_eq!(decode(&[0x4841, 0x0000], Type::F32, Order::Badc), Ok(12.5));
+        assert_eq!(decode(&[0x0000, 0x4841], Type::F32, Order::Dcba), Ok(12.5));
+        assert_eq!(
+            decode(&[0x4029, 0x0000, 0x0000, 0x0000], Type::F64, Order::Abcd),
+            Ok(12.5)
+        );
+        let nan = decode(&[0x7fc0, 0x0000], Type::F32, Order::Abcd).unwrap();
+        assert!(nan.is_nan());
+    }
+
+    #[test]
+    fn decodes_bcd() {
+        assert_eq!(decode(&[0x1234], Type::Bcd16, Order::Abcd), Ok(1234.0));
+        assert_eq!(
+            decode(&[0x0012, 0x3456], Type::Bcd32, Order::Abcd),
+            Ok(123456.0)
+        );
+        assert_eq!(
+            decode(&[0x12a4], Type::Bcd16, Order::Abcd),
+            Err(Error::InvalidBcd(0x12a4))
+        );
+        assert_eq!(from_bcd(0x9999_9999_9999_9999), Ok(9_999_999_999_999_999));
+        assert_eq!(to_bcd(1234), 0x1234);
+    }
+
+    #[test]
+    fn short_input_is_an_error() {
+        assert_eq!(
+            decode(&[0x4148], Type::F32, Order::Abcd),
+            Err(Error::TooShort { needed: 2, got: 1 })
+        );
+        assert_eq!(
+            decode(&[], Type::U16, Order::Abcd),
+            Err(Error::TooShort { needed: 1, got: 0 })
+        );
+    }
+
+    #[test]
+    fn extra_words_are_ignored() {
+        assert_eq!(decode(&[7, 8, 9], Type::U16, Order::Abcd), Ok(7.0));
+        assert_eq!(decode(&[0, 7, 9], Type::U32, Order::Abcd), Ok(7.0));
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        let samples = [0.0, 1.0, 7.0, 99.0, 1234.0, 9999.0];
+        for ty in TYPES {
+            for order in ORDERS {
+                for number in samples {
+                    let words = encode(number, ty, order).unwrap();
+                    assert_eq!(words.len(), ty.words(), "{ty:?} {order:?}");
+                    assert_eq!(decode(&words, ty, order), Ok(number), "{ty:?} {order:?}");
+                }
+            }
+        }
+        for ty in [Type::I16, Type::I32, Type::I64, Type::F32, Type::F64] {
+            for order in ORDERS {
+                let words = encode(-12.0, ty, order).unwrap();
+                assert_eq!(decode(&words, ty, order), Ok(-12.0), "{ty:?} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_integer_limits() {
+        assert_eq!(encode(65535.0, Type::U16, Order::Abcd), Ok(vec![0xffff]));
+        assert_eq!(encode(-32768.0, Type::I16, Order::Abcd), Ok(vec![0x8000]));
+        assert_eq!(
+            encode(-1.0, Type::I32, Order::Abcd),
+            Ok(vec![0xffff, 0xffff])
+        );
+        assert_eq!(
+            encode(4_294_967_295.0, Type::U32, Order::Cdab),
+            Ok(vec![0xffff, 0xffff])
+        );
+        assert_eq!(
+            encode(1.0, Type::U32, Order::Cdab),
+            Ok(vec![0x0001, 0x0000])
+        );
+        assert_eq!(encode(1.0, Type::U16, Order::Badc), Ok(vec![0x0100]));
+    }
+
+    #[test]
+    fn rounds_integers() {
+        assert_eq!(encode(1.4, Type::U16, Order::Abcd), Ok(vec![1]));
+        assert_eq!(encode(1.5, Type::U16, Order::Abcd), Ok(vec![2]));
+        assert_eq!(encode(-0.4, Type::U16, Order::Abcd), Ok(vec![0]));
+        assert_eq!(encode(-1.5, Type::I16, Order::Abcd), Ok(vec![0xfffe]));
+    }
+
+    #[test]
+    fn refuses_values_out_of_range() {
+        for (number, ty) in [
+            (65536.0, Type::U16),
+            (-1.0, Type::U16),
+            (32768.0, Type::I16),
+            (-32769.0, Type::I16),
+            (4_294_967_296.0, Type::U32),
+            (2_147_483_648.0, Type::I32),
+            (-1.0, Type::U64),
+            (10_000.0, Type::Bcd16),
+            (100_000_000.0, Type::Bcd32),
+            (f64::NAN, Type::U16),
+            (f64::INFINITY, Type::I32),
+        ] {
+            match encode(number, ty, Order::Abcd) {
+                Err(Error::OutOfRange { ty: refused, .. }) => assert_eq!(refused, ty),
+                other => panic!("{number} as {ty:?}: {other:?}"),
+            }
+        }
+        assert_eq!(
+            Error::OutOfRange {
+                value: 70000.0,
+                ty: Type::U16
+            }
+            .to_string(),
+            "70000 doesn't fit in u16"
+        );
+    }
+
+    #[test]
+    fn encodes_floats_as_they_are() {
+        assert_eq!(
+            encode(12.5, Type::F32, Order::Abcd),
+            Ok(vec![0x4148, 0x0000])
+        );
+        assert_eq!(
+            encode(12.5, Type::F32, Order::Dcba),
+            Ok(vec![0x0000, 0x4841])
+        );
+        let words = encode(f64::INFINITY, Type::F32, Order::Abcd).unwrap();
+        assert_eq!(decode(&words, Type::F32, Order::Abcd), Ok(f64::INFINITY));
+        let words = encode(0.1, Type::F64, Order::Cdab).unwrap();
+        assert_eq!(decode(&words, Type::F64, Order::Cdab), Ok(0.1));
+    }
+
+    #[test]
+    fn extracts_and_replaces_bits() {
+        assert_eq!(bits(0b1011_0100, 2, 3), 0b101);
+        assert_eq!(bits(0x8000, 15, 1), 1);
+        assert_eq!(bits(u64::MAX, 0, 64), u64::MAX);
+        assert_eq!(bits(0xff, 64, 4), 0);
+        assert_eq!(bits(0xff, 3, 0), 0);
+        assert_eq!(with_bits(0b1111_0000, 4, 2, 0), 0b1100_0000);
+        assert_eq!(with_bits(0, 8, 4, 0xff), 0x0f00);
+        assert_eq!(with_bits(0, 0, 64, 7), 7);
+        assert_eq!(with_bits(1, 64, 1, 1), 1);
+    }
+
+    #[test]
+    fn scales_both_ways() {
+        let scale = Scale {
+            factor: 0.1,
+            offset: -40.0,
+        };
+        assert!((scale.apply(650.0) - 25.0).abs() < 1e-9);
+        assert!((scale.remove(25.0) - 650.0).abs() < 1e-9);
+        assert_eq!(Scale::default().apply(3.0), 3.0);
+        assert_eq!(
+            Scale::power_of_ten(-2).map(|s| s.apply(1234.0)),
+            Some(12.34)
+        );
+        assert_eq!(Scale::power_of_ten(3).map(|s| s.apply(2.0)), Some(2000.0));
+        assert_eq!(Scale::power_of_ten(i16::MIN), None);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for ty in TYPES {
+            assert_eq!(Type::parse(ty.name()), Some(ty));
+        }
+        for order in ORDERS {
+            assert_eq!(Order::parse(order.name()), Some(order));
+        }
+        assert_eq!(Type::parse("f16"), None);
+        assert_eq!(Order::parse("ABCD"), None);
+    }
+}
@@ -0,0 +1,341 @@
+//! Shell completion scripts generated from the clap command tree.
+
+use clap::{Arg, Command, CommandFactory, ValueEnum};
+use std::fmt::Write;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Prints the completion script for `A` to stdout.
+pub fn print<A: CommandFactory>(shell: Shell, bin_name: &str) {
+    print!(
+        "{}",
+        generate(shell, crate::logging::command::<A>(), bin_name)
+    );
+}
+
+pub fn generate(shell: Shell, mut command: Command, bin_name: &str) -> String {
+    command.build();
+    let root = Node::new(&command, vec![bin_name.to_string()]);
+    match shell {
+        Shell::Bash => bash(&root, bin_name),
+        Shell::Zsh => zsh(&root, bin_name),
+        Shell::Fish => fish(&root, bin_name),
+    }
+}
+
+struct Flag {
+    short: Option<char>,
+    long: Option<String>,
+    help: String,
+    takes_value: bool,
+    values: Vec<String>,
+}
+
+struct Node {
+    path: Vec<String>,
+    about: String,
+    flags: Vec<Flag>,
+    positional_values: Vec<String>,
+    subcommands: Vec<Node>,
+}
+
+impl Node {
+    fn new(command: &Command, path: Vec<String>) -> Node {
+        let mut flags = Vec::new();
+        let mut positional_values = Vec::new();
+        for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+            if arg.is_positional() {
+                positional_values.extend(possible_values(arg));
+                continue;
+            }
+            flags.push(Flag {
+                short: arg.get_short(),
+                long: arg.get_long().map(str::to_string),
+                help: arg.get_help().unwrap_or("").to_string(),
+                takes_value: arg.is_takes_value_set(),
+                values: possible_values(arg),
+            });
+        }
+
+        let subcommands = command
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+            .map(|sub| {
+                let mut path = path.clone();
+                path.push(sub.get_name().to_string());
+                Node::new(sub, path)
+            })
+            .collect();
+
+        Node {
+            path,
+            about: command.get_about().unwrap_or("").to_string(),
+            flags,
+            positional_values,
+            subcommands,
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.path.last().map(String::as_str).unwrap_or("")
+    }
+
+    fn walk<'a>(&'a self, nodes: &mut Vec<&'a Node>) {
+        nodes.push(self);
+        for sub in &self.subcommands {
+            sub.walk(nodes);
+        }
+    }
+
+    fn words(&self) -> Vec<String> {
+        let mut words = Vec::new();
+        for flag in &self.flags {
+            if let Some(long) = &flag.long {
+                words.push(format!("--{long}"));
+            }
+            if let Some(short) = flag.short {
+                words.push(format!("-{short}"));
+            }
+        }
+        words.extend(self.subcommands.iter().map(|sub| sub.name().to_string()));
+        words.extend(self.positional_values.iter().cloned());
+        words
+    }
+}
+
+pub(crate) fn possible_values(arg: &Arg) -> Vec<String> {
+    if !arg.is_takes_value_set() {
+        return Vec::new();
+    }
+    arg.get_value_parser()
+        .possible_values()
+        .map(|values| {
+            values
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn function_name(path: &[String]) -> String {
+    let name: String = path
+        .join("__")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("_{name}")
+}
+
+fn single_quote(value: &str) -> String {
+    value.replace('\'', "'\\''")
+}
+
+fn bash(root: &Node, bin_name: &str) -> String {
+    let mut nodes = Vec::new();
+    root.walk(&mut nodes);
+    let mut out = String::new();
+    let function = function_name(&root.path);
+
+    let _ = writeln!(out, "{function}() {{");
+    let _ = writeln!(out, "    local cur prev path i");
+    let _ = writeln!(out, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(out, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(out, "    path=\"{}\"", root.path.join(":"));
+    let _ = writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do");
+    let _ = writeln!(out, "        case \"${{path}}:${{COMP_WORDS[i]}}\" in");
+    for node in nodes.iter().skip(1) {
+        let path = node.path.join(":");
+        let _ = writeln!(out, "            \"{path}\") path=\"{path}\" ;;");
+    }
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "    done");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "    case \"$path\" in");
+    for node in &nodes {
+        let _ = writeln!(out, "        \"{}\")", node.path.join(":"));
+        let with_values: Vec<&Flag> = node.flags.iter().filter(|f| f.takes_value).collect();
+        if !with_values.is_empty() {
+            let _ = writeln!(out, "            case \"$prev\" in");
+            for flag in with_values {
+                let mut names = Vec::new();
+                if let Some(long) = &flag.long {
+                    names.push(format!("--{long}"));
+                }
+                if let Some(short) = flag.short {
+                    names.push(format!("-{short}"));
+                }
+                let reply = if flag.values.is_empty() {
+                    "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+                } else {
+                    format!(
+                        "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                        flag.values.join(" ")
+                    )
+                };
+                let _ = writeln!(
+                    out,
+                    "                {}) {reply}; return 0 ;;",
+                    names.join("|")
+                );
+            }
+            let _ = writeln!(out, "            esac");
+        }
+        let _ = writeln!(
+            out,
+            "            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+            node.words().join(" ")
+        );
+        let _ = writeln!(out, "            ;;");
+    }
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(
+        out,
+        "complete -F {function} -o bashdefault -o default {bin_name}"
+    );
+    out
+}
+
+fn zsh(root: &Node, bin_name: &str) -> String {
+    let mut nodes = Vec::new();
+    root.walk(&mut nodes);
+    let mut out = String::new();
+    let _ = writeln!(out, "#compdef {bin_name}");
+    let _ = writeln!(out);
+
+    for node in &nodes {
+        let _ = writeln!(out, "{}() {{", function_name(&node.path));
+        let _ = writeln!(out, "    local context state state_descr line");
+        let _ = writeln!(out, "    typeset -A opt_args");
+        let _ = write!(out, "    _arguments -s -S");
+        for flag in &node.flags {
+            let help = single_quote(&flag.help.replace(['[', ']'], ""));
+            let action = if !flag.takes_value {
+                String::new()
+            } else if flag.values.is_empty() {
+                ":value:_default".to_string()
+            } else {
+                format!(":value:({})", flag.values.join(" "))
+            };
+            let mut specs = Vec::new();
+            if let Some(long) = &flag.long {
+                specs.push(format!("--{long}"));
+            }
+            if let Some(short) = flag.short {
+                specs.push(format!("-{short}"));
+            }
+            for spec in specs {
+                let _ = write!(out, " \\\n        '{spec}[{help}]{action}'");
+            }
+        }
+        if node.subcommands.is_empty() {
+            let _ = write!(out, " \\\n        '*:argument:_default'");
+            let _ = writeln!(out);
+        } else {
+            let _ = writeln!(out, " \\\n        '*::command:->command'");
+            let _ = writeln!(out);
+            let _ = writeln!(out, "    case $state in");
+            let _ = writeln!(out, "        command)");
+            let _ = writeln!(out, "            local -a commands");
+            let _ = writeln!(out, "            commands=(");
+            for sub in &node.subcommands {
+                let _ = writeln!(
+                    out,
+                    "                '{}:{}'",
+                    sub.name(),
+                    single_quote(&sub.about.replace(':', "\\:"))
+                );
+            }
+            let _ = writeln!(out, "            )");
+            // The first free word may be a positional argument, so look for a subcommand name
+            // anywhere before the cursor.
+            let _ = writeln!(out, "            local word");
+            let _ = writeln!(out, "            for word in ${{words[1,CURRENT-1]}}; do");
+            let _ = writeln!(out, "                case $word in");
+            for sub in &node.subcommands {
+                let _ = writeln!(
+                    out,
+                    "                    {}) local index=${{words[(i)$word]}}; words=(${{words[index,-1]}}); (( CURRENT -= index - 1 )); {}; return ;;",
+                    sub.name(),
+                    function_name(&sub.path)
+                );
+            }
+            let _ = writeln!(out, "                esac");
+            let _ = writeln!(out, "            done");
+            let _ = writeln!(out, "            _describe -t commands 'command' commands");
+            let _ = writeln!(out, "            ;;");
+            let _ = writeln!(out, "    esac");
+        }
+        let _ = writeln!(out, "}}");
+        let _ = writeln!(out);
+    }
+    let _ = writeln!(out, "{} \"$@\"", function_name(&root.path));
+    out
+}
+
+fn fish(root: &Node, bin_name: &str) -> String {
+    let mut nodes = Vec::new();
+    root.walk(&mut nodes);
+    let mut out = String::new();
+
+    for node in &nodes {
+        let condition = if node.path.len() == 1 {
+            "__fish_use_subcommand".to_string()
+        } else {
+            let siblings: Vec<&str> = node.subcommands.iter().map(|sub| sub.name()).collect();
+            if siblings.is_empty() {
+                format!("__fish_seen_subcommand_from {}", node.name())
+            } else {
+                format!(
+                    "__fish_seen_subcommand_from {}; and not __fish_seen_subcommand_from {}",
+                    node.name(),
+                    siblings.join(" ")
+                )
+            }
+        };
+
+        for flag in &node.flags {
+            let mut line = format!("complete -c {bin_name} -n '{condition}'");
+            if let Some(short) = flag.short {
+                let _ = write!(line, " -s {short}");
+            }
+            if let Some(long) = &flag.long {
+                let _ = write!(line, " -l {long}");
+            }
+            if flag.takes_value {
+                line.push_str(" -r");
+                if !flag.values.is_empty() {
+                    let _ = write!(line, " -f -a '{}'", flag.values.join(" "));
+                }
+            }
+            if !flag.help.is_empty() {
+                let _ = write!(line, " -d '{}'", single_quote(&flag.help));
+            }
+            let _ = writeln!(out, "{line}");
+        }
+        for sub in &node.subcommands {
+            let mut line = format!(
+                "complete -c {bin_name} -n '{condition}' -f -a {}",
+                sub.name()
+            );
+            if !sub.about.is_empty() {
+                let _ = write!(line, " -d '{}'", single_quote(&sub.about));
+            }
+            let _ = writeln!(out, "{line}");
+        }
+        if !node.positional_values.is_empty() {
+            let _ = writeln!(
+                out,
+                "complete -c {bin_name} -n '{condition}' -f -a '{}'",
+                node.positional_values.join(" ")
+            );
+        }
+    }
+    out
+}
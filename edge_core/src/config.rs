@@ -0,0 +1,857 @@
+//! Named connection profiles from `~/.config/edge_tools/config.toml` (or `$EDGE_CONFIG`):
+//!
+//! ```toml
+//! [profiles.site-a]
+//! address = "nats://10.20.0.5:4222"
+//! username = "edge"
+//! password = "hunter2"
+//! tls_ca = "/etc/edge/site-a-ca.pem"
+//! unit_id = 3
+//! retries = 5
+//! retry_delay = "500ms"
+//! cache_ttl = "2s"  # reads of the same registers within 2s share one answer
+//!
+//! [profiles.plc-3]
+//! address = "10.0.0.5"
+//! secondary = "10.0.0.6"  # the standby CPU, used while 10.0.0.5 doesn't answer
+//! failback = "10m"        # back to 10.0.0.5 this long after failing over, or "never"
+//!
+//! [profiles.site-b]
+//! username = "edge"
+//! password_file = "/run/secrets/site-b"  # or token_file
+//!
+//! [profiles.site-c]
+//! username = "edge"
+//! keyring = "edge_tools"  # password under this service and the username as account
+//!
+//! [profiles.site-d]
+//! username = "edge"
+//! password = "age:YWdlLWVuY3J5cHRpb24ub3JnL3Yx..."  # from `edge secret encrypt`
+//!
+//! [profiles.site-e]
+//! address = "tls://nats.site-e:4222"
+//! creds = "/etc/edge/site-e.creds"  # NATS 2.x operator mode; or nkey = "SUA..."
+//!
+//! [encryption]
+//! recipients = ["age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p"]
+//! identity = "/etc/edge_tools/identity.txt"
+//!
+//! [limits]
+//! queue = 256
+//! max_payload = "64KiB"
+//! overflow = "drop-oldest"
+//!
+//! [jobs.plant-poll]
+//! every = "5m"
+//! run = "modbus 10.0.0.5 read-register -r 40070 --output json"
+//!
+//! [jobs.rotate-logs]
+//! cron = "30 2 * * *"
+//! shell = "logrotate /etc/logrotate.d/edge"
+//! timeout = "10m"
+//!
+//! [writes]
+//! modbus = ["10.0.0.5 holding 40236"]
+//! nats = ["plant.setpoints.>"]
+//!
+//! [audit]
+//! file = "/var/log/edge/audit.jsonl"
+//! ```
+//!
+//! With `keyring` and no username, the token is looked up under the `token` account. Values
+//! given on the command line always win over the profile. Tools ignore keys they don't use;
+//! `edge config check` reports the ones no tool uses (see [`check`]).
+//!
+//! `[encryption]` lists who `.age` captures are encrypted to, and the identity file that
+//! decrypts them and `age:` passwords and tokens (`identity.txt` next to this file by
+//! default). See [`crate::age`].
+//!
+//! `[limits]` bounds what subscriptions and the bridge hold in memory on this machine; see
+//! [`crate::buffer`].
+//!
+//! `[writes]` refuses writes on this machine, or lists where tools may write; see
+//! [`crate::writes`].
+//!
+//! `[audit]` is where tools log what they write, and optionally a NATS server to mirror it to;
+//! see [`crate::audit`].
+//!
+//! `[jobs.NAME]` tables are what `edge schedule` runs: `every` so long or at the `cron` times
+//! (see [`crate::schedule`]), either an edge command line (`run`) or a shell command
+//! (`shell`), killed after `timeout` if one is given.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use crate::age::{self, Identity, Recipient};
+use crate::audit;
+use crate::auth::Credentials;
+use crate::buffer::{self, Limits, Overflow};
+use crate::failover::Failback;
+use crate::rate::Rate;
+use crate::retry::Retry;
+use crate::schedule::{self, Cron, Schedule};
+use crate::secret::{self, SecretError};
+use crate::toml::{self, Value};
+use crate::writes::{self, Policy};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NoConfigDirectory,
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::ParseError),
+    UnknownProfile(PathBuf, String),
+    InvalidValue {
+        profile: String,
+        key: String,
+        expected: &'static str,
+    },
+    Secret(String, SecretError),
+    Encryption(String),
+    Limits(String),
+    Writes(String),
+    Audit(String),
+    Job(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoConfigDirectory => {
+                write!(f, "neither XDG_CONFIG_HOME nor HOME is set")
+            }
+            ConfigError::Read(path, err) => write!(f, "unable to read {}: {err}", path.display()),
+            ConfigError::Parse(path, err) => write!(f, "{}: {err}", path.display()),
+            ConfigError::UnknownProfile(path, name) => {
+                write!(f, "no profile named {name} in {}", path.display())
+            }
+            ConfigError::InvalidValue {
+                profile,
+                key,
+                expected,
+            } => write!(f, "profile {profile}: {key} must be {expected}"),
+            ConfigError::Secret(profile, err) => write!(f, "profile {profile}: {err}"),
+            ConfigError::Encryption(message) => write!(f, "encryption: {message}"),
+            ConfigError::Limits(message) => write!(f, "limits: {message}"),
+            ConfigError::Writes(message) => write!(f, "writes: {message}"),
+            ConfigError::Audit(message) => write!(f, "audit: {message}"),
+            ConfigError::Job(name, message) => write!(f, "job {name}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// TLS material for a connection; paths are used as given.
+#[derive(Clone, Default)]
+pub struct Tls {
+    pub ca: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub required: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct Profile {
+    pub name: String,
+    values: BTreeMap<String, Value>,
+}
+
+impl Profile {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn string(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(self.invalid(key, "a string")),
+        }
+    }
+
+    pub fn integer<T: TryFrom<i64>>(&self, key: &str) -> Result<Option<T>, ConfigError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(value)) => T::try_from(*value)
+                .map(Some)
+                .map_err(|_| self.invalid(key, "an integer in range")),
+            Some(_) => Err(self.invalid(key, "an integer")),
+        }
+    }
+
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.values.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.invalid(key, "true or false")),
+        }
+    }
+
+    pub fn address(&self) -> Result<Option<String>, ConfigError> {
+        self.string("address")
+    }
+
+    /// The standby twin of `address`, for devices that come in redundant pairs; see
+    /// [`crate::failover`].
+    pub fn secondary(&self) -> Result<Option<String>, ConfigError> {
+        self.string("secondary")
+    }
+
+    /// Credentials, with the password or token read from a file or the keyring when the
+    /// profile says so.
+    pub fn credentials(&self) -> Result<Credentials, ConfigError> {
+        let error = |err| ConfigError::Secret(self.name.clone(), err);
+        let password_file = self.string("password_file")?.map(PathBuf::from);
+        let token_file = self.string("token_file")?.map(PathBuf::from);
+        let creds = self.string("creds")?.map(PathBuf::from);
+        let mut credentials = Credentials {
+            username: self.string("username")?,
+            password: self.secret("password")?,
+            token: self.secret("token")?,
+            creds: None,
+            nkey: self.secret("nkey")?,
+        }
+        .or_files(password_file.as_deref(), token_file.as_deref())
+        .and_then(|credentials| credentials.or_creds_file(creds.as_deref()))
+        .map_err(error)?;
+        if let Some(service) = self.string("keyring")? {
+            match &credentials.username {
+                Some(username) if credentials.password.is_none() => {
+                    credentials.password =
+                        Some(secret::keyring(&service, username).map_err(error)?);
+                }
+                None if credentials.token.is_none()
+                    && credentials.creds.is_none()
+                    && credentials.nkey.is_none() =>
+                {
+                    credentials.token = Some(secret::keyring(&service, "token").map_err(error)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(credentials)
+    }
+
+    /// A string that may be encrypted, as `age:` and the base64 of an age file.
+    pub fn secret(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        let value = match self.string(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let encrypted = match value.strip_prefix("age:") {
+            Some(encrypted) => encrypted,
+            None => return Ok(Some(value)),
+        };
+        let error = |err| ConfigError::Secret(self.name.clone(), err);
+        let encrypted = BASE64
+            .decode(encrypted.trim())
+            .map_err(|_| self.invalid(key, "age: followed by base64"))?;
+        let identities = encryption()?.identities()?;
+        let plain = age::decrypt(&encrypted, &identities)
+            .map_err(|err| error(SecretError::Decrypt(err)))?;
+        String::from_utf8(plain)
+            .map(Some)
+            .map_err(|_| self.invalid(key, "encrypted text"))
+    }
+
+    pub fn tls(&self) -> Result<Tls, ConfigError> {
+        Ok(Tls {
+            ca: self.string("tls_ca")?.map(PathBuf::from),
+            cert: self.string("tls_cert")?.map(PathBuf::from),
+            key: self.string("tls_key")?.map(PathBuf::from),
+            required: self.boolean("tls_required")?.unwrap_or(false),
+        })
+    }
+
+    /// The retry policy, with the defaults for whatever the profile leaves out.
+    pub fn retry(&self) -> Result<Retry, ConfigError> {
+        let delay = match self.string("retry_delay")? {
+            Some(delay) => Some(
+                humantime::parse_duration(&delay)
+                    .map_err(|_| self.invalid("retry_delay", "a duration such as 500ms"))?,
+            ),
+            None => None,
+        };
+        Ok(Retry::default().with(self.integer("retries")?, delay))
+    }
+
+    /// How long values read with this profile may be reused, if at all.
+    pub fn cache_ttl(&self) -> Result<Option<Duration>, ConfigError> {
+        match self.string("cache_ttl")? {
+            Some(ttl) => humantime::parse_duration(&ttl)
+                .map(Some)
+                .map_err(|_| self.invalid("cache_ttl", "a duration such as 2s")),
+            None => Ok(None),
+        }
+    }
+
+    /// The failback policy for the profile's `secondary`.
+    pub fn failback(&self) -> Result<Failback, ConfigError> {
+        match self.string("failback")? {
+            Some(failback) => failback
+                .parse()
+                .map_err(|_| self.invalid("failback", "a duration such as 5m or never")),
+            None => Ok(Failback::default()),
+        }
+    }
+
+    /// The most requests or messages a second to send with this profile, if it limits them.
+    pub fn max_rate(&self) -> Result<Option<Rate>, ConfigError> {
+        match self.string("max_rate")? {
+            Some(rate) => rate
+                .parse()
+                .map(Some)
+                .map_err(|_| self.invalid("max_rate", "a rate such as 10/s")),
+            None => Ok(None),
+        }
+    }
+
+    fn invalid(&self, key: &str, expected: &'static str) -> ConfigError {
+        ConfigError::InvalidValue {
+            profile: self.name.clone(),
+            key: key.to_string(),
+            expected,
+        }
+    }
+}
+
+impl Credentials {
+    /// Fills in whatever the command line left out from `profile`. A creds file or NKey on the
+    /// command line replaces whatever the profile logs in with, and user names, passwords and
+    /// tokens replace its creds file and NKey.
+    pub fn or_profile(self, profile: &Profile) -> Result<Credentials, ConfigError> {
+        if self.creds.is_some() || self.nkey.is_some() {
+            return Ok(self);
+        }
+        let defaults = profile.credentials()?;
+        let given = self.username.is_some() || self.password.is_some() || self.token.is_some();
+        Ok(Credentials {
+            username: self.username.or(defaults.username),
+            password: self.password.or(defaults.password),
+            token: self.token.or(defaults.token),
+            creds: defaults.creds.filter(|_| !given),
+            nkey: defaults.nkey.filter(|_| !given),
+        })
+    }
+}
+
+/// `$EDGE_CONFIG` if set, otherwise `edge_tools/config.toml` under the XDG config directory, or
+/// under `%APPDATA%` on Windows.
+pub fn default_path() -> Result<PathBuf, ConfigError> {
+    if let Some(path) = std::env::var_os("EDGE_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+    #[cfg(windows)]
+    if let Some(dir) = std::env::var_os("APPDATA").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir).join("edge_tools").join("config.toml"));
+    }
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => return Err(ConfigError::NoConfigDirectory),
+        },
+    };
+    Ok(base.join("edge_tools").join("config.toml"))
+}
+
+/// Loads the named profile, or an empty one when no profile was asked for.
+pub fn load_profile(name: Option<&str>) -> Result<Profile, ConfigError> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(Profile::default()),
+    };
+    let path = default_path()?;
+    let mut document = read(&path)?;
+
+    let key = vec!["profiles".to_string(), name.to_string()];
+    match document.remove(&key) {
+        Some(values) => {
+            log::debug!("Using profile {name} from {}", path.display());
+            Ok(Profile {
+                name: name.to_string(),
+                values,
+            })
+        }
+        None => Err(ConfigError::UnknownProfile(path, name.to_string())),
+    }
+}
+
+/// Every profile in the config file, by name; none without a config file.
+pub fn profiles() -> Result<Vec<Profile>, ConfigError> {
+    let path = default_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(read(&path)?
+        .into_iter()
+        .filter_map(|(key, values)| match key.as_slice() {
+            [profiles, name] if profiles == "profiles" => Some(Profile {
+                name: name.clone(),
+                values,
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Who captures are encrypted to and what decrypts them, from `[encryption]`.
+pub struct Encryption {
+    pub recipients: Vec<Recipient>,
+    pub identity: PathBuf,
+}
+
+impl Encryption {
+    /// The identities in the identity file.
+    pub fn identities(&self) -> Result<Vec<Identity>, ConfigError> {
+        let contents = secret::read_file(&self.identity)
+            .map_err(|err| ConfigError::Encryption(err.to_string()))?;
+        Identity::parse_file(&contents)
+            .map_err(|err| ConfigError::Encryption(format!("{}: {err}", self.identity.display())))
+    }
+}
+
+/// The `[encryption]` table; without a config file, no recipients and the default identity.
+pub fn encryption() -> Result<Encryption, ConfigError> {
+    let path = default_path()?;
+    let identity = path.with_file_name("identity.txt");
+    let mut document = match path.exists() {
+        true => read(&path)?,
+        false => toml::Document::new(),
+    };
+    let values = document
+        .remove(&vec!["encryption".to_string()])
+        .unwrap_or_default();
+    let invalid =
+        |key: &str, expected: &str| ConfigError::Encryption(format!("{key} must be {expected}"));
+    let recipients = match values.get("recipients") {
+        None => Vec::new(),
+        Some(Value::Array(recipients)) => recipients
+            .iter()
+            .map(|recipient| match recipient {
+                Value::String(recipient) => {
+                    Recipient::parse(recipient).map_err(ConfigError::Encryption)
+                }
+                _ => Err(invalid("recipients", "a list of age1... strings")),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(invalid("recipients", "a list of age1... strings")),
+    };
+    let identity = match values.get("identity") {
+        None => identity,
+        Some(Value::String(path)) => PathBuf::from(path),
+        Some(_) => return Err(invalid("identity", "a path")),
+    };
+    Ok(Encryption {
+        recipients,
+        identity,
+    })
+}
+
+/// The `[limits]` table, with the defaults for whatever it leaves out or without a config
+/// file, even where there's nowhere to look for one.
+pub fn limits() -> Result<Limits, ConfigError> {
+    let path = match default_path() {
+        Ok(path) => path,
+        Err(ConfigError::NoConfigDirectory) => return Ok(Limits::default()),
+        Err(err) => return Err(err),
+    };
+    let mut document = match path.exists() {
+        true => read(&path)?,
+        false => toml::Document::new(),
+    };
+    let values = document
+        .remove(&vec!["limits".to_string()])
+        .unwrap_or_default();
+    let invalid =
+        |key: &str, expected: &str| ConfigError::Limits(format!("{key} must be {expected}"));
+    let defaults = Limits::default();
+    let queue = match values.get("queue") {
+        None => defaults.queue,
+        Some(Value::Integer(queue)) if *queue > 0 => *queue as usize,
+        Some(_) => return Err(invalid("queue", "a positive integer")),
+    };
+    let max_payload = match values.get("max_payload") {
+        None => None,
+        Some(Value::Integer(size)) if *size > 0 => Some(*size as usize),
+        Some(Value::String(size)) => Some(buffer::parse_size(size).map_err(ConfigError::Limits)?),
+        Some(_) => return Err(invalid("max_payload", "a size such as 64KiB")),
+    };
+    let overflow = match values.get("overflow") {
+        None => defaults.overflow,
+        Some(Value::String(overflow)) => overflow.parse().map_err(ConfigError::Limits)?,
+        Some(_) => return Err(invalid("overflow", Overflow::NAMES)),
+    };
+    Ok(Limits {
+        queue,
+        max_payload,
+        overflow,
+    })
+}
+
+/// The `[writes]` table; anything may be written without one, or without a config file.
+pub fn writes() -> Result<Policy, ConfigError> {
+    let path = match default_path() {
+        Ok(path) => path,
+        Err(ConfigError::NoConfigDirectory) => return Ok(Policy::default()),
+        Err(err) => return Err(err),
+    };
+    let mut document = match path.exists() {
+        true => read(&path)?,
+        false => toml::Document::new(),
+    };
+    let values = document
+        .remove(&vec!["writes".to_string()])
+        .unwrap_or_default();
+    let list = |key: &str| -> Result<Option<Vec<String>>, ConfigError> {
+        match values.get(key) {
+            None => Ok(None),
+            Some(value) => strings(value)
+                .map(Some)
+                .map_err(|message| ConfigError::Writes(format!("{key} {message}"))),
+        }
+    };
+    let modbus = match list("modbus")? {
+        None => None,
+        Some(rules) => Some(
+            rules
+                .iter()
+                .map(|rule| rule.parse().map_err(ConfigError::Writes))
+                .collect::<Result<_, _>>()?,
+        ),
+    };
+    let read_only = match values.get("read_only") {
+        None => false,
+        Some(Value::Boolean(read_only)) => *read_only,
+        Some(_) => {
+            return Err(ConfigError::Writes(
+                "read_only must be true or false".to_string(),
+            ))
+        }
+    };
+    Ok(Policy {
+        read_only,
+        modbus,
+        nats: list("nats")?,
+        mqtt: list("mqtt")?,
+    })
+}
+
+/// The `[audit]` table, None without one or without a config file.
+pub fn audit() -> Result<Option<audit::Settings>, ConfigError> {
+    let path = match default_path() {
+        Ok(path) => path,
+        Err(ConfigError::NoConfigDirectory) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+    let Some(values) = read(&path)?.remove(&vec!["audit".to_string()]) else {
+        return Ok(None);
+    };
+    let text = |key: &str| match values.get(key) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(ConfigError::Audit(format!("{key} must be a string"))),
+    };
+    let file = text("file")?.ok_or_else(|| ConfigError::Audit("file is required".to_string()))?;
+    let subject = text("subject")?.unwrap_or_else(|| "edge.audit".to_string());
+    if subject.is_empty() || subject.contains(|c: char| c.is_whitespace() || c == '*' || c == '>') {
+        return Err(ConfigError::Audit(format!("invalid subject `{subject}`")));
+    }
+    Ok(Some(audit::Settings {
+        file: PathBuf::from(file),
+        nats: text("nats")?,
+        subject,
+    }))
+}
+
+fn strings(value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                Value::String(value) => Ok(value.clone()),
+                _ => Err("must be a list of strings".to_string()),
+            })
+            .collect(),
+        _ => Err("must be a list of strings".to_string()),
+    }
+}
+
+/// What a job runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    // An edge command line, split the way `edge run` splits a script line.
+    Edge(String),
+    Shell(String),
+}
+
+/// A `[jobs.NAME]` table.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub name: String,
+    pub schedule: Schedule,
+    pub command: Command,
+    pub timeout: Option<Duration>,
+}
+
+/// The `[jobs.NAME]` tables, by name; none without a config file.
+pub fn jobs() -> Result<Vec<Job>, ConfigError> {
+    let path = match default_path() {
+        Ok(path) => path,
+        Err(ConfigError::NoConfigDirectory) => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut jobs = Vec::new();
+    for (table, values) in read(&path)? {
+        let [jobs_table, name] = table.as_slice() else {
+            continue;
+        };
+        if jobs_table != "jobs" {
+            continue;
+        }
+        let invalid = |message: String| ConfigError::Job(name.clone(), message);
+        let string = |key: &str| match values.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.as_str())),
+            Some(_) => Err(invalid(format!("{key} must be a string"))),
+        };
+        let schedule = schedule::parse(string("every")?, string("cron")?).map_err(invalid)?;
+        let command = match (string("run")?, string("shell")?) {
+            (Some(line), None) => Command::Edge(line.to_string()),
+            (None, Some(line)) => Command::Shell(line.to_string()),
+            _ => return Err(invalid("expected either run or shell".to_string())),
+        };
+        let timeout = match string("timeout")? {
+            None => None,
+            Some(timeout) => Some(
+                humantime::parse_duration(timeout)
+                    .map_err(|err| invalid(format!("timeout: {err}")))?,
+            ),
+        };
+        jobs.push(Job {
+            name: name.clone(),
+            schedule,
+            command,
+            timeout,
+        });
+    }
+    Ok(jobs)
+}
+
+/// `secret` as a profile value that [`Profile::secret`] decrypts.
+pub fn encrypt_secret(secret: &str, recipients: &[Recipient]) -> std::io::Result<String> {
+    let encrypted = age::encrypt(secret.as_bytes(), recipients)?;
+    Ok(format!("age:{}", BASE64.encode(encrypted)))
+}
+
+fn read(path: &Path) -> Result<toml::Document, ConfigError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+    toml::parse(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+}
+
+/// Something `check` found wrong, on the line it's on when there is one.
+pub struct Problem {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// What a key has to be.
+enum Expected {
+    Text,
+    // A string that may be an `age:` value.
+    Secret,
+    Integer(i64),
+    Boolean,
+    Duration,
+    Rate,
+    Failback,
+    // Bytes, or a string such as 64KiB.
+    Size,
+    Overflow,
+    Cron,
+    Strings,
+    // Strings that are [`writes::Rule`]s.
+    WriteRules,
+}
+
+impl Expected {
+    fn check(&self, value: &Value) -> Result<(), String> {
+        match (self, value) {
+            (Expected::Text, Value::String(_)) | (Expected::Boolean, Value::Boolean(_)) => Ok(()),
+            (Expected::Secret, Value::String(value)) => match value.strip_prefix("age:") {
+                Some(encrypted) => match BASE64.decode(encrypted.trim()) {
+                    Ok(encrypted) if age::is_encrypted(&encrypted) => Ok(()),
+                    _ => Err("must be age: followed by the base64 of an age file".to_string()),
+                },
+                None => Ok(()),
+            },
+            (Expected::Integer(max), Value::Integer(value)) if (0..=*max).contains(value) => Ok(()),
+            (Expected::Integer(max), _) => Err(format!("must be an integer from 0 to {max}")),
+            (Expected::Duration, Value::String(value))
+                if humantime::parse_duration(value).is_ok() =>
+            {
+                Ok(())
+            }
+            (Expected::Duration, _) => Err("must be a duration such as 500ms".to_string()),
+            (Expected::Rate, Value::String(value)) if value.parse::<Rate>().is_ok() => Ok(()),
+            (Expected::Rate, _) => Err("must be a rate such as 10/s or 600/min".to_string()),
+            (Expected::Failback, Value::String(value)) if value.parse::<Failback>().is_ok() => {
+                Ok(())
+            }
+            (Expected::Failback, _) => Err("must be a duration such as 5m or never".to_string()),
+            (Expected::Size, Value::Integer(value)) if *value > 0 => Ok(()),
+            (Expected::Size, Value::String(value)) if buffer::parse_size(value).is_ok() => Ok(()),
+            (Expected::Size, _) => Err("must be a size such as 64KiB".to_string()),
+            (Expected::Overflow, Value::String(value)) if value.parse::<Overflow>().is_ok() => {
+                Ok(())
+            }
+            (Expected::Overflow, _) => Err(format!("must be {}", Overflow::NAMES)),
+            (Expected::Cron, Value::String(value)) => value.parse::<Cron>().map(|_| ()),
+            (Expected::Cron, _) => {
+                Err("must be a cron expression such as \"0 * * * *\"".to_string())
+            }
+            (Expected::Strings, _) => strings(value).map(|_| ()),
+            (Expected::WriteRules, _) => strings(value)?
+                .iter()
+                .try_for_each(|rule| rule.parse::<writes::Rule>().map(|_| ())),
+            (Expected::Boolean, _) => Err("must be true or false".to_string()),
+            (Expected::Text | Expected::Secret, _) => Err("must be a string".to_string()),
+        }
+    }
+}
+
+/// Every key a profile can set.
+const PROFILE_KEYS: [(&str, Expected); 20] = [
+    ("address", Expected::Text),
+    ("secondary", Expected::Text),
+    ("failback", Expected::Failback),
+    ("username", Expected::Text),
+    ("password", Expected::Secret),
+    ("password_file", Expected::Text),
+    ("token", Expected::Secret),
+    ("token_file", Expected::Text),
+    ("keyring", Expected::Text),
+    ("creds", Expected::Text),
+    ("nkey", Expected::Secret),
+    ("tls_ca", Expected::Text),
+    ("tls_cert", Expected::Text),
+    ("tls_key", Expected::Text),
+    ("tls_required", Expected::Boolean),
+    ("unit_id", Expected::Integer(u8::MAX as i64)),
+    ("retries", Expected::Integer(u32::MAX as i64)),
+    ("retry_delay", Expected::Duration),
+    ("max_rate", Expected::Rate),
+    ("cache_ttl", Expected::Duration),
+];
+
+/// Checks a config file against what profiles and `[encryption]` take, so a misspelt key is
+/// caught before a tool quietly ignores it. Only a malformed file is an error; everything
+/// else wrong is a problem, with its line.
+pub fn check(contents: &str) -> Result<Vec<Problem>, toml::ParseError> {
+    let (document, lines) = toml::parse_with_lines(contents)?;
+    let mut problems = Vec::new();
+    let mut problem = |path: Vec<String>, message: &str| {
+        problems.push(Problem {
+            line: lines.get(&path).copied(),
+            message: format!("{}: {message}", path.join(".")),
+        })
+    };
+    for (table, values) in &document {
+        let path = |key: &str| [table.as_slice(), &[key.to_string()]].concat();
+        let keys: &[(&str, Expected)] = match table.as_slice() {
+            [profiles, _] if profiles == "profiles" => &PROFILE_KEYS,
+            [encryption] if encryption == "encryption" => {
+                &[("recipients", Expected::Text), ("identity", Expected::Text)]
+            }
+            [limits] if limits == "limits" => &[
+                ("queue", Expected::Integer(u32::MAX as i64)),
+                ("max_payload", Expected::Size),
+                ("overflow", Expected::Overflow),
+            ],
+            [writes] if writes == "writes" => &[
+                ("read_only", Expected::Boolean),
+                ("modbus", Expected::WriteRules),
+                ("nats", Expected::Strings),
+                ("mqtt", Expected::Strings),
+            ],
+            [audit] if audit == "audit" => &[
+                ("file", Expected::Text),
+                ("nats", Expected::Text),
+                ("subject", Expected::Text),
+            ],
+            [jobs, _] if jobs == "jobs" => &[
+                ("every", Expected::Duration),
+                ("cron", Expected::Cron),
+                ("run", Expected::Text),
+                ("shell", Expected::Text),
+                ("timeout", Expected::Duration),
+            ],
+            [] => &[],
+            [profiles] if profiles == "profiles" => &[],
+            [jobs] if jobs == "jobs" => &[],
+            _ => {
+                problem(
+                    table.clone(),
+                    "unknown table, expected [profiles.NAME], [encryption], [limits], [writes], [audit] or [jobs.NAME]",
+                );
+                continue;
+            }
+        };
+        for (key, value) in values {
+            let checked = match keys.iter().find(|(name, _)| name == key) {
+                None => Err("unknown key".to_string()),
+                Some(("recipients", _)) => match value {
+                    Value::Array(recipients) => {
+                        recipients.iter().try_for_each(|recipient| match recipient {
+                            Value::String(recipient) => Recipient::parse(recipient).map(|_| ()),
+                            _ => Err("must be a list of age1... strings".to_string()),
+                        })
+                    }
+                    _ => Err("must be a list of age1... strings".to_string()),
+                },
+                Some((_, expected)) => expected.check(value),
+            };
+            if let Err(message) = checked {
+                problem(path(key), &message);
+            }
+        }
+        for (direct, file) in [
+            ("password", "password_file"),
+            ("token", "token_file"),
+            ("nkey", "creds"),
+        ] {
+            if values.contains_key(direct) && values.contains_key(file) {
+                problem(path(file), &format!("expected either {direct} or {file}"));
+            }
+        }
+        if matches!(table.as_slice(), [jobs, _] if jobs == "jobs") {
+            for (first, second) in [("every", "cron"), ("run", "shell")] {
+                match (values.contains_key(first), values.contains_key(second)) {
+                    (true, true) => problem(
+                        path(second),
+                        &format!("expected either {first} or {second}"),
+                    ),
+                    (false, false) => problem(
+                        table.clone(),
+                        &format!("expected either {first} or {second}"),
+                    ),
+                    _ => {}
+                }
+            }
+        }
+    }
+    problems.sort_by_key(|problem| problem.line);
+    Ok(problems)
+}
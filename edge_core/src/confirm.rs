@@ -0,0 +1,23 @@
+//! Confirmation before writes that change a live machine. Only asked when someone is at the
+//! terminal: scripts and pipes go ahead as before, and `--yes` skips the question.
+
+use std::io::{BufRead, IsTerminal, Write};
+
+use crate::exit::{self, Code};
+
+/// Asks `question` on the terminal and ends the process unless the answer is yes.
+pub fn confirm(question: &str, yes: bool) {
+    if yes || !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return;
+    }
+    eprint!("{question} [y/N] ");
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        answer.clear();
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => {}
+        _ => exit::fatal_with(Code::Failure, "Aborted, nothing was written."),
+    }
+}
@@ -0,0 +1,57 @@
+//! `--dry-run`: a write prints what it would send, down to the bytes on the wire, and leaves
+//! the device or broker alone.
+
+use crate::output::{Output, Record, Value};
+
+/// A write that wasn't made.
+pub struct Write<'a> {
+    // Where it would have gone: a device address, a server or a chip.
+    pub target: &'a str,
+    // What it would have done, e.g. `write single register (0x06)`.
+    pub function: &'a str,
+    // The request as named values, e.g. `address`, `value`.
+    pub details: Record,
+    // The encoded request, when the write goes over the wire.
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl Write<'_> {
+    pub fn print(&self, out: &Output) {
+        let mut record = Record::new()
+            .field("dry_run", true)
+            .field("target", self.target)
+            .field("function", self.function);
+        for (name, value) in self.details.fields() {
+            record.push(name, value.clone());
+        }
+        if let Some(bytes) = &self.bytes {
+            record.push("bytes", hex(bytes));
+        }
+        out.record(&record, || {
+            println!("dry run: {} to {}", self.function, self.target);
+            let details: Vec<String> = self
+                .details
+                .fields()
+                .map(|(name, value)| match value {
+                    Value::String(value) => format!("{name} {value}"),
+                    other => format!("{name} {}", crate::output::json(other)),
+                })
+                .collect();
+            if !details.is_empty() {
+                println!("  {}", details.join(", "));
+            }
+            if let Some(bytes) = &self.bytes {
+                println!("  {}", hex(bytes));
+            }
+        });
+    }
+}
+
+/// Bytes as spaced hex, e.g. `00 01 00 00`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
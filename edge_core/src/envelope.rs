@@ -0,0 +1,260 @@
+//! One message format every tool can write with `--output envelope` and the writing commands
+//! read back on stdin, so the tools compose through pipes into ad-hoc bridges:
+//!
+//! ```text
+//! $ modbus 10.0.0.5 --output envelope read-register -r 100 -k holding -w \
+//!     | nats nats://broker:4222 publish --envelopes -s 'site.inverter3.{topic}'
+//! ```
+//!
+//! Each envelope is one JSON line:
+//!
+//! ```text
+//! {"source":"10.0.0.5:502","topic":"holding 100","payload":215,"timestamp":"2026-10-14T08:32:30.412Z","quality":"good"}
+//! ```
+//!
+//! `source` is where the value came from, `topic` where on the source (a register, subject or
+//! topic), `payload` any JSON value, `timestamp` RFC 3339 and `quality` one of `good`, `stale`,
+//! `comm-fail`, `out-of-range` or `bad`. A `unit`, e.g. `"kWh"`, follows the payload when the
+//! value has one, and a `source_timestamp` follows the timestamp when the device or an
+//! upstream tool said when the value was taken. Only `payload` is required when reading them;
+//! either timestamp may also be seconds since the epoch.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+
+use crate::output::{self, Record, Value};
+use crate::point::{Point, Quality};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub source: String,
+    pub topic: String,
+    pub payload: Value,
+    pub unit: Option<String>,
+    pub timestamp: SystemTime,
+    pub source_timestamp: Option<SystemTime>,
+    pub quality: Quality,
+}
+
+impl Envelope {
+    /// A good value from `source` at `topic`, stamped now.
+    pub fn new(source: &str, topic: &str, payload: impl Into<Value>) -> Envelope {
+        Envelope {
+            source: source.to_string(),
+            topic: topic.to_string(),
+            payload: payload.into(),
+            unit: None,
+            timestamp: SystemTime::now(),
+            source_timestamp: None,
+            quality: Quality::Good,
+        }
+    }
+
+    /// Any tool result as an envelope from `source`. Fields named like the envelope's are
+    /// used as they are; otherwise the topic is the first of `topic`, `subject`, `point`,
+    /// `address` or `register`, and the payload the first of `payload`, `value` or `values`,
+    /// or the rest of the record when it has none of those.
+    pub fn from_record(source: &str, record: &Record) -> Envelope {
+        let text = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| match record.get(name)? {
+                    Value::String(text) => Some(text.clone()),
+                    Value::Null => None,
+                    other => Some(output::json(other)),
+                })
+                .unwrap_or_default()
+        };
+        let source = match text(&["source"]) {
+            from if from.is_empty() => source.to_string(),
+            from => from,
+        };
+        let topic = text(&["topic", "subject", "point", "address", "register"]);
+        let payload = ["payload", "value", "values"]
+            .iter()
+            .find_map(|name| record.get(name).cloned())
+            .unwrap_or_else(|| {
+                let mut rest = Record::new();
+                for (name, value) in record.fields() {
+                    if !matches!(
+                        name,
+                        "source" | "topic" | "unit" | "timestamp" | "source_timestamp" | "quality"
+                    ) {
+                        rest.push(name, value.clone());
+                    }
+                }
+                Value::Record(rest)
+            });
+        let timestamp = ["timestamp", "time"]
+            .iter()
+            .find_map(|name| record.get(name).and_then(time))
+            .unwrap_or_else(SystemTime::now);
+        let source_timestamp = ["source_timestamp", "source_time"]
+            .iter()
+            .find_map(|name| record.get(name).and_then(time));
+        let quality = match record.get("quality") {
+            Some(Value::String(name)) => Quality::parse(name).unwrap_or_default(),
+            _ => Quality::Good,
+        };
+        let unit = match record.get("unit") {
+            Some(Value::String(unit)) => Some(unit.clone()),
+            _ => None,
+        };
+        Envelope {
+            source,
+            topic,
+            payload,
+            unit,
+            timestamp,
+            source_timestamp,
+            quality,
+        }
+    }
+
+    /// Reads one envelope line.
+    pub fn parse(line: &str) -> Result<Envelope, String> {
+        let record = match crate::json::parse(line) {
+            Ok(Value::Record(record)) => record,
+            Ok(_) => return Err("expected a JSON object".to_string()),
+            Err(err) => return Err(format!("invalid JSON {err}")),
+        };
+        let text = |name: &str| match record.get(name) {
+            None | Some(Value::Null) => Ok(String::new()),
+            Some(Value::String(text)) => Ok(text.clone()),
+            Some(_) => Err(format!("`{name}` must be a string")),
+        };
+        let payload = record
+            .get("payload")
+            .cloned()
+            .ok_or_else(|| "no `payload`".to_string())?;
+        let timestamp = match record.get("timestamp") {
+            None | Some(Value::Null) => SystemTime::now(),
+            Some(value) => time(value).ok_or_else(|| {
+                format!(
+                    "invalid `timestamp` {}, expected RFC 3339",
+                    output::json(value)
+                )
+            })?,
+        };
+        let source_timestamp = match record.get("source_timestamp") {
+            None | Some(Value::Null) => None,
+            Some(value) => Some(time(value).ok_or_else(|| {
+                format!(
+                    "invalid `source_timestamp` {}, expected RFC 3339",
+                    output::json(value)
+                )
+            })?),
+        };
+        let quality = match text("quality")?.as_str() {
+            "" => Quality::Good,
+            name => Quality::parse(name)
+                .ok_or_else(|| format!("invalid `quality` {name}, expected {}", Quality::NAMES))?,
+        };
+        Ok(Envelope {
+            source: text("source")?,
+            topic: text("topic")?,
+            payload,
+            unit: Some(text("unit")?).filter(|unit| !unit.is_empty()),
+            timestamp,
+            source_timestamp,
+            quality,
+        })
+    }
+
+    pub fn to_record(&self) -> Record {
+        let mut record = Record::new()
+            .field("source", self.source.as_str())
+            .field("topic", self.topic.as_str())
+            .field("payload", self.payload.clone());
+        if let Some(unit) = &self.unit {
+            record.push("unit", unit.as_str());
+        }
+        record.push(
+            "timestamp",
+            humantime::format_rfc3339_millis(self.timestamp).to_string(),
+        );
+        if let Some(source_timestamp) = self.source_timestamp {
+            record.push(
+                "source_timestamp",
+                humantime::format_rfc3339_millis(source_timestamp).to_string(),
+            );
+        }
+        record.field("quality", self.quality.name())
+    }
+
+    /// The envelope as it goes on the wire, without the newline.
+    pub fn to_line(&self) -> String {
+        output::json(&Value::Record(self.to_record()))
+    }
+
+    /// The payload as message text: strings as they are, anything else as JSON.
+    pub fn payload_text(&self) -> String {
+        match &self.payload {
+            Value::String(text) => text.clone(),
+            other => output::json(other),
+        }
+    }
+}
+
+impl From<&Point> for Envelope {
+    fn from(point: &Point) -> Envelope {
+        Envelope {
+            source: point.source.clone(),
+            topic: point.name.clone(),
+            payload: (&point.value).into(),
+            unit: point.unit.clone(),
+            timestamp: point.time,
+            source_timestamp: point.source_time,
+            quality: point.quality,
+        }
+    }
+}
+
+/// Envelopes arriving on stdin, one per line. Lines that aren't envelopes are logged and
+/// skipped, so one bad message doesn't end a long-running pipe.
+pub struct Reader {
+    lines: Lines<BufReader<Stdin>>,
+    line: usize,
+}
+
+impl Reader {
+    pub fn stdin() -> Reader {
+        Reader {
+            lines: BufReader::new(tokio::io::stdin()).lines(),
+            line: 0,
+        }
+    }
+
+    /// The next envelope, or `None` once stdin is closed.
+    pub async fn next(&mut self) -> Option<Envelope> {
+        loop {
+            let line = match self.lines.next_line().await {
+                Ok(line) => line?,
+                Err(err) => {
+                    log::error!("Unable to read envelopes from stdin: {err}");
+                    return None;
+                }
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match Envelope::parse(&line) {
+                Ok(envelope) => return Some(envelope),
+                Err(err) => log::warn!("Skipping line {} of stdin: {err}", self.line),
+            }
+        }
+    }
+}
+
+fn time(value: &Value) -> Option<SystemTime> {
+    let seconds = match value {
+        Value::String(text) => return humantime::parse_rfc3339_weak(text).ok(),
+        Value::Integer(seconds) => u64::try_from(*seconds).ok()? as f64,
+        Value::Unsigned(seconds) => *seconds as f64,
+        Value::Float(seconds) if *seconds >= 0.0 => *seconds,
+        _ => return None,
+    };
+    Some(UNIX_EPOCH + Duration::from_secs_f64(seconds))
+}
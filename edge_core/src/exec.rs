@@ -0,0 +1,103 @@
+//! `--exec` hooks: a shell command run for each message or changed value, for quick
+//! integrations such as switching a relay or opening a ticket without writing a plugin:
+//!
+//! ```text
+//! $ nats nats://hub:4222 subscribe -s 'alarms.>' -w --exec 'curl -s -d @- https://tickets/api'
+//! ```
+//!
+//! The payload goes to the command's stdin, as text or JSON the way envelopes carry it, and
+//! what it is in the environment: `EDGE_SOURCE`, `EDGE_TOPIC` (the subject, topic or
+//! register), `EDGE_TIMESTAMP`, `EDGE_QUALITY` and `EDGE_UNIT` when there is one. Commands run
+//! one at a time in the background, in the order values arrive, so a slow one doesn't hold up
+//! reading; when too many are waiting, new values are skipped with a warning. What a command
+//! prints goes to stderr, apart from the tool's results.
+
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::envelope::Envelope;
+use crate::plugin;
+
+// Runs waiting before values are skipped.
+const QUEUE: usize = 64;
+
+struct Job {
+    payload: Vec<u8>,
+    vars: Vec<(&'static str, String)>,
+}
+
+pub struct Exec {
+    sender: mpsc::Sender<Job>,
+    task: JoinHandle<()>,
+}
+
+impl Exec {
+    /// Starts the background runner for `command`, which goes through `sh -c` (`cmd /C` on
+    /// Windows).
+    pub fn start(command: &str) -> Exec {
+        let (sender, receiver) = mpsc::channel(QUEUE);
+        let task = tokio::spawn(run_loop(command.to_string(), receiver));
+        Exec { sender, task }
+    }
+
+    /// Queues a run for `envelope`'s value.
+    pub fn run(&self, envelope: &Envelope) {
+        let mut vars = vec![
+            ("EDGE_SOURCE", envelope.source.clone()),
+            ("EDGE_TOPIC", envelope.topic.clone()),
+            (
+                "EDGE_TIMESTAMP",
+                humantime::format_rfc3339_millis(envelope.timestamp).to_string(),
+            ),
+            ("EDGE_QUALITY", envelope.quality.name().to_string()),
+        ];
+        if let Some(unit) = &envelope.unit {
+            vars.push(("EDGE_UNIT", unit.clone()));
+        }
+        let job = Job {
+            payload: envelope.payload_text().into_bytes(),
+            vars,
+        };
+        if self.sender.try_send(job).is_err() {
+            log::warn!(
+                "--exec is falling behind, skipping the value for {}",
+                envelope.topic
+            );
+        }
+    }
+
+    /// Waits for the runs still queued.
+    pub async fn finish(self) {
+        drop(self.sender);
+        let _ = self.task.await;
+    }
+}
+
+async fn run_loop(command: String, mut receiver: mpsc::Receiver<Job>) {
+    while let Some(job) = receiver.recv().await {
+        if let Err(err) = run_one(&command, job).await {
+            log::warn!("--exec `{command}` failed: {err}");
+        }
+    }
+}
+
+async fn run_one(command: &str, job: Job) -> Result<(), String> {
+    let mut child = plugin::shell(command)
+        .envs(job.vars)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::from(std::io::stderr()))
+        .spawn()
+        .map_err(|err| format!("unable to start it: {err}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read its stdin closes it early; that's fine.
+        let _ = stdin.write_all(&job.payload).await;
+    }
+    let status = child.wait().await.map_err(|err| err.to_string())?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(status.to_string()),
+    }
+}
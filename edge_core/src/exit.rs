@@ -0,0 +1,206 @@
+//! Exit statuses shared by every tool, so scripts can tell what kind of failure happened:
+//!
+//! | code | meaning                                                               |
+//! |------|-----------------------------------------------------------------------|
+//! | 0    | success                                                               |
+//! | 1    | any other failure                                                     |
+//! | 2    | invalid arguments or configuration (also used by clap for usage errors) |
+//! | 3    | connection failure: unreachable, refused, device or port missing      |
+//! | 4    | authentication or authorization failure                               |
+//! | 5    | protocol error: malformed or unexpected answer, error response        |
+//! | 6    | validation mismatch: a checked value was not what was expected        |
+//! | 7    | timeout waiting for the remote end                                    |
+//! | 8    | subscribe failure: the server refused or dropped a subscription       |
+//! | 9    | publish failure: the server refused a message or it couldn't be sent  |
+
+use std::fmt::Display;
+use std::sync::Mutex;
+
+// Run by `fatal_with` before it exits, last added first.
+type Finish = Box<dyn FnOnce() + Send>;
+static AT_EXIT: Mutex<Vec<Finish>> = Mutex::new(Vec::new());
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Code {
+    Failure = 1,
+    Usage = 2,
+    Connection = 3,
+    Auth = 4,
+    Protocol = 5,
+    Mismatch = 6,
+    Timeout = 7,
+    Subscribe = 8,
+    Publish = 9,
+}
+
+impl Code {
+    pub const ALL: [Code; 9] = [
+        Code::Failure,
+        Code::Usage,
+        Code::Connection,
+        Code::Auth,
+        Code::Protocol,
+        Code::Mismatch,
+        Code::Timeout,
+        Code::Subscribe,
+        Code::Publish,
+    ];
+
+    pub fn status(self) -> i32 {
+        self as i32
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Code::Failure => "any other failure",
+            Code::Usage => "invalid arguments or configuration",
+            Code::Connection => "connection failure",
+            Code::Auth => "authentication failure",
+            Code::Protocol => "protocol error",
+            Code::Mismatch => "validation mismatch",
+            Code::Timeout => "timeout",
+            Code::Subscribe => "subscribe failure",
+            Code::Publish => "publish failure",
+        }
+    }
+
+    /// The code for a process's exit status, e.g. a tool run as a child; statuses outside the
+    /// table are generic failures.
+    pub fn from_status(status: i32) -> Code {
+        Code::ALL
+            .into_iter()
+            .find(|code| code.status() == status)
+            .unwrap_or(Code::Failure)
+    }
+
+    /// The status for `err`: an [`Error`] anywhere in its source chain carries its own, I/O
+    /// errors are mapped by kind and anything else is a generic failure.
+    pub fn of(err: &(dyn std::error::Error + 'static)) -> Code {
+        let mut current = Some(err);
+        while let Some(err) = current {
+            if let Some(err) = err.downcast_ref::<Error>() {
+                return err.code;
+            }
+            if let Some(err) = err.downcast_ref::<std::io::Error>() {
+                return Code::from_io(err);
+            }
+            current = err.source();
+        }
+        Code::Failure
+    }
+
+    /// Best guess for an I/O error, used where a failure could be several of the above.
+    pub fn from_io(err: &std::io::Error) -> Code {
+        use std::io::ErrorKind::*;
+        match err.kind() {
+            TimedOut | WouldBlock => Code::Timeout,
+            ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected
+            | AddrNotAvailable | BrokenPipe | NotFound => Code::Connection,
+            PermissionDenied => Code::Auth,
+            InvalidData | UnexpectedEof => Code::Protocol,
+            InvalidInput => Code::Usage,
+            _ => Code::Failure,
+        }
+    }
+}
+
+/// An error that knows which exit status it should end the process with, for lower layers
+/// that return `Box<dyn Error>`.
+#[derive(Debug)]
+pub struct Error {
+    pub code: Code,
+    message: String,
+}
+
+impl Error {
+    pub fn new(code: Code, message: impl Display) -> Error {
+        Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The table above, for `--help`.
+pub const EXIT_CODES: &str = "EXIT STATUS:
+    0  success
+    1  any other failure
+    2  invalid arguments or configuration
+    3  connection failure
+    4  authentication failure
+    5  protocol error
+    6  validation mismatch
+    7  timeout
+    8  subscribe failure
+    9  publish failure";
+
+/// Logs `message` and exits with the generic failure status.
+pub fn fatal(message: impl Display) -> ! {
+    fatal_with(Code::Failure, message)
+}
+
+/// Logs `context: err` and exits with the status [`Code::of`] picks for `err`.
+pub fn fatal_error(context: impl Display, err: &(dyn std::error::Error + 'static)) -> ! {
+    fatal_with(Code::of(err), format!("{context}: {err}"))
+}
+
+/// Logs `message`, finishes what [`at_exit`] was given and exits with `code`.
+pub fn fatal_with(code: Code, message: impl Display) -> ! {
+    log::error!("{message}");
+    let finishing = std::mem::take(&mut *AT_EXIT.lock().unwrap_or_else(|err| err.into_inner()));
+    for finish in finishing.into_iter().rev() {
+        finish();
+    }
+    crate::telemetry::shutdown();
+    std::process::exit(code.status());
+}
+
+/// Has a fatal error run `finish` before it ends the process, for files that can't be read
+/// unless they're finished, such as an encrypted capture: exiting skips every `Drop`.
+pub fn at_exit(finish: impl FnOnce() + Send + 'static) {
+    AT_EXIT
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(Box::new(finish));
+}
+
+/// Unwraps a value or ends the process with a logged error, for failures a CLI can't recover
+/// from such as an unparsable address or a refused connection.
+pub trait OrExit<T> {
+    fn or_exit(self, context: &str) -> T;
+    fn or_exit_with(self, code: Code, context: &str) -> T;
+}
+
+impl<T, E: Display> OrExit<T> for Result<T, E> {
+    fn or_exit(self, context: &str) -> T {
+        self.or_exit_with(Code::Failure, context)
+    }
+
+    fn or_exit_with(self, code: Code, context: &str) -> T {
+        match self {
+            Ok(value) => value,
+            Err(err) => fatal_with(code, format!("{context}: {err}")),
+        }
+    }
+}
+
+impl<T> OrExit<T> for Option<T> {
+    fn or_exit(self, context: &str) -> T {
+        self.or_exit_with(Code::Failure, context)
+    }
+
+    fn or_exit_with(self, code: Code, context: &str) -> T {
+        match self {
+            Some(value) => value,
+            None => fatal_with(code, context),
+        }
+    }
+}
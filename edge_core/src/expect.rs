@@ -0,0 +1,524 @@
+//! Assertions on a command's results, for acceptance tests against simulators and real devices.
+//! Every tool takes them:
+//!
+//! ```text
+//! $ modbus 10.0.0.5 read-register -r 40236 -k holding --expect-value 1
+//! $ nats nats://broker:4222 subscribe -s site.status --expect-json '.payload.state == "ok"'
+//! $ modbus 10.0.0.5 read-register -r 100 -k holding --expect-json '.values[0] >= 200' --expect-within 10s
+//! ```
+//!
+//! The command runs as usual and then exits with [`Code::Mismatch`] unless one of its results
+//! met the expectation. With `--expect-within`, commands that can watch keep reading until one
+//! does, for at most that long.
+//!
+//! `--expect-value` compares a result's `payload`, `value` or `values` field, whichever comes
+//! first; numbers compare as numbers, so `42` matches `42.0` and a `"42"` payload, and a list of
+//! values is given with commas, e.g. `215,3`. `--expect-json` takes a small jq-like expression:
+//! paths like `.payload.state` or `.values[0]` (strings holding JSON are looked into),
+//! literals, `==`, `!=`, `<`, `<=`, `>`, `>=`, `and`, `or`, `not` and parentheses. A path alone
+//! holds when it's there and neither `false` nor `null`.
+
+use std::cmp::Ordering;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::exit::{self, Code};
+use crate::output::{self, Record, Value};
+
+static EXPECTATION: OnceLock<Expectation> = OnceLock::new();
+
+struct Expectation {
+    condition: Condition,
+    within: Option<Duration>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    met: bool,
+    // The last result checked, for the failure message.
+    last: Option<String>,
+}
+
+enum Condition {
+    Value(Value),
+    Json(Expr),
+    // Only --expect-within: any result will do.
+    Any,
+}
+
+/// `command` with the `--expect-*` options.
+pub fn args(command: Command<'static>) -> Command<'static> {
+    command
+        .arg(
+            Arg::new("expect_value")
+                .long("expect-value")
+                .value_name("VALUE")
+                .help(
+                    "Exit 6 unless a result's payload, value or values equal this, e.g. 1 or 215,3; \
+                     numbers compare as numbers",
+                )
+                .global(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("expect_json")
+                .long("expect-json")
+                .value_name("EXPRESSION")
+                .help(
+                    "Exit 6 unless a result makes this jq-like expression hold, e.g. \
+                     '.payload.state == \"ok\"' or '.values[0] >= 200'",
+                )
+                .global(true)
+                .conflicts_with("expect_value")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("expect_within")
+                .long("expect-within")
+                .value_name("DURATION")
+                .help(
+                    "Keep watching for a result meeting the expectation for at most this long, \
+                     e.g. 10s; alone, any result will do",
+                )
+                .global(true)
+                .value_parser(humantime::parse_duration)
+                .action(ArgAction::Set),
+        )
+}
+
+/// Sets up the expectation given in `matches`, from a command built with [`args`].
+pub fn install(matches: &ArgMatches) {
+    let within = matches.get_one::<Duration>("expect_within").copied();
+    let condition = if let Some(value) = matches.get_one::<String>("expect_value") {
+        Condition::Value(expected_value(value))
+    } else if let Some(expression) = matches.get_one::<String>("expect_json") {
+        let expr = parse(expression).unwrap_or_else(|err| {
+            exit::fatal_with(Code::Usage, format!("Invalid --expect-json: {err}"))
+        });
+        Condition::Json(expr)
+    } else if within.is_some() {
+        Condition::Any
+    } else {
+        return;
+    };
+    let _ = EXPECTATION.set(Expectation {
+        condition,
+        within,
+        state: Mutex::new(State::default()),
+    });
+}
+
+/// How long commands that can watch should keep trying to meet the expectation.
+pub fn within() -> Option<Duration> {
+    EXPECTATION.get()?.within
+}
+
+/// Whether a result has met the expectation; false without one.
+pub fn is_met() -> bool {
+    EXPECTATION
+        .get()
+        .is_some_and(|expectation| expectation.state().met)
+}
+
+/// Checks one result against the expectation, if there is one.
+pub fn check(record: &Record) {
+    let Some(expectation) = EXPECTATION.get() else {
+        return;
+    };
+    let mut state = expectation.state();
+    if state.met {
+        return;
+    }
+    let (met, last) = match &expectation.condition {
+        Condition::Value(expected) => match primary(record) {
+            Some(value) => (equal(value, expected), output::json(value)),
+            None => (false, output::json(&Value::Record(record.clone()))),
+        },
+        Condition::Json(expr) => (
+            truthy(&expr.eval(&Value::Record(record.clone()))),
+            output::json(&Value::Record(record.clone())),
+        ),
+        Condition::Any => (true, String::new()),
+    };
+    state.met = met;
+    state.last = Some(last);
+}
+
+/// Ends the process with [`Code::Mismatch`] unless the expectation, if any, was met.
+pub fn finish() {
+    let Some(expectation) = EXPECTATION.get() else {
+        return;
+    };
+    let state = expectation.state();
+    if state.met {
+        return;
+    }
+    let wanted = match &expectation.condition {
+        Condition::Value(value) => format!("value {}", output::json(value)),
+        Condition::Json(expr) => format!("`{}`", expr.source),
+        Condition::Any => "a result".to_string(),
+    };
+    let within = match expectation.within {
+        Some(within) => format!(" within {}", humantime::format_duration(within)),
+        None => String::new(),
+    };
+    let got = match &state.last {
+        Some(last) => format!("last result {last}"),
+        None => "no results".to_string(),
+    };
+    exit::fatal_with(
+        Code::Mismatch,
+        format!("Expected {wanted}{within}, got {got}"),
+    );
+}
+
+impl Expectation {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// `42`, `"ok"`, `true` or `215,3` as a value to compare with.
+fn expected_value(text: &str) -> Value {
+    let one = |text: &str| {
+        crate::json::parse(text.trim()).unwrap_or_else(|_| Value::String(text.trim().to_string()))
+    };
+    match text.contains(',') && !text.trim_start().starts_with(['"', '[', '{']) {
+        true => Value::List(text.split(',').map(one).collect()),
+        false => one(text),
+    }
+}
+
+/// The value a result is about.
+fn primary(record: &Record) -> Option<&Value> {
+    ["payload", "value", "values"]
+        .iter()
+        .find_map(|name| record.get(name))
+}
+
+fn equal(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        // One register read is still a list.
+        (Value::List(values), expected) if values.len() == 1 && !is_list(expected) => {
+            equal(&values[0], expected)
+        }
+        (Value::List(actual), Value::List(expected)) => {
+            actual.len() == expected.len() && actual.iter().zip(expected).all(|(a, e)| equal(a, e))
+        }
+        (actual, expected) => compare(actual, expected) == Some(Ordering::Equal),
+    }
+}
+
+fn is_list(value: &Value) -> bool {
+    matches!(value, Value::List(_))
+}
+
+/// Numbers compare numerically, also when one is text holding a number; strings, booleans and
+/// nulls only with their own kind.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.partial_cmp(&b);
+    }
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(number) => Some(*number as f64),
+        Value::Unsigned(number) => Some(*number as f64),
+        Value::Float(number) => Some(*number),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+struct Expr {
+    source: String,
+    node: Node,
+}
+
+enum Node {
+    Path(Vec<Step>),
+    Literal(Value),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Compare(Box<Node>, Op, Box<Node>),
+}
+
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Copy, Clone)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Expr {
+    fn eval(&self, input: &Value) -> Value {
+        self.node.eval(input)
+    }
+}
+
+impl Node {
+    fn eval(&self, input: &Value) -> Value {
+        match self {
+            Node::Path(steps) => {
+                let mut value = input.clone();
+                for step in steps {
+                    // Payloads often carry JSON as text.
+                    if let Value::String(text) = &value {
+                        match crate::json::parse(text) {
+                            Ok(parsed @ (Value::Record(_) | Value::List(_))) => value = parsed,
+                            _ => return Value::Null,
+                        }
+                    }
+                    value = match (step, &value) {
+                        (Step::Field(name), Value::Record(record)) => {
+                            record.get(name).cloned().unwrap_or(Value::Null)
+                        }
+                        (Step::Index(index), Value::List(values)) => {
+                            values.get(*index).cloned().unwrap_or(Value::Null)
+                        }
+                        _ => return Value::Null,
+                    };
+                }
+                value
+            }
+            Node::Literal(value) => value.clone(),
+            Node::Not(node) => Value::Bool(!truthy(&node.eval(input))),
+            Node::And(a, b) => Value::Bool(truthy(&a.eval(input)) && truthy(&b.eval(input))),
+            Node::Or(a, b) => Value::Bool(truthy(&a.eval(input)) || truthy(&b.eval(input))),
+            Node::Compare(a, op, b) => {
+                let (a, b) = (a.eval(input), b.eval(input));
+                let ordering = compare(&a, &b);
+                Value::Bool(match op {
+                    Op::Eq => equal(&a, &b),
+                    Op::Ne => !equal(&a, &b),
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Path(String),
+    Literal(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+fn parse(source: &str) -> Result<Expr, String> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+    };
+    let node = parser.or()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(format!("unexpected {token:?}"));
+    }
+    Ok(Expr {
+        source: source.to_string(),
+        node,
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '.' => {
+                i += 1;
+                while i < chars.len() && !chars[i].is_whitespace() && !"()=!<>".contains(chars[i]) {
+                    i += 1;
+                }
+                tokens.push(Token::Path(chars[start..i].iter().collect()));
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                if i >= chars.len() {
+                    return Err("unterminated string".to_string());
+                }
+                i += 1;
+                tokens.push(Token::Literal(chars[start..i].iter().collect()));
+            }
+            '=' | '!' | '<' | '>' => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let op = match two.as_str() {
+                    "==" => "==",
+                    "!=" => "!=",
+                    "<=" => "<=",
+                    ">=" => ">=",
+                    _ if c == '<' => "<",
+                    _ if c == '>' => ">",
+                    _ => return Err(format!("unknown operator at `{two}`")),
+                };
+                i += op.len();
+                tokens.push(Token::Op(op));
+            }
+            _ => {
+                while i < chars.len() && !chars[i].is_whitespace() && !"()=!<>".contains(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.as_str() {
+                    "and" => Token::Op("and"),
+                    "or" => Token::Op("or"),
+                    "not" => Token::Op("not"),
+                    _ => Token::Literal(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next_is(&self, op: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Op(next)) if *next == op)
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.next_is("or") {
+            self.position += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        while self.next_is("and") {
+            self.position += 1;
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.next_is("not") {
+            self.position += 1;
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = self.operand()?;
+        let op = match self.tokens.get(self.position) {
+            Some(Token::Op("==")) => Op::Eq,
+            Some(Token::Op("!=")) => Op::Ne,
+            Some(Token::Op("<")) => Op::Lt,
+            Some(Token::Op("<=")) => Op::Le,
+            Some(Token::Op(">")) => Op::Gt,
+            Some(Token::Op(">=")) => Op::Ge,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.operand()?;
+        Ok(Node::Compare(Box::new(left), op, Box::new(right)))
+    }
+
+    fn operand(&mut self) -> Result<Node, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or("expression ends too early")?;
+        self.position += 1;
+        match token {
+            Token::Open => {
+                let node = self.or()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(node)
+                    }
+                    _ => Err("missing `)`".to_string()),
+                }
+            }
+            Token::Path(path) => Ok(Node::Path(steps(path)?)),
+            Token::Literal(text) => crate::json::parse(text)
+                .map(Node::Literal)
+                .map_err(|_| format!("`{text}` isn't a path or a JSON value")),
+            other => Err(format!("unexpected {other:?}")),
+        }
+    }
+}
+
+/// `.a.b[0]` as steps; `.` alone is the whole result.
+fn steps(path: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let (index, after) = after
+                .split_once(']')
+                .ok_or_else(|| format!("missing `]` in {path}"))?;
+            let index = index
+                .parse()
+                .map_err(|_| format!("invalid index [{index}] in {path}"))?;
+            steps.push(Step::Index(index));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end > 0 {
+                steps.push(Step::Field(after[..end].to_string()));
+            } else if !after.is_empty() && !after.starts_with('[') {
+                return Err(format!("empty field name in {path}"));
+            }
+            rest = &after[end..];
+        } else {
+            return Err(format!("invalid path {path}"));
+        }
+    }
+    Ok(steps)
+}
@@ -0,0 +1,141 @@
+//! Redundant endpoints, such as the two CPUs of a redundant PLC: operations go to the primary
+//! and fail over to the secondary when the primary can't be reached, then fail back to the
+//! primary after the [`Failback`] delay. Only connection failures and timeouts fail over; an
+//! answer the device doesn't like would get the same answer from its twin. Both switches are
+//! logged, and [`Failover::served`] says which endpoint the last operation went to.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long operations stay on the secondary unless `--failback` says otherwise.
+pub const DEFAULT_FAILBACK: Duration = Duration::from_secs(300);
+
+/// When to go back to the primary after failing over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failback {
+    // Try the primary again this long after failing over.
+    After(Duration),
+    // Stay on the secondary until it fails too.
+    Never,
+}
+
+impl Default for Failback {
+    fn default() -> Failback {
+        Failback::After(DEFAULT_FAILBACK)
+    }
+}
+
+impl FromStr for Failback {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Failback, String> {
+        match text {
+            "never" => Ok(Failback::Never),
+            _ => humantime::parse_duration(text)
+                .map(Failback::After)
+                .map_err(|_| format!("expected a duration such as 5m or `never`, got `{text}`")),
+        }
+    }
+}
+
+impl Display for Failback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failback::After(delay) => write!(f, "{}", humantime::format_duration(*delay)),
+            Failback::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// A primary and a secondary endpoint; clones share which one is in use.
+#[derive(Clone, Debug)]
+pub struct Failover<A> {
+    endpoints: [A; 2],
+    failback: Failback,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    // Index of the endpoint in use, and since when.
+    active: usize,
+    since: Instant,
+    served: Option<usize>,
+}
+
+impl<A: Clone + PartialEq + Display> Failover<A> {
+    pub fn new(primary: A, secondary: A) -> Failover<A> {
+        Failover {
+            endpoints: [primary, secondary],
+            failback: Failback::default(),
+            state: Arc::new(Mutex::new(State {
+                active: 0,
+                since: Instant::now(),
+                served: None,
+            })),
+        }
+    }
+
+    pub fn failback(self, failback: Failback) -> Failover<A> {
+        Failover { failback, ..self }
+    }
+
+    pub fn primary(&self) -> &A {
+        &self.endpoints[0]
+    }
+
+    /// The endpoint to use next: the secondary after a failover, until the failback is due.
+    pub fn active(&self) -> A {
+        let mut state = self.lock();
+        if let (1, Failback::After(delay)) = (state.active, self.failback) {
+            if state.since.elapsed() >= delay {
+                log::info!("Failing back to {}", self.endpoints[0]);
+                state.active = 0;
+                state.since = Instant::now();
+            }
+        }
+        self.endpoints[state.active].clone()
+    }
+
+    /// Records that `endpoint` couldn't be reached, switching to the other one unless another
+    /// operation did already. Returns the endpoint to try instead, if it isn't `endpoint`.
+    pub fn failed(&self, endpoint: &A, err: &dyn Display) -> Option<A> {
+        let mut state = self.lock();
+        let failed = self
+            .endpoints
+            .iter()
+            .position(|candidate| candidate == endpoint)?;
+        if state.active == failed {
+            let other = 1 - failed;
+            log::warn!(
+                "Failing over from {} to {}: {err}",
+                self.endpoints[failed],
+                self.endpoints[other]
+            );
+            state.active = other;
+            state.since = Instant::now();
+        }
+        Some(self.endpoints[1 - failed].clone())
+    }
+
+    /// Records that `endpoint` served an operation.
+    pub fn succeeded(&self, endpoint: &A) {
+        let served = self
+            .endpoints
+            .iter()
+            .position(|candidate| candidate == endpoint);
+        self.lock().served = served;
+    }
+
+    /// The endpoint the last successful operation went to.
+    pub fn served(&self) -> Option<A> {
+        let served = self.lock().served;
+        served.map(|index| self.endpoints[index].clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("failover state poisoned")
+    }
+}
@@ -0,0 +1,270 @@
+//! One command against many devices at once. Where a tool takes a device address, a comma list
+//! or `@file` with one address per line (`#` comments allowed) names several, and the command
+//! runs against all of them concurrently:
+//!
+//! ```text
+//! $ modbus @inverters.txt read-register -r 100 -k holding
+//! 10.0.0.5: 215
+//! 10.0.0.7: 212
+//! 10.0.0.6: [... ERROR edge_core::exit] Unable to connect: Connection refused (os error 111)
+//! 2 of 3 targets succeeded
+//!   10.0.0.6: connection failure (exit status 3)
+//! ```
+//!
+//! Each target gets its own process, the tool run again with the list replaced by one address,
+//! so a slow or dead device never holds up the rest. Text lines are prefixed with the target;
+//! JSON, YAML and table results get a `target` field, plus a record with the error for targets
+//! that failed without printing any. Envelopes already name their source and pass through as
+//! they are. The exit status is that of the first failed target in
+//! list order.
+
+use std::ffi::OsString;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::exit::{self, Code};
+use crate::output::{Format, Output, Record, Value};
+
+/// Targets handled at once unless `--parallel` says otherwise.
+pub const DEFAULT_PARALLEL: usize = 16;
+
+/// The targets `address` names when it's a list, or `None` for a single address.
+pub fn targets(address: &str) -> Result<Option<Vec<String>>, exit::Error> {
+    let targets: Vec<String> = if let Some(path) = address.strip_prefix('@') {
+        let list = std::fs::read_to_string(path).map_err(|err| {
+            exit::Error::new(
+                Code::Usage,
+                format!("Unable to read the target list {path}: {err}"),
+            )
+        })?;
+        list.lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|target| !target.is_empty())
+            .map(str::to_string)
+            .collect()
+    } else if address.contains(',') {
+        address
+            .split(',')
+            .map(str::trim)
+            .filter(|target| !target.is_empty())
+            .map(str::to_string)
+            .collect()
+    } else {
+        return Ok(None);
+    };
+    if targets.is_empty() {
+        return Err(exit::Error::new(
+            Code::Usage,
+            format!("No targets in {address}"),
+        ));
+    }
+    if let Some(nested) = targets.iter().find(|target| target.starts_with('@')) {
+        return Err(exit::Error::new(
+            Code::Usage,
+            format!("Target lists can't include other lists ({nested})"),
+        ));
+    }
+    Ok(Some(targets))
+}
+
+enum Event {
+    Stdout(usize, String),
+    Stderr(usize, String),
+    Done(usize, Option<Code>),
+}
+
+/// Runs this process's command once per target, at most `parallel` at a time, and exits with
+/// the combined status. `address` is the list as given on the command line and `env` the
+/// variable the tool also reads its address from, so lists from the environment or a profile
+/// are replaced too.
+pub async fn run(
+    out: &Output,
+    address: &str,
+    targets: Vec<String>,
+    env: &str,
+    parallel: Option<usize>,
+) -> ! {
+    let exe = std::env::current_exe()
+        .unwrap_or_else(|err| exit::fatal_error("Unable to find the tool's binary", &err));
+    // Results come back as JSON to be tagged with their target, unless plain text will do.
+    let text = out.is_text() && !out.has_template();
+    let envelopes = out.format() == Format::Envelope && !out.has_template();
+    let collect = !out.has_template() && matches!(out.format(), Format::Yaml | Format::Table);
+    let args = child_args(!text && !envelopes && out.format() != Format::Json);
+    let limit = Arc::new(Semaphore::new(parallel.unwrap_or(DEFAULT_PARALLEL).max(1)));
+    let (events, mut received) = mpsc::unbounded_channel();
+
+    for (index, target) in targets.iter().enumerate() {
+        let mut command = tokio::process::Command::new(&exe);
+        command
+            .args(args.iter().map(|arg| match arg.to_str() {
+                Some(arg) if arg == address => OsString::from(target),
+                _ => arg.clone(),
+            }))
+            .env(env, target)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let limit = limit.clone();
+        let events = events.clone();
+        let target = target.clone();
+        tokio::spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            let code = match command.spawn() {
+                Ok(mut child) => {
+                    let stdout = child.stdout.take().map(BufReader::new);
+                    let stderr = child.stderr.take().map(BufReader::new);
+                    tokio::join!(
+                        forward(stdout, |line| Event::Stdout(index, line), &events),
+                        forward(stderr, |line| Event::Stderr(index, line), &events),
+                    );
+                    match child.wait().await {
+                        Ok(status) if status.success() => None,
+                        Ok(status) => Some(status.code().map_or(Code::Failure, Code::from_status)),
+                        Err(err) => {
+                            log::error!("Unable to wait for the run against {target}: {err}");
+                            Some(Code::Failure)
+                        }
+                    }
+                }
+                Err(err) => {
+                    log::error!("Unable to start the run against {target}: {err}");
+                    Some(Code::Failure)
+                }
+            };
+            let _ = events.send(Event::Done(index, code));
+        });
+    }
+    drop(events);
+
+    let mut collected: Vec<(usize, Record)> = Vec::new();
+    let mut results: Vec<Option<Option<Code>>> = vec![None; targets.len()];
+    // Targets that printed results, whose failure then needs no record of its own.
+    let mut printed = vec![false; targets.len()];
+    let mut emit = |index: usize, record: Record| match collect {
+        true => collected.push((index, record)),
+        false => out.record(&record, || {}),
+    };
+    while let Some(event) = received.recv().await {
+        match event {
+            Event::Stdout(index, line) => {
+                let target = &targets[index];
+                printed[index] = true;
+                if text {
+                    println!("{target}: {line}");
+                    continue;
+                }
+                if envelopes {
+                    println!("{line}");
+                    continue;
+                }
+                for record in records(target, &line) {
+                    emit(index, record);
+                }
+            }
+            // JSON log lines are passed on as they are, to keep them parseable.
+            Event::Stderr(_, line) if line.starts_with('{') => eprintln!("{line}"),
+            Event::Stderr(index, line) => eprintln!("{}: {line}", targets[index]),
+            Event::Done(index, code) => {
+                results[index] = Some(code);
+                if let (Some(code), false) = (code, text || envelopes || printed[index]) {
+                    let record = Record::new()
+                        .field("target", targets[index].as_str())
+                        .field("error", code.description())
+                        .field("status", code.status());
+                    emit(index, record);
+                }
+            }
+        }
+    }
+    if collect {
+        collected.sort_by_key(|(index, _)| *index);
+        let records: Vec<Record> = collected.into_iter().map(|(_, record)| record).collect();
+        out.records(&records, || {});
+    }
+
+    let ok = results
+        .iter()
+        .filter(|result| **result == Some(None))
+        .count();
+    eprintln!("{ok} of {} targets succeeded", targets.len());
+    let mut outcome = None;
+    for (target, result) in targets.iter().zip(&results) {
+        if let Some(Some(code)) = result {
+            eprintln!(
+                "  {target}: {} (exit status {})",
+                code.description(),
+                code.status()
+            );
+            outcome = outcome.or(Some(*code));
+        }
+    }
+    std::process::exit(outcome.map_or(0, Code::status));
+}
+
+/// This process's arguments, with `--output json` and no `--template` when `json` is set.
+fn child_args(json: bool) -> Vec<OsString> {
+    let args = std::env::args_os().skip(1);
+    if !json {
+        return args.collect();
+    }
+    let mut child = Vec::new();
+    let mut skip_next = false;
+    for arg in args {
+        let name = arg.to_str().and_then(|arg| arg.split('=').next());
+        if std::mem::take(&mut skip_next) {
+            continue;
+        }
+        match name {
+            Some("--output" | "--template")
+                if arg.to_str().is_some_and(|arg| arg.contains('=')) => {}
+            Some("--output" | "--template") => skip_next = true,
+            _ => child.push(arg),
+        }
+    }
+    // `--output` is global in every tool, so it can go after the subcommand.
+    child.push("--output=json".into());
+    child
+}
+
+async fn forward<R: tokio::io::AsyncRead + Unpin>(
+    reader: Option<BufReader<R>>,
+    event: impl Fn(String) -> Event,
+    events: &mpsc::UnboundedSender<Event>,
+) {
+    let Some(reader) = reader else {
+        return;
+    };
+    let mut lines = reader.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let _ = events.send(event(line));
+    }
+}
+
+/// One line of a target's JSON output as records: the record itself, every record of a list,
+/// or the line as `output` when it isn't a record.
+fn records(target: &str, line: &str) -> Vec<Record> {
+    let tagged = |value: Value| {
+        let mut record = Record::new().field("target", target);
+        match value {
+            Value::Record(fields) => {
+                for (name, value) in fields.fields().filter(|(name, _)| *name != "target") {
+                    record.push(name, value.clone());
+                }
+            }
+            other => record.push("output", other),
+        }
+        record
+    };
+    match crate::json::parse(line) {
+        Ok(Value::List(values)) => values.into_iter().map(tagged).collect(),
+        Ok(value) => vec![tagged(value)],
+        Err(_) => vec![tagged(Value::String(line.to_string()))],
+    }
+}
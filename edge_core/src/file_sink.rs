@@ -0,0 +1,496 @@
+//! `--file` and the bridge's `file` sinks: every value appended to a file as an NDJSON
+//! [`crate::envelope`] line by a background task, so a slow disk, such as an SD card that
+//! stalls while it syncs, never holds up the reader taking messages off the network. Lines
+//! wait in memory, at most the queue size of [`crate::buffer`]'s limits of them; past that
+//! they're spilled to segment files in the spill directory when there is one, up to its size
+//! limit, and dropped otherwise, with a warning now and then and a count when the file is
+//! closed. Once lines spill, the following ones do too until the file has caught up, so they
+//! reach it in order.
+//!
+//! Lines are written in batches and the file synced every flush interval, 1s by default, so a
+//! power cut loses at most that much of what was written. Spilled lines left when the command
+//! stops stay in the spill directory, and the next run on a file of the same name writes them
+//! first.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::buffer::{self, Depth, Limits};
+
+const DEFAULT_FLUSH: Duration = Duration::from_secs(1);
+const DEFAULT_SPILL_MAX: usize = 256 << 20;
+// Lines are written out between flushes once this many bytes wait.
+const CHUNK: usize = 64 << 10;
+// A spill segment is closed, and may be written to the file, at this size.
+const SEGMENT: u64 = 4 << 20;
+// How long closing waits for the file to catch up.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+// Drops are reported at most this often.
+const WARN_EVERY: Duration = Duration::from_secs(10);
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct FileArgs {
+    /// Also append every value to this file as an envelope line, written in the background.
+    #[clap(long, value_name = "PATH", action)]
+    pub file: Option<PathBuf>,
+    /// Write and sync the file this often, e.g. 200ms; 1s by default.
+    #[clap(long, value_name = "INTERVAL", value_parser = humantime::parse_duration)]
+    pub file_flush: Option<Duration>,
+    /// Keep lines the file can't take yet in this directory once the queue is full, rather
+    /// than dropping them.
+    #[clap(long, value_name = "DIR", action)]
+    pub file_spill: Option<PathBuf>,
+    /// Spill at most this much, e.g. 1GiB; 256MiB by default.
+    #[clap(long, value_name = "SIZE", value_parser = buffer::parse_size)]
+    pub file_spill_max: Option<usize>,
+}
+
+impl FileArgs {
+    /// The file to write, if any, holding at most `limits.queue` lines in memory.
+    pub fn options(&self, limits: Limits) -> Option<Options> {
+        let options = Options::new(self.file.clone()?);
+        Some(Options {
+            flush: self.file_flush.unwrap_or(options.flush),
+            queue: limits.queue,
+            spill: self.file_spill.clone(),
+            spill_max: self.file_spill_max.unwrap_or(options.spill_max),
+            ..options
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    pub path: PathBuf,
+    pub flush: Duration,
+    // Lines held in memory.
+    pub queue: usize,
+    // Directory for lines past the queue; dropped without one.
+    pub spill: Option<PathBuf>,
+    // Bytes.
+    pub spill_max: usize,
+}
+
+impl Options {
+    /// `path` with the default flush interval, queue and spill limit, and no spill directory.
+    pub fn new(path: PathBuf) -> Options {
+        Options {
+            path,
+            flush: DEFAULT_FLUSH,
+            queue: Limits::default().queue,
+            spill: None,
+            spill_max: DEFAULT_SPILL_MAX,
+        }
+    }
+}
+
+/// Lines handled so far.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub written: u64,
+    pub spilled: u64,
+    pub dropped: u64,
+}
+
+pub struct FileSink {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    options: Options,
+    // Spill segments are named after the file.
+    name: String,
+    // Pipes and devices can't be synced.
+    regular: bool,
+    lines: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn dropped(&self, state: &mut State, lines: u64, why: &str) {
+        state.counts.dropped += lines;
+        let now = Instant::now();
+        if state
+            .warned
+            .is_none_or(|warned| now.duration_since(warned) >= WARN_EVERY)
+        {
+            state.warned = Some(now);
+            log::warn!(
+                "{}: dropped a line, {why} ({} dropped so far)",
+                self.options.path.display(),
+                state.counts.dropped
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    memory: VecDeque<String>,
+    spill: Spill,
+    closing: bool,
+    counts: Counts,
+    warned: Option<Instant>,
+}
+
+/// Lines waiting in the spill directory, oldest segment first.
+#[derive(Default)]
+struct Spill {
+    segments: VecDeque<Segment>,
+    // The segment being appended to, after all of `segments`.
+    open: Option<(Segment, BufWriter<File>)>,
+    next: u64,
+}
+
+struct Segment {
+    path: PathBuf,
+    bytes: u64,
+    lines: u64,
+}
+
+/// What the writer does next.
+enum Work {
+    Lines(Vec<String>),
+    Segment(Segment),
+    Idle,
+    Done,
+}
+
+impl Spill {
+    /// Segments a previous run left in `directory` for the file `name`.
+    fn left(directory: &Path, name: &str) -> io::Result<Spill> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let number = path
+                .file_name()
+                .and_then(|file| file.to_str())
+                .and_then(|file| file.strip_prefix(name)?.strip_prefix('.'))
+                .and_then(|rest| rest.strip_suffix(".spill")?.parse::<u64>().ok());
+            if let Some(number) = number {
+                found.push((number, path));
+            }
+        }
+        found.sort();
+        let mut spill = Spill {
+            next: found.last().map_or(0, |(number, _)| number + 1),
+            ..Spill::default()
+        };
+        for (_, path) in found {
+            let contents = fs::read(&path)?;
+            spill.segments.push_back(Segment {
+                path,
+                bytes: contents.len() as u64,
+                lines: contents.iter().filter(|byte| **byte == b'\n').count() as u64,
+            });
+        }
+        Ok(spill)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.segments.is_empty() && self.open.is_none()
+    }
+
+    fn bytes(&self) -> u64 {
+        let open = self.open.iter().map(|(segment, _)| segment.bytes);
+        self.segments
+            .iter()
+            .map(|segment| segment.bytes)
+            .chain(open)
+            .sum()
+    }
+
+    fn lines(&self) -> u64 {
+        let open = self.open.iter().map(|(segment, _)| segment.lines);
+        self.segments
+            .iter()
+            .map(|segment| segment.lines)
+            .chain(open)
+            .sum()
+    }
+
+    fn append(&mut self, directory: &Path, name: &str, line: &str) -> io::Result<()> {
+        if self.open.is_none() {
+            let path = directory.join(format!("{name}.{:08}.spill", self.next));
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            self.next += 1;
+            let segment = Segment {
+                path,
+                bytes: 0,
+                lines: 0,
+            };
+            self.open = Some((segment, BufWriter::new(file)));
+        }
+        let (segment, file) = self.open.as_mut().expect("opened above");
+        file.write_all(line.as_bytes())?;
+        segment.bytes += line.len() as u64;
+        segment.lines += 1;
+        if segment.bytes >= SEGMENT {
+            self.seal()?;
+        }
+        Ok(())
+    }
+
+    /// Ends the segment being appended to, so it can be written to the file.
+    fn seal(&mut self) -> io::Result<()> {
+        let Some((segment, mut file)) = self.open.take() else {
+            return Ok(());
+        };
+        let flushed = file.flush();
+        self.segments.push_back(segment);
+        flushed
+    }
+}
+
+impl FileSink {
+    /// Opens `options.path` for appending, creating it and the spill directory as needed, and
+    /// picks up the lines a previous run left spilled. Must be called on the runtime.
+    pub fn open(options: Options) -> io::Result<FileSink> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&options.path)?;
+        let name = options
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| io::Error::other(format!("{} isn't a file", options.path.display())))?;
+        let spill = match &options.spill {
+            Some(directory) => {
+                fs::create_dir_all(directory)?;
+                Spill::left(directory, &name)?
+            }
+            None => Spill::default(),
+        };
+        if !spill.is_empty() {
+            log::info!(
+                "{}: writing {} lines left spilled by the last run first",
+                options.path.display(),
+                spill.lines()
+            );
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                spill,
+                ..State::default()
+            }),
+            options,
+            name,
+            regular: file.metadata()?.is_file(),
+            lines: Notify::new(),
+        });
+        let task = tokio::spawn(write_loop(shared.clone(), tokio::fs::File::from_std(file)));
+        Ok(FileSink { shared, task })
+    }
+
+    /// Queues `line` without waiting: in memory while there's room, in the spill directory
+    /// after that, or dropped.
+    pub fn write(&self, line: &str) {
+        let shared = &*self.shared;
+        let mut line = line.to_string();
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        let mut state = shared.lock();
+        if state.spill.is_empty() && state.memory.len() < shared.options.queue {
+            state.memory.push_back(line);
+        } else {
+            let Some(directory) = &shared.options.spill else {
+                return shared.dropped(&mut state, 1, "the queue is full");
+            };
+            if state.spill.bytes() + line.len() as u64 > shared.options.spill_max as u64 {
+                return shared.dropped(&mut state, 1, "the spill directory is full");
+            }
+            match state.spill.append(directory, &shared.name, &line) {
+                Ok(()) => state.counts.spilled += 1,
+                Err(err) => {
+                    return shared.dropped(&mut state, 1, &format!("unable to spill it: {err}"))
+                }
+            }
+        }
+        drop(state);
+        shared.lines.notify_one();
+    }
+
+    pub fn counts(&self) -> Counts {
+        self.shared.lock().counts
+    }
+
+    /// A view of the lines waiting, in memory and spilled, for `--stats`.
+    pub fn depth(&self) -> Depth {
+        let shared = Arc::downgrade(&self.shared);
+        Depth::new(
+            &format!("file {}", self.shared.options.path.display()),
+            self.shared.options.queue,
+            move || {
+                let shared = Weak::upgrade(&shared)?;
+                let state = shared.lock();
+                let waiting = state.memory.len() + state.spill.lines() as usize;
+                Some((waiting, state.counts.dropped))
+            },
+        )
+    }
+
+    /// Writes out and syncs what's waiting, for at most ten seconds; lines still spilled then
+    /// are left for the next run.
+    pub async fn close(self) {
+        self.shared.lock().closing = true;
+        self.shared.lines.notify_one();
+        let path = self.shared.options.path.display();
+        if tokio::time::timeout(CLOSE_TIMEOUT, self.task)
+            .await
+            .is_err()
+        {
+            log::warn!("{path}: gave up waiting for the file to catch up");
+        }
+        let state = self.shared.lock();
+        let Counts {
+            written,
+            spilled,
+            dropped,
+        } = state.counts;
+        let message =
+            format!("{path}: wrote {written} lines, {spilled} spilled, {dropped} dropped");
+        match dropped {
+            0 => log::info!("{message}"),
+            _ => log::warn!("{message}"),
+        }
+        if let (Some(directory), false) = (&self.shared.options.spill, state.spill.is_empty()) {
+            log::warn!(
+                "{path}: {} lines left in {} for the next run",
+                state.spill.lines(),
+                directory.display()
+            );
+        }
+    }
+}
+
+async fn write_loop(shared: Arc<Shared>, mut file: tokio::fs::File) {
+    let flush = shared.options.flush;
+    // Bytes and lines not yet handed to the file.
+    let mut pending = Vec::new();
+    let mut lines = 0;
+    // Written since the last sync.
+    let mut unsynced = false;
+    let mut synced = tokio::time::Instant::now();
+    loop {
+        let arrived = shared.lines.notified();
+        let work = {
+            let mut state = shared.lock();
+            state.take()
+        };
+        let done = match work {
+            Work::Lines(taken) => {
+                lines += taken.len() as u64;
+                for line in taken {
+                    pending.extend_from_slice(line.as_bytes());
+                }
+                false
+            }
+            Work::Segment(segment) => {
+                match tokio::fs::read(&segment.path).await {
+                    Ok(contents) => {
+                        pending.extend_from_slice(&contents);
+                        lines += segment.lines;
+                        // Written before the segment goes, so a crash repeats lines rather
+                        // than losing them.
+                        write(&shared, &mut file, &mut pending, &mut lines).await;
+                        unsynced = true;
+                    }
+                    Err(err) => {
+                        let why = format!("unable to read {}: {err}", segment.path.display());
+                        shared.dropped(&mut shared.lock(), segment.lines, &why);
+                    }
+                }
+                if let Err(err) = tokio::fs::remove_file(&segment.path).await {
+                    log::warn!("Unable to remove {}: {err}", segment.path.display());
+                }
+                false
+            }
+            Work::Idle => {
+                let due = synced + flush;
+                if pending.is_empty() && !unsynced {
+                    arrived.await;
+                } else {
+                    tokio::select! {
+                        _ = arrived => {}
+                        _ = tokio::time::sleep_until(due) => {}
+                    }
+                }
+                false
+            }
+            Work::Done => true,
+        };
+        if pending.len() >= CHUNK {
+            write(&shared, &mut file, &mut pending, &mut lines).await;
+            unsynced = true;
+        }
+        if done || synced.elapsed() >= flush {
+            if !pending.is_empty() {
+                write(&shared, &mut file, &mut pending, &mut lines).await;
+                unsynced = true;
+            }
+            if unsynced && shared.regular {
+                if let Err(err) = file.sync_data().await {
+                    log::warn!("Unable to sync {}: {err}", shared.options.path.display());
+                }
+                unsynced = false;
+            }
+            synced = tokio::time::Instant::now();
+        }
+        if done {
+            return;
+        }
+    }
+}
+
+/// Hands `pending` to the file, counting its lines as written or dropped.
+async fn write(
+    shared: &Shared,
+    file: &mut tokio::fs::File,
+    pending: &mut Vec<u8>,
+    lines: &mut u64,
+) {
+    let result = file.write_all(pending).await;
+    let mut state = shared.lock();
+    match result {
+        Ok(()) => state.counts.written += *lines,
+        Err(err) => shared.dropped(&mut state, *lines, &format!("unable to write it: {err}")),
+    }
+    pending.clear();
+    *lines = 0;
+}
+
+impl State {
+    fn take(&mut self) -> Work {
+        if !self.memory.is_empty() {
+            return Work::Lines(self.memory.drain(..).collect());
+        }
+        if self.spill.segments.is_empty() {
+            if let Err(err) = self.spill.seal() {
+                log::warn!("Unable to write a spill segment: {err}");
+            }
+        }
+        match self.spill.segments.pop_front() {
+            Some(segment) => Work::Segment(segment),
+            None if self.closing => Work::Done,
+            None => Work::Idle,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! `healthcheck` subcommands for container and systemd probes: one small operation against the
+//! device or server under a deadline, one line on stdout and an exit status from [`crate::exit`].
+//!
+//! ```text
+//! $ modbus 10.0.0.5 healthcheck
+//! ok: 10.0.0.5:502 answered read holding register 0 (12ms)
+//! $ nats nats://broker:4222 healthcheck --timeout 2s
+//! unhealthy: nats://broker:4222: no answer within 2s
+//! ```
+
+use std::error::Error;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::exit::Code;
+use crate::output::{Output, Record};
+
+/// Deadline for the whole check, connecting included, unless `--timeout` says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs `check` against `target`, prints the result and exits: 0 with what the check saw, or
+/// the status for its error. A check still running after `timeout` exits with
+/// [`Code::Timeout`].
+pub async fn check<F, E>(out: &Output, target: &str, timeout: Option<Duration>, check: F) -> !
+where
+    F: Future<Output = Result<String, E>>,
+    E: Into<Box<dyn Error>>,
+{
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let started = Instant::now();
+    let result = match tokio::time::timeout(timeout, check).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err(crate::exit::Error::new(
+            Code::Timeout,
+            format!("no answer within {}", humantime::format_duration(timeout)),
+        )
+        .into()),
+    };
+    report(out, target, started, result)
+}
+
+/// [`check`] for tools that talk to local devices without a runtime; a blocking `check` can't
+/// be cut short, so there's no deadline beyond the device's own.
+pub fn check_blocking<E>(out: &Output, target: &str, check: impl FnOnce() -> Result<String, E>) -> !
+where
+    E: Into<Box<dyn Error>>,
+{
+    let started = Instant::now();
+    let result = check().map_err(Into::into);
+    report(out, target, started, result)
+}
+
+fn report(
+    out: &Output,
+    target: &str,
+    started: Instant,
+    result: Result<String, Box<dyn Error>>,
+) -> ! {
+    let elapsed = started.elapsed();
+    let millis = elapsed.as_secs_f64() * 1000.0;
+    let mut record = Record::new()
+        .field("healthy", result.is_ok())
+        .field("target", target);
+    let code = match &result {
+        Ok(detail) => {
+            record.push("detail", detail.as_str());
+            None
+        }
+        Err(err) => {
+            record.push("error", err.to_string());
+            Some(Code::of(&**err))
+        }
+    };
+    record.push("elapsed_ms", (millis * 10.0).round() / 10.0);
+    out.record(&record, || match &result {
+        Ok(detail) => println!("ok: {target} {detail} ({}ms)", millis.round()),
+        Err(err) => println!("unhealthy: {target}: {err}"),
+    });
+    crate::telemetry::shutdown();
+    std::process::exit(code.map_or(0, Code::status));
+}
@@ -0,0 +1,553 @@
+//! A local historian: timestamped point values kept under a directory, partitioned by UTC day,
+//! as a buffer on the gateway and a source for later analysis.
+//!
+//! SQLite stores one database per day, `points-2026-10-14.sqlite`; Parquet stores Hive-style
+//! partitions, `date=2026-10-14/part-<ms>-<n>.parquet`, one file per flush. Both hold a
+//! `points(time, source, point, value, text)` table with the time in milliseconds since the
+//! epoch, numbers in `value` and anything else in `text`. The time is the source's when it
+//! gave one, so late arrivals land where they were measured. Retention deletes whole days.
+//! SQLite text is cut at 1000 bytes, and the files are written without a journal, so a crash
+//! mid-write can corrupt the day's database.
+//!
+//! Rollups keep months of trends where raw samples only fit for days: the numeric values of
+//! each point are downsampled into a `rollups(time, source, point, min, max, avg, count)` row
+//! per interval, stamped with the interval's start, in a directory of their own such as
+//! `rollups-1m/` laid out like the samples and with a retention of its own. An interval is
+//! written once it's over, or cut short when the historian closes; samples that arrive after
+//! their interval was written are only kept raw.
+
+mod parquet;
+mod sqlite;
+
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::point::Point;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Store {
+    #[default]
+    Sqlite,
+    Parquet,
+}
+
+impl Store {
+    pub fn name(self) -> &'static str {
+        match self {
+            Store::Sqlite => "sqlite",
+            Store::Parquet => "parquet",
+        }
+    }
+
+    /// How long samples wait in memory, and how many, before they're written out. Parquet
+    /// files can't be appended to, so they're written less often.
+    fn flush_after(self) -> (Duration, usize) {
+        match self {
+            Store::Sqlite => (Duration::from_secs(1), 256),
+            Store::Parquet => (Duration::from_secs(60), 10_000),
+        }
+    }
+}
+
+pub struct Options {
+    pub directory: PathBuf,
+    pub store: Store,
+    // Days older than this are deleted; kept forever when unset.
+    pub retention: Option<Duration>,
+    pub rollups: Vec<Rollup>,
+}
+
+/// Min, max and average per `interval`, kept for `retention` or forever; `1m:90d` or `1h` on
+/// the command line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rollup {
+    pub interval: Duration,
+    pub retention: Option<Duration>,
+}
+
+impl Rollup {
+    /// The directory its rows go in, e.g. `rollups-1m`.
+    fn directory(&self) -> String {
+        let interval = humantime::format_duration(self.interval).to_string();
+        format!("rollups-{}", interval.replace(' ', ""))
+    }
+}
+
+impl FromStr for Rollup {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Rollup, String> {
+        let (interval, retention) = match text.split_once(':') {
+            Some((interval, retention)) => (interval, Some(retention)),
+            None => (text, None),
+        };
+        let duration = |text: &str| {
+            humantime::parse_duration(text)
+                .map_err(|_| format!("expected INTERVAL[:RETENTION] such as 1m:90d, got `{text}`"))
+        };
+        let interval = duration(interval)?;
+        if interval.as_millis() == 0 {
+            return Err("the rollup interval must be at least 1ms".to_string());
+        }
+        Ok(Rollup {
+            interval,
+            retention: retention.map(duration).transpose()?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct HistorianError(PathBuf, io::Error);
+
+impl fmt::Display for HistorianError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.0.display(), self.1)
+    }
+}
+
+impl std::error::Error for HistorianError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.1)
+    }
+}
+
+struct Sample {
+    // Milliseconds since the epoch.
+    time: i64,
+    source: String,
+    point: String,
+    value: Option<f64>,
+    text: Option<String>,
+}
+
+/// One interval of a point's values, as a rollups row.
+struct Aggregate {
+    // Start of the interval, in milliseconds since the epoch.
+    time: i64,
+    source: String,
+    point: String,
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+}
+
+/// A value of a stored row.
+enum Cell<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+}
+
+/// A row of one of the stored tables, for the writers.
+trait Row {
+    // Milliseconds since the epoch, which decides the day it's stored under.
+    fn time(&self) -> i64;
+    fn cells(&self) -> Vec<Cell<'_>>;
+}
+
+impl Row for Sample {
+    fn time(&self) -> i64 {
+        self.time
+    }
+
+    fn cells(&self) -> Vec<Cell<'_>> {
+        vec![
+            Cell::Integer(self.time),
+            Cell::Text(&self.source),
+            Cell::Text(&self.point),
+            self.value.map_or(Cell::Null, Cell::Real),
+            self.text.as_deref().map_or(Cell::Null, Cell::Text),
+        ]
+    }
+}
+
+impl Row for Aggregate {
+    fn time(&self) -> i64 {
+        self.time
+    }
+
+    fn cells(&self) -> Vec<Cell<'_>> {
+        vec![
+            Cell::Integer(self.time),
+            Cell::Text(&self.source),
+            Cell::Text(&self.point),
+            Cell::Real(self.min),
+            Cell::Real(self.max),
+            Cell::Real(self.sum / self.count as f64),
+            Cell::Integer(self.count as i64),
+        ]
+    }
+}
+
+/// Shared by the tasks recording values; writes happen on [`Historian::record`] once enough
+/// samples are waiting, from a background flush otherwise, and when dropped.
+pub struct Historian {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    store: Store,
+    points: Partitions,
+    rollups: Vec<Rollups>,
+    pending: Vec<Sample>,
+    last_flush: Instant,
+    // Day the old partitions were last deleted on.
+    pruned: String,
+}
+
+/// What a table's files are called, and what they hold.
+#[derive(Copy, Clone)]
+enum Table {
+    Points,
+    Rollups,
+}
+
+impl Table {
+    fn name(self) -> &'static str {
+        match self {
+            Table::Points => "points",
+            Table::Rollups => "rollups",
+        }
+    }
+}
+
+/// The day partitions of one table under a directory.
+struct Partitions {
+    directory: PathBuf,
+    store: Store,
+    table: Table,
+    retention: Option<Duration>,
+    // The day's open database.
+    database: Option<(String, sqlite::Database)>,
+}
+
+/// The intervals of one [`Rollup`] being filled, and those waiting to be written.
+struct Rollups {
+    // Milliseconds.
+    interval: i64,
+    open: HashMap<(String, String), Aggregate>,
+    // Start of the last interval written out for each source and point.
+    closed: HashMap<(String, String), i64>,
+    pending: Vec<Aggregate>,
+    partitions: Partitions,
+}
+
+impl Historian {
+    pub fn open(options: Options) -> Result<Historian, HistorianError> {
+        let directory = options.directory;
+        let partitions = |directory: PathBuf, table, retention| {
+            std::fs::create_dir_all(&directory)
+                .map_err(|err| HistorianError(directory.clone(), err))?;
+            Ok(Partitions {
+                directory,
+                store: options.store,
+                table,
+                retention,
+                database: None,
+            })
+        };
+        let points = partitions(directory.clone(), Table::Points, options.retention)?;
+        let rollups = options
+            .rollups
+            .iter()
+            .map(|rollup| {
+                Ok(Rollups {
+                    interval: rollup.interval.as_millis() as i64,
+                    open: HashMap::new(),
+                    closed: HashMap::new(),
+                    pending: Vec::new(),
+                    partitions: partitions(
+                        directory.join(rollup.directory()),
+                        Table::Rollups,
+                        rollup.retention,
+                    )?,
+                })
+            })
+            .collect::<Result<_, HistorianError>>()?;
+        let mut state = State {
+            store: options.store,
+            points,
+            rollups,
+            pending: Vec::new(),
+            last_flush: Instant::now(),
+            pruned: String::new(),
+        };
+        state.prune()?;
+        let state = Arc::new(Mutex::new(state));
+
+        let (interval, _) = options.store.flush_after();
+        let background = Arc::downgrade(&state);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(state) = background.upgrade() else {
+                return;
+            };
+            let mut state = lock(&state);
+            if state.last_flush.elapsed() >= interval {
+                if let Err(err) = state.flush() {
+                    log::warn!("Unable to write the historian: {err}");
+                }
+            }
+        });
+        Ok(Historian { state })
+    }
+
+    /// Stores a point under its source and name; numbers (and numeric text) go into `value`,
+    /// other values into `text`.
+    pub fn record(&self, point: &Point) -> Result<(), HistorianError> {
+        let (value, text) = point.value.split();
+        let time = point
+            .measured()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as i64)
+            .unwrap_or(0);
+
+        let mut state = lock(&self.state);
+        let sample = Sample {
+            time,
+            source: point.source.clone(),
+            point: point.name.clone(),
+            value,
+            text,
+        };
+        for rollups in &mut state.rollups {
+            rollups.add(&sample);
+        }
+        state.pending.push(sample);
+        let (interval, batch) = state.store.flush_after();
+        if state.pending.len() >= batch || state.last_flush.elapsed() >= interval {
+            state.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), HistorianError> {
+        lock(&self.state).flush()
+    }
+}
+
+impl Drop for Historian {
+    fn drop(&mut self) {
+        let mut state = lock(&self.state);
+        for rollups in &mut state.rollups {
+            rollups.close(i64::MAX);
+        }
+        if let Err(err) = state.flush() {
+            log::warn!("Unable to write the historian: {err}");
+        }
+    }
+}
+
+impl State {
+    fn flush(&mut self) -> Result<(), HistorianError> {
+        self.last_flush = Instant::now();
+        let samples = std::mem::take(&mut self.pending);
+        self.points.write(&samples)?;
+        let now = now_millis();
+        for rollups in &mut self.rollups {
+            rollups.close(now);
+            let aggregates = std::mem::take(&mut rollups.pending);
+            rollups.partitions.write(&aggregates)?;
+        }
+        if self.pruned != day(now) {
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    fn prune(&mut self) -> Result<(), HistorianError> {
+        self.pruned = day(now_millis());
+        self.points.prune()?;
+        for rollups in &mut self.rollups {
+            rollups.partitions.prune()?;
+        }
+        Ok(())
+    }
+}
+
+impl Rollups {
+    /// Counts a sample's number into its interval, writing out the one before it if it's over.
+    fn add(&mut self, sample: &Sample) {
+        let Some(value) = sample.value else {
+            return;
+        };
+        let start = sample.time - sample.time.rem_euclid(self.interval);
+        let key = (sample.source.clone(), sample.point.clone());
+        // Its interval was written already.
+        if self.closed.get(&key).is_some_and(|closed| start <= *closed) {
+            return;
+        }
+        let fresh = || Aggregate {
+            time: start,
+            source: sample.source.clone(),
+            point: sample.point.clone(),
+            min: value,
+            max: value,
+            sum: 0.0,
+            count: 0,
+        };
+        let aggregate = self.open.entry(key.clone()).or_insert_with(fresh);
+        if start < aggregate.time {
+            // Late for its interval, which was closed when this one started.
+            return;
+        }
+        if start > aggregate.time {
+            self.closed.insert(key, aggregate.time);
+            self.pending.push(std::mem::replace(aggregate, fresh()));
+        }
+        aggregate.min = aggregate.min.min(value);
+        aggregate.max = aggregate.max.max(value);
+        aggregate.sum += value;
+        aggregate.count += 1;
+    }
+
+    /// Moves the intervals that are over by `now` to the ones waiting to be written.
+    fn close(&mut self, now: i64) {
+        let interval = self.interval;
+        let over: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(_, aggregate)| aggregate.time.saturating_add(interval) <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in over {
+            if let Some(aggregate) = self.open.remove(&key) {
+                self.closed.insert(key, aggregate.time);
+                self.pending.push(aggregate);
+            }
+        }
+        self.pending.sort_by_key(|aggregate| aggregate.time);
+    }
+}
+
+impl Partitions {
+    fn write(&mut self, rows: &[impl Row]) -> Result<(), HistorianError> {
+        // Rows arrive roughly in time order, so consecutive runs share a day.
+        let mut start = 0;
+        while start < rows.len() {
+            let day = day(rows[start].time());
+            let end = start
+                + rows[start..]
+                    .iter()
+                    .take_while(|row| self::day(row.time()) == day)
+                    .count();
+            self.write_day(&day, &rows[start..end])?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    fn write_day(&mut self, day: &str, rows: &[impl Row]) -> Result<(), HistorianError> {
+        match self.store {
+            Store::Sqlite => {
+                let path = self
+                    .directory
+                    .join(format!("{}-{day}.sqlite", self.table.name()));
+                let error = |err| HistorianError(path.clone(), err);
+                if self.database.as_ref().map(|(open, _)| open.as_str()) != Some(day) {
+                    let table = match self.table {
+                        Table::Points => &sqlite::POINTS,
+                        Table::Rollups => &sqlite::ROLLUPS,
+                    };
+                    self.database = None;
+                    self.database = Some((
+                        day.to_string(),
+                        sqlite::Database::open(&path, table).map_err(error)?,
+                    ));
+                }
+                let (_, database) = self.database.as_mut().expect("opened above");
+                for row in rows {
+                    database.insert(row).map_err(error)?;
+                }
+                database.flush().map_err(error)
+            }
+            Store::Parquet => {
+                static PART: AtomicU64 = AtomicU64::new(0);
+                let partition = self.directory.join(format!("date={day}"));
+                std::fs::create_dir_all(&partition)
+                    .map_err(|err| HistorianError(partition.clone(), err))?;
+                let path = partition.join(format!(
+                    "part-{}-{}.parquet",
+                    now_millis(),
+                    PART.fetch_add(1, Ordering::Relaxed)
+                ));
+                let columns: &[parquet::Column] = match self.table {
+                    Table::Points => &parquet::POINTS,
+                    Table::Rollups => &parquet::ROLLUPS,
+                };
+                parquet::write(&path, columns, rows).map_err(|err| HistorianError(path, err))
+            }
+        }
+    }
+
+    /// Deletes the days that ended longer than the retention ago.
+    fn prune(&mut self) -> Result<(), HistorianError> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let error = |path: &Path, err| HistorianError(path.to_path_buf(), err);
+        let entries =
+            std::fs::read_dir(&self.directory).map_err(|err| error(&self.directory, err))?;
+        for entry in entries {
+            let entry = entry.map_err(|err| error(&self.directory, err))?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let day = match self.store {
+                Store::Sqlite => name
+                    .strip_prefix(self.table.name())
+                    .and_then(|rest| rest.strip_prefix('-'))
+                    .and_then(|rest| rest.strip_suffix(".sqlite")),
+                Store::Parquet => name.strip_prefix("date="),
+            };
+            let Some(start) =
+                day.and_then(|day| humantime::parse_rfc3339(&format!("{day}T00:00:00Z")).ok())
+            else {
+                continue;
+            };
+            if start + DAY + retention > SystemTime::now() {
+                continue;
+            }
+            let path = entry.path();
+            log::info!("Deleting historian partition {}", path.display());
+            if self.database.as_ref().map(|(open, _)| open.as_str()) == day {
+                self.database = None;
+            }
+            let removed = match self.store {
+                Store::Sqlite => std::fs::remove_file(&path),
+                Store::Parquet => std::fs::remove_dir_all(&path),
+            };
+            removed.map_err(|err| error(&path, err))?;
+        }
+        Ok(())
+    }
+}
+
+fn lock(state: &Mutex<State>) -> std::sync::MutexGuard<'_, State> {
+    match state.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// The UTC day of a time in milliseconds, e.g. `2026-10-14`.
+fn day(millis: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64);
+    humantime::format_rfc3339_seconds(time).to_string()[..10].to_string()
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,642 @@
+//! Writes historian samples and rollups as Parquet files: one row group, one uncompressed PLAIN
+//! data page per column, with the metadata in Thrift's compact protocol. That's the baseline
+//! every Parquet reader supports.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{Cell, Row};
+
+// Parquet physical types, repetitions, converted types and encodings used here.
+const INT64: i32 = 2;
+const DOUBLE: i32 = 5;
+const BYTE_ARRAY: i32 = 6;
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const UTF8: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+pub struct Column {
+    name: &'static str,
+    kind: i32,
+    optional: bool,
+    converted: Option<i32>,
+}
+
+pub const POINTS: [Column; 5] = [
+    Column {
+        name: "time",
+        kind: INT64,
+        optional: false,
+        converted: Some(TIMESTAMP_MILLIS),
+    },
+    Column {
+        name: "source",
+        kind: BYTE_ARRAY,
+        optional: false,
+        converted: Some(UTF8),
+    },
+    Column {
+        name: "point",
+        kind: BYTE_ARRAY,
+        optional: false,
+        converted: Some(UTF8),
+    },
+    Column {
+        name: "value",
+        kind: DOUBLE,
+        optional: true,
+        converted: None,
+    },
+    Column {
+        name: "text",
+        kind: BYTE_ARRAY,
+        optional: true,
+        converted: Some(UTF8),
+    },
+];
+
+pub const ROLLUPS: [Column; 7] = [
+    Column {
+        name: "time",
+        kind: INT64,
+        optional: false,
+        converted: Some(TIMESTAMP_MILLIS),
+    },
+    Column {
+        name: "source",
+        kind: BYTE_ARRAY,
+        optional: false,
+        converted: Some(UTF8),
+    },
+    Column {
+        name: "point",
+        kind: BYTE_ARRAY,
+        optional: false,
+        converted: Some(UTF8),
+    },
+    Column {
+        name: "min",
+        kind: DOUBLE,
+        optional: false,
+        converted: None,
+    },
+    Column {
+        name: "max",
+        kind: DOUBLE,
+        optional: false,
+        converted: None,
+    },
+    Column {
+        name: "avg",
+        kind: DOUBLE,
+        optional: false,
+        converted: None,
+    },
+    Column {
+        name: "count",
+        kind: INT64,
+        optional: false,
+        converted: None,
+    },
+];
+
+/// Writes `rows` with `columns` to `path`, through a temporary file so readers never see half a
+/// file.
+pub fn write(path: &Path, columns: &[Column], rows: &[impl Row]) -> io::Result<()> {
+    let rows: Vec<Vec<Cell>> = rows.iter().map(Row::cells).collect();
+    let mut file = b"PAR1".to_vec();
+    let mut chunks = Vec::new();
+    for (index, column) in columns.iter().enumerate() {
+        let offset = file.len();
+        let page = page(index, column, &rows);
+        let mut header = Thrift::new();
+        // PageHeader: DATA_PAGE, sizes, DataPageHeader.
+        header.i32(1, 0);
+        header.i32(2, page.len() as i32);
+        header.i32(3, page.len() as i32);
+        header.begin_struct(5);
+        header.i32(1, rows.len() as i32);
+        header.i32(2, PLAIN);
+        header.i32(3, RLE);
+        header.i32(4, RLE);
+        header.end_struct();
+        header.end();
+        file.extend_from_slice(&header.out);
+        file.extend_from_slice(&page);
+        chunks.push((offset, file.len() - offset));
+    }
+
+    let mut meta = Thrift::new();
+    meta.i32(1, 1);
+    meta.list(2, 12, columns.len() + 1);
+    meta.element_struct();
+    meta.binary(4, b"schema");
+    meta.i32(5, columns.len() as i32);
+    meta.end_struct();
+    for column in columns {
+        meta.element_struct();
+        meta.i32(1, column.kind);
+        meta.i32(3, if column.optional { OPTIONAL } else { REQUIRED });
+        meta.binary(4, column.name.as_bytes());
+        if let Some(converted) = column.converted {
+            meta.i32(6, converted);
+        }
+        meta.end_struct();
+    }
+    meta.i64(3, rows.len() as i64);
+    meta.list(4, 12, 1);
+    meta.element_struct();
+    meta.list(1, 12, columns.len());
+    for (column, (offset, size)) in columns.iter().zip(&chunks) {
+        meta.element_struct();
+        meta.i64(2, *offset as i64);
+        meta.begin_struct(3);
+        meta.i32(1, column.kind);
+        meta.list(2, 5, 2);
+        meta.element_i32(PLAIN);
+        meta.element_i32(RLE);
+        meta.list(3, 8, 1);
+        meta.element_binary(column.name.as_bytes());
+        // UNCOMPRESSED
+        meta.i32(4, 0);
+        meta.i64(5, rows.len() as i64);
+        meta.i64(6, *size as i64);
+        meta.i64(7, *size as i64);
+        meta.i64(9, *offset as i64);
+        meta.end_struct();
+        meta.end_struct();
+    }
+    let total: usize = chunks.iter().map(|(_, size)| size).sum();
+    meta.i64(2, total as i64);
+    meta.i64(3, rows.len() as i64);
+    meta.end_struct();
+    meta.binary(
+        6,
+        concat!("edge_tools historian ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    meta.end();
+
+    file.extend_from_slice(&meta.out);
+    file.extend_from_slice(&(meta.out.len() as u32).to_le_bytes());
+    file.extend_from_slice(b"PAR1");
+
+    let temporary = path.with_extension("parquet.tmp");
+    fs::write(&temporary, &file)?;
+    fs::rename(&temporary, path)
+}
+
+/// Definition levels for optional columns, then the non-null values.
+fn page(index: usize, column: &Column, rows: &[Vec<Cell>]) -> Vec<u8> {
+    let mut values = Vec::new();
+    let mut present = Vec::with_capacity(rows.len());
+    for row in rows {
+        let written = match row[index] {
+            Cell::Integer(value) => {
+                values.extend_from_slice(&value.to_le_bytes());
+                true
+            }
+            Cell::Real(value) => {
+                values.extend_from_slice(&value.to_le_bytes());
+                true
+            }
+            Cell::Text(text) => {
+                values.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                values.extend_from_slice(text.as_bytes());
+                true
+            }
+            Cell::Null => false,
+        };
+        present.push(written);
+    }
+    if !column.optional {
+        return values;
+    }
+    let levels = levels(&present);
+    let mut page = (levels.len() as u32).to_le_bytes().to_vec();
+    page.extend(levels);
+    page.extend(values);
+    page
+}
+
+/// Definition levels in the RLE/bit-packing hybrid, as RLE runs of a 1-bit width.
+fn levels(present: &[bool]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut index = 0;
+    while index < present.len() {
+        let value = present[index];
+        let run = present[index..]
+            .iter()
+            .take_while(|level| **level == value)
+            .count();
+        uleb(run as u64 * 2, &mut out);
+        out.push(u8::from(value));
+        index += run;
+    }
+    out
+}
+
+/// Thrift compact protocol, just what the Parquet footer and page headers need.
+struct Thrift {
+    out: Vec<u8>,
+    // Last field id for each struct being written.
+    last: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Thrift {
+        Thrift {
+            out: Vec::new(),
+            last: vec![0],
+        }
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last.last_mut().expect("inside a struct");
+        let delta = id - *last;
+        if (1..=15).contains(&delta) {
+            self.out.push(((delta as u8) << 4) | kind);
+        } else {
+            self.out.push(kind);
+            uleb(zigzag(i64::from(id)), &mut self.out);
+        }
+        *last = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, 5);
+        uleb(zigzag(i64::from(value)), &mut self.out);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, 6);
+        uleb(zigzag(value), &mut self.out);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, 8);
+        self.element_binary(value);
+    }
+
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, 12);
+        self.last.push(0);
+    }
+
+    fn end_struct(&mut self) {
+        self.out.push(0);
+        self.last.pop();
+    }
+
+    /// Ends the outermost struct.
+    fn end(&mut self) {
+        self.out.push(0);
+    }
+
+    fn list(&mut self, id: i16, kind: u8, size: usize) {
+        self.field(id, 9);
+        if size < 15 {
+            self.out.push(((size as u8) << 4) | kind);
+        } else {
+            self.out.push(0xf0 | kind);
+            uleb(size as u64, &mut self.out);
+        }
+    }
+
+    fn element_struct(&mut self) {
+        self.last.push(0);
+    }
+
+    fn element_i32(&mut self, value: i32) {
+        uleb(zigzag(i64::from(value)), &mut self.out);
+    }
+
+    fn element_binary(&mut self, value: &[u8]) {
+        uleb(value.len() as u64, &mut self.out);
+        self.out.extend_from_slice(value);
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn uleb(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::historian::{Aggregate, Sample};
+    use std::path::PathBuf;
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "edge-historian-{name}-{}.parquet",
+            std::process::id()
+        ))
+    }
+
+    fn samples() -> Vec<Sample> {
+        (0..40)
+            .map(|index| Sample {
+                time: 1_792_000_000_000 + index * 1000,
+                source: "10.0.0.5".to_string(),
+                point: format!("feeder{}.voltage", index % 3),
+                // A run of values, a run of text, then alternating.
+                value: (index < 10 || (index >= 20 && index % 2 == 0))
+                    .then_some(index as f64 * 1.5),
+                text: (index >= 10 && (index < 20 || index % 2 == 1))
+                    .then(|| format!("state {index}")),
+            })
+            .collect()
+    }
+
+    /// A value in Thrift's compact protocol, as far as Parquet metadata uses it.
+    #[derive(Debug, PartialEq)]
+    enum Field {
+        Integer(i64),
+        Binary(Vec<u8>),
+        List(Vec<Field>),
+        Struct(Vec<(i16, Field)>),
+    }
+
+    impl Field {
+        fn get(&self, id: i16) -> &Field {
+            let Field::Struct(fields) = self else {
+                panic!("{self:?} isn't a struct");
+            };
+            match fields.iter().find(|(field, _)| *field == id) {
+                Some((_, value)) => value,
+                None => panic!("no field {id} in {self:?}"),
+            }
+        }
+
+        fn integer(&self, id: i16) -> i64 {
+            match self.get(id) {
+                Field::Integer(value) => *value,
+                other => panic!("field {id} is {other:?}"),
+            }
+        }
+
+        fn text(&self, id: i16) -> &str {
+            match self.get(id) {
+                Field::Binary(value) => std::str::from_utf8(value).unwrap(),
+                other => panic!("field {id} is {other:?}"),
+            }
+        }
+
+        fn list(&self, id: i16) -> &[Field] {
+            match self.get(id) {
+                Field::List(values) => values,
+                other => panic!("field {id} is {other:?}"),
+            }
+        }
+    }
+
+    fn read_uleb(data: &[u8], at: &mut usize) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = data[*at];
+            *at += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    fn read_zigzag(data: &[u8], at: &mut usize) -> i64 {
+        let value = read_uleb(data, at);
+        (value >> 1) as i64 ^ -((value & 1) as i64)
+    }
+
+    fn read_value(data: &[u8], at: &mut usize, kind: u8) -> Field {
+        match kind {
+            5 | 6 => Field::Integer(read_zigzag(data, at)),
+            8 => {
+                let length = read_uleb(data, at) as usize;
+                *at += length;
+                Field::Binary(data[*at - length..*at].to_vec())
+            }
+            9 => {
+                let header = data[*at];
+                *at += 1;
+                let size = match header >> 4 {
+                    15 => read_uleb(data, at) as usize,
+                    size => usize::from(size),
+                };
+                Field::List(
+                    (0..size)
+                        .map(|_| read_value(data, at, header & 0x0f))
+                        .collect(),
+                )
+            }
+            12 => read_struct(data, at),
+            _ => panic!("unexpected compact type {kind} at {at}"),
+        }
+    }
+
+    fn read_struct(data: &[u8], at: &mut usize) -> Field {
+        let (mut fields, mut last) = (Vec::new(), 0);
+        loop {
+            let header = data[*at];
+            *at += 1;
+            if header == 0 {
+                return Field::Struct(fields);
+            }
+            let id = match header >> 4 {
+                0 => read_zigzag(data, at) as i16,
+                delta => last + i16::from(delta),
+            };
+            assert!(id > last, "field {id} after {last}");
+            last = id;
+            fields.push((id, read_value(data, at, header & 0x0f)));
+        }
+    }
+
+    /// The footer of `file`, after checking its magic numbers.
+    fn footer(file: &[u8]) -> Field {
+        assert_eq!(&file[..4], b"PAR1");
+        assert_eq!(&file[file.len() - 4..], b"PAR1");
+        let length = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        let start = file.len() - 8 - length as usize;
+        let mut at = start;
+        let meta = read_struct(file, &mut at);
+        assert_eq!(at, file.len() - 8);
+        meta
+    }
+
+    /// The values of column `index` in the file: `None` for nulls.
+    fn column(file: &[u8], meta: &Field, index: usize) -> Vec<Option<Vec<u8>>> {
+        let schema = &meta.list(2)[index + 1];
+        let chunk = &meta.list(4)[0].list(1)[index];
+        let chunk_meta = chunk.get(3);
+        let offset = chunk.integer(2) as usize;
+        assert_eq!(chunk_meta.integer(9) as usize, offset);
+        assert_eq!(chunk_meta.integer(1), schema.integer(1));
+        assert_eq!(chunk_meta.integer(4), 0, "uncompressed");
+
+        let mut at = offset;
+        let header = read_struct(file, &mut at);
+        assert_eq!(header.integer(1), 0, "a data page");
+        let size = header.integer(3) as usize;
+        assert_eq!(header.integer(2) as usize, size);
+        assert_eq!(
+            at + size - offset,
+            chunk_meta.integer(6) as usize,
+            "the chunk is the header and the page"
+        );
+        let rows = header.get(5).integer(1) as usize;
+        assert_eq!(header.get(5).integer(2), PLAIN as i64);
+        let page = &file[at..at + size];
+
+        let mut at = 0;
+        let present = match schema.integer(3) as i32 {
+            REQUIRED => vec![true; rows],
+            _ => {
+                let length = u32::from_le_bytes(page[..4].try_into().unwrap()) as usize;
+                let mut levels = 4;
+                let mut present = Vec::new();
+                while levels < 4 + length {
+                    let header = read_uleb(page, &mut levels);
+                    assert_eq!(header & 1, 0, "an RLE run");
+                    let value = page[levels] == 1;
+                    levels += 1;
+                    present.extend(std::iter::repeat_n(value, header as usize / 2));
+                }
+                at = 4 + length;
+                present
+            }
+        };
+        assert_eq!(present.len(), rows);
+        let values = present
+            .into_iter()
+            .map(|present| {
+                present.then(|| {
+                    let width = match schema.integer(1) as i32 {
+                        BYTE_ARRAY => {
+                            at += 4;
+                            u32::from_le_bytes(page[at - 4..at].try_into().unwrap()) as usize
+                        }
+                        _ => 8,
+                    };
+                    at += width;
+                    page[at - width..at].to_vec()
+                })
+            })
+            .collect();
+        assert_eq!(at, page.len());
+        values
+    }
+
+    #[test]
+    fn writes_a_readable_footer() {
+        let path = scratch("footer");
+        write(&path, &POINTS, &samples()).unwrap();
+        let file = fs::read(&path).unwrap();
+        assert!(!path.with_extension("parquet.tmp").exists());
+        let meta = footer(&file);
+
+        assert_eq!(meta.integer(1), 1);
+        assert_eq!(meta.integer(3), 40);
+        assert!(meta.text(6).starts_with("edge_tools historian "));
+        let schema = meta.list(2);
+        assert_eq!(schema[0].text(4), "schema");
+        assert_eq!(schema[0].integer(5), POINTS.len() as i64);
+        let names: Vec<&str> = schema[1..].iter().map(|element| element.text(4)).collect();
+        assert_eq!(names, ["time", "source", "point", "value", "text"]);
+        for (element, column) in schema[1..].iter().zip(&POINTS) {
+            assert_eq!(element.integer(1), i64::from(column.kind));
+            let repetition = if column.optional { OPTIONAL } else { REQUIRED };
+            assert_eq!(element.integer(3), i64::from(repetition));
+            if let Some(converted) = column.converted {
+                assert_eq!(element.integer(6), i64::from(converted));
+            }
+        }
+
+        let groups = meta.list(4);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].integer(3), 40);
+        let chunks = groups[0].list(1);
+        assert_eq!(chunks.len(), POINTS.len());
+        let total: i64 = chunks.iter().map(|chunk| chunk.get(3).integer(6)).sum();
+        assert_eq!(groups[0].integer(2), total);
+        for (chunk, name) in chunks.iter().zip(names) {
+            assert_eq!(chunk.get(3).list(3), [Field::Binary(name.into())]);
+            assert_eq!(chunk.get(3).integer(5), 40);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_back_every_column() {
+        let path = scratch("columns");
+        let samples = samples();
+        write(&path, &POINTS, &samples).unwrap();
+        let file = fs::read(&path).unwrap();
+        let meta = footer(&file);
+
+        let number = |value: &Option<Vec<u8>>| {
+            value
+                .as_ref()
+                .map(|bytes| <[u8; 8]>::try_from(bytes.as_slice()).unwrap())
+        };
+        let text = |value: &Option<Vec<u8>>| {
+            value
+                .as_ref()
+                .map(|bytes| String::from_utf8(bytes.clone()).unwrap())
+        };
+        let columns: Vec<_> = (0..POINTS.len())
+            .map(|index| column(&file, &meta, index))
+            .collect();
+        for (row, sample) in samples.iter().enumerate() {
+            let time = number(&columns[0][row]).map(i64::from_le_bytes);
+            assert_eq!(time, Some(sample.time));
+            assert_eq!(text(&columns[1][row]).as_ref(), Some(&sample.source));
+            assert_eq!(text(&columns[2][row]).as_ref(), Some(&sample.point));
+            assert_eq!(
+                number(&columns[3][row]).map(f64::from_le_bytes),
+                sample.value
+            );
+            assert_eq!(text(&columns[4][row]), sample.text);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writes_rollups() {
+        let path = scratch("rollups");
+        let rows = [Aggregate {
+            time: 1_792_000_000_000,
+            source: "10.0.0.5".to_string(),
+            point: "feeder1.voltage".to_string(),
+            min: 229.5,
+            max: 231.0,
+            sum: 690.5,
+            count: 3,
+        }];
+        write(&path, &ROLLUPS, &rows).unwrap();
+        let file = fs::read(&path).unwrap();
+        let meta = footer(&file);
+        assert_eq!(meta.list(2).len(), ROLLUPS.len() + 1);
+        let average = column(&file, &meta, 5)[0].clone().unwrap();
+        assert_eq!(f64::from_le_bytes(average.try_into().unwrap()), 690.5 / 3.0);
+        let count = column(&file, &meta, 6)[0].clone().unwrap();
+        assert_eq!(i64::from_le_bytes(count.try_into().unwrap()), 3);
+        fs::remove_file(&path).unwrap();
+    }
+}
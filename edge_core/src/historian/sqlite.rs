@@ -0,0 +1,665 @@
+//! Writes historian samples and rollups into SQLite database files without linking SQLite.
+//!
+//! Rows only ever go at the end of the file's single table, so the writer keeps the rightmost
+//! path of the table's b-tree in memory and appends to it, splitting pages the way SQLite would
+//! when they fill. Nothing else about the file changes: it has no indexes, free pages or
+//! overflow pages, which is also what is checked before appending to an existing file.
+//!
+//! There is no rollback journal either: a flush rewrites the rightmost pages in place and then
+//! the header. A crash or power cut partway through can leave a file SQLite reports as
+//! malformed, and with it that day's samples.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{Cell, Row};
+
+const PAGE: usize = 4096;
+// The table; page 1 holds the header and the schema table.
+const ROOT: u32 = 2;
+const LEAF: u8 = 0x0d;
+const INTERIOR: u8 = 0x05;
+
+/// The table a database holds.
+pub struct Table {
+    name: &'static str,
+    schema: &'static str,
+}
+
+pub const POINTS: Table = Table {
+    name: "points",
+    schema: "CREATE TABLE points(time INTEGER, source TEXT, point TEXT, value REAL, text TEXT)",
+};
+
+pub const ROLLUPS: Table = Table {
+    name: "rollups",
+    schema: "CREATE TABLE rollups(time INTEGER, source TEXT, point TEXT, min REAL, max REAL, \
+             avg REAL, count INTEGER)",
+};
+// Longer text is cut, with a warning, so every row fits in its page without overflow pages.
+const MAX_TEXT: usize = 1000;
+
+pub struct Database {
+    file: File,
+    pages: u32,
+    // Root first, leaf last; these are the only pages that still change.
+    path: Vec<Node>,
+    next_rowid: i64,
+    change_counter: u32,
+}
+
+struct Node {
+    page: u32,
+    data: Vec<u8>,
+}
+
+impl Database {
+    /// Opens a database of `table` this writer created earlier, or creates a new one.
+    pub fn open(path: &Path, table: &'static Table) -> io::Result<Database> {
+        if path.exists() {
+            Database::reopen(path, table)
+        } else {
+            Database::create(path, table)
+        }
+    }
+
+    fn create(path: &Path, table: &'static Table) -> io::Result<Database> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let mut database = Database {
+            file,
+            pages: 2,
+            path: vec![Node {
+                page: ROOT,
+                data: empty_page(LEAF, 0),
+            }],
+            next_rowid: 1,
+            change_counter: 0,
+        };
+
+        let mut schema = empty_page(LEAF, 100);
+        // type, name, tbl_name, rootpage, sql
+        let row = record(&[
+            Cell::Text("table"),
+            Cell::Text(table.name),
+            Cell::Text(table.name),
+            Cell::Integer(i64::from(ROOT)),
+            Cell::Text(table.schema),
+        ]);
+        add_cell(&mut schema, 100, &leaf_cell(1, &row));
+        database.write_page(1, &schema)?;
+        database.flush()?;
+        Ok(database)
+    }
+
+    fn reopen(path: &Path, table: &'static Table) -> io::Result<Database> {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} {message}", path.display()),
+            )
+        };
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut first = vec![0u8; PAGE];
+        file.read_exact(&mut first)?;
+        if &first[..16] != b"SQLite format 3\0" || be16(&first, 16) as usize != PAGE {
+            return Err(invalid("is not a historian database"));
+        }
+        let schema = String::from_utf8_lossy(&first[100..]);
+        if !schema.contains(table.schema) || be32(&first, 36) != 0 || be32(&first, 32) != 0 {
+            return Err(invalid(
+                "was changed by another program and can't be appended to",
+            ));
+        }
+        let pages = (file.metadata()?.len() / PAGE as u64) as u32;
+
+        let mut database = Database {
+            file,
+            pages,
+            path: Vec::new(),
+            next_rowid: 1,
+            change_counter: be32(&first, 24),
+        };
+        let mut page = ROOT;
+        loop {
+            let data = database.read_page(page)?;
+            let kind = data[0];
+            let right = be32(&data, 8);
+            database.path.push(Node { page, data });
+            match kind {
+                INTERIOR if right > 0 && right <= pages && database.path.len() < 20 => page = right,
+                LEAF => break,
+                _ => return Err(invalid(&format!("has a damaged {} table", table.name))),
+            }
+        }
+        let leaf = &database.path[database.path.len() - 1].data;
+        let cells = be16(leaf, 3) as usize;
+        if cells > 0 {
+            let offset = be16(leaf, 8 + 2 * (cells - 1)) as usize;
+            let (_, used) = read_varint(&leaf[offset..]);
+            let (rowid, _) = read_varint(&leaf[offset + used..]);
+            database.next_rowid = rowid as i64 + 1;
+        }
+        Ok(database)
+    }
+
+    pub fn insert(&mut self, row: &impl Row) -> io::Result<()> {
+        let cells: Vec<Cell> = row
+            .cells()
+            .into_iter()
+            .map(|cell| match cell {
+                Cell::Text(text) if text.len() > MAX_TEXT => {
+                    log::warn!(
+                        "Cutting {} bytes of text to {MAX_TEXT} to fit a SQLite page",
+                        text.len()
+                    );
+                    Cell::Text(truncate(text))
+                }
+                other => other,
+            })
+            .collect();
+        let row = record(&cells);
+        let rowid = self.next_rowid;
+        let cell = leaf_cell(rowid, &row);
+
+        let leaf = self.path.len() - 1;
+        if !fits(&self.path[leaf].data, 0, cell.len()) {
+            self.new_leaf(rowid - 1)?;
+        }
+        let leaf = self.path.len() - 1;
+        add_cell(&mut self.path[leaf].data, 0, &cell);
+        self.next_rowid += 1;
+        Ok(())
+    }
+
+    /// Writes the pages on the rightmost path and then the header, so readers see a
+    /// consistent file once the header changes; without a journal, not after a crash midway.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for index in 0..self.path.len() {
+            let (page, data) = (self.path[index].page, self.path[index].data.clone());
+            self.write_page(page, &data)?;
+        }
+        self.change_counter = self.change_counter.wrapping_add(1);
+        let mut header = [0u8; 100];
+        header[..16].copy_from_slice(b"SQLite format 3\0");
+        header[16..18].copy_from_slice(&(PAGE as u16).to_be_bytes());
+        // Legacy (rollback journal) file format, no reserved bytes, fixed payload fractions.
+        header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        header[24..28].copy_from_slice(&self.change_counter.to_be_bytes());
+        header[28..32].copy_from_slice(&self.pages.to_be_bytes());
+        // Schema cookie and schema format 4.
+        header[40..44].copy_from_slice(&1u32.to_be_bytes());
+        header[44..48].copy_from_slice(&4u32.to_be_bytes());
+        // UTF-8 text.
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[92..96].copy_from_slice(&self.change_counter.to_be_bytes());
+        header[96..100].copy_from_slice(&3_040_000u32.to_be_bytes());
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.sync_data()
+    }
+
+    /// Closes the full leaf, whose largest rowid is `last`, and starts an empty one.
+    fn new_leaf(&mut self, last: i64) -> io::Result<()> {
+        if self.path.len() == 1 {
+            self.grow_root()?;
+        }
+        let page = self.allocate();
+        let closed = self.path.pop().expect("a leaf");
+        self.write_page(closed.page, &closed.data)?;
+        self.attach(self.path.len() - 1, page, last)?;
+        self.path.push(Node {
+            page,
+            data: empty_page(LEAF, 0),
+        });
+        Ok(())
+    }
+
+    /// Makes `child` the rightmost child of the interior node at `level`; the current
+    /// rightmost child, whose largest rowid is `last`, becomes an ordinary cell.
+    fn attach(&mut self, level: usize, child: u32, last: i64) -> io::Result<()> {
+        let cell = interior_cell(be32(&self.path[level].data, 8), last);
+        if !fits(&self.path[level].data, 0, cell.len()) {
+            if level == 0 {
+                self.grow_root()?;
+                return self.attach(1, child, last);
+            }
+            // Close this node too and continue in a new one at the same depth.
+            let page = self.allocate();
+            let closed = std::mem::replace(
+                &mut self.path[level],
+                Node {
+                    page,
+                    data: empty_page(INTERIOR, 0),
+                },
+            );
+            self.write_page(closed.page, &closed.data)?;
+            let depth = self.path.len();
+            self.attach(level - 1, page, last)?;
+            // Growing the root pushes this node one level down.
+            let level = level + self.path.len() - depth;
+            set_right(&mut self.path[level].data, child);
+            return Ok(());
+        }
+        add_cell(&mut self.path[level].data, 0, &cell);
+        set_right(&mut self.path[level].data, child);
+        Ok(())
+    }
+
+    /// The root page can't move, so its contents move to a new page below it.
+    fn grow_root(&mut self) -> io::Result<()> {
+        let page = self.allocate();
+        let data = std::mem::replace(&mut self.path[0].data, empty_page(INTERIOR, 0));
+        set_right(&mut self.path[0].data, page);
+        self.path.insert(1, Node { page, data });
+        Ok(())
+    }
+
+    fn allocate(&mut self) -> u32 {
+        self.pages += 1;
+        self.pages
+    }
+
+    fn read_page(&mut self, page: u32) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; PAGE];
+        self.file
+            .seek(SeekFrom::Start(u64::from(page - 1) * PAGE as u64))?;
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_page(&mut self, page: u32, data: &[u8]) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(u64::from(page - 1) * PAGE as u64))?;
+        self.file.write_all(data)
+    }
+}
+
+/// SQLite's record format: a header of serial types, then the values.
+fn record(cells: &[Cell]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for cell in cells {
+        let serial = match cell {
+            Cell::Null => 0,
+            Cell::Integer(0) => 8,
+            Cell::Integer(1) => 9,
+            Cell::Integer(value) => {
+                let (serial, width) = match *value {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&value.to_be_bytes()[8 - width..]);
+                serial
+            }
+            Cell::Real(value) => {
+                body.extend_from_slice(&value.to_be_bytes());
+                7
+            }
+            Cell::Text(text) => {
+                body.extend_from_slice(text.as_bytes());
+                13 + 2 * text.len() as u64
+            }
+        };
+        varint(serial, &mut types);
+    }
+    // The header length counts itself; one byte is enough for either table's columns.
+    let mut out = Vec::with_capacity(1 + types.len() + body.len());
+    varint(1 + types.len() as u64, &mut out);
+    out.extend(types);
+    out.extend(body);
+    out
+}
+
+fn leaf_cell(rowid: i64, payload: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(payload.len() + 12);
+    varint(payload.len() as u64, &mut cell);
+    varint(rowid as u64, &mut cell);
+    cell.extend_from_slice(payload);
+    cell
+}
+
+fn interior_cell(child: u32, key: i64) -> Vec<u8> {
+    let mut cell = child.to_be_bytes().to_vec();
+    varint(key as u64, &mut cell);
+    cell
+}
+
+/// A b-tree page with no cells; `start` is where its header begins (100 on page 1).
+fn empty_page(kind: u8, start: usize) -> Vec<u8> {
+    let mut data = vec![0u8; PAGE];
+    data[start] = kind;
+    // Cell content starts at the end of the page; 0 would mean 65536.
+    data[start + 5..start + 7].copy_from_slice(&(PAGE as u16).to_be_bytes());
+    data
+}
+
+fn header_size(data: &[u8], start: usize) -> usize {
+    if data[start] == INTERIOR {
+        12
+    } else {
+        8
+    }
+}
+
+fn fits(data: &[u8], start: usize, cell: usize) -> bool {
+    let cells = be16(data, start + 3) as usize;
+    let content = be16(data, start + 5) as usize;
+    start + header_size(data, start) + 2 * cells + 2 + cell <= content
+}
+
+/// Appends a cell; cells are placed from the end of the page towards its header.
+fn add_cell(data: &mut [u8], start: usize, cell: &[u8]) {
+    let cells = be16(data, start + 3) as usize;
+    let content = be16(data, start + 5) as usize - cell.len();
+    data[content..content + cell.len()].copy_from_slice(cell);
+    let pointer = start + header_size(data, start) + 2 * cells;
+    data[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    data[start + 3..start + 5].copy_from_slice(&(cells as u16 + 1).to_be_bytes());
+    data[start + 5..start + 7].copy_from_slice(&(content as u16).to_be_bytes());
+}
+
+fn set_right(data: &mut [u8], page: u32) {
+    data[8..12].copy_from_slice(&page.to_be_bytes());
+}
+
+fn truncate(text: &str) -> &str {
+    if text.len() <= MAX_TEXT {
+        return text;
+    }
+    let mut end = MAX_TEXT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// SQLite's big-endian varint; values here stay well below the 9-byte form.
+fn varint(mut value: u64, out: &mut Vec<u8>) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    out.extend(bytes);
+}
+
+fn read_varint(data: &[u8]) -> (u64, usize) {
+    let mut value = 0u64;
+    for (index, byte) in data.iter().take(9).enumerate() {
+        if index == 8 {
+            return ((value << 8) | u64::from(*byte), 9);
+        }
+        value = (value << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return (value, index + 1);
+        }
+    }
+    (value, data.len())
+}
+
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::historian::Sample;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scratch(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "edge-historian-{name}-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn sample(time: i64) -> Sample {
+        Sample {
+            time,
+            source: "10.0.0.5".to_string(),
+            point: format!("feeder{}.voltage", time % 7),
+            value: (time % 5 != 0).then_some(time as f64 / 4.0),
+            text: (time % 5 == 0).then(|| "tripped".repeat(time as usize % 300)),
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Null,
+        Integer(i64),
+        Real(f64),
+        Text(String),
+    }
+
+    fn expected(sample: &Sample) -> Vec<Value> {
+        vec![
+            Value::Integer(sample.time),
+            Value::Text(sample.source.clone()),
+            Value::Text(sample.point.clone()),
+            sample.value.map_or(Value::Null, Value::Real),
+            sample
+                .text
+                .as_deref()
+                .map_or(Value::Null, |text| Value::Text(truncate(text).to_string())),
+        ]
+    }
+
+    fn parse_record(payload: &[u8]) -> Vec<Value> {
+        let (header, mut at) = read_varint(payload);
+        let mut body = header as usize;
+        let mut values = Vec::new();
+        while at < header as usize {
+            let (serial, used) = read_varint(&payload[at..]);
+            at += used;
+            let integer = |width: usize| {
+                let mut bytes = [if payload[body] & 0x80 != 0 { 0xff } else { 0 }; 8];
+                bytes[8 - width..].copy_from_slice(&payload[body..body + width]);
+                i64::from_be_bytes(bytes)
+            };
+            let (value, width) = match serial {
+                0 => (Value::Null, 0),
+                1..=4 => (Value::Integer(integer(serial as usize)), serial as usize),
+                5 => (Value::Integer(integer(6)), 6),
+                6 => (Value::Integer(integer(8)), 8),
+                7 => (
+                    Value::Real(f64::from_be_bytes(
+                        payload[body..body + 8].try_into().unwrap(),
+                    )),
+                    8,
+                ),
+                8 => (Value::Integer(0), 0),
+                9 => (Value::Integer(1), 0),
+                _ => {
+                    assert!(serial >= 13 && serial % 2 == 1, "serial type {serial}");
+                    let length = (serial as usize - 13) / 2;
+                    let text = String::from_utf8(payload[body..body + length].to_vec()).unwrap();
+                    (Value::Text(text), length)
+                }
+            };
+            values.push(value);
+            body += width;
+        }
+        assert_eq!(body, payload.len());
+        values
+    }
+
+    /// The rows of the table b-tree under `page`, in order, checking each page on the way.
+    fn rows(file: &[u8], page: u32, seen: &mut Vec<u32>, out: &mut Vec<(i64, Vec<Value>)>) {
+        assert!(!seen.contains(&page), "page {page} used twice");
+        seen.push(page);
+        let data = &file[(page as usize - 1) * PAGE..page as usize * PAGE];
+        let cells = be16(data, 3) as usize;
+        let content = be16(data, 5) as usize;
+        let header = header_size(data, 0);
+        assert!(header + 2 * cells <= content);
+        for index in 0..cells {
+            let offset = be16(data, header + 2 * index) as usize;
+            assert!(offset >= content);
+            match data[0] {
+                LEAF => {
+                    let (length, used) = read_varint(&data[offset..]);
+                    let (rowid, more) = read_varint(&data[offset + used..]);
+                    let start = offset + used + more;
+                    let record = parse_record(&data[start..start + length as usize]);
+                    out.push((rowid as i64, record));
+                }
+                INTERIOR => {
+                    let (key, _) = read_varint(&data[offset + 4..]);
+                    rows(file, be32(data, offset), seen, out);
+                    assert_eq!(out.last().unwrap().0, key as i64);
+                }
+                kind => panic!("page {page} is of type {kind:#x}"),
+            }
+        }
+        if data[0] == INTERIOR {
+            rows(file, be32(data, 8), seen, out);
+        }
+    }
+
+    /// Checks the header and schema of the database at `path` and returns its rows.
+    fn read(path: &Path) -> Vec<(i64, Vec<Value>)> {
+        let file = fs::read(path).unwrap();
+        assert_eq!(&file[..16], b"SQLite format 3\0");
+        assert_eq!(be16(&file, 16) as usize, PAGE);
+        assert_eq!(file.len() % PAGE, 0);
+        let pages = be32(&file, 28);
+        assert_eq!(pages as usize, file.len() / PAGE);
+        // The page count is only trusted while the change counters agree.
+        assert_eq!(be32(&file, 24), be32(&file, 92));
+        // No free pages, UTF-8.
+        assert_eq!((be32(&file, 32), be32(&file, 36)), (0, 0));
+        assert_eq!(be32(&file, 56), 1);
+
+        assert_eq!((file[100], be16(&file, 103)), (LEAF, 1));
+        let offset = be16(&file, 108) as usize;
+        let (length, used) = read_varint(&file[offset..]);
+        let (rowid, more) = read_varint(&file[offset + used..]);
+        let start = offset + used + more;
+        assert_eq!(rowid, 1);
+        assert_eq!(
+            parse_record(&file[start..start + length as usize]),
+            [
+                Value::Text("table".to_string()),
+                Value::Text("points".to_string()),
+                Value::Text("points".to_string()),
+                Value::Integer(i64::from(ROOT)),
+                Value::Text(POINTS.schema.to_string()),
+            ]
+        );
+
+        let (mut seen, mut out) = (vec![1], Vec::new());
+        rows(&file, ROOT, &mut seen, &mut out);
+        assert_eq!(seen.len() as u32, pages, "every page is in the table");
+        out
+    }
+
+    #[test]
+    fn writes_the_header_schema_and_rows() {
+        let path = scratch("table");
+        let mut database = Database::open(&path, &POINTS).unwrap();
+        for time in 0..3 {
+            database.insert(&sample(time)).unwrap();
+        }
+        database.flush().unwrap();
+        let rows = read(&path);
+        assert_eq!(rows.len(), 3);
+        for (time, (rowid, values)) in rows.iter().enumerate() {
+            assert_eq!(*rowid, time as i64 + 1);
+            assert_eq!(*values, expected(&sample(time as i64)));
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * PAGE as u64);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Enough rows for the root to grow twice, written across several reopenings.
+    #[test]
+    fn reopens_and_appends() {
+        let path = scratch("append");
+        let mut written = 0;
+        for batch in [1, 50, 5000, 40_000] {
+            let mut database = Database::open(&path, &POINTS).unwrap();
+            for time in written..written + batch {
+                database.insert(&sample(time)).unwrap();
+            }
+            database.flush().unwrap();
+            drop(database);
+            written += batch;
+
+            let rows = read(&path);
+            assert_eq!(rows.len() as i64, written);
+            for (time, (rowid, values)) in rows.into_iter().enumerate() {
+                assert_eq!(rowid, time as i64 + 1);
+                assert_eq!(values, expected(&sample(time as i64)));
+            }
+        }
+        let file = fs::read(&path).unwrap();
+        let root = &file[PAGE..2 * PAGE];
+        let child = be32(root, 8) as usize;
+        assert_eq!(root[0], INTERIOR);
+        assert_eq!(file[(child - 1) * PAGE], INTERIOR, "three levels");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncates_long_text_to_fit_a_page() {
+        let path = scratch("long");
+        let mut database = Database::open(&path, &POINTS).unwrap();
+        let mut long = sample(5);
+        long.text = Some("é".repeat(MAX_TEXT));
+        database.insert(&long).unwrap();
+        database.flush().unwrap();
+        let rows = read(&path);
+        let Value::Text(text) = &rows[0].1[4] else {
+            panic!("{:?}", rows[0]);
+        };
+        assert_eq!(text.len(), MAX_TEXT);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_files_it_cant_append_to() {
+        let path = scratch("refused");
+        fs::write(&path, vec![0; PAGE]).unwrap();
+        let err = Database::open(&path, &POINTS).err().unwrap();
+        assert!(
+            err.to_string().ends_with("is not a historian database"),
+            "{err}"
+        );
+        fs::remove_file(&path).unwrap();
+
+        Database::open(&path, &POINTS).unwrap();
+        let err = Database::open(&path, &ROLLUPS).err().unwrap();
+        assert!(err.to_string().ends_with("can't be appended to"), "{err}");
+
+        let mut file = fs::read(&path).unwrap();
+        file[36..40].copy_from_slice(&1u32.to_be_bytes());
+        fs::write(&path, &file).unwrap();
+        let err = Database::open(&path, &POINTS).err().unwrap();
+        assert!(err.to_string().ends_with("can't be appended to"), "{err}");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+//! Reads JSON into output values, the other half of [`crate::output::json`]. Objects keep
+//! their key order; integers stay integers when they fit.
+
+use std::fmt;
+
+use crate::output::{Record, Value};
+
+// Deeper documents are rejected rather than risk the stack.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub struct ParseError {
+    // Byte offset into the input.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses one JSON document; anything but whitespace after it is an error.
+pub fn parse(input: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        position: 0,
+    };
+    let value = parser.value(0)?;
+    parser.whitespace();
+    if parser.position < parser.input.len() {
+        return Err(parser.error("trailing characters after the value"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            offset: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.position) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.input.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), ParseError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, ParseError> {
+        if self.input[self.position..].starts_with(word.as_bytes()) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'{') => {
+                self.position += 1;
+                let mut record = Record::new();
+                if self.peek() == Some(b'}') {
+                    self.position += 1;
+                    return Ok(Value::Record(record));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected a string key"));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    let value = self.value(depth + 1)?;
+                    record.push(&key, value);
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b'}') => {
+                            self.position += 1;
+                            return Ok(Value::Record(record));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(Value::List(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+                            return Ok(Value::List(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.position;
+        let mut integer = true;
+        while let Some(&byte) = self.input.get(self.position) {
+            match byte {
+                b'0'..=b'9' | b'-' => {}
+                b'.' | b'e' | b'E' | b'+' => integer = false,
+                _ => break,
+            }
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.position])
+            .map_err(|_| self.error("invalid number"))?;
+        // Rust parses a few spellings JSON doesn't, like `01`, `1.` or `.5`.
+        let digits = text.strip_prefix('-').unwrap_or(text).as_bytes();
+        let leading_zero = digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit();
+        let bare_point = text.ends_with('.') || text.contains(".e") || text.contains(".E");
+        let valid = digits.first().is_some_and(u8::is_ascii_digit) && !leading_zero && !bare_point;
+        let invalid = || ParseError {
+            offset: start,
+            message: format!("invalid number {text}"),
+        };
+        if !valid {
+            return Err(invalid());
+        }
+        if integer {
+            if let Ok(value) = text.parse::<i64>() {
+                return Ok(Value::Integer(value));
+            }
+            if let Ok(value) = text.parse::<u64>() {
+                return Ok(Value::Unsigned(value));
+            }
+        }
+        text.parse::<f64>().map(Value::Float).map_err(|_| invalid())
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        // Opening quote, checked by the caller.
+        self.position += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.position;
+            while let Some(&byte) = self.input.get(self.position) {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.position += 1;
+            }
+            // The input is a str and the run stops at ASCII, so this is whole characters.
+            out.push_str(
+                std::str::from_utf8(&self.input[start..self.position])
+                    .map_err(|_| self.error("invalid UTF-8"))?,
+            );
+            match self.input.get(self.position) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escape = self
+                        .input
+                        .get(self.position)
+                        .copied()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.position += 1;
+                    match escape {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => out.push(self.unicode()?),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                Some(_) => return Err(self.error("control character in string")),
+            }
+        }
+    }
+
+    /// The character of a `\u` escape, joining surrogate pairs.
+    fn unicode(&mut self) -> Result<char, ParseError> {
+        let high = self.hex()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.input[self.position..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.position += 2;
+            let low = self.hex()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn hex(&mut self) -> Result<u32, ParseError> {
+        let digits = self
+            .input
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+}
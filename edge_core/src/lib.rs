@@ -0,0 +1,62 @@
+//! Plumbing shared by the edge tools: address resolution, credentials, secrets and profiles,
+//! output formatting, templates and JSON parsing, logging, the point model, connection pools,
+//! read caches, failover between redundant endpoints, watch loops, reloading edited files and
+//! clean shutdown, periodic stats, bounded queues, job schedules, retries, dry runs,
+//! confirmations, write guards and the audit log, expectations for acceptance tests, health
+//! checks, serial port names, fan-out over many targets, fatal error handling, message hooks
+//! and `--exec` commands, codec plugins, metrics, telemetry, a small MQTT client, a WebSocket
+//! server for live dashboards, time-series database sinks, files written in the background and
+//! the NDJSON envelope the tools pipe into each other.
+
+pub mod age;
+pub mod audit;
+pub mod auth;
+pub mod buffer;
+pub mod cache;
+pub mod capture;
+pub mod codec;
+pub mod completions;
+pub mod config;
+pub mod confirm;
+pub mod dry_run;
+pub mod envelope;
+pub mod exec;
+pub mod exit;
+pub mod expect;
+pub mod failover;
+pub mod fanout;
+pub mod file_sink;
+pub mod health;
+pub mod historian;
+pub mod json;
+pub mod logging;
+pub mod man;
+pub mod metrics;
+pub mod mqtt;
+pub mod net;
+pub mod output;
+pub mod plugin;
+pub mod point;
+pub mod pool;
+pub mod rate;
+pub mod reload;
+pub mod retry;
+pub mod schedule;
+pub mod script;
+pub mod secret;
+pub mod serial;
+pub mod shutdown;
+pub mod stats;
+pub mod telemetry;
+pub mod template;
+pub mod toml;
+pub mod tsdb;
+pub mod tui;
+pub mod units;
+pub mod watch;
+pub mod websocket;
+pub mod writes;
+pub mod yaml;
+pub mod zstd;
+
+pub use exit::OrExit;
@@ -0,0 +1,84 @@
+//! Log output for every tool: env_logger's text lines by default, or with `--log-format json`
+//! (or `EDGE_LOG_FORMAT=json`) one JSON object per line, ready to ship to a central store:
+//!
+//! ```text
+//! {"time":"2026-10-14T08:32:30.412Z","level":"WARN","tool":"modbus","subcommand":"read-register","target":"edge_core::retry","message":"Reading registers failed, retrying in 194ms: Connection refused (os error 111)"}
+//! ```
+//!
+//! `RUST_LOG` filters either way.
+
+use clap::{
+    value_parser, Arg, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches, ValueEnum,
+};
+use std::io::Write;
+use std::time::SystemTime;
+
+use crate::output::{self, Record, Value};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// `A`'s command with the logging, [`crate::expect`] and [`crate::writes`] options every tool
+/// takes.
+pub fn command<A: CommandFactory>() -> Command<'static> {
+    crate::writes::args(crate::expect::args(A::command())).arg(
+        Arg::new("log_format")
+            .long("log-format")
+            .value_name("LOG_FORMAT")
+            .help("Log format: text lines (default) or json, one object per line")
+            .global(true)
+            .env("EDGE_LOG_FORMAT")
+            .action(ArgAction::Set)
+            .value_parser(value_parser!(LogFormat)),
+    )
+}
+
+/// Parses the command line of `tool` and starts logging, errors only unless `RUST_LOG` says
+/// otherwise.
+pub fn parse<A: CommandFactory + FromArgMatches>(tool: &str) -> A {
+    let mut command = command::<A>();
+    let matches = command.get_matches_mut();
+    let args = A::from_arg_matches(&matches).unwrap_or_else(|err| err.format(&mut command).exit());
+    init(tool, matches.subcommand_name(), format(&matches), "error");
+    crate::expect::install(&matches);
+    crate::writes::install(&matches);
+    args
+}
+
+/// The log format asked for in `matches`, from a command built by [`command`].
+pub fn format(matches: &ArgMatches) -> LogFormat {
+    matches
+        .get_one::<LogFormat>("log_format")
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Installs the logger; `default_filter` applies when `RUST_LOG` isn't set.
+pub fn init(tool: &str, subcommand: Option<&str>, format: LogFormat, default_filter: &str) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter));
+    if format == LogFormat::Json {
+        let tool = tool.to_string();
+        let subcommand = subcommand.map(str::to_string);
+        builder.format(move |buf, record| {
+            let mut line = Record::new()
+                .field(
+                    "time",
+                    humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                )
+                .field("level", record.level().as_str())
+                .field("tool", tool.as_str());
+            if let Some(subcommand) = &subcommand {
+                line.push("subcommand", subcommand.as_str());
+            }
+            line.push("target", record.target());
+            line.push("message", record.args().to_string());
+            writeln!(buf, "{}", output::json(&Value::Record(line)))
+        });
+    }
+    builder.init();
+}
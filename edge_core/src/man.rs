@@ -0,0 +1,218 @@
+//! Section 1 man pages generated from the clap command tree, one page per command and
+//! subcommand (`modbus.1`, `modbus-read-register.1`, ...).
+
+use clap::{Arg, Command, CommandFactory};
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::completions::possible_values;
+use crate::exit::Code;
+use crate::OrExit;
+
+/// Writes the pages into `directory` (the current directory by default) and lists them.
+pub fn generate<A: CommandFactory>(bin_name: &str, directory: Option<&Path>) {
+    let directory = directory.unwrap_or_else(|| Path::new("."));
+    let written = write_all::<A>(bin_name, directory).or_exit(&format!(
+        "Unable to write man pages to {}",
+        directory.display()
+    ));
+    for path in written {
+        println!("{}", path.display());
+    }
+}
+
+/// Writes a page for `A` and each of its visible subcommands into `directory`.
+pub fn write_all<A: CommandFactory>(bin_name: &str, directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut command = crate::logging::command::<A>().name(bin_name.to_string());
+    command.build();
+    std::fs::create_dir_all(directory)?;
+
+    let mut pages = Vec::new();
+    render_tree(&command, &[bin_name.to_string()], &mut pages);
+    let mut written = Vec::new();
+    for (name, page) in pages {
+        let path = directory.join(format!("{name}.1"));
+        std::fs::write(&path, page)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn render_tree(command: &Command, path: &[String], pages: &mut Vec<(String, String)>) {
+    pages.push((path.join("-"), render(command, path)));
+    for sub in visible_subcommands(command) {
+        let mut path = path.to_vec();
+        path.push(sub.get_name().to_string());
+        render_tree(sub, &path, pages);
+    }
+}
+
+fn visible_subcommands<'a, 'help>(
+    command: &'a Command<'help>,
+) -> impl Iterator<Item = &'a Command<'help>> {
+    command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+}
+
+fn render(command: &Command, path: &[String]) -> String {
+    let name = path.join("-");
+    let version = command.get_version().unwrap_or(env!("CARGO_PKG_VERSION"));
+    let about = command
+        .get_long_about()
+        .or_else(|| command.get_about())
+        .unwrap_or("");
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ".TH {} 1 \"\" \"{} {version}\" \"Edge Tools\"",
+        name.to_uppercase(),
+        path[0]
+    );
+    let _ = writeln!(out, ".SH NAME");
+    match command.get_about() {
+        Some(about) => {
+            let _ = writeln!(out, "{} \\- {}", escape(&name), escape(about));
+        }
+        None => {
+            let _ = writeln!(out, "{}", escape(&name));
+        }
+    }
+
+    let _ = writeln!(out, ".SH SYNOPSIS");
+    let _ = write!(out, "\\fB{}\\fR", escape(&path.join(" ")));
+    let arguments: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+    if arguments.iter().any(|arg| !arg.is_positional()) {
+        out.push_str(" [\\fIOPTIONS\\fR]");
+    }
+    for arg in arguments.iter().filter(|arg| arg.is_positional()) {
+        let value = value_name(arg);
+        if arg.is_required_set() {
+            let _ = write!(out, " \\fI{value}\\fR");
+        } else {
+            let _ = write!(out, " [\\fI{value}\\fR]");
+        }
+    }
+    if visible_subcommands(command).next().is_some() {
+        out.push_str(" \\fISUBCOMMAND\\fR");
+    }
+    out.push('\n');
+
+    if !about.is_empty() {
+        let _ = writeln!(out, ".SH DESCRIPTION");
+        let _ = writeln!(out, "{}", escape(about));
+    }
+
+    if !arguments.is_empty() {
+        let _ = writeln!(out, ".SH OPTIONS");
+        for arg in &arguments {
+            let _ = writeln!(out, ".TP");
+            let _ = writeln!(out, "{}", synopsis(arg));
+            let mut help = arg
+                .get_long_help()
+                .or(arg.get_help())
+                .unwrap_or("")
+                .to_string();
+            let values = possible_values(arg);
+            if !values.is_empty() {
+                if !help.is_empty() {
+                    help.push(' ');
+                }
+                let _ = write!(help, "[possible values: {}]", values.join(", "));
+            }
+            if let Some(env) = arg.get_env() {
+                if !help.is_empty() {
+                    help.push(' ');
+                }
+                let _ = write!(help, "[env: {}]", env.to_string_lossy());
+            }
+            if !help.is_empty() {
+                let _ = writeln!(out, "{}", escape(&help));
+            }
+        }
+    }
+
+    let subcommands: Vec<&Command> = visible_subcommands(command).collect();
+    if !subcommands.is_empty() {
+        let _ = writeln!(out, ".SH SUBCOMMANDS");
+        for sub in &subcommands {
+            let _ = writeln!(out, ".TP");
+            let _ = writeln!(out, "\\fB{}\\fR", escape(sub.get_name()));
+            if let Some(about) = sub.get_about() {
+                let _ = writeln!(out, "{}", escape(about));
+            }
+        }
+    }
+
+    if path.len() == 1 {
+        let _ = writeln!(out, ".SH EXIT STATUS");
+        let _ = writeln!(out, ".TP\n0\nsuccess");
+        for code in Code::ALL {
+            let _ = writeln!(out, ".TP\n{}\n{}", code.status(), code.description());
+        }
+    }
+
+    let mut see_also: Vec<String> = Vec::new();
+    if path.len() > 1 {
+        see_also.push(path[..path.len() - 1].join("-"));
+    }
+    see_also.extend(
+        subcommands
+            .iter()
+            .map(|sub| format!("{name}-{}", sub.get_name())),
+    );
+    if !see_also.is_empty() {
+        let _ = writeln!(out, ".SH SEE ALSO");
+        let refs: Vec<String> = see_also
+            .iter()
+            .map(|page| format!("\\fB{}\\fR(1)", escape(page)))
+            .collect();
+        let _ = writeln!(out, "{}", refs.join(", "));
+    }
+    out
+}
+
+fn value_name(arg: &Arg) -> String {
+    match arg.get_value_names() {
+        Some(names) if !names.is_empty() => names.join(" "),
+        _ => arg.get_id().to_uppercase(),
+    }
+}
+
+fn synopsis(arg: &Arg) -> String {
+    if arg.is_positional() {
+        return format!("\\fI{}\\fR", escape(&value_name(arg)));
+    }
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("\\fB\\-{short}\\fR"));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut synopsis = names.join(", ");
+    if arg.is_takes_value_set() {
+        let _ = write!(synopsis, " \\fI{}\\fR", escape(&value_name(arg)));
+    }
+    synopsis
+}
+
+/// Escapes backslashes, hyphens and line-leading control characters for roff.
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{line}")
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,186 @@
+//! Prometheus metrics for `--metrics-listen`: gauges and counters kept in memory and served in
+//! the text exposition format on `GET /metrics`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Gauge,
+    Counter,
+}
+
+struct Family {
+    help: String,
+    kind: Kind,
+    // Keyed by the rendered label set, e.g. `{subject="a.b"}`.
+    samples: BTreeMap<String, f64>,
+}
+
+/// Shared by every task that reports values; clones point at the same metrics.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, help, Kind::Gauge, labels, |sample| *sample = value);
+    }
+
+    pub fn increment(&self, name: &str, help: &str, labels: &[(&str, &str)]) {
+        self.update(name, help, Kind::Counter, labels, |sample| *sample += 1.0);
+    }
+
+    fn update(
+        &self,
+        name: &str,
+        help: &str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        change: impl FnOnce(&mut f64),
+    ) {
+        let mut families = match self.families.lock() {
+            Ok(families) => families,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let family = families.entry(metric_name(name)).or_insert_with(|| Family {
+            help: help.to_string(),
+            kind,
+            samples: BTreeMap::new(),
+        });
+        change(family.samples.entry(label_set(labels)).or_insert(0.0));
+    }
+
+    /// Everything in the text exposition format, families sorted by name.
+    pub fn render(&self) -> String {
+        let families = match self.families.lock() {
+            Ok(families) => families,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.kind {
+                Kind::Gauge => "gauge",
+                Kind::Counter => "counter",
+            };
+            let _ = writeln!(out, "# HELP {name} {}", family.help.replace('\n', " "));
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in &family.samples {
+                let _ = writeln!(out, "{name}{labels} {}", sample(*value));
+            }
+        }
+        out
+    }
+
+    /// Binds `address` and serves the metrics in the background. Binding happens before this
+    /// returns, so a port already in use is reported to the caller.
+    pub async fn serve(&self, address: SocketAddr) -> io::Result<()> {
+        let listener = TcpListener::bind(address).await?;
+        log::info!("Serving metrics on http://{address}/metrics");
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let metrics = metrics.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = respond(stream, &metrics).await {
+                                log::debug!("Metrics request failed: {err}");
+                            }
+                        });
+                    }
+                    Err(err) => log::warn!("Unable to accept a metrics connection: {err}"),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    // Only the request line matters; headers are read and ignored.
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 || request.len() > 8192 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or("");
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") | ("HEAD", "/metrics") => ("200 OK", metrics.render()),
+        ("GET", _) | ("HEAD", _) => ("404 Not Found", "Metrics are served on /metrics\n".into()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Replaces characters Prometheus doesn't allow in metric and label names with `_`.
+pub fn metric_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if out.starts_with(|c: char| c.is_ascii_digit()) || out.is_empty() {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn label_set(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{value}\"", metric_name(name).replace(':', "_"))
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn sample(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        format!("{value}")
+    }
+}
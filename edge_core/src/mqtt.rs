@@ -0,0 +1,278 @@
+//! Just enough MQTT 3.1.1 to publish and subscribe to QoS 0 messages.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::audit;
+use crate::buffer::{self, Limits};
+use crate::stats;
+use crate::writes::{self, Write};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub struct MqttOptions {
+    pub address: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive: Duration,
+}
+
+/// A message received on a subscribed topic.
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+pub struct MqttClient {
+    options: MqttOptions,
+    writer: Option<OwnedWriteHalf>,
+    // Cleared by the reader task when the broker closes the connection.
+    connected: Arc<AtomicBool>,
+    filters: Vec<String>,
+    incoming: Option<buffer::Sender<MqttMessage>>,
+}
+
+impl MqttClient {
+    pub fn new(options: MqttOptions) -> MqttClient {
+        MqttClient {
+            options,
+            writer: None,
+            connected: Arc::new(AtomicBool::new(false)),
+            filters: Vec::new(),
+            incoming: None,
+        }
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        self.options.keep_alive
+    }
+
+    /// Subscribes to `filters` and returns the messages published on them, queued and size
+    /// checked by `limits`. The subscription is renewed whenever the client reconnects, which
+    /// [`MqttClient::ping`] does as needed.
+    pub async fn subscribe(
+        &mut self,
+        filters: &[String],
+        limits: Limits,
+    ) -> Result<buffer::Receiver<MqttMessage>, Error> {
+        let (sender, receiver) = buffer::queue(&format!("MQTT {}", filters.join(", ")), limits);
+        self.filters = filters.to_vec();
+        self.incoming = Some(sender);
+        self.writer = None;
+        self.send(&[PINGREQ, 0]).await?;
+        Ok(receiver)
+    }
+
+    /// Publishes `payload` to `topic`, if the [`crate::writes`] policy allows it, and logs it
+    /// to the [`crate::audit`] log.
+    pub async fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), Error> {
+        let (write, value) = (Write::Mqtt(topic), audit::payload(payload));
+        writes::check(&write, &value)?;
+        let result = self.send_publish(topic, payload).await;
+        audit::record(&write, &value, &result);
+        result
+    }
+
+    async fn send_publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), Error> {
+        let mut packet = Vec::with_capacity(topic.len() + payload.len() + 2);
+        push_string(&mut packet, topic);
+        packet.extend_from_slice(payload);
+        let packet = frame(PUBLISH, &packet);
+
+        // Reconnect once if the broker dropped us since the last publish.
+        if let Err(err) = self.send(&packet).await {
+            log::warn!("MQTT publish failed, reconnecting: {err}");
+            self.writer = None;
+            self.send(&packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the connection alive; subscribers reconnect here when the broker went away.
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        if self.writer.is_some() || self.incoming.is_some() {
+            self.send(&[PINGREQ, 0]).await?;
+        }
+        Ok(())
+    }
+
+    /// Connects now rather than on the first publish, to find out whether the broker takes us.
+    pub async fn connect_now(&mut self) -> Result<(), Error> {
+        self.send(&[PINGREQ, 0]).await
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        if !self.connected.load(Ordering::SeqCst) {
+            self.writer = None;
+        }
+        if self.writer.is_none() {
+            self.writer = Some(self.connect().await?);
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(packet).await?;
+        }
+        Ok(())
+    }
+
+    async fn connect(&mut self) -> Result<OwnedWriteHalf, Error> {
+        let stream = TcpStream::connect(&self.options.address).await?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let mut flags = 0x02; // clean session
+        let mut payload = Vec::new();
+        push_string(&mut payload, &self.options.client_id);
+        if let Some(username) = &self.options.username {
+            flags |= 0x80;
+            push_string(&mut payload, username);
+        }
+        if let Some(password) = &self.options.password {
+            flags |= 0x40;
+            push_string(&mut payload, password);
+        }
+
+        let mut body = Vec::new();
+        push_string(&mut body, "MQTT");
+        body.push(4); // protocol level 3.1.1
+        body.push(flags);
+        let keep_alive = self.options.keep_alive.as_secs().min(u16::MAX as u64) as u16;
+        body.extend_from_slice(&keep_alive.to_be_bytes());
+        body.extend_from_slice(&payload);
+        writer.write_all(&frame(CONNECT, &body)).await?;
+
+        let mut connack = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(10), reader.read_exact(&mut connack))
+            .await
+            .map_err(|_| "timed out waiting for CONNACK")??;
+        if connack[0] != CONNACK {
+            return Err(format!("expected CONNACK, got packet type {:#04x}", connack[0]).into());
+        }
+        if connack[3] != 0 {
+            return Err(format!("broker refused connection, return code {}", connack[3]).into());
+        }
+        log::info!("Connected to MQTT broker {}", self.options.address);
+        stats::connected(&self.options.address);
+
+        if !self.filters.is_empty() {
+            // Packet identifier 1, every filter at QoS 0.
+            let mut body = 1u16.to_be_bytes().to_vec();
+            for filter in &self.filters {
+                push_string(&mut body, filter);
+                body.push(0);
+            }
+            writer.write_all(&frame(SUBSCRIBE, &body)).await?;
+            log::info!("Subscribed to {}", self.filters.join(", "));
+        }
+
+        let connected = Arc::new(AtomicBool::new(true));
+        self.connected = connected.clone();
+        let incoming = self.incoming.clone();
+        tokio::spawn(async move {
+            match read_packets(&mut reader, incoming).await {
+                Ok(()) => log::info!("MQTT broker closed the connection"),
+                Err(err) => log::warn!("MQTT connection failed: {err}"),
+            }
+            connected.store(false, Ordering::SeqCst);
+        });
+
+        Ok(writer)
+    }
+}
+
+/// Forwards PUBLISH packets to `incoming` and drops everything else (SUBACK, PINGRESP).
+async fn read_packets(
+    reader: &mut OwnedReadHalf,
+    incoming: Option<buffer::Sender<MqttMessage>>,
+) -> Result<(), Error> {
+    loop {
+        let mut header = [0u8; 1];
+        if reader.read(&mut header).await? == 0 {
+            return Ok(());
+        }
+        let mut length = 0usize;
+        for shift in (0..28).step_by(7) {
+            let byte = reader.read_u8().await?;
+            length |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let incoming = match &incoming {
+            Some(incoming) if header[0] & 0xf0 == PUBLISH => incoming,
+            _ => {
+                skip(reader, length).await?;
+                continue;
+            }
+        };
+        // Skipped without being read into memory.
+        if !incoming.limits().allows(length) {
+            skip(reader, length).await?;
+            incoming.discard(&format!("{length} bytes is over the payload limit"));
+            continue;
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).await?;
+
+        if body.len() < 2 {
+            return Err("truncated PUBLISH packet".into());
+        }
+        let topic_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let mut offset = 2 + topic_length;
+        // QoS 1 and 2 messages carry a packet identifier; we only ask for QoS 0.
+        if header[0] & 0x06 != 0 {
+            offset += 2;
+        }
+        if body.len() < offset {
+            return Err("truncated PUBLISH packet".into());
+        }
+        let message = MqttMessage {
+            topic: String::from_utf8_lossy(&body[2..2 + topic_length]).into_owned(),
+            payload: body[offset..].to_vec(),
+        };
+        if incoming.send(message).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn skip(reader: &mut OwnedReadHalf, length: usize) -> std::io::Result<()> {
+    let skipped = tokio::io::copy(&mut reader.take(length as u64), &mut tokio::io::sink()).await?;
+    match skipped == length as u64 {
+        true => Ok(()),
+        false => Err(std::io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+fn push_string(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+fn frame(packet_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    // Variable length encoding, 7 bits per byte.
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
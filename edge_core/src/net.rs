@@ -0,0 +1,38 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/// Resolves `host`, `host:port`, `ip:port` or `[ipv6]:port`, filling in `default_port` when the
+/// address doesn't carry one. Hostnames resolve to their first address.
+pub fn resolve(address: &str, default_port: u16) -> io::Result<SocketAddr> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = address.trim_matches(['[', ']']).parse::<std::net::IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+
+    let has_port = address
+        .rsplit_once(':')
+        .map(|(_, port)| port.parse::<u16>().is_ok())
+        .unwrap_or(false);
+    let mut addrs = if has_port {
+        address.to_socket_addrs()?
+    } else {
+        (address, default_port).to_socket_addrs()?
+    };
+    addrs.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{address} did not resolve to any address"),
+        )
+    })
+}
+
+/// This machine's name, from `/etc/hostname` or `COMPUTERNAME` on Windows; empty when neither
+/// says.
+pub fn hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,605 @@
+//! Result formatting shared by every tool: the human-readable text each command has always
+//! printed, or the same results as JSON (one document per line), YAML, an aligned table or
+//! [`crate::envelope`] lines chosen with `--output`. With `--timestamps` every result also carries the time it was
+//! emitted, as a leading `timestamp` field or, in text mode, a prefix on its line; with
+//! `--timestamps=both` a `monotonic` field follows it, so intervals stay right when the wall
+//! clock is stepped. Times a device or upstream tool gave a value are kept apart from these,
+//! in `source_timestamp` or `source_time` fields. With
+//! `--template` each result is rendered through a [`crate::template`] instead of any of these.
+//! Tools take the three options by flattening [`OutputArgs`] into their arguments.
+
+use clap::ValueEnum;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::envelope::Envelope;
+use crate::exit::Code;
+use crate::template::Template;
+use crate::OrExit;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Presentation {
+    Hex,
+    Dec,
+}
+
+/// Formats register or byte values as a list, e.g. `["1", "2"]` or `["0x1", "0x2"]`.
+pub fn format_values<T: Into<u64> + Copy>(values: &[T], presentation: Presentation) -> String {
+    let values: Vec<String> = values
+        .iter()
+        .map(|value| {
+            let value: u64 = (*value).into();
+            match presentation {
+                Presentation::Dec => format!("{}", value),
+                Presentation::Hex => format!("{:#x}", value),
+            }
+        })
+        .collect();
+    format!("{:?}", values)
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+    Table,
+    /// One NDJSON envelope per value, for piping into another tool.
+    Envelope,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Timestamps {
+    /// 2026-10-14T08:32:30.412Z, in UTC.
+    Rfc3339,
+    /// Seconds since the epoch, with milliseconds.
+    Unix,
+    /// Seconds since the tool started, by the monotonic clock.
+    Relative,
+    /// The RFC 3339 time, then the seconds since the start.
+    Both,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Unsigned(u64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Record(Record),
+}
+
+/// An ordered set of named values; field order is kept in every format.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    fields: Vec<(String, Value)>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record::default()
+    }
+
+    pub fn field(mut self, name: &str, value: impl Into<Value>) -> Record {
+        self.fields.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub fn push(&mut self, name: &str, value: impl Into<Value>) {
+        self.fields.push((name.to_string(), value.into()));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+macro_rules! value_from {
+    ($variant:ident, $convert:ty, $($source:ty),*) => {
+        $(impl From<$source> for Value {
+            fn from(value: $source) -> Value {
+                Value::$variant(<$convert>::from(value))
+            }
+        })*
+    };
+}
+
+value_from!(Integer, i64, i8, i16, i32, i64);
+value_from!(Unsigned, u64, u8, u16, u32, u64);
+value_from!(Float, f64, f32, f64);
+value_from!(Bool, bool, bool);
+value_from!(String, String, String, &str, &String);
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Unsigned(value as u64)
+    }
+}
+
+impl From<Record> for Value {
+    fn from(record: Record) -> Value {
+        Value::Record(record)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value> + Clone> From<&[T]> for Value {
+    fn from(values: &[T]) -> Value {
+        Value::List(values.iter().cloned().map(Into::into).collect())
+    }
+}
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct OutputArgs {
+    /// Result format: text (default), json, yaml, table or envelope.
+    #[clap(long, global = true, action)]
+    pub output: Option<Format>,
+    /// Stamp every result with the time: rfc3339 (default), unix seconds, seconds since the
+    /// start by the monotonic clock, or both the time and those seconds, e.g. --timestamps=both.
+    #[clap(
+        long,
+        global = true,
+        value_name = "FORMAT",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "rfc3339",
+        action
+    )]
+    pub timestamps: Option<Timestamps>,
+    /// Print every result through this Handlebars template file instead of --output.
+    #[clap(long, global = true, action)]
+    pub template: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    /// The [`Output`] these ask for; a template that doesn't load ends the process with the
+    /// usage status.
+    pub fn load(&self) -> Output {
+        let template = self
+            .template
+            .as_deref()
+            .map(|path| Template::load(path).or_exit_with(Code::Usage, "Invalid template"));
+        Output::new(self.output)
+            .timestamps(self.timestamps)
+            .template(template)
+    }
+}
+
+/// Prints results in the format picked on the command line. In text mode the caller's own
+/// closure does the printing so existing output stays exactly as it was.
+pub struct Output {
+    format: Format,
+    timestamps: Option<Timestamps>,
+    template: Option<Template>,
+    // Where results come from, for envelopes.
+    source: String,
+    start: Instant,
+    // Columns of the table header already printed for a stream of records.
+    columns: RefCell<Option<Vec<(String, usize)>>>,
+}
+
+impl Output {
+    pub fn new(format: Option<Format>) -> Output {
+        Output {
+            format: format.unwrap_or_default(),
+            timestamps: None,
+            template: None,
+            source: String::new(),
+            start: Instant::now(),
+            columns: RefCell::new(None),
+        }
+    }
+
+    /// Stamps every result with the time it's emitted.
+    pub fn timestamps(self, timestamps: Option<Timestamps>) -> Output {
+        Output { timestamps, ..self }
+    }
+
+    /// Renders every result through `template` instead of printing it in the chosen format.
+    pub fn template(self, template: Option<Template>) -> Output {
+        Output { template, ..self }
+    }
+
+    /// Names the device or server results come from in envelopes.
+    pub fn source(self, source: &str) -> Output {
+        Output {
+            source: source.to_string(),
+            ..self
+        }
+    }
+
+    pub fn has_template(&self) -> bool {
+        self.template.is_some()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text
+    }
+
+    /// Emits one result, e.g. a single reading or one message of a stream.
+    pub fn record(&self, record: &Record, text: impl FnOnce()) {
+        crate::expect::check(record);
+        if self.format == Format::Envelope && self.template.is_none() {
+            // Envelopes carry their own timestamp.
+            println!("{}", Envelope::from_record(&self.source, record).to_line());
+            return;
+        }
+        let stamp = self.stamp();
+        let record = &self.stamped(record, &stamp);
+        if let Some(template) = &self.template {
+            print!("{}", template.render(record));
+            return;
+        }
+        match self.format {
+            Format::Text => self.text(&stamp, text),
+            Format::Json => println!("{}", json(&Value::Record(record.clone()))),
+            Format::Yaml => print!("---\n{}", yaml(&Value::Record(record.clone()), 0)),
+            Format::Table => self.table_row(record),
+            Format::Envelope => unreachable!("printed above"),
+        }
+    }
+
+    /// Emits a value the tool already has as an envelope; other formats get its record.
+    pub fn envelope(&self, envelope: &Envelope) {
+        match (self.format, &self.template) {
+            (Format::Envelope, None) => println!("{}", envelope.to_line()),
+            _ => self.record(&envelope.to_record(), || {
+                println!("{}: {}", envelope.topic, envelope.payload_text())
+            }),
+        }
+    }
+
+    /// Emits a complete list of results: a JSON array, a YAML sequence or one table with
+    /// columns sized to fit.
+    pub fn records(&self, records: &[Record], text: impl FnOnce()) {
+        if self.format == Format::Envelope && self.template.is_none() {
+            for record in records {
+                self.record(record, || {});
+            }
+            return;
+        }
+        for record in records {
+            crate::expect::check(record);
+        }
+        let stamp = self.stamp();
+        let records: Vec<Record> = records
+            .iter()
+            .map(|record| self.stamped(record, &stamp))
+            .collect();
+        let records = records.as_slice();
+        if let Some(template) = &self.template {
+            for record in records {
+                print!("{}", template.render(record));
+            }
+            return;
+        }
+        match self.format {
+            Format::Text => self.text(&stamp, text),
+            Format::Json => println!("{}", json(&records.to_vec().into())),
+            Format::Yaml => print!("---\n{}", yaml(&records.to_vec().into(), 0)),
+            Format::Table => print!("{}", table(records)),
+            Format::Envelope => unreachable!("printed above"),
+        }
+    }
+
+    /// The time now as the fields the chosen format adds, if any.
+    fn stamp(&self) -> Vec<(&'static str, Value)> {
+        let wall =
+            || Value::String(humantime::format_rfc3339_millis(SystemTime::now()).to_string());
+        let monotonic = || Value::Float(self.start.elapsed().as_millis() as f64 / 1000.0);
+        match self.timestamps {
+            None => Vec::new(),
+            Some(Timestamps::Rfc3339) => vec![("timestamp", wall())],
+            Some(Timestamps::Unix) => {
+                let since = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                vec![("timestamp", Value::Float(since.as_millis() as f64 / 1000.0))]
+            }
+            Some(Timestamps::Relative) => vec![("timestamp", monotonic())],
+            Some(Timestamps::Both) => vec![("timestamp", wall()), ("monotonic", monotonic())],
+        }
+    }
+
+    fn stamped(&self, record: &Record, stamp: &[(&'static str, Value)]) -> Record {
+        // Results passed on from another run keep the time they were stamped with there.
+        if stamp.is_empty() || record.get("timestamp").is_some() {
+            return record.clone();
+        }
+        let mut stamped = Record::new();
+        for (name, value) in stamp {
+            stamped.push(name, value.clone());
+        }
+        for (name, value) in record.fields() {
+            stamped.push(name, value.clone());
+        }
+        stamped
+    }
+
+    fn text(&self, stamp: &[(&'static str, Value)], text: impl FnOnce()) {
+        for (name, value) in stamp {
+            let value = match value {
+                Value::Float(seconds) if *name == "monotonic" => format!("+{seconds:.3}"),
+                Value::Float(seconds) => format!("{seconds:.3}"),
+                other => cell(other),
+            };
+            print!("{value} ");
+        }
+        text();
+    }
+
+    fn table_row(&self, record: &Record) {
+        let mut columns = self.columns.borrow_mut();
+        let columns = columns.get_or_insert_with(|| {
+            let columns: Vec<(String, usize)> = record
+                .fields()
+                .map(|(name, value)| (name.to_string(), name.len().max(cell(value).len())))
+                .collect();
+            let header: Vec<String> = columns
+                .iter()
+                .map(|(name, width)| format!("{:<width$}", name.to_uppercase()))
+                .collect();
+            println!("{}", header.join("  ").trim_end());
+            columns
+        });
+        let row: Vec<String> = columns
+            .iter()
+            .map(|(name, width)| {
+                let value = record.get(name).map(cell).unwrap_or_default();
+                format!("{value:<width$}")
+            })
+            .collect();
+        println!("{}", row.join("  ").trim_end());
+    }
+}
+
+/// Compact single-line JSON.
+pub fn json(value: &Value) -> String {
+    let mut out = String::new();
+    write_json(&mut out, value);
+    out
+}
+
+fn write_json(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(value) => {
+            let _ = write!(out, "{value}");
+        }
+        Value::Integer(value) => {
+            let _ = write!(out, "{value}");
+        }
+        Value::Unsigned(value) => {
+            let _ = write!(out, "{value}");
+        }
+        Value::Float(value) if value.is_finite() => {
+            let _ = write!(out, "{value:?}");
+        }
+        Value::Float(_) => out.push_str("null"),
+        Value::String(value) => write_json_string(out, value),
+        Value::List(values) => {
+            out.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_json(out, value);
+            }
+            out.push(']');
+        }
+        Value::Record(record) => {
+            out.push('{');
+            for (index, (name, value)) in record.fields().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_json_string(out, name);
+                out.push(':');
+                write_json(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Block-style YAML, indented by `indent` levels of two spaces.
+pub fn yaml(value: &Value, indent: usize) -> String {
+    let mut out = String::new();
+    match value {
+        Value::List(values) if values.is_empty() => out.push_str("[]\n"),
+        Value::Record(record) if record.fields.is_empty() => out.push_str("{}\n"),
+        Value::List(values) => {
+            for value in values {
+                match value {
+                    // `- first: 1` with the remaining keys lined up under the first.
+                    Value::Record(record) if !record.fields.is_empty() => {
+                        let nested = yaml(value, indent + 1);
+                        let _ = write!(out, "{:width$}- ", "", width = indent * 2);
+                        out.push_str(&nested[(indent + 1) * 2..]);
+                    }
+                    value => {
+                        let _ = write!(out, "{:width$}-", "", width = indent * 2);
+                        write_yaml_nested(&mut out, value, indent + 1);
+                    }
+                }
+            }
+        }
+        Value::Record(record) => {
+            for (name, value) in record.fields() {
+                let _ = write!(
+                    out,
+                    "{:width$}{}:",
+                    "",
+                    yaml_scalar_string(name),
+                    width = indent * 2
+                );
+                write_yaml_nested(&mut out, value, indent + 1);
+            }
+        }
+        scalar => {
+            let _ = writeln!(out, "{}", yaml_scalar(scalar));
+        }
+    }
+    out
+}
+
+fn write_yaml_nested(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::List(values) if !values.is_empty() => {
+            out.push('\n');
+            out.push_str(&yaml(value, indent));
+        }
+        Value::Record(record) if !record.fields.is_empty() => {
+            out.push('\n');
+            out.push_str(&yaml(value, indent));
+        }
+        value => {
+            out.push(' ');
+            out.push_str(&yaml(value, indent));
+        }
+    }
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Float(value) if value.is_nan() => ".nan".to_string(),
+        Value::Float(value) if value.is_infinite() => {
+            if *value > 0.0 { ".inf" } else { "-.inf" }.to_string()
+        }
+        Value::String(value) => yaml_scalar_string(value),
+        value => json(value),
+    }
+}
+
+/// Plain scalars where YAML would read them back as the same string, JSON-quoted otherwise.
+fn yaml_scalar_string(value: &str) -> String {
+    let reserved = matches!(
+        value.to_ascii_lowercase().as_str(),
+        "" | "~" | "null" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n"
+    );
+    // Anything starting with a digit could read back as a number, date or time.
+    let plain = !reserved
+        && !value.starts_with(|c: char| c.is_ascii_digit())
+        && value.parse::<f64>().is_err()
+        && !value.starts_with(|c: char| c.is_whitespace() || "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && !value.ends_with(char::is_whitespace)
+        && !value.contains(": ")
+        && !value.contains(" #")
+        && !value.chars().any(char::is_control);
+    if plain {
+        value.to_string()
+    } else {
+        let mut out = String::new();
+        write_json_string(&mut out, value);
+        out
+    }
+}
+
+/// A table cell: scalars as they read, lists comma separated, nested records as JSON.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(value) => value.replace(['\n', '\r', '\t'], " "),
+        Value::List(values) => values.iter().map(cell).collect::<Vec<_>>().join(","),
+        value => json(value),
+    }
+}
+
+fn table(records: &[Record]) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    for record in records {
+        for (name, _) in record.fields() {
+            if !columns.iter().any(|column| column == name) {
+                columns.push(name.to_string());
+            }
+        }
+    }
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            columns
+                .iter()
+                .map(|column| record.get(column).map(cell).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            rows.iter()
+                .map(|row| row[index].chars().count())
+                .chain([column.len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut out = String::new();
+    let line = |cells: Vec<String>| -> String {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        cells.join("  ").trim_end().to_string()
+    };
+    let _ = writeln!(
+        out,
+        "{}",
+        line(columns.iter().map(|c| c.to_uppercase()).collect())
+    );
+    for row in rows {
+        let _ = writeln!(out, "{}", line(row));
+    }
+    out
+}
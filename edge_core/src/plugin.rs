@@ -0,0 +1,193 @@
+//! Codec plugins: an external program that turns payloads into values and back, for formats
+//! the tools don't know, without rebuilding them.
+//!
+//! The program is started once, through the shell, and talks JSON lines: one request per line
+//! on its stdin, one answer per line on its stdout. Payloads travel as base64.
+//!
+//! ```text
+//! > {"op":"decode","subject":"plant.meter","payload":"AAECAw=="}
+//! < {"value":{"power":1.5}}
+//! > {"op":"encode","subject":"plant.setpoint","value":21.5}
+//! < {"payload":"QawAAA=="}
+//! ```
+//!
+//! Either answer may be `{"error":"..."}` instead; a decode answer of `{"drop":true}` skips the
+//! message. Anything the program writes to stderr goes to the tool's stderr. A program that
+//! doesn't answer within 10 seconds, or exits, fails the message.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::fmt;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::json;
+use crate::output::{self, Record, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum PluginError {
+    Spawn(String, std::io::Error),
+    // The program exited, closed its output or took too long.
+    Gone(String),
+    // The answer wasn't what the protocol expects.
+    Protocol(String),
+    // The program answered with an error.
+    Failed(String),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::Spawn(command, err) => write!(f, "unable to start {command}: {err}"),
+            PluginError::Gone(message) => write!(f, "codec plugin {message}"),
+            PluginError::Protocol(message) => write!(f, "codec plugin answered badly: {message}"),
+            PluginError::Failed(message) => write!(f, "codec plugin failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+pub struct Codec {
+    command: String,
+    io: Mutex<Io>,
+    // Kept so the program is killed with the codec.
+    _child: Child,
+}
+
+struct Io {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    // Set once the program is gone or out of step; a late answer would pair with the next
+    // request.
+    broken: bool,
+}
+
+impl Codec {
+    pub fn spawn(command: &str) -> Result<Codec, PluginError> {
+        let mut child = shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| PluginError::Spawn(command.to_string(), err))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            unreachable!("both are piped above");
+        };
+        Ok(Codec {
+            command: command.to_string(),
+            io: Mutex::new(Io {
+                stdin,
+                stdout: BufReader::new(stdout),
+                broken: false,
+            }),
+            _child: child,
+        })
+    }
+
+    /// The value held by a payload, or `None` when the plugin drops it.
+    pub async fn decode(
+        &self,
+        subject: &str,
+        payload: &[u8],
+    ) -> Result<Option<Value>, PluginError> {
+        let request = Record::new()
+            .field("op", "decode")
+            .field("subject", subject)
+            .field("payload", BASE64.encode(payload));
+        let answer = self.call(request).await?;
+        if let Some(Value::Bool(true)) = answer.get("drop") {
+            return Ok(None);
+        }
+        match answer.get("value") {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(PluginError::Protocol(
+                "expected value, drop or error".to_string(),
+            )),
+        }
+    }
+
+    /// The payload to send for a value.
+    pub async fn encode(&self, subject: &str, value: &Value) -> Result<Vec<u8>, PluginError> {
+        let request = Record::new()
+            .field("op", "encode")
+            .field("subject", subject)
+            .field("value", value.clone());
+        let answer = self.call(request).await?;
+        match answer.get("payload") {
+            Some(Value::String(payload)) => BASE64
+                .decode(payload)
+                .map_err(|err| PluginError::Protocol(format!("payload isn't base64: {err}"))),
+            _ => Err(PluginError::Protocol(
+                "expected a base64 payload or an error".to_string(),
+            )),
+        }
+    }
+
+    async fn call(&self, request: Record) -> Result<Record, PluginError> {
+        let mut line = output::json(&Value::Record(request));
+        line.push('\n');
+        let gone = |what: &str| PluginError::Gone(format!("{} {what}", self.command));
+
+        let mut io = self.io.lock().await;
+        if io.broken {
+            return Err(gone("stopped after an earlier failure"));
+        }
+        let exchange = async {
+            io.stdin
+                .write_all(line.as_bytes())
+                .await
+                .map_err(|err| gone(&format!("stopped reading: {err}")))?;
+            io.stdin
+                .flush()
+                .await
+                .map_err(|err| gone(&format!("stopped reading: {err}")))?;
+            let mut answer = String::new();
+            let read = io
+                .stdout
+                .read_line(&mut answer)
+                .await
+                .map_err(|err| gone(&format!("stopped answering: {err}")))?;
+            if read == 0 {
+                return Err(gone("exited"));
+            }
+            Ok(answer)
+        };
+        let answer = tokio::time::timeout(TIMEOUT, exchange)
+            .await
+            .unwrap_or_else(|_| Err(gone("didn't answer in time")))
+            .inspect_err(|_| io.broken = true)?;
+
+        let answer = match json::parse(&answer) {
+            Ok(Value::Record(answer)) => answer,
+            Ok(_) => return Err(PluginError::Protocol("expected an object".to_string())),
+            Err(err) => return Err(PluginError::Protocol(err.to_string())),
+        };
+        match answer.get("error") {
+            Some(Value::String(message)) => Err(PluginError::Failed(message.clone())),
+            Some(other) => Err(PluginError::Failed(output::json(other))),
+            None => Ok(answer),
+        }
+    }
+}
+
+/// `command` run by `sh -c`, or `cmd /C` on Windows.
+#[cfg(unix)]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
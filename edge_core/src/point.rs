@@ -0,0 +1,384 @@
+//! The point model shared by the tools: a named value from a source, with the address it was
+//! read from, how it was decoded and when. Modbus registers, NATS subjects and MQTT topics all
+//! become points, so mappings, sinks and outputs treat them the same way.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::codec;
+use crate::output::{self, Record};
+use crate::units;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Text(String),
+}
+
+impl Value {
+    /// Reads a NATS or MQTT payload: `true`/`false`, a number, or anything else as text.
+    pub fn parse(payload: &[u8]) -> Value {
+        let text = String::from_utf8_lossy(payload);
+        let text = text.trim();
+        match text {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => match text.parse::<f64>() {
+                Ok(number) => Value::Number(number),
+                Err(_) => Value::Text(text.to_string()),
+            },
+        }
+    }
+
+    /// Booleans count as 0 and 1 so they can be scaled or written to registers.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            Value::Text(_) => None,
+        }
+    }
+
+    /// The value as a number, or as text when it isn't one, the way stores keep it in a
+    /// `value` or a `text` column.
+    pub fn split(&self) -> (Option<f64>, Option<String>) {
+        match self {
+            Value::Text(text) => match text.trim().parse::<f64>() {
+                Ok(number) => (Some(number), None),
+                Err(_) => (None, Some(text.clone())),
+            },
+            other => (other.as_number(), None),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            Value::Number(number) => Some(*number != 0.0),
+            Value::Text(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Whole numbers print without a trailing .0 so registers read back as they were.
+            Value::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+                write!(f, "{}", *number as i64)
+            }
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Text(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+impl From<&output::Value> for Value {
+    fn from(value: &output::Value) -> Value {
+        match value {
+            output::Value::Integer(number) => Value::Number(*number as f64),
+            output::Value::Unsigned(number) => Value::Number(*number as f64),
+            output::Value::Float(number) => Value::Number(*number),
+            output::Value::Bool(value) => Value::Bool(*value),
+            output::Value::String(text) => Value::Text(text.clone()),
+            output::Value::Null => Value::Text(String::new()),
+            other => Value::Text(output::json(other)),
+        }
+    }
+}
+
+impl From<&Value> for output::Value {
+    fn from(value: &Value) -> output::Value {
+        match value {
+            Value::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+                output::Value::Integer(*number as i64)
+            }
+            Value::Number(number) => output::Value::Float(*number),
+            Value::Bool(value) => output::Value::Bool(*value),
+            Value::Text(text) => output::Value::String(text.clone()),
+        }
+    }
+}
+
+/// How a value is laid out in registers: a [`codec::Type`], or a bit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataType {
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    Bcd16,
+    Bcd32,
+    Bool,
+}
+
+impl DataType {
+    pub const NAMES: &'static str = "u16, i16, u32, i32, u64, i64, f32, f64, bcd16, bcd32 or bool";
+
+    pub fn parse(name: &str) -> Option<DataType> {
+        match name {
+            "bool" => Some(DataType::Bool),
+            name => codec::Type::parse(name).map(DataType::from),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self.codec() {
+            Some(ty) => ty.name(),
+            None => "bool",
+        }
+    }
+
+    /// The codec type for numbers; `None` for bits.
+    pub fn codec(self) -> Option<codec::Type> {
+        Some(match self {
+            DataType::U16 => codec::Type::U16,
+            DataType::I16 => codec::Type::I16,
+            DataType::U32 => codec::Type::U32,
+            DataType::I32 => codec::Type::I32,
+            DataType::U64 => codec::Type::U64,
+            DataType::I64 => codec::Type::I64,
+            DataType::F32 => codec::Type::F32,
+            DataType::F64 => codec::Type::F64,
+            DataType::Bcd16 => codec::Type::Bcd16,
+            DataType::Bcd32 => codec::Type::Bcd32,
+            DataType::Bool => return None,
+        })
+    }
+
+    /// Registers the value spans.
+    pub fn words(self) -> u16 {
+        self.codec().map_or(1, |ty| ty.words() as u16)
+    }
+
+    /// The value held by `words` in `order`.
+    pub fn decode(self, words: &[u16], order: codec::Order) -> Result<Value, codec::Error> {
+        match self.codec() {
+            Some(ty) => codec::decode(words, ty, order).map(Value::Number),
+            None => match words.first() {
+                Some(word) => Ok(Value::Bool(*word != 0)),
+                None => Err(codec::Error::TooShort { needed: 1, got: 0 }),
+            },
+        }
+    }
+
+    /// The words that hold `number` in `order`; booleans are a 0 or a 1 in one register.
+    pub fn encode(self, number: f64, order: codec::Order) -> Result<Vec<u16>, codec::Error> {
+        codec::encode(number, self.codec().unwrap_or(codec::Type::U16), order)
+    }
+}
+
+impl From<codec::Type> for DataType {
+    fn from(ty: codec::Type) -> DataType {
+        match ty {
+            codec::Type::U16 => DataType::U16,
+            codec::Type::I16 => DataType::I16,
+            codec::Type::U32 => DataType::U32,
+            codec::Type::I32 => DataType::I32,
+            codec::Type::U64 => DataType::U64,
+            codec::Type::I64 => DataType::I64,
+            codec::Type::F32 => DataType::F32,
+            codec::Type::F64 => DataType::F64,
+            codec::Type::Bcd16 => DataType::Bcd16,
+            codec::Type::Bcd32 => DataType::Bcd32,
+        }
+    }
+}
+
+/// Whether a value can be trusted, so consumers can tell a frozen or failed reading from a
+/// healthy one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Quality {
+    #[default]
+    Good,
+    // Unchanged, or not heard from, for longer than the source allows.
+    Stale,
+    // The last value known before reading it failed.
+    CommFail,
+    // Read, but outside the range the point is declared with.
+    OutOfRange,
+    // Read, but not to be trusted.
+    Bad,
+}
+
+impl Quality {
+    pub const NAMES: &'static str = "good, stale, comm-fail, out-of-range or bad";
+
+    pub fn parse(name: &str) -> Option<Quality> {
+        match name {
+            "good" => Some(Quality::Good),
+            "stale" => Some(Quality::Stale),
+            "comm-fail" => Some(Quality::CommFail),
+            "out-of-range" => Some(Quality::OutOfRange),
+            "bad" => Some(Quality::Bad),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Good => "good",
+            Quality::Stale => "stale",
+            Quality::CommFail => "comm-fail",
+            Quality::OutOfRange => "out-of-range",
+            Quality::Bad => "bad",
+        }
+    }
+
+    pub fn is_good(self) -> bool {
+        self == Quality::Good
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Point {
+    // Name of the source: a mapping's source, or the device or server read from.
+    pub source: String,
+    // Where on the source: `holding 100`, a subject or a topic.
+    pub address: String,
+    // Point name from a map, the address when there's none.
+    pub name: String,
+    pub value: Value,
+    // How the raw value was decoded, for register values.
+    pub data_type: Option<DataType>,
+    // Engineering unit, e.g. `°C`.
+    pub unit: Option<String>,
+    // Factor the raw value was multiplied by.
+    pub scale: Option<f64>,
+    pub quality: Quality,
+    // When the point was read or received.
+    pub time: SystemTime,
+    // When the device or upstream tool says the value was taken, if it says.
+    pub source_time: Option<SystemTime>,
+    // Fields added by a script.
+    pub extra: Record,
+}
+
+impl Point {
+    /// A good point read now, named after its address.
+    pub fn new(source: &str, address: &str, value: Value) -> Point {
+        Point {
+            source: source.to_string(),
+            address: address.to_string(),
+            name: address.to_string(),
+            value,
+            data_type: None,
+            unit: None,
+            scale: None,
+            quality: Quality::Good,
+            time: SystemTime::now(),
+            source_time: None,
+            extra: Record::new(),
+        }
+    }
+
+    /// Multiplies the raw value by `scale` and remembers it.
+    pub fn scaled(mut self, scale: Option<f64>) -> Point {
+        if let (Some(scale), Some(number)) = (scale, self.value.as_number()) {
+            if !matches!(self.value, Value::Bool(_)) {
+                self.value = Value::Number(number * scale);
+                self.scale = Some(scale);
+            }
+        }
+        self
+    }
+
+    /// Marks the point out of range when its value is a number below `min` or above `max`.
+    pub fn checked(mut self, min: Option<f64>, max: Option<f64>) -> Point {
+        if let Value::Number(number) = self.value {
+            if min.is_some_and(|min| number < min) || max.is_some_and(|max| number > max) {
+                self.quality = Quality::OutOfRange;
+            }
+        }
+        self
+    }
+
+    /// The point with its value in `unit`, converted from the unit it was read in. Points
+    /// already in `unit`, and text and booleans, are left as they are.
+    pub fn converted(&self, unit: &str) -> Result<Point, units::Error> {
+        let Value::Number(number) = self.value else {
+            return Ok(self.clone());
+        };
+        let from = match self.unit.as_deref() {
+            Some(from) if from == unit => return Ok(self.clone()),
+            Some(from) => from,
+            None => return Err(units::Error::Missing),
+        };
+        Ok(Point {
+            value: Value::Number(units::convert(number, from, unit)?),
+            unit: Some(unit.to_string()),
+            ..self.clone()
+        })
+    }
+
+    /// The time the value was taken: the source's when it gave one, otherwise when it was
+    /// received.
+    pub fn measured(&self) -> SystemTime {
+        self.source_time.unwrap_or(self.time)
+    }
+
+    /// The same fields for every protocol; unset ones are left out.
+    pub fn to_record(&self) -> Record {
+        let mut record = Record::new()
+            .field("source", self.source.as_str())
+            .field("point", self.name.as_str());
+        if self.address != self.name {
+            record.push("address", self.address.as_str());
+        }
+        record.push("value", &self.value);
+        if let Some(data_type) = self.data_type {
+            record.push("type", data_type.name());
+        }
+        if let Some(unit) = &self.unit {
+            record.push("unit", unit.as_str());
+        }
+        if let Some(scale) = self.scale {
+            record.push("scale", scale);
+        }
+        record.push("quality", self.quality.name());
+        record.push(
+            "time",
+            humantime::format_rfc3339_millis(self.time).to_string(),
+        );
+        if let Some(source_time) = self.source_time {
+            record.push(
+                "source_time",
+                humantime::format_rfc3339_millis(source_time).to_string(),
+            );
+        }
+        for (name, value) in self.extra.fields() {
+            record.push(name, value.clone());
+        }
+        record
+    }
+
+    /// The point as a script left it: `point`, `value` and `unit` may have changed, anything
+    /// else not part of the model is kept as an extra field.
+    pub fn changed(&self, record: Record) -> Point {
+        let mut point = Point {
+            extra: Record::new(),
+            ..self.clone()
+        };
+        for (name, value) in record.fields() {
+            match (name, value) {
+                (
+                    "source" | "address" | "type" | "scale" | "quality" | "time" | "source_time",
+                    _,
+                ) => {}
+                ("point", output::Value::String(name)) => point.name = name.clone(),
+                ("point", other) => point.name = output::json(other),
+                ("value", value) => point.value = Value::from(value),
+                ("unit", output::Value::String(unit)) => point.unit = Some(unit.clone()),
+                ("unit", output::Value::Null) => point.unit = None,
+                (name, value) => point.extra.push(name, value.clone()),
+            }
+        }
+        point
+    }
+}
@@ -0,0 +1,124 @@
+//! Connections kept open between operations, so polling a device, serving API requests or
+//! bridging points reuses a socket rather than opening one per request. Operations on one key
+//! (usually the address) share at most [`Pool::new`]'s `size` connections: the others wait
+//! their turn, which is what small PLCs that take a connection or two want, and what a Modbus
+//! gateway with many units behind one address needs. A connection only goes back to the pool
+//! when [`Pooled::release`] says the operation left it in a usable state; dropped otherwise,
+//! as after a timeout with an answer still on its way, it's closed.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How long a connection may sit unused before it's closed rather than reused; devices often
+/// drop idle clients sooner or later.
+pub const IDLE: Duration = Duration::from_secs(30);
+
+pub struct Pool<K, C> {
+    size: usize,
+    slots: Arc<Mutex<HashMap<K, Slot<C>>>>,
+}
+
+struct Slot<C> {
+    turns: Arc<Semaphore>,
+    idle: Vec<(C, Instant)>,
+}
+
+impl<K: Clone + Eq + Hash, C> Pool<K, C> {
+    /// At most `size` connections to each key, at least one.
+    pub fn new(size: usize) -> Pool<K, C> {
+        Pool {
+            size: size.max(1),
+            slots: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// A connection to `key`: an idle one if there is one, or a new one from `connect` once
+    /// fewer than `size` are in use.
+    pub async fn get<F, Fut, E>(&self, key: K, connect: F) -> Result<Pooled<K, C>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<C, E>>,
+    {
+        let turns = self
+            .lock()
+            .entry(key.clone())
+            .or_insert_with(|| Slot {
+                turns: Arc::new(Semaphore::new(self.size)),
+                idle: Vec::new(),
+            })
+            .turns
+            .clone();
+        let turn = turns
+            .acquire_owned()
+            .await
+            .expect("pool semaphores are never closed");
+        let idle = self.lock().get_mut(&key).and_then(|slot| {
+            slot.idle.retain(|(_, since)| since.elapsed() < IDLE);
+            slot.idle.pop()
+        });
+        let (connection, reused) = match idle {
+            Some((connection, _)) => (connection, true),
+            None => (connect().await?, false),
+        };
+        Ok(Pooled {
+            connection: Some(connection),
+            key,
+            reused,
+            slots: self.slots.clone(),
+            _turn: turn,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<K, Slot<C>>> {
+        self.slots.lock().expect("pool poisoned")
+    }
+}
+
+/// A connection taken from a [`Pool`]; closed on drop unless released.
+pub struct Pooled<K: Eq + Hash, C> {
+    connection: Option<C>,
+    key: K,
+    reused: bool,
+    slots: Arc<Mutex<HashMap<K, Slot<C>>>>,
+    _turn: OwnedSemaphorePermit,
+}
+
+impl<K: Eq + Hash, C> Pooled<K, C> {
+    /// Whether the connection was used before, so a failure may only mean the other end closed
+    /// it meanwhile.
+    pub fn reused(&self) -> bool {
+        self.reused
+    }
+
+    /// What the connection is to.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Puts the connection back for the next operation on its key.
+    pub fn release(mut self) {
+        let connection = self.connection.take().expect("released once");
+        if let Some(slot) = self.slots.lock().expect("pool poisoned").get_mut(&self.key) {
+            slot.idle.push((connection, Instant::now()));
+        }
+    }
+}
+
+impl<K: Eq + Hash, C> Deref for Pooled<K, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.connection.as_ref().expect("not released")
+    }
+}
+
+impl<K: Eq + Hash, C> DerefMut for Pooled<K, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.connection.as_mut().expect("not released")
+    }
+}
@@ -0,0 +1,120 @@
+//! Rate limiting with a token bucket, so a bulk write, a SunSpec walk or a publish loop fed by
+//! another tool can't send faster than a serial gateway or a small broker keeps up with.
+//! Requests over the rate wait for their turn rather than fail.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// How many requests a period may have, written like `10/s`, `600/min` or `1/250ms`; a bare number is
+/// per second.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rate {
+    pub count: f64,
+    pub period: Duration,
+}
+
+impl Rate {
+    pub fn per_second(self) -> f64 {
+        self.count / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Rate, String> {
+        let invalid = || format!("expected a rate such as 10/s or 600/min, got `{value}`");
+        let (count, period) = value.split_once('/').unwrap_or((value, "s"));
+        let count: f64 = count.trim().parse().map_err(|_| invalid())?;
+        let period = period.trim();
+        // `s` and `min` mean one of them.
+        let period = match period.starts_with(|c: char| c.is_ascii_digit()) {
+            true => humantime::parse_duration(period),
+            false => humantime::parse_duration(&format!("1{period}")),
+        }
+        .map_err(|_| invalid())?;
+        if !(count.is_finite() && count > 0.0) || period.is_zero() {
+            return Err(invalid());
+        }
+        Ok(Rate { count, period })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.count,
+            humantime::format_duration(self.period)
+        )
+    }
+}
+
+/// A token bucket shared by its clones. It starts full and holds `burst` tokens, one by
+/// default, so requests are spaced evenly rather than sent in bursts.
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    per_second: f64,
+    burst: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // Below zero when requests are already waiting for tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limiter {
+    pub fn new(rate: Rate) -> Limiter {
+        Limiter {
+            per_second: rate.per_second(),
+            burst: 1.0,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: 1.0,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    /// Lets up to `burst` requests through at once after a quiet spell.
+    pub fn burst(self, burst: u32) -> Limiter {
+        let burst = f64::from(burst.max(1));
+        self.bucket.lock().expect("rate limiter poisoned").tokens = burst;
+        Limiter { burst, ..self }
+    }
+
+    /// Waits until one more request fits in the rate. Waiting requests go in the order they
+    /// asked.
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("rate limiter poisoned");
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+            bucket.updated = now;
+            bucket.tokens -= 1.0;
+            match bucket.tokens < 0.0 {
+                true => Duration::from_secs_f64(-bucket.tokens / self.per_second),
+                false => Duration::ZERO,
+            }
+        };
+        if !wait.is_zero() {
+            log::debug!("Waiting {wait:?} to stay under the rate limit");
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Waits for `limiter` when there is one.
+pub async fn acquire(limiter: Option<&Limiter>) {
+    if let Some(limiter) = limiter {
+        limiter.acquire().await;
+    }
+}
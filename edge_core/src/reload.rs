@@ -0,0 +1,75 @@
+//! Picking up edits to the file a long-running command was started from, such as a bridge's
+//! mapping. [`Reload::changed`] resolves when the file's modification time or size changes,
+//! which is checked every second, or on SIGHUP, the usual way to ask a Unix daemon to reread
+//! its configuration (`ExecReload=kill -HUP $MAINPID` in a systemd unit). Reading the file and
+//! deciding what to do with it is up to the command.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// How often the file is looked at.
+const POLL: Duration = Duration::from_secs(1);
+
+pub struct Reload {
+    path: PathBuf,
+    seen: Option<(SystemTime, u64)>,
+    poll: tokio::time::Interval,
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl Reload {
+    /// Starts watching `path` as it is now; must be called on the runtime.
+    pub fn watch(path: &Path) -> Reload {
+        let mut poll = tokio::time::interval(POLL);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Reload {
+            path: path.to_path_buf(),
+            seen: stamp(path),
+            poll,
+            #[cfg(unix)]
+            hangup: {
+                use tokio::signal::unix::{signal, SignalKind};
+                signal(SignalKind::hangup())
+                    .inspect_err(|err| log::warn!("Unable to catch SIGHUP: {err}"))
+                    .ok()
+            },
+        }
+    }
+
+    /// Resolves the next time the file changes or a SIGHUP arrives. Safe to cancel, as in a
+    /// `select!` loop.
+    pub async fn changed(&mut self) {
+        loop {
+            #[cfg(unix)]
+            let hangup = async {
+                match self.hangup.as_mut() {
+                    Some(hangup) => hangup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+            tokio::select! {
+                _ = hangup => {
+                    log::info!("Reloading {} on SIGHUP", self.path.display());
+                    self.seen = stamp(&self.path);
+                    return;
+                }
+                _ = self.poll.tick() => {}
+            }
+            let now = stamp(&self.path);
+            // A file that's being replaced may be missing for a moment; its return is a change.
+            if now.is_some() && now != self.seen {
+                log::info!("Reloading {}, which changed", self.path.display());
+                self.seen = now;
+                return;
+            }
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
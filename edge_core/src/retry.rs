@@ -0,0 +1,133 @@
+//! Retrying transient failures: a few more attempts with exponentially growing, jittered delays
+//! between them, so a device that drops one connection or a server that restarts doesn't end
+//! the run. Only connection failures and timeouts are retried; a refused login or an error
+//! answer would fail the same way again.
+
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::exit::{self, Code};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Retry {
+    // Attempts in total, 1 to never retry; 0 retries forever.
+    pub attempts: u32,
+    // Delay before the first retry; each one after doubles it, up to `max_delay`.
+    pub delay: Duration,
+    pub max_delay: Duration,
+    // Each delay is randomly lengthened or shortened by up to this fraction, so clients that
+    // failed together don't retry together.
+    pub jitter: f64,
+}
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry {
+            attempts: 3,
+            delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl Retry {
+    /// The policy with `retries` retries after the first attempt and `delay` before the first
+    /// of them, where given.
+    pub fn with(self, retries: Option<u32>, delay: Option<Duration>) -> Retry {
+        Retry {
+            attempts: retries.map_or(self.attempts, |retries| retries.saturating_add(1)),
+            delay: delay.unwrap_or(self.delay),
+            ..self
+        }
+    }
+
+    /// Keeps retrying, for long-running tasks that have nothing better to do.
+    pub fn forever(self) -> Retry {
+        Retry {
+            attempts: 0,
+            ..self
+        }
+    }
+
+    /// How long to wait before retry number `retry`, counting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1).min(31);
+        let delay = self
+            .delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+        let mut bytes = [0; 4];
+        let unit = match SystemRandom::new().fill(&mut bytes) {
+            Ok(()) => f64::from(u32::from_be_bytes(bytes)) / f64::from(u32::MAX),
+            Err(_) => 0.5,
+        };
+        delay.mul_f64(1.0 - jitter + 2.0 * jitter * unit)
+    }
+
+    /// Runs `attempt` until it succeeds, fails with an error that isn't transient, or the
+    /// attempts run out. Each retry is logged as a warning about `what`.
+    pub async fn run<T, E, F, Fut>(&self, what: &str, mut attempt: F) -> Result<T, E>
+    where
+        E: Transient + Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut retry = 0;
+        loop {
+            let err = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            retry += 1;
+            if !err.is_transient() || (self.attempts != 0 && retry >= self.attempts) {
+                return Err(err);
+            }
+            let delay = self.delay(retry);
+            log::warn!(
+                "{what} failed, retrying in {}: {err}",
+                humantime::format_duration(Duration::from_millis(delay.as_millis() as u64))
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Errors worth another attempt.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+fn transient_code(code: Code) -> bool {
+    matches!(code, Code::Connection | Code::Timeout)
+}
+
+impl Transient for std::io::Error {
+    fn is_transient(&self) -> bool {
+        transient_code(Code::from_io(self))
+    }
+}
+
+impl Transient for exit::Error {
+    fn is_transient(&self) -> bool {
+        transient_code(self.code)
+    }
+}
+
+impl Transient for Box<dyn std::error::Error> {
+    fn is_transient(&self) -> bool {
+        transient_code(Code::of(self.as_ref()))
+    }
+}
+
+impl Transient for Box<dyn std::error::Error + Send + Sync> {
+    fn is_transient(&self) -> bool {
+        transient_code(Code::of(self.as_ref()))
+    }
+}
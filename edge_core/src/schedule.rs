@@ -0,0 +1,202 @@
+//! When recurring jobs run: every so often, or at the times a cron expression names. Cron
+//! expressions have the usual five fields, minute, hour, day of the month, month and day of
+//! the week (0 or 7 is Sunday), each `*`, a number, a range like `1-5`, a step like `*/15` or
+//! `8-18/2`, or a list of those. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+//! stand for the obvious expressions. Times are UTC, whatever the machine's time zone.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How far ahead to look for a time a cron expression matches; `0 0 29 2 *` matches once in
+// four years at worst.
+const SEARCH_DAYS: i64 = 366 * 5;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// The first time after `now` the job is due; `start` is when an `every` schedule began
+    /// counting. None when a cron expression never matches, like `0 0 31 2 *`.
+    pub fn next(&self, start: SystemTime, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Every(every) => {
+                let since = now.duration_since(start).unwrap_or_default();
+                let runs = since.as_nanos() / every.as_nanos().max(1) + 1;
+                let nanos = every.as_nanos().saturating_mul(runs);
+                start.checked_add(Duration::from_nanos(nanos.min(u64::MAX as u128) as u64))
+            }
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(every) => write!(f, "every {}", humantime::format_duration(*every)),
+            Schedule::Cron(cron) => write!(f, "{}", cron.text),
+        }
+    }
+}
+
+/// A parsed cron expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    text: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Whether each day field is `*`; when both are restricted, either may match.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Cron, String> {
+        let expanded = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "expected five fields (minute hour day month weekday) in `{text}`"
+            ));
+        };
+        // Sunday is 0 or 7.
+        let weekdays = field(weekday, "weekday", 0, 7)?;
+        Ok(Cron {
+            text: text.trim().to_string(),
+            minutes: field(minute, "minute", 0, 59)?,
+            hours: field(hour, "hour", 0, 23)?,
+            days: field(day, "day", 1, 31)?,
+            months: field(month, "month", 1, 12)?,
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+/// The bits of the values a cron field allows.
+fn field(text: &str, name: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid {name} `{text}`, expected {min}-{max}, * or a list");
+    let number = |value: &str| -> Result<u32, String> {
+        match value.parse::<u32>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(invalid()),
+        }
+    };
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(invalid()),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (number(first)?, number(last)?),
+                // `5/10` starts at 5 and runs to the end.
+                None if step > 1 => (number(range)?, max),
+                None => {
+                    let value = number(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    /// The first whole minute after `now` the expression matches.
+    pub fn next_after(&self, now: SystemTime) -> Option<SystemTime> {
+        let seconds = now.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        // The next whole minute, as a day and a minute of that day.
+        let next = seconds.div_euclid(60) + 1;
+        let (today, first) = (next.div_euclid(24 * 60), next.rem_euclid(24 * 60));
+        for day in today..today + SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let from = if day == today { first } else { 0 };
+            for minute in from..24 * 60 {
+                if self.hours & 1 << (minute / 60) != 0 && self.minutes & 1 << (minute % 60) != 0 {
+                    let at = (day * 24 * 60 + minute) * 60;
+                    return Some(UNIX_EPOCH + Duration::from_secs(at as u64));
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, days: i64) -> bool {
+        let (month, day) = civil(days);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4).rem_euclid(7);
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        let by_day = self.days & 1 << day != 0;
+        let by_weekday = self.weekdays & 1 << weekday != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => by_day || by_weekday,
+            _ => by_day && by_weekday,
+        }
+    }
+}
+
+/// Month and day of the month for a count of days since 1970-01-01.
+fn civil(days: i64) -> (u32, u32) {
+    let days = days + 719_468;
+    let of_era = days.rem_euclid(146_097);
+    let year_of_era = (of_era - of_era / 1460 + of_era / 36_524 - of_era / 146_096) / 365;
+    let of_year = of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted = (5 * of_year + 2) / 153;
+    let day = (of_year - (153 * shifted + 2) / 5 + 1) as u32;
+    let month = if shifted < 10 {
+        shifted + 3
+    } else {
+        shifted - 9
+    } as u32;
+    (month, day)
+}
+
+/// `every` as a duration, or `cron` as an expression; exactly one of them.
+pub fn parse(every: Option<&str>, cron: Option<&str>) -> Result<Schedule, String> {
+    match (every, cron) {
+        (Some(every), None) => match humantime::parse_duration(every) {
+            Ok(every) if !every.is_zero() => Ok(Schedule::Every(every)),
+            _ => Err(format!(
+                "every: expected a duration such as 5m, got `{every}`"
+            )),
+        },
+        (None, Some(cron)) => cron
+            .parse()
+            .map(Schedule::Cron)
+            .map_err(|err| format!("cron: {err}")),
+        _ => Err("expected either every or cron".to_string()),
+    }
+}
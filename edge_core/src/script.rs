@@ -0,0 +1,1749 @@
+//! Message hooks written in a subset of [Rhai](https://rhai.rs). The rhai crate isn't available
+//! to the workspace, so this interprets the part of the language a per-message hook needs:
+//! `let`/`const`, assignment (also `+=` and friends, `msg.field = ...` and `list[0] = ...`),
+//! `if`/`else`, `for x in list` and `for i in 0..n`, `return`, `throw`, arrays, object maps
+//! (`#{unit: "kW"}`), the usual operators, and built-in functions that can be called either way
+//! (`abs(x)` or `x.abs()`). Function definitions, other loops, closures, `switch` and modules
+//! are rejected when the script is loaded rather than misread.
+//!
+//! A hook sees the message as the object map `msg`, and what the script evaluates to decides
+//! what happens to it:
+//!
+//! ```text
+//! if msg.value < 0 { return false; }   // false drops the message
+//! msg.value = msg.value * 0.1;          // changes to msg are kept
+//! msg.site = "north";                   // and so are new fields
+//! ```
+//!
+//! Returning an object map replaces the message; any other result keeps `msg`. `print` and
+//! `debug` write to stderr, since stdout carries the tool's output.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::output::{self, Record};
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Read(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+    Runtime { line: usize, message: String },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Read(path, err) => write!(f, "unable to read {}: {err}", path.display()),
+            ScriptError::Syntax { line, message } => {
+                write!(f, "syntax error on line {line}: {message}")
+            }
+            ScriptError::Runtime { line: 0, message } => write!(f, "{message}"),
+            ScriptError::Runtime { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScriptError::Read(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed hook, ready to run on any number of messages.
+#[derive(Debug)]
+pub struct Script {
+    body: Block,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script, ScriptError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| ScriptError::Read(path.into(), err))?;
+        Script::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Script, ScriptError> {
+        let tokens = lex(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let body = parser.block_contents()?;
+        match parser.peek() {
+            Token::End => Ok(Script { body }),
+            token => Err(parser.error(format!("unexpected {token}"))),
+        }
+    }
+
+    /// Runs the hook on one message; `None` means the script dropped it.
+    pub fn apply(&self, message: Record) -> Result<Option<Record>, ScriptError> {
+        let mut scope = Scope::default();
+        scope.push("msg", Value::from(&output::Value::Record(message)), false);
+        let result = match eval_block(&self.body, &mut scope) {
+            Ok(value) | Err(Flow::Return(value)) => value,
+            Err(Flow::Error(err)) => return Err(err),
+        };
+        match result {
+            Value::Bool(false) => Ok(None),
+            Value::Map(entries) => Ok(Some(record(entries))),
+            _ => match scope.get("msg") {
+                Some(Value::Map(entries)) => Ok(Some(record(entries.clone()))),
+                Some(other) => Err(ScriptError::Runtime {
+                    line: 0,
+                    message: format!("msg must stay an object map, not {}", other.type_name()),
+                }),
+                None => unreachable!("msg is pushed before the script runs"),
+            },
+        }
+    }
+}
+
+fn record(entries: Vec<(String, Value)>) -> Record {
+    let mut record = Record::new();
+    for (name, value) in entries {
+        record.push(&name, value);
+    }
+    record
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Value>),
+    // Entries in insertion order, so fields come out in the order they went in.
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The names Rhai's `type_of` uses.
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unit => "()",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "i64",
+            Value::Float(_) => "f64",
+            Value::Str(_) => "string",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Unit => Ok(()),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Str(value) => write!(f, "{value}"),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match value {
+                        Value::Str(text) => write!(f, "{text:?}")?,
+                        value => write!(f, "{value}")?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "#{{")?;
+                for (index, (name, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match value {
+                        Value::Str(text) => write!(f, "{name:?}: {text:?}")?,
+                        value => write!(f, "{name:?}: {value}")?,
+                    }
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<&output::Value> for Value {
+    fn from(value: &output::Value) -> Value {
+        match value {
+            output::Value::Null => Value::Unit,
+            output::Value::Bool(value) => Value::Bool(*value),
+            output::Value::Integer(value) => Value::Int(*value),
+            output::Value::Unsigned(value) => match i64::try_from(*value) {
+                Ok(value) => Value::Int(value),
+                Err(_) => Value::Float(*value as f64),
+            },
+            output::Value::Float(value) => Value::Float(*value),
+            output::Value::String(value) => Value::Str(value.clone()),
+            output::Value::List(values) => Value::Array(values.iter().map(Value::from).collect()),
+            output::Value::Record(record) => Value::Map(
+                record
+                    .fields()
+                    .map(|(name, value)| (name.to_string(), Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<Value> for output::Value {
+    fn from(value: Value) -> output::Value {
+        match value {
+            Value::Unit => output::Value::Null,
+            Value::Bool(value) => output::Value::Bool(value),
+            Value::Int(value) => output::Value::Integer(value),
+            Value::Float(value) => output::Value::Float(value),
+            Value::Str(value) => output::Value::String(value),
+            Value::Array(values) => {
+                output::Value::List(values.into_iter().map(Into::into).collect())
+            }
+            Value::Map(entries) => output::Value::Record(record(entries)),
+        }
+    }
+}
+
+// Lexing
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(value) => write!(f, "number {value}"),
+            Token::Float(value) => write!(f, "number {value}"),
+            Token::Str(value) => write!(f, "string {value:?}"),
+            Token::Ident(name) => write!(f, "`{name}`"),
+            Token::Punct(punct) => write!(f, "`{punct}`"),
+            Token::End => write!(f, "end of script"),
+        }
+    }
+}
+
+// Longest first, so `..=` isn't read as `..` followed by `=`.
+const PUNCTUATION: &[&str] = &[
+    "..=", "#{", "**", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "..", "(",
+    ")", "{", "}", "[", "]", ",", ";", ":", ".", "+", "-", "*", "/", "%", "<", ">", "!", "=",
+];
+
+fn lex(source: &str) -> Result<Vec<(Token, usize)>, ScriptError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut position = 0;
+    let error = |line: usize, message: String| ScriptError::Syntax { line, message };
+
+    while position < chars.len() {
+        let c = chars[position];
+        let rest: String = chars[position..chars.len().min(position + 3)]
+            .iter()
+            .collect();
+        if c == '\n' {
+            line += 1;
+            position += 1;
+        } else if c.is_whitespace() {
+            position += 1;
+        } else if rest.starts_with("//") {
+            while position < chars.len() && chars[position] != '\n' {
+                position += 1;
+            }
+        } else if rest.starts_with("/*") {
+            let start = line;
+            position += 2;
+            loop {
+                match chars.get(position) {
+                    None => return Err(error(start, "unterminated comment".into())),
+                    Some('*') if chars.get(position + 1) == Some(&'/') => {
+                        position += 2;
+                        break;
+                    }
+                    Some('\n') => line += 1,
+                    Some(_) => {}
+                }
+                position += 1;
+            }
+        } else if c.is_ascii_digit() {
+            let (token, next) = lex_number(&chars, position).map_err(|m| error(line, m))?;
+            tokens.push((token, line));
+            position = next;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = position;
+            while position < chars.len()
+                && (chars[position].is_alphanumeric() || chars[position] == '_')
+            {
+                position += 1;
+            }
+            let name: String = chars[start..position].iter().collect();
+            tokens.push((Token::Ident(name), line));
+        } else if c == '"' {
+            let start = line;
+            position += 1;
+            let mut text = String::new();
+            loop {
+                match chars.get(position) {
+                    None => return Err(error(start, "unterminated string".into())),
+                    Some('"') => break,
+                    Some('\\') => {
+                        let (escaped, next) =
+                            lex_escape(&chars, position + 1).map_err(|m| error(line, m))?;
+                        text.push(escaped);
+                        position = next;
+                        continue;
+                    }
+                    Some('\n') => {
+                        line += 1;
+                        text.push('\n');
+                    }
+                    Some(c) => text.push(*c),
+                }
+                position += 1;
+            }
+            position += 1;
+            tokens.push((Token::Str(text), start));
+        } else if c == '`' {
+            return Err(error(
+                line,
+                "back-tick strings aren't supported in hooks, use \"...\" and +".into(),
+            ));
+        } else if c == '\'' {
+            return Err(error(
+                line,
+                "character literals aren't supported in hooks, use a string".into(),
+            ));
+        } else {
+            match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => {
+                    tokens.push((Token::Punct(punct), line));
+                    position += punct.chars().count();
+                }
+                None => return Err(error(line, format!("unexpected character `{c}`"))),
+            }
+        }
+    }
+    tokens.push((Token::End, line));
+    Ok(tokens)
+}
+
+fn lex_number(chars: &[char], start: usize) -> Result<(Token, usize), String> {
+    let mut position = start;
+    let digits = |position: &mut usize, accept: &dyn Fn(char) -> bool| {
+        let mut text = String::new();
+        while let Some(&c) = chars.get(*position) {
+            if accept(c) {
+                text.push(c);
+            } else if c != '_' {
+                break;
+            }
+            *position += 1;
+        }
+        text
+    };
+
+    if chars[start] == '0' {
+        let radix = match chars.get(start + 1) {
+            Some('x') | Some('X') => Some(16),
+            Some('o') | Some('O') => Some(8),
+            Some('b') | Some('B') => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            position += 2;
+            let text = digits(&mut position, &|c| c.is_digit(radix));
+            return i64::from_str_radix(&text, radix)
+                .map(|value| (Token::Int(value), position))
+                .map_err(|_| format!("invalid number 0{}{text}", chars[start + 1]));
+        }
+    }
+
+    let mut text = digits(&mut position, &|c| c.is_ascii_digit());
+    let mut float = false;
+    // `1..5` is a range, not the float `1.` followed by `.5`.
+    if chars.get(position) == Some(&'.')
+        && chars.get(position + 1).is_some_and(|c| c.is_ascii_digit())
+    {
+        position += 1;
+        text.push('.');
+        text.push_str(&digits(&mut position, &|c| c.is_ascii_digit()));
+        float = true;
+    }
+    if matches!(chars.get(position), Some('e') | Some('E')) {
+        let mut exponent = position + 1;
+        let mut sign = String::new();
+        if let Some(&c @ ('+' | '-')) = chars.get(exponent) {
+            sign.push(c);
+            exponent += 1;
+        }
+        if chars.get(exponent).is_some_and(|c| c.is_ascii_digit()) {
+            position = exponent;
+            text.push('e');
+            text.push_str(&sign);
+            text.push_str(&digits(&mut position, &|c| c.is_ascii_digit()));
+            float = true;
+        }
+    }
+    if float {
+        text.parse()
+            .map(|value| (Token::Float(value), position))
+            .map_err(|_| format!("invalid number {text}"))
+    } else {
+        text.parse()
+            .map(|value| (Token::Int(value), position))
+            .map_err(|_| format!("number {text} is too large"))
+    }
+}
+
+/// Reads the escape after a backslash at `position`, returning the character and where the
+/// string continues.
+fn lex_escape(chars: &[char], position: usize) -> Result<(char, usize), String> {
+    let hex = |length: usize| -> Result<(char, usize), String> {
+        let digits: String = chars.iter().skip(position + 1).take(length).collect();
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|_| digits.len() == length)
+            .and_then(char::from_u32)
+            .map(|c| (c, position + 1 + length))
+            .ok_or_else(|| format!("invalid escape \\{}{digits}", chars[position]))
+    };
+    match chars.get(position) {
+        Some('n') => Ok(('\n', position + 1)),
+        Some('t') => Ok(('\t', position + 1)),
+        Some('r') => Ok(('\r', position + 1)),
+        Some('0') => Ok(('\0', position + 1)),
+        Some('\\') => Ok(('\\', position + 1)),
+        Some('"') => Ok(('"', position + 1)),
+        Some('\'') => Ok(('\'', position + 1)),
+        Some('x') => hex(2),
+        Some('u') => hex(4),
+        Some('U') => hex(8),
+        Some(c) => Err(format!("unknown escape \\{c}")),
+        None => Err("unterminated string".into()),
+    }
+}
+
+// Parsing
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Range,
+    RangeInclusive,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Variable(String, usize),
+    Array(Vec<Expr>),
+    Map(Vec<(String, Expr)>),
+    Property(Box<Expr>, String, usize),
+    Index(Box<Expr>, Box<Expr>, usize),
+    // Method calls are calls with the receiver as the first argument.
+    Call(String, Vec<Expr>, usize),
+    Not(Box<Expr>, usize),
+    Negate(Box<Expr>, usize),
+    Binary(Op, Box<Expr>, Box<Expr>, usize),
+    If(Box<Expr>, Block, Option<Block>, usize),
+    Block(Block),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Let(String, Expr, bool),
+    Assign(Expr, Option<Op>, Expr, usize),
+    Expr(Expr),
+    Return(Option<Expr>),
+    Throw(Expr, usize),
+    For(String, Expr, Block, usize),
+}
+
+#[derive(Debug, Default)]
+struct Block {
+    statements: Vec<Stmt>,
+    // A last expression without a semicolon is the block's value.
+    tail: Option<Box<Expr>>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: String) -> ScriptError {
+        ScriptError::Syntax {
+            line: self.line(),
+            message,
+        }
+    }
+
+    fn at(&self, punct: &str) -> bool {
+        matches!(self.peek(), Token::Punct(p) if *p == punct)
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(name) if name == keyword)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.at(punct);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ScriptError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{punct}`, found {}", self.peek())))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, ScriptError> {
+        match self.peek().clone() {
+            Token::Ident(name) if !is_keyword(&name) => {
+                self.next();
+                Ok(name)
+            }
+            token => Err(self.error(format!("expected a name, found {token}"))),
+        }
+    }
+
+    fn at_block_end(&self) -> bool {
+        self.at("}") || *self.peek() == Token::End
+    }
+
+    /// Statements up to a closing `}` or the end of the script.
+    fn block_contents(&mut self) -> Result<Block, ScriptError> {
+        let mut block = Block::default();
+        loop {
+            while self.eat(";") {}
+            if self.at_block_end() {
+                return Ok(block);
+            }
+            let line = self.line();
+            let keyword = match self.peek() {
+                Token::Ident(name) => name.clone(),
+                _ => String::new(),
+            };
+            let statement = match keyword.as_str() {
+                "let" | "const" => {
+                    self.next();
+                    let name = self.identifier()?;
+                    self.expect("=")?;
+                    Stmt::Let(name, self.expression()?, keyword == "const")
+                }
+                "return" => {
+                    self.next();
+                    if self.at(";") || self.at_block_end() {
+                        Stmt::Return(None)
+                    } else {
+                        Stmt::Return(Some(self.expression()?))
+                    }
+                }
+                "throw" => {
+                    self.next();
+                    Stmt::Throw(self.expression()?, line)
+                }
+                "for" => {
+                    self.next();
+                    let name = self.identifier()?;
+                    if !self.at_keyword("in") {
+                        return Err(self.error(format!("expected `in`, found {}", self.peek())));
+                    }
+                    self.next();
+                    let list = self.expression()?;
+                    let body = self.braced_block()?;
+                    block.statements.push(Stmt::For(name, list, body, line));
+                    continue;
+                }
+                "fn" => return Err(self.error("functions can't be defined in hooks".into())),
+                "while" | "loop" | "do" => {
+                    return Err(
+                        self.error(format!("only `for` loops are supported, not `{keyword}`"))
+                    )
+                }
+                "break" | "continue" | "switch" | "import" | "export" | "private" | "try"
+                | "catch" | "Fn" => {
+                    return Err(self.error(format!("`{keyword}` isn't supported in hooks")))
+                }
+                _ => {
+                    let expr = self.expression()?;
+                    let op = match self.peek() {
+                        Token::Punct("=") => Some(None),
+                        Token::Punct("+=") => Some(Some(Op::Add)),
+                        Token::Punct("-=") => Some(Some(Op::Sub)),
+                        Token::Punct("*=") => Some(Some(Op::Mul)),
+                        Token::Punct("/=") => Some(Some(Op::Div)),
+                        Token::Punct("%=") => Some(Some(Op::Rem)),
+                        _ => None,
+                    };
+                    match op {
+                        Some(op) => {
+                            if !matches!(
+                                expr,
+                                Expr::Variable(..) | Expr::Property(..) | Expr::Index(..)
+                            ) {
+                                return Err(self.error("can't assign to this expression".into()));
+                            }
+                            self.next();
+                            Stmt::Assign(expr, op, self.expression()?, line)
+                        }
+                        // `if` and blocks end at their brace and need no semicolon.
+                        None if matches!(expr, Expr::If(..) | Expr::Block(_)) => {
+                            if self.at_block_end() {
+                                block.tail = Some(Box::new(expr));
+                                return Ok(block);
+                            }
+                            block.statements.push(Stmt::Expr(expr));
+                            continue;
+                        }
+                        None if self.at_block_end() => {
+                            block.tail = Some(Box::new(expr));
+                            return Ok(block);
+                        }
+                        None => Stmt::Expr(expr),
+                    }
+                }
+            };
+            block.statements.push(statement);
+            if !self.at_block_end() {
+                self.expect(";")?;
+            }
+        }
+    }
+
+    fn braced_block(&mut self) -> Result<Block, ScriptError> {
+        self.expect("{")?;
+        let block = self.block_contents()?;
+        self.expect("}")?;
+        Ok(block)
+    }
+
+    fn expression(&mut self) -> Result<Expr, ScriptError> {
+        self.binary(0)
+    }
+
+    /// Precedence climbing over Rhai's operator levels, loosest first.
+    fn binary(&mut self, level: usize) -> Result<Expr, ScriptError> {
+        const LEVELS: &[&[(&str, Op)]] = &[
+            &[("||", Op::Or)],
+            &[("&&", Op::And)],
+            &[("==", Op::Eq), ("!=", Op::Ne)],
+            &[
+                ("<", Op::Lt),
+                ("<=", Op::Le),
+                (">", Op::Gt),
+                (">=", Op::Ge),
+                ("in", Op::In),
+            ],
+            &[("..", Op::Range), ("..=", Op::RangeInclusive)],
+            &[("+", Op::Add), ("-", Op::Sub)],
+            &[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)],
+        ];
+        if level == LEVELS.len() {
+            return self.power();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let line = self.line();
+            let op = LEVELS[level].iter().find(|(text, _)| match self.peek() {
+                Token::Punct(punct) => punct == text,
+                Token::Ident(name) => name == text,
+                _ => false,
+            });
+            let Some(&(_, op)) = op else {
+                return Ok(left);
+            };
+            self.next();
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), line);
+        }
+    }
+
+    /// `**` binds tighter than the other operators and to the right.
+    fn power(&mut self) -> Result<Expr, ScriptError> {
+        let base = self.unary()?;
+        let line = self.line();
+        if self.eat("**") {
+            let exponent = self.power()?;
+            return Ok(Expr::Binary(
+                Op::Pow,
+                Box::new(base),
+                Box::new(exponent),
+                line,
+            ));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ScriptError> {
+        let line = self.line();
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?), line));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?), line));
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ScriptError> {
+        let mut expr = self.primary()?;
+        loop {
+            let line = self.line();
+            if self.eat(".") {
+                let name = self.identifier()?;
+                if self.at("(") {
+                    let mut args = vec![expr];
+                    args.extend(self.arguments()?);
+                    expr = Expr::Call(name, args, line);
+                } else {
+                    expr = Expr::Property(Box::new(expr), name, line);
+                }
+            } else if self.eat("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index), line);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expr>, ScriptError> {
+        self.expect("(")?;
+        let mut args = Vec::new();
+        while !self.eat(")") {
+            args.push(self.expression()?);
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+        Ok(args)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ScriptError> {
+        let line = self.line();
+        match self.next() {
+            Token::Int(value) => Ok(Expr::Literal(Value::Int(value))),
+            Token::Float(value) => Ok(Expr::Literal(Value::Float(value))),
+            Token::Str(value) => Ok(Expr::Literal(Value::Str(value))),
+            Token::Punct("(") => {
+                if self.eat(")") {
+                    return Ok(Expr::Literal(Value::Unit));
+                }
+                let expr = self.expression()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Punct("[") => {
+                let mut values = Vec::new();
+                while !self.eat("]") {
+                    values.push(self.expression()?);
+                    if !self.eat(",") {
+                        self.expect("]")?;
+                        break;
+                    }
+                }
+                Ok(Expr::Array(values))
+            }
+            Token::Punct("#{") => {
+                let mut entries = Vec::new();
+                while !self.eat("}") {
+                    let name = match self.next() {
+                        Token::Ident(name) | Token::Str(name) => name,
+                        token => {
+                            return Err(ScriptError::Syntax {
+                                line,
+                                message: format!("expected a property name, found {token}"),
+                            })
+                        }
+                    };
+                    self.expect(":")?;
+                    entries.push((name, self.expression()?));
+                    if !self.eat(",") {
+                        self.expect("}")?;
+                        break;
+                    }
+                }
+                Ok(Expr::Map(entries))
+            }
+            Token::Punct("{") => {
+                let block = self.block_contents()?;
+                self.expect("}")?;
+                Ok(Expr::Block(block))
+            }
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "if" => self.if_expression(line),
+                "fn" => Err(self.error("closures aren't supported in hooks".into())),
+                _ if is_keyword(&name) => Err(ScriptError::Syntax {
+                    line,
+                    message: format!("unexpected `{name}`"),
+                }),
+                _ if self.at("(") => Ok(Expr::Call(name, self.arguments()?, line)),
+                _ => Ok(Expr::Variable(name, line)),
+            },
+            token => Err(ScriptError::Syntax {
+                line,
+                message: format!("unexpected {token}"),
+            }),
+        }
+    }
+
+    fn if_expression(&mut self, line: usize) -> Result<Expr, ScriptError> {
+        let condition = self.expression()?;
+        let then = self.braced_block()?;
+        let otherwise = if self.at_keyword("else") {
+            self.next();
+            if self.at_keyword("if") {
+                let line = self.line();
+                self.next();
+                Some(Block {
+                    statements: Vec::new(),
+                    tail: Some(Box::new(self.if_expression(line)?)),
+                })
+            } else {
+                Some(self.braced_block()?)
+            }
+        } else {
+            None
+        };
+        Ok(Expr::If(Box::new(condition), then, otherwise, line))
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    matches!(
+        name,
+        "let"
+            | "const"
+            | "if"
+            | "else"
+            | "for"
+            | "in"
+            | "return"
+            | "throw"
+            | "true"
+            | "false"
+            | "fn"
+            | "while"
+            | "loop"
+            | "do"
+            | "break"
+            | "continue"
+            | "switch"
+            | "import"
+            | "export"
+    )
+}
+
+// Evaluation
+
+enum Flow {
+    Return(Value),
+    Error(ScriptError),
+}
+
+type Eval<T> = Result<T, Flow>;
+
+fn fail<T>(line: usize, message: impl Into<String>) -> Eval<T> {
+    Err(Flow::Error(ScriptError::Runtime {
+        line,
+        message: message.into(),
+    }))
+}
+
+// Ranges become arrays, so keep them from eating all the memory.
+const MAX_RANGE: i64 = 1_000_000;
+
+#[derive(Default)]
+struct Scope {
+    // Innermost last; blocks drop what they pushed when they end.
+    variables: Vec<(String, Value, bool)>,
+}
+
+impl Scope {
+    fn push(&mut self, name: &str, value: Value, constant: bool) {
+        self.variables.push((name.to_string(), value, constant));
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.variables
+            .iter()
+            .rev()
+            .find(|(variable, _, _)| variable == name)
+            .map(|(_, value, _)| value)
+    }
+}
+
+fn eval_block(block: &Block, scope: &mut Scope) -> Eval<Value> {
+    let mark = scope.variables.len();
+    for statement in &block.statements {
+        execute(statement, scope)?;
+    }
+    let value = match &block.tail {
+        Some(expr) => eval(expr, scope)?,
+        None => Value::Unit,
+    };
+    scope.variables.truncate(mark);
+    Ok(value)
+}
+
+fn execute(statement: &Stmt, scope: &mut Scope) -> Eval<()> {
+    match statement {
+        Stmt::Let(name, value, constant) => {
+            let value = eval(value, scope)?;
+            scope.push(name, value, *constant);
+        }
+        Stmt::Assign(target, op, value, line) => {
+            let value = eval(value, scope)?;
+            let (root, path) = place(target, scope)?;
+            let slot = slot(scope, &root, &path, *line)?;
+            *slot = match op {
+                Some(op) => binary(*op, slot.clone(), value).or_else(|err| fail(*line, err))?,
+                None => value,
+            };
+        }
+        Stmt::Expr(expr) => {
+            eval(expr, scope)?;
+        }
+        Stmt::Return(value) => {
+            let value = match value {
+                Some(value) => eval(value, scope)?,
+                None => Value::Unit,
+            };
+            return Err(Flow::Return(value));
+        }
+        Stmt::Throw(value, line) => {
+            let value = eval(value, scope)?;
+            return fail(*line, value.to_string());
+        }
+        Stmt::For(name, list, body, line) => {
+            let items = match eval(list, scope)? {
+                Value::Array(items) => items,
+                Value::Map(entries) => entries.into_iter().map(|(_, value)| value).collect(),
+                Value::Str(text) => text.chars().map(|c| Value::Str(c.to_string())).collect(),
+                other => return fail(*line, format!("can't loop over a {}", other.type_name())),
+            };
+            for item in items {
+                scope.push(name, item, false);
+                let result = eval_block(body, scope);
+                scope.variables.pop();
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Step {
+    Property(String),
+    Index(Value),
+}
+
+/// Splits an assignment target into its variable and the properties and indexes below it,
+/// evaluating the indexes up front.
+fn place(target: &Expr, scope: &mut Scope) -> Eval<(String, Vec<Step>)> {
+    match target {
+        Expr::Variable(name, _) => Ok((name.clone(), Vec::new())),
+        Expr::Property(inner, name, _) => {
+            let (root, mut path) = place(inner, scope)?;
+            path.push(Step::Property(name.clone()));
+            Ok((root, path))
+        }
+        Expr::Index(inner, index, _) => {
+            let index = eval(index, scope)?;
+            let (root, mut path) = place(inner, scope)?;
+            path.push(Step::Index(index));
+            Ok((root, path))
+        }
+        _ => unreachable!("assignment targets are checked when parsing"),
+    }
+}
+
+fn slot<'a>(scope: &'a mut Scope, root: &str, path: &[Step], line: usize) -> Eval<&'a mut Value> {
+    let Some((_, value, constant)) = scope
+        .variables
+        .iter_mut()
+        .rev()
+        .find(|(name, _, _)| name == root)
+    else {
+        return fail(line, format!("variable {root} isn't defined"));
+    };
+    if *constant {
+        return fail(line, format!("{root} is a constant"));
+    }
+    let mut value = value;
+    for step in path {
+        value = match (value, step) {
+            (Value::Map(entries), Step::Property(name))
+            | (Value::Map(entries), Step::Index(Value::Str(name))) => {
+                let position = match entries.iter().position(|(key, _)| key == name) {
+                    Some(position) => position,
+                    None => {
+                        entries.push((name.clone(), Value::Unit));
+                        entries.len() - 1
+                    }
+                };
+                &mut entries[position].1
+            }
+            (Value::Array(items), Step::Index(Value::Int(index))) => {
+                let length = items.len();
+                match array_position(length, *index) {
+                    Some(position) => &mut items[position],
+                    None => {
+                        return fail(line, format!("index {index} is out of range for {length}"))
+                    }
+                }
+            }
+            (value, Step::Property(name)) => {
+                return fail(
+                    line,
+                    format!("can't set property {name} on a {}", value.type_name()),
+                )
+            }
+            (value, Step::Index(index)) => {
+                return fail(
+                    line,
+                    format!(
+                        "can't set a {} index on a {}",
+                        index.type_name(),
+                        value.type_name()
+                    ),
+                )
+            }
+        };
+    }
+    Ok(value)
+}
+
+/// Negative indexes count from the end, as in Rhai.
+fn array_position(length: usize, index: i64) -> Option<usize> {
+    let position = if index < 0 {
+        length as i64 + index
+    } else {
+        index
+    };
+    usize::try_from(position).ok().filter(|p| *p < length)
+}
+
+fn eval(expr: &Expr, scope: &mut Scope) -> Eval<Value> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Variable(name, line) => match scope.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => fail(*line, format!("variable {name} isn't defined")),
+        },
+        Expr::Array(items) => items
+            .iter()
+            .map(|item| eval(item, scope))
+            .collect::<Eval<_>>()
+            .map(Value::Array),
+        Expr::Map(entries) => {
+            let mut map: Vec<(String, Value)> = Vec::new();
+            for (name, value) in entries {
+                let value = eval(value, scope)?;
+                match map.iter_mut().find(|(key, _)| key == name) {
+                    Some(entry) => entry.1 = value,
+                    None => map.push((name.clone(), value)),
+                }
+            }
+            Ok(Value::Map(map))
+        }
+        Expr::Property(target, name, line) => match eval(target, scope)? {
+            // A missing property reads as (), so hooks can test for optional fields.
+            Value::Map(entries) => Ok(entries
+                .into_iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .unwrap_or(Value::Unit)),
+            other => fail(
+                *line,
+                format!("a {} has no property {name}", other.type_name()),
+            ),
+        },
+        Expr::Index(target, index, line) => {
+            let target = eval(target, scope)?;
+            let index = eval(index, scope)?;
+            match (target, index) {
+                (Value::Array(items), Value::Int(index)) => {
+                    match array_position(items.len(), index) {
+                        Some(position) => Ok(items[position].clone()),
+                        None => fail(
+                            *line,
+                            format!("index {index} is out of range for {}", items.len()),
+                        ),
+                    }
+                }
+                (Value::Map(entries), Value::Str(name)) => Ok(entries
+                    .into_iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value)
+                    .unwrap_or(Value::Unit)),
+                (Value::Str(text), Value::Int(index)) => {
+                    let chars: Vec<char> = text.chars().collect();
+                    match array_position(chars.len(), index) {
+                        Some(position) => Ok(Value::Str(chars[position].to_string())),
+                        None => fail(
+                            *line,
+                            format!("index {index} is out of range for {}", chars.len()),
+                        ),
+                    }
+                }
+                (target, index) => fail(
+                    *line,
+                    format!(
+                        "can't index a {} with a {}",
+                        target.type_name(),
+                        index.type_name()
+                    ),
+                ),
+            }
+        }
+        Expr::Call(name, args, line) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, scope))
+                .collect::<Eval<Vec<_>>>()?;
+            call(name, args).or_else(|err| fail(*line, err))
+        }
+        Expr::Not(value, line) => match eval(value, scope)? {
+            Value::Bool(value) => Ok(Value::Bool(!value)),
+            other => fail(*line, format!("can't apply ! to a {}", other.type_name())),
+        },
+        Expr::Negate(value, line) => match eval(value, scope)? {
+            Value::Int(value) => match value.checked_neg() {
+                Some(value) => Ok(Value::Int(value)),
+                None => fail(*line, "integer overflow"),
+            },
+            Value::Float(value) => Ok(Value::Float(-value)),
+            other => fail(*line, format!("can't negate a {}", other.type_name())),
+        },
+        Expr::Binary(Op::And, left, right, line) | Expr::Binary(Op::Or, left, right, line) => {
+            let is_and = matches!(expr, Expr::Binary(Op::And, ..));
+            let left = match eval(left, scope)? {
+                Value::Bool(value) => value,
+                other => {
+                    return fail(
+                        *line,
+                        format!("expected a bool, got a {}", other.type_name()),
+                    )
+                }
+            };
+            if left != is_and {
+                return Ok(Value::Bool(left));
+            }
+            match eval(right, scope)? {
+                Value::Bool(value) => Ok(Value::Bool(value)),
+                other => fail(
+                    *line,
+                    format!("expected a bool, got a {}", other.type_name()),
+                ),
+            }
+        }
+        Expr::Binary(op, left, right, line) => {
+            let left = eval(left, scope)?;
+            let right = eval(right, scope)?;
+            binary(*op, left, right).or_else(|err| fail(*line, err))
+        }
+        Expr::If(condition, then, otherwise, line) => match eval(condition, scope)? {
+            Value::Bool(true) => eval_block(then, scope),
+            Value::Bool(false) => match otherwise {
+                Some(otherwise) => eval_block(otherwise, scope),
+                None => Ok(Value::Unit),
+            },
+            other => fail(
+                *line,
+                format!("if needs a bool condition, got a {}", other.type_name()),
+            ),
+        },
+        Expr::Block(block) => eval_block(block, scope),
+    }
+}
+
+fn binary(op: Op, left: Value, right: Value) -> Result<Value, String> {
+    use Value::*;
+    let overflow = || "integer overflow".to_string();
+    let mismatch = |left: &Value, right: &Value| {
+        let symbol = match op {
+            Op::Or => "||",
+            Op::And => "&&",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::In => "in",
+            Op::Range => "..",
+            Op::RangeInclusive => "..=",
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Rem => "%",
+            Op::Pow => "**",
+        };
+        format!(
+            "can't apply {symbol} to a {} and a {}",
+            left.type_name(),
+            right.type_name()
+        )
+    };
+
+    match op {
+        Op::Eq => return Ok(Bool(equals(&left, &right))),
+        Op::Ne => return Ok(Bool(!equals(&left, &right))),
+        Op::Lt | Op::Le | Op::Gt | Op::Ge => {
+            let ordering = match (&left, &right) {
+                (Str(a), Str(b)) => a.partial_cmp(b),
+                (a, b) => match (a.number(), b.number()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ => return Err(mismatch(&left, &right)),
+                },
+            };
+            let Some(ordering) = ordering else {
+                return Ok(Bool(false));
+            };
+            return Ok(Bool(match op {
+                Op::Lt => ordering.is_lt(),
+                Op::Le => ordering.is_le(),
+                Op::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }));
+        }
+        Op::In => {
+            return match (&left, &right) {
+                (item, Array(items)) => Ok(Bool(items.iter().any(|other| equals(item, other)))),
+                (Str(name), Map(entries)) => Ok(Bool(entries.iter().any(|(key, _)| key == name))),
+                (Str(part), Str(text)) => Ok(Bool(text.contains(part.as_str()))),
+                _ => Err(mismatch(&left, &right)),
+            }
+        }
+        Op::Range | Op::RangeInclusive => {
+            let (Int(start), Int(end)) = (&left, &right) else {
+                return Err(mismatch(&left, &right));
+            };
+            let end = if op == Op::RangeInclusive {
+                end.saturating_add(1)
+            } else {
+                *end
+            };
+            if end.saturating_sub(*start) > MAX_RANGE {
+                return Err(format!("ranges are limited to {MAX_RANGE} values"));
+            }
+            return Ok(Array((*start..end).map(Int).collect()));
+        }
+        _ => {}
+    }
+
+    match (op, left, right) {
+        (Op::Add, Str(a), b) => Ok(Str(format!("{a}{b}"))),
+        (Op::Add, a, Str(b)) => Ok(Str(format!("{a}{b}"))),
+        (Op::Add, Array(mut a), Array(b)) => {
+            a.extend(b);
+            Ok(Array(a))
+        }
+        (Op::Add, Map(mut a), Map(b)) => {
+            for (name, value) in b {
+                match a.iter_mut().find(|(key, _)| *key == name) {
+                    Some(entry) => entry.1 = value,
+                    None => a.push((name, value)),
+                }
+            }
+            Ok(Map(a))
+        }
+        (Op::Add, Int(a), Int(b)) => a.checked_add(b).map(Int).ok_or_else(overflow),
+        (Op::Sub, Int(a), Int(b)) => a.checked_sub(b).map(Int).ok_or_else(overflow),
+        (Op::Mul, Int(a), Int(b)) => a.checked_mul(b).map(Int).ok_or_else(overflow),
+        (Op::Div | Op::Rem, Int(_), Int(0)) => Err("division by zero".into()),
+        (Op::Div, Int(a), Int(b)) => a.checked_div(b).map(Int).ok_or_else(overflow),
+        (Op::Rem, Int(a), Int(b)) => a.checked_rem(b).map(Int).ok_or_else(overflow),
+        (Op::Pow, Int(a), Int(b)) if b >= 0 => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_pow(b))
+            .map(Int)
+            .ok_or_else(overflow),
+        (op, a, b) => match (a.number(), b.number()) {
+            (Some(x), Some(y)) => Ok(Float(match op {
+                Op::Add => x + y,
+                Op::Sub => x - y,
+                Op::Mul => x * y,
+                Op::Div => x / y,
+                Op::Rem => x % y,
+                _ => x.powf(y),
+            })),
+            _ => Err(mismatch(&a, &b)),
+        },
+    }
+}
+
+/// Integers and floats compare by value; everything else must match in type.
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(_), Value::Float(_)) | (Value::Float(_), Value::Int(_)) => {
+            left.number() == right.number()
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equals(a, b))
+        }
+        (left, right) => left == right,
+    }
+}
+
+/// Built-in functions, named as in Rhai's standard packages. They never change their
+/// arguments: `s.trim()` returns the trimmed string.
+fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    use Value::*;
+    let result = match (name, args.as_slice()) {
+        ("abs", [Int(x)]) => Int(x.checked_abs().ok_or("integer overflow")?),
+        ("abs", [Float(x)]) => Float(x.abs()),
+        ("round" | "floor" | "ceiling", [Int(x)]) => Int(*x),
+        ("round", [Float(x)]) => Float(x.round()),
+        ("floor", [Float(x)]) => Float(x.floor()),
+        ("ceiling", [Float(x)]) => Float(x.ceil()),
+        ("sqrt", [x]) if x.number().is_some() => Float(x.number().unwrap_or_default().sqrt()),
+        ("min" | "max", [Int(a), Int(b)]) => Int(if name == "min" { *a.min(b) } else { *a.max(b) }),
+        ("min" | "max", [a, b]) if a.number().is_some() && b.number().is_some() => {
+            let (a, b) = (
+                a.number().unwrap_or_default(),
+                b.number().unwrap_or_default(),
+            );
+            Float(if name == "min" { a.min(b) } else { a.max(b) })
+        }
+        ("to_int", [Int(x)]) => Int(*x),
+        ("to_int", [Float(x)]) if x.is_finite() && x.abs() < 9.2e18 => Int(*x as i64),
+        ("to_int", [Bool(x)]) => Int(i64::from(*x)),
+        ("to_float", [x]) if x.number().is_some() => Float(x.number().unwrap_or_default()),
+        ("to_string", [x]) => Str(x.to_string()),
+        ("parse_int", [Str(text)]) => Int(text
+            .trim()
+            .parse()
+            .map_err(|_| format!("can't parse {text:?} as an integer"))?),
+        ("parse_int", [Str(text), Int(radix)]) if (2..=36).contains(radix) => {
+            Int(i64::from_str_radix(text.trim(), *radix as u32)
+                .map_err(|_| format!("can't parse {text:?} as a base {radix} integer"))?)
+        }
+        ("parse_float", [Str(text)]) => Float(
+            text.trim()
+                .parse()
+                .map_err(|_| format!("can't parse {text:?} as a number"))?,
+        ),
+        ("len", [Str(text)]) => Int(text.chars().count() as i64),
+        ("len", [Array(items)]) => Int(items.len() as i64),
+        ("len", [Map(entries)]) => Int(entries.len() as i64),
+        ("is_empty", [Str(text)]) => Bool(text.is_empty()),
+        ("is_empty", [Array(items)]) => Bool(items.is_empty()),
+        ("is_empty", [Map(entries)]) => Bool(entries.is_empty()),
+        ("contains", [Str(text), Str(part)]) => Bool(text.contains(part.as_str())),
+        ("contains", [Array(items), item]) => Bool(items.iter().any(|other| equals(item, other))),
+        ("contains", [Map(entries), Str(name)]) => Bool(entries.iter().any(|(key, _)| key == name)),
+        ("starts_with", [Str(text), Str(part)]) => Bool(text.starts_with(part.as_str())),
+        ("ends_with", [Str(text), Str(part)]) => Bool(text.ends_with(part.as_str())),
+        ("to_upper", [Str(text)]) => Str(text.to_uppercase()),
+        ("to_lower", [Str(text)]) => Str(text.to_lowercase()),
+        ("trim", [Str(text)]) => Str(text.trim().to_string()),
+        ("replace", [Str(text), Str(from), Str(to)]) => Str(text.replace(from.as_str(), to)),
+        ("split", [Str(text), Str(separator)]) => Array(
+            text.split(separator.as_str())
+                .map(|part| Str(part.to_string()))
+                .collect(),
+        ),
+        ("sub_string", [Str(text), Int(start)]) => {
+            let chars: Vec<char> = text.chars().collect();
+            let start = clamp(*start, chars.len());
+            Str(chars[start..].iter().collect())
+        }
+        ("sub_string", [Str(text), Int(start), Int(length)]) => {
+            let chars: Vec<char> = text.chars().collect();
+            let start = clamp(*start, chars.len());
+            let end = clamp(start as i64 + (*length).max(0), chars.len());
+            Str(chars[start..end].iter().collect())
+        }
+        ("index_of", [Str(text), Str(part)]) => Int(text
+            .find(part.as_str())
+            .map(|byte| text[..byte].chars().count() as i64)
+            .unwrap_or(-1)),
+        ("index_of", [Array(items), item]) => Int(items
+            .iter()
+            .position(|other| equals(item, other))
+            .map(|position| position as i64)
+            .unwrap_or(-1)),
+        ("keys", [Map(entries)]) => {
+            Array(entries.iter().map(|(key, _)| Str(key.clone())).collect())
+        }
+        ("values", [Map(entries)]) => {
+            Array(entries.iter().map(|(_, value)| value.clone()).collect())
+        }
+        ("type_of", [x]) => Str(x.type_name().to_string()),
+        ("print", [x]) => {
+            eprintln!("{x}");
+            Unit
+        }
+        ("debug", [x]) => {
+            match x {
+                Str(text) => eprintln!("{text:?}"),
+                x => eprintln!("{x}"),
+            }
+            Unit
+        }
+        _ => {
+            let types: Vec<&str> = args.iter().map(Value::type_name).collect();
+            return Err(format!("no function {name}({})", types.join(", ")));
+        }
+    };
+    Ok(result)
+}
+
+fn clamp(index: i64, length: usize) -> usize {
+    index.clamp(0, length as i64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Value::*;
+
+    /// What `source` evaluates to, with an empty `msg`.
+    fn run(source: &str) -> Result<Value, ScriptError> {
+        let script = Script::parse(source)?;
+        let mut scope = Scope::default();
+        scope.push("msg", Map(Vec::new()), false);
+        match eval_block(&script.body, &mut scope) {
+            Ok(value) | Err(Flow::Return(value)) => Ok(value),
+            Err(Flow::Error(err)) => Err(err),
+        }
+    }
+
+    fn value(source: &str) -> Value {
+        run(source).unwrap_or_else(|err| panic!("{source}: {err}"))
+    }
+
+    fn error(source: &str) -> String {
+        match run(source) {
+            Ok(value) => panic!("{source} gave {value:?}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn text(text: &str) -> Value {
+        Str(text.to_string())
+    }
+
+    #[test]
+    fn lexes_literals() {
+        assert_eq!(value("1_000"), Int(1000));
+        assert_eq!(value("0xff + 0o17 + 0b101"), Int(255 + 15 + 5));
+        assert_eq!(value("1.5e3"), Float(1500.0));
+        assert_eq!(value("2e-1"), Float(0.2));
+        assert_eq!(value("1..3"), Array(vec![Int(1), Int(2)]));
+        assert_eq!(value(r#""a\tb\x41\u00e9\"""#), text("a\tbAé\""));
+        assert_eq!(value("// a comment\n/* and\nanother */ 7"), Int(7));
+        assert_eq!(value("#{unit: \"kW\", \"x y\": 1}.unit"), text("kW"));
+        assert_eq!(value("()"), Unit);
+    }
+
+    #[test]
+    fn follows_rhai_precedence() {
+        assert_eq!(value("1 + 2 * 3"), Int(7));
+        assert_eq!(value("(1 + 2) * 3"), Int(9));
+        assert_eq!(value("10 - 4 - 3"), Int(3));
+        assert_eq!(value("7 % 4 * 2"), Int(6));
+        assert_eq!(value("2 ** 3 ** 2"), Int(512));
+        assert_eq!(value("2 * 3 ** 2"), Int(18));
+        assert_eq!(value("-2 ** 2"), Int(4));
+        assert_eq!(value("!true == false"), Bool(true));
+        assert_eq!(value("1 + 2 < 4 && 3 == 3"), Bool(true));
+        assert_eq!(value("false && true || true"), Bool(true));
+        assert_eq!(value("true || false && false"), Bool(true));
+        assert_eq!(value("1 + 1 .. 2 + 2"), Array(vec![Int(2), Int(3)]));
+        assert_eq!(value("1 ..= 2"), Array(vec![Int(1), Int(2)]));
+        assert_eq!(value("2 in [1, 2] == true"), Bool(true));
+    }
+
+    #[test]
+    fn mixes_integers_and_floats() {
+        assert_eq!(value("7 / 2"), Int(3));
+        assert_eq!(value("7 / 2.0"), Float(3.5));
+        assert_eq!(value("-7 % 3"), Int(-1));
+        assert_eq!(value("1 == 1.0"), Bool(true));
+        assert_eq!(value("[1, 2] == [1.0, 2]"), Bool(true));
+        assert_eq!(value("2 ** -1"), Float(0.5));
+        assert_eq!(value("\"v\" + 1 + 2"), text("v12"));
+        assert_eq!(value("1 + 2 + \"v\""), text("3v"));
+        assert_eq!(value("\"b\" > \"a\""), Bool(true));
+    }
+
+    #[test]
+    fn short_circuits() {
+        assert_eq!(value("true || 1 / 0 == 0"), Bool(true));
+        assert_eq!(value("false && undefined"), Bool(false));
+    }
+
+    #[test]
+    fn scopes_variables_to_their_block() {
+        assert_eq!(value("let x = 1; { let x = 2; } x"), Int(1));
+        assert_eq!(value("let x = 1; { x = 2; } x"), Int(2));
+        assert_eq!(value("let x = 1; let x = x + 1; x"), Int(2));
+        assert_eq!(value("let x = 1; if true { let x = 5; x += 1; } x"), Int(1));
+        assert_eq!(
+            value("let total = 0; for i in 0..4 { total += i; } total"),
+            Int(6)
+        );
+        assert_eq!(
+            error("for i in 0..3 {} i"),
+            "line 1: variable i isn't defined"
+        );
+        assert_eq!(
+            error("{ let y = 1; } y"),
+            "line 1: variable y isn't defined"
+        );
+        assert_eq!(error("const a = 1;\na = 2;"), "line 2: a is a constant");
+        assert_eq!(value("const a = 1; { let a = 2; a }"), Int(2));
+    }
+
+    #[test]
+    fn evaluates_ifs_and_blocks_as_values() {
+        let source = "let y = if 1 > 2 { 1 } else if 2 > 1 { 2 } else { 3 }; y";
+        assert_eq!(value(source), Int(2));
+        assert_eq!(value("if false { 1 }"), Unit);
+        assert_eq!(value("let y = { let a = 2; a * 3 }; y"), Int(6));
+        assert_eq!(value("if true { 1 } 2"), Int(2));
+        assert_eq!(value("for x in [1] { return 5; } 6"), Int(5));
+        assert_eq!(value("1;"), Unit);
+    }
+
+    #[test]
+    fn assigns_through_properties_and_indexes() {
+        let source = "let m = #{a: [1, 2]}; m.a[-1] += 5; m.b = \"new\"; m[\"c\"] = 0; m";
+        assert_eq!(
+            value(source),
+            Map(vec![
+                ("a".to_string(), Array(vec![Int(1), Int(7)])),
+                ("b".to_string(), text("new")),
+                ("c".to_string(), Int(0)),
+            ])
+        );
+        assert_eq!(value("let s = \"héllo\"; s[1] + s[-1]"), text("éo"));
+        assert_eq!(
+            error("let l = [1];\nl[3] = 1;"),
+            "line 2: index 3 is out of range for 1"
+        );
+        assert_eq!(
+            error("let n = 1; n.x = 2;"),
+            "line 1: can't set property x on a i64"
+        );
+        assert_eq!(
+            error("undefined = 1;"),
+            "line 1: variable undefined isn't defined"
+        );
+    }
+
+    #[test]
+    fn calls_functions_either_way() {
+        assert_eq!(value("abs(-3)"), Int(3));
+        assert_eq!(value("(-3).abs()"), Int(3));
+        assert_eq!(value("\" kW \".trim().to_upper()"), text("KW"));
+        assert_eq!(value("\"a,b\".split(\",\").len()"), Int(2));
+        assert_eq!(value("\"héllo\".sub_string(1, 3)"), text("éll"));
+        assert_eq!(value("\"héllo\".index_of(\"l\")"), Int(2));
+        assert_eq!(
+            value("parse_int(\"ff\", 16) + \"2.5\".parse_float()"),
+            Float(257.5)
+        );
+        assert_eq!(value("max(1, 2.5)"), Float(2.5));
+        assert_eq!(value("#{a: 1}.keys()"), Array(vec![text("a")]));
+        assert_eq!(value("type_of(#{})"), text("map"));
+        assert_eq!(value("to_string([1, \"a\"])"), text("[1, \"a\"]"));
+    }
+
+    #[test]
+    fn reports_syntax_errors_where_they_are() {
+        assert_eq!(
+            error("let x = 1;\nlet = 2;"),
+            "syntax error on line 2: expected a name, found `=`"
+        );
+        assert_eq!(
+            error("let x = (1 +\n2;"),
+            "syntax error on line 2: expected `)`, found `;`"
+        );
+        assert_eq!(
+            error("let x = 1\nlet y = 2;"),
+            "syntax error on line 2: expected `;`, found `let`"
+        );
+        assert_eq!(
+            error("\n\"abc\n\ndef"),
+            "syntax error on line 2: unterminated string"
+        );
+        assert_eq!(
+            error("/* open"),
+            "syntax error on line 1: unterminated comment"
+        );
+        assert_eq!(
+            error("1 $ 2"),
+            "syntax error on line 1: unexpected character `$`"
+        );
+        assert_eq!(
+            error("\"\\q\""),
+            "syntax error on line 1: unknown escape \\q"
+        );
+        assert_eq!(
+            error("99999999999999999999"),
+            "syntax error on line 1: number 99999999999999999999 is too large"
+        );
+        assert_eq!(
+            error("1 + 2 = 3;"),
+            "syntax error on line 1: can't assign to this expression"
+        );
+        assert_eq!(error("}"), "syntax error on line 1: unexpected `}`");
+    }
+
+    #[test]
+    fn rejects_what_hooks_dont_support() {
+        assert_eq!(
+            error("fn double(x) { x * 2 }"),
+            "syntax error on line 1: functions can't be defined in hooks"
+        );
+        assert_eq!(
+            error("let x = 0;\nwhile x < 3 { x += 1; }"),
+            "syntax error on line 2: only `for` loops are supported, not `while`"
+        );
+        assert_eq!(
+            error("for x in [1] { break; }"),
+            "syntax error on line 1: `break` isn't supported in hooks"
+        );
+        assert_eq!(
+            error("let f = fn(x) { x };"),
+            "syntax error on line 1: closures aren't supported in hooks"
+        );
+        assert_eq!(
+            error("`x`"),
+            "syntax error on line 1: back-tick strings aren't supported in hooks, use \"...\" and +"
+        );
+    }
+
+    #[test]
+    fn reports_runtime_errors_where_they_are() {
+        assert_eq!(
+            error("let x = 1;\n\nx.foo()"),
+            "line 3: no function foo(i64)"
+        );
+        assert_eq!(
+            error("1 +\n\"a\" * 2"),
+            "line 2: can't apply * to a string and a i64"
+        );
+        assert_eq!(error("let x = 0;\nthrow \"bad \" + x;"), "line 2: bad 0");
+        assert_eq!(error("9223372036854775807 + 1"), "line 1: integer overflow");
+        assert_eq!(error("1 / 0"), "line 1: division by zero");
+        assert_eq!(
+            error("if 1 { 2 }"),
+            "line 1: if needs a bool condition, got a i64"
+        );
+        assert_eq!(error("for x in 5 {}"), "line 1: can't loop over a i64");
+        assert_eq!(
+            error("0..2000000"),
+            "line 1: ranges are limited to 1000000 values"
+        );
+        assert_eq!(error("[1][1]"), "line 1: index 1 is out of range for 1");
+        assert_eq!(error("1 || true"), "line 1: expected a bool, got a i64");
+        assert_eq!(error("msg.a.b"), "line 1: a () has no property b");
+    }
+
+    #[test]
+    fn applies_to_messages() {
+        let message = || {
+            Record::new()
+                .field("point", "feeder1.voltage")
+                .field("value", 2301)
+        };
+        let script = Script::parse("msg.value = msg.value * 0.1; msg.site = \"north\";").unwrap();
+        let record = script.apply(message()).unwrap().unwrap();
+        assert_eq!(
+            record.get("value"),
+            Some(&output::Value::Float(230.10000000000002))
+        );
+        assert_eq!(
+            record.get("site"),
+            Some(&output::Value::String("north".into()))
+        );
+        assert_eq!(record.fields().count(), 3);
+
+        let script = Script::parse("if msg.value > 1000 { return false; }").unwrap();
+        assert!(script.apply(message()).unwrap().is_none());
+        let script = Script::parse("#{replaced: true}").unwrap();
+        let record = script.apply(message()).unwrap().unwrap();
+        assert_eq!(record.get("replaced"), Some(&output::Value::Bool(true)));
+        assert_eq!(record.get("point"), None);
+
+        let script = Script::parse("msg = 1; 2").unwrap();
+        assert_eq!(
+            script.apply(message()).unwrap_err().to_string(),
+            "msg must stay an object map, not i64"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Secrets kept out of command lines and config files: read from a file (such as a mounted
+//! Docker or Kubernetes secret), or looked up in the OS keyring through the platform's own
+//! tool, `secret-tool` (libsecret) on Linux and `security` on macOS.
+//!
+//! A keyring entry is found by service and account, e.g. stored with
+//! `secret-tool store --label "edge site-a" service edge_tools account edge`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::auth::Credentials;
+
+#[derive(Debug)]
+pub enum SecretError {
+    Read(PathBuf, std::io::Error),
+    Empty(PathBuf),
+    Decrypt(crate::age::Error),
+    Keyring {
+        service: String,
+        account: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::Read(path, err) => write!(f, "unable to read {}: {err}", path.display()),
+            SecretError::Empty(path) => write!(f, "{} is empty", path.display()),
+            SecretError::Decrypt(err) => write!(f, "{err}"),
+            SecretError::Keyring {
+                service,
+                account,
+                message,
+            } => write!(
+                f,
+                "no keyring secret for service {service} account {account}: {message}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// The file's contents without the trailing newline editors and `echo` add.
+pub fn read_file(path: &Path) -> Result<String, SecretError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| SecretError::Read(path.to_path_buf(), err))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            if metadata.permissions().mode() & 0o077 != 0 {
+                log::warn!(
+                    "{} can be read by other users; chmod 600 it",
+                    path.display()
+                );
+            }
+        }
+    }
+    let secret = contents.trim_end_matches(['\r', '\n']);
+    if secret.is_empty() {
+        return Err(SecretError::Empty(path.to_path_buf()));
+    }
+    Ok(secret.to_string())
+}
+
+pub fn keyring(service: &str, account: &str) -> Result<String, SecretError> {
+    let error = |message: String| SecretError::Keyring {
+        service: service.to_string(),
+        account: account.to_string(),
+        message,
+    };
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        command.args(["find-generic-password", "-s", service, "-a", account, "-w"]);
+        command
+    } else if cfg!(unix) {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", service, "account", account]);
+        command
+    } else {
+        return Err(error(
+            "keyrings are only supported on Linux and macOS".to_string(),
+        ));
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            let program = command.get_program().to_string_lossy().to_string();
+            error(format!("unable to run {program}: {err}"))
+        })?;
+    let secret = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches(['\r', '\n'])
+        .to_string();
+    if !output.status.success() || secret.is_empty() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(error(if message.is_empty() {
+            "not found".to_string()
+        } else {
+            message
+        }));
+    }
+    Ok(secret)
+}
+
+impl Credentials {
+    /// Reads the password and token from files, unless they were given directly.
+    pub fn or_files(
+        self,
+        password_file: Option<&Path>,
+        token_file: Option<&Path>,
+    ) -> Result<Credentials, SecretError> {
+        let read = |value: Option<String>, path: Option<&Path>| match (value, path) {
+            (Some(value), _) => Ok(Some(value)),
+            (None, Some(path)) => read_file(path).map(Some),
+            (None, None) => Ok(None),
+        };
+        Ok(Credentials {
+            username: self.username,
+            password: read(self.password, password_file)?,
+            token: read(self.token, token_file)?,
+            ..self
+        })
+    }
+
+    /// Reads a NATS .creds file, unless the credentials already have one.
+    pub fn or_creds_file(self, path: Option<&Path>) -> Result<Credentials, SecretError> {
+        let creds = match (self.creds, path) {
+            (Some(creds), _) => Some(creds),
+            // Parsers of the file expect its last line to end too.
+            (None, Some(path)) => Some(read_file(path)? + "\n"),
+            (None, None) => None,
+        };
+        Ok(Credentials { creds, ..self })
+    }
+}
@@ -0,0 +1,57 @@
+//! Serial port names as they are typed: `/dev/ttyUSB0` on Linux, `COM3` on Windows. Ports from
+//! COM10 up are often written `\\.\COM10`, the device path Windows needs for them; the serial
+//! library adds that prefix itself, so it's taken off here rather than doubled. On Unix,
+//! [`ports`] lists the ports there are and [`accessible`] says whether this user may use one.
+
+/// `name` the way the serial library opens it.
+pub fn port_name(name: &str) -> &str {
+    let name = name.trim();
+    #[cfg(windows)]
+    if let Some(bare) = name
+        .strip_prefix(r"\\.\")
+        .or_else(|| name.strip_prefix("//./"))
+    {
+        return bare;
+    }
+    name
+}
+
+/// What to try when a port won't open, for the error message.
+pub fn hint() -> &'static str {
+    match cfg!(windows) {
+        true => "ports are named like COM3; Device Manager lists them under Ports (COM & LPT)",
+        false => "ports are named like /dev/ttyUSB0; check the user is in the dialout group",
+    }
+}
+
+/// The serial devices plugged in or built in, on Unix: USB adapters and board UARTs. The
+/// legacy `ttyS` ports are left out, since Linux creates them whether or not there's a port.
+#[cfg(unix)]
+pub fn ports() -> Vec<std::path::PathBuf> {
+    const PREFIXES: [&str; 5] = ["ttyUSB", "ttyACM", "ttyAMA", "ttymxc", "serial"];
+    let Ok(entries) = std::fs::read_dir("/dev") else {
+        return Vec::new();
+    };
+    let mut ports: Vec<_> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    ports.sort();
+    ports
+}
+
+/// Whether this user may open `port` for reading and writing, checked without opening it.
+#[cfg(unix)]
+pub fn accessible(port: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(port.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: the path is a valid C string for the duration of the call.
+    unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) == 0 }
+}
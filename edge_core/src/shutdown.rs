@@ -0,0 +1,251 @@
+//! Clean exits for commands that run until they're stopped: watches, subscriptions, bridges and
+//! listeners. The first SIGINT or SIGTERM (Ctrl-C or Ctrl-Break on Windows, or a stop from the
+//! service manager) asks the command to stop once the value in hand is written out, sinks are
+//! flushed and connections closed; a second one exits at once. A watch or subscription that
+//! fails stops the same way before it exits with the error's status. Either way the command
+//! ends with a [`Summary`] of what it did on stderr:
+//!
+//! ```text
+//! ^C
+//! Stopped after 1m 12.4s: 724 reads (10.0/s), 3 errors
+//! ```
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+// Exit status for the second signal, as a shell reports a process killed by SIGINT.
+const INTERRUPTED: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static LISTEN: Once = Once::new();
+static NOTIFY: Notify = Notify::const_new();
+
+/// Starts catching SIGINT and SIGTERM on the runtime; calling it again does nothing.
+pub fn listen() {
+    LISTEN.call_once(|| {
+        let Ok(mut signals) = Signals::new() else {
+            log::warn!("Unable to catch signals; stopping won't print a summary");
+            return;
+        };
+        tokio::spawn(async move {
+            loop {
+                signals.recv().await;
+                request();
+            }
+        });
+    });
+}
+
+/// Asks the command to stop, as a signal does: the first time once the value in hand is
+/// written out, the second time at once.
+pub fn request() {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        std::process::exit(INTERRUPTED);
+    }
+    log::info!("Stopping; signal again to exit at once");
+    NOTIFY.notify_waiters();
+}
+
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> std::io::Result<Signals> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Signals {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.interrupt.recv() => {}
+            _ = self.terminate.recv() => {}
+        }
+    }
+}
+
+// Ctrl-Break is what a console sends in place of SIGTERM.
+#[cfg(windows)]
+struct Signals {
+    interrupt: tokio::signal::windows::CtrlC,
+    terminate: tokio::signal::windows::CtrlBreak,
+}
+
+#[cfg(windows)]
+impl Signals {
+    fn new() -> std::io::Result<Signals> {
+        use tokio::signal::windows::{ctrl_break, ctrl_c};
+        Ok(Signals {
+            interrupt: ctrl_c()?,
+            terminate: ctrl_break()?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.interrupt.recv() => {}
+            _ = self.terminate.recv() => {}
+        }
+    }
+}
+
+/// [`listen`] for tools without a runtime, where loops check [`is_requested`] or sleep with
+/// [`sleep`].
+pub fn listen_blocking() {
+    extern "C" fn handle(_: libc::c_int) {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            // Only async-signal-safe calls in here.
+            unsafe { libc::_exit(INTERRUPTED) };
+        }
+    }
+    LISTEN.call_once(|| {
+        let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    });
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Resolves once a stop is asked for, right away if it already was. Never resolves without
+/// [`listen`].
+pub async fn requested() {
+    let notified = NOTIFY.notified();
+    if is_requested() {
+        return;
+    }
+    notified.await;
+}
+
+/// Sleeps for `duration` unless a stop is asked for first; false when it was.
+pub fn sleep(duration: Duration) -> bool {
+    let end = Instant::now() + duration;
+    while !is_requested() {
+        let left = end.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+    false
+}
+
+/// What a long-running command got through, for the line it ends with. Clones count into the
+/// same totals, for [`crate::stats`] reports.
+#[derive(Clone)]
+pub struct Summary {
+    start: Instant,
+    // Counted things in the order they were first seen, e.g. reads or messages.
+    counts: Arc<Mutex<Vec<(&'static str, u64)>>>,
+    errors: Arc<AtomicU64>,
+}
+
+impl Summary {
+    /// Starts the clock on a run counting `what`, plural nouns like `reads`, listed in the
+    /// summary even when none were seen.
+    pub fn new(what: &[&'static str]) -> Summary {
+        Summary {
+            start: Instant::now(),
+            counts: Arc::new(Mutex::new(what.iter().map(|what| (*what, 0)).collect())),
+            errors: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn count(&self, what: &'static str) {
+        self.add(what, 1);
+    }
+
+    pub fn add(&self, what: &'static str, count: u64) {
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        match counts.iter_mut().find(|(name, _)| *name == what) {
+            Some((_, total)) => *total += count,
+            None => counts.push((what, count)),
+        }
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How long the run has been going, the counts so far and the errors.
+    pub(crate) fn totals(&self) -> (Duration, Vec<(&'static str, u64)>, u64) {
+        let counts = self
+            .counts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        (
+            self.start.elapsed(),
+            counts,
+            self.errors.load(Ordering::Relaxed),
+        )
+    }
+
+    /// E.g. `Stopped after 12.4s: 120 messages (9.7/s), 2 errors`.
+    pub fn line(&self) -> String {
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs_f64();
+        let mut parts: Vec<String> = self
+            .counts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .iter()
+            .map(|(what, count)| {
+                let what = noun(what, *count);
+                match seconds {
+                    s if s >= 0.1 => format!("{count} {what} ({:.1}/s)", *count as f64 / s),
+                    _ => format!("{count} {what}"),
+                }
+            })
+            .collect();
+        let errors = self.errors.load(Ordering::Relaxed);
+        parts.push(match errors {
+            1 => "1 error".to_string(),
+            errors => format!("{errors} errors"),
+        });
+        let verb = if is_requested() {
+            "Stopped"
+        } else {
+            "Finished"
+        };
+        format!("{verb} after {}: {}", duration(elapsed), parts.join(", "))
+    }
+
+    /// Prints [`Summary::line`] on stderr, leaving stdout to the results.
+    pub fn print(&self) {
+        eprintln!("{}", self.line());
+    }
+}
+
+/// `what` for `count` of them: `reads`, but `read` for one.
+pub(crate) fn noun(what: &str, count: u64) -> &str {
+    match count {
+        1 => what.strip_suffix('s').unwrap_or(what),
+        _ => what,
+    }
+}
+
+/// `1h 2m 12.4s`, to a tenth of a second.
+pub(crate) fn duration(elapsed: Duration) -> String {
+    let tenths = elapsed.as_millis() / 100;
+    let (hours, minutes) = (tenths / 36_000, tenths / 600 % 60);
+    let seconds = format!("{}.{}s", tenths / 10 % 60, tenths % 10);
+    match (hours, minutes) {
+        (0, 0) => seconds,
+        (0, minutes) => format!("{minutes}m {seconds}"),
+        (hours, minutes) => format!("{hours}h {minutes}m {seconds}"),
+    }
+}
@@ -0,0 +1,144 @@
+//! `--stats`: while a long-running command works, a line on stderr every interval with what its
+//! [`Summary`] has counted, how fast since the last line, the errors, the reconnects to devices
+//! and servers, and how full its queues are, so a capture or bridge that's falling behind shows
+//! it before it's stopped:
+//!
+//! ```text
+//! Stats after 1m 0.0s: 724 reads (+120, 12.0/s), 3 errors (+1), 2 reconnects
+//! Stats after 30.0s: 301 points (+101, 10.1/s), 0 errors, 0 reconnects, bridge queue 12/1024
+//! ```
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::buffer::Depth;
+use crate::shutdown::{self, Summary};
+
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct StatsArgs {
+    /// Print throughput, errors, reconnects and queue depths on stderr this often while the
+    /// command runs, e.g. 10s.
+    #[clap(long, value_name = "INTERVAL", value_parser = humantime::parse_duration)]
+    pub stats: Option<Duration>,
+}
+
+/// Counts a connection opened again after the device or server dropped it.
+pub fn reconnected() {
+    RECONNECTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Notes a new connection to `endpoint`, counting it as a reconnect unless it's the first.
+pub fn connected(endpoint: &str) {
+    static SEEN: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    let mut seen = SEEN
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if !seen.insert(endpoint.to_string()) {
+        reconnected();
+    }
+}
+
+/// Reconnects counted so far by the whole process.
+pub fn reconnects() -> u64 {
+    RECONNECTS.load(Ordering::Relaxed)
+}
+
+/// Prints a line about `summary` and `queues` every `interval` until dropped. Does nothing
+/// without an interval, so commands can hold one either way.
+pub struct Reporter {
+    task: Option<JoinHandle<()>>,
+}
+
+impl Reporter {
+    pub fn start(interval: Option<Duration>, summary: &Summary, queues: Vec<Depth>) -> Reporter {
+        let Some(interval) = interval.filter(|interval| !interval.is_zero()) else {
+            return Reporter { task: None };
+        };
+        let summary = summary.clone();
+        let task = tokio::spawn(async move {
+            let start = tokio::time::Instant::now();
+            let mut ticks = tokio::time::interval_at(start + interval, interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut previous = Previous::default();
+            loop {
+                ticks.tick().await;
+                eprintln!("{}", line(&summary, &queues, &mut previous));
+            }
+        });
+        Reporter { task: Some(task) }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// The totals at the last line, for the changes since.
+#[derive(Default)]
+struct Previous {
+    elapsed: Duration,
+    counts: Vec<(&'static str, u64)>,
+    errors: u64,
+}
+
+fn line(summary: &Summary, queues: &[Depth], previous: &mut Previous) -> String {
+    let (elapsed, counts, errors) = summary.totals();
+    let seconds = elapsed.saturating_sub(previous.elapsed).as_secs_f64();
+    let mut parts: Vec<String> = counts
+        .iter()
+        .map(|(what, count)| {
+            let before = previous
+                .counts
+                .iter()
+                .find(|(name, _)| name == what)
+                .map_or(0, |(_, count)| *count);
+            let change = count - before;
+            let what = shutdown::noun(what, *count);
+            match seconds {
+                s if s >= 0.1 => format!("{count} {what} (+{change}, {:.1}/s)", change as f64 / s),
+                _ => format!("{count} {what} (+{change})"),
+            }
+        })
+        .collect();
+    let noun = shutdown::noun("errors", errors);
+    parts.push(match errors - previous.errors {
+        0 => format!("{errors} {noun}"),
+        change => format!("{errors} {noun} (+{change})"),
+    });
+    let reconnects = reconnects();
+    parts.push(format!(
+        "{reconnects} {}",
+        shutdown::noun("reconnects", reconnects)
+    ));
+    for queue in queues {
+        let Some((waiting, dropped)) = queue.get() else {
+            continue;
+        };
+        let mut part = format!("{} queue {waiting}/{}", queue.what(), queue.limit());
+        if dropped > 0 {
+            part.push_str(&format!(" ({dropped} dropped)"));
+        }
+        parts.push(part);
+    }
+    *previous = Previous {
+        elapsed,
+        counts,
+        errors,
+    };
+    format!(
+        "Stats after {}: {}",
+        shutdown::duration(elapsed),
+        parts.join(", ")
+    )
+}
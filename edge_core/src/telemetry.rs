@@ -0,0 +1,535 @@
+//! OpenTelemetry traces and metrics about the tools' own work: connections, requests and
+//! message handling. They are exported as OTLP/HTTP JSON to `<endpoint>/v1/traces` and
+//! `<endpoint>/v1/metrics` from a background thread; the opentelemetry crates aren't available
+//! to the workspace, and JSON over plain HTTP is what every collector accepts.
+//!
+//! Until [`init`] is called every function here is a no-op, so instrumented code doesn't need
+//! to check whether telemetry is on.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::output::{self, Record, Value};
+
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+const BATCH: usize = 256;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(2);
+// Histogram bucket bounds in seconds, from a quick register read to a stuck connection.
+const BOUNDS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+#[derive(Debug)]
+pub struct TelemetryError(String);
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TelemetryError {}
+
+/// Starts exporting to `endpoint` (`http://host:port`, port 4318 by default) as `service`.
+/// `OTEL_SERVICE_NAME` overrides the service name, as in the OpenTelemetry SDKs.
+pub fn init(endpoint: &str, service: &str) -> Result<(), TelemetryError> {
+    let target = Target::parse(endpoint)?;
+    let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| service.to_string());
+    let (sender, receiver) = mpsc::channel();
+    let metrics = Arc::new(Mutex::new(MetricState::default()));
+    let started = now_nanos();
+    let thread = {
+        let metrics = metrics.clone();
+        let resource = resource(&service);
+        std::thread::spawn(move || export_loop(&target, &resource, started, &receiver, &metrics))
+    };
+    EXPORTER
+        .set(Exporter {
+            sender: Mutex::new(Some(sender)),
+            metrics,
+            thread: Mutex::new(Some(thread)),
+        })
+        .map_err(|_| TelemetryError("telemetry is already initialised".into()))
+}
+
+/// Sends whatever hasn't been exported yet and stops the exporter, waiting at most a couple
+/// of seconds for the collector. Called on exit, including fatal errors.
+pub fn shutdown() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    if let Ok(mut sender) = exporter.sender.lock() {
+        // Dropping the sender ends the export loop after a final export.
+        sender.take();
+    }
+    let thread = exporter
+        .thread
+        .lock()
+        .ok()
+        .and_then(|mut thread| thread.take());
+    if let Some(thread) = thread {
+        let deadline = Instant::now() + TIMEOUT * 2;
+        while !thread.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Starts a new trace with this span at its root.
+pub fn span(name: &str) -> Span {
+    Span::start(name, random_id(), None)
+}
+
+/// Counts one occurrence, exported as a monotonic sum.
+pub fn count(name: &str, attributes: &[(&str, &str)]) {
+    if let Some(exporter) = EXPORTER.get() {
+        if let Ok(mut metrics) = exporter.metrics.lock() {
+            *metrics
+                .sums
+                .entry(name.to_string())
+                .or_default()
+                .entry(key(attributes))
+                .or_default() += 1.0;
+        }
+    }
+}
+
+/// Records a duration in seconds, exported as a histogram.
+pub fn duration(name: &str, seconds: f64, attributes: &[(&str, &str)]) {
+    if let Some(exporter) = EXPORTER.get() {
+        if let Ok(mut metrics) = exporter.metrics.lock() {
+            let histogram = metrics
+                .histograms
+                .entry(name.to_string())
+                .or_default()
+                .entry(key(attributes))
+                .or_insert_with(|| Histogram {
+                    count: 0,
+                    sum: 0.0,
+                    buckets: vec![0; BOUNDS.len() + 1],
+                });
+            histogram.count += 1;
+            histogram.sum += seconds;
+            let bucket = BOUNDS
+                .iter()
+                .position(|bound| seconds <= *bound)
+                .unwrap_or(BOUNDS.len());
+            histogram.buckets[bucket] += 1;
+        }
+    }
+}
+
+/// Ends `span` with the outcome of `result` and records it in the `<metric>` counter (by
+/// result) and the `<metric>.duration` histogram.
+pub fn finish<T, E: fmt::Display>(
+    mut span: Span,
+    metric: &str,
+    attributes: &[(&str, &str)],
+    result: &Result<T, E>,
+) {
+    let seconds = span.started.elapsed().as_secs_f64();
+    let outcome = match result {
+        Ok(_) => "ok",
+        Err(err) => {
+            span.fail(err);
+            "error"
+        }
+    };
+    let mut counted = attributes.to_vec();
+    counted.push(("result", outcome));
+    count(metric, &counted);
+    duration(&format!("{metric}.duration"), seconds, attributes);
+    span.end();
+}
+
+/// A timed operation; it is exported when dropped or ended.
+pub struct Span {
+    name: String,
+    trace: u128,
+    id: u64,
+    parent: Option<u64>,
+    start: u128,
+    started: Instant,
+    attributes: Vec<(String, Value)>,
+    error: Option<String>,
+    ended: bool,
+}
+
+impl Span {
+    fn start(name: &str, trace: u128, parent: Option<u64>) -> Span {
+        Span {
+            name: name.to_string(),
+            trace,
+            id: random_id() as u64,
+            parent,
+            start: now_nanos(),
+            started: Instant::now(),
+            attributes: Vec::new(),
+            error: None,
+            ended: EXPORTER.get().is_none(),
+        }
+    }
+
+    /// A span for a step of this one, in the same trace.
+    pub fn child(&self, name: &str) -> Span {
+        Span::start(name, self.trace, Some(self.id))
+    }
+
+    pub fn attribute(mut self, key: &str, value: impl Into<Value>) -> Span {
+        self.set(key, value);
+        self
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<Value>) {
+        self.attributes.push((key.to_string(), value.into()));
+    }
+
+    /// Marks the span as failed.
+    pub fn fail(&mut self, error: impl fmt::Display) {
+        self.error = Some(error.to_string());
+    }
+
+    pub fn end(mut self) {
+        self.export();
+    }
+
+    fn export(&mut self) {
+        if self.ended {
+            return;
+        }
+        self.ended = true;
+        let Some(exporter) = EXPORTER.get() else {
+            return;
+        };
+        let mut span = Record::new()
+            .field("traceId", format!("{:032x}", self.trace))
+            .field("spanId", format!("{:016x}", self.id));
+        if let Some(parent) = self.parent {
+            span.push("parentSpanId", format!("{parent:016x}"));
+        }
+        span.push("name", self.name.as_str());
+        // SPAN_KIND_INTERNAL
+        span.push("kind", 1);
+        span.push("startTimeUnixNano", self.start.to_string());
+        span.push("endTimeUnixNano", now_nanos().to_string());
+        span.push("attributes", attributes(&self.attributes));
+        let status = match &self.error {
+            // STATUS_CODE_ERROR
+            Some(message) => Record::new()
+                .field("code", 2)
+                .field("message", message.as_str()),
+            // STATUS_CODE_OK
+            None => Record::new().field("code", 1),
+        };
+        span.push("status", status);
+        if let Ok(sender) = exporter.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                let _ = sender.send(span);
+            }
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.export();
+    }
+}
+
+struct Exporter {
+    sender: Mutex<Option<mpsc::Sender<Record>>>,
+    metrics: Arc<Mutex<MetricState>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Histogram {
+    count: u64,
+    sum: f64,
+    buckets: Vec<u64>,
+}
+
+/// Cumulative since the tool started; keyed by metric name, then by attribute set.
+#[derive(Default)]
+struct MetricState {
+    sums: BTreeMap<String, BTreeMap<Vec<(String, String)>, f64>>,
+    histograms: BTreeMap<String, BTreeMap<Vec<(String, String)>, Histogram>>,
+}
+
+fn key(attributes: &[(&str, &str)]) -> Vec<(String, String)> {
+    let mut key: Vec<(String, String)> = attributes
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    key.sort();
+    key
+}
+
+struct Target {
+    host: String,
+    port: u16,
+    // Base path the signal paths are appended to, without a trailing slash.
+    path: String,
+}
+
+impl Target {
+    fn parse(endpoint: &str) -> Result<Target, TelemetryError> {
+        let rest = match endpoint.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some((scheme, _)) => {
+                return Err(TelemetryError(format!(
+                    "unsupported scheme {scheme}, only http:// OTLP endpoints are supported"
+                )))
+            }
+            None => endpoint,
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], rest[index..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        // `[v6]:port`, `host:port` or just a host.
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']') {
+                Some((host, port)) => (host, port.strip_prefix(':')),
+                None => return Err(TelemetryError(format!("unterminated [ in {endpoint}"))),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| TelemetryError(format!("invalid port in {endpoint}")))?,
+            None => 4318,
+        };
+        if host.is_empty() {
+            return Err(TelemetryError(format!("no host in {endpoint}")));
+        }
+        Ok(Target {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    fn post(&self, signal: &str, body: &str) -> io::Result<()> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "collector did not resolve"))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {}/v1/{signal} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        )?;
+        // Read the whole (short) response; closing with it unread resets the connection.
+        let mut response = Vec::new();
+        stream.take(64 * 1024).read_to_end(&mut response)?;
+        let status = String::from_utf8_lossy(&response);
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "collector answered {}",
+                status.lines().next().unwrap_or("nothing")
+            ))),
+        }
+    }
+}
+
+fn export_loop(
+    target: &Target,
+    resource: &Record,
+    started: u128,
+    spans: &mpsc::Receiver<Record>,
+    metrics: &Mutex<MetricState>,
+) {
+    let scope = Record::new()
+        .field("name", "edge_tools")
+        .field("version", env!("CARGO_PKG_VERSION"));
+    let mut batch = Vec::new();
+    let mut last_export = Instant::now();
+    let mut failing = false;
+    let mut report = |result: io::Result<()>| match result {
+        // Only the first failure in a row is a warning, so a missing collector doesn't flood
+        // the log.
+        Err(err) if !failing => {
+            log::warn!("Unable to export telemetry: {err}");
+            failing = true;
+        }
+        Err(err) => log::debug!("Unable to export telemetry: {err}"),
+        Ok(()) => failing = false,
+    };
+
+    loop {
+        let wait = EXPORT_INTERVAL.saturating_sub(last_export.elapsed());
+        let done = match spans.recv_timeout(wait) {
+            Ok(span) => {
+                batch.push(span);
+                false
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => false,
+            Err(mpsc::RecvTimeoutError::Disconnected) => true,
+        };
+        let due = done || last_export.elapsed() >= EXPORT_INTERVAL;
+        if batch.len() >= BATCH || (!batch.is_empty() && due) {
+            let body = Record::new().field(
+                "resourceSpans",
+                vec![Record::new().field("resource", resource.clone()).field(
+                    "scopeSpans",
+                    vec![Record::new()
+                        .field("scope", scope.clone())
+                        .field("spans", std::mem::take(&mut batch))],
+                )],
+            );
+            report(target.post("traces", &output::json(&body.into())));
+        }
+        if due {
+            let metrics = match metrics.lock() {
+                Ok(metrics) => metric_records(&metrics, started),
+                Err(_) => Vec::new(),
+            };
+            if !metrics.is_empty() {
+                let body = Record::new().field(
+                    "resourceMetrics",
+                    vec![Record::new().field("resource", resource.clone()).field(
+                        "scopeMetrics",
+                        vec![Record::new()
+                            .field("scope", scope.clone())
+                            .field("metrics", metrics)],
+                    )],
+                );
+                report(target.post("metrics", &output::json(&body.into())));
+            }
+            last_export = Instant::now();
+        }
+        if done {
+            return;
+        }
+    }
+}
+
+fn metric_records(metrics: &MetricState, started: u128) -> Vec<Record> {
+    let now = now_nanos().to_string();
+    let started = started.to_string();
+    let point = |attributes: &[(String, String)]| {
+        let attributes: Vec<(String, Value)> = attributes
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::from(value)))
+            .collect();
+        Record::new()
+            .field("attributes", self::attributes(&attributes))
+            .field("startTimeUnixNano", started.as_str())
+            .field("timeUnixNano", now.as_str())
+    };
+
+    let mut records = Vec::new();
+    for (name, series) in &metrics.sums {
+        let points: Vec<Record> = series
+            .iter()
+            .map(|(attributes, value)| point(attributes).field("asDouble", *value))
+            .collect();
+        records.push(
+            Record::new().field("name", name.as_str()).field(
+                "sum",
+                Record::new()
+                    // AGGREGATION_TEMPORALITY_CUMULATIVE
+                    .field("aggregationTemporality", 2)
+                    .field("isMonotonic", true)
+                    .field("dataPoints", points),
+            ),
+        );
+    }
+    for (name, series) in &metrics.histograms {
+        let points: Vec<Record> = series
+            .iter()
+            .map(|(attributes, histogram)| {
+                let buckets: Vec<String> =
+                    histogram.buckets.iter().map(|n| n.to_string()).collect();
+                point(attributes)
+                    .field("count", histogram.count.to_string())
+                    .field("sum", histogram.sum)
+                    .field("bucketCounts", buckets)
+                    .field("explicitBounds", &BOUNDS[..])
+            })
+            .collect();
+        records.push(
+            Record::new()
+                .field("name", name.as_str())
+                .field("unit", "s")
+                .field(
+                    "histogram",
+                    Record::new()
+                        .field("aggregationTemporality", 2)
+                        .field("dataPoints", points),
+                ),
+        );
+    }
+    records
+}
+
+fn resource(service: &str) -> Record {
+    let service = [
+        ("service.name".to_string(), Value::from(service)),
+        (
+            "service.version".to_string(),
+            Value::from(env!("CARGO_PKG_VERSION")),
+        ),
+        ("host.name".to_string(), Value::from(crate::net::hostname())),
+    ];
+    Record::new().field("attributes", attributes(&service))
+}
+
+/// OTLP's `KeyValue` list: `{"key": ..., "value": {"stringValue": ...}}`.
+fn attributes(attributes: &[(String, Value)]) -> Vec<Record> {
+    attributes
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(value) => Record::new().field("boolValue", *value),
+                Value::Integer(value) => Record::new().field("intValue", value.to_string()),
+                Value::Unsigned(value) => Record::new().field("intValue", value.to_string()),
+                Value::Float(value) => Record::new().field("doubleValue", *value),
+                Value::String(value) => Record::new().field("stringValue", value.as_str()),
+                other => Record::new().field("stringValue", output::json(other)),
+            };
+            Record::new()
+                .field("key", key.as_str())
+                .field("value", value)
+        })
+        .collect()
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0)
+}
+
+/// Random enough for trace and span ids: a randomly keyed hash of a counter and the clock.
+fn random_id() -> u128 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let half = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(now_nanos());
+        hasher.finish()
+    };
+    (u128::from(half()) << 64) | u128::from(half())
+}
@@ -0,0 +1,732 @@
+//! `--template` files in a subset of [Handlebars](https://handlebarsjs.com), for shaping each
+//! result into the exact line format a downstream system expects. The handlebars crate isn't
+//! available to the workspace, so this renders the core of the language: `{{field}}` and
+//! `{{nested.field}}` (`{{values.[0]}}` or `{{values.0}}` for list items), `{{this}}`, `../`
+//! and `@root`, the `#if`, `#unless`, `#each` and `#with` blocks with `{{else}}`, the `lookup`
+//! helper, `{{! comments }}`, `~` whitespace control and the usual rule that block tags alone
+//! on a line don't leave an empty line behind. Partials and other helpers are rejected when the
+//! template is loaded.
+//!
+//! ```text
+//! {{timestamp}};{{unit_id}};{{#each values}}{{../register}}+{{@index}}={{this}}{{#unless @last}},{{/unless}}{{/each}}
+//! ```
+//!
+//! The template sees one result as the tool's JSON output would show it and is printed as
+//! written, so a file ending in a newline gives one line per result. Values are inserted as
+//! they are: nothing is HTML-escaped, `{{{triple}}}` braces mean the same as double ones.
+//! Missing fields render as nothing, lists as their items joined with commas and records as
+//! JSON.
+
+use std::fmt;
+use std::io;
+use std::path::{Path as FilePath, PathBuf};
+
+use crate::output::{self, Record, Value};
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Read(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Read(path, err) => {
+                write!(f, "unable to read {}: {err}", path.display())
+            }
+            TemplateError::Syntax { line, message } => {
+                write!(f, "syntax error on line {line}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TemplateError::Read(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed template, ready to render any number of results.
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn load(path: &FilePath) -> Result<Template, TemplateError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| TemplateError::Read(path.into(), err))?;
+        Template::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        let mut tokens = lex(source)?.into_iter().peekable();
+        let nodes = parse_nodes(&mut tokens, None)?;
+        Ok(Template { nodes })
+    }
+
+    pub fn render(&self, record: &Record) -> String {
+        let root = Value::Record(record.clone());
+        let mut rendered = String::new();
+        let scope = [Frame {
+            value: &root,
+            data: Data::default(),
+        }];
+        render(&self.nodes, &scope, &mut rendered);
+        rendered
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Insert(Expr),
+    If {
+        // `#unless` is `#if` the other way round.
+        negate: bool,
+        condition: Expr,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        list: Expr,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    With {
+        value: Expr,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Debug)]
+enum Expr {
+    Path(Path),
+    Literal(Value),
+    Lookup(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Default)]
+struct Path {
+    // Contexts to go up before looking at the segments, one per `../`.
+    parents: usize,
+    root: bool,
+    // `@index`, `@key`, `@first` or `@last`.
+    data: Option<String>,
+    segments: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Tag { line: usize, tag: Tag },
+}
+
+#[derive(Debug)]
+enum Tag {
+    Insert(Vec<String>),
+    Open(String, Vec<String>),
+    Close(String),
+    Else,
+    Comment,
+}
+
+impl Tag {
+    // Tags that produce nothing themselves, so a line holding only one of them disappears.
+    fn is_standalone(&self) -> bool {
+        !matches!(self, Tag::Insert(_))
+    }
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> TemplateError {
+    TemplateError::Syntax {
+        line,
+        message: message.into(),
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut text = String::new();
+    let mut rest = source;
+    let mut line = 1;
+    let mut strip_next = false;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("\\{{") {
+            text.push_str("{{");
+            rest = after;
+            continue;
+        }
+        if !rest.starts_with("{{") {
+            let c = rest.chars().next().expect("not empty");
+            if c == '\n' {
+                line += 1;
+            }
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let tag_line = line;
+        let (inner, after) = if let Some(body) = rest.strip_prefix("{{!--") {
+            let end = body
+                .find("--}}")
+                .ok_or_else(|| syntax(tag_line, "unterminated {{!-- comment"))?;
+            (&rest[2..5 + end], &body[end + 4..])
+        } else if rest.starts_with("{{{") {
+            let end = rest
+                .find("}}}")
+                .ok_or_else(|| syntax(tag_line, "unterminated {{{"))?;
+            (&rest[3..end], &rest[end + 3..])
+        } else {
+            let end = rest
+                .find("}}")
+                .ok_or_else(|| syntax(tag_line, "unterminated {{"))?;
+            (&rest[2..end], &rest[end + 2..])
+        };
+        line += inner.matches('\n').count();
+        lex_tag(inner, tag_line, &mut tokens, &mut text, &mut strip_next)?;
+        rest = after;
+    }
+    push_text(&mut tokens, &mut text, &mut strip_next);
+    remove_standalone_lines(&mut tokens);
+    Ok(tokens)
+}
+
+fn lex_tag(
+    inner: &str,
+    line: usize,
+    tokens: &mut Vec<Token>,
+    text: &mut String,
+    strip_next: &mut bool,
+) -> Result<(), TemplateError> {
+    let (strip_before, inner) = match inner.strip_prefix('~') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+    let (strip_after, inner) = match inner.strip_suffix('~') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+    if strip_before {
+        let kept = text.trim_end().len();
+        text.truncate(kept);
+    }
+    push_text(tokens, text, strip_next);
+    if strip_before {
+        if let Some(Token::Text(previous)) = tokens.last_mut() {
+            let kept = previous.trim_end().len();
+            previous.truncate(kept);
+        }
+    }
+    let inner = inner.trim();
+    let tag = if inner.starts_with('!') {
+        Tag::Comment
+    } else if let Some(open) = inner.strip_prefix('#') {
+        let mut words = words(open, line)?.into_iter();
+        let name = words
+            .next()
+            .ok_or_else(|| syntax(line, "block without a helper name"))?;
+        Tag::Open(name, words.collect())
+    } else if let Some(close) = inner.strip_prefix('/') {
+        Tag::Close(close.trim().to_string())
+    } else if inner == "else" || inner == "^" {
+        Tag::Else
+    } else if inner.starts_with('>') {
+        return Err(syntax(line, "partials aren't supported"));
+    } else if inner.starts_with('^') {
+        return Err(syntax(
+            line,
+            "inverse blocks aren't supported, use {{#unless}}",
+        ));
+    } else {
+        let words = words(inner, line)?;
+        if words.is_empty() {
+            return Err(syntax(line, "empty {{}}"));
+        }
+        Tag::Insert(words)
+    };
+    tokens.push(Token::Tag { line, tag });
+    *strip_next = strip_after;
+    Ok(())
+}
+
+fn push_text(tokens: &mut Vec<Token>, text: &mut String, strip_next: &mut bool) {
+    let mut taken = std::mem::take(text);
+    if *strip_next {
+        taken = taken.trim_start().to_string();
+        *strip_next = false;
+    }
+    if !taken.is_empty() {
+        tokens.push(Token::Text(taken));
+    }
+}
+
+/// Drops the indentation and line break around block tags and comments that are alone on
+/// their line, so a template can put `{{#each}}` on a line of its own.
+fn remove_standalone_lines(tokens: &mut Vec<Token>) {
+    let standalone: Vec<usize> = (0..tokens.len())
+        .filter(|&index| is_standalone(tokens, index))
+        .collect();
+    for index in standalone {
+        if let Some(Token::Text(text)) = index.checked_sub(1).map(|before| &mut tokens[before]) {
+            let kept = text.rfind('\n').map_or(0, |newline| newline + 1);
+            text.truncate(kept);
+        }
+        if let Some(Token::Text(text)) = tokens.get_mut(index + 1) {
+            let removed = text.find('\n').map_or(text.len(), |newline| newline + 1);
+            text.drain(..removed);
+        }
+    }
+    tokens.retain(|token| !matches!(token, Token::Text(text) if text.is_empty()));
+}
+
+fn is_standalone(tokens: &[Token], index: usize) -> bool {
+    match &tokens[index] {
+        Token::Tag { tag, .. } if tag.is_standalone() => {}
+        _ => return false,
+    }
+    let before = match index.checked_sub(1).map(|before| &tokens[before]) {
+        None => true,
+        Some(Token::Text(text)) => match text.rfind('\n') {
+            Some(newline) => text[newline + 1..].trim().is_empty(),
+            None => index == 1 && text.trim().is_empty(),
+        },
+        Some(Token::Tag { .. }) => false,
+    };
+    let after = match tokens.get(index + 1) {
+        None => true,
+        Some(Token::Text(text)) => match text.find('\n') {
+            Some(newline) => text[..newline].trim().is_empty(),
+            None => index + 2 == tokens.len() && text.trim().is_empty(),
+        },
+        Some(Token::Tag { .. }) => false,
+    };
+    before && after
+}
+
+/// The words of a tag: paths, numbers and quoted strings.
+fn words(inner: &str, line: usize) -> Result<Vec<String>, TemplateError> {
+    let mut words = Vec::new();
+    let mut chars = inner.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' || c == '\'' {
+            word.push(chars.next().expect("peeked"));
+            loop {
+                match chars.next() {
+                    Some(end) if end == c => break,
+                    Some(other) => word.push(other),
+                    None => return Err(syntax(line, "unterminated string")),
+                }
+            }
+            word.push(c);
+        } else {
+            let mut in_brackets = false;
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() && !in_brackets {
+                    break;
+                }
+                match c {
+                    '[' => in_brackets = true,
+                    ']' => in_brackets = false,
+                    _ => {}
+                }
+                word.push(c);
+                chars.next();
+            }
+            if in_brackets {
+                return Err(syntax(line, "unterminated ["));
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Nodes up to the end of the template or, inside a block, up to its `{{else}}` or close tag,
+/// which is left for the caller.
+fn parse_nodes(
+    tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>,
+    block: Option<(&str, usize)>,
+) -> Result<Vec<Node>, TemplateError> {
+    let mut nodes = Vec::new();
+    loop {
+        match tokens.peek() {
+            None => {
+                return match block {
+                    Some((name, line)) => {
+                        Err(syntax(line, format!("{{{{#{name}}}}} isn't closed")))
+                    }
+                    None => Ok(nodes),
+                }
+            }
+            Some(Token::Tag {
+                tag: Tag::Else | Tag::Close(_),
+                line,
+            }) if block.is_none() => {
+                return Err(syntax(*line, "{{else}} or close tag outside a block"));
+            }
+            Some(Token::Tag {
+                tag: Tag::Else | Tag::Close(_),
+                ..
+            }) => return Ok(nodes),
+            _ => {}
+        }
+        let Some(token) = tokens.next() else {
+            continue;
+        };
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text)),
+            Token::Tag {
+                tag: Tag::Comment, ..
+            } => {}
+            Token::Tag {
+                tag: Tag::Insert(words),
+                line,
+            } => nodes.push(Node::Insert(expression(&words, line)?)),
+            Token::Tag {
+                tag: Tag::Open(name, arguments),
+                line,
+            } => {
+                if arguments.is_empty() {
+                    return Err(syntax(line, format!("{{{{#{name}}}}} needs a value")));
+                }
+                let value = expression(&arguments, line)?;
+                let body = parse_nodes(tokens, Some((&name, line)))?;
+                let otherwise = match tokens.next() {
+                    Some(Token::Tag { tag: Tag::Else, .. }) => {
+                        let otherwise = parse_nodes(tokens, Some((&name, line)))?;
+                        match tokens.next() {
+                            Some(Token::Tag {
+                                tag: Tag::Close(closed),
+                                line: close_line,
+                            }) => check_close(&name, &closed, close_line)?,
+                            _ => return Err(syntax(line, "a block can only have one {{else}}")),
+                        }
+                        otherwise
+                    }
+                    Some(Token::Tag {
+                        tag: Tag::Close(closed),
+                        line: close_line,
+                    }) => {
+                        check_close(&name, &closed, close_line)?;
+                        Vec::new()
+                    }
+                    _ => unreachable!("parse_nodes stops at else or close"),
+                };
+                nodes.push(match name.as_str() {
+                    "if" | "unless" => Node::If {
+                        negate: name == "unless",
+                        condition: value,
+                        then: body,
+                        otherwise,
+                    },
+                    "each" => Node::Each {
+                        list: value,
+                        body,
+                        otherwise,
+                    },
+                    "with" => Node::With {
+                        value,
+                        body,
+                        otherwise,
+                    },
+                    other => return Err(syntax(line, format!("unknown block helper `{other}`"))),
+                });
+            }
+            Token::Tag {
+                tag: Tag::Else | Tag::Close(_),
+                ..
+            } => unreachable!("handled above"),
+        }
+    }
+}
+
+fn check_close(opened: &str, closed: &str, line: usize) -> Result<(), TemplateError> {
+    if opened == closed {
+        Ok(())
+    } else {
+        Err(syntax(
+            line,
+            format!("{{{{/{closed}}}}} doesn't close {{{{#{opened}}}}}"),
+        ))
+    }
+}
+
+fn expression(words: &[String], line: usize) -> Result<Expr, TemplateError> {
+    match words {
+        [word] => operand(word, line),
+        [helper, object, key] if helper == "lookup" => Ok(Expr::Lookup(
+            Box::new(operand(object, line)?),
+            Box::new(operand(key, line)?),
+        )),
+        [helper, ..] if helper == "lookup" => Err(syntax(line, "lookup needs an object and a key")),
+        [helper, ..] => Err(syntax(line, format!("unknown helper `{helper}`"))),
+        [] => Err(syntax(line, "empty expression")),
+    }
+}
+
+fn operand(word: &str, line: usize) -> Result<Expr, TemplateError> {
+    if let Some(quote @ ('"' | '\'')) = word.chars().next() {
+        let inner = word.trim_start_matches(quote).trim_end_matches(quote);
+        return Ok(Expr::Literal(Value::String(inner.to_string())));
+    }
+    match word {
+        "true" => return Ok(Expr::Literal(Value::Bool(true))),
+        "false" => return Ok(Expr::Literal(Value::Bool(false))),
+        "null" | "undefined" => return Ok(Expr::Literal(Value::Null)),
+        _ => {}
+    }
+    if word.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        if let Ok(number) = word.parse::<i64>() {
+            return Ok(Expr::Literal(Value::Integer(number)));
+        }
+        if let Ok(number) = word.parse::<f64>() {
+            return Ok(Expr::Literal(Value::Float(number)));
+        }
+    }
+    path(word, line).map(Expr::Path)
+}
+
+fn path(word: &str, line: usize) -> Result<Path, TemplateError> {
+    let mut path = Path::default();
+    let mut rest = word;
+    if let Some(data) = rest.strip_prefix('@') {
+        let (name, after) = match data.find(['.', '/']) {
+            Some(end) => (&data[..end], &data[end + 1..]),
+            None => (data, ""),
+        };
+        match name {
+            "root" => path.root = true,
+            "index" | "key" | "first" | "last" => path.data = Some(name.to_string()),
+            other => return Err(syntax(line, format!("unknown data variable @{other}"))),
+        }
+        rest = after;
+    }
+    while let Some(after) = rest.strip_prefix("../") {
+        path.parents += 1;
+        rest = after;
+    }
+    let mut chars = rest.chars().peekable();
+    let mut segment = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    segment.push(c);
+                }
+            }
+            '.' | '/' => {
+                push_segment(&mut path, std::mem::take(&mut segment));
+            }
+            c => segment.push(c),
+        }
+    }
+    push_segment(&mut path, segment);
+    if path.data.is_some() && !path.segments.is_empty() {
+        return Err(syntax(
+            line,
+            format!("@{} has no fields", path.data.unwrap_or_default()),
+        ));
+    }
+    Ok(path)
+}
+
+fn push_segment(path: &mut Path, segment: String) {
+    // `this`, `.` and an empty segment all mean the current context.
+    if !(segment.is_empty() || segment == "this" || segment == ".") {
+        path.segments.push(segment);
+    }
+}
+
+#[derive(Default, Clone)]
+struct Data {
+    index: Option<usize>,
+    key: Option<String>,
+    first: bool,
+    last: bool,
+}
+
+struct Frame<'a> {
+    value: &'a Value,
+    data: Data,
+}
+
+fn render(nodes: &[Node], scope: &[Frame<'_>], rendered: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => rendered.push_str(text),
+            Node::Insert(expr) => {
+                if let Some(value) = evaluate(expr, scope) {
+                    rendered.push_str(&display(&value));
+                }
+            }
+            Node::If {
+                negate,
+                condition,
+                then,
+                otherwise,
+            } => {
+                let truthy = evaluate(condition, scope).is_some_and(|value| truthy(&value));
+                let branch = if truthy != *negate { then } else { otherwise };
+                render(branch, scope, rendered);
+            }
+            Node::Each {
+                list,
+                body,
+                otherwise,
+            } => {
+                let items: Vec<(Data, Value)> = match evaluate(list, scope) {
+                    Some(Value::List(values)) => {
+                        let count = values.len();
+                        values
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, value)| {
+                                let data = Data {
+                                    index: Some(index),
+                                    key: None,
+                                    first: index == 0,
+                                    last: index + 1 == count,
+                                };
+                                (data, value)
+                            })
+                            .collect()
+                    }
+                    Some(Value::Record(record)) => {
+                        let count = record.fields().count();
+                        record
+                            .fields()
+                            .enumerate()
+                            .map(|(index, (name, value))| {
+                                let data = Data {
+                                    index: Some(index),
+                                    key: Some(name.to_string()),
+                                    first: index == 0,
+                                    last: index + 1 == count,
+                                };
+                                (data, value.clone())
+                            })
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+                if items.is_empty() {
+                    render(otherwise, scope, rendered);
+                }
+                for (data, value) in &items {
+                    render_in(body, scope, value, data.clone(), rendered);
+                }
+            }
+            Node::With {
+                value,
+                body,
+                otherwise,
+            } => match evaluate(value, scope) {
+                Some(value) if truthy(&value) => {
+                    render_in(body, scope, &value, Data::default(), rendered)
+                }
+                _ => render(otherwise, scope, rendered),
+            },
+        }
+    }
+}
+
+/// Renders `nodes` with `value` as the current context.
+fn render_in(
+    nodes: &[Node],
+    scope: &[Frame<'_>],
+    value: &Value,
+    data: Data,
+    rendered: &mut String,
+) {
+    let mut inner: Vec<Frame<'_>> = scope
+        .iter()
+        .map(|frame| Frame {
+            value: frame.value,
+            data: frame.data.clone(),
+        })
+        .collect();
+    inner.push(Frame { value, data });
+    render(nodes, &inner, rendered);
+}
+
+fn evaluate(expr: &Expr, scope: &[Frame<'_>]) -> Option<Value> {
+    match expr {
+        Expr::Literal(value) => Some(value.clone()),
+        Expr::Path(path) => resolve(path, scope),
+        Expr::Lookup(object, key) => {
+            let object = evaluate(object, scope)?;
+            let key = evaluate(key, scope)?;
+            member(&object, &display(&key)).cloned()
+        }
+    }
+}
+
+fn resolve(path: &Path, scope: &[Frame<'_>]) -> Option<Value> {
+    let frame = if path.root {
+        scope.first()?
+    } else {
+        scope.get(scope.len().checked_sub(1 + path.parents)?)?
+    };
+    if let Some(name) = &path.data {
+        let data = &frame.data;
+        return match name.as_str() {
+            "index" => data.index.map(Value::from),
+            "key" => data.key.clone().map(Value::String),
+            "first" => Some(Value::Bool(data.first)),
+            "last" => Some(Value::Bool(data.last)),
+            _ => None,
+        };
+    }
+    let mut value = frame.value;
+    for segment in &path.segments {
+        value = member(value, segment)?;
+    }
+    Some(value.clone())
+}
+
+fn member<'v>(value: &'v Value, name: &str) -> Option<&'v Value> {
+    match value {
+        Value::Record(record) => record.get(name),
+        Value::List(values) => values.get(name.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+/// Handlebars' idea of false: missing, null, false, 0, "" and [].
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Integer(value) => *value != 0,
+        Value::Unsigned(value) => *value != 0,
+        Value::Float(value) => *value != 0.0,
+        Value::String(value) => !value.is_empty(),
+        Value::List(values) => !values.is_empty(),
+        Value::Record(_) => true,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        Value::List(values) => values.iter().map(display).collect::<Vec<_>>().join(","),
+        value => output::json(value),
+    }
+}
@@ -0,0 +1,306 @@
+//! Just enough TOML for the config file: tables, string/integer/float/boolean values and
+//! single-line arrays. Inline tables, multi-line strings and dates are rejected with an error
+//! rather than misread.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{value}"),
+            Value::Integer(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value}"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+        }
+    }
+}
+
+/// Tables keyed by their dotted header path (`profiles.site-a`), with keys before any header
+/// stored under the empty path.
+pub type Document = BTreeMap<Vec<String>, BTreeMap<String, Value>>;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The lines of table headers and keys, by table path and then key.
+pub type Lines = BTreeMap<Vec<String>, usize>;
+
+pub fn parse(input: &str) -> Result<Document, ParseError> {
+    parse_with_lines(input).map(|(document, _)| document)
+}
+
+/// Parses `input` and notes where each table and key is, to point errors found later at
+/// their line.
+pub fn parse_with_lines(input: &str) -> Result<(Document, Lines), ParseError> {
+    let mut document = Document::new();
+    let mut lines = Lines::new();
+    let mut table: Vec<String> = Vec::new();
+    document.insert(table.clone(), BTreeMap::new());
+
+    for (index, line) in input.lines().enumerate() {
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+        let mut cursor = Cursor::new(line);
+        cursor.skip_whitespace();
+        if cursor.at_end_of_line() {
+            continue;
+        }
+
+        if cursor.eat('[') {
+            if cursor.eat('[') {
+                return Err(error("arrays of tables are not supported".to_string()));
+            }
+            table = cursor.key_path(']').map_err(error)?;
+            if !cursor.eat(']') {
+                return Err(error("expected ] after table name".to_string()));
+            }
+            cursor.skip_whitespace();
+            if !cursor.at_end_of_line() {
+                return Err(error("unexpected text after table header".to_string()));
+            }
+            if document.insert(table.clone(), BTreeMap::new()).is_some() {
+                return Err(error(format!("table [{}] defined twice", table.join("."))));
+            }
+            lines.insert(table.clone(), index + 1);
+            continue;
+        }
+
+        let key = cursor.key_path('=').map_err(error)?;
+        if key.len() != 1 {
+            return Err(error("dotted keys are not supported".to_string()));
+        }
+        if !cursor.eat('=') {
+            return Err(error("expected = after key".to_string()));
+        }
+        cursor.skip_whitespace();
+        let value = cursor.value().map_err(error)?;
+        cursor.skip_whitespace();
+        if !cursor.at_end_of_line() {
+            return Err(error("unexpected text after value".to_string()));
+        }
+
+        let entries = document.entry(table.clone()).or_default();
+        if entries.insert(key[0].clone(), value).is_some() {
+            return Err(error(format!("key {} defined twice", key[0])));
+        }
+        lines.insert([table.as_slice(), &key].concat(), index + 1);
+    }
+    Ok((document, lines))
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: &'a str) -> Cursor<'a> {
+        Cursor { rest: line }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    fn at_end_of_line(&self) -> bool {
+        self.rest.is_empty() || self.rest.starts_with('#')
+    }
+
+    /// Reads `a.b."c d"` up to (not including) `terminator`.
+    fn key_path(&mut self, terminator: char) -> Result<Vec<String>, String> {
+        let mut path = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let part = match self.peek() {
+                Some('"') => {
+                    self.bump();
+                    self.basic_string()?
+                }
+                Some('\'') => {
+                    self.bump();
+                    self.literal_string()?
+                }
+                _ => {
+                    let end = self
+                        .rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                        .unwrap_or(self.rest.len());
+                    if end == 0 {
+                        return Err(format!("expected a key, found {:?}", self.rest));
+                    }
+                    let part = self.rest[..end].to_string();
+                    self.rest = &self.rest[end..];
+                    part
+                }
+            };
+            path.push(part);
+            self.skip_whitespace();
+            if self.eat('.') {
+                continue;
+            }
+            if self.peek() == Some(terminator) {
+                return Ok(path);
+            }
+            return Err(format!("expected {terminator:?} after key"));
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => {
+                if self.rest.starts_with("\"\"\"") {
+                    return Err("multi-line strings are not supported".to_string());
+                }
+                self.bump();
+                Ok(Value::String(self.basic_string()?))
+            }
+            Some('\'') => {
+                if self.rest.starts_with("'''") {
+                    return Err("multi-line strings are not supported".to_string());
+                }
+                self.bump();
+                Ok(Value::String(self.literal_string()?))
+            }
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(']') {
+                        return Ok(Value::Array(values));
+                    }
+                    if self.rest.is_empty() {
+                        return Err("multi-line arrays are not supported".to_string());
+                    }
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    if !self.eat(',') && self.peek() != Some(']') {
+                        return Err("expected , or ] in array".to_string());
+                    }
+                }
+            }
+            Some('{') => Err("inline tables are not supported".to_string()),
+            _ => {
+                let end = self
+                    .rest
+                    .find([' ', '\t', ',', ']', '#'])
+                    .unwrap_or(self.rest.len());
+                let token = &self.rest[..end];
+                self.rest = &self.rest[end..];
+                scalar(token)
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                None => return Err("unterminated string".to_string()),
+                Some('"') => return Ok(value),
+                Some('\\') => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('u') => value.push(self.unicode_escape(4)?),
+                    Some('U') => value.push(self.unicode_escape(8)?),
+                    other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, digits: usize) -> Result<char, String> {
+        let hex = self.rest.get(..digits).ok_or("truncated unicode escape")?;
+        self.rest = &self.rest[digits..];
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("invalid unicode escape {hex}"))
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        let end = self.rest.find('\'').ok_or("unterminated string")?;
+        let value = self.rest[..end].to_string();
+        self.rest = &self.rest[end + 1..];
+        Ok(value)
+    }
+}
+
+fn scalar(token: &str) -> Result<Value, String> {
+    match token {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        "" => return Err("missing value".to_string()),
+        _ => {}
+    }
+
+    let digits = token.replace('_', "");
+    let (sign, unsigned) = match digits.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let radix = [("0x", 16), ("0o", 8), ("0b", 2)]
+        .iter()
+        .find_map(|(prefix, radix)| unsigned.strip_prefix(prefix).map(|rest| (rest, *radix)));
+    if let Some((rest, radix)) = radix {
+        return i64::from_str_radix(rest, radix)
+            .map(|value| Value::Integer(sign * value))
+            .map_err(|err| format!("invalid integer {token}: {err}"));
+    }
+    if let Ok(value) = digits.parse::<i64>() {
+        return Ok(Value::Integer(value));
+    }
+    if let Ok(value) = digits.parse::<f64>() {
+        return Ok(Value::Float(value));
+    }
+    Err(format!("unsupported value {token}"))
+}
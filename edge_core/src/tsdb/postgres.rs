@@ -0,0 +1,286 @@
+//! Just enough of the PostgreSQL frontend/backend protocol to run simple queries: startup,
+//! cleartext and SCRAM-SHA-256 authentication, and the simple query flow. TLS isn't supported;
+//! connect through a local socket or a trusted network.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::rand::SecureRandom;
+use ring::{digest, hmac, pbkdf2};
+use std::num::NonZeroU32;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+use crate::exit::{Code, Error};
+
+pub struct Connection {
+    stream: BufStream<TcpStream>,
+}
+
+impl Connection {
+    pub async fn connect(
+        address: &str,
+        user: &str,
+        password: Option<&str>,
+        database: &str,
+    ) -> Result<Connection, Error> {
+        let addr = crate::net::resolve(address, 5432)
+            .map_err(|err| Error::new(Code::Usage, format!("{address}: {err}")))?;
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|err| Error::new(Code::from_io(&err), format!("{address}: {err}")))?;
+        let mut connection = Connection {
+            stream: BufStream::new(stream),
+        };
+
+        let mut startup = 196_608i32.to_be_bytes().to_vec();
+        for (key, value) in [("user", user), ("database", database)] {
+            startup.extend_from_slice(key.as_bytes());
+            startup.push(0);
+            startup.extend_from_slice(value.as_bytes());
+            startup.push(0);
+        }
+        startup.push(0);
+        let mut message = ((startup.len() + 4) as i32).to_be_bytes().to_vec();
+        message.extend(startup);
+        connection.write(&message).await?;
+
+        let password =
+            || password.ok_or_else(|| Error::new(Code::Auth, "the server asks for a password"));
+        let mut scram: Option<Scram> = None;
+        loop {
+            let (kind, body) = connection.read().await?;
+            match kind {
+                b'R' => match be32(&body) {
+                    0 => {}
+                    3 => {
+                        let mut reply = password()?.as_bytes().to_vec();
+                        reply.push(0);
+                        connection.send(b'p', &reply).await?;
+                    }
+                    10 => {
+                        let mechanisms = String::from_utf8_lossy(&body[4..]);
+                        if !mechanisms.split('\0').any(|m| m == "SCRAM-SHA-256") {
+                            return Err(Error::new(
+                                Code::Auth,
+                                format!("unsupported SASL mechanisms {mechanisms}"),
+                            ));
+                        }
+                        let started = Scram::start(password()?)?;
+                        let first = started.client_first();
+                        let mut reply = b"SCRAM-SHA-256\0".to_vec();
+                        reply.extend_from_slice(&(first.len() as i32).to_be_bytes());
+                        reply.extend_from_slice(first.as_bytes());
+                        connection.send(b'p', &reply).await?;
+                        scram = Some(started);
+                    }
+                    11 => {
+                        let scram = scram.as_mut().ok_or_else(|| protocol("unexpected SASL"))?;
+                        let reply = scram.client_final(&String::from_utf8_lossy(&body[4..]))?;
+                        connection.send(b'p', reply.as_bytes()).await?;
+                    }
+                    12 => {
+                        let scram = scram.as_ref().ok_or_else(|| protocol("unexpected SASL"))?;
+                        scram.verify(&String::from_utf8_lossy(&body[4..]))?;
+                    }
+                    5 => {
+                        return Err(Error::new(
+                            Code::Auth,
+                            "md5 password authentication isn't supported, use scram-sha-256",
+                        ))
+                    }
+                    other => {
+                        return Err(Error::new(
+                            Code::Auth,
+                            format!("unsupported authentication request {other}"),
+                        ))
+                    }
+                },
+                b'E' => return Err(server_error(&body)),
+                b'Z' => return Ok(connection),
+                // Parameter status, backend key data and notices.
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs one or more statements with the simple query protocol.
+    pub async fn execute(&mut self, sql: &str) -> Result<(), Error> {
+        let mut query = sql.as_bytes().to_vec();
+        query.push(0);
+        self.send(b'Q', &query).await?;
+        let mut failed = None;
+        loop {
+            let (kind, body) = self.read().await?;
+            match kind {
+                b'E' => failed = Some(server_error(&body)),
+                b'Z' => {
+                    return match failed {
+                        Some(err) => Err(err),
+                        None => Ok(()),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn send(&mut self, kind: u8, body: &[u8]) -> Result<(), Error> {
+        let mut message = vec![kind];
+        message.extend_from_slice(&((body.len() + 4) as i32).to_be_bytes());
+        message.extend_from_slice(body);
+        self.write(&message).await
+    }
+
+    async fn write(&mut self, message: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(message).await.map_err(io)?;
+        self.stream.flush().await.map_err(io)
+    }
+
+    async fn read(&mut self) -> Result<(u8, Vec<u8>), Error> {
+        let kind = self.stream.read_u8().await.map_err(io)?;
+        let length = self.stream.read_i32().await.map_err(io)?;
+        if !(4..=64 * 1024 * 1024).contains(&length) {
+            return Err(protocol("bad message length"));
+        }
+        let mut body = vec![0u8; length as usize - 4];
+        self.stream.read_exact(&mut body).await.map_err(io)?;
+        Ok((kind, body))
+    }
+}
+
+/// SCRAM-SHA-256 as in RFC 7677, without channel binding.
+struct Scram {
+    password: String,
+    nonce: String,
+    client_first_bare: String,
+    // Expected server signature, once the proof has been sent.
+    server_signature: Option<Vec<u8>>,
+}
+
+impl Scram {
+    fn start(password: &str) -> Result<Scram, Error> {
+        let mut nonce = [0u8; 18];
+        ring::rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::new(Code::Failure, "no random numbers for SCRAM"))?;
+        let nonce = BASE64.encode(nonce);
+        Ok(Scram {
+            password: password.to_string(),
+            client_first_bare: format!("n=,r={nonce}"),
+            nonce,
+            server_signature: None,
+        })
+    }
+
+    fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare)
+    }
+
+    fn client_final(&mut self, server_first: &str) -> Result<String, Error> {
+        let attribute = |name: &str| {
+            server_first
+                .split(',')
+                .find_map(|part| part.strip_prefix(name))
+                .ok_or_else(|| protocol("incomplete SCRAM challenge"))
+        };
+        let nonce = attribute("r=")?;
+        if !nonce.starts_with(&self.nonce) {
+            return Err(protocol("SCRAM nonce mismatch"));
+        }
+        let salt = BASE64
+            .decode(attribute("s=")?)
+            .map_err(|_| protocol("bad SCRAM salt"))?;
+        let iterations = attribute("i=")?
+            .parse::<u32>()
+            .ok()
+            .and_then(NonZeroU32::new)
+            .ok_or_else(|| protocol("bad SCRAM iteration count"))?;
+
+        let mut salted = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            &salt,
+            self.password.as_bytes(),
+            &mut salted,
+        );
+        let salted = hmac::Key::new(hmac::HMAC_SHA256, &salted);
+        let client_key = hmac::sign(&salted, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+        let without_proof = format!("c=biws,r={nonce}");
+        let auth_message = format!("{},{server_first},{without_proof}", self.client_first_bare);
+        let signature = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()),
+            auth_message.as_bytes(),
+        );
+        let proof: Vec<u8> = client_key
+            .as_ref()
+            .iter()
+            .zip(signature.as_ref())
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+        let server_key = hmac::sign(&salted, b"Server Key");
+        self.server_signature = Some(
+            hmac::sign(
+                &hmac::Key::new(hmac::HMAC_SHA256, server_key.as_ref()),
+                auth_message.as_bytes(),
+            )
+            .as_ref()
+            .to_vec(),
+        );
+        Ok(format!("{without_proof},p={}", BASE64.encode(proof)))
+    }
+
+    fn verify(&self, server_final: &str) -> Result<(), Error> {
+        let signature = server_final
+            .strip_prefix("v=")
+            .and_then(|signature| BASE64.decode(signature.trim_end_matches('\0')).ok());
+        match (signature, &self.server_signature) {
+            (Some(signature), Some(expected)) if &signature == expected => Ok(()),
+            _ => Err(Error::new(
+                Code::Auth,
+                "the server's SCRAM signature doesn't match",
+            )),
+        }
+    }
+}
+
+/// An ErrorResponse: `severity: message`, with authentication failures as such.
+fn server_error(body: &[u8]) -> Error {
+    let mut severity = "ERROR".to_string();
+    let mut code = String::new();
+    let mut message = String::new();
+    for field in body.split(|byte| *byte == 0) {
+        let Some((kind, value)) = field.split_first() else {
+            continue;
+        };
+        let value = String::from_utf8_lossy(value).to_string();
+        match kind {
+            b'S' => severity = value,
+            b'C' => code = value,
+            b'M' => message = value,
+            _ => {}
+        }
+    }
+    let status = match code.as_str() {
+        "28000" | "28P01" | "42501" => Code::Auth,
+        "3D000" => Code::Usage,
+        _ => Code::Protocol,
+    };
+    Error::new(status, format!("{severity}: {message}"))
+}
+
+fn be32(body: &[u8]) -> i32 {
+    match body {
+        [a, b, c, d, ..] => i32::from_be_bytes([*a, *b, *c, *d]),
+        _ => -1,
+    }
+}
+
+fn io(err: std::io::Error) -> Error {
+    Error::new(Code::from_io(&err), err)
+}
+
+fn protocol(message: &str) -> Error {
+    Error::new(Code::Protocol, message)
+}
@@ -0,0 +1,490 @@
+//! A full-screen live table for `--tui`: one row per subject or tag with its latest value, how
+//! often it changed, how long ago, and a sparkline of recent numeric values. It draws with
+//! plain ANSI escapes from its own thread, so tools keep their async loops and just feed it
+//! updates.
+//!
+//! Keys: ↑/↓ (or k/j), PgUp/PgDn and Home/End scroll, `s` changes the sort column, `r` reverses
+//! it, `q` or Ctrl-C quits.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Numeric values kept per row for the sparkline.
+const HISTORY: usize = 120;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+pub struct Dashboard {
+    updates: Option<mpsc::Sender<(String, String)>>,
+    // Set by the drawing thread when the user quits.
+    closed: Arc<AtomicBool>,
+    // Set by the tool to take the terminal back right away.
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal until the user quits or the dashboard is dropped. Fails when
+    /// stdin or stdout isn't a terminal.
+    pub fn start(title: &str, key_column: &str) -> io::Result<Dashboard> {
+        let terminal = Terminal::raw()?;
+        let (updates, receiver) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut screen = Screen {
+            title: title.to_string(),
+            key_column: key_column.to_uppercase(),
+            rows: Vec::new(),
+            sort: Sort::Key,
+            reverse: false,
+            offset: 0,
+            ended: false,
+        };
+        let flag = closed.clone();
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let result = screen.run(&terminal, &receiver, &stopped);
+            flag.store(true, Ordering::SeqCst);
+            drop(terminal);
+            result
+        });
+        Ok(Dashboard {
+            updates: Some(updates),
+            closed,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn update(&self, key: impl Into<String>, value: impl Into<String>) {
+        if let Some(updates) = &self.updates {
+            let _ = updates.send((key.into(), value.into()));
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the user has quit.
+    pub async fn closed(&self) {
+        while !self.is_closed() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Waits for the user to quit, leaving the last values on screen, then gives the terminal
+    /// back.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.updates = None;
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Ok(())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Raw mode on stdin and the alternate screen on stdout, both undone on drop.
+#[cfg(unix)]
+struct Terminal {
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    fn raw() -> io::Result<Terminal> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 || libc::isatty(libc::STDOUT_FILENO) != 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the dashboard needs an interactive terminal",
+                ));
+            }
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            // Reads return after 100ms without input, which paces the redraw loop.
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            print!("\x1b[?1049h\x1b[?25l");
+            io::stdout().flush()?;
+            Ok(Terminal { saved })
+        }
+    }
+
+    /// Columns and rows, 80x24 when the terminal won't say.
+    fn size(&self) -> (usize, usize) {
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0
+            {
+                (size.ws_col as usize, size.ws_row as usize)
+            } else {
+                (80, 24)
+            }
+        }
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        let n = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        n.max(0) as usize
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+// Elsewhere the dashboard isn't available and watches print their usual lines.
+#[cfg(not(unix))]
+struct Terminal;
+
+#[cfg(not(unix))]
+impl Terminal {
+    fn raw() -> io::Result<Terminal> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the dashboard needs a Unix terminal",
+        ))
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (80, 24)
+    }
+
+    fn read(&self, _buffer: &mut [u8]) -> usize {
+        0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Sort {
+    Key,
+    Value,
+    Count,
+    Age,
+}
+
+impl Sort {
+    fn next(self) -> Sort {
+        match self {
+            Sort::Key => Sort::Value,
+            Sort::Value => Sort::Count,
+            Sort::Count => Sort::Age,
+            Sort::Age => Sort::Key,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Sort::Key => "key",
+            Sort::Value => "value",
+            Sort::Count => "updates",
+            Sort::Age => "age",
+        }
+    }
+}
+
+struct Row {
+    key: String,
+    value: String,
+    count: u64,
+    updated: Instant,
+    history: VecDeque<f64>,
+}
+
+enum Key {
+    Quit,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Sort,
+    Reverse,
+}
+
+struct Screen {
+    title: String,
+    key_column: String,
+    rows: Vec<Row>,
+    sort: Sort,
+    reverse: bool,
+    offset: usize,
+    // The tool stopped sending updates.
+    ended: bool,
+}
+
+impl Screen {
+    fn run(
+        &mut self,
+        terminal: &Terminal,
+        updates: &mpsc::Receiver<(String, String)>,
+        stop: &AtomicBool,
+    ) -> io::Result<()> {
+        let mut buffer = [0u8; 64];
+        let mut last_draw = Instant::now() - Duration::from_secs(1);
+        let mut dirty = true;
+        while !stop.load(Ordering::SeqCst) {
+            loop {
+                match updates.try_recv() {
+                    Ok((key, value)) => {
+                        self.update(key, value);
+                        dirty = true;
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        dirty |= !self.ended;
+                        self.ended = true;
+                        break;
+                    }
+                }
+            }
+
+            let (width, height) = terminal.size();
+            let page = height.saturating_sub(3).max(1);
+            let n = terminal.read(&mut buffer);
+            for key in keys(&buffer[..n]) {
+                match key {
+                    Key::Quit => return Ok(()),
+                    Key::Up => self.offset = self.offset.saturating_sub(1),
+                    Key::Down => self.offset += 1,
+                    Key::PageUp => self.offset = self.offset.saturating_sub(page),
+                    Key::PageDown => self.offset += page,
+                    Key::Home => self.offset = 0,
+                    Key::End => self.offset = self.rows.len(),
+                    Key::Sort => self.sort = self.sort.next(),
+                    Key::Reverse => self.reverse = !self.reverse,
+                }
+                dirty = true;
+            }
+            self.offset = self.offset.min(self.rows.len().saturating_sub(page));
+
+            // Ages tick even without updates, so redraw at least every half second.
+            if dirty || last_draw.elapsed() >= Duration::from_millis(500) {
+                self.sort_rows();
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(self.render(width, height).as_bytes())?;
+                stdout.flush()?;
+                last_draw = Instant::now();
+                dirty = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, key: String, value: String) {
+        let row = match self.rows.iter_mut().position(|row| row.key == key) {
+            Some(index) => &mut self.rows[index],
+            None => {
+                self.rows.push(Row {
+                    key,
+                    value: String::new(),
+                    count: 0,
+                    updated: Instant::now(),
+                    history: VecDeque::new(),
+                });
+                self.rows.last_mut().expect("just pushed")
+            }
+        };
+        if let Ok(number) = value.trim().parse::<f64>() {
+            if row.history.len() == HISTORY {
+                row.history.pop_front();
+            }
+            row.history.push_back(number);
+        }
+        row.value = value;
+        row.count += 1;
+        row.updated = Instant::now();
+    }
+
+    fn sort_rows(&mut self) {
+        let sort = self.sort;
+        self.rows.sort_by(|a, b| {
+            let ordering = match sort {
+                Sort::Key => a.key.cmp(&b.key),
+                Sort::Value => match (a.value.trim().parse::<f64>(), b.value.trim().parse::<f64>())
+                {
+                    (Ok(x), Ok(y)) => x.total_cmp(&y),
+                    _ => a.value.cmp(&b.value),
+                },
+                Sort::Count => a.count.cmp(&b.count),
+                // Most recently updated first.
+                Sort::Age => b.updated.cmp(&a.updated),
+            };
+            ordering.then_with(|| a.key.cmp(&b.key))
+        });
+        if self.reverse {
+            self.rows.reverse();
+        }
+    }
+
+    fn render(&self, width: usize, height: usize) -> String {
+        let key_width = self
+            .rows
+            .iter()
+            .map(|row| row.key.chars().count())
+            .chain([self.key_column.len()])
+            .max()
+            .unwrap_or(0)
+            .min(width * 2 / 5);
+        let value_width = self
+            .rows
+            .iter()
+            .map(|row| row.value.chars().count())
+            .chain([5])
+            .max()
+            .unwrap_or(0)
+            .min(width / 4);
+        let spark_width = width.saturating_sub(key_width + value_width + 8 + 7 + 8);
+
+        let mut out = String::from("\x1b[H\x1b[2J");
+        let status = format!(
+            "{} rows, by {}{}{}",
+            self.rows.len(),
+            self.sort.name(),
+            if self.reverse { " (reversed)" } else { "" },
+            if self.ended { ", stopped" } else { "" },
+        );
+        let title = fit(
+            &self.title,
+            width.saturating_sub(status.chars().count() + 2),
+        );
+        let gap = width.saturating_sub(title.chars().count() + status.chars().count());
+        out.push_str(&format!("\x1b[1m{title}{:gap$}{status}\x1b[0m\r\n", ""));
+        out.push_str(&format!(
+            "\x1b[7m{:<key_width$}  {:>value_width$}  {:>6}  {:>6}  {:<spark_width$}\x1b[0m\r\n",
+            self.key_column, "VALUE", "COUNT", "AGE", "HISTORY"
+        ));
+
+        let page = height.saturating_sub(3).max(1);
+        for row in self.rows.iter().skip(self.offset).take(page) {
+            out.push_str(&format!(
+                "{:<key_width$}  {:>value_width$}  {:>6}  {:>6}  {}\r\n",
+                fit(&row.key, key_width),
+                fit(&row.value, value_width),
+                row.count,
+                age(row.updated.elapsed()),
+                sparkline(&row.history, spark_width)
+            ));
+        }
+        out.push_str(&format!(
+            "\x1b[{height};1H\x1b[2m{}\x1b[0m",
+            fit(
+                "q quit  s sort  r reverse  ↑↓ PgUp PgDn scroll",
+                width.saturating_sub(1)
+            )
+        ));
+        out
+    }
+}
+
+fn keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut position = 0;
+    while position < input.len() {
+        let rest = &input[position..];
+        let (key, length) = match rest {
+            [0x1b, b'[', b'A', ..] => (Some(Key::Up), 3),
+            [0x1b, b'[', b'B', ..] => (Some(Key::Down), 3),
+            [0x1b, b'[', b'H', ..] => (Some(Key::Home), 3),
+            [0x1b, b'[', b'F', ..] => (Some(Key::End), 3),
+            [0x1b, b'[', b'5', b'~', ..] => (Some(Key::PageUp), 4),
+            [0x1b, b'[', b'6', b'~', ..] => (Some(Key::PageDown), 4),
+            [b'q', ..] | [b'Q', ..] | [3, ..] => (Some(Key::Quit), 1),
+            [b'k', ..] => (Some(Key::Up), 1),
+            [b'j', ..] => (Some(Key::Down), 1),
+            [b' ', ..] => (Some(Key::PageDown), 1),
+            [b'g', ..] => (Some(Key::Home), 1),
+            [b'G', ..] => (Some(Key::End), 1),
+            [b's', ..] => (Some(Key::Sort), 1),
+            [b'r', ..] => (Some(Key::Reverse), 1),
+            _ => (None, 1),
+        };
+        keys.extend(key);
+        position += length;
+    }
+    keys
+}
+
+/// Cuts `text` to `width` characters, marking the cut with an ellipsis.
+fn fit(text: &str, width: usize) -> String {
+    let text = text.replace(['\n', '\r', '\t'], " ");
+    if text.chars().count() <= width {
+        return text;
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    if width > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+fn age(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    if seconds < 10.0 {
+        format!("{seconds:.1}s")
+    } else if seconds < 120.0 {
+        format!("{}s", seconds as u64)
+    } else if seconds < 7200.0 {
+        format!("{}m", seconds as u64 / 60)
+    } else {
+        format!("{}h", seconds as u64 / 3600)
+    }
+}
+
+/// The newest `width` values scaled between their own minimum and maximum.
+fn sparkline(history: &VecDeque<f64>, width: usize) -> String {
+    let values: Vec<f64> = history
+        .iter()
+        .skip(history.len().saturating_sub(width))
+        .copied()
+        .collect();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| {
+            if max > min {
+                let level = (value - min) / (max - min) * (SPARKS.len() - 1) as f64;
+                SPARKS[level.round() as usize]
+            } else {
+                SPARKS[SPARKS.len() / 2]
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,116 @@
+//! Engineering units a point can be declared in, and conversions between the common ones of
+//! the same quantity: temperatures (°C, °F, K), pressures (Pa, hPa, kPa, MPa, mbar, bar,
+//! psi), energy (J, kJ, Wh, kWh, MWh) and power (W, kW, MW). Other units are carried through
+//! as labels but can't be converted.
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Quantity {
+    Temperature,
+    Pressure,
+    Energy,
+    Power,
+}
+
+struct Unit {
+    names: &'static [&'static str],
+    quantity: Quantity,
+    // The value in the quantity's base unit (K, Pa, J or W) is `value * factor + offset`.
+    factor: f64,
+    offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], quantity: Quantity, factor: f64) -> Unit {
+    Unit {
+        names,
+        quantity,
+        factor,
+        offset: 0.0,
+    }
+}
+
+const UNITS: [Unit; 18] = [
+    unit(&["K"], Quantity::Temperature, 1.0),
+    Unit {
+        names: &["°C", "C", "degC"],
+        quantity: Quantity::Temperature,
+        factor: 1.0,
+        offset: 273.15,
+    },
+    Unit {
+        names: &["°F", "F", "degF"],
+        quantity: Quantity::Temperature,
+        factor: 5.0 / 9.0,
+        offset: 459.67 * 5.0 / 9.0,
+    },
+    unit(&["Pa"], Quantity::Pressure, 1.0),
+    unit(&["hPa"], Quantity::Pressure, 1e2),
+    unit(&["kPa"], Quantity::Pressure, 1e3),
+    unit(&["MPa"], Quantity::Pressure, 1e6),
+    unit(&["mbar"], Quantity::Pressure, 1e2),
+    unit(&["bar"], Quantity::Pressure, 1e5),
+    unit(&["psi"], Quantity::Pressure, 6_894.757_293_168),
+    unit(&["J"], Quantity::Energy, 1.0),
+    unit(&["kJ"], Quantity::Energy, 1e3),
+    unit(&["Wh"], Quantity::Energy, 3.6e3),
+    unit(&["kWh"], Quantity::Energy, 3.6e6),
+    unit(&["MWh"], Quantity::Energy, 3.6e9),
+    unit(&["W"], Quantity::Power, 1.0),
+    unit(&["kW"], Quantity::Power, 1e3),
+    unit(&["MW"], Quantity::Power, 1e6),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // A point without a unit to convert from.
+    Missing,
+    Unknown(String),
+    Incompatible { from: String, to: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Missing => write!(f, "no unit to convert from"),
+            Error::Unknown(name) => write!(f, "no conversions for unit {name}"),
+            Error::Incompatible { from, to } => write!(f, "cannot convert {from} to {to}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn find(name: &str) -> Result<&'static Unit, Error> {
+    UNITS
+        .iter()
+        .find(|unit| unit.names.contains(&name.trim()))
+        .ok_or_else(|| Error::Unknown(name.to_string()))
+}
+
+/// Whether values in `name` can be converted to other units.
+pub fn is_known(name: &str) -> bool {
+    find(name).is_ok()
+}
+
+/// Whether `from` values can be converted to `to`.
+pub fn can_convert(from: &str, to: &str) -> Result<(), Error> {
+    match (find(from)?, find(to)?) {
+        (from, to) if from.quantity == to.quantity => Ok(()),
+        _ => Err(Error::Incompatible {
+            from: from.to_string(),
+            to: to.to_string(),
+        }),
+    }
+}
+
+/// `value` in `from` as a value in `to`.
+pub fn convert(value: f64, from: &str, to: &str) -> Result<f64, Error> {
+    can_convert(from, to)?;
+    let (from, to) = (find(from)?, find(to)?);
+    if std::ptr::eq(from, to) {
+        return Ok(value);
+    }
+    let base = value * from.factor + from.offset;
+    Ok((base - to.offset) / to.factor)
+}
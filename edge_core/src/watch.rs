@@ -0,0 +1,207 @@
+//! Repeating commands the same way in every tool. Polling commands flatten [`WatchArgs`] into
+//! their arguments and streams of messages [`StreamArgs`]:
+//!
+//! ```text
+//! -w, --watch                keep going until stopped
+//! -i, --interval <INTERVAL>  time between reads, e.g. 500ms or 5s (1s by default)
+//!     --reads <READS>        stop after this many reads (--count for messages)
+//!     --duration <DURATION>  stop after this long, e.g. 10m
+//! ```
+//!
+//! Any of `--interval`, `--reads`, `--count` or `--duration` implies `--watch`, and so does
+//! `--expect-within`, until the expectation is met (see [`crate::expect`]). Every watch also
+//! stops on SIGINT or SIGTERM (see [`crate::shutdown`]).
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::{expect, shutdown};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct WatchArgs {
+    /// Keep reading until stopped.
+    #[clap(short, long, action)]
+    pub watch: bool,
+    /// Time between reads, e.g. 500ms or 5s (1s by default); a bare number is milliseconds.
+    #[clap(short, long, value_parser = parse_interval)]
+    pub interval: Option<Duration>,
+    /// Stop after this many reads.
+    #[clap(long, action)]
+    pub reads: Option<u64>,
+    /// Stop after this long, e.g. 10m.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct StreamArgs {
+    /// Keep receiving until stopped instead of exiting after the first message.
+    #[clap(short, long, action)]
+    pub watch: bool,
+    /// Stop after this many messages.
+    #[clap(long, action)]
+    pub count: Option<u64>,
+    /// Stop after this long, e.g. 10m.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub duration: Option<Duration>,
+}
+
+/// How a read should repeat: once, or every `interval` until the read asks to stop, `count`
+/// reads are done or `duration` is up.
+#[derive(Copy, Clone, Debug)]
+pub struct Watch {
+    pub enabled: bool,
+    pub interval: Duration,
+    pub count: Option<u64>,
+    pub duration: Option<Duration>,
+}
+
+impl Watch {
+    pub fn new(enabled: bool, interval: Option<Duration>) -> Watch {
+        Watch {
+            enabled,
+            interval: interval.unwrap_or(DEFAULT_INTERVAL),
+            count: None,
+            duration: None,
+        }
+    }
+
+    pub fn from_args(args: &WatchArgs) -> Watch {
+        Watch {
+            enabled: args.watch
+                || args.interval.is_some()
+                || args.reads.is_some()
+                || args.duration.is_some(),
+            interval: args.interval.unwrap_or(DEFAULT_INTERVAL),
+            count: args.reads,
+            duration: args.duration,
+        }
+        .expecting()
+    }
+
+    pub fn from_stream_args(args: &StreamArgs) -> Watch {
+        Watch {
+            enabled: args.watch || args.count.is_some() || args.duration.is_some(),
+            interval: DEFAULT_INTERVAL,
+            count: args.count,
+            duration: args.duration,
+        }
+        .expecting()
+    }
+
+    /// With `--expect-within`, watches until the expectation is met or the time is up.
+    fn expecting(self) -> Watch {
+        match expect::within() {
+            Some(within) => Watch {
+                enabled: true,
+                duration: Some(
+                    self.duration
+                        .map_or(within, |duration| duration.min(within)),
+                ),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// Watches regardless of the flags, e.g. for a dashboard.
+    pub fn always(self) -> Watch {
+        Watch {
+            enabled: true,
+            ..self
+        }
+    }
+
+    /// Whether `done` reads or messages are all that's wanted: one without watching, `count`
+    /// with one.
+    pub fn is_done(&self, done: u64) -> bool {
+        if expect::within().is_some() && expect::is_met() {
+            return true;
+        }
+        match (self.enabled, self.count) {
+            (false, _) => done >= 1,
+            (true, Some(count)) => done >= count,
+            (true, None) => false,
+        }
+    }
+
+    /// Resolves when the watch's `duration` is up, counted from `start`; never without one.
+    pub async fn expired(&self, start: Instant) {
+        match self.duration.filter(|_| self.enabled) {
+            Some(duration) => tokio::time::sleep_until((start + duration).into()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Runs `read` once, or repeatedly while watching. `read` returns false to stop early.
+    /// A watch also stops on SIGINT or SIGTERM, after the read in progress (see
+    /// [`crate::shutdown`]).
+    pub async fn run<F, Fut>(&self, mut read: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        if self.enabled {
+            shutdown::listen();
+        }
+        let start = Instant::now();
+        let mut ticker = tokio::time::interval(self.interval);
+        let mut done = 0;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = self.expired(start) => break,
+                _ = shutdown::requested() => break,
+            }
+            if !read().await {
+                break;
+            }
+            done += 1;
+            if self.is_done(done) || shutdown::is_requested() {
+                break;
+            }
+        }
+    }
+
+    /// Blocking equivalent of [`Watch::run`] for tools without a runtime.
+    pub fn run_blocking<F: FnMut() -> bool>(&self, mut read: F) {
+        if self.enabled {
+            shutdown::listen_blocking();
+        }
+        let start = Instant::now();
+        let mut done = 0;
+        loop {
+            if !read() {
+                break;
+            }
+            done += 1;
+            if self.is_done(done) {
+                break;
+            }
+            let interval = match self.duration {
+                Some(duration) => match duration.checked_sub(start.elapsed()) {
+                    Some(left) if left > self.interval => self.interval,
+                    _ => break,
+                },
+                None => self.interval,
+            };
+            if !shutdown::sleep(interval) {
+                break;
+            }
+        }
+    }
+}
+
+/// `500ms`, `5s` or a bare number of milliseconds, as `--interval` always took.
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let interval = match value.parse::<u64>() {
+        Ok(millis) => Duration::from_millis(millis),
+        Err(_) => humantime::parse_duration(value).map_err(|err| err.to_string())?,
+    };
+    match interval.is_zero() {
+        true => Err("the interval must be longer than 0".to_string()),
+        false => Ok(interval),
+    }
+}
@@ -0,0 +1,211 @@
+//! A WebSocket server that pushes JSON events to every browser connected to it, for live
+//! dashboards. Any path upgrades; plain HTTP requests get a `426`. Clients only listen: what
+//! they send is read for pings and closes and otherwise ignored. A client that falls more than
+//! [`BACKLOG`] events behind misses the oldest of them rather than holding up the others.
+
+use std::io;
+use std::net::SocketAddr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::digest;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+
+/// Events kept for each client that hasn't taken them yet.
+pub const BACKLOG: usize = 256;
+
+// From RFC 6455, appended to the client's key for the accept header.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Frames from clients larger than this close the connection.
+const MAX_FRAME: u64 = 64 * 1024;
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Sends events to whoever is connected; clones share the clients.
+#[derive(Clone)]
+pub struct Server {
+    events: broadcast::Sender<String>,
+}
+
+impl Server {
+    /// Binds `address` and accepts clients in the background. Binding happens before this
+    /// returns, so a port already in use is reported to the caller.
+    pub async fn serve(address: SocketAddr) -> io::Result<Server> {
+        let listener = TcpListener::bind(address).await?;
+        log::info!("Serving live events on ws://{address}/");
+        let (events, _) = broadcast::channel(BACKLOG);
+        let server = Server { events };
+        let accepting = server.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let events = accepting.events.subscribe();
+                        tokio::spawn(async move {
+                            match client(stream, events).await {
+                                Ok(()) => log::debug!("WebSocket client {peer} left"),
+                                Err(err) => log::debug!("WebSocket client {peer} failed: {err}"),
+                            }
+                        });
+                    }
+                    Err(err) => log::warn!("Unable to accept a WebSocket connection: {err}"),
+                }
+            }
+        });
+        Ok(server)
+    }
+
+    /// Sends `event` to every client connected now; nothing happens without any.
+    pub fn send(&self, event: String) {
+        let _ = self.events.send(event);
+    }
+}
+
+async fn client(stream: TcpStream, mut events: broadcast::Receiver<String>) -> io::Result<()> {
+    let (mut reader, mut writer) = stream.into_split();
+    let Some(key) = handshake(&mut reader).await? else {
+        let body = "Live events are served over WebSocket\n";
+        let response = format!(
+            "HTTP/1.1 426 Upgrade Required\r\nUpgrade: websocket\r\nConnection: close\r\n\
+             Content-Type: text/plain\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        writer.write_all(response.as_bytes()).await?;
+        return writer.shutdown().await;
+    };
+    let accept = BASE64.encode(digest::digest(
+        &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{key}{GUID}").as_bytes(),
+    ));
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    );
+    writer.write_all(response.as_bytes()).await?;
+
+    // Frames the client asks for, answered between events; the reader ends with the client.
+    let (control, mut answers) = mpsc::channel(8);
+    tokio::spawn(async move {
+        if let Err(err) = read_frames(&mut reader, &control).await {
+            log::debug!("WebSocket read failed: {err}");
+        }
+    });
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => writer.write_all(&frame(TEXT, event.as_bytes())).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("A WebSocket client fell behind and missed {missed} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            answer = answers.recv() => match answer {
+                Some((opcode, payload)) => {
+                    writer.write_all(&frame(opcode, &payload)).await?;
+                    if opcode == CLOSE {
+                        return writer.shutdown().await;
+                    }
+                }
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Reads the upgrade request, returning the client's key; None for anything but an upgrade.
+async fn handshake(reader: &mut OwnedReadHalf) -> io::Result<Option<String>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 || request.len() > 8192 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    if !request.starts_with("GET ") {
+        return Ok(None);
+    }
+    let header = |name: &str| {
+        request.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| value.trim().to_string())
+        })
+    };
+    match header("upgrade") {
+        Some(upgrade) if upgrade.eq_ignore_ascii_case("websocket") => {
+            Ok(header("sec-websocket-key"))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads client frames until a close, queueing pongs and the close reply.
+async fn read_frames(
+    reader: &mut OwnedReadHalf,
+    control: &mpsc::Sender<(u8, Vec<u8>)>,
+) -> io::Result<()> {
+    loop {
+        let mut head = [0u8; 2];
+        reader.read_exact(&mut head).await?;
+        let opcode = head[0] & 0x0f;
+        let masked = head[1] & 0x80 != 0;
+        let length = match head[1] & 0x7f {
+            126 => reader.read_u16().await? as u64,
+            127 => reader.read_u64().await?,
+            length => length as u64,
+        };
+        if length > MAX_FRAME {
+            let _ = control.send((CLOSE, 1009u16.to_be_bytes().to_vec())).await;
+            return Ok(());
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            reader.read_exact(&mut mask).await?;
+        }
+        let mut payload = vec![0u8; length as usize];
+        reader.read_exact(&mut payload).await?;
+        if masked {
+            for (index, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[index % 4];
+            }
+        }
+        let answer = match opcode {
+            PING => PONG,
+            CLOSE => CLOSE,
+            _ => continue,
+        };
+        if control.send((answer, payload)).await.is_err() || answer == CLOSE {
+            return Ok(());
+        }
+    }
+}
+
+/// An unmasked, unfragmented frame, as servers send them.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
@@ -0,0 +1,275 @@
+//! Guards against writing where nothing should be written. `--read-only` refuses every write a
+//! tool would make, and the config file's `[writes]` table can do the same for the machine or
+//! list the only registers, subjects and topics that may be written:
+//!
+//! ```toml
+//! [writes]
+//! read_only = false
+//! modbus = ["10.0.0.5 holding 40236", "10.0.0.5:1502 holding 40300-40310", "* coil 0-15"]
+//! nats = ["plant.setpoints.>", "alerts.*"]
+//! mqtt = ["plant/setpoints/#"]
+//! ```
+//!
+//! A protocol without a list may be written anywhere, and one with a list only where an entry
+//! matches: Modbus entries are a device (or `*` for any), `holding` or `coil` and a number or
+//! range; NATS subjects take `*` and `>` wildcards and MQTT topics `+` and `#`, as
+//! subscriptions do. Writes are Modbus registers and coils, NATS and MQTT publishes and GPIO
+//! outputs, from the command line, a bridge or `edge api`; the agent's replies aren't. GPIO
+//! outputs have no list: `--read-only` and `read_only` refuse them, but a machine with lists
+//! for the other protocols still lets `gpio set` drive any line.
+//! Refused writes fail with [`Code::Usage`], as do all writes while the [`crate::audit`] log
+//! can't be opened. `--read-only` is passed on to the commands a tool
+//! runs, such as script steps and scheduled jobs, through `EDGE_READ_ONLY`.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use clap::builder::FalseyValueParser;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::exit::{self, Code};
+
+const ENV: &str = "EDGE_READ_ONLY";
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// The config's `[writes]` table, read on the first write.
+static POLICY: OnceLock<Result<Policy, String>> = OnceLock::new();
+
+/// `command` with `--read-only`.
+pub fn args(command: Command<'static>) -> Command<'static> {
+    command.arg(
+        Arg::new("read_only")
+            .long("read-only")
+            .help(
+                "Refuse every write: registers, coils, publishes and GPIO outputs, in commands \
+                 this one runs too",
+            )
+            .global(true)
+            .env(ENV)
+            .action(ArgAction::SetTrue)
+            // `EDGE_READ_ONLY=1` as well as `true`; `0`, `false`, `no` and `off` don't count.
+            .value_parser(FalseyValueParser::new()),
+    )
+}
+
+/// Turns read-only mode on when `matches`, from a command built with [`args`], ask for it.
+pub fn install(matches: &ArgMatches) {
+    if matches.get_flag("read_only") {
+        READ_ONLY.store(true, Ordering::Relaxed);
+        std::env::set_var(ENV, "true");
+    }
+}
+
+/// Something a tool is about to write.
+pub enum Write<'a> {
+    // `kind` is holding or coil.
+    Modbus {
+        device: SocketAddr,
+        kind: &'a str,
+        register: u16,
+        count: u16,
+    },
+    Nats(&'a str),
+    Mqtt(&'a str),
+    // Output lines of a GPIO chip.
+    Gpio(&'a str),
+}
+
+impl fmt::Display for Write<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Write::Modbus {
+                device,
+                kind,
+                register,
+                count: 1,
+            } => write!(f, "{kind} {register} of {device}"),
+            Write::Modbus {
+                device,
+                kind,
+                register,
+                count,
+            } => write!(
+                f,
+                "{kind} {register}-{} of {device}",
+                register.saturating_add(count.saturating_sub(1))
+            ),
+            Write::Nats(subject) => write!(f, "subject {subject}"),
+            Write::Mqtt(topic) => write!(f, "topic {topic}"),
+            Write::Gpio(chip) => write!(f, "outputs of {chip}"),
+        }
+    }
+}
+
+/// Whether `write` of `value` may go ahead; refusals go in the [`crate::audit`] log.
+pub fn check(write: &Write, value: impl fmt::Display) -> Result<(), exit::Error> {
+    let client = std::env::var(crate::audit::CLIENT).ok();
+    check_for(client.as_deref(), write, value)
+}
+
+/// Like [`check`], for a write made on behalf of `client`.
+pub fn check_for(
+    client: Option<&str>,
+    write: &Write,
+    value: impl fmt::Display,
+) -> Result<(), exit::Error> {
+    let allowed = allowed(write);
+    if allowed.is_err() {
+        crate::audit::record_for(client, write, value, &allowed);
+    }
+    allowed
+}
+
+fn allowed(write: &Write) -> Result<(), exit::Error> {
+    let refused = |why: &str| {
+        Err(exit::Error::new(
+            Code::Usage,
+            format!("Refused to write {write}: {why}"),
+        ))
+    };
+    if READ_ONLY.load(Ordering::Relaxed) {
+        return refused("running with --read-only");
+    }
+    let policy = match POLICY.get_or_init(|| {
+        crate::config::writes()
+            .map_err(|err| err.to_string())
+            .and_then(Policy::resolve)
+    }) {
+        Ok(policy) => policy,
+        Err(err) => return refused(&format!("unable to read the config's [writes]: {err}")),
+    };
+    if policy.read_only {
+        return refused("the config says read_only");
+    }
+    let allowed = match write {
+        Write::Modbus { .. } => policy
+            .modbus
+            .as_ref()
+            .map(|rules| rules.iter().any(|rule| rule.allows(write))),
+        Write::Nats(subject) => policy.nats.as_ref().map(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| matches(pattern, subject, '.', "*", ">"))
+        }),
+        Write::Mqtt(topic) => policy.mqtt.as_ref().map(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| matches(pattern, topic, '/', "+", "#"))
+        }),
+        // No list to check against; see the module docs.
+        Write::Gpio(_) => None,
+    };
+    match allowed {
+        Some(false) => {
+            let list = match write {
+                Write::Modbus { .. } => "modbus",
+                Write::Nats(_) => "nats",
+                _ => "mqtt",
+            };
+            refused(&format!("not in the config's [writes] {list} list"))
+        }
+        _ => crate::audit::ready().or_else(|err| refused(&err)),
+    }
+}
+
+/// The `[writes]` table.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    pub read_only: bool,
+    // None where anything may be written.
+    pub modbus: Option<Vec<Rule>>,
+    pub nats: Option<Vec<String>>,
+    pub mqtt: Option<Vec<String>>,
+}
+
+impl Policy {
+    /// Looks up the devices the Modbus rules name.
+    fn resolve(mut self) -> Result<Policy, String> {
+        for rule in self.modbus.iter_mut().flatten() {
+            if let Some(device) = &rule.device {
+                let address = crate::net::resolve(device, 502)
+                    .map_err(|err| format!("modbus: unable to resolve {device}: {err}"))?;
+                rule.address = Some(address);
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// A `modbus` entry: registers or coils of a device.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    // None for `*`.
+    device: Option<String>,
+    address: Option<SocketAddr>,
+    kind: String,
+    first: u16,
+    last: u16,
+}
+
+impl Rule {
+    fn allows(&self, write: &Write) -> bool {
+        let Write::Modbus {
+            device,
+            kind,
+            register,
+            count,
+        } = write
+        else {
+            return false;
+        };
+        let last = register.saturating_add(count.saturating_sub(1));
+        (self.device.is_none() || self.address == Some(*device))
+            && self.kind == *kind
+            && self.first <= *register
+            && last <= self.last
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Rule, String> {
+        let invalid = || {
+            format!("expected DEVICE holding|coil REGISTERS, e.g. `10.0.0.5 holding 40236-40240`, got `{text}`")
+        };
+        let [device, kind, registers] = text.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(invalid());
+        };
+        if !matches!(kind, "holding" | "coil") {
+            return Err(invalid());
+        }
+        let (first, last) = registers.split_once('-').unwrap_or((registers, registers));
+        let (first, last) = match (first.parse::<u16>(), last.parse::<u16>()) {
+            (Ok(first), Ok(last)) if first <= last => (first, last),
+            _ => return Err(invalid()),
+        };
+        Ok(Rule {
+            device: (device != "*").then(|| device.to_string()),
+            address: None,
+            kind: kind.to_string(),
+            first,
+            last,
+        })
+    }
+}
+
+/// Whether `name` matches `pattern`, split at `separator`, where `one` stands for any one
+/// level and `rest` for all those left.
+fn matches(pattern: &str, name: &str, separator: char, one: &str, rest: &str) -> bool {
+    let mut names = name.split(separator);
+    for level in pattern.split(separator) {
+        if level == rest {
+            return names.next().is_some();
+        }
+        match names.next() {
+            Some(name) if level == one || level == name => {}
+            _ => return false,
+        }
+    }
+    names.next().is_none()
+}
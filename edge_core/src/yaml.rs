@@ -0,0 +1,572 @@
+//! Just enough YAML for mapping files: block mappings and sequences, plain and quoted scalars
+//! and single-line flow collections (`[1, 2]`, `{a: 1}`). Anchors, tags, block scalars and
+//! multiple documents are rejected with an error rather than misread.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    // Entries in file order.
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Integers are accepted where a float is expected.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Integer(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// What the value is, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Integer(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::String(_) => "a string",
+            Value::List(_) => "a list",
+            Value::Map(_) => "a mapping",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The lines entries were written on, by their path such as `sources.plc.points[0].type`.
+#[derive(Debug, Default)]
+pub struct Lines(BTreeMap<String, usize>);
+
+impl Lines {
+    /// The line `path` is on, or that of the nearest entry around it, e.g. the key of a
+    /// flow mapping for a path into it.
+    pub fn of(&self, path: &str) -> Option<usize> {
+        let mut path = path;
+        loop {
+            if let Some(line) = self.0.get(path) {
+                return Some(*line);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+struct Line {
+    number: usize,
+    indent: usize,
+    content: String,
+}
+
+pub fn parse(input: &str) -> Result<Value, ParseError> {
+    parse_with_lines(input).map(|(value, _)| value)
+}
+
+/// Parses `input` and notes where each entry is, to point errors found later at their line.
+pub fn parse_with_lines(input: &str) -> Result<(Value, Lines), ParseError> {
+    let mut lines = Vec::new();
+    for (index, raw) in input.lines().enumerate() {
+        let number = index + 1;
+        let trimmed = raw.trim_start_matches(' ');
+        if trimmed.starts_with('\t') {
+            return Err(ParseError {
+                line: number,
+                message: "tabs are not allowed for indentation".to_string(),
+            });
+        }
+        let content = trimmed.trim_end();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        if content == "---" || content.starts_with("--- ") {
+            if !lines.is_empty() {
+                return Err(ParseError {
+                    line: number,
+                    message: "multiple documents are not supported".to_string(),
+                });
+            }
+            continue;
+        }
+        if content == "..." {
+            break;
+        }
+        lines.push(Line {
+            number,
+            indent: raw.len() - trimmed.len(),
+            content: content.to_string(),
+        });
+    }
+
+    if lines.is_empty() {
+        return Ok((Value::Null, Lines::default()));
+    }
+    let mut parser = Parser {
+        lines,
+        position: 0,
+        path: String::new(),
+        entries: Lines::default(),
+    };
+    let indent = parser.lines[0].indent;
+    let value = parser.block(indent)?;
+    if let Some(line) = parser.lines.get(parser.position) {
+        return Err(parser.error(line.number, "unexpected indentation"));
+    }
+    Ok((value, parser.entries))
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    position: usize,
+    // Path of the collection being parsed, and where its entries were.
+    path: String,
+    entries: Lines,
+}
+
+impl Parser {
+    fn error(&self, line: usize, message: &str) -> ParseError {
+        ParseError {
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    fn current(&self) -> Option<&Line> {
+        self.lines.get(self.position)
+    }
+
+    /// Parses an entry of the current collection with `path` as its path.
+    fn entry<T>(
+        &mut self,
+        path: String,
+        line: usize,
+        parse: impl FnOnce(&mut Parser) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        self.entries.0.insert(path.clone(), line);
+        let outer = std::mem::replace(&mut self.path, path);
+        let value = parse(self);
+        self.path = outer;
+        value
+    }
+
+    /// A sequence or mapping whose lines start at `indent`.
+    fn block(&mut self, indent: usize) -> Result<Value, ParseError> {
+        match self.current() {
+            Some(line) if is_sequence_item(&line.content) => self.sequence(indent),
+            Some(line) if split_key(&line.content).is_some() => self.mapping(indent),
+            Some(line) => {
+                let number = line.number;
+                let value = inline_value(&line.content).map_err(|m| self.error(number, &m))?;
+                self.position += 1;
+                // A lone scalar can't be followed by anything at the same level.
+                match self.current() {
+                    Some(next) if next.indent >= indent => {
+                        Err(self.error(next.number, "expected a mapping or a sequence"))
+                    }
+                    _ => Ok(value),
+                }
+            }
+            None => Ok(Value::Null),
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, ParseError> {
+        let mut values = Vec::new();
+        while let Some(line) = self.current() {
+            if line.indent < indent {
+                break;
+            }
+            if line.indent > indent {
+                return Err(self.error(line.number, "unexpected indentation"));
+            }
+            if !is_sequence_item(&line.content) {
+                break;
+            }
+
+            let number = line.number;
+            let rest = line.content[1..].trim_start_matches(' ').to_string();
+            let item_indent = indent + line.content.len() - rest.len();
+            let path = format!("{}[{}]", self.path, values.len());
+            let value = self.entry(path, number, |parser| {
+                if rest.is_empty() {
+                    parser.position += 1;
+                    parser.nested(indent)
+                } else if is_sequence_item(&rest) || split_key(&rest).is_some() {
+                    // `- key: value` starts a mapping lined up with `key`; treat the rest of
+                    // the line as if it were on a line of its own.
+                    parser.lines[parser.position] = Line {
+                        number,
+                        indent: item_indent,
+                        content: rest,
+                    };
+                    parser.block(item_indent)
+                } else {
+                    parser.position += 1;
+                    inline_value(&rest).map_err(|m| parser.error(number, &m))
+                }
+            })?;
+            values.push(value);
+        }
+        Ok(Value::List(values))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value, ParseError> {
+        let mut entries: Vec<(String, Value)> = Vec::new();
+        while let Some(line) = self.current() {
+            if line.indent < indent {
+                break;
+            }
+            if line.indent > indent {
+                return Err(self.error(line.number, "unexpected indentation"));
+            }
+            let number = line.number;
+            let (key, rest) = match split_key(&line.content) {
+                Some((key, rest)) => (key, rest.to_string()),
+                None if is_sequence_item(&line.content) => break,
+                None => return Err(self.error(number, "expected key: value")),
+            };
+            let key = key.map_err(|m| self.error(number, &m))?;
+            if entries.iter().any(|(name, _)| *name == key) {
+                return Err(self.error(number, &format!("key {key} defined twice")));
+            }
+            self.position += 1;
+
+            let path = match self.path.is_empty() {
+                true => key.clone(),
+                false => format!("{}.{key}", self.path),
+            };
+            let value = self.entry(path, number, |parser| {
+                if !rest.is_empty() {
+                    return inline_value(&rest).map_err(|m| parser.error(number, &m));
+                }
+                match parser.current() {
+                    // Sequences may sit at the same indentation as their key.
+                    Some(next) if next.indent == indent && is_sequence_item(&next.content) => {
+                        parser.sequence(indent)
+                    }
+                    _ => parser.nested(indent),
+                }
+            })?;
+            entries.push((key, value));
+        }
+        Ok(Value::Map(entries))
+    }
+
+    /// The block indented under the line at `indent`, or null when there is none.
+    fn nested(&mut self, indent: usize) -> Result<Value, ParseError> {
+        match self.current() {
+            Some(next) if next.indent > indent => {
+                let indent = next.indent;
+                self.block(indent)
+            }
+            _ => Ok(Value::Null),
+        }
+    }
+}
+
+fn is_sequence_item(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+/// Splits `key: rest`, returning `None` when the line isn't a mapping entry at all.
+fn split_key(content: &str) -> Option<(Result<String, String>, &str)> {
+    if content.starts_with(['"', '\'']) {
+        let mut cursor = Cursor::new(content);
+        let key = cursor.quoted();
+        let rest = cursor.rest.trim_start_matches(' ');
+        let rest = rest.strip_prefix(':')?;
+        if !(rest.is_empty() || rest.starts_with(' ')) {
+            return None;
+        }
+        return Some((key, rest.trim_start_matches(' ')));
+    }
+    if content.starts_with(['[', '{']) {
+        return None;
+    }
+
+    let end = content
+        .find(": ")
+        .or_else(|| content.strip_suffix(':').map(str::len))?;
+    let key = content[..end].trim_end();
+    if key.contains(" #") {
+        return None;
+    }
+    let rest = content[end + 1..].trim_start_matches(' ');
+    Some((Ok(key.to_string()), rest))
+}
+
+/// A value written on the same line as its key or dash.
+fn inline_value(content: &str) -> Result<Value, String> {
+    let mut cursor = Cursor::new(content);
+    let value = cursor.value(false)?;
+    cursor.skip_whitespace();
+    if !cursor.at_end_of_line() {
+        return Err(format!("unexpected text after value: {}", cursor.rest));
+    }
+    Ok(value)
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: &'a str) -> Cursor<'a> {
+        Cursor { rest: line }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches(' ');
+    }
+
+    fn at_end_of_line(&self) -> bool {
+        self.rest.is_empty() || self.rest.starts_with('#')
+    }
+
+    /// Reads a value; inside a flow collection `,`, `]` and `}` end plain scalars.
+    fn value(&mut self, in_flow: bool) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') | Some('\'') => self.quoted().map(Value::String),
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(']') {
+                        return Ok(Value::List(values));
+                    }
+                    if self.rest.is_empty() {
+                        return Err("multi-line flow sequences are not supported".to_string());
+                    }
+                    values.push(self.value(true)?);
+                    self.skip_whitespace();
+                    if !self.eat(',') && self.peek() != Some(']') {
+                        return Err("expected , or ] in flow sequence".to_string());
+                    }
+                }
+            }
+            Some('{') => {
+                self.bump();
+                let mut entries: Vec<(String, Value)> = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.eat('}') {
+                        return Ok(Value::Map(entries));
+                    }
+                    if self.rest.is_empty() {
+                        return Err("multi-line flow mappings are not supported".to_string());
+                    }
+                    let key = match self.value(true)? {
+                        Value::String(key) => key,
+                        Value::Null => return Err("missing key in flow mapping".to_string()),
+                        other => scalar_text(&other),
+                    };
+                    self.skip_whitespace();
+                    if !self.eat(':') {
+                        return Err("expected : in flow mapping".to_string());
+                    }
+                    self.skip_whitespace();
+                    let value = self.value(true)?;
+                    entries.push((key, value));
+                    self.skip_whitespace();
+                    if !self.eat(',') && self.peek() != Some('}') {
+                        return Err("expected , or } in flow mapping".to_string());
+                    }
+                }
+            }
+            Some('&') | Some('*') => Err("anchors and aliases are not supported".to_string()),
+            Some('!') => Err("tags are not supported".to_string()),
+            Some('|') | Some('>') => Err("block scalars are not supported".to_string()),
+            _ => {
+                let mut end = self.rest.len();
+                for (index, c) in self.rest.char_indices() {
+                    let comment = c == '#' && self.rest[..index].ends_with(' ');
+                    let flow_end = in_flow
+                        && (matches!(c, ',' | ']' | '}')
+                            || (c == ':' && self.rest[index + 1..].starts_with(' ')));
+                    if comment || flow_end {
+                        end = index;
+                        break;
+                    }
+                }
+                let token = self.rest[..end].trim_end();
+                self.rest = &self.rest[end..];
+                Ok(plain_scalar(token))
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        let quote = self.bump().unwrap_or('"');
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                None => return Err("unterminated string".to_string()),
+                Some('\'') if quote == '\'' => {
+                    // '' is an escaped quote inside single quotes.
+                    if !self.eat('\'') {
+                        return Ok(value);
+                    }
+                    value.push('\'');
+                }
+                Some('"') if quote == '"' => return Ok(value),
+                Some('\\') if quote == '"' => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('0') => value.push('\0'),
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('/') => value.push('/'),
+                    Some('x') => value.push(self.unicode_escape(2)?),
+                    Some('u') => value.push(self.unicode_escape(4)?),
+                    Some('U') => value.push(self.unicode_escape(8)?),
+                    other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, digits: usize) -> Result<char, String> {
+        let hex = self.rest.get(..digits).ok_or("truncated escape")?;
+        self.rest = &self.rest[digits..];
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("invalid escape {hex}"))
+    }
+}
+
+/// Resolves a plain scalar the way YAML 1.2's core schema does.
+fn plain_scalar(token: &str) -> Value {
+    match token {
+        "" | "~" | "null" | "Null" | "NULL" => return Value::Null,
+        "true" | "True" | "TRUE" => return Value::Bool(true),
+        "false" | "False" | "FALSE" => return Value::Bool(false),
+        ".inf" | "+.inf" | ".Inf" | ".INF" => return Value::Float(f64::INFINITY),
+        "-.inf" | "-.Inf" | "-.INF" => return Value::Float(f64::NEG_INFINITY),
+        ".nan" | ".NaN" | ".NAN" => return Value::Float(f64::NAN),
+        _ => {}
+    }
+
+    let (sign, unsigned) = match token.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let radix = [("0x", 16), ("0o", 8)]
+        .iter()
+        .find_map(|(prefix, radix)| unsigned.strip_prefix(prefix).map(|rest| (rest, *radix)));
+    if let Some((rest, radix)) = radix {
+        if let Ok(value) = i64::from_str_radix(rest, radix) {
+            return Value::Integer(sign * value);
+        }
+    }
+    let numeric = unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && unsigned
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    if numeric {
+        if let Ok(value) = token.parse::<i64>() {
+            return Value::Integer(value);
+        }
+        if let Ok(value) = token.parse::<f64>() {
+            return Value::Float(value);
+        }
+    }
+    Value::String(token.to_string())
+}
+
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::Bool(value) => value.to_string(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::String(value) => value.clone(),
+        _ => String::new(),
+    }
+}
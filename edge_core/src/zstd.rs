@@ -0,0 +1,1299 @@
+//! Zstandard (RFC 8878) compression for files that would otherwise fill a gateway's flash in a
+//! few days, like multi-day captures. Files named `*.zst` are written compressed, and files
+//! starting with the zstd magic number are decompressed when read back, whoever wrote them:
+//!
+//! ```text
+//! $ modbus 10.0.0.5 --capture inverter.pcapng.zst read-register -r 100 -k holding -w
+//! $ modbus analyze-pcap inverter.pcapng.zst
+//! $ zstd -d inverter.pcapng.zst    # for Wireshark
+//! ```
+//!
+//! The decoder takes anything the reference implementation writes, except frames that need a
+//! dictionary. The encoder is a greedy LZ77 over each frame with raw literals and the
+//! predefined sequence tables: not as tight as `zstd -3`, but a fraction of the size of
+//! protocol traffic stored as it is.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+const MAGIC: u32 = 0xfd2f_b528;
+// Skippable frames have any of the 16 magic numbers from this one.
+const SKIPPABLE: u32 = 0x184d_2a50;
+const MAX_BLOCK: usize = 128 * 1024;
+// What a Writer collects before compressing it into a frame of its own.
+const FRAME_SIZE: usize = 1024 * 1024;
+const MIN_MATCH: usize = 4;
+// The largest offset the predefined offset table can code.
+const MAX_OFFSET: usize = (1 << 28) - 3;
+const HASH_LOG: u32 = 16;
+
+// Predefined distributions for literal lengths, match lengths and offsets (RFC 8878 3.1.1.3.2.2).
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+// Literal and match length codes: the smallest value each stands for and the extra bits after.
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid zstd data: {}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+fn corrupt<T>(what: &str) -> Result<T, Error> {
+    Err(Error(what.to_string()))
+}
+
+/// Whether `path` should be written compressed: it ends in `.zst`.
+pub fn is_zst(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+/// Whether `data` starts with a zstd frame.
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC.to_le_bytes())
+}
+
+/// `data` as one zstd frame, with its size and checksum.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 32);
+    out.extend_from_slice(&MAGIC.to_le_bytes());
+    // A single segment: the window is the whole content, whose size comes first.
+    let size = data.len() as u64;
+    let (flag, content_size) = match size {
+        0..=255 => (0, vec![size as u8]),
+        256..=65_791 => (1, ((size - 256) as u16).to_le_bytes().to_vec()),
+        65_792..=0xffff_ffff => (2, (size as u32).to_le_bytes().to_vec()),
+        _ => (3, size.to_le_bytes().to_vec()),
+    };
+    out.push(flag << 6 | 0x20 | 0x04);
+    out.extend(content_size);
+
+    let mut matcher = Matcher::new(data);
+    let mut start = 0;
+    loop {
+        let end = (start + MAX_BLOCK).min(data.len());
+        let block = &data[start..end];
+        let last = end == data.len();
+        let (kind, size, content) = match block.first() {
+            Some(&first) if block.iter().all(|&byte| byte == first) => {
+                (1, block.len(), vec![first])
+            }
+            _ => match matcher.block(start, end) {
+                compressed if compressed.len() < block.len() => (2, compressed.len(), compressed),
+                _ => (0, block.len(), block.to_vec()),
+            },
+        };
+        let header = u32::from(last) | kind << 1 | (size as u32) << 3;
+        out.extend_from_slice(&header.to_le_bytes()[..3]);
+        out.extend(content);
+        if last {
+            break;
+        }
+        start = end;
+    }
+    out.extend_from_slice(&(xxh64(data) as u32).to_le_bytes());
+    out
+}
+
+/// Every frame in `data`, one after the other; skippable frames are left out.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    let mut input = Input::new(data);
+    while !input.is_empty() {
+        let magic = input.number(4)? as u32;
+        if magic & 0xffff_fff0 == SKIPPABLE {
+            let size = input.number(4)? as usize;
+            input.take(size)?;
+            continue;
+        }
+        if magic != MAGIC {
+            return corrupt("not a zstd frame");
+        }
+        decode_frame(&mut input, &mut out)?;
+    }
+    Ok(out)
+}
+
+/// Compresses what's written into frames of up to a megabyte each. [`Write::flush`] ends the
+/// frame in progress, so a reader can decompress everything written up to then; flushing
+/// after every small write costs compression.
+pub struct Writer<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Writer<W> {
+        Writer {
+            inner,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_all(&compress(&self.pending))?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if self.pending.len() >= FRAME_SIZE {
+            self.write_frame()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_frame()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Writer<W> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::warn!("Unable to write the end of a compressed file: {err}");
+        }
+    }
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    fn new(data: &'a [u8]) -> Input<'a> {
+        Input { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let Some(taken) = self.data.get(self.position..self.position + count) else {
+            return corrupt("truncated");
+        };
+        self.position += count;
+        Ok(taken)
+    }
+
+    /// A little endian number of `count` bytes.
+    fn number(&mut self, count: usize) -> Result<u64, Error> {
+        Ok(little_endian(self.take(count)?))
+    }
+}
+
+fn little_endian(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |number, &byte| number << 8 | u64::from(byte))
+}
+
+/// Tables and offsets later blocks of a frame can refer back to.
+struct Frame {
+    start: usize,
+    repeats: [usize; 3],
+    huffman: Option<Huffman>,
+    literal_lengths: Option<Fse>,
+    offsets: Option<Fse>,
+    match_lengths: Option<Fse>,
+}
+
+fn decode_frame(input: &mut Input, out: &mut Vec<u8>) -> Result<(), Error> {
+    let descriptor = input.take(1)?[0];
+    if descriptor & 0x08 != 0 {
+        return corrupt("reserved frame header bit set");
+    }
+    let single_segment = descriptor & 0x20 != 0;
+    if !single_segment {
+        // The window size; the whole frame is kept in memory anyway.
+        input.take(1)?;
+    }
+    let dictionary = input.number([0, 1, 2, 4][usize::from(descriptor & 3)])?;
+    if dictionary != 0 {
+        return corrupt("frames that need a dictionary aren't supported");
+    }
+    let content_size = match (descriptor >> 6, single_segment) {
+        (0, false) => None,
+        (0, true) => Some(input.number(1)?),
+        (1, _) => Some(input.number(2)? + 256),
+        (2, _) => Some(input.number(4)?),
+        _ => Some(input.number(8)?),
+    };
+
+    let mut frame = Frame {
+        start: out.len(),
+        repeats: [1, 4, 8],
+        huffman: None,
+        literal_lengths: None,
+        offsets: None,
+        match_lengths: None,
+    };
+    loop {
+        let header = input.number(3)? as usize;
+        let size = header >> 3;
+        if size > MAX_BLOCK {
+            return corrupt("block larger than 128 KiB");
+        }
+        match (header >> 1) & 3 {
+            0 => out.extend_from_slice(input.take(size)?),
+            1 => {
+                let byte = input.take(1)?[0];
+                out.resize(out.len() + size, byte);
+            }
+            2 => decode_block(input.take(size)?, out, &mut frame)?,
+            _ => return corrupt("reserved block type"),
+        }
+        if header & 1 == 1 {
+            break;
+        }
+    }
+    let content = &out[frame.start..];
+    if content_size.is_some_and(|size| size != content.len() as u64) {
+        return corrupt("content size doesn't match the frame header");
+    }
+    if descriptor & 0x04 != 0 && input.number(4)? as u32 != xxh64(content) as u32 {
+        return corrupt("checksum mismatch");
+    }
+    Ok(())
+}
+
+fn decode_block(block: &[u8], out: &mut Vec<u8>, frame: &mut Frame) -> Result<(), Error> {
+    let (literals, used) = decode_literals(block, &mut frame.huffman)?;
+    let sequences = decode_sequences(&block[used..], frame)?;
+    let mut next = 0;
+    for (literal_length, offset, match_length) in sequences {
+        let Some(copied) = literals.get(next..next + literal_length) else {
+            return corrupt("sequences use more literals than there are");
+        };
+        out.extend_from_slice(copied);
+        next += literal_length;
+        if offset == 0 || offset > out.len() - frame.start {
+            return corrupt("match offset before the start of the frame");
+        }
+        let from = out.len() - offset;
+        if offset >= match_length {
+            out.extend_from_within(from..from + match_length);
+        } else {
+            // Overlapping matches repeat what they're copying.
+            for index in from..from + match_length {
+                out.push(out[index]);
+            }
+        }
+    }
+    out.extend_from_slice(&literals[next..]);
+    Ok(())
+}
+
+fn decode_literals(block: &[u8], huffman: &mut Option<Huffman>) -> Result<(Vec<u8>, usize), Error> {
+    let byte = |index: usize| block.get(index).copied().map(usize::from);
+    let Some(first) = byte(0) else {
+        return corrupt("empty compressed block");
+    };
+    let kind = first & 3;
+    let format = (first >> 2) & 3;
+    if kind < 2 {
+        let (size, header) = match format {
+            0 | 2 => (first >> 3, 1),
+            1 => ((first >> 4) + (byte(1).unwrap_or(0) << 4), 2),
+            _ => (
+                (first >> 4) + (byte(1).unwrap_or(0) << 4) + (byte(2).unwrap_or(0) << 12),
+                3,
+            ),
+        };
+        return match kind {
+            0 => match block.get(header..header + size) {
+                Some(literals) => Ok((literals.to_vec(), header + size)),
+                None => corrupt("truncated literals"),
+            },
+            _ => match block.get(header) {
+                Some(&byte) => Ok((vec![byte; size], header + 1)),
+                None => corrupt("truncated literals"),
+            },
+        };
+    }
+
+    let header = [3, 3, 4, 5][format];
+    let Some(bytes) = block.get(..header) else {
+        return corrupt("truncated literals header");
+    };
+    let fields = little_endian(bytes) as usize;
+    let (size, compressed) = match header {
+        3 => ((fields >> 4) & 0x3ff, (fields >> 14) & 0x3ff),
+        4 => ((fields >> 4) & 0x3fff, (fields >> 18) & 0x3fff),
+        _ => ((fields >> 4) & 0x3_ffff, (fields >> 22) & 0x3_ffff),
+    };
+    let Some(mut data) = block.get(header..header + compressed) else {
+        return corrupt("truncated literals");
+    };
+    if kind == 2 {
+        let (table, used) = Huffman::read(data)?;
+        *huffman = Some(table);
+        data = &data[used..];
+    }
+    let Some(table) = huffman.as_ref() else {
+        return corrupt("literals reuse a Huffman table there isn't");
+    };
+    let mut literals = Vec::with_capacity(size);
+    if format == 0 {
+        table.decode(data, size, &mut literals)?;
+    } else {
+        let Some(jump) = data.get(..6) else {
+            return corrupt("truncated literals jump table");
+        };
+        let sizes = [0, 2, 4].map(|at| usize::from(u16::from_le_bytes([jump[at], jump[at + 1]])));
+        let mut streams = &data[6..];
+        let each = size.div_ceil(4);
+        for (index, count) in [each, each, each, size.saturating_sub(3 * each)]
+            .into_iter()
+            .enumerate()
+        {
+            let length = sizes.get(index).copied().unwrap_or(streams.len());
+            if length > streams.len() {
+                return corrupt("truncated literals stream");
+            }
+            table.decode(&streams[..length], count, &mut literals)?;
+            streams = &streams[length..];
+        }
+    }
+    Ok((literals, header + compressed))
+}
+
+fn decode_sequences(data: &[u8], frame: &mut Frame) -> Result<Vec<(usize, usize, usize)>, Error> {
+    let byte = |index: usize| match data.get(index) {
+        Some(&byte) => Ok(usize::from(byte)),
+        None => corrupt("truncated sequences header"),
+    };
+    let (count, mut used) = match byte(0)? {
+        0 if data.len() == 1 => return Ok(Vec::new()),
+        0 => return corrupt("data after an empty sequences section"),
+        count @ 1..=127 => (count, 1),
+        high @ 128..=254 => (((high - 128) << 8) + byte(1)?, 2),
+        _ => (byte(1)? + (byte(2)? << 8) + 0x7f00, 3),
+    };
+    let modes = byte(used)?;
+    used += 1;
+    if modes & 3 != 0 {
+        return corrupt("reserved sequences bits set");
+    }
+    let tables = [
+        (modes >> 6, &LL_DEFAULT[..], 6, 9, 35),
+        (modes >> 4 & 3, &OF_DEFAULT[..], 5, 8, 31),
+        (modes >> 2 & 3, &ML_DEFAULT[..], 6, 9, 52),
+    ];
+    for (index, (mode, default, default_log, max_log, max_symbol)) in tables.into_iter().enumerate()
+    {
+        let previous = match index {
+            0 => &mut frame.literal_lengths,
+            1 => &mut frame.offsets,
+            _ => &mut frame.match_lengths,
+        };
+        match mode {
+            0 => *previous = Some(Fse::new(default, default_log)?),
+            1 => {
+                let symbol = byte(used)?;
+                if symbol > max_symbol {
+                    return corrupt("sequence code out of range");
+                }
+                *previous = Some(Fse::rle(symbol as u8));
+                used += 1;
+            }
+            2 => {
+                let (counts, log, length) = read_distribution(&data[used..], max_log, max_symbol)?;
+                *previous = Some(Fse::new(&counts, log)?);
+                used += length;
+            }
+            _ if previous.is_none() => return corrupt("sequences reuse a table there isn't"),
+            _ => {}
+        }
+    }
+    let (Some(literal_lengths), Some(offsets), Some(match_lengths)) =
+        (&frame.literal_lengths, &frame.offsets, &frame.match_lengths)
+    else {
+        unreachable!("every table set above");
+    };
+
+    let mut bits = BackwardBits::new(&data[used..])?;
+    let mut literal_state = bits.read(literal_lengths.log) as usize;
+    let mut offset_state = bits.read(offsets.log) as usize;
+    let mut match_state = bits.read(match_lengths.log) as usize;
+    let mut sequences = Vec::with_capacity(count);
+    for index in 0..count {
+        let offset_code = offsets.entries[offset_state].symbol;
+        let match_code = usize::from(match_lengths.entries[match_state].symbol);
+        let literal_code = usize::from(literal_lengths.entries[literal_state].symbol);
+        if offset_code > 31 || match_code >= ML_BASE.len() || literal_code >= LL_BASE.len() {
+            return corrupt("sequence code out of range");
+        }
+        let offset_value = (1usize << offset_code) + bits.read(offset_code) as usize;
+        let match_length = ML_BASE[match_code] as usize + bits.read(ML_BITS[match_code]) as usize;
+        let literal_length =
+            LL_BASE[literal_code] as usize + bits.read(LL_BITS[literal_code]) as usize;
+
+        let repeats = &mut frame.repeats;
+        let offset = match (offset_value, literal_length) {
+            (value @ 4.., _) => {
+                *repeats = [value - 3, repeats[0], repeats[1]];
+                value - 3
+            }
+            (1, 1..) => repeats[0],
+            (1, 0) | (2, 1..) => {
+                repeats.swap(0, 1);
+                repeats[0]
+            }
+            (2, 0) | (3, 1..) => {
+                *repeats = [repeats[2], repeats[0], repeats[1]];
+                repeats[0]
+            }
+            _ => {
+                let offset = repeats[0].saturating_sub(1);
+                *repeats = [offset, repeats[0], repeats[1]];
+                offset
+            }
+        };
+        sequences.push((literal_length, offset, match_length));
+
+        if index + 1 < count {
+            literal_state = literal_lengths.update(literal_state, &mut bits);
+            match_state = match_lengths.update(match_state, &mut bits);
+            offset_state = offsets.update(offset_state, &mut bits);
+        }
+    }
+    if bits.remaining != 0 {
+        return corrupt("sequences don't use up their bitstream");
+    }
+    Ok(sequences)
+}
+
+/// Reads a bitstream from its end: the highest set bit of the last byte marks where it
+/// starts, and reads past the beginning see zeros.
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    // Bits left; negative once a read went past the beginning.
+    remaining: isize,
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> Result<BackwardBits<'a>, Error> {
+        match data.last() {
+            Some(&last) if last != 0 => Ok(BackwardBits {
+                data,
+                remaining: (data.len() * 8) as isize - last.leading_zeros() as isize - 1,
+            }),
+            _ => corrupt("bitstream without an end mark"),
+        }
+    }
+
+    fn peek(&self, count: u8) -> u64 {
+        let count = isize::from(count);
+        if count == 0 || self.remaining <= 0 {
+            return 0;
+        }
+        let low = self.remaining - count;
+        match low >= 0 {
+            true => self.bits(low as usize, count as usize),
+            false => self.bits(0, self.remaining as usize) << -low,
+        }
+    }
+
+    fn read(&mut self, count: u8) -> u64 {
+        let value = self.peek(count);
+        self.remaining -= isize::from(count);
+        value
+    }
+
+    /// `count` bits from bit `start`, counting from the first byte's lowest bit.
+    fn bits(&self, start: usize, count: usize) -> u64 {
+        let first = start / 8;
+        let bytes = &self.data[first..self.data.len().min(first + 8)];
+        let value = little_endian(bytes) >> (start % 8);
+        value & ((1u64 << count) - 1)
+    }
+}
+
+/// Reads bits from the start of `data`, lowest first, as table descriptions are written.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl ForwardBits<'_> {
+    fn peek(&self, count: u32) -> u32 {
+        let first = self.position / 8;
+        let bytes = self.data.get(first..).unwrap_or_default();
+        let bytes = &bytes[..bytes.len().min(8)];
+        let value = little_endian(bytes) >> (self.position % 8);
+        (value & ((1u64 << count) - 1)) as u32
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.position += count as usize;
+        value
+    }
+}
+
+/// An FSE table description: the normalised count of each symbol, `-1` for "less than one",
+/// with the accuracy log and the bytes it took.
+fn read_distribution(
+    data: &[u8],
+    max_log: u8,
+    max_symbol: usize,
+) -> Result<(Vec<i16>, u8, usize), Error> {
+    let mut bits = ForwardBits { data, position: 0 };
+    let log = bits.read(4) as u8 + 5;
+    if log > max_log {
+        return corrupt("FSE accuracy log too large");
+    }
+    let mut remaining: i32 = (1 << log) + 1;
+    let mut threshold: i32 = 1 << log;
+    let mut width = u32::from(log) + 1;
+    let mut counts: Vec<i16> = Vec::new();
+    while remaining > 1 {
+        if counts.len() > max_symbol {
+            return corrupt("FSE table with too many symbols");
+        }
+        let max = 2 * threshold - 1 - remaining;
+        let low = bits.peek(width - 1) as i32;
+        let value = if low < max {
+            bits.position += width as usize - 1;
+            low
+        } else {
+            let value = bits.read(width) as i32;
+            if value >= threshold {
+                value - max
+            } else {
+                value
+            }
+        };
+        let count = value - 1;
+        remaining -= count.abs();
+        counts.push(count as i16);
+        if count == 0 {
+            loop {
+                let repeat = bits.read(2);
+                counts.extend(std::iter::repeat_n(0, repeat as usize));
+                if repeat != 3 {
+                    break;
+                }
+            }
+        }
+        if remaining < threshold {
+            if remaining <= 1 {
+                break;
+            }
+            width = 32 - (remaining as u32).leading_zeros();
+            threshold = 1 << (width - 1);
+        }
+    }
+    let used = bits.position.div_ceil(8);
+    if remaining != 1 || counts.len() > max_symbol + 1 || used > data.len() {
+        return corrupt("invalid FSE table description");
+    }
+    Ok((counts, log, used))
+}
+
+/// Where each symbol of a distribution goes in a table of `1 << log` states.
+fn spread(counts: &[i16], log: u8) -> Result<Vec<u8>, Error> {
+    let size = 1usize << log;
+    let total: usize = counts
+        .iter()
+        .map(|&count| count.unsigned_abs() as usize)
+        .sum();
+    if total != size {
+        return corrupt("FSE counts don't add up");
+    }
+    let mut symbols = vec![0u8; size];
+    // "Less than one" symbols take the last states, the rest are spread over what's left.
+    let mut high = size;
+    for (symbol, _) in counts.iter().enumerate().filter(|&(_, &count)| count == -1) {
+        high -= 1;
+        symbols[high] = symbol as u8;
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut position = 0;
+    for (symbol, &count) in counts.iter().enumerate() {
+        for _ in 0..count.max(0) {
+            symbols[position] = symbol as u8;
+            loop {
+                position = (position + step) & (size - 1);
+                if position < high {
+                    break;
+                }
+            }
+        }
+    }
+    if position != 0 {
+        return corrupt("invalid FSE distribution");
+    }
+    Ok(symbols)
+}
+
+#[derive(Clone, Copy)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    base: u16,
+}
+
+/// An FSE decoding table.
+struct Fse {
+    log: u8,
+    entries: Vec<FseEntry>,
+}
+
+impl Fse {
+    fn new(counts: &[i16], log: u8) -> Result<Fse, Error> {
+        let size = 1u32 << log;
+        let symbols = spread(counts, log)?;
+        let mut next: Vec<u32> = counts.iter().map(|&count| count.max(1) as u32).collect();
+        let entries = symbols
+            .iter()
+            .map(|&symbol| {
+                let state = next[usize::from(symbol)];
+                next[usize::from(symbol)] += 1;
+                let bits = log - (31 - state.leading_zeros()) as u8;
+                FseEntry {
+                    symbol,
+                    bits,
+                    base: ((state << bits) - size) as u16,
+                }
+            })
+            .collect();
+        Ok(Fse { log, entries })
+    }
+
+    /// A table that only ever gives `symbol`.
+    fn rle(symbol: u8) -> Fse {
+        Fse {
+            log: 0,
+            entries: vec![FseEntry {
+                symbol,
+                bits: 0,
+                base: 0,
+            }],
+        }
+    }
+
+    fn update(&self, state: usize, bits: &mut BackwardBits) -> usize {
+        let entry = self.entries[state];
+        usize::from(entry.base) + bits.read(entry.bits) as usize
+    }
+}
+
+/// A Huffman decoding table for literals, looked up by the next `log` bits.
+struct Huffman {
+    log: u8,
+    entries: Vec<(u8, u8)>,
+}
+
+impl Huffman {
+    /// The tree description at the start of `data`, and the bytes it took.
+    fn read(data: &[u8]) -> Result<(Huffman, usize), Error> {
+        let Some(&header) = data.first() else {
+            return corrupt("truncated Huffman tree");
+        };
+        let (mut weights, used) = if header < 128 {
+            let size = usize::from(header);
+            let Some(compressed) = data.get(1..1 + size) else {
+                return corrupt("truncated Huffman tree");
+            };
+            (compressed_weights(compressed)?, 1 + size)
+        } else {
+            let count = usize::from(header) - 127;
+            let Some(packed) = data.get(1..1 + count.div_ceil(2)) else {
+                return corrupt("truncated Huffman tree");
+            };
+            let weights = (0..count)
+                .map(|index| match index % 2 {
+                    0 => packed[index / 2] >> 4,
+                    _ => packed[index / 2] & 0x0f,
+                })
+                .collect();
+            (weights, 1 + count.div_ceil(2))
+        };
+        if weights.len() > 255 || weights.iter().any(|&weight| weight > 11) {
+            return corrupt("invalid Huffman weights");
+        }
+        // The last symbol's weight is whatever makes the total a power of two.
+        let total: u32 = weights
+            .iter()
+            .filter(|&&weight| weight > 0)
+            .map(|&weight| 1 << (weight - 1))
+            .sum();
+        if total == 0 {
+            return corrupt("invalid Huffman weights");
+        }
+        let log = 32 - total.leading_zeros();
+        let left = (1 << log) - total;
+        if log > 11 || !left.is_power_of_two() {
+            return corrupt("invalid Huffman weights");
+        }
+        weights.push(left.trailing_zeros() as u8 + 1);
+
+        let mut starts = vec![0usize; log as usize + 2];
+        let mut position = 0;
+        for (weight, start) in starts.iter_mut().enumerate().take(log as usize + 1).skip(1) {
+            *start = position;
+            let count = weights
+                .iter()
+                .filter(|&&w| usize::from(w) == weight)
+                .count();
+            position += count << (weight - 1);
+        }
+        let mut entries = vec![(0u8, 0u8); 1 << log];
+        for (symbol, &weight) in weights.iter().enumerate().filter(|&(_, &w)| w > 0) {
+            let weight = usize::from(weight);
+            let length = 1 << (weight - 1);
+            let bits = (log as usize + 1 - weight) as u8;
+            for entry in &mut entries[starts[weight]..starts[weight] + length] {
+                *entry = (symbol as u8, bits);
+            }
+            starts[weight] += length;
+        }
+        Ok((
+            Huffman {
+                log: log as u8,
+                entries,
+            },
+            used,
+        ))
+    }
+
+    /// Decodes `count` literals from one stream onto `out`.
+    fn decode(&self, stream: &[u8], count: usize, out: &mut Vec<u8>) -> Result<(), Error> {
+        let mut bits = BackwardBits::new(stream)?;
+        for _ in 0..count {
+            let (symbol, length) = self.entries[bits.peek(self.log) as usize];
+            out.push(symbol);
+            bits.remaining -= isize::from(length);
+        }
+        if bits.remaining != 0 {
+            return corrupt("Huffman stream doesn't match its literal count");
+        }
+        Ok(())
+    }
+}
+
+/// Huffman weights compressed with FSE: two interleaved states until the stream runs out.
+fn compressed_weights(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let (counts, log, used) = read_distribution(data, 6, 255)?;
+    let table = Fse::new(&counts, log)?;
+    let mut bits = BackwardBits::new(&data[used..])?;
+    let mut states = [bits.read(log) as usize, bits.read(log) as usize];
+    let mut weights = Vec::new();
+    for turn in [0, 1].into_iter().cycle() {
+        weights.push(table.entries[states[turn]].symbol);
+        states[turn] = table.update(states[turn], &mut bits);
+        if bits.remaining < 0 {
+            weights.push(table.entries[states[1 - turn]].symbol);
+            break;
+        }
+        if weights.len() > 255 {
+            return corrupt("too many Huffman weights");
+        }
+    }
+    Ok(weights)
+}
+
+/// Finds matches for the blocks of one frame, which may refer back anywhere in it.
+struct Matcher<'a> {
+    data: &'a [u8],
+    // The last position plus one where each hash of four bytes was seen.
+    table: Vec<u32>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Matcher<'a> {
+        Matcher {
+            data,
+            table: vec![0; 1 << HASH_LOG],
+        }
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.data[position..position + 4];
+        let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        (word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_LOG)) as usize
+    }
+
+    /// The compressed block for `data[start..end]`.
+    fn block(&mut self, start: usize, end: usize) -> Vec<u8> {
+        let data = self.data;
+        let mut literals = Vec::new();
+        let mut sequences = Vec::new();
+        let mut anchor = start;
+        let mut position = start;
+        while position + MIN_MATCH <= end {
+            let hash = self.hash(position);
+            let candidate = self.table[hash] as usize;
+            self.table[hash] = position as u32 + 1;
+            if candidate == 0
+                || position + 1 - candidate > MAX_OFFSET
+                || data[candidate - 1..candidate + 3] != data[position..position + 4]
+            {
+                position += 1;
+                continue;
+            }
+            let candidate = candidate - 1;
+            let mut length = MIN_MATCH;
+            while position + length < end && data[candidate + length] == data[position + length] {
+                length += 1;
+            }
+            literals.extend_from_slice(&data[anchor..position]);
+            sequences.push((
+                (position - anchor) as u32,
+                (position - candidate) as u32,
+                length as u32,
+            ));
+            for inside in position + 1..(position + length).min(end - MIN_MATCH + 1) {
+                let hash = self.hash(inside);
+                self.table[hash] = inside as u32 + 1;
+            }
+            position += length;
+            anchor = position;
+        }
+        literals.extend_from_slice(&data[anchor..end]);
+        encode_block(&literals, &sequences)
+    }
+}
+
+/// A compressed block of raw literals and `(literal length, offset, match length)` sequences
+/// coded with the predefined tables. Offsets are always given as they are, never as repeats.
+fn encode_block(literals: &[u8], sequences: &[(u32, u32, u32)]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(literals.len() + sequences.len() * 3 + 8);
+    let size = literals.len();
+    match size {
+        0..=31 => out.push((size as u8) << 3),
+        32..=4095 => out.extend([(size << 4) as u8 | 0b0100, (size >> 4) as u8]),
+        _ => out.extend([
+            (size << 4) as u8 | 0b1100,
+            (size >> 4) as u8,
+            (size >> 12) as u8,
+        ]),
+    }
+    out.extend_from_slice(literals);
+
+    let count = sequences.len();
+    match count {
+        0..=127 => out.push(count as u8),
+        128..=0x7eff => out.extend([(count >> 8) as u8 + 128, count as u8]),
+        _ => {
+            out.push(255);
+            out.extend(((count - 0x7f00) as u16).to_le_bytes());
+        }
+    }
+    if count == 0 {
+        return out;
+    }
+    // Predefined tables for all three.
+    out.push(0);
+
+    let literal_lengths = FseEncoder::new(&LL_DEFAULT, 6);
+    let offsets = FseEncoder::new(&OF_DEFAULT, 5);
+    let match_lengths = FseEncoder::new(&ML_DEFAULT, 6);
+    let codes: Vec<(usize, usize, u8)> = sequences
+        .iter()
+        .map(|&(literal_length, offset, match_length)| {
+            (
+                LL_BASE.partition_point(|&base| base <= literal_length) - 1,
+                ML_BASE.partition_point(|&base| base <= match_length) - 1,
+                (31 - (offset + 3).leading_zeros()) as u8,
+            )
+        })
+        .collect();
+    let mut bits = BitWriter::default();
+    let extras = |bits: &mut BitWriter, index: usize| {
+        let (literal_length, offset, match_length) = sequences[index];
+        let (literal_code, match_code, offset_code) = codes[index];
+        bits.add(
+            u64::from(literal_length - LL_BASE[literal_code]),
+            LL_BITS[literal_code],
+        );
+        bits.add(
+            u64::from(match_length - ML_BASE[match_code]),
+            ML_BITS[match_code],
+        );
+        bits.add(u64::from(offset + 3 - (1 << offset_code)), offset_code);
+    };
+
+    // Backwards, so the decoder reads the first sequence first.
+    let (literal_code, match_code, offset_code) = codes[count - 1];
+    let mut match_state = match_lengths.init(match_code);
+    let mut offset_state = offsets.init(usize::from(offset_code));
+    let mut literal_state = literal_lengths.init(literal_code);
+    extras(&mut bits, count - 1);
+    for index in (0..count - 1).rev() {
+        let (literal_code, match_code, offset_code) = codes[index];
+        offsets.encode(&mut offset_state, usize::from(offset_code), &mut bits);
+        match_lengths.encode(&mut match_state, match_code, &mut bits);
+        literal_lengths.encode(&mut literal_state, literal_code, &mut bits);
+        extras(&mut bits, index);
+    }
+    bits.add(u64::from(match_state), match_lengths.log);
+    bits.add(u64::from(offset_state), offsets.log);
+    bits.add(u64::from(literal_state), literal_lengths.log);
+    out.extend(bits.finish());
+    out
+}
+
+/// An FSE encoding table, the mirror of [`Fse`].
+struct FseEncoder {
+    log: u8,
+    // Next states, in the order of the symbols they encode.
+    states: Vec<u16>,
+    // Per symbol: what to add to a state to find the bits it sheds, and where its states start.
+    transforms: Vec<(i32, i32)>,
+}
+
+impl FseEncoder {
+    fn new(counts: &[i16], log: u8) -> FseEncoder {
+        let size = 1i32 << log;
+        let symbols = spread(counts, log).expect("predefined distributions are valid");
+        let mut next = Vec::with_capacity(counts.len());
+        let mut total = 0;
+        let mut transforms = Vec::with_capacity(counts.len());
+        for &count in counts {
+            let count = i32::from(count);
+            next.push(total as usize);
+            transforms.push(match count {
+                0 => (((i32::from(log) + 1) << 16) - size, 0),
+                -1 | 1 => ((i32::from(log) << 16) - size, total - 1),
+                _ => {
+                    let bits = i32::from(log) - (31 - (count - 1).leading_zeros()) as i32;
+                    ((bits << 16) - (count << bits), total - count)
+                }
+            });
+            total += count.abs();
+        }
+        let mut states = vec![0u16; size as usize];
+        for (position, &symbol) in symbols.iter().enumerate() {
+            let slot = &mut next[usize::from(symbol)];
+            states[*slot] = (size as usize + position) as u16;
+            *slot += 1;
+        }
+        FseEncoder {
+            log,
+            states,
+            transforms,
+        }
+    }
+
+    /// The state to start from for the last `symbol` encoded, the first decoded.
+    fn init(&self, symbol: usize) -> u32 {
+        let (bits_delta, state_delta) = self.transforms[symbol];
+        let bits = ((bits_delta + (1 << 15)) >> 16) as u32;
+        let value = ((bits << 16) as i32 - bits_delta) >> bits;
+        u32::from(self.states[(value + state_delta) as usize])
+    }
+
+    fn encode(&self, state: &mut u32, symbol: usize, bits: &mut BitWriter) {
+        let (bits_delta, state_delta) = self.transforms[symbol];
+        let shed = ((*state as i32 + bits_delta) >> 16) as u32;
+        bits.add(u64::from(*state), shed as u8);
+        *state = u32::from(self.states[((*state >> shed) as i32 + state_delta) as usize]);
+    }
+}
+
+/// Writes bits lowest first, for the decoder to read back from the end.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    container: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn add(&mut self, value: u64, count: u8) {
+        if count == 0 {
+            return;
+        }
+        let value = value & ((1u64 << count) - 1);
+        self.container |= value << self.count;
+        self.count += u32::from(count);
+        while self.count >= 8 {
+            self.out.push(self.container as u8);
+            self.container >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// The bytes written, ending with the mark the decoder starts from.
+    fn finish(mut self) -> Vec<u8> {
+        self.add(1, 1);
+        if self.count > 0 {
+            self.out.push(self.container as u8);
+        }
+        self.out
+    }
+}
+
+/// XXH64 with seed 0; frames carry the low 32 bits of their content's.
+fn xxh64(data: &[u8]) -> u64 {
+    const PRIME_1: u64 = 0x9e37_79b1_85eb_ca87;
+    const PRIME_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+    const PRIME_3: u64 = 0x1656_67b1_9e37_79f9;
+    const PRIME_4: u64 = 0x85eb_ca77_c2b2_ae63;
+    const PRIME_5: u64 = 0x27d4_eb2f_1656_67c5;
+    let round = |accumulator: u64, lane: u64| {
+        accumulator
+            .wrapping_add(lane.wrapping_mul(PRIME_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME_1)
+    };
+    let lane = |bytes: &[u8]| little_endian(&bytes[..8]);
+
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut accumulators = [
+            PRIME_1.wrapping_add(PRIME_2),
+            PRIME_2,
+            0,
+            0u64.wrapping_sub(PRIME_1),
+        ];
+        while rest.len() >= 32 {
+            for (index, accumulator) in accumulators.iter_mut().enumerate() {
+                *accumulator = round(*accumulator, lane(&rest[index * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let [a, b, c, d] = accumulators;
+        let mut hash = a
+            .rotate_left(1)
+            .wrapping_add(b.rotate_left(7))
+            .wrapping_add(c.rotate_left(12))
+            .wrapping_add(d.rotate_left(18));
+        for accumulator in accumulators {
+            hash = (hash ^ round(0, accumulator))
+                .wrapping_mul(PRIME_1)
+                .wrapping_add(PRIME_4);
+        }
+        hash
+    } else {
+        PRIME_5
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, lane(rest)))
+            .rotate_left(27)
+            .wrapping_mul(PRIME_1)
+            .wrapping_add(PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash = (hash ^ little_endian(&rest[..4]).wrapping_mul(PRIME_1))
+            .rotate_left(23)
+            .wrapping_mul(PRIME_2)
+            .wrapping_add(PRIME_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = (hash ^ u64::from(byte).wrapping_mul(PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME_1);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ (hash >> 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(text: &str) -> Vec<u8> {
+        let text: String = text.split_whitespace().collect();
+        (0..text.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&text[at..at + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Frames written by the reference implementation, zstd 1.5.7. 300000 times "a" at -1: a
+    // compressed first block, then RLE blocks.
+    const RLE: &str = "28b52ffda4e09304005400001061610100fbff39c00202001061039f04618d5f04a6";
+    // RANDOM at -19: a raw block.
+    const RAW: &str = "
+        28b52ffd2440010200dc0465aa1fad1d5adae5ac1b1e5f1370796cfd10ff19af601d04acb41d022b4678733af2df5fae
+        b70859d1ee3910cb4895b5cc892911ff06b6622edf3cf935fd11112b95";
+    // readings() at -19: Huffman-coded literals, sequences with their own tables and repeat
+    // offsets, and a checksum.
+    const TEXT: &str = "
+        28b52ffd64e200250300c2830d10907d085fb864318a0fa5ff0cf0f515cce6cb93f56a2484ce31a61442c8ff83e64744
+        8c35f4e07c88518847f932b5dbd9f05ca7bd011aa010f8d2fe1d90a3c4383dbd4ae97882943a5006ca41194c06cb20e5
+        1d28639c14e7a214eba3fc2d9402cea16e15";
+
+    /// 64 bytes that don't compress, from an LCG.
+    fn random() -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..64)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345) & 0x7fff_ffff;
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn readings() -> Vec<u8> {
+        (0..12)
+            .map(|point| {
+                format!(
+                    "point {point}: voltage 230.{} V, current {}.5 A\n",
+                    point % 7,
+                    point % 3
+                )
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn decodes_rle_blocks() {
+        assert_eq!(decompress(&bytes(RLE)).unwrap(), vec![b'a'; 300_000]);
+    }
+
+    #[test]
+    fn decodes_raw_blocks() {
+        assert_eq!(decompress(&bytes(RAW)).unwrap(), random());
+    }
+
+    #[test]
+    fn decodes_compressed_literals_and_repeat_offsets() {
+        assert_eq!(decompress(&bytes(TEXT)).unwrap(), readings());
+    }
+
+    #[test]
+    fn skips_skippable_frames() {
+        let mut data = Vec::new();
+        for magic in [SKIPPABLE, SKIPPABLE + 15] {
+            data.extend_from_slice(&magic.to_le_bytes());
+            data.extend_from_slice(&5u32.to_le_bytes());
+            data.extend_from_slice(b"notes");
+        }
+        data.extend(bytes(RAW));
+        data.extend_from_slice(&SKIPPABLE.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend(bytes(TEXT));
+        assert_eq!(decompress(&data).unwrap(), [random(), readings()].concat());
+    }
+
+    #[test]
+    fn checks_the_checksum() {
+        assert_eq!(xxh64(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(xxh64(b"a"), 0xd24e_c4f1_a98c_6e5b);
+        assert_eq!(xxh64(b"abc"), 0x44bc_2cf5_ad77_0999);
+
+        let mut data = bytes(TEXT);
+        *data.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            decompress(&data).unwrap_err().to_string(),
+            "invalid zstd data: checksum mismatch"
+        );
+    }
+
+    #[test]
+    fn refuses_what_isnt_zstd() {
+        assert!(!is_compressed(b"plain text"));
+        assert!(decompress(b"plain text").is_err());
+        let data = bytes(TEXT);
+        assert!(decompress(&data[..data.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut state: u32 = 1;
+        let noise: Vec<u8> = (0..300_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect();
+        let inputs = [
+            Vec::new(),
+            b"x".to_vec(),
+            readings(),
+            readings().repeat(2000),
+            vec![0; 200_000],
+            noise,
+        ];
+        for input in inputs {
+            let compressed = compress(&input);
+            assert!(is_compressed(&compressed));
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+        assert!(compress(&readings().repeat(2000)).len() < readings().len() * 20);
+    }
+
+    // A frame each time a megabyte is collected, on flush, and on drop.
+    #[test]
+    fn round_trips_through_writer_frames() {
+        let input = readings().repeat(FRAME_SIZE / readings().len() * 2 + 1);
+        let mut file = Vec::new();
+        {
+            let mut writer = Writer::new(&mut file);
+            for chunk in input.chunks(7000) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.flush().unwrap();
+            writer.write_all(b"after the flush").unwrap();
+        }
+        let frames = file
+            .windows(4)
+            .filter(|window| *window == MAGIC.to_le_bytes())
+            .count();
+        assert_eq!(frames, 3);
+        assert_eq!(
+            decompress(&file).unwrap(),
+            [input, b"after the flush".to_vec()].concat()
+        );
+    }
+}
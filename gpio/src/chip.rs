@@ -0,0 +1,327 @@
+//! Bindings for the v2 GPIO character device uAPI (linux/gpio.h, kernel 5.10+).
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_NAME_SIZE: usize = 32;
+const LINES_MAX: usize = 64;
+const LINE_NUM_ATTRS_MAX: usize = 10;
+
+pub const FLAG_USED: u64 = 1 << 0;
+pub const FLAG_ACTIVE_LOW: u64 = 1 << 1;
+pub const FLAG_INPUT: u64 = 1 << 2;
+pub const FLAG_OUTPUT: u64 = 1 << 3;
+pub const FLAG_EDGE_RISING: u64 = 1 << 4;
+pub const FLAG_EDGE_FALLING: u64 = 1 << 5;
+pub const FLAG_OPEN_DRAIN: u64 = 1 << 6;
+pub const FLAG_OPEN_SOURCE: u64 = 1 << 7;
+pub const FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+pub const FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+pub const FLAG_BIAS_DISABLED: u64 = 1 << 10;
+const FLAG_EVENT_CLOCK_REALTIME: u64 = 1 << 11;
+
+const ATTR_ID_OUTPUT_VALUES: u32 = 2;
+const ATTR_ID_DEBOUNCE: u32 = 3;
+
+const EVENT_RISING_EDGE: u32 = 1;
+
+#[repr(C)]
+struct ChipInfo {
+    name: [u8; MAX_NAME_SIZE],
+    label: [u8; MAX_NAME_SIZE],
+    lines: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    // Union of flags, output values and debounce period; all fit in the u64.
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; LINES_MAX],
+    consumer: [u8; MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+struct RawLineInfo {
+    name: [u8; MAX_NAME_SIZE],
+    consumer: [u8; MAX_NAME_SIZE],
+    offset: u32,
+    num_attrs: u32,
+    flags: u64,
+    attrs: [LineAttribute; LINE_NUM_ATTRS_MAX],
+    padding: [u32; 4],
+}
+
+#[repr(C)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+#[repr(C)]
+struct RawLineEvent {
+    timestamp_ns: u64,
+    id: u32,
+    offset: u32,
+    seqno: u32,
+    line_seqno: u32,
+    padding: [u32; 6],
+}
+
+const fn iowr(nr: u64, size: usize) -> u64 {
+    (3 << 30) | ((size as u64) << 16) | (0xb4 << 8) | nr
+}
+
+const fn ior(nr: u64, size: usize) -> u64 {
+    (2 << 30) | ((size as u64) << 16) | (0xb4 << 8) | nr
+}
+
+const GET_CHIPINFO: u64 = ior(0x01, size_of::<ChipInfo>());
+const GET_LINEINFO: u64 = iowr(0x05, size_of::<RawLineInfo>());
+const GET_LINE: u64 = iowr(0x07, size_of::<LineRequest>());
+const GET_VALUES: u64 = iowr(0x0e, size_of::<LineValues>());
+
+fn ioctl<T>(fd: RawFd, request: u64, argument: &mut T) -> io::Result<()> {
+    // SAFETY: every request number above encodes the size of the struct passed with it.
+    let result = unsafe { libc::ioctl(fd, request as _, argument as *mut T) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn name(bytes: &[u8]) -> String {
+    CStr::from_bytes_until_nul(bytes)
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+pub struct LineInfo {
+    pub offset: u32,
+    pub name: String,
+    pub consumer: String,
+    pub flags: u64,
+}
+
+pub struct Chip {
+    file: File,
+    pub name: String,
+    pub label: String,
+    pub lines: u32,
+}
+
+impl Chip {
+    /// Opens a chip by path, or by bare name (`gpiochip0`) or number (`0`) under /dev.
+    pub fn open(chip: &str) -> io::Result<Chip> {
+        let path = if chip.starts_with('/') {
+            chip.to_string()
+        } else if chip.chars().all(|c| c.is_ascii_digit()) {
+            format!("/dev/gpiochip{chip}")
+        } else {
+            format!("/dev/{chip}")
+        };
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        // SAFETY: ChipInfo is plain old data.
+        let mut info: ChipInfo = unsafe { std::mem::zeroed() };
+        ioctl(file.as_raw_fd(), GET_CHIPINFO, &mut info)?;
+        Ok(Chip {
+            file,
+            name: name(&info.name),
+            label: name(&info.label),
+            lines: info.lines,
+        })
+    }
+
+    pub fn line_info(&self, offset: u32) -> io::Result<LineInfo> {
+        // SAFETY: RawLineInfo is plain old data.
+        let mut info: RawLineInfo = unsafe { std::mem::zeroed() };
+        info.offset = offset;
+        ioctl(self.file.as_raw_fd(), GET_LINEINFO, &mut info)?;
+        Ok(LineInfo {
+            offset,
+            name: name(&info.name),
+            consumer: name(&info.consumer),
+            flags: info.flags,
+        })
+    }
+
+    /// Requests `offsets` with `flags`; for outputs `values` gives the initial levels and for
+    /// edge detection `debounce` sets the debounce period.
+    pub fn request(
+        &self,
+        offsets: &[u32],
+        flags: u64,
+        values: Option<&[bool]>,
+        debounce: Option<Duration>,
+    ) -> io::Result<Lines> {
+        if offsets.is_empty() || offsets.len() > LINES_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("between 1 and {LINES_MAX} lines can be requested"),
+            ));
+        }
+
+        // SAFETY: LineRequest is plain old data.
+        let mut request: LineRequest = unsafe { std::mem::zeroed() };
+        request.offsets[..offsets.len()].copy_from_slice(offsets);
+        let consumer = b"edge-gpio";
+        request.consumer[..consumer.len()].copy_from_slice(consumer);
+        request.num_lines = offsets.len() as u32;
+        request.config.flags = flags | FLAG_EVENT_CLOCK_REALTIME;
+
+        let all = mask(offsets.len());
+        let mut num_attrs = 0;
+        if let Some(values) = values {
+            request.config.attrs[num_attrs] = LineConfigAttribute {
+                attr: LineAttribute {
+                    id: ATTR_ID_OUTPUT_VALUES,
+                    padding: 0,
+                    value: bits(values),
+                },
+                mask: all,
+            };
+            num_attrs += 1;
+        }
+        if let Some(debounce) = debounce {
+            request.config.attrs[num_attrs] = LineConfigAttribute {
+                attr: LineAttribute {
+                    id: ATTR_ID_DEBOUNCE,
+                    padding: 0,
+                    value: debounce.as_micros() as u64,
+                },
+                mask: all,
+            };
+            num_attrs += 1;
+        }
+        request.config.num_attrs = num_attrs as u32;
+
+        ioctl(self.file.as_raw_fd(), GET_LINE, &mut request)?;
+        Ok(Lines {
+            // SAFETY: the kernel hands us a new fd that nothing else owns.
+            file: unsafe { File::from_raw_fd(request.fd) },
+            offsets: offsets.to_vec(),
+        })
+    }
+}
+
+fn mask(count: usize) -> u64 {
+    if count >= 64 {
+        u64::MAX
+    } else {
+        (1 << count) - 1
+    }
+}
+
+fn bits(values: &[bool]) -> u64 {
+    values
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &value)| bits | (u64::from(value) << i))
+}
+
+pub struct Edge {
+    pub offset: u32,
+    pub rising: bool,
+    pub timestamp: SystemTime,
+    pub seqno: u32,
+}
+
+/// Lines held by a request; they are released when this is dropped.
+pub struct Lines {
+    file: File,
+    offsets: Vec<u32>,
+}
+
+impl Lines {
+    pub fn values(&self) -> io::Result<Vec<bool>> {
+        let mut values = LineValues {
+            bits: 0,
+            mask: mask(self.offsets.len()),
+        };
+        ioctl(self.file.as_raw_fd(), GET_VALUES, &mut values)?;
+        Ok((0..self.offsets.len())
+            .map(|i| values.bits & (1 << i) != 0)
+            .collect())
+    }
+
+    /// Blocks until the next edge event on any of the requested lines.
+    pub fn read_edge(&mut self) -> io::Result<Edge> {
+        let mut buffer = [0u8; size_of::<RawLineEvent>()];
+        self.file.read_exact(&mut buffer)?;
+        // SAFETY: the buffer is exactly one event long and RawLineEvent is plain old data.
+        let event: RawLineEvent = unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const _) };
+        Ok(Edge {
+            offset: event.offset,
+            rising: event.id == EVENT_RISING_EDGE,
+            timestamp: UNIX_EPOCH + Duration::from_nanos(event.timestamp_ns),
+            seqno: event.line_seqno,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_kernel_uapi_layout() {
+        assert_eq!(size_of::<ChipInfo>(), 68);
+        assert_eq!(size_of::<RawLineInfo>(), 256);
+        assert_eq!(size_of::<LineRequest>(), 592);
+        assert_eq!(size_of::<LineValues>(), 16);
+        assert_eq!(size_of::<RawLineEvent>(), 48);
+        // GPIO_GET_CHIPINFO_IOCTL and the GPIO_V2_* requests from linux/gpio.h.
+        assert_eq!(GET_CHIPINFO, 0x8044_b401);
+        assert_eq!(GET_LINEINFO, 0xc100_b405);
+        assert_eq!(GET_LINE, 0xc250_b407);
+        assert_eq!(GET_VALUES, 0xc010_b40e);
+    }
+
+    #[test]
+    fn packs_line_values_into_bits() {
+        assert_eq!(mask(0), 0);
+        assert_eq!(mask(3), 0b111);
+        assert_eq!(mask(64), u64::MAX);
+        assert_eq!(bits(&[true, false, true]), 0b101);
+        assert_eq!(bits(&[]), 0);
+    }
+
+    #[test]
+    fn reads_nul_terminated_names() {
+        let mut bytes = [0u8; MAX_NAME_SIZE];
+        bytes[..9].copy_from_slice(b"gpiochip0");
+        assert_eq!(name(&bytes), "gpiochip0");
+        assert_eq!(name(&[b'x'; MAX_NAME_SIZE]), "");
+    }
+}
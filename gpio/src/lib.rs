@@ -0,0 +1,429 @@
+mod chip;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use edge_core::audit;
+use edge_core::completions::{self, Shell};
+use edge_core::confirm::confirm;
+use edge_core::dry_run;
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::{Format, OutputArgs, Record};
+use edge_core::writes::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chip::Chip;
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Chip path, name or number. Defaults to gpiochip0.
+    #[clap(short, long, env = "EDGE_GPIO_CHIP", action)]
+    chip: Option<String>,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+    /// Print the levels set would drive instead of requesting the lines.
+    #[clap(long, global = true, action)]
+    dry_run: bool,
+    /// Drive lines without asking for confirmation on a terminal.
+    #[clap(short, long, global = true, action)]
+    yes: bool,
+
+    #[clap(subcommand)]
+    command: Option<Subcommands>,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// List the lines of the chip with their direction and consumer.
+    Info,
+
+    /// Open the chip and exit 0 if that works, for container and systemd health probes.
+    Healthcheck,
+
+    /// Read input levels.
+    Get {
+        #[clap(value_parser, required = true)]
+        lines: Vec<u32>,
+        #[clap(short, long, action)]
+        bias: Option<Bias>,
+        #[clap(short, long, action)]
+        active_low: bool,
+    },
+
+    /// Drive outputs, given as LINE=VALUE pairs.
+    ///
+    /// --read-only and the config's [writes] read_only refuse this, but its lists don't cover
+    /// GPIO lines.
+    Set {
+        #[clap(value_parser = parse_assignment, required = true)]
+        values: Vec<(u32, bool)>,
+        #[clap(short, long, action)]
+        drive: Option<Drive>,
+        #[clap(short, long, action)]
+        active_low: bool,
+        /// Keep the lines requested for this many seconds; some drivers reset released lines.
+        #[clap(long, action)]
+        hold: Option<u64>,
+    },
+
+    /// Print edges on input lines as they happen.
+    Watch {
+        #[clap(value_parser, required = true)]
+        lines: Vec<u32>,
+        #[clap(short, long, action)]
+        edge: Option<EdgeKind>,
+        #[clap(short, long, action)]
+        bias: Option<Bias>,
+        #[clap(short, long, action)]
+        active_low: bool,
+        /// Debounce period in milliseconds.
+        #[clap(long, action)]
+        debounce: Option<u64>,
+        /// Exit after this many edges.
+        #[clap(short = 'n', long, action)]
+        count: Option<u64>,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Bias {
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Drive {
+    PushPull,
+    OpenDrain,
+    OpenSource,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum EdgeKind {
+    Rising,
+    Falling,
+    Both,
+}
+
+pub fn run(cli: Args) {
+    if let Some(Subcommands::Completions { shell }) = cli.command {
+        completions::print::<Args>(shell, "gpio");
+        return;
+    }
+    if let Some(Subcommands::GenMan { directory }) = &cli.command {
+        man::generate::<Args>("gpio", directory.as_deref());
+        return;
+    }
+
+    let chip_name = cli.chip.as_deref().unwrap_or("gpiochip0");
+    let out = cli.output.load();
+    if let Some(Subcommands::Healthcheck) = cli.command {
+        health::check_blocking(&out, chip_name, || {
+            Chip::open(chip_name).map(|chip| {
+                format!(
+                    "opened {} [{}] with {} lines",
+                    chip.name, chip.label, chip.lines
+                )
+            })
+        });
+    }
+    let chip = match Chip::open(chip_name) {
+        Ok(chip) => chip,
+        Err(err) => exit::fatal_error(format!("Unable to open GPIO chip {chip_name}"), &err),
+    };
+
+    let command = if let Some(command) = cli.command {
+        command
+    } else {
+        exit::fatal_with(Code::Usage, "No subcommand specified.");
+    };
+
+    match command {
+        Subcommands::Completions { .. } | Subcommands::GenMan { .. } => {
+            unreachable!("handled above")
+        }
+        Subcommands::Healthcheck => unreachable!("handled before opening the chip"),
+        Subcommands::Info => {
+            let lines: Vec<chip::LineInfo> = (0..chip.lines)
+                .filter_map(|offset| match chip.line_info(offset) {
+                    Ok(info) => Some(info),
+                    Err(err) => {
+                        log::warn!("Unable to read line {offset}: {err}");
+                        None
+                    }
+                })
+                .collect();
+            let rows: Vec<Record> = lines
+                .iter()
+                .map(|info| {
+                    Record::new()
+                        .field("offset", info.offset)
+                        .field(
+                            "name",
+                            (!info.name.is_empty()).then_some(info.name.as_str()),
+                        )
+                        .field(
+                            "consumer",
+                            (!info.consumer.is_empty()).then_some(info.consumer.as_str()),
+                        )
+                        .field("flags", flag_names(info.flags))
+                })
+                .collect();
+            let text = || {
+                println!("{} [{}] {} lines", chip.name, chip.label, chip.lines);
+                for info in &lines {
+                    println!(
+                        "{:>4} {:<20} {:<20} {}",
+                        info.offset,
+                        display_or_dash(&info.name),
+                        display_or_dash(&info.consumer),
+                        flag_names(info.flags).join(" ")
+                    );
+                }
+            };
+            match out.format() {
+                Format::Table => out.records(&rows, text),
+                _ => {
+                    let record = Record::new()
+                        .field("chip", chip.name.as_str())
+                        .field("label", chip.label.as_str())
+                        .field("lines", rows);
+                    out.record(&record, text)
+                }
+            }
+        }
+        Subcommands::Get {
+            lines,
+            bias,
+            active_low,
+        } => {
+            let flags = chip::FLAG_INPUT | bias_flags(bias) | active_low_flag(active_low);
+            let request = request(&chip, &lines, flags, None, None);
+            match request.values() {
+                Ok(values) => {
+                    let records = level_records(&lines, &values);
+                    out.records(&records, || {
+                        for (line, value) in lines.iter().zip(values) {
+                            println!("{line}={}", u8::from(value));
+                        }
+                    });
+                }
+                Err(err) => exit::fatal_error("Unable to read lines", &err),
+            }
+        }
+        Subcommands::Set {
+            values,
+            drive,
+            active_low,
+            hold,
+        } => {
+            let (lines, levels): (Vec<u32>, Vec<bool>) = values.into_iter().unzip();
+            let assignments: Vec<String> = lines
+                .iter()
+                .zip(&levels)
+                .map(|(line, level)| format!("{line}={}", u8::from(*level)))
+                .collect();
+            if cli.dry_run {
+                dry_run::Write {
+                    target: &chip.name,
+                    function: "drive output lines",
+                    details: Record::new()
+                        .field("values", assignments.join(" "))
+                        .field("active_low", active_low),
+                    bytes: None,
+                }
+                .print(&out);
+                return;
+            }
+            let (write, value) = (Write::Gpio(&chip.name), assignments.join(" "));
+            if let Err(err) = writes::check(&write, &value) {
+                exit::fatal_with(err.code, err);
+            }
+            confirm(
+                &format!("Drive {} on {}?", assignments.join(" "), chip.name),
+                cli.yes,
+            );
+            let drive = match drive.unwrap_or(Drive::PushPull) {
+                Drive::PushPull => 0,
+                Drive::OpenDrain => chip::FLAG_OPEN_DRAIN,
+                Drive::OpenSource => chip::FLAG_OPEN_SOURCE,
+            };
+            let flags = chip::FLAG_OUTPUT | drive | active_low_flag(active_low);
+            // The initial values are applied atomically with the request itself.
+            let request = chip.request(&lines, flags, Some(&levels), None);
+            audit::record(&write, &value, &request.as_ref().map(|_| ()));
+            let request = request.unwrap_or_else(|err| {
+                exit::fatal_error(format!("Unable to request lines {:?}", lines), &err)
+            });
+            if !out.is_text() {
+                out.records(&level_records(&lines, &levels), || {});
+            }
+            if let Some(seconds) = hold {
+                std::thread::sleep(Duration::from_secs(seconds));
+            }
+            drop(request);
+        }
+        Subcommands::Watch {
+            lines,
+            edge,
+            bias,
+            active_low,
+            debounce,
+            count,
+        } => {
+            let edge = match edge.unwrap_or(EdgeKind::Both) {
+                EdgeKind::Rising => chip::FLAG_EDGE_RISING,
+                EdgeKind::Falling => chip::FLAG_EDGE_FALLING,
+                EdgeKind::Both => chip::FLAG_EDGE_RISING | chip::FLAG_EDGE_FALLING,
+            };
+            let flags = chip::FLAG_INPUT | edge | bias_flags(bias) | active_low_flag(active_low);
+            let debounce = debounce.map(Duration::from_millis);
+            let mut request = request(&chip, &lines, flags, None, debounce);
+
+            let mut seen = 0;
+            while count.map(|count| seen < count).unwrap_or(true) {
+                let edge = match request.read_edge() {
+                    Ok(edge) => edge,
+                    Err(err) => exit::fatal_error("Unable to read edge event", &err),
+                };
+                let timestamp = humantime::format_rfc3339_nanos(edge.timestamp);
+                let kind = if edge.rising { "rising" } else { "falling" };
+                let record = Record::new()
+                    .field("source_timestamp", timestamp.to_string())
+                    .field("line", edge.offset)
+                    .field("edge", kind)
+                    .field("seqno", edge.seqno);
+                out.record(&record, || {
+                    println!("{} {} {} #{}", timestamp, edge.offset, kind, edge.seqno)
+                });
+                seen += 1;
+            }
+        }
+    }
+}
+
+fn request(
+    chip: &Chip,
+    lines: &[u32],
+    flags: u64,
+    values: Option<&[bool]>,
+    debounce: Option<Duration>,
+) -> chip::Lines {
+    match chip.request(lines, flags, values, debounce) {
+        Ok(request) => request,
+        Err(err) => exit::fatal_error(format!("Unable to request lines {:?}", lines), &err),
+    }
+}
+
+fn bias_flags(bias: Option<Bias>) -> u64 {
+    match bias {
+        Some(Bias::PullUp) => chip::FLAG_BIAS_PULL_UP,
+        Some(Bias::PullDown) => chip::FLAG_BIAS_PULL_DOWN,
+        Some(Bias::Disabled) => chip::FLAG_BIAS_DISABLED,
+        None => 0,
+    }
+}
+
+fn active_low_flag(active_low: bool) -> u64 {
+    if active_low {
+        chip::FLAG_ACTIVE_LOW
+    } else {
+        0
+    }
+}
+
+fn level_records(lines: &[u32], values: &[bool]) -> Vec<Record> {
+    lines
+        .iter()
+        .zip(values)
+        .map(|(line, value)| {
+            Record::new()
+                .field("line", *line)
+                .field("value", u8::from(*value))
+        })
+        .collect()
+}
+
+fn flag_names(flags: u64) -> Vec<&'static str> {
+    let names = [
+        (chip::FLAG_USED, "used"),
+        (chip::FLAG_INPUT, "input"),
+        (chip::FLAG_OUTPUT, "output"),
+        (chip::FLAG_ACTIVE_LOW, "active-low"),
+        (chip::FLAG_OPEN_DRAIN, "open-drain"),
+        (chip::FLAG_OPEN_SOURCE, "open-source"),
+        (chip::FLAG_BIAS_PULL_UP, "pull-up"),
+        (chip::FLAG_BIAS_PULL_DOWN, "pull-down"),
+        (chip::FLAG_BIAS_DISABLED, "bias-disabled"),
+        (chip::FLAG_EDGE_RISING, "rising"),
+        (chip::FLAG_EDGE_FALLING, "falling"),
+    ];
+    names
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+fn display_or_dash(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
+
+fn parse_assignment(value: &str) -> Result<(u32, bool), String> {
+    let (line, level) = value
+        .split_once('=')
+        .ok_or_else(|| format!("{value}: expected LINE=VALUE"))?;
+    let line = line
+        .parse()
+        .map_err(|err| format!("{value}: invalid line: {err}"))?;
+    let level = match level {
+        "1" | "high" | "on" => true,
+        "0" | "low" | "off" => false,
+        _ => return Err(format!("{value}: value must be 0 or 1")),
+    };
+    Ok((line, level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_line_assignments() {
+        assert_eq!(parse_assignment("17=1").unwrap(), (17, true));
+        assert_eq!(parse_assignment("4=off").unwrap(), (4, false));
+        assert!(parse_assignment("17").is_err());
+        assert!(parse_assignment("x=1").is_err());
+        assert!(parse_assignment("17=2").is_err());
+    }
+
+    #[test]
+    fn names_line_flags() {
+        let flags = chip::FLAG_USED | chip::FLAG_OUTPUT | chip::FLAG_BIAS_PULL_UP;
+        assert_eq!(flag_names(flags), ["used", "output", "pull-up"]);
+        assert!(flag_names(0).is_empty());
+        assert_eq!(bias_flags(Some(Bias::PullDown)), chip::FLAG_BIAS_PULL_DOWN);
+        assert_eq!(active_low_flag(false) | bias_flags(None), 0);
+    }
+}
@@ -0,0 +1,4 @@
+fn main() {
+    gpio::run(edge_core::logging::parse("gpio"));
+    edge_core::expect::finish();
+}
@@ -0,0 +1,355 @@
+use edge_core::exit::{self, Code};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 8;
+
+const MESSAGE_TYPE_REQUEST: u8 = 0;
+const MESSAGE_TYPE_RESPONSE: u8 = 1;
+const MESSAGE_TYPE_ERROR: u8 = 3;
+const MESSAGE_TYPE_NAK: u8 = 15;
+
+const MESSAGE_ID_SESSION_INITIATE: u8 = 0;
+const MESSAGE_ID_SESSION_CLOSE: u8 = 1;
+const MESSAGE_ID_TOKEN_PASSING_PDU: u8 = 3;
+
+/// Primary master, so the gateway routes our requests like a host system's.
+const PRIMARY_MASTER: u8 = 1;
+
+/// HART frame delimiters for master to field device (STX) requests.
+const DELIMITER_STX_SHORT: u8 = 0x02;
+const DELIMITER_STX_LONG: u8 = 0x82;
+/// HART frame delimiters for field device to master (ACK) responses.
+const DELIMITER_ACK_SHORT: u8 = 0x06;
+const DELIMITER_ACK_LONG: u8 = 0x86;
+
+/// How the field device is addressed in the HART frame.
+#[derive(Clone, Copy)]
+pub enum DeviceAddress {
+    /// Polling address, only valid for command 0.
+    Short(u8),
+    /// 5 byte unique address, required by every other command.
+    Long([u8; 5]),
+}
+
+impl DeviceAddress {
+    pub fn parse_long(hex: &str) -> Result<DeviceAddress, String> {
+        let hex = hex.trim_start_matches("0x");
+        if hex.len() != 10 || !hex.is_ascii() {
+            return Err(format!("long address must be 10 hex digits, got {hex:?}"));
+        }
+        let mut address = [0u8; 5];
+        for (i, byte) in address.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|err| format!("invalid long address {hex:?}: {err}"))?;
+        }
+        Ok(DeviceAddress::Long(address))
+    }
+}
+
+/// Response to a HART command, after stripping the frame.
+pub struct CommandResponse {
+    pub response_code: u8,
+    pub device_status: u8,
+    pub data: Vec<u8>,
+}
+
+enum Transport {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+pub struct Client {
+    transport: Transport,
+    sequence: u16,
+    timeout: Duration,
+}
+
+impl Client {
+    pub async fn connect(
+        addr: &SocketAddr,
+        udp: bool,
+        timeout: Duration,
+    ) -> Result<Client, Box<dyn std::error::Error>> {
+        let transport = if udp {
+            let bind: SocketAddr = if addr.is_ipv4() {
+                "0.0.0.0:0".parse()?
+            } else {
+                "[::]:0".parse()?
+            };
+            let socket = UdpSocket::bind(bind).await?;
+            socket.connect(addr).await?;
+            Transport::Udp(socket)
+        } else {
+            let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+                .await
+                .map_err(|_| {
+                    exit::Error::new(Code::Timeout, format!("timed out connecting to {addr}"))
+                })??;
+            Transport::Tcp(stream)
+        };
+
+        let mut client = Client {
+            transport,
+            sequence: 0,
+            timeout,
+        };
+
+        // Master type followed by the inactivity close timer in milliseconds.
+        let mut body = vec![PRIMARY_MASTER];
+        body.extend_from_slice(&(timeout.as_millis().max(10_000) as u32).to_be_bytes());
+        let (status, _) = client.request(MESSAGE_ID_SESSION_INITIATE, &body).await?;
+        if status != 0 {
+            log::warn!("Session initiate returned status {status}");
+        }
+        Ok(client)
+    }
+
+    pub async fn close(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(MESSAGE_ID_SESSION_CLOSE, &[]).await?;
+        Ok(())
+    }
+
+    pub async fn command(
+        &mut self,
+        address: DeviceAddress,
+        command: u8,
+        data: &[u8],
+    ) -> Result<CommandResponse, Box<dyn std::error::Error>> {
+        let frame = encode_frame(address, command, data)?;
+        let (status, body) = self.request(MESSAGE_ID_TOKEN_PASSING_PDU, &frame).await?;
+        if status != 0 {
+            return Err(format!("gateway rejected command {command} with status {status}").into());
+        }
+        decode_frame(&body, command)
+    }
+
+    async fn request(
+        &mut self,
+        message_id: u8,
+        body: &[u8],
+    ) -> Result<(u8, Vec<u8>), Box<dyn std::error::Error>> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let message = encode_message(message_id, self.sequence, body);
+        log::trace!("Sending {:02x?}", message);
+
+        let response = tokio::time::timeout(self.timeout, self.exchange(&message))
+            .await
+            .map_err(|_| {
+                let message = format!("no response within {}s", self.timeout.as_secs());
+                exit::Error::new(Code::Timeout, message)
+            })??;
+        log::trace!("Received {:02x?}", response);
+        decode_message(&response, message_id, self.sequence)
+    }
+
+    async fn exchange(&mut self, message: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match &mut self.transport {
+            Transport::Tcp(stream) => {
+                stream.write_all(message).await?;
+                let mut header = [0u8; HEADER_LEN];
+                stream.read_exact(&mut header).await?;
+                let length = u16::from_be_bytes([header[6], header[7]]) as usize;
+                if length < HEADER_LEN {
+                    return Err(format!("invalid HART-IP byte count {length}").into());
+                }
+                let mut response = header.to_vec();
+                response.resize(length, 0);
+                stream.read_exact(&mut response[HEADER_LEN..]).await?;
+                Ok(response)
+            }
+            Transport::Udp(socket) => {
+                socket.send(message).await?;
+                let mut buffer = vec![0u8; 65535];
+                let len = socket.recv(&mut buffer).await?;
+                buffer.truncate(len);
+                Ok(buffer)
+            }
+        }
+    }
+}
+
+/// A request message: the HART-IP header, then `body`.
+fn encode_message(message_id: u8, sequence: u16, body: &[u8]) -> Vec<u8> {
+    let length = (HEADER_LEN + body.len()) as u16;
+    let mut message = Vec::with_capacity(length as usize);
+    message.push(VERSION);
+    message.push(MESSAGE_TYPE_REQUEST);
+    message.push(message_id);
+    message.push(0);
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message.extend_from_slice(&length.to_be_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+/// The status and body of the response to request `message_id`/`sequence`.
+fn decode_message(
+    response: &[u8],
+    message_id: u8,
+    sequence: u16,
+) -> Result<(u8, Vec<u8>), Box<dyn std::error::Error>> {
+    if response.len() < HEADER_LEN {
+        return Err("response shorter than the HART-IP header".into());
+    }
+    let (message_type, response_id, status) = (response[1], response[2], response[3]);
+    let response_sequence = u16::from_be_bytes([response[4], response[5]]);
+    match message_type {
+        MESSAGE_TYPE_RESPONSE => {}
+        MESSAGE_TYPE_ERROR | MESSAGE_TYPE_NAK => {
+            return Err(format!("gateway returned error, status {status}").into());
+        }
+        other => return Err(format!("unexpected message type {other}").into()),
+    }
+    if response_id != message_id || response_sequence != sequence {
+        return Err(format!(
+            "response for message {response_id}/{response_sequence} does not match request {message_id}/{sequence}"
+        )
+        .into());
+    }
+    Ok((status, response[HEADER_LEN..].to_vec()))
+}
+
+fn encode_frame(
+    address: DeviceAddress,
+    command: u8,
+    data: &[u8],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if data.len() > u8::MAX as usize {
+        return Err("command data longer than 255 bytes".into());
+    }
+
+    let mut frame = Vec::with_capacity(data.len() + 9);
+    match address {
+        DeviceAddress::Short(poll) => {
+            frame.push(DELIMITER_STX_SHORT);
+            frame.push(0x80 | (poll & 0x3f));
+        }
+        DeviceAddress::Long(mut long) => {
+            frame.push(DELIMITER_STX_LONG);
+            long[0] = 0x80 | (long[0] & 0x3f);
+            frame.extend_from_slice(&long);
+        }
+    }
+    frame.push(command);
+    frame.push(data.len() as u8);
+    frame.extend_from_slice(data);
+    frame.push(checksum(&frame));
+    Ok(frame)
+}
+
+fn decode_frame(frame: &[u8], command: u8) -> Result<CommandResponse, Box<dyn std::error::Error>> {
+    let address_len = match frame.first() {
+        Some(&DELIMITER_ACK_SHORT) => 1,
+        Some(&DELIMITER_ACK_LONG) => 5,
+        Some(other) => return Err(format!("unexpected frame delimiter {other:#04x}").into()),
+        None => return Err("empty response frame".into()),
+    };
+
+    let header_len = 1 + address_len + 2;
+    if frame.len() < header_len + 1 {
+        return Err("response frame is truncated".into());
+    }
+    let response_command = frame[1 + address_len];
+    let byte_count = frame[2 + address_len] as usize;
+    if frame.len() < header_len + byte_count + 1 {
+        return Err("response frame is shorter than its byte count".into());
+    }
+    if response_command != command {
+        return Err(
+            format!("response is for command {response_command}, expected {command}").into(),
+        );
+    }
+
+    let end = header_len + byte_count;
+    if checksum(&frame[..end]) != frame[end] {
+        return Err("response frame checksum mismatch".into());
+    }
+    if byte_count < 2 {
+        return Err("response frame missing its status bytes".into());
+    }
+
+    Ok(CommandResponse {
+        response_code: frame[header_len],
+        device_status: frame[header_len + 1],
+        data: frame[header_len + 2..end].to_vec(),
+    })
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_header_before_the_body() {
+        let message = encode_message(MESSAGE_ID_TOKEN_PASSING_PDU, 0x0102, &[0xaa, 0xbb]);
+        assert_eq!(message, [1, 0, 3, 0, 0x01, 0x02, 0, 10, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn decodes_the_response_to_the_request_only() {
+        let response = [1, MESSAGE_TYPE_RESPONSE, 3, 0, 0, 7, 0, 9, 0xcc];
+        let (status, body) = decode_message(&response, 3, 7).unwrap();
+        assert_eq!((status, body), (0, vec![0xcc]));
+
+        assert!(decode_message(&response, 3, 8).is_err());
+        assert!(decode_message(&response, 0, 7).is_err());
+        assert!(decode_message(&response[..7], 3, 7).is_err());
+        let nak = [1, MESSAGE_TYPE_NAK, 3, 5, 0, 7, 0, 8];
+        let err = decode_message(&nak, 3, 7).unwrap_err();
+        assert!(err.to_string().contains("status 5"), "{err}");
+    }
+
+    #[test]
+    fn frames_commands_with_their_address_and_checksum() {
+        let frame = encode_frame(DeviceAddress::Short(2), 0, &[]).unwrap();
+        assert_eq!(frame, [0x02, 0x82, 0, 0, 0x80]);
+
+        let address = DeviceAddress::parse_long("0x2607123456").unwrap();
+        let frame = encode_frame(address, 1, &[]).unwrap();
+        // The primary master bit is set on the first address byte.
+        assert_eq!(frame[..7], [0x82, 0xa6, 0x07, 0x12, 0x34, 0x56, 1]);
+        assert_eq!(frame[8], checksum(&frame[..8]));
+
+        assert!(DeviceAddress::parse_long("26071234").is_err());
+        assert!(DeviceAddress::parse_long("26071234zz").is_err());
+        assert!(DeviceAddress::parse_long("12345\u{e9}789").is_err());
+        assert!(encode_frame(DeviceAddress::Short(0), 0, &[0; 256]).is_err());
+    }
+
+    #[test]
+    fn decodes_response_frames() {
+        let mut frame = vec![
+            DELIMITER_ACK_SHORT,
+            0x80,
+            1,
+            7,
+            0,
+            0x40,
+            0x27,
+            0x41,
+            0x20,
+            0,
+            0,
+        ];
+        frame.push(checksum(&frame));
+        let response = decode_frame(&frame, 1).unwrap();
+        assert_eq!(response.response_code, 0);
+        assert_eq!(response.device_status, 0x40);
+        assert_eq!(response.data, [0x27, 0x41, 0x20, 0, 0]);
+
+        assert!(decode_frame(&frame, 2).is_err());
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+        assert!(decode_frame(&frame, 1).is_err());
+        assert!(decode_frame(&frame[..5], 1).is_err());
+        assert!(decode_frame(&[DELIMITER_STX_SHORT, 0x80, 1, 0, 0], 1).is_err());
+    }
+}
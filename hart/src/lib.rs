@@ -0,0 +1,637 @@
+mod hartip;
+
+use clap::{Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::fanout;
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::{Output, OutputArgs, Record};
+use edge_core::OrExit;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hartip::{Client, CommandResponse, DeviceAddress};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Gateway address, HART-IP listens on 5094 by default. A comma list or @file with one
+    /// address per line runs the command against every gateway in it at once.
+    #[clap(value_parser, env = "EDGE_HART_ADDRESS")]
+    address: Option<String>,
+
+    /// Use UDP instead of TCP.
+    #[clap(long, action)]
+    udp: bool,
+
+    /// Polling address used to find the device when no long address is given.
+    #[clap(short, long, action)]
+    poll_address: Option<u8>,
+
+    /// 5 byte unique address (10 hex digits) of a device behind the gateway.
+    #[clap(short, long, action)]
+    long_address: Option<String>,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+
+    /// Seconds to wait for the gateway to answer.
+    #[clap(short, long, action)]
+    timeout: Option<u64>,
+    /// Gateways talked to at once when the address is a list (default 16).
+    #[clap(long, action)]
+    parallel: Option<usize>,
+
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Command 0: read unique identifier.
+    Identify,
+    /// Command 3: read loop current and dynamic variables (PV/SV/TV/QV).
+    ReadVariables,
+    /// Commands 13 and 20: read tag, descriptor, date and long tag.
+    ReadTag,
+    /// Send any command with raw data bytes and print the response as hex.
+    Command {
+        #[clap(short, long, action)]
+        number: u8,
+        /// Request data as hex, e.g. 0102ff.
+        #[clap(short, long, action)]
+        data: Option<String>,
+    },
+    /// Open a session, send command 0 and exit 0 if the device answers, for container and
+    /// systemd health probes. --timeout bounds the whole check.
+    Healthcheck,
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+/// Decoded command 0 response.
+struct Identity {
+    manufacturer_id: u16,
+    device_type: u16,
+    device_id: [u8; 3],
+    universal_revision: u8,
+    device_revision: u8,
+    software_revision: u8,
+    hardware_revision: u8,
+    long_address: [u8; 5],
+}
+
+impl Identity {
+    fn decode(data: &[u8]) -> Result<Identity, Box<dyn std::error::Error>> {
+        if data.len() < 12 || data[0] != 254 {
+            return Err("malformed command 0 response".into());
+        }
+        let universal_revision = data[4];
+        let device_id = [data[9], data[10], data[11]];
+
+        // HART 7 splits the manufacturer out of the (now expanded) device type.
+        let (manufacturer_id, device_type) = if universal_revision >= 7 && data.len() >= 19 {
+            (
+                u16::from_be_bytes([data[17], data[18]]),
+                u16::from_be_bytes([data[1], data[2]]),
+            )
+        } else {
+            (u16::from(data[1]), u16::from(data[2]))
+        };
+
+        Ok(Identity {
+            manufacturer_id,
+            device_type,
+            device_id,
+            universal_revision,
+            device_revision: data[5],
+            software_revision: data[6],
+            hardware_revision: data[7] >> 3,
+            long_address: [
+                data[1] & 0x3f,
+                data[2],
+                device_id[0],
+                device_id[1],
+                device_id[2],
+            ],
+        })
+    }
+}
+
+pub async fn run(cli: Args) {
+    if let Subcommands::Completions { shell } = cli.command {
+        completions::print::<Args>(shell, "hart");
+        return;
+    }
+    if let Subcommands::GenMan { directory } = &cli.command {
+        man::generate::<Args>("hart", directory.as_deref());
+        return;
+    }
+
+    let address = cli
+        .address
+        .as_deref()
+        .or_exit_with(Code::Usage, "A gateway address is required.");
+    let out = cli.output.load();
+    if let Some(targets) = fanout::targets(address).or_exit("Invalid address") {
+        fanout::run(&out, address, targets, "EDGE_HART_ADDRESS", cli.parallel).await;
+    }
+    let addr = parse_address(address)
+        .or_exit_with(Code::Usage, &format!("Unable to parse address {address}"));
+
+    let long_address = match cli.long_address.as_deref().map(DeviceAddress::parse_long) {
+        Some(Ok(address)) => Some(address),
+        Some(Err(err)) => exit::fatal_with(Code::Usage, err),
+        None => None,
+    };
+
+    let timeout = Duration::from_secs(cli.timeout.unwrap_or(5));
+    if let Subcommands::Healthcheck = cli.command {
+        let address = long_address.unwrap_or(DeviceAddress::Short(cli.poll_address.unwrap_or(0)));
+        health::check(&out, &addr.to_string(), Some(timeout), async {
+            let result: Result<String, Box<dyn std::error::Error>> = async {
+                let mut client = Client::connect(&addr, cli.udp, timeout).await?;
+                let response = checked(client.command(address, 0, &[]).await?);
+                if let Err(err) = client.close().await {
+                    log::warn!("Unable to close HART-IP session: {err}");
+                }
+                let identity = Identity::decode(&response?.data)?;
+                Ok(format!(
+                    "answered command 0 from device {}",
+                    hex(&identity.long_address)
+                ))
+            }
+            .await;
+            // As in fatal_hart, anything that isn't I/O is a bad answer.
+            result.map_err(|err| match Code::of(&*err) {
+                Code::Failure => exit::Error::new(Code::Protocol, err).into(),
+                _ => err,
+            })
+        })
+        .await;
+    }
+    let mut client = match Client::connect(&addr, cli.udp, timeout).await {
+        Ok(client) => client,
+        Err(err) => fatal_hart(
+            &format!("Unable to open HART-IP session with {addr}"),
+            &*err,
+        ),
+    };
+
+    let poll_address = DeviceAddress::Short(cli.poll_address.unwrap_or(0));
+    let result = match cli.command {
+        Subcommands::Completions { .. } | Subcommands::GenMan { .. } => {
+            unreachable!("handled above")
+        }
+        Subcommands::Healthcheck => unreachable!("handled before connecting"),
+        Subcommands::Identify => {
+            identify(&mut client, long_address.unwrap_or(poll_address), &out).await
+        }
+        Subcommands::ReadVariables => {
+            match resolve_address(&mut client, long_address, poll_address).await {
+                Ok(address) => read_variables(&mut client, address, &out).await,
+                Err(err) => Err(err),
+            }
+        }
+        Subcommands::ReadTag => {
+            match resolve_address(&mut client, long_address, poll_address).await {
+                Ok(address) => read_tag(&mut client, address, &out).await,
+                Err(err) => Err(err),
+            }
+        }
+        Subcommands::Command { number, data } => {
+            let data = data
+                .as_deref()
+                .map(parse_hex)
+                .transpose()
+                .or_exit_with(Code::Usage, "Invalid command data")
+                .unwrap_or_default();
+            // Command 0 is the only one devices answer on their polling address.
+            let address = if number == 0 {
+                Ok(long_address.unwrap_or(poll_address))
+            } else {
+                resolve_address(&mut client, long_address, poll_address).await
+            };
+            match address {
+                Ok(address) => raw_command(&mut client, address, number, &data, &out).await,
+                Err(err) => Err(err),
+            }
+        }
+    };
+
+    if let Err(err) = client.close().await {
+        log::warn!("Unable to close HART-IP session: {err}");
+    }
+
+    if let Err(err) = result {
+        fatal_hart("Request failed", &*err);
+    }
+}
+
+/// Errors that aren't I/O or timeouts come from decoding what the gateway or device sent.
+fn fatal_hart(context: &str, err: &(dyn std::error::Error + 'static)) -> ! {
+    match Code::of(err) {
+        Code::Failure => exit::fatal_with(Code::Protocol, format!("{context}: {err}")),
+        _ => exit::fatal_error(context, err),
+    }
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr, std::net::AddrParseError> {
+    address
+        .parse::<SocketAddr>()
+        .or_else(|_| format!("{address}:5094").parse::<SocketAddr>())
+}
+
+/// Everything but command 0 needs the long address, so look it up when it wasn't given.
+async fn resolve_address(
+    client: &mut Client,
+    long_address: Option<DeviceAddress>,
+    poll_address: DeviceAddress,
+) -> Result<DeviceAddress, Box<dyn std::error::Error>> {
+    if let Some(address) = long_address {
+        return Ok(address);
+    }
+    let response = checked(client.command(poll_address, 0, &[]).await?)?;
+    let identity = Identity::decode(&response.data)?;
+    log::info!("Resolved long address {}", hex(&identity.long_address));
+    Ok(DeviceAddress::Long(identity.long_address))
+}
+
+async fn identify(
+    client: &mut Client,
+    address: DeviceAddress,
+    out: &Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = checked(client.command(address, 0, &[]).await?)?;
+    let identity = Identity::decode(&response.data)?;
+
+    let record = Record::new()
+        .field("manufacturer_id", identity.manufacturer_id)
+        .field("device_type", identity.device_type)
+        .field("device_id", hex(&identity.device_id))
+        .field("long_address", hex(&identity.long_address))
+        .field("hart_revision", identity.universal_revision)
+        .field("device_revision", identity.device_revision)
+        .field("software_revision", identity.software_revision)
+        .field("hardware_revision", identity.hardware_revision)
+        .field("device_status", device_status(response.device_status));
+    out.record(&record, || {
+        println!("Manufacturer ID: {:#06x}", identity.manufacturer_id);
+        println!("Device type: {:#06x}", identity.device_type);
+        println!("Device ID: {}", hex(&identity.device_id));
+        println!("Long address: {}", hex(&identity.long_address));
+        println!("HART revision: {}", identity.universal_revision);
+        println!("Device revision: {}", identity.device_revision);
+        println!("Software revision: {}", identity.software_revision);
+        println!("Hardware revision: {}", identity.hardware_revision);
+        print_device_status(response.device_status);
+    });
+    Ok(())
+}
+
+async fn read_variables(
+    client: &mut Client,
+    address: DeviceAddress,
+    out: &Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = checked(client.command(address, 3, &[]).await?)?;
+    let data = &response.data;
+    if data.len() < 4 {
+        return Err("malformed command 3 response".into());
+    }
+
+    let names = ["PV", "SV", "TV", "QV"];
+    let variables: Vec<Record> = names
+        .iter()
+        .zip(data[4..].chunks_exact(5))
+        .map(|(name, variable)| {
+            Record::new()
+                .field("name", *name)
+                .field("value", float_value(&variable[1..5]))
+                .field("unit", variable[0])
+                .field("unit_name", unit_name(variable[0]))
+        })
+        .collect();
+    let record = Record::new()
+        .field("loop_current_ma", float_value(&data[0..4]))
+        .field("variables", variables)
+        .field("device_status", device_status(response.device_status));
+    out.record(&record, || {
+        println!("Loop current: {}", format_float(&data[0..4], Some(39)));
+        for (name, variable) in names.iter().zip(data[4..].chunks_exact(5)) {
+            println!(
+                "{name}: {}",
+                format_float(&variable[1..5], Some(variable[0]))
+            );
+        }
+        print_device_status(response.device_status);
+    });
+    Ok(())
+}
+
+async fn read_tag(
+    client: &mut Client,
+    address: DeviceAddress,
+    out: &Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = checked(client.command(address, 13, &[]).await?)?;
+    let data = &response.data;
+    if data.len() < 21 {
+        return Err("malformed command 13 response".into());
+    }
+
+    let tag = unpack_ascii(&data[0..6]);
+    let descriptor = unpack_ascii(&data[6..18]);
+    let date = format!(
+        "{:04}-{:02}-{:02}",
+        1900 + u16::from(data[20]),
+        data[19],
+        data[18]
+    );
+
+    // Long tags only exist from HART 6 on; older devices answer "command not implemented".
+    let long_tag = match client.command(address, 20, &[]).await {
+        Ok(response) if response.response_code == 0 => {
+            let end = response
+                .data
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(response.data.len());
+            let long_tag: String = response.data[..end].iter().map(|b| *b as char).collect();
+            Some(long_tag.trim_end().to_string())
+        }
+        Ok(response) => {
+            log::debug!("Command 20 not supported: {}", response.response_code);
+            None
+        }
+        Err(err) => {
+            log::debug!("Command 20 failed: {err}");
+            None
+        }
+    };
+
+    let record = Record::new()
+        .field("tag", tag.trim_end())
+        .field("descriptor", descriptor.trim_end())
+        .field("date", date.as_str())
+        .field("long_tag", long_tag.as_deref())
+        .field("device_status", device_status(response.device_status));
+    out.record(&record, || {
+        println!("Tag: {}", tag.trim_end());
+        println!("Descriptor: {}", descriptor.trim_end());
+        println!("Date: {date}");
+        if let Some(long_tag) = &long_tag {
+            println!("Long tag: {long_tag}");
+        }
+        print_device_status(response.device_status);
+    });
+    Ok(())
+}
+
+async fn raw_command(
+    client: &mut Client,
+    address: DeviceAddress,
+    number: u8,
+    data: &[u8],
+    out: &Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client.command(address, number, data).await?;
+    let record = Record::new()
+        .field("command", number)
+        .field("response_code", response.response_code)
+        .field("device_status", response.device_status)
+        .field("data", hex(&response.data));
+    out.record(&record, || {
+        println!("Response code: {}", response.response_code);
+        println!("Device status: {:#04x}", response.device_status);
+        println!("Data: {}", hex(&response.data));
+    });
+    Ok(())
+}
+
+/// Turns communication errors and command errors into `Err`, leaving warnings through.
+fn checked(response: CommandResponse) -> Result<CommandResponse, Box<dyn std::error::Error>> {
+    if response.response_code & 0x80 != 0 {
+        return Err(format!(
+            "field device communication error {:#04x}",
+            response.response_code
+        )
+        .into());
+    }
+    match response.response_code {
+        0 => Ok(response),
+        // Command specific warnings, the data is still valid.
+        8 | 14 | 24..=27 | 30 | 31 => {
+            log::warn!("Device answered with warning {}", response.response_code);
+            Ok(response)
+        }
+        64 => Err("command not implemented by the device".into()),
+        code => Err(format!("device answered with response code {code}").into()),
+    }
+}
+
+fn print_device_status(status: u8) {
+    let set = device_status(status);
+    if !set.is_empty() {
+        println!("Device status: {}", set.join(", "));
+    }
+}
+
+fn device_status(status: u8) -> Vec<&'static str> {
+    let flags = [
+        (0x80, "device malfunction"),
+        (0x40, "configuration changed"),
+        (0x20, "cold start"),
+        (0x10, "more status available"),
+        (0x08, "loop current fixed"),
+        (0x04, "loop current saturated"),
+        (0x02, "non-primary variable out of limits"),
+        (0x01, "primary variable out of limits"),
+    ];
+    flags
+        .iter()
+        .filter(|(bit, _)| status & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// The IEEE 754 value, or `None` for the "not available" NaN.
+fn float_value(bytes: &[u8]) -> Option<f32> {
+    let value = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (!value.is_nan()).then_some(value)
+}
+
+fn format_float(bytes: &[u8], unit: Option<u8>) -> String {
+    let value = f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if value.is_nan() {
+        return "not available".to_string();
+    }
+    match unit.and_then(unit_name) {
+        Some(unit) => format!("{value} {unit}"),
+        None => match unit {
+            Some(code) => format!("{value} (unit {code})"),
+            None => format!("{value}"),
+        },
+    }
+}
+
+/// Common unit codes from the HART common tables.
+fn unit_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        1 => "inH2O",
+        2 => "inHg",
+        4 => "mmH2O",
+        5 => "mmHg",
+        6 => "psi",
+        7 => "bar",
+        8 => "mbar",
+        11 => "Pa",
+        12 => "kPa",
+        14 => "atm",
+        16 => "gal/min",
+        17 => "l/min",
+        19 => "m3/h",
+        24 => "l/s",
+        28 => "m3/s",
+        32 => "degC",
+        33 => "degF",
+        35 => "K",
+        36 => "mV",
+        37 => "Ohm",
+        38 => "Hz",
+        39 => "mA",
+        41 => "l",
+        43 => "m3",
+        44 => "ft",
+        45 => "m",
+        47 => "in",
+        48 => "cm",
+        49 => "mm",
+        51 => "s",
+        57 => "%",
+        58 => "V",
+        59 => "pH",
+        61 => "kg",
+        70 => "g/s",
+        73 => "kg/s",
+        75 => "kg/h",
+        91 => "g/cm3",
+        92 => "kg/m3",
+        237 => "MPa",
+        250 => "not used",
+        251 => "none",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Decodes HART packed ASCII: four 6-bit characters in every three bytes.
+fn unpack_ascii(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 4 / 3);
+    for chunk in bytes.chunks(3) {
+        let mut word = 0u32;
+        for (i, byte) in chunk.iter().enumerate() {
+            word |= u32::from(*byte) << (16 - 8 * i);
+        }
+        for shift in [18, 12, 6, 0] {
+            let value = ((word >> shift) & 0x3f) as u8;
+            // Values without bit 5 set map to 0x40-0x5f, the rest stay in 0x20-0x3f.
+            let c = if value & 0x20 == 0 {
+                value | 0x40
+            } else {
+                value
+            };
+            out.push(c as char);
+        }
+    }
+    out
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !hex.is_ascii() {
+        return Err(format!("{hex:?} is not hex digits"));
+    }
+    if !hex.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in {hex:?}"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|err| format!("{hex:?}: {err}")))
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpacks_packed_ascii() {
+        assert_eq!(unpack_ascii(&[0x04, 0x20, 0xc4]), "ABCD");
+        assert_eq!(unpack_ascii(&[0x82, 0x08, 0x20]), "    ");
+    }
+
+    #[test]
+    fn decodes_command_zero_identities() {
+        let data = [254, 0x26, 0x07, 5, 5, 3, 2, 0x28, 0, 0x12, 0x34, 0x56];
+        let identity = Identity::decode(&data).unwrap();
+        assert_eq!(
+            (identity.manufacturer_id, identity.device_type),
+            (0x26, 0x07)
+        );
+        assert_eq!(identity.hardware_revision, 5);
+        assert_eq!(identity.long_address, [0x26, 0x07, 0x12, 0x34, 0x56]);
+
+        assert!(Identity::decode(&data[..11]).is_err());
+        assert!(Identity::decode(&[0; 12]).is_err());
+    }
+
+    #[test]
+    fn parses_command_data_in_hex() {
+        assert_eq!(parse_hex("01 ff\t0A").unwrap(), [0x01, 0xff, 0x0a]);
+        assert_eq!(hex(&[0x01, 0xff]), "01ff");
+        assert!(parse_hex("0").is_err());
+        assert!(parse_hex("zz").is_err());
+        assert!(parse_hex("a\u{e9}a").is_err());
+    }
+
+    #[test]
+    fn names_the_device_status_bits() {
+        assert_eq!(
+            device_status(0x81),
+            ["device malfunction", "primary variable out of limits"]
+        );
+        assert!(device_status(0).is_empty());
+    }
+
+    #[test]
+    fn formats_floats_with_their_unit() {
+        assert_eq!(format_float(&1.5f32.to_be_bytes(), Some(7)), "1.5 bar");
+        assert_eq!(
+            format_float(&1.5f32.to_be_bytes(), Some(200)),
+            "1.5 (unit 200)"
+        );
+        assert_eq!(
+            format_float(&f32::NAN.to_be_bytes(), Some(7)),
+            "not available"
+        );
+        assert_eq!(float_value(&f32::NAN.to_be_bytes()), None);
+    }
+}
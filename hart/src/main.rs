@@ -0,0 +1,5 @@
+#[tokio::main]
+async fn main() {
+    hart::run(edge_core::logging::parse("hart")).await;
+    edge_core::expect::finish();
+}
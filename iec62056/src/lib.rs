@@ -0,0 +1,516 @@
+mod obis;
+
+use clap::{Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::{OutputArgs, Record};
+use edge_core::serial;
+use edge_core::OrExit;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::{DataBits, Parity, SerialPort, SerialPortBuilderExt, SerialStream, StopBits};
+
+use obis::DataLine;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+
+/// Every mode C exchange starts at 300 baud before the meter proposes a faster rate.
+const SIGN_ON_BAUD: u32 = 300;
+
+/// Mode C baud rate ids and their rates, slowest first.
+const MODE_C_BAUDS: [(char, u32); 7] = [
+    ('0', 300),
+    ('1', 600),
+    ('2', 1200),
+    ('3', 2400),
+    ('4', 4800),
+    ('5', 9600),
+    ('6', 19200),
+];
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Serial port the optical probe is attached to, e.g. /dev/ttyUSB0 or COM3
+    #[clap(value_parser, env = "EDGE_IEC62056_PORT")]
+    port: Option<String>,
+
+    /// Optional device address for buses with several meters behind one probe.
+    #[clap(short, long, action)]
+    device_address: Option<String>,
+
+    /// Seconds without any byte from the meter before giving up.
+    #[clap(short, long, action)]
+    timeout: Option<u64>,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+
+    #[clap(subcommand)]
+    command: Subcommands,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Sign on and print the identification message only.
+    Identify,
+
+    /// Sign on and exit 0 if the meter identifies itself, for container and systemd health
+    /// probes. The whole check gives up after 5s.
+    Healthcheck,
+
+    /// Sign on, switch to the proposed baud rate and print the data block.
+    Readout {
+        /// Upper bound for the baud rate, for probes that can't keep up with the meter; the
+        /// fastest mode C rate up to it is used, e.g. 4800 for 5000.
+        #[clap(short, long, action)]
+        max_baud: Option<u32>,
+        /// Print the data block exactly as received instead of decoding it.
+        #[clap(short, long, action)]
+        raw: bool,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+/// The identification message the meter answers the sign-on with: `/XXXZIdent\r\n`.
+struct Identification {
+    manufacturer: String,
+    baud_id: char,
+    identification: String,
+}
+
+impl Identification {
+    fn parse(line: &str) -> Result<Identification, Box<dyn std::error::Error>> {
+        let line = line.trim_end();
+        let body = line
+            .strip_prefix('/')
+            .ok_or_else(|| format!("identification does not start with '/': {line:?}"))?;
+        if body.len() < 5 || !body.is_char_boundary(3) || !body.is_char_boundary(4) {
+            return Err(format!("identification too short: {line:?}").into());
+        }
+        let manufacturer = body[..3].to_string();
+        let baud_id = body[3..4].chars().next().unwrap_or('0');
+        // Some meters add an escape sequence (`\2` for mode E) before the identification.
+        let identification = body[4..].to_string();
+        Ok(Identification {
+            manufacturer,
+            baud_id,
+            identification,
+        })
+    }
+
+    /// Baud rate proposed by the meter, according to the mode C table.
+    fn baud_rate(&self) -> Option<u32> {
+        MODE_C_BAUDS
+            .iter()
+            .find(|(id, _)| *id == self.baud_id)
+            .map(|(_, baud)| *baud)
+    }
+}
+
+pub async fn run(cli: Args) {
+    if let Subcommands::Completions { shell } = cli.command {
+        completions::print::<Args>(shell, "iec62056");
+        return;
+    }
+    if let Subcommands::GenMan { directory } = &cli.command {
+        man::generate::<Args>("iec62056", directory.as_deref());
+        return;
+    }
+
+    let port_name = cli
+        .port
+        .as_deref()
+        .or_exit_with(Code::Usage, "A serial port is required.");
+    let timeout = Duration::from_secs(cli.timeout.unwrap_or(5));
+    let out = cli.output.load();
+    if let Subcommands::Healthcheck = cli.command {
+        health::check(&out, port_name, None, async {
+            let result: Result<String, Box<dyn std::error::Error>> = async {
+                let mut port =
+                    open_port(port_name).map_err(|err| exit::Error::new(Code::Connection, err))?;
+                let identification =
+                    sign_on(&mut port, cli.device_address.as_deref(), timeout).await?;
+                if let Err(err) = send_break(&mut port).await {
+                    log::warn!("Unable to send break sequence: {err}");
+                }
+                Ok(format!(
+                    "identified as {} {}",
+                    identification.manufacturer, identification.identification
+                ))
+            }
+            .await;
+            // As in fatal_meter, anything that isn't I/O is a bad answer.
+            result.map_err(|err| match Code::of(&*err) {
+                Code::Failure => exit::Error::new(Code::Protocol, err).into(),
+                _ => err,
+            })
+        })
+        .await;
+    }
+    let mut port = open_port(port_name).or_exit_with(
+        Code::Connection,
+        &format!(
+            "Unable to open serial port {port_name} ({})",
+            serial::hint()
+        ),
+    );
+
+    let identification = match sign_on(&mut port, cli.device_address.as_deref(), timeout).await {
+        Ok(identification) => identification,
+        Err(err) => fatal_meter("Meter did not answer the sign-on", &*err),
+    };
+
+    match cli.command {
+        Subcommands::Completions { .. } | Subcommands::GenMan { .. } => {
+            unreachable!("handled above")
+        }
+        Subcommands::Healthcheck => unreachable!("handled before opening the port"),
+        Subcommands::Identify => {
+            let record = Record::new()
+                .field("manufacturer", identification.manufacturer.as_str())
+                .field("identification", identification.identification.as_str())
+                .field("baud_rate", identification.baud_rate())
+                .field("baud_id", identification.baud_id.to_string());
+            out.record(&record, || {
+                println!("Manufacturer: {}", identification.manufacturer);
+                println!("Identification: {}", identification.identification);
+                match identification.baud_rate() {
+                    Some(baud) => println!("Baud rate: {baud}"),
+                    None => println!("Baud rate: unknown ({})", identification.baud_id),
+                }
+            });
+            // Leave the meter in a clean state rather than waiting for it to time out.
+            if let Err(err) = send_break(&mut port).await {
+                log::warn!("Unable to send break sequence: {err}");
+            }
+        }
+        Subcommands::Readout { max_baud, raw } => {
+            let data = match readout(&mut port, &identification, max_baud, timeout).await {
+                Ok(data) => data,
+                Err(err) => fatal_meter("Data readout failed", &*err),
+            };
+
+            if raw {
+                out.record(&Record::new().field("data", data.as_str()), || {
+                    print!("{data}")
+                });
+                return;
+            }
+
+            let lines: Vec<&str> = data
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && *line != "!")
+                .collect();
+            let records: Vec<Record> = lines.iter().map(|line| data_line_record(line)).collect();
+            out.records(&records, || {
+                for line in &lines {
+                    print_data_line(line);
+                }
+            });
+        }
+    }
+}
+
+/// Errors that aren't I/O or timeouts come from what the meter sent.
+fn fatal_meter(context: &str, err: &(dyn std::error::Error + 'static)) -> ! {
+    match Code::of(err) {
+        Code::Failure => exit::fatal_with(Code::Protocol, format!("{context}: {err}")),
+        _ => exit::fatal_error(context, err),
+    }
+}
+
+fn data_line_record(line: &str) -> Record {
+    let parsed = match DataLine::parse(line) {
+        Some(parsed) => parsed,
+        None => {
+            log::warn!("Unable to decode data line: {line}");
+            return Record::new().field("raw", line);
+        }
+    };
+    let values: Vec<Record> = parsed
+        .values
+        .iter()
+        .map(|value| {
+            Record::new()
+                .field("value", value.value.as_str())
+                .field("unit", value.unit.as_deref())
+        })
+        .collect();
+    Record::new()
+        .field("address", parsed.address.as_str())
+        .field("obis", parsed.obis.as_ref().map(|obis| obis.to_string()))
+        .field(
+            "description",
+            parsed.obis.as_ref().and_then(|obis| obis.description()),
+        )
+        .field("values", values)
+        .field("raw", line)
+}
+
+fn print_data_line(line: &str) {
+    let parsed = if let Some(parsed) = DataLine::parse(line) {
+        parsed
+    } else {
+        // Already warned about when building its record.
+        println!("{line}");
+        return;
+    };
+
+    let values: Vec<String> = parsed.values.iter().map(|v| v.to_string()).collect();
+    let description = parsed.obis.as_ref().and_then(|obis| obis.description());
+    let address = match &parsed.obis {
+        Some(obis) => obis.to_string(),
+        None => parsed.address.clone(),
+    };
+    match description {
+        Some(description) => println!("{address:<16} {description:<36} {}", values.join(" ")),
+        None => println!("{address:<16} {:<36} {}", "", values.join(" ")),
+    }
+}
+
+fn open_port(path: &str) -> Result<SerialStream, Box<dyn std::error::Error>> {
+    let port = tokio_serial::new(serial::port_name(path), SIGN_ON_BAUD)
+        .data_bits(DataBits::Seven)
+        .parity(Parity::Even)
+        .stop_bits(StopBits::One)
+        .open_native_async()?;
+    Ok(port)
+}
+
+async fn sign_on(
+    port: &mut SerialStream,
+    device_address: Option<&str>,
+    timeout: Duration,
+) -> Result<Identification, Box<dyn std::error::Error>> {
+    let request = format!("/?{}!\r\n", device_address.unwrap_or(""));
+    log::debug!("Sending sign-on {:?}", request);
+    port.write_all(request.as_bytes()).await?;
+
+    let line = read_line(port, timeout).await?;
+    log::debug!("Received identification {:?}", line);
+    Identification::parse(&line)
+}
+
+async fn readout(
+    port: &mut SerialStream,
+    identification: &Identification,
+    max_baud: Option<u32>,
+    timeout: Duration,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (baud_id, baud) = match (identification.baud_rate(), max_baud) {
+        (Some(proposed), Some(max)) if proposed > max => {
+            log::info!("Meter proposed {proposed} baud, limiting to {max}");
+            mode_c_baud_at_most(max)
+                .ok_or_else(|| format!("no mode C baud rate is {max} or below"))?
+        }
+        (Some(proposed), _) => (identification.baud_id, proposed),
+        (None, _) => {
+            log::warn!(
+                "Meter proposed baud rate id {:?}, which is not mode C. Staying at {SIGN_ON_BAUD}.",
+                identification.baud_id
+            );
+            ('0', SIGN_ON_BAUD)
+        }
+    };
+
+    // ACK 0 Z 0: normal protocol, agreed baud rate, data readout.
+    let ack = [ACK, b'0', baud_id as u8, b'0', b'\r', b'\n'];
+    port.write_all(&ack).await?;
+    port.flush().await?;
+
+    // The ACK must be fully on the wire at the old rate before switching.
+    tokio::time::sleep(transmission_time(ack.len(), SIGN_ON_BAUD)).await;
+    if baud != SIGN_ON_BAUD {
+        log::info!("Switching to {baud} baud");
+        port.set_baud_rate(baud)?;
+    }
+
+    let block = read_data_message(port, timeout).await?;
+    Ok(block)
+}
+
+/// Reads `STX data ! CR LF ETX BCC` and validates the block check character.
+async fn read_data_message(
+    port: &mut SerialStream,
+    timeout: Duration,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut started = false;
+    let mut block = Vec::new();
+
+    loop {
+        let byte = read_byte(port, timeout).await?;
+        if !started {
+            match byte {
+                STX => started = true,
+                NAK => return Err("meter answered with NAK".into()),
+                // Some probes echo what we sent; skip anything before the message starts.
+                _ => log::trace!("Skipping byte {byte:#04x} before STX"),
+            }
+            continue;
+        }
+
+        block.push(byte);
+        if byte == ETX {
+            break;
+        }
+    }
+
+    let received_bcc = read_byte(port, timeout).await?;
+    data_block(&block, received_bcc)
+}
+
+/// Checks `block`, everything after STX up to and including ETX, against the block check
+/// character received after it, returning the data without the ETX.
+fn data_block(block: &[u8], received_bcc: u8) -> Result<String, Box<dyn std::error::Error>> {
+    let bcc = block_check(block);
+    if received_bcc != bcc {
+        return Err(format!(
+            "block check character mismatch: computed {bcc:#04x}, received {received_bcc:#04x}"
+        )
+        .into());
+    }
+
+    let data = block.strip_suffix(&[ETX]).unwrap_or(block);
+    // read_byte has masked the parity bit already, but a header inside the data is noise.
+    if let Some(byte) = data.iter().find(|b| **b == SOH) {
+        log::warn!("Data block contains unexpected byte {byte:#04x}");
+    }
+    Ok(String::from_utf8_lossy(data).into_owned())
+}
+
+/// The block check character: the XOR of every byte after the SOH or STX, ETX included.
+fn block_check(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+async fn read_line(
+    port: &mut SerialStream,
+    timeout: Duration,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut line = Vec::new();
+    loop {
+        let byte = read_byte(port, timeout).await?;
+        // Anything before the start of the identification is echo or noise.
+        if line.is_empty() && byte != b'/' {
+            continue;
+        }
+        line.push(byte);
+        if line.ends_with(b"\r\n") {
+            return Ok(String::from_utf8_lossy(&line).into_owned());
+        }
+    }
+}
+
+async fn read_byte(
+    port: &mut SerialStream,
+    timeout: Duration,
+) -> Result<u8, Box<dyn std::error::Error>> {
+    let mut byte = [0u8; 1];
+    match tokio::time::timeout(timeout, port.read_exact(&mut byte)).await {
+        Ok(result) => {
+            result?;
+            // 7E1 framing: mask the parity bit in case the driver passes it through.
+            Ok(byte[0] & 0x7f)
+        }
+        Err(_) => {
+            let message = format!("no data from meter within {}s", timeout.as_secs());
+            Err(exit::Error::new(Code::Timeout, message).into())
+        }
+    }
+}
+
+async fn send_break(port: &mut SerialStream) -> Result<(), Box<dyn std::error::Error>> {
+    // SOH B 0 ETX BCC
+    let mut message = vec![SOH, b'B', b'0', ETX];
+    message.push(block_check(&message[1..]));
+    port.write_all(&message).await?;
+    port.flush().await?;
+    Ok(())
+}
+
+/// The fastest mode C baud rate up to `max`, with its id.
+fn mode_c_baud_at_most(max: u32) -> Option<(char, u32)> {
+    MODE_C_BAUDS
+        .iter()
+        .rev()
+        .find(|(_, baud)| *baud <= max)
+        .copied()
+}
+
+fn transmission_time(bytes: usize, baud: u32) -> Duration {
+    // 10 bits per 7E1 character, plus some slack for the UART FIFO.
+    let millis = (bytes as u64 * 10 * 1000) / baud as u64;
+    Duration::from_millis(millis + 50)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_identifications() {
+        let identification = Identification::parse("/ISk5MT174-0001\r\n").unwrap();
+        assert_eq!(identification.manufacturer, "ISk");
+        assert_eq!(identification.baud_id, '5');
+        assert_eq!(identification.identification, "MT174-0001");
+        assert_eq!(identification.baud_rate(), Some(9600));
+
+        // Mode E meters put an escape sequence before the identification.
+        let identification = Identification::parse("/LGZ4\\2ZMD3104107.B32\r\n").unwrap();
+        assert_eq!(identification.identification, "\\2ZMD3104107.B32");
+        assert_eq!(identification.baud_rate(), Some(4800));
+
+        // A baud id outside mode C.
+        let identification = Identification::parse("/ABCAident\r\n").unwrap();
+        assert_eq!(identification.baud_rate(), None);
+
+        assert!(Identification::parse("ISk5MT174\r\n").is_err());
+        assert!(Identification::parse("/ISk5\r\n").is_err());
+        assert!(Identification::parse("/IS\u{e9}MT174\r\n").is_err());
+    }
+
+    #[test]
+    fn limits_the_baud_rate_to_the_fastest_mode_c_one_below() {
+        assert_eq!(mode_c_baud_at_most(9600), Some(('5', 9600)));
+        assert_eq!(mode_c_baud_at_most(5000), Some(('4', 4800)));
+        assert_eq!(mode_c_baud_at_most(115200), Some(('6', 19200)));
+        assert_eq!(mode_c_baud_at_most(300), Some(('0', 300)));
+        assert_eq!(mode_c_baud_at_most(110), None);
+    }
+
+    #[test]
+    fn checks_the_block_check_character() {
+        // The break message SOH B0 ETX is always followed by 'q'.
+        assert_eq!(block_check(&[b'B', b'0', ETX]), b'q');
+
+        let block = b"1.8.0(001234.5*kWh)\r\n!\r\n\x03";
+        let bcc = block_check(block);
+        assert_eq!(
+            data_block(block, bcc).unwrap(),
+            "1.8.0(001234.5*kWh)\r\n!\r\n"
+        );
+        let err = data_block(block, bcc ^ 1).unwrap_err();
+        assert!(err.to_string().contains("mismatch"), "{err}");
+    }
+}
@@ -0,0 +1,5 @@
+#[tokio::main]
+async fn main() {
+    iec62056::run(edge_core::logging::parse("iec62056")).await;
+    edge_core::expect::finish();
+}
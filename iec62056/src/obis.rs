@@ -0,0 +1,227 @@
+use std::fmt;
+
+/// An OBIS code as found in the data block, e.g. `1-0:1.8.0*255`.
+///
+/// Meters routinely drop the leading `A-B:` and trailing `*F` groups, so those are optional.
+/// The C group may also be a letter (`C.1.0`, `F.F`) for manufacturer and service codes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Obis {
+    pub medium: Option<u8>,
+    pub channel: Option<u8>,
+    pub quantity: String,
+    pub measurement: String,
+    pub tariff: Option<String>,
+    pub billing_period: Option<String>,
+}
+
+impl Obis {
+    pub fn parse(code: &str) -> Option<Obis> {
+        let (ab, rest) = match code.split_once(':') {
+            Some((ab, rest)) => (Some(ab), rest),
+            None => (None, code),
+        };
+
+        let (medium, channel) = match ab {
+            Some(ab) => {
+                let (a, b) = ab.split_once('-')?;
+                (Some(a.parse().ok()?), Some(b.parse().ok()?))
+            }
+            None => (None, None),
+        };
+
+        let (cde, billing_period) = match rest.split_once(['*', '&']) {
+            Some((cde, f)) => (cde, Some(f.to_string())),
+            None => (rest, None),
+        };
+
+        let mut groups = cde.split('.');
+        let quantity = groups.next().filter(|g| !g.is_empty())?.to_string();
+        let measurement = groups.next()?.to_string();
+        let tariff = groups.next().map(str::to_string);
+        if groups.next().is_some() {
+            return None;
+        }
+
+        Some(Obis {
+            medium,
+            channel,
+            quantity,
+            measurement,
+            tariff,
+            billing_period,
+        })
+    }
+
+    /// Human readable description for the codes commonly seen on electricity meters.
+    pub fn description(&self) -> Option<&'static str> {
+        // Abstract objects (medium 0) and electricity (medium 1) share the C.D space on
+        // shortened codes, so the medium only matters when it is present and not 1.
+        if let Some(medium) = self.medium {
+            if medium != 0 && medium != 1 {
+                return None;
+            }
+        }
+
+        let description = match (self.quantity.as_str(), self.measurement.as_str()) {
+            ("0", "0") => "Device address",
+            ("0", "1") => "Billing period counter",
+            ("0", "2") => "Firmware version",
+            ("0", "9") => match self.tariff.as_deref() {
+                Some("1") => "Local time",
+                Some("2") => "Local date",
+                _ => return None,
+            },
+            ("C", "1") => "Meter serial number",
+            ("F", "F") => "Error register",
+            ("1", "8") => "Active energy import (+A)",
+            ("2", "8") => "Active energy export (-A)",
+            ("3", "8") => "Reactive energy import (+R)",
+            ("4", "8") => "Reactive energy export (-R)",
+            ("15", "8") => "Active energy absolute (|A|)",
+            ("1", "6") => "Maximum demand import (+A)",
+            ("2", "6") => "Maximum demand export (-A)",
+            ("1", "7") => "Active power import (+P)",
+            ("2", "7") => "Active power export (-P)",
+            ("14", "7") => "Supply frequency",
+            ("13", "7") => "Power factor",
+            ("21", "7") => "Active power import L1",
+            ("41", "7") => "Active power import L2",
+            ("61", "7") => "Active power import L3",
+            ("31", "7") => "Current L1",
+            ("51", "7") => "Current L2",
+            ("71", "7") => "Current L3",
+            ("91", "7") => "Current neutral",
+            ("32", "7") => "Voltage L1",
+            ("52", "7") => "Voltage L2",
+            ("72", "7") => "Voltage L3",
+            _ => return None,
+        };
+        Some(description)
+    }
+}
+
+impl fmt::Display for Obis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(a), Some(b)) = (self.medium, self.channel) {
+            write!(f, "{a}-{b}:")?;
+        }
+        write!(f, "{}.{}", self.quantity, self.measurement)?;
+        if let Some(e) = &self.tariff {
+            write!(f, ".{e}")?;
+        }
+        if let Some(bp) = &self.billing_period {
+            write!(f, "*{bp}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of the data block: `address(value*unit)`.
+///
+/// Some objects (load profiles, maximum demand with timestamp) carry several value groups,
+/// so all of them are kept.
+pub struct DataLine {
+    pub address: String,
+    pub obis: Option<Obis>,
+    pub values: Vec<DataValue>,
+}
+
+pub struct DataValue {
+    pub value: String,
+    pub unit: Option<String>,
+}
+
+impl DataLine {
+    pub fn parse(line: &str) -> Option<DataLine> {
+        let open = line.find('(')?;
+        let address = line[..open].trim().to_string();
+
+        let mut values = Vec::new();
+        let mut rest = &line[open..];
+        while let Some(stripped) = rest.strip_prefix('(') {
+            let close = stripped.find(')')?;
+            let group = &stripped[..close];
+            let (value, unit) = match group.split_once('*') {
+                Some((value, unit)) => (value.to_string(), Some(unit.to_string())),
+                None => (group.to_string(), None),
+            };
+            values.push(DataValue { value, unit });
+            rest = &stripped[close + 1..];
+        }
+
+        let obis = Obis::parse(&address);
+        Some(DataLine {
+            address,
+            obis,
+            values,
+        })
+    }
+}
+
+impl fmt::Display for DataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unit {
+            Some(unit) => write!(f, "{} {}", self.value, unit),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_full_and_shortened_codes() {
+        let obis = Obis::parse("1-0:1.8.0*255").unwrap();
+        assert_eq!((obis.medium, obis.channel), (Some(1), Some(0)));
+        assert_eq!(
+            (obis.quantity.as_str(), obis.measurement.as_str()),
+            ("1", "8")
+        );
+        assert_eq!(obis.tariff.as_deref(), Some("0"));
+        assert_eq!(obis.billing_period.as_deref(), Some("255"));
+        assert_eq!(obis.to_string(), "1-0:1.8.0*255");
+        assert_eq!(obis.description(), Some("Active energy import (+A)"));
+
+        let obis = Obis::parse("1.8.1&02").unwrap();
+        assert_eq!((obis.medium, obis.channel), (None, None));
+        assert_eq!(obis.billing_period.as_deref(), Some("02"));
+        assert_eq!(obis.to_string(), "1.8.1*02");
+
+        let obis = Obis::parse("F.F").unwrap();
+        assert_eq!(obis.tariff, None);
+        assert_eq!(obis.description(), Some("Error register"));
+
+        // Other media share the shortened codes but not their descriptions.
+        assert_eq!(Obis::parse("7-0:1.8.0").unwrap().description(), None);
+
+        assert!(Obis::parse("1.8.0.1").is_none());
+        assert!(Obis::parse("1:1.8.0").is_none());
+        assert!(Obis::parse(".8").is_none());
+        assert!(Obis::parse("18").is_none());
+    }
+
+    #[test]
+    fn parses_data_lines() {
+        let line = DataLine::parse("1.8.0(001234.567*kWh)").unwrap();
+        assert_eq!(line.address, "1.8.0");
+        assert!(line.obis.is_some());
+        assert_eq!(line.values.len(), 1);
+        assert_eq!(line.values[0].value, "001234.567");
+        assert_eq!(line.values[0].unit.as_deref(), Some("kWh"));
+        assert_eq!(line.values[0].to_string(), "001234.567 kWh");
+
+        // Maximum demand with the time it happened.
+        let line = DataLine::parse("1.6.0(02.28*kW)(2103151430)").unwrap();
+        let values: Vec<String> = line.values.iter().map(|v| v.to_string()).collect();
+        assert_eq!(values, ["02.28 kW", "2103151430"]);
+
+        let line = DataLine::parse("SERIAL(12345678)").unwrap();
+        assert!(line.obis.is_none());
+        assert_eq!(line.values[0].unit, None);
+
+        assert!(DataLine::parse("1.8.0").is_none());
+        assert!(DataLine::parse("1.8.0(001234.567*kWh").is_none());
+    }
+}
@@ -0,0 +1,452 @@
+//! Offline analysis of Modbus TCP traffic in a capture file.
+
+use edge_core::output::Record;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::pcap::{self, Segment};
+
+type Endpoint = (IpAddr, u16);
+
+/// One direction of a TCP connection, reassembled by sequence number.
+#[derive(Default)]
+struct Stream {
+    next_sequence: Option<u32>,
+    buffer: Vec<u8>,
+}
+
+impl Stream {
+    /// Adds a segment and returns any complete MBAP frames, plus whether data was lost.
+    fn push(&mut self, segment: &Segment) -> (Vec<Vec<u8>>, bool) {
+        let mut lost = false;
+        if segment.syn {
+            self.next_sequence = Some(segment.sequence.wrapping_add(1));
+            self.buffer.clear();
+        }
+        if segment.payload.is_empty() {
+            return (Vec::new(), false);
+        }
+
+        let expected = *self.next_sequence.get_or_insert(segment.sequence);
+        let offset = segment.sequence.wrapping_sub(expected) as i32;
+        let payload = if offset < 0 {
+            // Retransmission, keep only the part we haven't seen yet.
+            let seen = offset.unsigned_abs() as usize;
+            if seen >= segment.payload.len() {
+                return (Vec::new(), false);
+            }
+            &segment.payload[seen..]
+        } else {
+            if offset > 0 {
+                // The capture missed data; assume the next segment starts a fresh frame.
+                lost = !self.buffer.is_empty();
+                self.buffer.clear();
+            }
+            segment.payload
+        };
+        self.next_sequence = Some(segment.sequence.wrapping_add(segment.payload.len() as u32));
+        self.buffer.extend_from_slice(payload);
+
+        let mut frames = Vec::new();
+        while self.buffer.len() >= 7 {
+            let protocol = u16::from_be_bytes([self.buffer[2], self.buffer[3]]);
+            let length = u16::from_be_bytes([self.buffer[4], self.buffer[5]]) as usize;
+            if protocol != 0 || !(2..=254).contains(&length) {
+                lost = true;
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < 6 + length {
+                break;
+            }
+            frames.push(self.buffer.drain(..6 + length).collect());
+        }
+        (frames, lost)
+    }
+}
+
+struct Outstanding {
+    timestamp: Duration,
+    unit: u8,
+    function: u8,
+}
+
+#[derive(Default)]
+struct FunctionStats {
+    requests: u64,
+    responses: u64,
+    exceptions: u64,
+    latencies: Vec<Duration>,
+}
+
+#[derive(Default)]
+struct UnitStats {
+    functions: BTreeMap<u8, FunctionStats>,
+    // (function, exception code) -> count
+    exceptions: BTreeMap<(u8, u8), u64>,
+    unanswered: u64,
+    unmatched_responses: u64,
+}
+
+#[derive(Default)]
+pub struct Analysis {
+    first_timestamp: Option<Duration>,
+    last_timestamp: Duration,
+    packets: usize,
+    modbus_segments: usize,
+    streams: HashMap<(Endpoint, Endpoint), Stream>,
+    outstanding: HashMap<(Endpoint, Endpoint, u16), Outstanding>,
+    // (server, unit id) -> stats
+    units: BTreeMap<(SocketAddr, u8), UnitStats>,
+    lost_data: u64,
+}
+
+impl Analysis {
+    pub fn run(file: &[u8], port: u16) -> Result<Analysis, Box<dyn std::error::Error>> {
+        let mut analysis = Analysis::default();
+        let packets = pcap::packets(file)?;
+        analysis.packets = packets.len();
+
+        for packet in &packets {
+            let segment = match pcap::tcp_segment(packet) {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let request = if segment.destination.1 == port {
+                true
+            } else if segment.source.1 == port {
+                false
+            } else {
+                continue;
+            };
+            analysis.modbus_segments += 1;
+            analysis.record_timestamp(segment.timestamp);
+
+            let key = (segment.source, segment.destination);
+            if segment.rst {
+                analysis.streams.remove(&key);
+                analysis
+                    .streams
+                    .remove(&(segment.destination, segment.source));
+                continue;
+            }
+            let (frames, lost) = analysis.streams.entry(key).or_default().push(&segment);
+            if lost {
+                analysis.lost_data += 1;
+            }
+            if segment.fin {
+                analysis.streams.remove(&key);
+            }
+
+            for frame in frames {
+                if request {
+                    analysis.request(&segment, &frame);
+                } else {
+                    analysis.response(&segment, &frame);
+                }
+            }
+        }
+
+        // Whatever is still outstanding at the end of the capture never got an answer.
+        let outstanding: Vec<_> = analysis.outstanding.drain().collect();
+        for ((_, server, _), outstanding) in outstanding {
+            analysis.unit(server, outstanding.unit).unanswered += 1;
+        }
+        Ok(analysis)
+    }
+
+    fn record_timestamp(&mut self, timestamp: Duration) {
+        if timestamp.is_zero() {
+            return;
+        }
+        self.first_timestamp.get_or_insert(timestamp);
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+    }
+
+    fn unit(&mut self, server: Endpoint, unit: u8) -> &mut UnitStats {
+        self.units
+            .entry((SocketAddr::new(server.0, server.1), unit))
+            .or_default()
+    }
+
+    fn request(&mut self, segment: &Segment, frame: &[u8]) {
+        let (transaction, unit, function) = header(frame);
+        let server = segment.destination;
+        self.unit(server, unit)
+            .functions
+            .entry(function)
+            .or_default()
+            .requests += 1;
+
+        let key = (segment.source, server, transaction);
+        let previous = self.outstanding.insert(
+            key,
+            Outstanding {
+                timestamp: segment.timestamp,
+                unit,
+                function,
+            },
+        );
+        if previous.is_some() {
+            self.unit(server, unit).unanswered += 1;
+        }
+    }
+
+    fn response(&mut self, segment: &Segment, frame: &[u8]) {
+        let (transaction, unit, function) = header(frame);
+        let server = segment.source;
+        let key = (segment.destination, server, transaction);
+        let outstanding = self.outstanding.remove(&key);
+        let stats = self.unit(server, unit);
+
+        let outstanding = match outstanding {
+            Some(outstanding) if outstanding.function == function & 0x7f => outstanding,
+            _ => {
+                stats.unmatched_responses += 1;
+                return;
+            }
+        };
+        let function_stats = stats.functions.entry(outstanding.function).or_default();
+        function_stats.responses += 1;
+        function_stats
+            .latencies
+            .push(segment.timestamp.saturating_sub(outstanding.timestamp));
+
+        if function & 0x80 != 0 {
+            function_stats.exceptions += 1;
+            let code = frame.get(8).copied().unwrap_or(0);
+            *stats
+                .exceptions
+                .entry((outstanding.function, code))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn to_record(&self) -> Record {
+        let duration = self
+            .first_timestamp
+            .map(|first| self.last_timestamp.saturating_sub(first))
+            .unwrap_or_default();
+        let units: Vec<Record> = self
+            .units
+            .iter()
+            .map(|((server, unit), stats)| {
+                let functions: Vec<Record> = stats
+                    .functions
+                    .iter()
+                    .map(|(function, f)| {
+                        let mut sorted = f.latencies.clone();
+                        sorted.sort();
+                        Record::new()
+                            .field("function", *function)
+                            .field("name", function_name(*function))
+                            .field("requests", f.requests)
+                            .field("responses", f.responses)
+                            .field("exceptions", f.exceptions)
+                            .field("avg_ms", (!sorted.is_empty()).then(|| ms(average(&sorted))))
+                            .field("max_ms", sorted.last().map(|max| ms(*max)))
+                    })
+                    .collect();
+                let exceptions: Vec<Record> = stats
+                    .exceptions
+                    .iter()
+                    .map(|((function, code), count)| {
+                        Record::new()
+                            .field("function", *function)
+                            .field("code", *code)
+                            .field("name", exception_name(*code))
+                            .field("count", *count)
+                    })
+                    .collect();
+                Record::new()
+                    .field("server", server.to_string())
+                    .field("unit", *unit)
+                    .field("unanswered", stats.unanswered)
+                    .field("unmatched_responses", stats.unmatched_responses)
+                    .field("functions", functions)
+                    .field("exceptions", exceptions)
+            })
+            .collect();
+        Record::new()
+            .field("packets", self.packets)
+            .field("modbus_segments", self.modbus_segments)
+            .field("duration_s", duration.as_secs_f64())
+            .field("gaps", self.lost_data)
+            .field("units", units)
+    }
+
+    /// One row per server, unit and function code, for table output.
+    pub fn rows(&self) -> Vec<Record> {
+        let mut rows = Vec::new();
+        for ((server, unit), stats) in &self.units {
+            for (function, f) in &stats.functions {
+                let mut sorted = f.latencies.clone();
+                sorted.sort();
+                rows.push(
+                    Record::new()
+                        .field("server", server.to_string())
+                        .field("unit", *unit)
+                        .field(
+                            "function",
+                            format!("{function:02} {}", function_name(*function)),
+                        )
+                        .field("requests", f.requests)
+                        .field("responses", f.responses)
+                        .field("exceptions", f.exceptions)
+                        .field("avg_ms", (!sorted.is_empty()).then(|| ms(average(&sorted))))
+                        .field("max_ms", sorted.last().map(|max| ms(*max))),
+                );
+            }
+        }
+        rows
+    }
+
+    pub fn print(&self) {
+        let duration = self
+            .first_timestamp
+            .map(|first| self.last_timestamp.saturating_sub(first))
+            .unwrap_or_default();
+        println!(
+            "{} packets, {} Modbus TCP segments over {:.1}s",
+            self.packets,
+            self.modbus_segments,
+            duration.as_secs_f64()
+        );
+        if self.lost_data > 0 {
+            println!(
+                "{} gaps in the capture; frames around them were skipped",
+                self.lost_data
+            );
+        }
+
+        for ((server, unit), stats) in &self.units {
+            let requests: u64 = stats.functions.values().map(|f| f.requests).sum();
+            let responses: u64 = stats.functions.values().map(|f| f.responses).sum();
+            let exceptions: u64 = stats.functions.values().map(|f| f.exceptions).sum();
+            let mut latencies: Vec<Duration> = stats
+                .functions
+                .values()
+                .flat_map(|f| f.latencies.iter().copied())
+                .collect();
+            latencies.sort();
+
+            println!();
+            println!("{server} unit {unit}");
+            println!(
+                "  requests {requests}, responses {responses}, exceptions {exceptions}, unanswered {}",
+                stats.unanswered
+            );
+            if stats.unmatched_responses > 0 {
+                println!(
+                    "  {} responses without a matching request",
+                    stats.unmatched_responses
+                );
+            }
+            if !latencies.is_empty() {
+                println!(
+                    "  latency min {} avg {} p95 {} max {}",
+                    millis(latencies[0]),
+                    millis(average(&latencies)),
+                    millis(percentile(&latencies, 0.95)),
+                    millis(latencies[latencies.len() - 1])
+                );
+            }
+
+            println!(
+                "  {:<34} {:>8} {:>9} {:>10} {:>10} {:>10}",
+                "function", "requests", "responses", "exceptions", "avg", "max"
+            );
+            for (function, f) in &stats.functions {
+                let mut sorted = f.latencies.clone();
+                sorted.sort();
+                let (avg, max) = match sorted.last() {
+                    Some(max) => (millis(average(&sorted)), millis(*max)),
+                    None => ("-".to_string(), "-".to_string()),
+                };
+                println!(
+                    "  {:<34} {:>8} {:>9} {:>10} {:>10} {:>10}",
+                    format!("{function:02} {}", function_name(*function)),
+                    f.requests,
+                    f.responses,
+                    f.exceptions,
+                    avg,
+                    max
+                );
+            }
+
+            for ((function, code), count) in &stats.exceptions {
+                println!(
+                    "  exception {code:#04x} {} on function {function:02}: {count}",
+                    exception_name(*code)
+                );
+            }
+        }
+    }
+}
+
+/// Transaction id, unit id and function code of an MBAP frame.
+fn header(frame: &[u8]) -> (u16, u8, u8) {
+    (
+        u16::from_be_bytes([frame[0], frame[1]]),
+        frame[6],
+        frame.get(7).copied().unwrap_or(0),
+    )
+}
+
+fn average(latencies: &[Duration]) -> Duration {
+    latencies.iter().sum::<Duration>() / latencies.len() as u32
+}
+
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn ms(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+fn function_name(function: u8) -> &'static str {
+    match function {
+        1 => "read coils",
+        2 => "read discrete inputs",
+        3 => "read holding registers",
+        4 => "read input registers",
+        5 => "write single coil",
+        6 => "write single register",
+        7 => "read exception status",
+        8 => "diagnostics",
+        11 => "get comm event counter",
+        12 => "get comm event log",
+        15 => "write multiple coils",
+        16 => "write multiple registers",
+        17 => "report server id",
+        20 => "read file record",
+        21 => "write file record",
+        22 => "mask write register",
+        23 => "read/write multiple registers",
+        24 => "read fifo queue",
+        43 => "encapsulated interface",
+        _ => "unknown",
+    }
+}
+
+fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x08 => "memory parity error",
+        0x0a => "gateway path unavailable",
+        0x0b => "gateway target failed to respond",
+        _ => "unknown",
+    }
+}
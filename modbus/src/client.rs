@@ -0,0 +1,395 @@
+//! The Modbus TCP operations behind the `modbus` commands, for Rust applications that would
+//! otherwise shell out to the CLI. Calls retry connection failures and timeouts by the device's
+//! policy and report to [`edge_core::telemetry`] when it's set up:
+//!
+//! ```no_run
+//! use modbus::client::{self, Device, Read, RegisterKind};
+//!
+//! # async fn example() -> Result<(), client::Error> {
+//! let device = Device::resolve("10.0.0.5")?.unit_id(3);
+//! let values = client::read_registers(&device, &Read::new(RegisterKind::Holding, 40070)).await?;
+//! client::write_register(&device, 40236, 1).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A device with a [`Limiter`] waits for it before every request, retries included. One with
+//! a [`Device::cache_ttl`] serves repeated reads of the same registers from an
+//! [`edge_core::cache`] shared by every call in the process, until the values are that old or
+//! a write to the device changes them.
+//!
+//! Calls share one connection per address through an [`edge_core::pool`], kept open between
+//! them: concurrent calls to a device take turns on it, units behind a gateway included.
+//! Connecting and each request give up after the device's [`Device::timeout`], closing the
+//! connection, so a device that takes connections but stopped answering fails like one that's
+//! unreachable.
+//!
+//! A device with a [`Device::secondary`], such as the standby CPU of a redundant PLC, fails
+//! over to it when the address in use can't be reached, and back by the [`Failback`] policy;
+//! [`Device::served_by`] is where the last call went.
+//!
+//! Errors are [`std::io::Error`]s for the connection and the device's exception answers (see
+//! [`is_exception`]), or [`edge_core::exit::Error`]s with their exit code otherwise.
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use edge_core::cache::Cache;
+use edge_core::failover::{Failback, Failover};
+use edge_core::pool::Pool;
+use edge_core::rate::{self, Limiter};
+use edge_core::retry::{Retry, Transient};
+use edge_core::stats;
+use edge_core::telemetry::{self, Span};
+use tokio_modbus::client::{Context, Reader, Writer};
+use tokio_modbus::slave::{Slave, SlaveContext};
+
+use crate::sunspec::{self, Model};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// How long connecting and each request wait for the device unless it says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+type Request<'a, T> = Pin<Box<dyn Future<Output = std::io::Result<T>> + Send + 'a>>;
+
+/// A device and how to talk to it.
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub address: SocketAddr,
+    pub unit_id: u8,
+    pub retry: Retry,
+    pub limiter: Option<Limiter>,
+    // `address` and its secondary, when it has one.
+    pub failover: Option<Failover<SocketAddr>>,
+    // How long values read stay good for other reads; None to always ask the device.
+    pub cache_ttl: Option<Duration>,
+    // How long connecting and each request wait for an answer.
+    pub timeout: Duration,
+}
+
+impl Device {
+    /// Unit 1 at `address`, with the default retry policy.
+    pub fn new(address: SocketAddr) -> Device {
+        Device {
+            address,
+            unit_id: 1,
+            retry: Retry::default(),
+            limiter: None,
+            failover: None,
+            cache_ttl: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// A device at `address` as the CLI takes it: a host name or IP, with port 502 unless one
+    /// is given.
+    pub fn resolve(address: &str) -> Result<Device, Error> {
+        Ok(Device::new(edge_core::net::resolve(address, 502)?))
+    }
+
+    pub fn unit_id(self, unit_id: u8) -> Device {
+        Device { unit_id, ..self }
+    }
+
+    pub fn retry(self, retry: Retry) -> Device {
+        Device { retry, ..self }
+    }
+
+    /// Shares `limiter` with whatever else holds it, so several devices can be held to one
+    /// rate.
+    pub fn limiter(self, limiter: Option<Limiter>) -> Device {
+        Device { limiter, ..self }
+    }
+
+    pub fn cache_ttl(self, cache_ttl: Option<Duration>) -> Device {
+        Device { cache_ttl, ..self }
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Device {
+        Device { timeout, ..self }
+    }
+
+    /// Fails over to `secondary` while `address` can't be reached. Clones of the device share
+    /// which of the two is in use.
+    pub fn secondary(self, secondary: SocketAddr, failback: Failback) -> Device {
+        let failover = Failover::new(self.address, secondary).failback(failback);
+        Device {
+            failover: Some(failover),
+            ..self
+        }
+    }
+
+    /// The address the next call goes to.
+    pub fn endpoint(&self) -> SocketAddr {
+        match &self.failover {
+            Some(failover) => failover.active(),
+            None => self.address,
+        }
+    }
+
+    /// The address that answered the last call that got an answer.
+    pub fn served_by(&self) -> SocketAddr {
+        self.failover
+            .as_ref()
+            .and_then(Failover::served)
+            .unwrap_or(self.address)
+    }
+
+    /// Notes how a call to `endpoint` went, returning the address to try instead after a
+    /// connection failure or timeout.
+    fn outcome<T, E: Transient + fmt::Display>(
+        &self,
+        endpoint: &SocketAddr,
+        result: &Result<T, E>,
+    ) -> Option<SocketAddr> {
+        let failover = self.failover.as_ref()?;
+        match result {
+            Err(err) if err.is_transient() => failover.failed(endpoint, err),
+            _ => {
+                failover.succeeded(endpoint);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum)]
+pub enum RegisterKind {
+    Holding,
+    Input,
+}
+
+impl RegisterKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            RegisterKind::Holding => "holding",
+            RegisterKind::Input => "input",
+        }
+    }
+}
+
+/// Registers to read in one request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Read {
+    pub kind: RegisterKind,
+    pub register: u16,
+    pub count: u16,
+}
+
+impl Read {
+    /// The single register at `register`.
+    pub fn new(kind: RegisterKind, register: u16) -> Read {
+        Read {
+            kind,
+            register,
+            count: 1,
+        }
+    }
+
+    pub fn count(self, count: u16) -> Read {
+        Read { count, ..self }
+    }
+}
+
+pub async fn read_registers(device: &Device, read: &Read) -> Result<Vec<u16>, Error> {
+    let fetch = || {
+        device
+            .retry
+            .run("Reading registers", || read_once(device, read))
+    };
+    match device.cache_ttl {
+        Some(ttl) => {
+            cache()
+                .get((device.address, device.unit_id, *read), ttl, fetch)
+                .await
+        }
+        None => fetch().await,
+    }
+}
+
+/// Writes one holding register.
+pub async fn write_register(device: &Device, register: u16, value: u16) -> Result<(), Error> {
+    let result = device
+        .retry
+        .run("Writing the register", || {
+            write_once(device, register, value)
+        })
+        .await;
+    // Even a failed write may have reached the device.
+    cache().invalidate(|(address, unit_id, read)| {
+        *address == device.address
+            && *unit_id == device.unit_id
+            && read.kind == RegisterKind::Holding
+            && (read.register..read.register.saturating_add(read.count)).contains(&register)
+    });
+    result
+}
+
+/// Registers read by device, unit and request.
+fn cache() -> &'static Cache<(SocketAddr, u8, Read), Vec<u16>> {
+    static CACHE: OnceLock<Cache<(SocketAddr, u8, Read), Vec<u16>>> = OnceLock::new();
+    CACHE.get_or_init(Cache::new)
+}
+
+/// Walks the SunSpec model chain, from `base_address` or wherever the marker is found.
+pub async fn read_sunspec(device: &Device, base_address: Option<u16>) -> Result<Vec<Model>, Error> {
+    let span = telemetry::span("modbus.sunspec")
+        .attribute("server.address", device.endpoint().to_string())
+        .attribute("modbus.unit_id", device.unit_id);
+    // The walk has a connection of its own; a failover takes effect on the next attempt.
+    let models = device
+        .retry
+        .run("Reading SunSpec models", || async {
+            let endpoint = device.endpoint();
+            let models = sunspec::read_models(
+                &endpoint,
+                device.unit_id,
+                base_address,
+                device.limiter.as_ref(),
+            )
+            .await;
+            device.outcome(&endpoint, &models);
+            models
+        })
+        .await;
+    telemetry::finish(
+        span,
+        "modbus.requests",
+        &[("operation", "sunspec")],
+        &models,
+    );
+    models
+}
+
+/// Whether `err` is the device answering with an exception rather than not answering at all;
+/// tokio-modbus reports those as `ErrorKind::Other`.
+pub fn is_exception(err: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<std::io::Error>(),
+        Some(io) if io.kind() == std::io::ErrorKind::Other
+    )
+}
+
+async fn read_once(device: &Device, read: &Read) -> Result<Vec<u16>, Error> {
+    // Waiting for the limiter isn't part of the request's time.
+    rate::acquire(device.limiter.as_ref()).await;
+    let span = telemetry::span("modbus.read")
+        .attribute("server.address", device.endpoint().to_string())
+        .attribute("modbus.unit_id", device.unit_id)
+        .attribute("modbus.kind", read.kind.name())
+        .attribute("modbus.register", read.register)
+        .attribute("modbus.count", read.count);
+    let read = *read;
+    let result = request(&span, device, |context| {
+        Box::pin(async move {
+            match read.kind {
+                RegisterKind::Holding => {
+                    context
+                        .read_holding_registers(read.register, read.count)
+                        .await
+                }
+                RegisterKind::Input => {
+                    context
+                        .read_input_registers(read.register, read.count)
+                        .await
+                }
+            }
+        })
+    })
+    .await
+    .map_err(Error::from);
+    telemetry::finish(span, "modbus.requests", &[("operation", "read")], &result);
+    result
+}
+
+async fn write_once(device: &Device, register: u16, value: u16) -> Result<(), Error> {
+    rate::acquire(device.limiter.as_ref()).await;
+    let span = telemetry::span("modbus.write")
+        .attribute("server.address", device.endpoint().to_string())
+        .attribute("modbus.unit_id", device.unit_id)
+        .attribute("modbus.register", register);
+    let result = request(&span, device, |context| {
+        Box::pin(context.write_single_register(register, value))
+    })
+    .await
+    .map_err(Error::from);
+    telemetry::finish(span, "modbus.requests", &[("operation", "write")], &result);
+    result
+}
+
+/// Runs `request` on the pooled connection to `device`, or a new one when a reused connection
+/// fails because the device closed it meanwhile. A device with a secondary has one go at the
+/// other address when the one in use can't be reached or doesn't answer in time. A connection
+/// whose request timed out is closed, as its answer may still come.
+async fn request<T>(
+    span: &Span,
+    device: &Device,
+    request: impl for<'a> Fn(&'a mut Context) -> Request<'a, T>,
+) -> std::io::Result<T> {
+    static POOL: OnceLock<Pool<SocketAddr, Context>> = OnceLock::new();
+    let pool = POOL.get_or_init(|| Pool::new(1));
+    let mut endpoint = device.endpoint();
+    let mut failed_over = false;
+    loop {
+        let connecting = || connect(span, &endpoint, device.timeout);
+        let result = match pool.get(endpoint, connecting).await {
+            Ok(mut context) => {
+                context.set_slave(Slave(device.unit_id));
+                let result = tokio::time::timeout(device.timeout, request(&mut context))
+                    .await
+                    .unwrap_or_else(|_| Err(timed_out(&endpoint, device.timeout)));
+                match &result {
+                    Ok(_) => context.release(),
+                    Err(err) if is_exception(err) => context.release(),
+                    Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {}
+                    Err(err) if context.reused() => {
+                        log::debug!("Reconnecting to {endpoint}: {err}");
+                        continue;
+                    }
+                    Err(_) => {}
+                }
+                result
+            }
+            Err(err) => Err(err),
+        };
+        match device.outcome(&endpoint, &result) {
+            Some(other) if !failed_over => {
+                endpoint = other;
+                failed_over = true;
+            }
+            _ => return result,
+        }
+    }
+}
+
+async fn connect(
+    parent: &Span,
+    socket_addr: &SocketAddr,
+    timeout: Duration,
+) -> std::io::Result<tokio_modbus::client::Context> {
+    let span = parent.child("modbus.connect");
+    let result = tokio::time::timeout(timeout, tokio_modbus::client::tcp::connect(*socket_addr))
+        .await
+        .unwrap_or_else(|_| Err(timed_out(socket_addr, timeout)));
+    telemetry::finish(span, "modbus.connects", &[], &result);
+    if result.is_ok() {
+        stats::connected(&socket_addr.to_string());
+    }
+    result
+}
+
+fn timed_out(endpoint: &SocketAddr, timeout: Duration) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!(
+            "{endpoint} didn't answer within {}",
+            humantime::format_duration(timeout)
+        ),
+    )
+}
@@ -0,0 +1,287 @@
+//! Reader for libpcap and pcapng capture files, down to TCP segments.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub struct Packet<'a> {
+    pub timestamp: Duration,
+    pub link_type: u32,
+    pub data: &'a [u8],
+}
+
+pub struct Segment<'a> {
+    pub timestamp: Duration,
+    pub source: (IpAddr, u16),
+    pub destination: (IpAddr, u16),
+    pub sequence: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub payload: &'a [u8],
+}
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Splits a capture file into packets, detecting the format from the magic number.
+pub fn packets(file: &[u8]) -> Result<Vec<Packet<'_>>, Box<dyn std::error::Error>> {
+    if file.len() < 4 {
+        return Err("file too short to be a capture".into());
+    }
+    match &file[..4] {
+        [0x0a, 0x0d, 0x0d, 0x0a] => pcapng_packets(file),
+        _ => pcap_packets(file),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Endian(bool);
+
+impl Endian {
+    fn u16(&self, data: &[u8]) -> u16 {
+        let bytes = [data[0], data[1]];
+        if self.0 {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, data: &[u8]) -> u32 {
+        let bytes = [data[0], data[1], data[2], data[3]];
+        if self.0 {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+fn pcap_packets(file: &[u8]) -> Result<Vec<Packet<'_>>, Box<dyn std::error::Error>> {
+    let (endian, nanos) = match &file[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (Endian(false), false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (Endian(true), false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (Endian(false), true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (Endian(true), true),
+        _ => return Err("not a pcap or pcapng file".into()),
+    };
+    if file.len() < 24 {
+        return Err("truncated pcap header".into());
+    }
+    let link_type = endian.u32(&file[20..24]) & 0x0fff_ffff;
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset + 16 <= file.len() {
+        let header = &file[offset..offset + 16];
+        let seconds = endian.u32(&header[0..4]) as u64;
+        let fraction = endian.u32(&header[4..8]);
+        let captured = endian.u32(&header[8..12]) as usize;
+        offset += 16;
+        if offset + captured > file.len() {
+            log::warn!("Capture ends in the middle of a packet");
+            break;
+        }
+
+        let fraction = if nanos {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+        packets.push(Packet {
+            timestamp: Duration::from_secs(seconds) + fraction,
+            link_type,
+            data: &file[offset..offset + captured],
+        });
+        offset += captured;
+    }
+    Ok(packets)
+}
+
+fn pcapng_packets(file: &[u8]) -> Result<Vec<Packet<'_>>, Box<dyn std::error::Error>> {
+    // (link type, timestamp units per second) for each interface in the current section.
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut endian = Endian(false);
+    let mut packets = Vec::new();
+    let mut offset = 0;
+
+    while offset + 12 <= file.len() {
+        let block_type = &file[offset..offset + 4];
+        if block_type == [0x0a, 0x0d, 0x0d, 0x0a] {
+            // Section header: the byte order magic decides how to read everything after it.
+            endian = Endian(file[offset + 8..offset + 12] == [0x1a, 0x2b, 0x3c, 0x4d]);
+            interfaces.clear();
+        }
+        let block_type = endian.u32(block_type);
+        let length = endian.u32(&file[offset + 4..offset + 8]) as usize;
+        if length < 12 || offset + length > file.len() {
+            log::warn!("Capture ends in the middle of a block");
+            break;
+        }
+        let body = &file[offset + 8..offset + length - 4];
+
+        match block_type {
+            // Interface description
+            0x0000_0001 if body.len() >= 8 => {
+                let link_type = endian.u16(&body[0..2]) as u32;
+                interfaces.push((link_type, interface_resolution(endian, &body[8..])));
+            }
+            // Enhanced packet
+            0x0000_0006 if body.len() >= 20 => {
+                let interface = endian.u32(&body[0..4]) as usize;
+                let timestamp =
+                    ((endian.u32(&body[4..8]) as u64) << 32) | endian.u32(&body[8..12]) as u64;
+                let captured = endian.u32(&body[12..16]) as usize;
+                let (link_type, resolution) = match interfaces.get(interface) {
+                    Some(interface) => *interface,
+                    None => {
+                        log::warn!("Packet for undeclared interface {interface}");
+                        offset += length;
+                        continue;
+                    }
+                };
+                if 20 + captured <= body.len() {
+                    packets.push(Packet {
+                        timestamp: Duration::from_secs(timestamp / resolution)
+                            + Duration::from_nanos(
+                                (timestamp % resolution) * 1_000_000_000 / resolution,
+                            ),
+                        link_type,
+                        data: &body[20..20 + captured],
+                    });
+                }
+            }
+            // Simple packet, no timestamp.
+            0x0000_0003 if body.len() >= 4 => {
+                if let Some((link_type, _)) = interfaces.first() {
+                    packets.push(Packet {
+                        timestamp: Duration::ZERO,
+                        link_type: *link_type,
+                        data: &body[4..],
+                    });
+                }
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+/// Reads `if_tsresol` from the interface options, defaulting to microseconds.
+fn interface_resolution(endian: Endian, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = endian.u16(&options[0..2]);
+        let length = endian.u16(&options[2..4]) as usize;
+        if code == 0 || options.len() < 4 + length {
+            break;
+        }
+        if code == 9 && length >= 1 {
+            let value = options[4];
+            let exponent = (value & 0x7f) as u32;
+            return if value & 0x80 != 0 {
+                2u64.saturating_pow(exponent)
+            } else {
+                10u64.saturating_pow(exponent)
+            };
+        }
+        options = options.get(4 + ((length + 3) & !3)..).unwrap_or(&[]);
+    }
+    1_000_000
+}
+
+/// Decodes the link, network and transport headers, returning the TCP segment if there is one.
+pub fn tcp_segment<'a>(packet: &Packet<'a>) -> Option<Segment<'a>> {
+    let data = packet.data;
+    let (ethertype, ip) = match packet.link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+            // Skip 802.1Q / QinQ tags.
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            (ethertype, data.get(offset + 2..)?)
+        }
+        LINKTYPE_LINUX_SLL => (
+            u16::from_be_bytes([*data.get(14)?, *data.get(15)?]),
+            data.get(16..)?,
+        ),
+        LINKTYPE_LINUX_SLL2 => (
+            u16::from_be_bytes([*data.first()?, *data.get(1)?]),
+            data.get(20..)?,
+        ),
+        LINKTYPE_NULL => {
+            // Host byte order address family; 2 is AF_INET everywhere, IPv6 varies by OS.
+            let family = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+            let family = if family > 0xffff {
+                family.swap_bytes()
+            } else {
+                family
+            };
+            (if family == 2 { 0x0800 } else { 0x86dd }, data.get(4..)?)
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => match data.first()? >> 4 {
+            4 => (0x0800, data),
+            6 => (0x86dd, data),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (source, destination, tcp) = match ethertype {
+        0x0800 => {
+            let header_length = ((*ip.first()? & 0x0f) as usize) * 4;
+            if *ip.get(9)? != 6 {
+                return None;
+            }
+            // Only the first fragment carries the TCP header; Modbus never needs fragmenting.
+            let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]);
+            if fragment & 0x1fff != 0 {
+                return None;
+            }
+            let total = (u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize).min(ip.len());
+            let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(source),
+                IpAddr::from(destination),
+                ip.get(header_length..total)?,
+            )
+        }
+        0x86dd => {
+            // Extension headers are rare enough on plant networks to not bother with.
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let payload = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+            let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(source),
+                IpAddr::from(destination),
+                ip.get(40..(40 + payload).min(ip.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let data_offset = ((*tcp.get(12)? >> 4) as usize) * 4;
+    let flags = *tcp.get(13)?;
+    Some(Segment {
+        timestamp: packet.timestamp,
+        source: (source, u16::from_be_bytes([tcp[0], tcp[1]])),
+        destination: (destination, u16::from_be_bytes([tcp[2], tcp[3]])),
+        sequence: u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?),
+        syn: flags & 0x02 != 0,
+        fin: flags & 0x01 != 0,
+        rst: flags & 0x04 != 0,
+        payload: tcp.get(data_offset..)?,
+    })
+}
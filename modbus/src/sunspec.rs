@@ -0,0 +1,568 @@
+use crate::client::Error;
+use edge_core::codec::{self, Order, Scale, Type};
+use edge_core::exit::{self, Code};
+use edge_core::output::Record;
+use edge_core::rate::{self, Limiter};
+use std::net::SocketAddr;
+use tokio_modbus::client::{Context, Reader};
+use tokio_modbus::slave::Slave;
+
+/// "SunS" marker that identifies the start of a SunSpec register map.
+const SUNS_MARKER: [u16; 2] = [0x5375, 0x6e53];
+/// Base addresses the specification allows, in the order devices most commonly use them.
+const BASE_ADDRESSES: [u16; 3] = [40000, 0, 50000];
+const END_MODEL_ID: u16 = 0xffff;
+/// Maximum number of registers in a single read holding registers request.
+const MAX_READ: u16 = 125;
+
+#[derive(Clone, Copy)]
+enum PointKind {
+    Uint16,
+    Int16,
+    Acc32,
+    Float32,
+    Enum16,
+    Bitfield16,
+    Bitfield32,
+    Str(u16),
+}
+
+impl PointKind {
+    fn registers(self) -> u16 {
+        match self {
+            Uint16 | Int16 | Enum16 | Bitfield16 => 1,
+            Acc32 | Float32 | Bitfield32 => 2,
+            Str(len) => len,
+        }
+    }
+}
+
+/// A single point of a model, at `offset` registers from the start of the model body.
+struct Point {
+    name: &'static str,
+    label: &'static str,
+    offset: u16,
+    kind: PointKind,
+    scale_factor: Option<u16>,
+    units: &'static str,
+}
+
+const fn point(
+    name: &'static str,
+    label: &'static str,
+    offset: u16,
+    kind: PointKind,
+    scale_factor: Option<u16>,
+    units: &'static str,
+) -> Point {
+    Point {
+        name,
+        label,
+        offset,
+        kind,
+        scale_factor,
+        units,
+    }
+}
+
+use PointKind::*;
+
+const COMMON: &[Point] = &[
+    point("Mn", "Manufacturer", 0, Str(16), None, ""),
+    point("Md", "Model", 16, Str(16), None, ""),
+    point("Opt", "Options", 32, Str(8), None, ""),
+    point("Vr", "Version", 40, Str(8), None, ""),
+    point("SN", "Serial number", 48, Str(16), None, ""),
+    point("DA", "Device address", 64, Uint16, None, ""),
+];
+
+const INVERTER: &[Point] = &[
+    point("A", "AC current", 0, Uint16, Some(4), "A"),
+    point("AphA", "Phase A current", 1, Uint16, Some(4), "A"),
+    point("AphB", "Phase B current", 2, Uint16, Some(4), "A"),
+    point("AphC", "Phase C current", 3, Uint16, Some(4), "A"),
+    point("PPVphAB", "Phase voltage AB", 5, Uint16, Some(11), "V"),
+    point("PPVphBC", "Phase voltage BC", 6, Uint16, Some(11), "V"),
+    point("PPVphCA", "Phase voltage CA", 7, Uint16, Some(11), "V"),
+    point("PhVphA", "Phase voltage AN", 8, Uint16, Some(11), "V"),
+    point("PhVphB", "Phase voltage BN", 9, Uint16, Some(11), "V"),
+    point("PhVphC", "Phase voltage CN", 10, Uint16, Some(11), "V"),
+    point("W", "AC power", 12, Int16, Some(13), "W"),
+    point("Hz", "Line frequency", 14, Uint16, Some(15), "Hz"),
+    point("VA", "AC apparent power", 16, Int16, Some(17), "VA"),
+    point("VAr", "AC reactive power", 18, Int16, Some(19), "var"),
+    point("PF", "AC power factor", 20, Int16, Some(21), "%"),
+    point("WH", "AC energy", 22, Acc32, Some(24), "Wh"),
+    point("DCA", "DC current", 25, Uint16, Some(26), "A"),
+    point("DCV", "DC voltage", 27, Uint16, Some(28), "V"),
+    point("DCW", "DC power", 29, Int16, Some(30), "W"),
+    point("TmpCab", "Cabinet temperature", 31, Int16, Some(35), "C"),
+    point("TmpSnk", "Heat sink temperature", 32, Int16, Some(35), "C"),
+    point(
+        "TmpTrns",
+        "Transformer temperature",
+        33,
+        Int16,
+        Some(35),
+        "C",
+    ),
+    point("TmpOt", "Other temperature", 34, Int16, Some(35), "C"),
+    point("St", "Operating state", 36, Enum16, None, ""),
+    point("StVnd", "Vendor operating state", 37, Enum16, None, ""),
+    point("Evt1", "Event flags", 38, Bitfield32, None, ""),
+    point("Evt2", "Event flags (reserved)", 40, Bitfield32, None, ""),
+];
+
+const INVERTER_FLOAT: &[Point] = &[
+    point("A", "AC current", 0, Float32, None, "A"),
+    point("AphA", "Phase A current", 2, Float32, None, "A"),
+    point("AphB", "Phase B current", 4, Float32, None, "A"),
+    point("AphC", "Phase C current", 6, Float32, None, "A"),
+    point("PPVphAB", "Phase voltage AB", 8, Float32, None, "V"),
+    point("PPVphBC", "Phase voltage BC", 10, Float32, None, "V"),
+    point("PPVphCA", "Phase voltage CA", 12, Float32, None, "V"),
+    point("PhVphA", "Phase voltage AN", 14, Float32, None, "V"),
+    point("PhVphB", "Phase voltage BN", 16, Float32, None, "V"),
+    point("PhVphC", "Phase voltage CN", 18, Float32, None, "V"),
+    point("W", "AC power", 20, Float32, None, "W"),
+    point("Hz", "Line frequency", 22, Float32, None, "Hz"),
+    point("VA", "AC apparent power", 24, Float32, None, "VA"),
+    point("VAr", "AC reactive power", 26, Float32, None, "var"),
+    point("PF", "AC power factor", 28, Float32, None, "%"),
+    point("WH", "AC energy", 30, Float32, None, "Wh"),
+    point("DCA", "DC current", 32, Float32, None, "A"),
+    point("DCV", "DC voltage", 34, Float32, None, "V"),
+    point("DCW", "DC power", 36, Float32, None, "W"),
+    point("TmpCab", "Cabinet temperature", 38, Float32, None, "C"),
+    point("TmpSnk", "Heat sink temperature", 40, Float32, None, "C"),
+    point("TmpTrns", "Transformer temperature", 42, Float32, None, "C"),
+    point("TmpOt", "Other temperature", 44, Float32, None, "C"),
+    point("St", "Operating state", 46, Enum16, None, ""),
+    point("StVnd", "Vendor operating state", 47, Enum16, None, ""),
+    point("Evt1", "Event flags", 48, Bitfield32, None, ""),
+];
+
+const METER: &[Point] = &[
+    point("A", "Total AC current", 0, Int16, Some(4), "A"),
+    point("AphA", "Phase A current", 1, Int16, Some(4), "A"),
+    point("AphB", "Phase B current", 2, Int16, Some(4), "A"),
+    point("AphC", "Phase C current", 3, Int16, Some(4), "A"),
+    point("PhV", "Line to neutral voltage", 5, Int16, Some(13), "V"),
+    point("PhVphA", "Phase voltage AN", 6, Int16, Some(13), "V"),
+    point("PhVphB", "Phase voltage BN", 7, Int16, Some(13), "V"),
+    point("PhVphC", "Phase voltage CN", 8, Int16, Some(13), "V"),
+    point("PPV", "Line to line voltage", 9, Int16, Some(13), "V"),
+    point("PPVphAB", "Phase voltage AB", 10, Int16, Some(13), "V"),
+    point("PPVphBC", "Phase voltage BC", 11, Int16, Some(13), "V"),
+    point("PPVphCA", "Phase voltage CA", 12, Int16, Some(13), "V"),
+    point("Hz", "Frequency", 14, Int16, Some(15), "Hz"),
+    point("W", "Total real power", 16, Int16, Some(20), "W"),
+    point("WphA", "Real power phase A", 17, Int16, Some(20), "W"),
+    point("WphB", "Real power phase B", 18, Int16, Some(20), "W"),
+    point("WphC", "Real power phase C", 19, Int16, Some(20), "W"),
+    point("VA", "Total apparent power", 21, Int16, Some(25), "VA"),
+    point("VAR", "Total reactive power", 26, Int16, Some(30), "var"),
+    point("PF", "Average power factor", 31, Int16, Some(35), "%"),
+    point(
+        "TotWhExp",
+        "Total real energy exported",
+        36,
+        Acc32,
+        Some(52),
+        "Wh",
+    ),
+    point(
+        "TotWhImp",
+        "Total real energy imported",
+        44,
+        Acc32,
+        Some(52),
+        "Wh",
+    ),
+    point(
+        "TotVAhExp",
+        "Total apparent energy exported",
+        53,
+        Acc32,
+        Some(69),
+        "VAh",
+    ),
+    point(
+        "TotVAhImp",
+        "Total apparent energy imported",
+        61,
+        Acc32,
+        Some(69),
+        "VAh",
+    ),
+    point("Evt", "Meter event flags", 103, Bitfield32, None, ""),
+];
+
+const NAMEPLATE: &[Point] = &[
+    point("DERTyp", "DER type", 0, Enum16, None, ""),
+    point("WRtg", "Continuous power rating", 1, Uint16, Some(2), "W"),
+    point("VARtg", "Apparent power rating", 3, Uint16, Some(4), "VA"),
+    point("ARtg", "Current rating", 10, Uint16, Some(11), "A"),
+    point(
+        "PFRtgQ1",
+        "Minimum power factor Q1",
+        12,
+        Int16,
+        Some(16),
+        "cos()",
+    ),
+    point("WHRtg", "Energy storage rating", 17, Uint16, Some(18), "Wh"),
+];
+
+const SETTINGS: &[Point] = &[
+    point("WMax", "Maximum power output", 0, Uint16, Some(20), "W"),
+    point("VRef", "Voltage at PCC", 1, Uint16, Some(21), "V"),
+    point("VRefOfs", "Voltage offset", 2, Int16, Some(22), "V"),
+    point("VMax", "Maximum voltage", 3, Uint16, Some(23), "V"),
+    point("VMin", "Minimum voltage", 4, Uint16, Some(23), "V"),
+    point("VAMax", "Maximum apparent power", 5, Uint16, Some(24), "VA"),
+];
+
+const STATUS: &[Point] = &[
+    point("PVConn", "PV connection status", 0, Bitfield16, None, ""),
+    point(
+        "StorConn",
+        "Storage connection status",
+        1,
+        Bitfield16,
+        None,
+        "",
+    ),
+    point("ECPConn", "ECP connection status", 2, Bitfield16, None, ""),
+    point("ActWh", "AC lifetime active energy", 3, Acc32, None, "Wh"),
+];
+
+const CONTROLS: &[Point] = &[
+    point("Conn_WinTms", "Connect window", 0, Uint16, None, "s"),
+    point("Conn_RvrtTms", "Connect timeout", 1, Uint16, None, "s"),
+    point("Conn", "Connection control", 2, Enum16, None, ""),
+    point(
+        "WMaxLimPct",
+        "Power output limit",
+        3,
+        Uint16,
+        Some(21),
+        "% WMax",
+    ),
+    point("WMaxLim_Ena", "Power limit enabled", 7, Enum16, None, ""),
+    point(
+        "OutPFSet",
+        "Power factor setpoint",
+        8,
+        Int16,
+        Some(22),
+        "cos()",
+    ),
+    point("OutPFSet_Ena", "Power factor enabled", 12, Enum16, None, ""),
+];
+
+struct ModelDefinition {
+    name: &'static str,
+    points: &'static [Point],
+}
+
+fn model_definition(id: u16) -> Option<ModelDefinition> {
+    let (name, points): (&str, &[Point]) = match id {
+        1 => ("Common", COMMON),
+        101 => ("Single phase inverter", INVERTER),
+        102 => ("Split phase inverter", INVERTER),
+        103 => ("Three phase inverter", INVERTER),
+        111 => ("Single phase inverter (float)", INVERTER_FLOAT),
+        112 => ("Split phase inverter (float)", INVERTER_FLOAT),
+        113 => ("Three phase inverter (float)", INVERTER_FLOAT),
+        120 => ("Nameplate", NAMEPLATE),
+        121 => ("Basic settings", SETTINGS),
+        122 => ("Measurements status", STATUS),
+        123 => ("Immediate controls", CONTROLS),
+        201 => ("Single phase meter", METER),
+        202 => ("Split phase meter", METER),
+        203 => ("Wye-connect three phase meter", METER),
+        204 => ("Delta-connect three phase meter", METER),
+        // Known models without point definitions here are still listed by name.
+        10 => ("Communication interface header", &[]),
+        11 => ("Ethernet link layer", &[]),
+        12 => ("IPv4", &[]),
+        124 => ("Storage", &[]),
+        126 => ("Static volt-var", &[]),
+        160 => ("Multiple MPPT inverter extension", &[]),
+        _ => return None,
+    };
+    Some(ModelDefinition { name, points })
+}
+
+/// A model as found while walking the chain, with its raw register body.
+pub struct Model {
+    pub id: u16,
+    pub address: u16,
+    pub body: Vec<u16>,
+}
+
+pub(crate) async fn read_models(
+    socket_addr: &SocketAddr,
+    unit_id: u8,
+    base_address: Option<u16>,
+    limiter: Option<&Limiter>,
+) -> Result<Vec<Model>, Error> {
+    let mut context =
+        tokio_modbus::client::tcp::connect_slave(*socket_addr, Slave(unit_id)).await?;
+
+    let base = match base_address {
+        Some(base) => {
+            if !has_marker(&mut context, base, limiter).await {
+                let message = format!("No SunSpec marker at address {base}");
+                return Err(exit::Error::new(Code::Protocol, message).into());
+            }
+            base
+        }
+        None => discover_base(&mut context, limiter).await?,
+    };
+    log::info!("Found SunSpec marker at address {base}");
+
+    let past_the_end = "Model chain runs past the end of the register space";
+    let mut models = Vec::new();
+    let mut address = base.checked_add(2).ok_or(past_the_end)?;
+    loop {
+        rate::acquire(limiter).await;
+        let header = context.read_holding_registers(address, 2).await?;
+        let (id, length) = (header[0], header[1]);
+        if id == END_MODEL_ID {
+            break;
+        }
+
+        let start = address.checked_add(2).ok_or(past_the_end)?;
+        let body = read_block(&mut context, start, length, limiter).await?;
+        log::debug!("Model {id} at {address} with {length} registers");
+        models.push(Model { id, address, body });
+
+        address = address
+            .checked_add(2)
+            .and_then(|address| address.checked_add(length))
+            .ok_or(past_the_end)?;
+    }
+
+    Ok(models)
+}
+
+async fn discover_base(context: &mut Context, limiter: Option<&Limiter>) -> Result<u16, Error> {
+    for base in BASE_ADDRESSES {
+        if has_marker(context, base, limiter).await {
+            return Ok(base);
+        }
+    }
+    let message = format!("No SunSpec marker found at any of {:?}", BASE_ADDRESSES);
+    Err(exit::Error::new(Code::Protocol, message).into())
+}
+
+async fn has_marker(context: &mut Context, address: u16, limiter: Option<&Limiter>) -> bool {
+    rate::acquire(limiter).await;
+    match context.read_holding_registers(address, 2).await {
+        Ok(registers) => registers == SUNS_MARKER,
+        Err(err) => {
+            // Devices answer illegal address for base candidates they don't use.
+            log::debug!("No marker at {address}: {err}");
+            false
+        }
+    }
+}
+
+async fn read_block(
+    context: &mut Context,
+    address: u16,
+    length: u16,
+    limiter: Option<&Limiter>,
+) -> Result<Vec<u16>, Error> {
+    let mut registers = Vec::with_capacity(length as usize);
+    let mut offset = 0;
+    while offset < length {
+        let count = (length - offset).min(MAX_READ);
+        rate::acquire(limiter).await;
+        let start = address
+            .checked_add(offset)
+            .ok_or("Model runs past the end of the register space")?;
+        let mut chunk = context.read_holding_registers(start, count).await?;
+        registers.append(&mut chunk);
+        offset += count;
+    }
+    Ok(registers)
+}
+
+pub(crate) fn print_model(model: &Model) {
+    let definition = model_definition(model.id);
+    let name = definition
+        .as_ref()
+        .map(|d| d.name)
+        .unwrap_or("Unknown model");
+    println!(
+        "Model {} ({}) @ {}, {} registers",
+        model.id,
+        name,
+        model.address,
+        model.body.len()
+    );
+
+    let points = definition.map(|d| d.points).unwrap_or(&[]);
+    for point in points {
+        let value = match decode_point(point, &model.body) {
+            Some(value) => value,
+            // Shorter model than defined, e.g. an older revision.
+            None => continue,
+        };
+        println!("  {:<12} {:<32} {}", point.name, point.label, value);
+    }
+}
+
+/// The model's decoded points, as `modbus sunspec` prints them with `--output json`.
+pub fn model_record(model: &Model) -> Record {
+    let definition = model_definition(model.id);
+    let points: Vec<Record> = definition
+        .as_ref()
+        .map(|d| d.points)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|point| {
+            let value = decode_point(point, &model.body)?;
+            Some(
+                Record::new()
+                    .field("name", point.name)
+                    .field("label", point.label)
+                    .field("value", value),
+            )
+        })
+        .collect();
+    Record::new()
+        .field("id", model.id)
+        .field("name", definition.map(|d| d.name))
+        .field("address", model.address)
+        .field("length", model.body.len())
+        .field("points", points)
+}
+
+/// The point's value as printed, `n/a` for the values SunSpec marks as not implemented; None
+/// when the body is too short to hold the point at all.
+fn decode_point(point: &Point, body: &[u16]) -> Option<String> {
+    let offset = point.offset as usize;
+    let words = body.get(offset..offset + point.kind.registers() as usize)?;
+    let register = |i: usize| words.get(i).copied();
+    // SunSpec values are big-endian, high word first.
+    let decode = |ty: Type| codec::decode(words, ty, Order::Abcd).ok();
+
+    let raw: Option<f64> = match point.kind {
+        Str(_) => return Some(decode_string(words)),
+        Uint16 => register(0).filter(|v| *v != 0xffff).map(f64::from),
+        Int16 => register(0).filter(|v| *v != 0x8000).and(decode(Type::I16)),
+        Enum16 => {
+            let value = register(0)?;
+            return Some(if value == 0xffff {
+                "n/a".to_string()
+            } else {
+                format!("{value}")
+            });
+        }
+        Bitfield16 => {
+            let value = register(0)?;
+            return Some(if value == 0xffff {
+                "n/a".to_string()
+            } else {
+                format!("{value:#06x}")
+            });
+        }
+        Acc32 | Bitfield32 => {
+            let value = codec::raw(words, 2, Order::Abcd).ok()?;
+            match point.kind {
+                Bitfield32 => {
+                    return Some(if value == 0xffff_ffff {
+                        "n/a".to_string()
+                    } else {
+                        format!("{value:#010x}")
+                    });
+                }
+                // Accumulators use 0 as their "not implemented" value.
+                Acc32 if value == 0 => None,
+                _ => Some(value as f64),
+            }
+        }
+        Float32 => {
+            let value = decode(Type::F32)?;
+            (!value.is_nan()).then_some(value)
+        }
+    };
+
+    let mut value = match raw {
+        Some(value) => value,
+        None => return Some("n/a".to_string()),
+    };
+
+    if let Some(sf_offset) = point.scale_factor {
+        match body.get(sf_offset as usize) {
+            Some(sf) => match Scale::power_of_ten(*sf as i16) {
+                Some(scale) => value = scale.apply(value),
+                None => return Some("n/a".to_string()),
+            },
+            None => return Some("n/a".to_string()),
+        }
+    }
+
+    if point.units.is_empty() {
+        Some(format!("{value}"))
+    } else {
+        Some(format!("{value} {}", point.units))
+    }
+}
+
+fn decode_string(registers: &[u16]) -> String {
+    let bytes: Vec<u8> = registers.iter().flat_map(|r| r.to_be_bytes()).collect();
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(kind: PointKind, scale_factor: Option<u16>, body: &[u16]) -> Option<String> {
+        decode_point(&point("P", "Point", 0, kind, scale_factor, "W"), body)
+    }
+
+    #[test]
+    fn decodes_points_with_their_scale_factor() {
+        assert_eq!(decode(Uint16, None, &[1234]).unwrap(), "1234 W");
+        assert_eq!(
+            decode(Uint16, Some(1), &[1234, (-1i16) as u16]).unwrap(),
+            "123.4 W"
+        );
+        assert_eq!(decode(Int16, None, &[(-5i16) as u16]).unwrap(), "-5 W");
+        assert_eq!(decode(Acc32, None, &[1, 2]).unwrap(), "65538 W");
+        let [high, low] = [(1.5f32.to_bits() >> 16) as u16, 1.5f32.to_bits() as u16];
+        assert_eq!(decode(Float32, None, &[high, low]).unwrap(), "1.5 W");
+        assert_eq!(decode(Enum16, None, &[3]).unwrap(), "3");
+        assert_eq!(decode(Bitfield16, None, &[0x12]).unwrap(), "0x0012");
+        assert_eq!(decode(Bitfield32, None, &[0x1, 0x2]).unwrap(), "0x00010002");
+        assert_eq!(decode(Str(3), None, &[0x4142, 0x4300, 0]).unwrap(), "ABC");
+    }
+
+    #[test]
+    fn decodes_unimplemented_values_as_not_available() {
+        assert_eq!(decode(Uint16, None, &[0xffff]).unwrap(), "n/a");
+        assert_eq!(decode(Int16, None, &[0x8000]).unwrap(), "n/a");
+        assert_eq!(decode(Acc32, None, &[0, 0]).unwrap(), "n/a");
+        assert_eq!(decode(Float32, None, &[0x7fc0, 0]).unwrap(), "n/a");
+        assert_eq!(decode(Enum16, None, &[0xffff]).unwrap(), "n/a");
+        assert_eq!(decode(Bitfield32, None, &[0xffff, 0xffff]).unwrap(), "n/a");
+        // A scale factor that's not implemented or missing leaves the value unknown too.
+        assert_eq!(decode(Uint16, Some(1), &[10, 0x8000]).unwrap(), "n/a");
+        assert_eq!(decode(Uint16, Some(1), &[10]).unwrap(), "n/a");
+    }
+
+    #[test]
+    fn skips_points_past_the_end_of_a_shorter_model() {
+        assert_eq!(decode(Uint16, None, &[]), None);
+        assert_eq!(decode(Acc32, None, &[1]), None);
+        assert_eq!(decode(Float32, None, &[0x3fc0]), None);
+        assert_eq!(decode(Str(4), None, &[0x4142, 0x4300]), None);
+    }
+}
@@ -0,0 +1,248 @@
+//! The client API against an in-process device.
+
+use std::time::Duration;
+
+use edge_core::failover::Failback;
+use edge_core::retry::Retry;
+use modbus::client::{self, Device, Read, RegisterKind};
+use simulators::ModbusSimulator;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_and_writes_registers() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    simulator.set_input(7, &[1, 2, 3]);
+    let device = Device::new(simulator.address()).unit_id(4);
+
+    // Spawned to check the futures can move between threads, as in a gateway.
+    let task = tokio::spawn(async move {
+        client::write_register(&device, 40, 99).await?;
+        client::read_registers(&device, &Read::new(RegisterKind::Input, 7).count(3)).await
+    });
+
+    assert_eq!(task.await.unwrap().unwrap(), vec![1, 2, 3]);
+    assert_eq!(simulator.holding(40), 99);
+    assert!(simulator
+        .requests()
+        .iter()
+        .all(|request| request.unit_id == 4));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tells_exceptions_from_connection_failures() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    simulator.fail(5, 2);
+    let device = Device::new(simulator.address());
+
+    let err = client::read_registers(&device, &Read::new(RegisterKind::Holding, 5))
+        .await
+        .unwrap_err();
+
+    assert!(client::is_exception(&*err));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn calls_share_one_connection_per_device() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    simulator.set_holding(10, &[5]);
+
+    let reads: Vec<_> = (1..=8)
+        .map(|unit_id| {
+            let device = Device::new(simulator.address()).unit_id(unit_id);
+            tokio::spawn(async move {
+                client::read_registers(&device, &Read::new(RegisterKind::Holding, 10)).await
+            })
+        })
+        .collect();
+    for read in reads {
+        assert_eq!(read.await.unwrap().unwrap(), vec![5]);
+    }
+
+    assert_eq!(simulator.connections(), 1);
+    let mut units: Vec<u8> = simulator.requests().iter().map(|r| r.unit_id).collect();
+    units.sort();
+    assert_eq!(units, (1..=8).collect::<Vec<u8>>());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconnects_when_the_device_closed_the_connection() {
+    let first = ModbusSimulator::start().await.unwrap();
+    let address = first.address();
+    // No retries: reconnecting after a closed connection must not need one.
+    let device = Device::new(address).retry(Retry {
+        attempts: 1,
+        ..Retry::default()
+    });
+    let read = Read::new(RegisterKind::Holding, 3);
+    client::read_registers(&device, &read).await.unwrap();
+    drop(first);
+
+    let second = ModbusSimulator::start_on(address).await.unwrap();
+    second.set_holding(3, &[9]);
+
+    assert_eq!(
+        client::read_registers(&device, &read).await.unwrap(),
+        vec![9]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_over_to_the_secondary_and_back() {
+    let primary = ModbusSimulator::start().await.unwrap();
+    let address = primary.address();
+    drop(primary);
+    let secondary = ModbusSimulator::start().await.unwrap();
+    secondary.set_holding(3, &[2]);
+    // No retries: failing over must not need one.
+    let device = Device::new(address)
+        .retry(Retry {
+            attempts: 1,
+            ..Retry::default()
+        })
+        .secondary(
+            secondary.address(),
+            Failback::After(Duration::from_millis(300)),
+        );
+    let read = Read::new(RegisterKind::Holding, 3);
+
+    assert_eq!(
+        client::read_registers(&device, &read).await.unwrap(),
+        vec![2]
+    );
+    assert_eq!(device.served_by(), secondary.address());
+
+    // Back up, but only used again once the failback is due.
+    let primary = ModbusSimulator::start_on(address).await.unwrap();
+    primary.set_holding(3, &[1]);
+    assert_eq!(
+        client::read_registers(&device, &read).await.unwrap(),
+        vec![2]
+    );
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(
+        client::read_registers(&device, &read).await.unwrap(),
+        vec![1]
+    );
+    assert_eq!(device.served_by(), address);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up_on_a_device_that_stopped_answering() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    simulator.set_holding(3, &[1]);
+    let device = Device::new(simulator.address())
+        .retry(Retry {
+            attempts: 2,
+            delay: Duration::ZERO,
+            ..Retry::default()
+        })
+        .timeout(Duration::from_millis(200));
+    let read = Read::new(RegisterKind::Holding, 3);
+    client::read_registers(&device, &read).await.unwrap();
+
+    simulator.delay(Duration::from_secs(5));
+    let started = std::time::Instant::now();
+    let err = client::read_registers(&device, &read).await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(2), "{err}");
+    assert!(
+        err.to_string().contains("didn't answer within 200ms"),
+        "{err}"
+    );
+    // The retry gets a new connection rather than waiting behind the late answer.
+    assert_eq!(simulator.connections(), 2);
+
+    simulator.delay(Duration::ZERO);
+    assert_eq!(
+        client::read_registers(&device, &read).await.unwrap(),
+        vec![1]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_over_when_the_primary_stops_answering() {
+    let primary = ModbusSimulator::start().await.unwrap();
+    primary.delay(Duration::from_secs(5));
+    let secondary = ModbusSimulator::start().await.unwrap();
+    secondary.set_holding(3, &[2]);
+    let device = Device::new(primary.address())
+        .retry(Retry {
+            attempts: 1,
+            ..Retry::default()
+        })
+        .timeout(Duration::from_millis(200))
+        .secondary(
+            secondary.address(),
+            Failback::After(Duration::from_secs(60)),
+        );
+
+    assert_eq!(
+        client::read_registers(&device, &Read::new(RegisterKind::Holding, 3))
+            .await
+            .unwrap(),
+        vec![2]
+    );
+    assert_eq!(device.served_by(), secondary.address());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn walks_the_sunspec_model_chain() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    // SunS, a common model of 2 registers, a meter of 3, the end marker.
+    simulator.set_holding(
+        40000,
+        &[0x5375, 0x6e53, 1, 2, 10, 11, 203, 3, 20, 21, 22, 0xffff, 0],
+    );
+    let device = Device::new(simulator.address());
+
+    let models = client::read_sunspec(&device, None).await.unwrap();
+    let chain: Vec<(u16, u16, Vec<u16>)> = models
+        .into_iter()
+        .map(|model| (model.id, model.address, model.body))
+        .collect();
+    assert_eq!(
+        chain,
+        [(1, 40002, vec![10, 11]), (203, 40006, vec![20, 21, 22])]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_a_sunspec_chain_running_past_the_last_register() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    // An empty model whose successor's body would start past 65535.
+    simulator.set_holding(65530, &[0x5375, 0x6e53, 1, 0, 101, 0]);
+    let device = Device::new(simulator.address()).retry(Retry {
+        attempts: 1,
+        ..Retry::default()
+    });
+
+    let err = client::read_sunspec(&device, Some(65530))
+        .await
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("past the end"), "{err}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_repeated_reads_from_the_cache_until_a_write() {
+    let simulator = ModbusSimulator::start().await.unwrap();
+    simulator.set_holding(20, &[1, 2]);
+    let device = Device::new(simulator.address()).cache_ttl(Some(Duration::from_secs(60)));
+    let read = Read::new(RegisterKind::Holding, 20).count(2);
+
+    let reads: Vec<_> = (0..4)
+        .map(|_| {
+            let device = device.clone();
+            tokio::spawn(async move { client::read_registers(&device, &read).await })
+        })
+        .collect();
+    for read in reads {
+        assert_eq!(read.await.unwrap().unwrap(), vec![1, 2]);
+    }
+    assert_eq!(simulator.requests().len(), 1);
+
+    client::write_register(&device, 21, 7).await.unwrap();
+    assert_eq!(
+        client::read_registers(&device, &read).await.unwrap(),
+        vec![1, 7]
+    );
+    assert_eq!(simulator.requests().len(), 3);
+}
@@ -0,0 +1,717 @@
+//! The `modbus` binary against an in-process device.
+
+use simulators::{ModbusSimulator, NatsSimulator};
+use std::net::TcpListener;
+use std::process::Output;
+use std::time::Duration;
+use tokio::process::Command;
+
+async fn modbus(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_modbus"))
+        .args(args)
+        .env_clear()
+        .output()
+        .await
+        .expect("modbus runs")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_holding_registers() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215, 3]);
+    let address = device.address().to_string();
+
+    let output = modbus(&[
+        &address,
+        "--output",
+        "json",
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "-c",
+        "2",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        r#"{"register":100,"kind":"holding","unit_id":1,"values":[215,3]}"#
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_registers_rather_than_reads() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215, 3]);
+    let address = device.address().to_string();
+
+    let output = modbus(&[
+        &address,
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "--count",
+        "2",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), r#"["215", "3"]"#);
+    assert_eq!(device.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_a_register() {
+    let device = ModbusSimulator::start().await.unwrap();
+    let address = device.address().to_string();
+
+    let output = modbus(&[&address, "write-register", "-a", "40", "-v", "7", "-u", "3"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(device.holding(40), 7);
+    assert_eq!(device.requests()[0].unit_id, 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_writes_when_read_only_or_not_allowed() {
+    let device = ModbusSimulator::start().await.unwrap();
+    let address = device.address().to_string();
+
+    let output = modbus(&[
+        "--read-only",
+        &address,
+        "write-register",
+        "-a",
+        "40",
+        "-v",
+        "7",
+    ])
+    .await;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--read-only"));
+
+    let config = std::env::temp_dir().join(format!("modbus-writes-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        format!("[writes]\nmodbus = [\"{address} holding 40-41\"]\n"),
+    )
+    .unwrap();
+    let write = |register: &'static str| {
+        Command::new(env!("CARGO_BIN_EXE_modbus"))
+            .args([&address, "write-register", "-a", register, "-v", "7"])
+            .env_clear()
+            .env("EDGE_CONFIG", &config)
+            .output()
+    };
+    let refused = write("42").await.unwrap();
+    let allowed = write("41").await.unwrap();
+    std::fs::remove_file(&config).unwrap();
+
+    assert_eq!(refused.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("[writes] modbus list"));
+    assert_eq!(allowed.status.code(), Some(0));
+    assert_eq!(device.holding(40), 0);
+    assert_eq!(device.holding(41), 7);
+    assert_eq!(device.holding(42), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn audits_writes_to_a_file_and_nats() {
+    let device = ModbusSimulator::start().await.unwrap();
+    let server = NatsSimulator::start().await.unwrap();
+    let address = device.address().to_string();
+    let dir = std::env::temp_dir().join(format!("modbus-audit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (config, log) = (dir.join("config.toml"), dir.join("audit.jsonl"));
+    std::fs::write(
+        &config,
+        format!(
+            "[writes]\nmodbus = [\"{address} holding 40\"]\n\n[audit]\nfile = \"{}\"\n\
+             nats = \"{}\"\n",
+            log.display(),
+            server.url()
+        ),
+    )
+    .unwrap();
+    let write = |register: &'static str| {
+        Command::new(env!("CARGO_BIN_EXE_modbus"))
+            .args([&address, "write-register", "-a", register, "-v", "7"])
+            .env_clear()
+            .env("EDGE_CONFIG", &config)
+            .env("USER", "alice")
+            .output()
+    };
+    assert_eq!(write("40").await.unwrap().status.code(), Some(0));
+    assert_eq!(write("41").await.unwrap().status.code(), Some(2));
+    let entries = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let entries: Vec<&str> = entries.lines().collect();
+    assert_eq!(entries.len(), 2, "{entries:?}");
+    assert!(entries[0].contains(r#""user":"alice","#));
+    assert!(entries[0].contains(&format!(
+        r#""tool":"modbus","target":"holding 40 of {address}","value":"7","result":"ok"}}"#
+    )));
+    assert!(entries[1].contains(&format!(
+        r#""target":"holding 41 of {address}","value":"7","result":"error","error":"Refused"#
+    )));
+    let mirrored: Vec<String> = server
+        .messages()
+        .into_iter()
+        .filter(|message| message.subject == "edge.audit")
+        .map(|message| String::from_utf8(message.payload).unwrap())
+        .collect();
+    assert_eq!(mirrored, entries);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exceptions_exit_with_protocol_error() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.fail(100, 2);
+    let address = device.address().to_string();
+
+    let output = modbus(&[&address, "read-register", "-r", "100", "-k", "holding"]).await;
+
+    assert_eq!(output.status.code(), Some(5));
+    // Exceptions are answers, not transient failures, so they aren't retried.
+    assert_eq!(device.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_run_sends_nothing() {
+    let device = ModbusSimulator::start().await.unwrap();
+    let address = device.address().to_string();
+
+    let output = modbus(&[
+        &address,
+        "--dry-run",
+        "write-register",
+        "-a",
+        "100",
+        "-v",
+        "7",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("00 00 00 00 00 06 01 06 00 64 00 07"));
+    assert!(device.requests().is_empty());
+    assert_eq!(device.holding(100), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_until_the_device_is_up() {
+    let address = free_address();
+    let run = tokio::spawn({
+        let address = address.to_string();
+        async move {
+            modbus(&[
+                &address,
+                "--retries",
+                "10",
+                "--retry-delay",
+                "100ms",
+                "read-register",
+                "-r",
+                "1",
+                "-k",
+                "input",
+            ])
+            .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let device = ModbusSimulator::start_on(address).await.unwrap();
+    device.set_input(1, &[42]);
+
+    let output = run.await.unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("42"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_connections_exit_with_connection_error() {
+    let address = free_address().to_string();
+
+    let output = modbus(&[
+        &address,
+        "--retries",
+        "0",
+        "read-register",
+        "-r",
+        "1",
+        "-k",
+        "input",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(3));
+}
+
+// A local address nothing listens on, at least for now.
+fn free_address() -> std::net::SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn healthcheck_counts_exceptions_as_healthy() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.fail(0, 2);
+    let address = device.address().to_string();
+
+    let output = modbus(&[&address, "healthcheck"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("exception"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn healthcheck_fails_without_a_device() {
+    let address = free_address().to_string();
+
+    let output = modbus(&[&address, "healthcheck"]).await;
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stdout(&output).starts_with("unhealthy: "));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_every_device_in_a_list() {
+    let first = ModbusSimulator::start().await.unwrap();
+    first.set_holding(100, &[215]);
+    let second = ModbusSimulator::start().await.unwrap();
+    second.set_holding(100, &[212]);
+    let missing = free_address();
+    let list = format!("{},{},{missing}", first.address(), second.address());
+
+    let output = modbus(&[
+        &list,
+        "--retries",
+        "0",
+        "--output",
+        "json",
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(3));
+    let mut lines: Vec<String> = stdout(&output).lines().map(str::to_string).collect();
+    lines.sort();
+    let mut expected = vec![
+        format!(
+            r#"{{"target":"{}","register":100,"kind":"holding","unit_id":1,"values":[215]}}"#,
+            first.address()
+        ),
+        format!(
+            r#"{{"target":"{}","register":100,"kind":"holding","unit_id":1,"values":[212]}}"#,
+            second.address()
+        ),
+        format!(r#"{{"target":"{missing}","error":"connection failure","status":3}}"#),
+    ];
+    expected.sort();
+    assert_eq!(lines, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_every_device_in_a_target_file() {
+    let first = ModbusSimulator::start().await.unwrap();
+    let second = ModbusSimulator::start().await.unwrap();
+    let list = std::env::temp_dir().join(format!("modbus-targets-{}", std::process::id()));
+    std::fs::write(
+        &list,
+        format!(
+            "# site\n{}\n{} # second\n",
+            first.address(),
+            second.address()
+        ),
+    )
+    .unwrap();
+
+    let target = format!("@{}", list.display());
+    let output = modbus(&[&target, "write-register", "-a", "40", "-v", "7"]).await;
+    std::fs::remove_file(&list).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(first.holding(40), 7);
+    assert_eq!(second.holding(40), 7);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn renders_readings_through_a_template() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215, 3]);
+    let address = device.address().to_string();
+    let template = std::env::temp_dir().join(format!("modbus-template-{}", std::process::id()));
+    std::fs::write(
+        &template,
+        "{{#each values}}\n{{../kind}}:{{@index}}={{this}}\n{{/each}}\n",
+    )
+    .unwrap();
+
+    let output = modbus(&[
+        &address,
+        "--template",
+        template.to_str().unwrap(),
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "-c",
+        "2",
+    ])
+    .await;
+    std::fs::remove_file(&template).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "holding:0=215\nholding:1=3");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pipes_envelopes_from_one_device_to_another() {
+    let source = ModbusSimulator::start().await.unwrap();
+    source.set_holding(100, &[215, 3]);
+    let target = ModbusSimulator::start().await.unwrap();
+    let source_address = source.address().to_string();
+
+    let read = modbus(&[
+        &source_address,
+        "--output",
+        "envelope",
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "-c",
+        "2",
+    ])
+    .await;
+    assert_eq!(read.status.code(), Some(0));
+    let envelopes = stdout(&read);
+    let first = envelopes.lines().next().unwrap();
+    assert!(first.starts_with(&format!(
+        r#"{{"source":"{source_address}","topic":"holding 100","payload":215,"timestamp":"#
+    )));
+    assert!(first.ends_with(r#","quality":"good"}"#));
+
+    let mut write = Command::new(env!("CARGO_BIN_EXE_modbus"))
+        .args([
+            &target.address().to_string(),
+            "write-register",
+            "--envelopes",
+        ])
+        .env_clear()
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = write.stdin.take().unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut stdin, envelopes.as_bytes())
+        .await
+        .unwrap();
+    drop(stdin);
+
+    assert_eq!(write.wait().await.unwrap().code(), Some(0));
+    assert_eq!(target.holding(100), 215);
+    assert_eq!(target.holding(101), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watching_stops_cleanly_on_sigint() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+
+    let watch = Command::new(env!("CARGO_BIN_EXE_modbus"))
+        .args([
+            &device.address().to_string(),
+            "read-register",
+            "-r",
+            "100",
+            "-k",
+            "holding",
+            "-w",
+            "-i",
+            "100",
+        ])
+        .env_clear()
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let pid = watch.id().unwrap().to_string();
+    let killed = std::process::Command::new("kill")
+        .args(["-INT", &pid])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let output = watch.wait_with_output().await.unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).lines().all(|line| line == r#"["215"]"#));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Stopped after "), "{stderr}");
+    assert!(stderr.trim_end().ends_with(", 0 errors"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watches_for_a_count_of_reads() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let address = device.address().to_string();
+
+    let output = modbus(&[
+        &address,
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "--reads",
+        "3",
+        "--interval",
+        "50ms",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "[\"215\"]\n[\"215\"]\n[\"215\"]");
+    assert_eq!(device.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_stats_while_watching() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let address = device.address().to_string();
+
+    let output = modbus(&[
+        &address,
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "--reads",
+        "8",
+        "--interval",
+        "100ms",
+        "--stats",
+        "300ms",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stats: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.starts_with("Stats after "))
+        .collect();
+    assert!(stats.len() >= 2, "{stderr}");
+    assert!(stats[0].contains(" reads (+"), "{stderr}");
+    assert!(stats[0].ends_with(", 0 errors, 0 reconnects"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_rollups_beside_the_historian_samples() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let address = device.address().to_string();
+    let dir = std::env::temp_dir().join(format!("modbus-rollups-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let output = modbus(&[
+        &address,
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "--reads",
+        "4",
+        "--interval",
+        "20ms",
+        "--historian",
+        dir.to_str().unwrap(),
+        "--historian-rollup",
+        "1h:30d",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let files: Vec<_> = std::fs::read_dir(dir.join("rollups-1h"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{files:?}");
+    let name = files[0].file_name().unwrap().to_string_lossy().to_string();
+    assert!(
+        name.starts_with("rollups-") && name.ends_with(".sqlite"),
+        "{name}"
+    );
+    let database = std::fs::read(&files[0]).unwrap();
+    let contains = |needle: &[u8]| {
+        database
+            .windows(needle.len())
+            .any(|window| window == needle)
+    };
+    assert!(contains(b"CREATE TABLE rollups("));
+    // One row: min, max and avg of 215, then a count of 4.
+    let mut row = 215f64.to_be_bytes().repeat(3);
+    row.push(4);
+    assert!(contains(&row));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_failed_read_still_writes_out_the_historian_and_the_summary() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let dir = std::env::temp_dir().join(format!("modbus-failed-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let watch = Command::new(env!("CARGO_BIN_EXE_modbus"))
+        .args([
+            &device.address().to_string(),
+            "read-register",
+            "-r",
+            "100",
+            "-k",
+            "holding",
+            "-w",
+            "-i",
+            "50",
+            "--historian",
+            dir.to_str().unwrap(),
+        ])
+        .env_clear()
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    device.fail(100, 2);
+    let output = watch.wait_with_output().await.unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(", 1 error\n"), "{stderr}");
+    let samples: Vec<u8> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "sqlite")
+        })
+        .flat_map(|path| std::fs::read(path).unwrap())
+        .collect();
+    let value = 215f64.to_be_bytes();
+    assert!(samples.windows(value.len()).any(|window| window == value));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn exits_with_mismatch_unless_the_expected_value_is_read() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let address = device.address().to_string();
+    let read = |expected: &'static str| {
+        let address = address.clone();
+        async move {
+            modbus(&[
+                &address,
+                "read-register",
+                "-r",
+                "100",
+                "-k",
+                "holding",
+                "--expect-value",
+                expected,
+            ])
+            .await
+        }
+    };
+
+    assert_eq!(read("215").await.status.code(), Some(0));
+    let output = read("216").await;
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Expected value 216"));
+}
+
+#[tokio::test]
+async fn expect_within_keeps_reading_until_the_expectation_is_met() {
+    let device = ModbusSimulator::start().await.unwrap();
+    device.set_holding(100, &[215]);
+    let address = device.address().to_string();
+
+    let args = [
+        &address,
+        "read-register",
+        "-r",
+        "100",
+        "-k",
+        "holding",
+        "--interval",
+        "50ms",
+        "--expect-json",
+        ".values[0] == 300",
+        "--expect-within",
+        "5s",
+    ];
+    let watch = modbus(&args);
+    let change = async {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        device.set_holding(100, &[300]);
+    };
+    let (output, ()) = tokio::join!(watch, change);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).ends_with("[\"300\"]"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn analyzes_a_compressed_capture() {
+    let device = ModbusSimulator::start().await.unwrap();
+    let address = device.address().to_string();
+    let port = device.address().port().to_string();
+    let capture =
+        std::env::temp_dir().join(format!("modbus-capture-{}.pcapng.zst", std::process::id()));
+    let capture = capture.to_str().unwrap();
+
+    let read = modbus(&[
+        &address,
+        "--capture",
+        capture,
+        "read-register",
+        "-r",
+        "1",
+        "-k",
+        "holding",
+    ])
+    .await;
+    let analysis = modbus(&["--output", "json", "analyze-pcap", capture, "--port", &port]).await;
+    let magic = std::fs::read(capture).unwrap()[..4].to_vec();
+    std::fs::remove_file(capture).unwrap();
+
+    assert_eq!(read.status.code(), Some(0));
+    assert_eq!(magic, [0x28, 0xb5, 0x2f, 0xfd]);
+    assert_eq!(analysis.status.code(), Some(0));
+    assert!(stdout(&analysis)
+        .contains(r#""function":3,"name":"read holding registers","requests":1,"responses":1"#));
+}
@@ -0,0 +1,205 @@
+//! `nats bench`: publishes a burst of messages on one connection, receives them on another and
+//! reports the throughput either way and how long messages took from one to the other. Each
+//! payload starts with its sequence number and the time it was sent, so latencies are measured
+//! by one clock.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use edge_core::audit;
+use edge_core::output::{Output, Record};
+use edge_core::rate::{self, Limiter};
+use edge_core::writes::{self, Write};
+use futures::StreamExt;
+
+use crate::client::{self, Client};
+
+// The sequence number and the nanoseconds since the start, at the head of every payload.
+const HEADER: usize = 16;
+// How long the receiving side waits for the next message before counting the rest as lost.
+const IDLE: Duration = Duration::from_secs(5);
+
+pub(crate) struct Bench {
+    pub(crate) subject: String,
+    pub(crate) count: u64,
+    // Payload size; payloads are at least 16 bytes.
+    pub(crate) size: usize,
+    // Publishing tasks sharing the messages.
+    pub(crate) concurrency: usize,
+    pub(crate) limiter: Option<Limiter>,
+}
+
+pub(crate) struct Report {
+    sent: u64,
+    size: usize,
+    concurrency: usize,
+    publishing: Duration,
+    // From the start to the last message received.
+    receiving: Duration,
+    // Sorted, one per message received.
+    latencies: Vec<Duration>,
+}
+
+/// Publishes `bench.count` messages through `publisher` while `subscriber` receives them;
+/// checked and audited as one write.
+pub(crate) async fn run(publisher: &Client, subscriber: &Client, bench: &Bench) -> Result<Report> {
+    let size = bench.size.max(HEADER);
+    let (write, value) = (
+        Write::Nats(&bench.subject),
+        format!("{} messages of {size} bytes", bench.count),
+    );
+    writes::check(&write, &value)?;
+    let mut subscription = client::subscribe(subscriber, &bench.subject).await?;
+    // The subscription has to be in place before the first message.
+    subscriber
+        .flush()
+        .await
+        .map_err(|err| anyhow!("Unable to flush: {err}"))?;
+
+    let start = Instant::now();
+    let count = bench.count;
+    let receiving = async {
+        let mut latencies = Vec::new();
+        let mut last = start;
+        while (latencies.len() as u64) < count {
+            let Ok(Some(message)) = tokio::time::timeout(IDLE, subscription.next()).await else {
+                break;
+            };
+            last = Instant::now();
+            let sent = message
+                .payload
+                .get(8..HEADER)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map_or(0, u64::from_be_bytes);
+            latencies.push(
+                last.duration_since(start)
+                    .saturating_sub(Duration::from_nanos(sent)),
+            );
+        }
+        (latencies, last.duration_since(start))
+    };
+    let concurrency = bench.concurrency.max(1);
+    let publishing = async {
+        let workers: Vec<_> = (0..concurrency as u64)
+            .map(|worker| {
+                let (publisher, subject) = (publisher.clone(), bench.subject.clone());
+                let limiter = bench.limiter.clone();
+                tokio::spawn(async move {
+                    let mut payload = vec![0; size];
+                    for sequence in (worker..count).step_by(concurrency) {
+                        rate::acquire(limiter.as_ref()).await;
+                        let sent = start.elapsed().as_nanos() as u64;
+                        payload[..8].copy_from_slice(&sequence.to_be_bytes());
+                        payload[8..HEADER].copy_from_slice(&sent.to_be_bytes());
+                        publisher
+                            .publish(subject.clone(), payload.clone().into())
+                            .await
+                            .map_err(|err| anyhow!("Unable to publish: {err:?}"))?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.await??;
+        }
+        publisher
+            .flush()
+            .await
+            .map_err(|err| anyhow!("Unable to flush: {err}"))?;
+        Ok::<_, anyhow::Error>(start.elapsed())
+    };
+    let ((mut latencies, receiving), publishing) = tokio::join!(receiving, publishing);
+    let result = publishing.map(|publishing| {
+        latencies.sort();
+        Report {
+            sent: count,
+            size,
+            concurrency,
+            publishing,
+            receiving,
+            latencies,
+        }
+    });
+    audit::record(&write, &value, &result.as_ref().map(drop));
+    result
+}
+
+impl Report {
+    pub(crate) fn print(&self, out: &Output, subject: &str) {
+        let received = self.latencies.len() as u64;
+        let rate = |messages: u64, time: Duration| match time.as_secs_f64() {
+            seconds if seconds > 0.0 => messages as f64 / seconds,
+            _ => 0.0,
+        };
+        let published = rate(self.sent, self.publishing);
+        let arrived = rate(received, self.receiving);
+        let megabytes = |per_second: f64| per_second * self.size as f64 / 1e6;
+        let round = |value: f64| (value * 10.0).round() / 10.0;
+        let mut record = Record::new()
+            .field("subject", subject)
+            .field("messages", self.sent)
+            .field("size", self.size)
+            .field("concurrency", self.concurrency)
+            .field("published_per_second", round(published))
+            .field("published_mb_per_second", round(megabytes(published)))
+            .field("received", received)
+            .field("received_per_second", round(arrived))
+            .field("received_mb_per_second", round(megabytes(arrived)))
+            .field("lost", self.sent - received);
+        let latencies = [
+            ("min", percentile(&self.latencies, 0.0)),
+            ("p50", percentile(&self.latencies, 0.5)),
+            ("p95", percentile(&self.latencies, 0.95)),
+            ("p99", percentile(&self.latencies, 0.99)),
+            ("max", percentile(&self.latencies, 1.0)),
+        ];
+        for (name, latency) in &latencies {
+            let millis = latency.map(|latency| round(latency.as_secs_f64() * 1000.0));
+            record.push(&format!("latency_{name}_ms"), millis);
+        }
+        out.record(&record, || {
+            println!(
+                "Published {} messages of {} bytes in {}: {published:.1} msgs/s, {:.1} MB/s",
+                self.sent,
+                self.size,
+                seconds(self.publishing),
+                megabytes(published)
+            );
+            let lost = match self.sent - received {
+                0 => String::new(),
+                lost => format!(" ({lost} lost)"),
+            };
+            println!(
+                "Received {received} messages{lost} in {}: {arrived:.1} msgs/s, {:.1} MB/s",
+                seconds(self.receiving),
+                megabytes(arrived)
+            );
+            if received > 0 {
+                let latencies: Vec<String> = latencies
+                    .iter()
+                    .filter_map(|(name, latency)| {
+                        Some(format!(
+                            "{name} {:.1}ms",
+                            (*latency)?.as_secs_f64() * 1000.0
+                        ))
+                    })
+                    .collect();
+                println!("Latency {}", latencies.join(" "));
+            }
+        });
+    }
+}
+
+/// The latency `fraction` of the way up `sorted`, e.g. 0.95 for p95.
+pub(crate) fn percentile(sorted: &[Duration], fraction: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    Some(sorted[index])
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
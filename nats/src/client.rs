@@ -0,0 +1,744 @@
+//! NATS connections and publishing as the `nats` commands do them, for Rust applications that
+//! would otherwise shell out to the CLI. Connecting takes the same credentials, TLS settings
+//! and retry policy as the command line or a profile, and reports to
+//! [`edge_core::telemetry`] when it's set up:
+//!
+//! ```no_run
+//! use nats::client::{self, Options};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let profile = edge_core::config::load_profile(Some("site"))?;
+//! let options = Options::new("nats://broker:4222").with_profile(&profile)?;
+//! let connection = client::connect(&options).await?;
+//! client::publish(&connection, "site.status", b"up".to_vec()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! An address may list the servers of a cluster separated by commas, of which the client
+//! connects to one that answers and fails over to the others, and to any the cluster tells it
+//! about. With a `secondary`, such as the mirror of a broker, connecting tries it when the
+//! address can't be reached. The connection then stays on the server it got, reconnecting to it, as
+//! its [`Tuning`] allows.
+//!
+//! Services that answer on a subject are asked with [`request`], which waits for the reply.
+//!
+//! JetStream streams are published to with [`publish_jetstream`] and read back, from their
+//! oldest message, through a [`Consumer`] with [`consume`].
+//!
+//! Failures carry an [`edge_core::exit::Error`] with the status the CLI would exit with, e.g.
+//! [`Code::Auth`] for refused credentials.
+
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_nats::jetstream::consumer::{self as js_consumer, pull, push, AckPolicy, FromConsumer};
+use async_nats::{ConnectOptions, ServerAddr};
+use edge_core::audit;
+use edge_core::auth::{Auth, Credentials};
+use edge_core::buffer::Limits;
+use edge_core::config::{Profile, Tls};
+use edge_core::exit::{self, Code};
+use edge_core::json;
+use edge_core::output::{self, Record, Value};
+use edge_core::retry::{Retry, Transient};
+use edge_core::stats;
+use edge_core::telemetry;
+use edge_core::writes::{self, Write};
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::TcpStream;
+
+pub use async_nats::jetstream::publish::PublishAck;
+pub use async_nats::{Client, HeaderMap};
+
+/// A server and how to log in to it.
+#[derive(Clone, Default)]
+pub struct Options {
+    // A nats:// or tls:// URL, or several separated by commas.
+    pub address: String,
+    // Connected to when `address` can't be reached.
+    pub secondary: Option<String>,
+    pub credentials: Credentials,
+    pub tls: Tls,
+    pub retry: Retry,
+    // Only the queue is used here: how many messages each subscription holds.
+    pub limits: Limits,
+    pub tuning: Tuning,
+}
+
+/// How a connection keeps going over a poor link, such as a cellular modem; async-nats's
+/// defaults where unset. It reconnects on its own, waiting longer after each failed attempt up
+/// to 4s.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Tuning {
+    // Failed attempts in a row after which reconnecting gives up and the process exits with
+    // `Code::Connection`; unlimited by default.
+    pub max_reconnects: Option<usize>,
+    // How often the server is pinged to notice a dead link; 60s by default.
+    pub ping_interval: Option<Duration>,
+    // Outgoing messages held while the link is down or slow, after which publishing waits;
+    // 128 by default.
+    pub send_buffer: Option<usize>,
+}
+
+impl Options {
+    /// `address` without credentials or TLS, with the default retry policy.
+    pub fn new(address: impl Into<String>) -> Options {
+        Options {
+            address: address.into(),
+            ..Options::default()
+        }
+    }
+
+    /// Fills in what `profile` has and the options don't: the secondary, credentials, TLS and
+    /// retries.
+    pub fn with_profile(self, profile: &Profile) -> Result<Options> {
+        Ok(Options {
+            secondary: match self.secondary {
+                Some(secondary) => Some(secondary),
+                None => profile.secondary()?,
+            },
+            credentials: self.credentials.or_profile(profile)?,
+            tls: profile.tls()?,
+            retry: profile.retry()?,
+            ..self
+        })
+    }
+
+    pub fn credentials(self, credentials: Credentials) -> Options {
+        Options {
+            credentials,
+            ..self
+        }
+    }
+
+    pub fn secondary(self, secondary: impl Into<String>) -> Options {
+        Options {
+            secondary: Some(secondary.into()),
+            ..self
+        }
+    }
+
+    pub fn retry(self, retry: Retry) -> Options {
+        Options { retry, ..self }
+    }
+
+    pub fn limits(self, limits: Limits) -> Options {
+        Options { limits, ..self }
+    }
+
+    pub fn tuning(self, tuning: Tuning) -> Options {
+        Options { tuning, ..self }
+    }
+
+    /// The servers without any credentials in their URLs, for logs and telemetry.
+    pub fn server(&self) -> String {
+        server(&self.address)
+    }
+
+    /// The async-nats options these describe; checked once before connecting, since they're
+    /// used up by each attempt.
+    pub fn connect_options(&self) -> Result<ConnectOptions, exit::Error> {
+        let invalid = |err: &dyn std::fmt::Display| exit::Error::new(Code::Usage, err);
+        let options = match self.credentials.resolve().map_err(|err| invalid(&err))? {
+            Auth::UserPassword { username, password } => {
+                log::info!("Using username and password to connect to nats.");
+                ConnectOptions::with_user_and_password(username, password)
+            }
+            Auth::Token(token) => {
+                log::info!("Using token to connect to nats");
+                ConnectOptions::with_token(token)
+            }
+            Auth::Creds(creds) => {
+                log::info!("Using a creds file to connect to nats.");
+                ConnectOptions::with_credentials(&creds)
+                    .map_err(|err| invalid(&format!("Invalid creds file: {err}")))?
+            }
+            Auth::NKey(seed) => {
+                log::info!("Using an NKey to connect to nats.");
+                ConnectOptions::with_nkey(seed)
+            }
+            Auth::None => {
+                log::info!("No authentication specified");
+                ConnectOptions::new()
+            }
+        };
+
+        let tls = &self.tls;
+        let mut options = options
+            .subscription_capacity(self.limits.queue)
+            .require_tls(tls.required);
+        if let Some(ping_interval) = self.tuning.ping_interval {
+            options = options.ping_interval(ping_interval);
+        }
+        if let Some(send_buffer) = self.tuning.send_buffer {
+            options = options.client_capacity(send_buffer);
+        }
+        if let Some(ca) = &tls.ca {
+            options = options.add_root_certificates(ca.clone());
+        }
+        match (&tls.cert, &tls.key) {
+            (Some(cert), Some(key)) => {
+                options = options.add_client_certificate(cert.clone(), key.clone())
+            }
+            (None, None) => {}
+            _ => return Err(invalid(&"tls_cert and tls_key must be given together.")),
+        }
+
+        // Attempts to reconnect that failed since the last disconnect, None while connected.
+        let failures = Arc::new(Mutex::new(None::<usize>));
+        let max_reconnects = self.tuning.max_reconnects;
+        let options = options.event_callback(move |event| {
+            let failures = failures.clone();
+            async move {
+                // Not sure what to throw in with this block.
+                // TODO: a more reified vision for this block.
+                let mut failures = failures.lock().unwrap_or_else(|err| err.into_inner());
+                match event {
+                    async_nats::Event::Disconnect => {
+                        log::info!("Disconnected nats connection");
+                        telemetry::count("nats.disconnects", &[]);
+                        *failures = Some(0);
+                    }
+                    async_nats::Event::Reconnect => {
+                        log::info!("Nats client reconnected,");
+                        telemetry::count("nats.reconnects", &[]);
+                        stats::reconnected();
+                        *failures = None;
+                    }
+                    async_nats::Event::ClientError(err) => {
+                        log::error!("Nats client received error : {}", err);
+                        telemetry::count("nats.client_errors", &[]);
+                        if let Some(failed) = failures.as_mut() {
+                            *failed += 1;
+                            if max_reconnects.is_some_and(|max| *failed > max) {
+                                exit::fatal_with(
+                                    Code::Connection,
+                                    format!(
+                                        "Gave up reconnecting after {} failed attempts",
+                                        *failed
+                                    ),
+                                );
+                            }
+                        }
+                    }
+                    // async-nats has already reported the subscribe or publish as done; the
+                    // server refuses it afterwards.
+                    async_nats::Event::ServerError(async_nats::ServerError::Other(err))
+                        if err.starts_with("permissions violation for subscription") =>
+                    {
+                        exit::fatal_with(Code::Subscribe, format!("Unable to subscribe: {err}"))
+                    }
+                    async_nats::Event::ServerError(async_nats::ServerError::Other(err))
+                        if err.starts_with("permissions violation for publish") =>
+                    {
+                        exit::fatal_with(Code::Publish, format!("Unable to publish: {err}"))
+                    }
+                    other => log::warn!("Nats client unused event: {}", other),
+                };
+            }
+        });
+        Ok(options)
+    }
+}
+
+/// Connects, retrying connection failures and timeouts by `options.retry`; each attempt tries
+/// the secondary, if any, when the address can't be reached.
+pub async fn connect(options: &Options) -> Result<Client> {
+    // Invalid options fail before the first attempt rather than on every one.
+    let mut first = Some(options.connect_options()?);
+    let connection = options
+        .retry
+        .run("Connecting to NATS", || {
+            let connect_options = first.take().map_or_else(|| options.connect_options(), Ok);
+            async move {
+                let connection = connect_to(connect_options?, &options.address).await;
+                match (connection, &options.secondary) {
+                    (Err(err), Some(secondary)) if err.is_transient() => {
+                        log::warn!(
+                            "Unable to reach {}, trying {}: {err}",
+                            options.server(),
+                            server(secondary)
+                        );
+                        let connection = connect_to(options.connect_options()?, secondary).await;
+                        if connection.is_ok() {
+                            log::info!("Connected to the secondary {}", server(secondary));
+                        }
+                        connection
+                    }
+                    (connection, _) => connection,
+                }
+            }
+        })
+        .await?;
+    Ok(connection)
+}
+
+async fn connect_to(options: ConnectOptions, address: &str) -> Result<Client, exit::Error> {
+    let servers = seeds(address)
+        .map(str::parse)
+        .collect::<Result<Vec<ServerAddr>, _>>()
+        .map_err(|err| {
+            exit::Error::new(Code::Usage, format!("Invalid address {address}: {err}"))
+        })?;
+    let span = telemetry::span("nats.connect").attribute("server.address", server(address));
+    let connection = options.connect(servers.as_slice()).await;
+    telemetry::finish(span, "nats.connects", &[], &connection);
+    connection.map_err(|err| exit::Error::new(connect_error_code(&err), err))
+}
+
+/// The servers listed in `address`, separated by commas; the client picks among them and fails
+/// over to the others, as well as to those the cluster tells it about.
+pub fn seeds(address: &str) -> impl Iterator<Item = &str> {
+    address
+        .split(',')
+        .map(str::trim)
+        .filter(|seed| !seed.is_empty())
+}
+
+/// The INFO the server at `seed` greets clients with, for what async-nats leaves out of its
+/// `ServerInfo`, such as the cluster name. Read over a plain TCP connection and closed before
+/// logging in, since servers send it before any TLS handshake.
+pub async fn greeting(seed: &str, timeout: Duration) -> Result<Record> {
+    let host = server(seed);
+    let host = host.rsplit("://").next().unwrap_or_default();
+    let host = host.trim_end_matches('/');
+    let read = async {
+        let upstream = edge_core::net::resolve(host, 4222)?;
+        let stream = TcpStream::connect(upstream).await?;
+        let mut line = String::new();
+        // INFO lines are a few hundred bytes; a cluster's connect_urls can add some more.
+        BufReader::new(stream.take(64 * 1024))
+            .read_line(&mut line)
+            .await?;
+        Ok::<_, std::io::Error>(line)
+    };
+    let line = tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "No INFO from {host} within {}",
+                humantime::format_duration(timeout)
+            )
+        })?
+        .map_err(|err| anyhow!("Unable to read INFO from {host}: {err}"))?;
+    match line
+        .strip_prefix("INFO ")
+        .map(|info| json::parse(info.trim()))
+    {
+        Some(Ok(Value::Record(info))) => Ok(info),
+        _ => bail!("{host} didn't greet with INFO: {}", line.trim()),
+    }
+}
+
+/// `address` without any credentials in its URLs.
+pub(crate) fn server(address: &str) -> String {
+    seeds(address)
+        .map(|seed| seed.rsplit_once('@').map_or(seed, |(_, host)| host))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Subscribes to `subject`, which may have wildcards; failures carry `Code::Subscribe`.
+/// async-nats only fails that once the connection is gone: a server that refuses the
+/// subscription, e.g. for permissions, says so later, and the process then exits with the
+/// same status.
+pub async fn subscribe(connection: &Client, subject: &str) -> Result<async_nats::Subscriber> {
+    connection
+        .subscribe(subject.to_string())
+        .await
+        .map_err(|err| {
+            exit::Error::new(Code::Subscribe, format!("Unable to subscribe: {err}")).into()
+        })
+}
+
+/// Publishes `payload` on `subject` and waits until the server has it, if the write policy
+/// allows it; publishes are audited.
+pub async fn publish(connection: &Client, subject: &str, payload: Vec<u8>) -> Result<()> {
+    publish_with_headers(connection, subject, HeaderMap::new(), payload).await
+}
+
+/// [`publish`] with headers, such as a trace ID or a content type.
+pub async fn publish_with_headers(
+    connection: &Client,
+    subject: &str,
+    headers: HeaderMap,
+    payload: Vec<u8>,
+) -> Result<()> {
+    publish_checked(connection, subject, headers, payload, true).await
+}
+
+/// [`publish_with_headers`] without waiting for the server, for a run of messages; [`flush`]
+/// after the last one.
+pub async fn queue_with_headers(
+    connection: &Client,
+    subject: &str,
+    headers: HeaderMap,
+    payload: Vec<u8>,
+) -> Result<()> {
+    publish_checked(connection, subject, headers, payload, false).await
+}
+
+/// Waits until the server has everything published so far.
+pub async fn flush(connection: &Client) -> Result<()> {
+    connection
+        .flush()
+        .await
+        .map_err(|err| exit::Error::new(Code::Publish, format!("Unable to flush: {err:?}")).into())
+}
+
+async fn publish_checked(
+    connection: &Client,
+    subject: &str,
+    headers: HeaderMap,
+    payload: Vec<u8>,
+    wait: bool,
+) -> Result<()> {
+    let (write, value) = (Write::Nats(subject), audit::payload(&payload));
+    writes::check(&write, &value)?;
+    let span = telemetry::span("nats.publish")
+        .attribute("messaging.destination.name", subject)
+        .attribute("messaging.message.body.size", payload.len());
+    let result = async {
+        // The server would drop the connection over it rather than refuse the one message.
+        let max_payload = connection.server_info().max_payload;
+        if max_payload > 0 && payload.len() > max_payload {
+            return Err(exit::Error::new(
+                Code::Publish,
+                format!(
+                    "Unable to publish {} bytes on {subject}: the server takes at most {max_payload}",
+                    payload.len()
+                ),
+            )
+            .into());
+        }
+        let subject = subject.to_string();
+        // As with subscribing, only a connection that's gone fails these, and a refusal, e.g.
+        // for permissions, comes later.
+        let failed = |err: &dyn std::fmt::Debug| {
+            exit::Error::new(Code::Publish, format!("Unable to publish: {err:?}"))
+        };
+        if headers.is_empty() {
+            connection
+                .publish(subject, payload.into())
+                .await
+                .map_err(|err| failed(&err))?;
+        } else {
+            connection
+                .publish_with_headers(subject, headers, payload.into())
+                .await
+                .map_err(|err| failed(&err))?;
+        }
+        if wait {
+            flush(connection).await?;
+        }
+        Ok(())
+    }
+    .await;
+    telemetry::finish(span, "nats.publishes", &[], &result);
+    audit::record(&write, &value, &result);
+    result
+}
+
+/// Publishes `payload` on `subject` through JetStream and waits for the stream that captures
+/// it to acknowledge it, within 5s; checked and audited like [`publish`].
+pub async fn publish_jetstream(
+    connection: &Client,
+    subject: &str,
+    payload: Vec<u8>,
+) -> Result<PublishAck> {
+    publish_jetstream_with_headers(connection, subject, HeaderMap::new(), payload).await
+}
+
+/// [`publish_jetstream`] with headers, e.g. `Nats-Msg-Id` for the stream to spot duplicates.
+pub async fn publish_jetstream_with_headers(
+    connection: &Client,
+    subject: &str,
+    headers: HeaderMap,
+    payload: Vec<u8>,
+) -> Result<PublishAck> {
+    let (write, value) = (Write::Nats(subject), audit::payload(&payload));
+    writes::check(&write, &value)?;
+    let span = telemetry::span("nats.jetstream.publish")
+        .attribute("messaging.destination.name", subject)
+        .attribute("messaging.message.body.size", payload.len());
+    let jetstream = async_nats::jetstream::new(connection.clone());
+    let subject_name = subject.to_string();
+    let published = if headers.is_empty() {
+        jetstream.publish(subject_name, payload.into()).await
+    } else {
+        jetstream
+            .publish_with_headers(subject_name, headers, payload.into())
+            .await
+    };
+    let result = published.map_err(|err| match err.downcast_ref::<std::io::Error>() {
+        // What waiting on a subject no stream captures looks like.
+        Some(io) if io.kind() == std::io::ErrorKind::TimedOut => exit::Error::new(
+            Code::Timeout,
+            format!("No stream acknowledged the message on {subject}"),
+        )
+        .into(),
+        // The server's answer there when no stream captures the subject.
+        Some(io) if io.kind() == std::io::ErrorKind::NotFound => {
+            exit::Error::new(Code::Publish, format!("No stream captures {subject}")).into()
+        }
+        // Anything else is an error answer from the stream, e.g. a sequence mismatch.
+        _ => exit::Error::new(
+            Code::Publish,
+            format!("Unable to publish to JetStream: {err}"),
+        )
+        .into(),
+    });
+    telemetry::finish(span, "nats.publishes", &[("mode", "jetstream")], &result);
+    audit::record(&write, &value, &result.as_ref().map(drop));
+    result
+}
+
+/// Publishes `payload` on `subject` with a reply inbox and waits up to `timeout` for the
+/// first reply; checked and audited like [`publish`], since services act on requests.
+pub async fn request(
+    connection: &Client,
+    subject: &str,
+    payload: Vec<u8>,
+    timeout: Duration,
+) -> Result<async_nats::Message> {
+    let (write, value) = (Write::Nats(subject), audit::payload(&payload));
+    writes::check(&write, &value)?;
+    let span = telemetry::span("nats.request")
+        .attribute("messaging.destination.name", subject)
+        .attribute("messaging.message.body.size", payload.len());
+    let request = async_nats::Request::new()
+        .payload(payload.into())
+        .timeout(Some(timeout));
+    let result = connection
+        .send_request(subject.to_string(), request)
+        .await
+        .map_err(|err| match err.downcast_ref::<std::io::Error>() {
+            Some(io) if io.kind() == std::io::ErrorKind::TimedOut => exit::Error::new(
+                Code::Timeout,
+                format!(
+                    "No reply on {subject} within {}",
+                    humantime::format_duration(timeout)
+                ),
+            )
+            .into(),
+            // The server's answer when nothing is subscribed to the subject.
+            Some(io) if io.kind() == std::io::ErrorKind::NotFound => exit::Error::new(
+                Code::Connection,
+                format!("Nothing answers requests on {subject}"),
+            )
+            .into(),
+            _ => anyhow!("Unable to send the request: {err}"),
+        });
+    telemetry::finish(span, "nats.requests", &[], &result);
+    audit::record(&write, &value, &result.as_ref().map(drop));
+    result
+}
+
+/// A JetStream consumer to read a stream through, as `subscribe --stream` does.
+#[derive(Clone, Debug, Default)]
+pub struct Consumer {
+    pub stream: String,
+    // An existing consumer to read through, push or pull.
+    pub name: Option<String>,
+    // A durable consumer to read through, created unless it exists. With neither, an
+    // ephemeral consumer is created for the run.
+    pub durable: Option<String>,
+    // Only the messages on these subjects, for a new consumer; the whole stream by default.
+    pub filter: Option<String>,
+    // Whether a new consumer waits for each message to be acknowledged with [`ack`].
+    pub ack: bool,
+    // Fetch this many messages at a time from a pull consumer. A new consumer is a pull
+    // consumer with a batch and a push consumer without.
+    pub batch: Option<usize>,
+}
+
+// Messages fetched at a time from an existing pull consumer without a batch.
+const BATCH: usize = 100;
+// How often the server shows an idle pull consumer is still there; async-nats 0.20 fails
+// pull consumers without one.
+const HEARTBEAT: Duration = Duration::from_secs(10);
+
+/// Messages as a consumer delivers them, or why it stopped.
+pub type Messages = Pin<Box<dyn Stream<Item = Result<async_nats::Message>> + Send>>;
+
+/// Reads the stream through `consumer`, binding to it or creating it first.
+pub async fn consume(connection: &Client, consumer: &Consumer) -> Result<Messages> {
+    let name = &consumer.stream;
+    let jetstream = async_nats::jetstream::new(connection.clone());
+    let stream = jetstream
+        .get_stream(name)
+        .await
+        .map_err(|err| anyhow!("Unable to find stream {name}: {err}"))?;
+    let existing = match consumer.name.as_ref().or(consumer.durable.as_ref()) {
+        Some(consumer) => {
+            api(
+                connection,
+                &format!("CONSUMER.INFO.{name}.{consumer}"),
+                Record::new(),
+            )
+            .await?
+        }
+        None => None,
+    };
+    let consumer_name = match (existing, &consumer.name) {
+        (Some(info), _) => text(&info, "name")?,
+        (None, Some(missing)) => bail!("Stream {name} has no consumer {missing}"),
+        (None, None) => create_consumer(connection, consumer).await?,
+    };
+    let info = stream
+        .consumer_info(&consumer_name)
+        .await
+        .map_err(|err| anyhow!("Unable to read consumer {consumer_name}: {err}"))?;
+    if !consumer.ack && info.config.ack_policy != AckPolicy::None {
+        log::warn!("Consumer {consumer_name} waits for acknowledgements, so without --ack it will deliver every message again");
+    }
+    let invalid =
+        |err: async_nats::Error| anyhow!("Unable to read consumer {consumer_name}: {err}");
+    let messages = match info.config.deliver_subject {
+        Some(_) => {
+            let config =
+                push::Config::try_from_consumer_config(info.config.clone()).map_err(invalid)?;
+            js_consumer::Consumer::new(config, info, jetstream)
+                .messages()
+                .await
+                .map_err(invalid)?
+                .boxed()
+        }
+        None => {
+            let config =
+                pull::Config::try_from_consumer_config(info.config.clone()).map_err(invalid)?;
+            js_consumer::Consumer::new(config, info, jetstream)
+                .stream()
+                .max_messages_per_batch(consumer.batch.unwrap_or(BATCH))
+                .heartbeat(HEARTBEAT)
+                .messages()
+                .await
+                .map_err(invalid)?
+                .boxed()
+        }
+    };
+    Ok(Box::pin(messages.map(move |message| {
+        message
+            .map(|message| message.message)
+            .map_err(|err| anyhow!("Lost consumer {consumer_name}: {err}"))
+    })))
+}
+
+/// Tells the server a message from a consumer with acknowledgements has been handled, by its
+/// reply subject.
+pub async fn ack(connection: &Client, reply: &str) -> Result<()> {
+    connection
+        .publish(reply.to_string(), "".into())
+        .await
+        .map_err(|err| anyhow!("Unable to acknowledge: {err}"))
+}
+
+/// Creates the consumer, returning the name the server gave it.
+// Asked for directly: async-nats 0.20 prints the API subject on stdout when it creates one.
+async fn create_consumer(connection: &Client, consumer: &Consumer) -> Result<String> {
+    let stream = &consumer.stream;
+    let ack = if consumer.ack { "explicit" } else { "none" };
+    let mut config = Record::new()
+        .field("deliver_policy", "all")
+        .field("ack_policy", ack)
+        .field("replay_policy", "instant");
+    if let Some(filter) = &consumer.filter {
+        config.push("filter_subject", filter.as_str());
+    }
+    if consumer.batch.is_none() {
+        config.push("deliver_subject", connection.new_inbox());
+    }
+    let request = match &consumer.durable {
+        Some(durable) => {
+            config.push("durable_name", durable.as_str());
+            format!("CONSUMER.DURABLE.CREATE.{stream}.{durable}")
+        }
+        None => format!("CONSUMER.CREATE.{stream}"),
+    };
+    let body = Record::new()
+        .field("stream_name", stream.as_str())
+        .field("config", config);
+    match api(connection, &request, body).await? {
+        Some(info) => text(&info, "name"),
+        None => bail!("Unable to find stream {stream}"),
+    }
+}
+
+/// Asks the JetStream API, e.g. `CONSUMER.INFO.<stream>.<consumer>`, for its answer; None
+/// when it says there's no such stream or consumer.
+pub(crate) async fn api(
+    connection: &Client,
+    request: &str,
+    body: Record,
+) -> Result<Option<Record>> {
+    let body = output::json(&Value::Record(body));
+    let reply = connection
+        .request(format!("$JS.API.{request}"), body.into())
+        .await
+        .map_err(|err| anyhow!("JetStream didn't answer {request}: {err}"))?;
+    let Ok(Value::Record(response)) = json::parse(&String::from_utf8_lossy(&reply.payload)) else {
+        bail!("JetStream gave an unreadable answer to {request}");
+    };
+    let Some(Value::Record(error)) = response.get("error") else {
+        return Ok(Some(response));
+    };
+    if matches!(error.get("code"), Some(Value::Integer(404))) {
+        return Ok(None);
+    }
+    let description = text(error, "description").unwrap_or_default();
+    bail!("JetStream refused {request}: {description}")
+}
+
+pub(crate) fn text(record: &Record, name: &str) -> Result<String> {
+    match record.get(name) {
+        Some(Value::String(text)) => Ok(text.clone()),
+        _ => bail!("JetStream left out the {name}"),
+    }
+}
+
+/// Every subject messages are published on from now on, each the first time it's seen;
+/// `_INBOX` reply subjects are left out with `skip_inboxes`.
+pub async fn subjects(
+    connection: &Client,
+    skip_inboxes: bool,
+) -> Result<impl Stream<Item = String>> {
+    let mut seen = HashSet::new();
+    Ok(traffic(connection, skip_inboxes)
+        .await?
+        .filter_map(move |(subject, _)| {
+            let new = seen.insert(subject.clone());
+            async move { new.then_some(subject) }
+        }))
+}
+
+/// The subject and payload size of every message published from now on, as
+/// [`subjects`] sees them.
+pub async fn traffic(
+    connection: &Client,
+    skip_inboxes: bool,
+) -> Result<impl Stream<Item = (String, usize)>> {
+    let subscription = subscribe(connection, ">").await?;
+    Ok(subscription.filter_map(move |message| {
+        let subject = message.subject;
+        let wanted = !(skip_inboxes && subject.starts_with("_INBOX"));
+        async move { wanted.then(|| (subject, message.payload.len())) }
+    }))
+}
+
+/// The client reports server `-ERR` lines during the handshake as plain I/O errors, so spot
+/// authentication failures by their text.
+pub(crate) fn connect_error_code(err: &std::io::Error) -> Code {
+    let message = err.to_string();
+    if message.contains("authorization violation") {
+        Code::Auth
+    } else if message.starts_with("nats:") {
+        Code::Protocol
+    } else {
+        Code::from_io(err)
+    }
+}
@@ -0,0 +1,244 @@
+//! The consumers of a JetStream stream: listed with how much each has yet to deliver, and
+//! durable ones created and deleted, e.g. to clear a durable a gateway left stuck without
+//! reaching for the cluster's own tooling. Creating and deleting are checked and audited like
+//! [`client::publish`], as one write each.
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use edge_core::audit;
+use edge_core::output::{Record, Value};
+use edge_core::writes::{self, Write};
+
+use crate::client::{self, Client};
+
+/// Which messages a consumer waits to have acknowledged before moving on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum AckPolicy {
+    None,
+    /// Acknowledging a message acknowledges every one before it.
+    All,
+    #[default]
+    Explicit,
+}
+
+impl AckPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AckPolicy::None => "none",
+            AckPolicy::All => "all",
+            AckPolicy::Explicit => "explicit",
+        }
+    }
+}
+
+/// Where in the stream a new consumer starts.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DeliverPolicy {
+    #[default]
+    All,
+    Last,
+    /// Only what's published from now on.
+    New,
+    /// The last message on each subject.
+    LastPerSubject,
+}
+
+impl DeliverPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliverPolicy::All => "all",
+            DeliverPolicy::Last => "last",
+            DeliverPolicy::New => "new",
+            DeliverPolicy::LastPerSubject => "last_per_subject",
+        }
+    }
+}
+
+/// A durable consumer to create.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub name: String,
+    pub ack_policy: AckPolicy,
+    pub deliver_policy: DeliverPolicy,
+    // Only the messages on these subjects; the whole stream by default.
+    pub filter: Option<String>,
+    // Push the messages to this subject; a pull consumer waits to be asked without one.
+    pub deliver_subject: Option<String>,
+}
+
+/// A consumer as the server describes it.
+#[derive(Clone, Debug)]
+pub struct Info {
+    pub stream: String,
+    pub name: String,
+    pub durable: bool,
+    pub ack_policy: String,
+    pub deliver_policy: String,
+    pub filter: Option<String>,
+    pub deliver_subject: Option<String>,
+    // The last stream sequence delivered.
+    pub delivered: u64,
+    // Messages left to deliver, delivered but not acknowledged, and delivered again.
+    pub pending: u64,
+    pub ack_pending: u64,
+    pub redelivered: u64,
+    // Pull requests waiting for messages.
+    pub waiting: u64,
+}
+
+impl Info {
+    fn parse(info: &Record) -> Result<Info> {
+        let config = match info.get("config") {
+            Some(Value::Record(config)) => config,
+            _ => bail!("JetStream left out the config"),
+        };
+        let text = |record: &Record, name: &str| match record.get(name) {
+            Some(Value::String(text)) if !text.is_empty() => Some(text.clone()),
+            _ => None,
+        };
+        let delivered = match info.get("delivered") {
+            Some(Value::Record(delivered)) => number(delivered, "stream_seq"),
+            _ => 0,
+        };
+        Ok(Info {
+            stream: client::text(info, "stream_name")?,
+            name: client::text(info, "name")?,
+            durable: text(config, "durable_name").is_some(),
+            ack_policy: text(config, "ack_policy").unwrap_or_else(|| "none".to_string()),
+            deliver_policy: text(config, "deliver_policy").unwrap_or_else(|| "all".to_string()),
+            filter: text(config, "filter_subject"),
+            deliver_subject: text(config, "deliver_subject"),
+            delivered,
+            pending: number(info, "num_pending"),
+            ack_pending: number(info, "num_ack_pending"),
+            redelivered: number(info, "num_redelivered"),
+            waiting: number(info, "num_waiting"),
+        })
+    }
+
+    /// Pull or push, as in the listing.
+    pub fn kind(&self) -> &'static str {
+        match self.deliver_subject {
+            Some(_) => "push",
+            None => "pull",
+        }
+    }
+
+    pub fn record(&self) -> Record {
+        Record::new()
+            .field("stream", self.stream.as_str())
+            .field("name", self.name.as_str())
+            .field("durable", self.durable)
+            .field("kind", self.kind())
+            .field("ack_policy", self.ack_policy.as_str())
+            .field("deliver_policy", self.deliver_policy.as_str())
+            .field("filter_subject", self.filter.as_deref())
+            .field("deliver_subject", self.deliver_subject.as_deref())
+            .field("delivered", self.delivered)
+            .field("pending", self.pending)
+            .field("ack_pending", self.ack_pending)
+            .field("redelivered", self.redelivered)
+            .field("waiting", self.waiting)
+    }
+}
+
+fn number(record: &Record, name: &str) -> u64 {
+    match record.get(name) {
+        Some(Value::Integer(number)) => (*number).max(0) as u64,
+        Some(Value::Unsigned(number)) => *number,
+        _ => 0,
+    }
+}
+
+/// Every consumer of `stream`, in the order the server gives them.
+pub async fn list(connection: &Client, stream: &str) -> Result<Vec<Info>> {
+    let mut consumers = Vec::new();
+    loop {
+        let body = Record::new().field("offset", consumers.len());
+        let request = format!("CONSUMER.LIST.{stream}");
+        let Some(page) = client::api(connection, &request, body).await? else {
+            bail!("Unable to find stream {stream}");
+        };
+        let infos = match page.get("consumers") {
+            Some(Value::List(infos)) => infos,
+            // The server sends null rather than an empty list.
+            _ => break,
+        };
+        for info in infos {
+            let Value::Record(info) = info else {
+                bail!("JetStream gave an unreadable answer to {request}");
+            };
+            consumers.push(Info::parse(info)?);
+        }
+        if infos.is_empty() || consumers.len() as u64 >= number(&page, "total") {
+            break;
+        }
+    }
+    Ok(consumers)
+}
+
+/// The consumer `name` of `stream`.
+pub async fn info(connection: &Client, stream: &str, name: &str) -> Result<Info> {
+    let request = format!("CONSUMER.INFO.{stream}.{name}");
+    match client::api(connection, &request, Record::new()).await? {
+        Some(info) => Info::parse(&info),
+        None => bail!("Stream {stream} has no consumer {name}"),
+    }
+}
+
+/// Creates the durable consumer `config` describes on `stream`; one by that name with the
+/// same settings is left as it is.
+pub async fn create(connection: &Client, stream: &str, config: &Config) -> Result<Info> {
+    let name = &config.name;
+    let subject = format!("$JS.API.CONSUMER.DURABLE.CREATE.{stream}.{name}");
+    let write = Write::Nats(&subject);
+    let value = format!(
+        "create {name}, ack {}, deliver {}",
+        config.ack_policy.as_str(),
+        config.deliver_policy.as_str()
+    );
+    writes::check(&write, &value)?;
+    let result = async {
+        let mut settings = Record::new()
+            .field("durable_name", name.as_str())
+            .field("deliver_policy", config.deliver_policy.as_str())
+            .field("ack_policy", config.ack_policy.as_str())
+            .field("replay_policy", "instant");
+        if let Some(filter) = &config.filter {
+            settings.push("filter_subject", filter.as_str());
+        }
+        if let Some(deliver_subject) = &config.deliver_subject {
+            settings.push("deliver_subject", deliver_subject.as_str());
+        }
+        let body = Record::new()
+            .field("stream_name", stream)
+            .field("config", settings);
+        let request = format!("CONSUMER.DURABLE.CREATE.{stream}.{name}");
+        match client::api(connection, &request, body).await? {
+            Some(info) => Info::parse(&info),
+            None => bail!("Unable to find stream {stream}"),
+        }
+    }
+    .await;
+    audit::record(&write, &value, &result.as_ref().map(drop));
+    result
+}
+
+/// Deletes the consumer `name` of `stream`, with what it had yet to deliver or have
+/// acknowledged; the stream keeps its messages.
+pub async fn delete(connection: &Client, stream: &str, name: &str) -> Result<()> {
+    let subject = format!("$JS.API.CONSUMER.DELETE.{stream}.{name}");
+    let write = Write::Nats(&subject);
+    let value = format!("delete {name}");
+    writes::check(&write, &value)?;
+    let result = async {
+        let request = format!("CONSUMER.DELETE.{stream}.{name}");
+        match client::api(connection, &request, Record::new()).await? {
+            Some(_) => Ok(()),
+            None => bail!("Stream {stream} has no consumer {name}"),
+        }
+    }
+    .await;
+    audit::record(&write, &value, &result);
+    result
+}
@@ -0,0 +1,189 @@
+//! `subscribe --grep` and `--jsonpath`: which messages are printed and passed on. `--grep` is a
+//! regular expression searched for in the payload as shown; `--jsonpath` a path into the JSON
+//! payload, such as `$.alarms[0].code` or `$.readings[*].value`, that matches when it leads to
+//! something other than null or false, or, followed by a comparison, to a value that passes
+//! it:
+//!
+//! ```text
+//! $.temperature > 30
+//! $.state == "fault"
+//! $['device-id'] != "plc-2"
+//! ```
+
+use std::cmp::Ordering;
+
+use edge_core::json;
+use edge_core::output::Value;
+use regex::Regex;
+
+pub(crate) struct Filter {
+    pub(crate) grep: Option<Regex>,
+    pub(crate) jsonpath: Option<Query>,
+    // Pass the messages that don't match instead.
+    pub(crate) invert: bool,
+}
+
+impl Filter {
+    /// Whether the message with `payload`, as shown, and `value`, as decoded, gets through.
+    pub(crate) fn passes(&self, payload: &str, value: &Value) -> bool {
+        let grep = self.grep.as_ref().is_none_or(|grep| grep.is_match(payload));
+        let jsonpath = self.jsonpath.as_ref().is_none_or(|query| match value {
+            // Without a codec the payload is text, which may hold JSON.
+            Value::String(_) => json::parse(payload).is_ok_and(|value| query.matches(&value)),
+            value => query.matches(value),
+        });
+        (grep && jsonpath) != self.invert
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Query {
+    path: Vec<Step>,
+    comparison: Option<(Operator, Value)>,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Field(String),
+    Index(usize),
+    // `*` or `[*]`: every field or element.
+    Any,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Query {
+    /// Parses `$...` with an optional comparison, for the command line.
+    pub fn parse(text: &str) -> Result<Query, String> {
+        let text = text.trim();
+        let Some(mut rest) = text.strip_prefix('$') else {
+            return Err(format!("expected a path starting with $, got `{text}`"));
+        };
+        let mut path = Vec::new();
+        loop {
+            if let Some(after) = rest.strip_prefix("[*]").or_else(|| rest.strip_prefix(".*")) {
+                path.push(Step::Any);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(after.len());
+                if end == 0 {
+                    return Err(format!("expected a field name after . in `{text}`"));
+                }
+                path.push(Step::Field(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some(end) = after.find(']') else {
+                    return Err(format!("unclosed [ in `{text}`"));
+                };
+                let inside = after[..end].trim();
+                let quoted = inside
+                    .strip_prefix('\'')
+                    .and_then(|name| name.strip_suffix('\''))
+                    .or_else(|| inside.strip_prefix('"')?.strip_suffix('"'));
+                path.push(match (quoted, inside.parse()) {
+                    (Some(name), _) => Step::Field(name.to_string()),
+                    (None, Ok(index)) => Step::Index(index),
+                    (None, Err(_)) => return Err(format!("invalid index [{inside}] in `{text}`")),
+                });
+                rest = &after[end + 1..];
+            } else {
+                break;
+            }
+        }
+        let rest = rest.trim();
+        if rest.is_empty() {
+            return Ok(Query {
+                path,
+                comparison: None,
+            });
+        }
+        let operators = [
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+        ];
+        let Some((operator, literal)) = operators
+            .iter()
+            .find_map(|(symbol, operator)| Some((*operator, rest.strip_prefix(symbol)?)))
+        else {
+            return Err(format!(
+                "expected a comparison after the path, got `{rest}`"
+            ));
+        };
+        let literal = json::parse(literal.trim())
+            .map_err(|_| format!("expected a JSON value to compare with, got `{literal}`"))?;
+        Ok(Query {
+            path,
+            comparison: Some((operator, literal)),
+        })
+    }
+
+    /// Whether any value the path leads to in `value` passes.
+    fn matches(&self, value: &Value) -> bool {
+        let mut found = vec![value];
+        for step in &self.path {
+            found = found
+                .into_iter()
+                .flat_map(|value| -> Vec<&Value> {
+                    match (step, value) {
+                        (Step::Field(name), Value::Record(record)) => {
+                            record.get(name).into_iter().collect()
+                        }
+                        (Step::Index(index), Value::List(list)) => {
+                            list.get(*index).into_iter().collect()
+                        }
+                        (Step::Any, Value::Record(record)) => {
+                            record.fields().map(|(_, value)| value).collect()
+                        }
+                        (Step::Any, Value::List(list)) => list.iter().collect(),
+                        _ => Vec::new(),
+                    }
+                })
+                .collect();
+        }
+        found.into_iter().any(|value| match &self.comparison {
+            None => !matches!(value, Value::Null | Value::Bool(false)),
+            Some((operator, literal)) => {
+                let order = compare(value, literal);
+                match operator {
+                    Operator::Equal => order == Some(Ordering::Equal),
+                    Operator::NotEqual => order != Some(Ordering::Equal),
+                    Operator::Less => order == Some(Ordering::Less),
+                    Operator::LessOrEqual => order.is_some_and(Ordering::is_le),
+                    Operator::Greater => order == Some(Ordering::Greater),
+                    Operator::GreaterOrEqual => order.is_some_and(Ordering::is_ge),
+                }
+            }
+        })
+    }
+}
+
+/// Numbers compare by value and strings by their text; anything else is only ever equal.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    let number = |value: &Value| match value {
+        Value::Integer(number) => Some(*number as f64),
+        Value::Unsigned(number) => Some(*number as f64),
+        Value::Float(number) => Some(*number),
+        _ => None,
+    };
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => match (number(a), number(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => (a == b).then_some(Ordering::Equal),
+        },
+    }
+}
@@ -0,0 +1,210 @@
+//! `nats latency`: publishes a small probe at a steady pace for a while, answers each one on a
+//! second connection and reports how long probes took to get there and back, e.g. to check a
+//! leaf-node link from a substation. The second connection can go to another server, the one
+//! across the link, so probes cross it both ways.
+//!
+//! Each probe carries its sequence number and the time it was sent, and its echo the time it
+//! arrived, all by the one clock of this process, so one-way latencies need no clock sync.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use edge_core::audit;
+use edge_core::exit::{self, Code};
+use edge_core::output::{Output, Record};
+use edge_core::shutdown;
+use edge_core::writes::{self, Write};
+use futures::StreamExt;
+
+use crate::bench::percentile;
+use crate::client::{self, Client};
+
+// The sequence number and the nanoseconds since the start it was sent, then in the echo the
+// nanoseconds since the start it arrived.
+const PROBE: usize = 16;
+const ECHO: usize = 24;
+// How long probes still on their way get to come back once probing stops.
+const GRACE: Duration = Duration::from_secs(2);
+
+pub(crate) struct Probes {
+    pub(crate) subject: String,
+    pub(crate) duration: Duration,
+    pub(crate) interval: Duration,
+}
+
+pub(crate) struct Report {
+    sent: u64,
+    // The sequence numbers of the probes that came back: any other run echoing probes on the
+    // same subject sends some twice.
+    returned: HashSet<u64>,
+    // Sorted, one each per probe that came back.
+    one_way: Vec<Duration>,
+    round_trip: Vec<Duration>,
+}
+
+/// Sends probes through `publisher` for `probes.duration`, or until a stop is asked for, while
+/// `responder` echoes them; checked and audited as one write.
+pub(crate) async fn run(publisher: &Client, responder: &Client, probes: &Probes) -> Result<Report> {
+    let (write, value) = (
+        Write::Nats(&probes.subject),
+        format!(
+            "probes every {} for {}",
+            humantime::format_duration(probes.interval),
+            humantime::format_duration(probes.duration)
+        ),
+    );
+    writes::check(&write, &value)?;
+    let result = probe(publisher, responder, probes).await;
+    audit::record(&write, &value, &result.as_ref().map(drop));
+    result
+}
+
+async fn probe(publisher: &Client, responder: &Client, probes: &Probes) -> Result<Report> {
+    let mut incoming = client::subscribe(responder, &probes.subject).await?;
+    let inbox = publisher.new_inbox();
+    let mut echoes = client::subscribe(publisher, &inbox).await?;
+    // Both subscriptions have to be in place before the first probe.
+    for connection in [publisher, responder] {
+        connection
+            .flush()
+            .await
+            .map_err(|err| anyhow!("Unable to flush: {err}"))?;
+    }
+    let failed = |err: &dyn std::fmt::Debug| {
+        exit::Error::new(Code::Publish, format!("Unable to publish: {err:?}"))
+    };
+
+    let start = Instant::now();
+    let echoing = {
+        let responder = responder.clone();
+        tokio::spawn(async move {
+            while let Some(message) = incoming.next().await {
+                let (Some(reply), Some(probe)) = (message.reply, message.payload.get(..PROBE))
+                else {
+                    continue;
+                };
+                let arrived = start.elapsed().as_nanos() as u64;
+                let mut echo = probe.to_vec();
+                echo.extend_from_slice(&arrived.to_be_bytes());
+                // A lost echo shows up as a lost probe.
+                let _ = responder.publish(reply, echo.into()).await;
+            }
+        })
+    };
+
+    shutdown::listen();
+    let mut ticks = tokio::time::interval(probes.interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let deadline = tokio::time::sleep(probes.duration);
+    tokio::pin!(deadline);
+    let mut report = Report {
+        sent: 0,
+        returned: HashSet::new(),
+        one_way: Vec::new(),
+        round_trip: Vec::new(),
+    };
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                let sent = start.elapsed().as_nanos() as u64;
+                let mut payload = report.sent.to_be_bytes().to_vec();
+                payload.extend_from_slice(&sent.to_be_bytes());
+                publisher
+                    .publish_with_reply(probes.subject.clone(), inbox.clone(), payload.into())
+                    .await
+                    .map_err(|err| failed(&err))?;
+                report.sent += 1;
+            }
+            Some(echo) = echoes.next() => report.add(start, &echo.payload),
+            _ = &mut deadline => break,
+            _ = shutdown::requested() => break,
+        }
+    }
+    let grace = tokio::time::sleep(GRACE);
+    tokio::pin!(grace);
+    while (report.round_trip.len() as u64) < report.sent {
+        tokio::select! {
+            Some(echo) = echoes.next() => report.add(start, &echo.payload),
+            _ = &mut grace => break,
+            _ = shutdown::requested() => break,
+        }
+    }
+    echoing.abort();
+    report.one_way.sort();
+    report.round_trip.sort();
+    Ok(report)
+}
+
+impl Report {
+    fn add(&mut self, start: Instant, echo: &[u8]) {
+        let Some(echo) = echo.get(..ECHO) else {
+            return;
+        };
+        let nanos = |range: std::ops::Range<usize>| {
+            Duration::from_nanos(u64::from_be_bytes(echo[range].try_into().expect("8 bytes")))
+        };
+        let sequence = u64::from_be_bytes(echo[..8].try_into().expect("8 bytes"));
+        if !self.returned.insert(sequence) {
+            return;
+        }
+        let (sent, arrived) = (nanos(8..PROBE), nanos(PROBE..ECHO));
+        self.one_way.push(arrived.saturating_sub(sent));
+        self.round_trip.push(start.elapsed().saturating_sub(sent));
+    }
+
+    /// How many probes came back.
+    pub(crate) fn received(&self) -> u64 {
+        self.round_trip.len() as u64
+    }
+
+    pub(crate) fn print(&self, out: &Output, subject: &str) {
+        let received = self.received();
+        let round = |value: f64| (value * 1000.0).round() / 1000.0;
+        let distribution = |sorted: &[Duration]| {
+            [
+                ("min", percentile(sorted, 0.0)),
+                ("p50", percentile(sorted, 0.5)),
+                ("p95", percentile(sorted, 0.95)),
+                ("p99", percentile(sorted, 0.99)),
+                ("max", percentile(sorted, 1.0)),
+            ]
+        };
+        let mut record = Record::new()
+            .field("subject", subject)
+            .field("sent", self.sent)
+            .field("received", received)
+            .field("lost", self.sent - received);
+        for (kind, sorted) in [("one_way", &self.one_way), ("rtt", &self.round_trip)] {
+            for (name, latency) in distribution(sorted) {
+                let millis = latency.map(|latency| round(latency.as_secs_f64() * 1000.0));
+                record.push(&format!("{kind}_{name}_ms"), millis);
+            }
+        }
+        out.record(&record, || {
+            let lost = match self.sent - received {
+                0 => String::new(),
+                lost => format!(", {lost} lost"),
+            };
+            println!(
+                "Sent {} probes on {subject}, {received} came back{lost}",
+                self.sent
+            );
+            if received == 0 {
+                return;
+            }
+            for (label, sorted) in [("One-way", &self.one_way), ("RTT", &self.round_trip)] {
+                let latencies: Vec<String> = distribution(sorted)
+                    .iter()
+                    .filter_map(|(name, latency)| {
+                        Some(format!(
+                            "{name} {:.3}ms",
+                            (*latency)?.as_secs_f64() * 1000.0
+                        ))
+                    })
+                    .collect();
+                println!("{label} {}", latencies.join(" "));
+            }
+        });
+    }
+}
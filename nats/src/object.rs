@@ -0,0 +1,339 @@
+//! Object Store buckets, for files too big for one message such as firmware images and config
+//! bundles. async-nats 0.20 has no object store, so this lays objects out the way other NATS
+//! clients do (ADR-20), and buckets are shared with them: a bucket is the stream
+//! `OBJ_<bucket>`, an object its chunks on `$O.<bucket>.C.<nuid>` and a rolled-up description
+//! on `$O.<bucket>.M.<base64 name>` with its size and SHA-256 digest.
+//!
+//! Objects are read and written whole, in memory. Storing and deleting are checked and audited
+//! like [`client::publish`], as one write each. Storing over an object purges the old one's
+//! chunks, so the CLI asks before either on a terminal.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE as BASE64_URL};
+use base64::Engine;
+use edge_core::audit;
+use edge_core::json;
+use edge_core::output::{self, Record, Value};
+use edge_core::telemetry;
+use edge_core::writes::{self, Write};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::client::{self, Client, HeaderMap};
+
+// What other clients split objects into by default.
+const CHUNK: usize = 128 * 1024;
+// How long reading waits for the next chunk before giving up on the rest.
+const IDLE: Duration = Duration::from_secs(5);
+// How long the server keeps a reading consumer around once it's left behind.
+const INACTIVE: Duration = Duration::from_secs(60);
+
+/// An object as its bucket describes it.
+#[derive(Clone, Debug)]
+pub struct Info {
+    pub name: String,
+    pub size: u64,
+    pub chunks: u64,
+    // When it was stored, RFC 3339.
+    pub modified: String,
+    // `SHA-256=` and the digest in URL-safe base64.
+    pub digest: String,
+    // Names its chunks.
+    nuid: String,
+}
+
+impl Info {
+    pub fn record(&self, bucket: &str) -> Record {
+        Record::new()
+            .field("bucket", bucket)
+            .field("name", self.name.as_str())
+            .field("size", self.size)
+            .field("chunks", self.chunks)
+            .field("modified", self.modified.as_str())
+            .field("digest", self.digest.as_str())
+    }
+}
+
+/// Stores `data` as `name` in `bucket`, replacing the object by that name and creating the
+/// bucket if there's none yet.
+pub async fn put(connection: &Client, bucket: &str, name: &str, data: &[u8]) -> Result<Info> {
+    let (subject, value) = (
+        meta_subject(bucket, name)?,
+        format!("{name}, {}", size(data)),
+    );
+    let write = Write::Nats(&subject);
+    writes::check(&write, &value)?;
+    let span = telemetry::span("nats.object.put")
+        .attribute("nats.object.bucket", bucket)
+        .attribute("messaging.message.body.size", data.len());
+    let result = async {
+        create_bucket(connection, bucket).await?;
+        let previous = info(connection, bucket, name).await?;
+        let jetstream = async_nats::jetstream::new(connection.clone());
+        let nuid = nuid::next();
+        for chunk in data.chunks(CHUNK) {
+            jetstream
+                .publish(format!("$O.{bucket}.C.{nuid}"), chunk.to_vec().into())
+                .await
+                .map_err(|err| anyhow!("Unable to store a chunk of {name}: {err}"))?;
+        }
+        let info = Info {
+            name: name.to_string(),
+            size: data.len() as u64,
+            chunks: data.chunks(CHUNK).count() as u64,
+            modified: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            digest: format!("SHA-256={}", BASE64_URL.encode(Sha256::digest(data))),
+            nuid: nuid.clone(),
+        };
+        publish_meta(connection, bucket, &meta(bucket, &info, false)).await?;
+        // Only once the new description is in place, so readers never find it without chunks.
+        if let Some(previous) = previous {
+            purge(connection, bucket, &previous.nuid).await?;
+        }
+        Ok(info)
+    }
+    .await;
+    telemetry::finish(span, "nats.objects", &[("operation", "put")], &result);
+    audit::record(&write, &value, &result.as_ref().map(drop));
+    result
+}
+
+/// The content of `name` in `bucket`, checked against its size and digest.
+pub async fn get(connection: &Client, bucket: &str, name: &str) -> Result<Vec<u8>> {
+    let span = telemetry::span("nats.object.get").attribute("nats.object.bucket", bucket);
+    let result = async {
+        let Some(info) = info(connection, bucket, name).await? else {
+            bail!("Bucket {bucket} has no object {name}");
+        };
+        let chunks = read(connection, bucket, &format!("$O.{bucket}.C.{}", info.nuid)).await?;
+        let data = chunks.concat();
+        if chunks.len() as u64 != info.chunks || data.len() as u64 != info.size {
+            bail!(
+                "Object {name} is incomplete: {} of {} in the bucket",
+                size(&data),
+                info.size
+            );
+        }
+        let digest = format!("SHA-256={}", BASE64_URL.encode(Sha256::digest(&data)));
+        if digest != info.digest {
+            bail!("Object {name} doesn't match its digest");
+        }
+        Ok(data)
+    }
+    .await;
+    telemetry::finish(span, "nats.objects", &[("operation", "get")], &result);
+    result
+}
+
+/// The objects in `bucket`, by name.
+pub async fn list(connection: &Client, bucket: &str) -> Result<Vec<Info>> {
+    if stream_info(connection, bucket).await?.is_none() {
+        bail!("There's no bucket {bucket}");
+    }
+    let metas = read(connection, bucket, &format!("$O.{bucket}.M.>")).await?;
+    let mut infos = Vec::new();
+    for meta in metas {
+        if let Some(info) = parse_meta(&meta)? {
+            infos.push(info);
+        }
+    }
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(infos)
+}
+
+/// Deletes `name` from `bucket`, chunks and all.
+pub async fn delete(connection: &Client, bucket: &str, name: &str) -> Result<()> {
+    let subject = meta_subject(bucket, name)?;
+    let write = Write::Nats(&subject);
+    let value = format!("delete {name}");
+    writes::check(&write, &value)?;
+    let result = async {
+        let Some(info) = info(connection, bucket, name).await? else {
+            bail!("Bucket {bucket} has no object {name}");
+        };
+        publish_meta(connection, bucket, &meta(bucket, &info, true)).await?;
+        purge(connection, bucket, &info.nuid).await
+    }
+    .await;
+    audit::record(&write, &value, &result);
+    result
+}
+
+/// Where the description of `name` goes; bucket names are letters, digits, `-` and `_`.
+fn meta_subject(bucket: &str, name: &str) -> Result<String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if bucket.is_empty() || !bucket.chars().all(valid) {
+        bail!("Invalid bucket name {bucket:?}");
+    }
+    if name.is_empty() {
+        bail!("Objects need a name");
+    }
+    Ok(format!("$O.{bucket}.M.{}", BASE64_URL.encode(name)))
+}
+
+async fn stream_info(connection: &Client, bucket: &str) -> Result<Option<Record>> {
+    client::api(
+        connection,
+        &format!("STREAM.INFO.OBJ_{bucket}"),
+        Record::new(),
+    )
+    .await
+}
+
+async fn create_bucket(connection: &Client, bucket: &str) -> Result<()> {
+    if stream_info(connection, bucket).await?.is_some() {
+        return Ok(());
+    }
+    let stream = format!("OBJ_{bucket}");
+    let subjects = vec![
+        Value::String(format!("$O.{bucket}.C.>")),
+        Value::String(format!("$O.{bucket}.M.>")),
+    ];
+    let config = Record::new()
+        .field("name", stream.as_str())
+        .field("subjects", Value::List(subjects))
+        .field("retention", "limits")
+        .field("storage", "file")
+        .field("discard", "new")
+        .field("num_replicas", 1)
+        .field("allow_rollup_hdrs", true)
+        .field("allow_direct", true);
+    match client::api(connection, &format!("STREAM.CREATE.{stream}"), config).await? {
+        Some(_) => Ok(()),
+        None => bail!("Unable to create bucket {bucket}"),
+    }
+}
+
+/// The current description of `name`, None when there's no such object or it was deleted.
+pub async fn info(connection: &Client, bucket: &str, name: &str) -> Result<Option<Info>> {
+    let body = Record::new().field("last_by_subj", meta_subject(bucket, name)?);
+    let request = format!("STREAM.MSG.GET.OBJ_{bucket}");
+    let Some(response) = client::api(connection, &request, body).await? else {
+        return Ok(None);
+    };
+    let data = match response.get("message") {
+        Some(Value::Record(message)) => client::text(message, "data")?,
+        _ => bail!("JetStream left out the message"),
+    };
+    let meta = BASE64
+        .decode(data)
+        .map_err(|err| anyhow!("Unreadable description of {name}: {err}"))?;
+    parse_meta(&meta)
+}
+
+fn meta(bucket: &str, info: &Info, deleted: bool) -> Record {
+    let mut meta = Record::new()
+        .field("name", info.name.as_str())
+        .field("bucket", bucket)
+        .field("nuid", info.nuid.as_str())
+        .field("size", if deleted { 0 } else { info.size })
+        .field("chunks", if deleted { 0 } else { info.chunks })
+        .field("mtime", info.modified.as_str())
+        .field("digest", info.digest.as_str())
+        .field("options", Record::new().field("max_chunk_size", CHUNK));
+    if deleted {
+        meta.push("deleted", true);
+    }
+    meta
+}
+
+fn parse_meta(meta: &[u8]) -> Result<Option<Info>> {
+    let Ok(Value::Record(meta)) = json::parse(&String::from_utf8_lossy(meta)) else {
+        bail!("Unreadable object description");
+    };
+    if matches!(meta.get("deleted"), Some(Value::Bool(true))) {
+        return Ok(None);
+    }
+    let number = |name: &str| match meta.get(name) {
+        Some(Value::Integer(number)) => *number as u64,
+        _ => 0,
+    };
+    Ok(Some(Info {
+        name: client::text(&meta, "name")?,
+        size: number("size"),
+        chunks: number("chunks"),
+        modified: client::text(&meta, "mtime").unwrap_or_default(),
+        digest: client::text(&meta, "digest").unwrap_or_default(),
+        nuid: client::text(&meta, "nuid")?,
+    }))
+}
+
+/// Publishes a description, replacing the one before it.
+async fn publish_meta(connection: &Client, bucket: &str, meta: &Record) -> Result<()> {
+    let name = client::text(meta, "name")?;
+    let mut headers = HeaderMap::new();
+    headers.insert("Nats-Rollup", "sub");
+    async_nats::jetstream::new(connection.clone())
+        .publish_with_headers(
+            meta_subject(bucket, &name)?,
+            headers,
+            output::json(&Value::Record(meta.clone())).into(),
+        )
+        .await
+        .map_err(|err| anyhow!("Unable to store the description of {name}: {err}"))?;
+    Ok(())
+}
+
+async fn purge(connection: &Client, bucket: &str, nuid: &str) -> Result<()> {
+    let body = Record::new().field("filter", format!("$O.{bucket}.C.{nuid}"));
+    match client::api(connection, &format!("STREAM.PURGE.OBJ_{bucket}"), body).await? {
+        Some(_) => Ok(()),
+        None => bail!("There's no bucket {bucket}"),
+    }
+}
+
+/// The payloads of every message of the bucket on `filter`, oldest first, through a consumer
+/// of its own.
+async fn read(connection: &Client, bucket: &str, filter: &str) -> Result<Vec<Vec<u8>>> {
+    let stream = format!("OBJ_{bucket}");
+    let config = Record::new()
+        .field("filter_subject", filter)
+        .field("deliver_policy", "all")
+        .field("ack_policy", "none")
+        .field("inactive_threshold", INACTIVE.as_nanos() as u64);
+    let body = Record::new()
+        .field("stream_name", stream.as_str())
+        .field("config", config);
+    let request = format!("CONSUMER.CREATE.{stream}");
+    let Some(consumer) = client::api(connection, &request, body).await? else {
+        bail!("There's no bucket {bucket}");
+    };
+    let name = client::text(&consumer, "name")?;
+    let pending = match consumer.get("num_pending") {
+        Some(Value::Integer(pending)) => *pending as u64,
+        _ => 0,
+    };
+    let inbox = connection.new_inbox();
+    let mut subscription = client::subscribe(connection, &inbox).await?;
+    let mut payloads = Vec::new();
+    if pending > 0 {
+        let body = Record::new().field("batch", pending).field("no_wait", true);
+        connection
+            .publish_with_reply(
+                format!("$JS.API.CONSUMER.MSG.NEXT.{stream}.{name}"),
+                inbox,
+                output::json(&Value::Record(body)).into(),
+            )
+            .await
+            .map_err(|err| anyhow!("Unable to read bucket {bucket}: {err}"))?;
+    }
+    while (payloads.len() as u64) < pending {
+        match tokio::time::timeout(IDLE, subscription.next()).await {
+            // A status such as 404 when there's nothing more after all.
+            Ok(Some(message)) if message.status.is_some() => break,
+            Ok(Some(message)) => payloads.push(message.payload.to_vec()),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    let request = format!("CONSUMER.DELETE.{stream}.{name}");
+    if let Err(err) = client::api(connection, &request, Record::new()).await {
+        log::debug!("Leaving consumer {name} to expire: {err}");
+    }
+    Ok(payloads)
+}
+
+fn size(data: &[u8]) -> String {
+    format!("{} bytes", data.len())
+}
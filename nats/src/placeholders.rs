@@ -0,0 +1,155 @@
+//! Placeholders in `publish --message`, filled in again for every message sent, so that with
+//! `--repeat` one command generates a stream of realistic test data:
+//!
+//! ```text
+//! {"seq":{{seq}},"at":"{{timestamp}}","id":"{{uuid}}","temperature":{{rand(18.0,26.5)}}}
+//! ```
+//!
+//! `{{seq}}` counts the messages from 1, `{{timestamp}}` is when the message was made, in
+//! RFC 3339, `{{uuid}}` a random (version 4) UUID and `{{rand(min,max)}}` a random number from
+//! min to max, both included, with as many decimals as the bounds are written with. `{{{{`
+//! stands for a `{{` that isn't one, so `{{{{seq}}` sends `{{seq}}`. Payloads read from
+//! `--file` or stdin are sent as they are.
+
+use std::time::SystemTime;
+
+use rand::Rng;
+
+/// What `publish` sends: the same bytes every time, or text with placeholders.
+pub(crate) enum Message {
+    Fixed(Vec<u8>),
+    Template(Vec<Part>),
+}
+
+pub(crate) enum Part {
+    Text(String),
+    Seq,
+    Timestamp,
+    Uuid,
+    Integer(i64, i64),
+    Decimal { min: f64, max: f64, decimals: usize },
+}
+
+impl Message {
+    /// `text` with its placeholders, if it has any; an unknown or unclosed one is an error, for
+    /// the command line.
+    pub(crate) fn parse(text: &str) -> Result<Message, String> {
+        let mut parts = Vec::new();
+        // The text since the last placeholder, with its escapes undone.
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+            if let Some(after) = rest.strip_prefix("{{") {
+                literal.push_str("{{");
+                rest = after;
+                continue;
+            }
+            let Some(length) = rest.find("}}") else {
+                return Err(format!(
+                    "unclosed {{{{ in `{text}`; write {{{{{{{{ for a {{{{ that isn't a placeholder"
+                ));
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::parse(rest[..length].trim())?);
+            rest = &rest[length + 2..];
+        }
+        literal.push_str(rest);
+        if parts.is_empty() {
+            return Ok(Message::Fixed(literal.into_bytes()));
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Message::Template(parts))
+    }
+
+    /// The payload of the `seq`-th message.
+    pub(crate) fn render(&self, seq: u64) -> Vec<u8> {
+        let parts = match self {
+            Message::Fixed(payload) => return payload.clone(),
+            Message::Template(parts) => parts,
+        };
+        let mut rng = rand::thread_rng();
+        let mut rendered = String::new();
+        for part in parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Seq => rendered.push_str(&seq.to_string()),
+                Part::Timestamp => {
+                    let now = humantime::format_rfc3339_millis(SystemTime::now());
+                    rendered.push_str(&now.to_string());
+                }
+                Part::Uuid => rendered.push_str(&uuid(&mut rng)),
+                Part::Integer(min, max) => {
+                    rendered.push_str(&rng.gen_range(*min..=*max).to_string())
+                }
+                Part::Decimal { min, max, decimals } => {
+                    let value = rng.gen_range(*min..=*max);
+                    rendered.push_str(&format!("{value:.decimals$}"));
+                }
+            }
+        }
+        rendered.into_bytes()
+    }
+}
+
+impl Part {
+    fn parse(placeholder: &str) -> Result<Part, String> {
+        match placeholder {
+            "seq" => return Ok(Part::Seq),
+            "timestamp" => return Ok(Part::Timestamp),
+            "uuid" => return Ok(Part::Uuid),
+            _ => {}
+        }
+        let bounds = placeholder
+            .strip_prefix("rand(")
+            .and_then(|arguments| arguments.strip_suffix(')'))
+            .and_then(|arguments| arguments.split_once(','));
+        let Some((min, max)) = bounds.map(|(min, max)| (min.trim(), max.trim())) else {
+            return Err(format!(
+                "unknown placeholder {{{{{placeholder}}}}}, expected seq, timestamp, uuid or rand(min,max); \
+                 write {{{{{{{{ for a {{{{ that isn't a placeholder"
+            ));
+        };
+        let invalid = || format!("expected two numbers, the lower first, in {{{{{placeholder}}}}}");
+        if let (Ok(low), Ok(high)) = (min.parse(), max.parse()) {
+            return match low <= high {
+                true => Ok(Part::Integer(low, high)),
+                false => Err(invalid()),
+            };
+        }
+        let (low, high) = match (min.parse::<f64>(), max.parse::<f64>()) {
+            (Ok(low), Ok(high)) if low <= high && low.is_finite() && high.is_finite() => {
+                (low, high)
+            }
+            _ => return Err(invalid()),
+        };
+        let decimals = |bound: &str| bound.split_once('.').map_or(0, |(_, digits)| digits.len());
+        Ok(Part::Decimal {
+            min: low,
+            max: high,
+            decimals: decimals(min).max(decimals(max)),
+        })
+    }
+}
+
+/// A random UUID, as in `0b7c6f3e-9d1a-4c2e-8f5b-3a6d2e1c9b70`.
+fn uuid(rng: &mut impl Rng) -> String {
+    let mut bytes: [u8; 16] = rng.gen();
+    // The version, 4, and the RFC 4122 variant.
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
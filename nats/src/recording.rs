@@ -0,0 +1,229 @@
+//! `nats record` and `nats replay`: messages written to a file as they arrive, to publish again
+//! later with the same spacing, e.g. to reproduce a field incident in the lab. Each message is
+//! one JSON line with the time it came, its subject, reply subject and headers when it had
+//! them, and the payload as text, or in base64 when it isn't UTF-8:
+//!
+//! ```text
+//! {"timestamp":"2026-10-14T08:32:30.412518Z","subject":"site.meter","headers":{"Unit":"V"},"payload":"230.1"}
+//! {"timestamp":"2026-10-14T08:32:31.412730Z","subject":"site.blob","payload_base64":"AJ+Slg=="}
+//! ```
+//!
+//! Replies go nowhere on replay, since nothing waits on the recorded inboxes any more.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use edge_core::json;
+use edge_core::output::{self, Output, Record, Value};
+use edge_core::shutdown::{self, Summary};
+use futures::StreamExt;
+
+use crate::client::{self, Client};
+use crate::{flush, header_record, message_headers, queue, Destination};
+
+struct Entry {
+    timestamp: SystemTime,
+    subject: String,
+    reply: Option<String>,
+    headers: Vec<(String, String)>,
+    payload: Vec<u8>,
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        let timestamp = humantime::format_rfc3339_micros(self.timestamp).to_string();
+        let mut record = Record::new()
+            .field("timestamp", timestamp)
+            .field("subject", self.subject.as_str());
+        if let Some(reply) = &self.reply {
+            record.push("reply", reply.as_str());
+        }
+        if !self.headers.is_empty() {
+            record.push("headers", header_record(&self.headers));
+        }
+        match std::str::from_utf8(&self.payload) {
+            Ok(text) => record.push("payload", text),
+            Err(_) => record.push("payload_base64", BASE64.encode(&self.payload)),
+        }
+        output::json(&Value::Record(record))
+    }
+
+    fn parse(line: &str) -> Result<Entry> {
+        let Ok(Value::Record(record)) = json::parse(line) else {
+            bail!("not a JSON object");
+        };
+        let text = |name: &str| match record.get(name) {
+            Some(Value::String(text)) => Some(text.clone()),
+            _ => None,
+        };
+        let timestamp = text("timestamp").ok_or_else(|| anyhow!("no timestamp"))?;
+        let timestamp = humantime::parse_rfc3339_weak(&timestamp)
+            .map_err(|err| anyhow!("invalid timestamp {timestamp}: {err}"))?;
+        let mut headers = Vec::new();
+        if let Some(Value::Record(recorded)) = record.get("headers") {
+            for (key, value) in recorded.fields() {
+                match value {
+                    Value::String(value) => headers.push((key.to_string(), value.clone())),
+                    Value::List(values) => {
+                        for value in values {
+                            if let Value::String(value) = value {
+                                headers.push((key.to_string(), value.clone()));
+                            }
+                        }
+                    }
+                    _ => bail!("invalid value for header {key}"),
+                }
+            }
+        }
+        let payload = match (text("payload"), text("payload_base64")) {
+            (Some(payload), _) => payload.into_bytes(),
+            (None, Some(encoded)) => BASE64
+                .decode(encoded)
+                .map_err(|err| anyhow!("invalid payload_base64: {err}"))?,
+            (None, None) => Vec::new(),
+        };
+        Ok(Entry {
+            timestamp,
+            subject: text("subject").ok_or_else(|| anyhow!("no subject"))?,
+            reply: text("reply"),
+            headers,
+            payload,
+        })
+    }
+}
+
+/// Writes the messages on `subject` to `path` until `count` came, `duration` is up or a stop is
+/// asked for.
+pub(crate) async fn record(
+    connection: &Client,
+    subject: &str,
+    path: &Path,
+    count: Option<u64>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .map_err(|err| anyhow!("Unable to create {}: {err}", path.display()))?;
+    let mut file = BufWriter::new(file);
+    let mut messages = client::subscribe(connection, subject).await?;
+    shutdown::listen();
+    let summary = Summary::new(&["messages"]);
+    let deadline = async {
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    let mut recorded = 0;
+    while count.is_none_or(|count| recorded < count) {
+        let message = tokio::select! {
+            message = messages.next() => message,
+            _ = &mut deadline => break,
+            _ = shutdown::requested() => break,
+        };
+        let Some(message) = message else {
+            break;
+        };
+        let entry = Entry {
+            timestamp: SystemTime::now(),
+            headers: message_headers(&message),
+            subject: message.subject,
+            reply: message.reply,
+            payload: message.payload.to_vec(),
+        };
+        writeln!(file, "{}", entry.to_line())
+            .map_err(|err| anyhow!("Unable to write {}: {err}", path.display()))?;
+        recorded += 1;
+        summary.count("messages");
+    }
+    file.flush()
+        .map_err(|err| anyhow!("Unable to write {}: {err}", path.display()))?;
+    summary.print();
+    Ok(())
+}
+
+/// The slowest and fastest `--speed` besides 0; within them the delays can't overflow.
+const SPEEDS: std::ops::RangeInclusive<f64> = 0.001..=1000.0;
+
+/// `--speed`: 0, or a factor within [`SPEEDS`].
+pub(crate) fn parse_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid speed {value}"))?;
+    if speed == 0.0 || SPEEDS.contains(&speed) {
+        Ok(speed)
+    } else {
+        Err(format!(
+            "the speed must be 0 or from {} to {}",
+            SPEEDS.start(),
+            SPEEDS.end()
+        ))
+    }
+}
+
+/// Publishes the messages recorded in `path` to `destination`, `speed` times as fast as they
+/// came; all at once for a speed of 0.
+pub(crate) async fn replay(
+    destination: &Destination<'_>,
+    out: &Output,
+    path: &Path,
+    speed: f64,
+) -> Result<()> {
+    let file = std::fs::File::open(path)
+        .map_err(|err| anyhow!("Unable to open {}: {err}", path.display()))?;
+    shutdown::listen();
+    let summary = Summary::new(&["messages"]);
+    // When the first message came and when it was published again.
+    let mut first: Option<(SystemTime, Instant)> = None;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| anyhow!("Unable to read {}: {err}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = Entry::parse(&line)
+            .map_err(|err| anyhow!("Line {} of {}: {err}", number + 1, path.display()))?;
+        let (recorded, started) = *first.get_or_insert((entry.timestamp, Instant::now()));
+        if speed > 0.0 {
+            let offset = entry.timestamp.duration_since(recorded).unwrap_or_default();
+            let due = started + offset.div_f64(speed);
+            tokio::select! {
+                _ = tokio::time::sleep_until(due.into()) => {}
+                _ = shutdown::requested() => break,
+            }
+        } else if shutdown::is_requested() {
+            break;
+        }
+        queue(
+            destination,
+            out,
+            entry.subject,
+            &entry.headers,
+            entry.payload,
+        )
+        .await?;
+        summary.count("messages");
+    }
+    flush(destination).await?;
+    summary.print();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speeds_are_0_or_within_range() {
+        assert_eq!(parse_speed("0"), Ok(0.0));
+        assert_eq!(parse_speed("0.5"), Ok(0.5));
+        assert_eq!(parse_speed("1000"), Ok(1000.0));
+        for speed in ["-1", "0.0001", "1001", "inf", "NaN", "fast"] {
+            assert!(parse_speed(speed).is_err(), "{speed}");
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! `nats list-subjects`: the subjects messages are published on, with how many messages and
+//! bytes went by on each and how fast, as a table once the listing stops: after a
+//! `--duration`, at the `--max-subjects`-th subject or when stopped with Ctrl-C, which also
+//! ends with a summary on stderr.
+//!
+//! With `--tree` the subjects are shown by token instead, and a branch with more tokens below
+//! it than `--collapse` has them merged into one `*`, so a thousand devices read as one:
+//!
+//! ```text
+//! BRANCH             MESSAGES         BYTES  SUBJECTS
+//! plant.*                5000        146000      3000
+//!   sensor               4000        144000      2000
+//!     humidity           2000         72000      1000
+//!     temperature        2000         72000      1000
+//!   status               1000          2000      1000
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use edge_core::output::{Output, Record};
+use edge_core::shutdown::{self, Summary};
+use futures::{FutureExt, StreamExt};
+
+use crate::client::{self, Client};
+
+pub(crate) struct Listing {
+    // Leave out `_INBOX` reply subjects.
+    pub(crate) skip_inboxes: bool,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) max_subjects: Option<usize>,
+    // Show the subjects as a tree, merging the tokens below a branch with more than this many.
+    pub(crate) tree: Option<usize>,
+}
+
+#[derive(Default)]
+struct Traffic {
+    messages: u64,
+    bytes: u64,
+}
+
+pub(crate) async fn run(connection: &Client, out: &Output, listing: &Listing) -> Result<()> {
+    let mut messages = Box::pin(client::traffic(connection, listing.skip_inboxes).await?);
+    shutdown::listen();
+    let start = Instant::now();
+    let deadline = async {
+        match listing.duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    let summary = Summary::new(&["messages"]);
+    let mut subjects: HashMap<String, Traffic> = HashMap::new();
+    let mut stopping = false;
+    loop {
+        let message = if stopping {
+            // Once stopping, only the messages the client already holds are counted.
+            match messages.next().now_or_never() {
+                Some(message) => message,
+                None => break,
+            }
+        } else {
+            tokio::select! {
+                message = messages.next() => message,
+                _ = &mut deadline => break,
+                _ = shutdown::requested() => {
+                    stopping = true;
+                    continue;
+                }
+            }
+        };
+        let Some((subject, bytes)) = message else {
+            break;
+        };
+        summary.count("messages");
+        let traffic = subjects.entry(subject).or_default();
+        traffic.messages += 1;
+        traffic.bytes += bytes as u64;
+        if listing
+            .max_subjects
+            .is_some_and(|max| subjects.len() >= max)
+        {
+            break;
+        }
+    }
+    summary.add("subjects", subjects.len() as u64);
+    match listing.tree {
+        Some(collapse) => print_tree(out, &subjects, collapse),
+        None => print(out, subjects, start.elapsed()),
+    }
+    if stopping {
+        summary.print();
+    }
+    Ok(())
+}
+
+/// The busiest subjects first, with their rate over the whole listing.
+fn print(out: &Output, subjects: HashMap<String, Traffic>, elapsed: Duration) {
+    let mut subjects: Vec<(String, Traffic)> = subjects.into_iter().collect();
+    subjects.sort_by(|(a, a_traffic), (b, b_traffic)| {
+        b_traffic.messages.cmp(&a_traffic.messages).then(a.cmp(b))
+    });
+    let seconds = elapsed.as_secs_f64();
+    let rate = |messages: u64| match seconds > 0.0 {
+        true => (messages as f64 / seconds * 10.0).round() / 10.0,
+        false => 0.0,
+    };
+    let records: Vec<Record> = subjects
+        .iter()
+        .map(|(subject, traffic)| {
+            Record::new()
+                .field("subject", subject.as_str())
+                .field("messages", traffic.messages)
+                .field("bytes", traffic.bytes)
+                .field("per_second", rate(traffic.messages))
+        })
+        .collect();
+    out.records(&records, || {
+        let width = subjects
+            .iter()
+            .map(|(subject, _)| subject.len())
+            .max()
+            .unwrap_or_default()
+            .max("SUBJECT".len());
+        println!(
+            "{:<width$}  {:>10}  {:>12}  {:>9}",
+            "SUBJECT", "MESSAGES", "BYTES", "MSGS/S"
+        );
+        for (subject, traffic) in &subjects {
+            println!(
+                "{subject:<width$}  {:>10}  {:>12}  {:>9.1}",
+                traffic.messages,
+                traffic.bytes,
+                rate(traffic.messages)
+            );
+        }
+    });
+}
+
+/// Subjects sharing the tokens up to here, counted together.
+#[derive(Default)]
+struct Branch {
+    children: BTreeMap<String, Branch>,
+    messages: u64,
+    bytes: u64,
+    subjects: u64,
+    // Of those, the subjects that end here rather than further down.
+    ends: u64,
+}
+
+impl Branch {
+    fn add(&mut self, subject: &str, traffic: &Traffic) {
+        let mut branch = self;
+        for token in subject.split('.') {
+            branch = branch.children.entry(token.to_string()).or_default();
+            branch.messages += traffic.messages;
+            branch.bytes += traffic.bytes;
+            branch.subjects += 1;
+        }
+        branch.ends += 1;
+    }
+
+    fn merge(&mut self, other: Branch) {
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+        self.subjects += other.subjects;
+        self.ends += other.ends;
+        for (token, child) in other.children {
+            self.children.entry(token).or_default().merge(child);
+        }
+    }
+
+    /// Merges the children of every branch with more than `most` of them into a `*`.
+    fn collapse(&mut self, most: usize) {
+        if self.children.len() > most {
+            let mut merged = Branch::default();
+            for (_, child) in std::mem::take(&mut self.children) {
+                merged.merge(child);
+            }
+            self.children.insert("*".to_string(), merged);
+        }
+        for child in self.children.values_mut() {
+            child.collapse(most);
+        }
+    }
+
+    /// The branches below this one, depth first; a branch with one child and no subjects of
+    /// its own shares a row with it, as in `plant.*`.
+    fn rows<'a>(&'a self, prefix: &str, depth: usize, rows: &mut Vec<Row<'a>>) {
+        for (token, child) in &self.children {
+            let (mut label, mut child) = (token.clone(), child);
+            while child.ends == 0 && child.children.len() == 1 {
+                let (next, grandchild) = child.children.iter().next().expect("one child");
+                label = format!("{label}.{next}");
+                child = grandchild;
+            }
+            let path = match prefix {
+                "" => label.clone(),
+                prefix => format!("{prefix}.{label}"),
+            };
+            rows.push(Row {
+                path: path.clone(),
+                label,
+                depth,
+                branch: child,
+            });
+            child.rows(&path, depth + 1, rows);
+        }
+    }
+}
+
+struct Row<'a> {
+    path: String,
+    // What's printed for the branch, the tokens that path adds to the row above.
+    label: String,
+    depth: usize,
+    branch: &'a Branch,
+}
+
+fn print_tree(out: &Output, subjects: &HashMap<String, Traffic>, collapse: usize) {
+    let mut root = Branch::default();
+    for (subject, traffic) in subjects {
+        root.add(subject, traffic);
+    }
+    root.collapse(collapse.max(1));
+    let mut rows = Vec::new();
+    root.rows("", 0, &mut rows);
+    let records: Vec<Record> = rows
+        .iter()
+        .map(|row| {
+            Record::new()
+                .field("branch", row.path.as_str())
+                .field("depth", row.depth)
+                .field("messages", row.branch.messages)
+                .field("bytes", row.branch.bytes)
+                .field("subjects", row.branch.subjects)
+        })
+        .collect();
+    out.records(&records, || {
+        let indented: Vec<String> = rows
+            .iter()
+            .map(|row| format!("{}{}", "  ".repeat(row.depth), row.label))
+            .collect();
+        let width = indented
+            .iter()
+            .map(String::len)
+            .max()
+            .unwrap_or_default()
+            .max("BRANCH".len());
+        println!(
+            "{:<width$}  {:>10}  {:>12}  {:>8}",
+            "BRANCH", "MESSAGES", "BYTES", "SUBJECTS"
+        );
+        for (label, row) in indented.iter().zip(&rows) {
+            let branch = row.branch;
+            println!(
+                "{label:<width$}  {:>10}  {:>12}  {:>8}",
+                branch.messages, branch.bytes, branch.subjects
+            );
+        }
+    });
+}
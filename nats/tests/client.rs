@@ -0,0 +1,69 @@
+//! The client API against an in-process server.
+
+use edge_core::auth::Credentials;
+use edge_core::exit::{self, Code};
+use edge_core::retry::Retry;
+use futures::StreamExt;
+use nats::client::{self, Options};
+use simulators::NatsSimulator;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_with_a_password() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_user("edge", "secret");
+    let options = Options::new(server.url()).credentials(Credentials {
+        username: Some("edge".to_string()),
+        password: Some("secret".to_string()),
+        ..Credentials::default()
+    });
+
+    // Spawned to check the futures can move between threads, as in a gateway.
+    let task = tokio::spawn(async move {
+        let connection = client::connect(&options).await?;
+        client::publish(&connection, "site.status", b"up".to_vec()).await
+    });
+
+    task.await.unwrap().unwrap();
+    assert!(server.wait_for_messages(1, TIMEOUT).await);
+    assert_eq!(server.messages()[0].payload, b"up");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_logins_carry_the_auth_code() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_token("right");
+    let options = Options::new(server.url())
+        .credentials(Credentials {
+            token: Some("wrong".to_string()),
+            ..Credentials::default()
+        })
+        .retry(Retry {
+            attempts: 1,
+            ..Retry::default()
+        });
+
+    let err = client::connect(&options).await.unwrap_err();
+
+    let err = err.downcast_ref::<exit::Error>().expect("an exit::Error");
+    assert_eq!(err.code, Code::Auth);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_each_subject_once() {
+    let server = NatsSimulator::start().await.unwrap();
+    let connection = client::connect(&Options::new(server.url())).await.unwrap();
+    let subjects = client::subjects(&connection, true).await.unwrap();
+    assert!(server.wait_for_subscription(">", TIMEOUT).await);
+
+    for subject in ["a.one", "_INBOX.x", "a.one", "b.two"] {
+        server.publish(subject, b"");
+    }
+
+    let subjects: Vec<String> = tokio::time::timeout(TIMEOUT, subjects.take(2).collect())
+        .await
+        .unwrap();
+    assert_eq!(subjects, ["a.one", "b.two"]);
+}
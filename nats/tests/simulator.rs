@@ -0,0 +1,1596 @@
+//! The `nats` binary against an in-process server.
+
+use edge_core::json;
+use edge_core::output::Value;
+use simulators::NatsSimulator;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+use tokio::process::Command;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn nats() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_nats"));
+    command.env_clear().kill_on_drop(true);
+    command
+}
+
+async fn run(args: &[&str]) -> Output {
+    tokio::time::timeout(TIMEOUT, nats().args(args).output())
+        .await
+        .expect("nats finishes")
+        .expect("nats runs")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[&server.url(), "publish", "-s", "site.meter", "-m", "230.1"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let messages = server.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].subject, "site.meter");
+    assert_eq!(messages[0].payload, b"230.1");
+}
+
+/// The URL of a port nothing listens on any more.
+fn dead_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("nats://{}", listener.local_addr().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_through_whichever_seed_answers() {
+    let server = NatsSimulator::start().await.unwrap();
+    let seeds = format!("{},{}", dead_url(), server.url());
+
+    let output = run(&[&seeds, "publish", "-s", "site.meter", "-m", "230.1"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreachable_servers_exit_with_connection_error() {
+    let url = dead_url();
+
+    let published = run(&[&url, "--retries", "0", "publish", "-s", "site.a", "-m", "1"]).await;
+    let subscribed = run(&[&url, "--retries", "0", "subscribe", "-s", "site.>"]).await;
+
+    assert_eq!(published.status.code(), Some(3));
+    assert_eq!(subscribed.status.code(), Some(3));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_subscriptions_exit_with_subscribe_error() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.deny_subscribe("site.>");
+
+    let output = run(&[&server.url(), "subscribe", "-s", "site.meter", "--watch"]).await;
+
+    assert_eq!(output.status.code(), Some(8), "{output:?}");
+    assert!(server.subscriptions().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refused_publishes_exit_with_publish_error() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.deny_publish("site.>");
+
+    let output = run(&[
+        &server.url(),
+        "publish",
+        "-s",
+        "site.meter",
+        "-m",
+        "230.1",
+        "--repeat",
+        "0",
+        "--interval",
+        "100ms",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(9), "{output:?}");
+    assert!(server.messages().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn payloads_the_server_cannot_take_exit_with_publish_error() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.set_max_payload(16);
+
+    let output = run(&[
+        &server.url(),
+        "publish",
+        "-s",
+        "site.a",
+        "-m",
+        &"x".repeat(17),
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(9), "{output:?}");
+    assert!(server.messages().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_repeatedly_at_an_interval() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let started = std::time::Instant::now();
+    let output = run(&[
+        &server.url(),
+        "publish",
+        "-s",
+        "site.soak",
+        "-m",
+        "ping",
+        "--repeat",
+        "3",
+        "--interval",
+        "200ms",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let payloads: Vec<Vec<u8>> = server.messages().into_iter().map(|m| m.payload).collect();
+    assert_eq!(payloads, [b"ping", b"ping", b"ping"]);
+    // Two intervals after the first message.
+    assert!(started.elapsed() >= Duration::from_millis(400));
+}
+
+async fn run_with_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = nats()
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut stdin, input.as_bytes())
+        .await
+        .unwrap();
+    drop(stdin);
+    tokio::time::timeout(TIMEOUT, child.wait_with_output())
+        .await
+        .expect("nats finishes")
+        .expect("nats runs")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_each_line_of_stdin() {
+    let server = NatsSimulator::start().await.unwrap();
+    let url = server.url();
+
+    let output = run_with_stdin(
+        &[&url, "publish", "-s", "site.log", "--stdin-lines"],
+        "boot\r\n\nlink up\n",
+    )
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let payloads: Vec<Vec<u8>> = server.messages().into_iter().map(|m| m.payload).collect();
+    assert_eq!(payloads, [b"boot".to_vec(), b"link up".to_vec()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_tsv_lines_on_their_subject() {
+    let server = NatsSimulator::start().await.unwrap();
+    let url = server.url();
+    let input = "site.a\t1\nno subject\nsite.b\tx\ty\n";
+
+    let output = run_with_stdin(
+        &[
+            &url,
+            "publish",
+            "-s",
+            "site.other",
+            "--stdin-lines",
+            "--tsv",
+        ],
+        input,
+    )
+    .await;
+    let without_fallback =
+        run_with_stdin(&[&url, "publish", "--stdin-lines", "--tsv"], input).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(without_fallback.status.code(), Some(0));
+    let published: Vec<(String, Vec<u8>)> = server
+        .messages()
+        .into_iter()
+        .map(|message| (message.subject, message.payload))
+        .collect();
+    let expected = |subject: &str, payload: &[u8]| (subject.to_string(), payload.to_vec());
+    assert_eq!(
+        published,
+        [
+            expected("site.a", b"1"),
+            expected("site.other", b"no subject"),
+            expected("site.b", b"x\ty"),
+            expected("site.a", b"1"),
+            expected("site.b", b"x\ty"),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fills_in_placeholders_for_each_message() {
+    let server = NatsSimulator::start().await.unwrap();
+    let template =
+        r#"{"seq":{{seq}},"value":{{rand(0,100)}},"id":"{{uuid}}","at":"{{timestamp}}"}"#;
+
+    let output = run(&[
+        &server.url(),
+        "publish",
+        "-s",
+        "site.sensor",
+        "-m",
+        template,
+        "--repeat",
+        "3",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let messages = server.messages();
+    assert_eq!(messages.len(), 3);
+    let mut ids = Vec::new();
+    for (seq, message) in (1..).zip(&messages) {
+        let Value::Record(record) =
+            json::parse(std::str::from_utf8(&message.payload).unwrap()).unwrap()
+        else {
+            panic!("not a record");
+        };
+        assert_eq!(record.get("seq"), Some(&Value::Integer(seq)));
+        let Some(Value::Integer(value)) = record.get("value") else {
+            panic!("no value");
+        };
+        assert!((0..=100).contains(value));
+        let Some(Value::String(id)) = record.get("id") else {
+            panic!("no id");
+        };
+        assert_eq!((id.len(), &id[14..15]), (36, "4"));
+        ids.push(id.clone());
+        assert!(matches!(record.get("at"), Some(Value::String(at)) if at.ends_with('Z')));
+    }
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_placeholders_are_usage_errors() {
+    let output = run(&[
+        "nats://127.0.0.1:1",
+        "publish",
+        "-s",
+        "a",
+        "-m",
+        "{{sequence}}",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn escaped_braces_are_sent_as_they_are() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    for message in ["{{{{seq}} is {{seq}}", "{\"a\":[{{{{}}]}"] {
+        let output = run(&[&server.url(), "publish", "-s", "site.text", "-m", message]).await;
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    let payloads: Vec<Vec<u8>> = server.messages().into_iter().map(|m| m.payload).collect();
+    assert_eq!(payloads, [&b"{{seq}} is 1"[..], br#"{"a":[{{}}]}"#]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_binary_payloads_from_stdin() {
+    let server = NatsSimulator::start().await.unwrap();
+    let mut publisher = nats()
+        .args([&server.url(), "publish", "-s", "site.blob", "-m", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = publisher.stdin.take().unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut stdin, &[0, 159, 146, 150])
+        .await
+        .unwrap();
+    drop(stdin);
+
+    let status = tokio::time::timeout(TIMEOUT, publisher.wait())
+        .await
+        .expect("nats finishes")
+        .unwrap();
+    assert_eq!(status.code(), Some(0));
+    assert_eq!(server.messages()[0].payload, [0, 159, 146, 150]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn jetstream_publishes_print_the_ack() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.add_stream("METERS", &["site.>"]);
+    let publish = |message: &'static str| {
+        let url = server.url();
+        async move {
+            run(&[
+                &url,
+                "publish",
+                "-s",
+                "site.meter",
+                "-m",
+                message,
+                "--jetstream",
+            ])
+            .await
+        }
+    };
+
+    publish("230.1").await;
+    let output = publish("230.4").await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "Published to stream METERS as sequence 2");
+    let messages = server.messages();
+    assert_eq!(messages.len(), 2);
+    assert!(messages[1].reply.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_a_stream_through_a_pull_consumer() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.add_stream("METERS", &["site.>"]);
+    for value in ["230.1", "230.4", "229.8"] {
+        server.publish("site.meter", value.as_bytes());
+    }
+    server.publish("other.meter", b"0");
+
+    let output = run(&[
+        &server.url(),
+        "subscribe",
+        "--stream",
+        "METERS",
+        "--batch",
+        "2",
+        "--ack",
+        "-w",
+        "--count",
+        "3",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "230.1\n230.4\n229.8");
+    assert!(server.wait_for_acks("METERS", 3, TIMEOUT).await);
+    assert_eq!(server.acknowledged("METERS"), [1, 2, 3]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn durable_consumers_pick_up_where_they_stopped() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.add_stream("METERS", &["site.>"]);
+    for value in ["1", "2"] {
+        server.publish("site.meter", value.as_bytes());
+    }
+    let url = server.url();
+    let args = [
+        &url,
+        "subscribe",
+        "--stream",
+        "METERS",
+        "--durable",
+        "edge",
+        "-w",
+        "--count",
+    ];
+
+    let first = run(&[&args[..], &["2"]].concat()).await;
+    server.publish("site.meter", b"3");
+    let second = run(&[&args[..], &["1"]].concat()).await;
+
+    assert_eq!(stdout(&first), "1\n2");
+    assert_eq!(second.status.code(), Some(0));
+    assert_eq!(stdout(&second), "3");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn durable_consumers_are_created_listed_and_deleted() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.add_stream("METERS", &["site.>"]);
+    server.publish("site.meter", b"1");
+    let url = server.url();
+
+    let create = run(&[
+        &url,
+        "consumer",
+        "create",
+        "METERS",
+        "edge",
+        "--ack-policy",
+        "all",
+        "--filter",
+        "site.meter",
+    ])
+    .await;
+    assert_eq!(create.status.code(), Some(0), "{create:?}");
+    assert_eq!(
+        stdout(&create),
+        "Created edge on METERS: pull, ack all, deliver all"
+    );
+    let list = run(&[&url, "consumer", "list", "METERS"]).await;
+    assert_eq!(stdout(&list), "edge\tpull\tsite.meter\t1\t0");
+    let info = run(&[
+        &url, "--output", "json", "consumer", "info", "METERS", "edge",
+    ])
+    .await;
+    let Ok(Value::Record(info)) = json::parse(&stdout(&info)) else {
+        panic!("a JSON object: {info:?}");
+    };
+    assert_eq!(info.get("ack_policy"), Some(&Value::String("all".into())));
+    assert_eq!(info.get("durable"), Some(&Value::Bool(true)));
+
+    let delete = run(&[&url, "--yes", "consumer", "delete", "METERS", "edge"]).await;
+    assert_eq!(delete.status.code(), Some(0));
+    let list = run(&[&url, "consumer", "list", "METERS"]).await;
+    assert_eq!(stdout(&list), "");
+    let delete = run(&[&url, "consumer", "delete", "METERS", "edge"]).await;
+    assert_eq!(delete.status.code(), Some(1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_run_deletes_no_consumer() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.add_stream("METERS", &["site.>"]);
+    let url = server.url();
+    run(&[&url, "consumer", "create", "METERS", "edge"]).await;
+
+    let output = run(&[&url, "--dry-run", "consumer", "delete", "METERS", "edge"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let list = run(&[&url, "consumer", "list", "METERS"]).await;
+    assert!(stdout(&list).starts_with("edge\t"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_print_the_reply() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.respond("svc.time", b"12:00");
+
+    let output = run(&[&server.url(), "request", "-s", "svc.time", "-m", "now"]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(stdout(&output), "12:00");
+    assert_eq!(server.messages()[0].payload, b"now");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_nobody_answers_fail() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[&server.url(), "request", "-s", "svc.time"]).await;
+
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replies_from_a_template() {
+    let server = NatsSimulator::start().await.unwrap();
+    let template = std::env::temp_dir().join(format!("nats-reply-{}.hbs", std::process::id()));
+    std::fs::write(&template, "{{payload}} at 12:00").unwrap();
+    let _responder = nats()
+        .args([&server.url(), "reply", "-s", "svc.time", "--reply-template"])
+        .arg(&template)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("svc.time", TIMEOUT).await);
+
+    server.request("svc.time", "_INBOX.test", b"now");
+
+    assert!(server.wait_for_messages(1, TIMEOUT).await);
+    std::fs::remove_file(&template).unwrap();
+    let messages = server.messages();
+    assert_eq!(messages[0].subject, "_INBOX.test");
+    assert_eq!(messages[0].payload, b"now at 12:00");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn benchmarks_through_the_server() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[
+        &server.url(),
+        "bench",
+        "--count",
+        "50",
+        "--size",
+        "64",
+        "--concurrency",
+        "2",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    let report = stdout(&output);
+    assert!(
+        report.starts_with("Published 50 messages of 64 bytes in "),
+        "{report}"
+    );
+    assert!(report.contains("Received 50 messages in "), "{report}");
+    assert!(report.contains("\nLatency min "), "{report}");
+    assert_eq!(server.messages().len(), 50);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn measures_latency_through_the_server() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[
+        &server.url(),
+        "--output",
+        "json",
+        "latency",
+        "--duration",
+        "300ms",
+        "--interval",
+        "50ms",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    let Ok(Value::Record(report)) = json::parse(&stdout(&output)) else {
+        panic!("a JSON object: {output:?}");
+    };
+    let Some(Value::Integer(sent)) = report.get("sent") else {
+        panic!("a probe count: {report:?}");
+    };
+    assert!(*sent >= 5, "{report:?}");
+    assert_eq!(report.get("lost"), Some(&Value::Integer(0)));
+    for field in ["one_way_p50_ms", "rtt_p99_ms"] {
+        assert!(
+            matches!(report.get(field), Some(Value::Float(_))),
+            "{field}: {report:?}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn latency_probes_that_never_come_back_time_out() {
+    let (near, far) = (
+        NatsSimulator::start().await.unwrap(),
+        NatsSimulator::start().await.unwrap(),
+    );
+
+    let output = run(&[
+        &near.url(),
+        "latency",
+        "--duration",
+        "100ms",
+        "--via",
+        &far.url(),
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(7), "{output:?}");
+    assert!(stdout(&output).contains("0 came back"), "{output:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subscribes() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "subscribe", "-s", "site.>"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish("site.meter", b"hello");
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after one message")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("hello"));
+}
+
+#[cfg(unix)]
+fn interrupt(child: &tokio::process::Child) {
+    let status = std::process::Command::new("kill")
+        .args(["-INT", &child.id().unwrap().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupted_subscribes_print_what_they_got() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "subscribe", "-s", "site.>", "--watch"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+    for value in ["1", "2", "3"] {
+        server.publish("site.meter", value.as_bytes());
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    interrupt(&subscriber);
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "1\n2\n3");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Stopped after "), "{stderr}");
+    assert!(stderr.contains(": 3 messages"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_subscribes_still_write_out_the_historian_and_the_summary() {
+    let server = NatsSimulator::start().await.unwrap();
+    let dir = std::env::temp_dir().join(format!("nats-failed-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("hook.rhai");
+    std::fs::write(
+        &script,
+        "if msg.payload == \"bad\" { msg.missing.field }\nmsg",
+    )
+    .unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "subscribe", "-s", "site.>", "--watch"])
+        .args(["--script", script.to_str().unwrap()])
+        .args(["--historian", dir.join("history").to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+    for value in ["21.5", "bad"] {
+        server.publish("site.meter", value.as_bytes());
+    }
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "21.5");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(": 2 messages"), "{stderr}");
+    let samples: Vec<u8> = std::fs::read_dir(dir.join("history"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "sqlite")
+        })
+        .flat_map(|path| std::fs::read(path).unwrap())
+        .collect();
+    let value = 21.5f64.to_be_bytes();
+    assert!(samples.windows(value.len()).any(|window| window == value));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subscribes_time_out_short_of_the_count() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "subscribe", "-s", "site.>"])
+        .args(["--count", "2", "--timeout", "1s"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish("site.meter", b"230.1");
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber gives up")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(7));
+    assert_eq!(stdout(&output), "230.1");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Only 1 message within 1s"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subscribes_to_messages_matching_a_regex() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "subscribe", "-s", "site.>", "--count", "2"])
+        .args(["--grep", "^(ALARM|TRIP)"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    for payload in ["ok", "ALARM high pressure", "ok", "TRIP breaker 3"] {
+        server.publish("site.events", payload.as_bytes());
+    }
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after two matches")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "ALARM high pressure\nTRIP breaker 3");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subscribes_to_messages_failing_a_jsonpath() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "subscribe", "-s", "site.>", "--count", "1"])
+        .args(["--jsonpath", "$.readings[*].temperature > 30", "--invert"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish(
+        "site.plc",
+        br#"{"readings":[{"temperature":20},{"temperature":31.5}]}"#,
+    );
+    server.publish(
+        "site.plc",
+        br#"{"readings":[{"temperature":20},{"temperature":22}]}"#,
+    );
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after one message")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("22"), "{}", stdout(&output));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shows_binary_payloads_in_hex() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([
+            &server.url(),
+            "subscribe",
+            "-s",
+            "site.>",
+            "--encoding",
+            "hex",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish("site.blob", &[0, 159, 146, 150]);
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after one message")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "009f9296");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishes_headers() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[
+        &server.url(),
+        "publish",
+        "-s",
+        "site.meter",
+        "-m",
+        "230.1",
+        "--header",
+        "Trace-Id=4bf92f35",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    let headers = server.messages()[0].headers.clone().unwrap();
+    assert!(String::from_utf8_lossy(&headers).contains("Trace-Id: 4bf92f35\r\n"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn verbose_subscribes_show_headers() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([&server.url(), "-v", "true", "subscribe", "-s", "site.>"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish_with_headers("site.meter", &[("Content-Type", "text/plain")], b"230.1");
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after one message")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("Header: Content-Type: text/plain\nPayload: 230.1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_subscribes_print_a_document_per_message() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([
+            &server.url(),
+            "--output",
+            "json",
+            "subscribe",
+            "-s",
+            "site.>",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish_with_headers("site.meter", &[("Trace-Id", "4bf92f35")], b"230.1");
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after one message")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let Ok(Value::Record(message)) = json::parse(&stdout(&output)) else {
+        panic!("not a JSON object: {output:?}");
+    };
+    assert!(matches!(message.get("timestamp"), Some(Value::String(_))));
+    assert_eq!(message.get("subject"), Some(&Value::from("site.meter")));
+    assert_eq!(message.get("payload"), Some(&Value::from("230.1")));
+    assert_eq!(message.get("bytes"), Some(&Value::Integer(5)));
+    let Some(Value::Record(headers)) = message.get("headers") else {
+        panic!("no headers: {message:?}");
+    };
+    assert_eq!(headers.get("Trace-Id"), Some(&Value::from("4bf92f35")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_in_with_a_password() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_user("edge", "secret");
+
+    let output = run(&[
+        &server.url(),
+        "-u",
+        "edge",
+        "-p",
+        "secret",
+        "publish",
+        "-s",
+        "a",
+        "-m",
+        "b",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wrong_passwords_exit_with_auth_error() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_user("edge", "secret");
+
+    let output = run(&[
+        &server.url(),
+        "-u",
+        "edge",
+        "-p",
+        "wrong",
+        "--retries",
+        "0",
+        "publish",
+        "-s",
+        "a",
+        "-m",
+        "b",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(server.messages().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_the_password_from_a_file() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_user("edge", "secret");
+    let file = std::env::temp_dir().join(format!("nats-password-{}", std::process::id()));
+    std::fs::write(&file, "secret\n").unwrap();
+
+    let output = run(&[
+        &server.url(),
+        "-u",
+        "edge",
+        "--password-file",
+        file.to_str().unwrap(),
+        "publish",
+        "-s",
+        "a",
+        "-m",
+        "b",
+    ])
+    .await;
+    let _ = std::fs::remove_file(&file);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.messages().len(), 1);
+}
+
+// A user NKey, and its public key.
+const SEED: &str = "SUAACAQDAQCQMBYIBEFAWDANBYHRAEISCMKBKFQXDAMRUGY4DUPB6IC5CQ";
+const PUBLIC_KEY: &str = "UB43KVROR7TFJ6KAPCYRF2FJROTZAH4FHLTJLPWX4DRZCC5NASLGJBFE";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_in_with_an_nkey() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_nkey(PUBLIC_KEY);
+
+    let output = run(&[
+        &server.url(),
+        "--nkey",
+        SEED,
+        "publish",
+        "-s",
+        "a",
+        "-m",
+        "b",
+    ])
+    .await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_in_with_a_creds_file() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_jwt("eyJ0eXAiOiJKV1QiLCJhbGciOiJlZDI1NTE5LW5rZXkifQ.e30.c2ln");
+    let file = std::env::temp_dir().join(format!("nats-creds-{}", std::process::id()));
+    std::fs::write(
+        &file,
+        format!(
+            "-----BEGIN NATS USER JWT-----\n\
+             eyJ0eXAiOiJKV1QiLCJhbGciOiJlZDI1NTE5LW5rZXkifQ.e30.c2ln\n\
+             ------END NATS USER JWT------\n\n\
+             -----BEGIN USER NKEY SEED-----\n{SEED}\n------END USER NKEY SEED------\n"
+        ),
+    )
+    .unwrap();
+
+    let output = run(&[
+        &server.url(),
+        "--creds",
+        file.to_str().unwrap(),
+        "publish",
+        "-s",
+        "a",
+        "-m",
+        "b",
+    ])
+    .await;
+    let _ = std::fs::remove_file(&file);
+
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert_eq!(server.messages().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_run_publishes_nothing() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[&server.url(), "--dry-run", "publish", "-s", "a", "-m", "b"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("dry run"));
+    assert!(
+        !server
+            .wait_for_messages(1, Duration::from_millis(200))
+            .await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn healthcheck_connects() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[&server.url(), "healthcheck"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("ok: "));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn info_shows_the_server() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.set_cluster("substation");
+
+    let output = run(&[&server.url(), "info"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let text = stdout(&output);
+    assert!(text.contains("Version: 2.9.0\n"), "{text}");
+    assert!(text.contains("Cluster: substation\n"), "{text}");
+    assert!(text.contains("Max payload: 1048576 bytes\n"), "{text}");
+    assert!(text.contains("\nRTT: "), "{text}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn info_as_json() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[&server.url(), "--output", "json", "info"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let Value::Record(info) = json::parse(&stdout(&output)).unwrap() else {
+        panic!("not a record: {}", stdout(&output));
+    };
+    assert_eq!(info.get("url"), Some(&Value::from(server.url().as_str())));
+    assert_eq!(info.get("server_name"), Some(&Value::from("simulator")));
+    assert_eq!(info.get("cluster"), Some(&Value::Null));
+    assert!(matches!(info.get("rtt_ms"), Some(Value::Float(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn info_shows_the_seed_that_answered() {
+    let server = NatsSimulator::start().await.unwrap();
+    let seeds = format!("{},{}", dead_url(), server.url());
+
+    let output = run(&[&seeds, "--output", "json", "info"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    let Value::Record(info) = json::parse(&stdout(&output)).unwrap() else {
+        panic!("not a record: {}", stdout(&output));
+    };
+    assert_eq!(info.get("url"), Some(&Value::from(server.url().as_str())));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn healthcheck_reports_refused_logins() {
+    let server = NatsSimulator::start().await.unwrap();
+    server.require_token("secret");
+
+    let output = run(&[&server.url(), "-t", "wrong", "healthcheck"]).await;
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stdout(&output).starts_with("unhealthy: "));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_rate_spaces_publishes() {
+    let server = NatsSimulator::start().await.unwrap();
+    let envelopes: String = (0..5)
+        .map(|value| format!("{{\"source\":\"plc\",\"topic\":\"meter\",\"payload\":{value}}}\n"))
+        .collect();
+
+    let started = std::time::Instant::now();
+    let mut publisher = nats()
+        .args([
+            &server.url(),
+            "--max-rate",
+            "20/s",
+            "publish",
+            "-s",
+            "site.{topic}",
+            "--envelopes",
+        ])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = publisher.stdin.take().unwrap();
+    tokio::io::AsyncWriteExt::write_all(&mut stdin, envelopes.as_bytes())
+        .await
+        .unwrap();
+    drop(stdin);
+    let status = tokio::time::timeout(TIMEOUT, publisher.wait())
+        .await
+        .expect("publish finishes")
+        .unwrap();
+
+    assert_eq!(status.code(), Some(0));
+    assert_eq!(server.messages().len(), 5);
+    // Four waits of 50ms after the first message.
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exec_runs_a_command_per_message() {
+    let server = NatsSimulator::start().await.unwrap();
+    let file = std::env::temp_dir().join(format!("nats-exec-{}", std::process::id()));
+    let command = format!(
+        "printf '%s ' \"$EDGE_TOPIC\" >> {0}; cat >> {0}; echo >> {0}",
+        file.display()
+    );
+    let subscriber = nats()
+        .args([
+            &server.url(),
+            "subscribe",
+            "-s",
+            "site.>",
+            "--count",
+            "2",
+            "--exec",
+            &command,
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish("site.meter", b"230.1");
+    server.publish("site.alarm", b"door open");
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after two messages")
+        .unwrap();
+    let ran = std::fs::read_to_string(&file).unwrap_or_default();
+    let _ = std::fs::remove_file(&file);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(ran, "site.meter 230.1\nsite.alarm door open\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drops_messages_over_max_payload() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([
+            &server.url(),
+            "subscribe",
+            "-s",
+            "site.>",
+            "--max-payload",
+            "1k",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    server.publish("site.dump", &[b'x'; 2048]);
+    server.publish("site.meter", b"230.1");
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after one message")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = stdout(&output);
+    assert!(stdout.contains("230.1"));
+    assert!(!stdout.contains("site.dump"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_every_message_to_the_file_in_order() {
+    let server = NatsSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("nats-file-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let file = directory.join("messages.ndjson");
+    let spill = directory.join("spill");
+    let subscriber = nats()
+        .args([
+            &server.url(),
+            "subscribe",
+            "-s",
+            "site.>",
+            "--count",
+            "200",
+            "--file",
+            file.to_str().unwrap(),
+            "--file-spill",
+            spill.to_str().unwrap(),
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    for n in 0..200 {
+        server.publish("site.meter", n.to_string().as_bytes());
+    }
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("subscriber stops after 200 messages")
+        .unwrap();
+    let written = std::fs::read_to_string(&file).unwrap_or_default();
+    let spilled = std::fs::read_dir(&spill).map_or(0, |entries| entries.count());
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let payloads: Vec<&str> = written
+        .lines()
+        .map(|line| {
+            let start = line.find("\"payload\":").expect("an envelope") + 10;
+            line[start..].split([',', '}']).next().unwrap()
+        })
+        .collect();
+    let expected: Vec<String> = (0..200).map(|n| format!("\"{n}\"")).collect();
+    assert_eq!(payloads, expected);
+    assert_eq!(spilled, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tls_refuses_a_plain_server() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[
+        &server.url(),
+        "--tls",
+        "--retries",
+        "0",
+        "publish",
+        "-s",
+        "site.meter",
+        "-m",
+        "230.1",
+    ])
+    .await;
+
+    assert_ne!(output.status.code(), Some(0));
+    assert!(server.messages().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn objects_round_trip() {
+    let server = NatsSimulator::start().await.unwrap();
+    let directory = std::env::temp_dir().join(format!("nats-object-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (image, fetched) = (
+        directory.join("firmware.bin"),
+        directory.join("fetched.bin"),
+    );
+    // Three chunks, the last one short.
+    let firmware: Vec<u8> = (0..300_000u32).map(|n| (n % 251) as u8).collect();
+    std::fs::write(&image, &firmware).unwrap();
+    let (url, image) = (server.url(), image.to_str().unwrap());
+
+    let put = run(&[&url, "object", "put", "updates", image]).await;
+    assert_eq!(put.status.code(), Some(0));
+    assert!(stdout(&put).contains("300000 bytes in 3 chunk(s)"));
+    let ls = run(&[&url, "--output", "json", "object", "ls", "updates"]).await;
+    let Ok(Value::List(listed)) = json::parse(&stdout(&ls)) else {
+        panic!(
+            "a JSON list: {:?} {}",
+            ls.status,
+            String::from_utf8_lossy(&ls.stderr)
+        );
+    };
+    let [Value::Record(object)] = listed.as_slice() else {
+        panic!("one object: {}", stdout(&ls));
+    };
+    assert_eq!(
+        object.get("name"),
+        Some(&Value::String("firmware.bin".into()))
+    );
+    let get = run(&[
+        &url,
+        "object",
+        "get",
+        "updates",
+        "firmware.bin",
+        "--file",
+        fetched.to_str().unwrap(),
+    ])
+    .await;
+    assert_eq!(get.status.code(), Some(0));
+    assert_eq!(std::fs::read(&fetched).unwrap(), firmware);
+
+    let rm = run(&[&url, "--yes", "object", "rm", "updates", "firmware.bin"]).await;
+    assert_eq!(rm.status.code(), Some(0));
+    let get = run(&[&url, "object", "get", "updates", "firmware.bin"]).await;
+    assert_ne!(get.status.code(), Some(0));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn objects_replace_their_chunks() {
+    let server = NatsSimulator::start().await.unwrap();
+    let url = server.url();
+    let put = |content: &'static [u8]| {
+        let url = url.clone();
+        async move {
+            let mut put = nats()
+                .args([
+                    &url,
+                    "--yes",
+                    "object",
+                    "put",
+                    "configs",
+                    "-",
+                    "--name",
+                    "site.toml",
+                ])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap();
+            let mut stdin = put.stdin.take().unwrap();
+            tokio::io::AsyncWriteExt::write_all(&mut stdin, content)
+                .await
+                .unwrap();
+            drop(stdin);
+            tokio::time::timeout(TIMEOUT, put.wait())
+                .await
+                .expect("nats finishes")
+                .unwrap()
+        }
+    };
+
+    assert_eq!(put(b"interval = 10").await.code(), Some(0));
+    assert_eq!(put(b"interval = 5").await.code(), Some(0));
+
+    let get = run(&[&url, "object", "get", "configs", "site.toml"]).await;
+    assert_eq!(stdout(&get), "interval = 5");
+    let ls = run(&[&url, "object", "ls", "configs"]).await;
+    assert_eq!(stdout(&ls).lines().count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_subjects_busiest_first() {
+    let server = NatsSimulator::start().await.unwrap();
+    let lister = nats()
+        .args([&server.url(), "--output", "json", "list-subjects"])
+        .args(["--max-subjects", "2"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription(">", TIMEOUT).await);
+
+    for _ in 0..3 {
+        server.publish("site.meter", b"230.1");
+    }
+    server.publish("site.alarm", b"high");
+
+    let output = tokio::time::timeout(TIMEOUT, lister.wait_with_output())
+        .await
+        .expect("listing stops at the second subject")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let Ok(Value::List(subjects)) = json::parse(&stdout(&output)) else {
+        panic!("a JSON list: {}", stdout(&output));
+    };
+    let counts: Vec<(Option<&Value>, Option<&Value>, Option<&Value>)> = subjects
+        .iter()
+        .map(|subject| match subject {
+            Value::Record(record) => (
+                record.get("subject"),
+                record.get("messages"),
+                record.get("bytes"),
+            ),
+            _ => panic!("a record per subject"),
+        })
+        .collect();
+    let meter = Value::String("site.meter".into());
+    let alarm = Value::String("site.alarm".into());
+    assert_eq!(
+        counts,
+        [
+            (
+                Some(&meter),
+                Some(&Value::Integer(3)),
+                Some(&Value::Integer(15))
+            ),
+            (
+                Some(&alarm),
+                Some(&Value::Integer(1)),
+                Some(&Value::Integer(4))
+            ),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_subjects_for_a_duration() {
+    let server = NatsSimulator::start().await.unwrap();
+
+    let output = run(&[&server.url(), "list-subjects", "--duration", "200ms"]).await;
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(
+        stdout(&output).starts_with("SUBJECT"),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupted_subject_listings_print_the_table() {
+    let server = NatsSimulator::start().await.unwrap();
+    let listing = nats()
+        .args([&server.url(), "list-subjects"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription(">", TIMEOUT).await);
+    server.publish("site.meter", b"1");
+    server.publish("site.valve", b"2");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    interrupt(&listing);
+
+    let output = tokio::time::timeout(TIMEOUT, listing.wait_with_output())
+        .await
+        .expect("listing stops")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let table = stdout(&output);
+    assert!(
+        table.contains("site.meter") && table.contains("site.valve"),
+        "{table}"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(": 2 messages"), "{stderr}");
+    assert!(stderr.contains("2 subjects"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_subjects_as_a_collapsed_tree() {
+    let server = NatsSimulator::start().await.unwrap();
+    let lister = nats()
+        .args([
+            &server.url(),
+            "list-subjects",
+            "--tree",
+            "--max-subjects",
+            "24",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription(">", TIMEOUT).await);
+
+    for device in 1..=12 {
+        server.publish(&format!("plant.{device}.sensor.temperature"), b"20.5");
+        server.publish(&format!("plant.{device}.status"), b"ok");
+    }
+
+    let output = tokio::time::timeout(TIMEOUT, lister.wait_with_output())
+        .await
+        .expect("listing stops at the last subject")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let rows: Vec<Vec<String>> = stdout(&output)
+        .lines()
+        .map(|line| line.split_whitespace().map(String::from).collect())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            ["BRANCH", "MESSAGES", "BYTES", "SUBJECTS"],
+            ["plant.*", "24", "72", "24"],
+            ["sensor.temperature", "12", "48", "12"],
+            ["status", "12", "24", "12"],
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_and_replays_messages() {
+    let field = NatsSimulator::start().await.unwrap();
+    let file = std::env::temp_dir().join(format!("nats-record-{}.ndjson", std::process::id()));
+    let recorder = nats()
+        .args([&field.url(), "record", "-s", "site.>", "--count", "2"])
+        .arg(&file)
+        .spawn()
+        .unwrap();
+    assert!(field.wait_for_subscription("site.>", TIMEOUT).await);
+
+    field.publish_with_headers("site.meter", &[("Unit", "V")], b"230.1");
+    field.publish("site.blob", &[0, 159, 146, 150]);
+
+    let recorded = tokio::time::timeout(TIMEOUT, recorder.wait_with_output())
+        .await
+        .expect("recording stops after two messages")
+        .unwrap();
+    assert_eq!(recorded.status.code(), Some(0));
+    let lab = NatsSimulator::start().await.unwrap();
+    let replayed = run(&[&lab.url(), "replay", file.to_str().unwrap(), "--speed", "0"]).await;
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(replayed.status.code(), Some(0));
+    let messages = lab.messages();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].subject, "site.meter");
+    assert_eq!(messages[0].payload, b"230.1");
+    let headers = String::from_utf8_lossy(messages[0].headers.as_deref().unwrap_or_default());
+    assert!(headers.contains("Unit: V"), "{headers}");
+    assert_eq!(messages[1].payload, [0, 159, 146, 150]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_at_speed() {
+    let server = NatsSimulator::start().await.unwrap();
+    let file = std::env::temp_dir().join(format!("nats-replay-{}.ndjson", std::process::id()));
+    std::fs::write(
+        &file,
+        "{\"timestamp\":\"2026-10-14T08:00:00Z\",\"subject\":\"site.meter\",\"payload\":\"1\"}\n\
+         {\"timestamp\":\"2026-10-14T08:00:00.600Z\",\"subject\":\"site.meter\",\"payload\":\"2\"}\n",
+    )
+    .unwrap();
+
+    let start = std::time::Instant::now();
+    let output = run(&[
+        &server.url(),
+        "replay",
+        file.to_str().unwrap(),
+        "--speed",
+        "2",
+    ])
+    .await;
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    let payloads: Vec<Vec<u8>> = server.messages().into_iter().map(|m| m.payload).collect();
+    assert_eq!(payloads, [b"1", b"2"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up_reconnecting_after_max_reconnects() {
+    let server = NatsSimulator::start().await.unwrap();
+    let subscriber = nats()
+        .args([
+            &server.url(),
+            "--max-reconnects",
+            "2",
+            "--ping-interval",
+            "1s",
+        ])
+        .args(["subscribe", "-s", "site.>", "--watch"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    assert!(server.wait_for_subscription("site.>", TIMEOUT).await);
+
+    drop(server);
+
+    let output = tokio::time::timeout(TIMEOUT, subscriber.wait_with_output())
+        .await
+        .expect("nats gives up")
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn help_and_man_pages_describe_options() {
+    let description = "Decode payloads with this program";
+    let directory = std::env::temp_dir().join(format!("nats-man-{}", std::process::id()));
+
+    let help = run(&["subscribe", "--help"]).await;
+    let man = run(&["gen-man", directory.to_str().unwrap()]).await;
+
+    assert_eq!(help.status.code(), Some(0));
+    assert!(stdout(&help).contains(description), "{}", stdout(&help));
+    assert_eq!(man.status.code(), Some(0));
+    let page = std::fs::read_to_string(directory.join("nats-subscribe.1")).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert!(page.contains(description), "{page}");
+}
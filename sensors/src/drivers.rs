@@ -0,0 +1,239 @@
+//! Drivers for the temperature/humidity chips we find on gateway carrier boards.
+
+use clap::ValueEnum;
+use std::fmt;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::i2c::I2cDevice;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Chip {
+    Sht31,
+    Htu21d,
+    Si7021,
+    Aht20,
+    Tmp102,
+    Lm75,
+}
+
+impl Chip {
+    pub fn default_address(&self) -> u16 {
+        match self {
+            Chip::Sht31 => 0x44,
+            Chip::Htu21d | Chip::Si7021 => 0x40,
+            Chip::Aht20 => 0x38,
+            Chip::Tmp102 | Chip::Lm75 => 0x48,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Reading {
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        if let Some(temperature) = self.temperature {
+            write!(f, "temperature={temperature:.2}C")?;
+            first = false;
+        }
+        if let Some(humidity) = self.humidity {
+            if !first {
+                write!(f, " ")?;
+            }
+            write!(f, "humidity={humidity:.1}%")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read(device: &mut I2cDevice, chip: Chip) -> Result<Reading, Box<dyn std::error::Error>> {
+    match chip {
+        Chip::Sht31 => read_sht31(device),
+        Chip::Htu21d | Chip::Si7021 => read_htu21d(device),
+        Chip::Aht20 => read_aht20(device),
+        Chip::Tmp102 => read_lm75_family(device, 4, 0.0625),
+        Chip::Lm75 => read_lm75_family(device, 5, 0.125),
+    }
+}
+
+fn read_sht31(device: &mut I2cDevice) -> Result<Reading, Box<dyn std::error::Error>> {
+    // Single shot, high repeatability, no clock stretching.
+    device.write(&[0x24, 0x00])?;
+    sleep(Duration::from_millis(16));
+    sht31_reading(&device.read(6)?)
+}
+
+/// Temperature, CRC, humidity, CRC, each value 16 bits.
+fn sht31_reading(data: &[u8]) -> Result<Reading, Box<dyn std::error::Error>> {
+    check_crc8(&data[0..2], data[2], 0xff)?;
+    check_crc8(&data[3..5], data[5], 0xff)?;
+
+    let temperature = u16::from_be_bytes([data[0], data[1]]) as f64;
+    let humidity = u16::from_be_bytes([data[3], data[4]]) as f64;
+    Ok(Reading {
+        temperature: Some(-45.0 + 175.0 * temperature / 65535.0),
+        humidity: Some(100.0 * humidity / 65535.0),
+    })
+}
+
+fn read_htu21d(device: &mut I2cDevice) -> Result<Reading, Box<dyn std::error::Error>> {
+    // "No hold master" measurements, so we poll instead of relying on clock stretching.
+    let mut measure = |command: u8| -> Result<f64, Box<dyn std::error::Error>> {
+        device.write(&[command])?;
+        sleep(Duration::from_millis(50));
+        htu21d_measurement(&device.read(3)?)
+    };
+
+    let temperature = measure(0xf3)?;
+    let humidity = measure(0xf5)?;
+    Ok(htu21d_reading(temperature, humidity))
+}
+
+/// A 16 bit measurement followed by its CRC.
+fn htu21d_measurement(data: &[u8]) -> Result<f64, Box<dyn std::error::Error>> {
+    check_crc8(&data[0..2], data[2], 0x00)?;
+    // The two low bits are status bits.
+    Ok((u16::from_be_bytes([data[0], data[1]]) & 0xfffc) as f64)
+}
+
+fn htu21d_reading(temperature: f64, humidity: f64) -> Reading {
+    Reading {
+        temperature: Some(-46.85 + 175.72 * temperature / 65536.0),
+        humidity: Some((-6.0 + 125.0 * humidity / 65536.0).clamp(0.0, 100.0)),
+    }
+}
+
+fn read_aht20(device: &mut I2cDevice) -> Result<Reading, Box<dyn std::error::Error>> {
+    let status = device.read(1)?[0];
+    if status & 0x08 == 0 {
+        // Not calibrated yet, send the initialisation command first.
+        device.write(&[0xbe, 0x08, 0x00])?;
+        sleep(Duration::from_millis(10));
+    }
+
+    device.write(&[0xac, 0x33, 0x00])?;
+    sleep(Duration::from_millis(80));
+    aht20_reading(&device.read(7)?)
+}
+
+/// Status, 20 bits of humidity, 20 bits of temperature, CRC.
+fn aht20_reading(data: &[u8]) -> Result<Reading, Box<dyn std::error::Error>> {
+    if data[0] & 0x80 != 0 {
+        return Err("AHT20 measurement still busy".into());
+    }
+    check_crc8(&data[0..6], data[6], 0xff)?;
+
+    let humidity =
+        (u32::from(data[1]) << 12) | (u32::from(data[2]) << 4) | (u32::from(data[3]) >> 4);
+    let temperature =
+        ((u32::from(data[3]) & 0x0f) << 16) | (u32::from(data[4]) << 8) | u32::from(data[5]);
+    Ok(Reading {
+        temperature: Some(temperature as f64 / 1048576.0 * 200.0 - 50.0),
+        humidity: Some(humidity as f64 / 1048576.0 * 100.0),
+    })
+}
+
+/// TMP102 and LM75 share the register layout: a left aligned two's complement temperature in
+/// register 0, differing only in resolution.
+fn read_lm75_family(
+    device: &mut I2cDevice,
+    shift: u32,
+    resolution: f64,
+) -> Result<Reading, Box<dyn std::error::Error>> {
+    let data = device.read_register(0x00, 2)?;
+    Ok(Reading {
+        temperature: Some(lm75_temperature(&data, shift, resolution)),
+        humidity: None,
+    })
+}
+
+fn lm75_temperature(data: &[u8], shift: u32, resolution: f64) -> f64 {
+    let raw = i16::from_be_bytes([data[0], data[1]]) >> shift;
+    raw as f64 * resolution
+}
+
+/// CRC-8 with polynomial 0x31 as used by Sensirion, TE and Aosong parts.
+fn check_crc8(data: &[u8], expected: u8, init: u8) -> Result<(), Box<dyn std::error::Error>> {
+    let mut crc = init;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    if crc != expected {
+        return Err(
+            format!("CRC mismatch: expected {expected:#04x}, calculated {crc:#04x}").into(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(value: Option<f64>, expected: f64) {
+        let value = value.unwrap();
+        assert!((value - expected).abs() < 0.01, "{value} is not {expected}");
+    }
+
+    #[test]
+    fn checks_the_crc_against_the_datasheet_examples() {
+        // Sensirion's 0xbeef and TE's 0x683a.
+        check_crc8(&[0xbe, 0xef], 0x92, 0xff).unwrap();
+        check_crc8(&[0x68, 0x3a], 0x7c, 0x00).unwrap();
+        let err = check_crc8(&[0xbe, 0xef], 0x93, 0xff).unwrap_err();
+        assert!(err.to_string().contains("CRC mismatch"), "{err}");
+    }
+
+    #[test]
+    fn converts_sht31_measurements() {
+        let reading = sht31_reading(&[0x66, 0x66, 0x93, 0x80, 0x00, 0xa2]).unwrap();
+        assert_near(reading.temperature, 25.0);
+        assert_near(reading.humidity, 50.0);
+        assert_eq!(reading.to_string(), "temperature=25.00C humidity=50.0%");
+        assert!(sht31_reading(&[0x66, 0x66, 0x92, 0x80, 0x00, 0xa2]).is_err());
+    }
+
+    #[test]
+    fn converts_htu21d_measurements() {
+        let temperature = htu21d_measurement(&[0x68, 0x3a, 0x7c]).unwrap();
+        let humidity = htu21d_measurement(&[0x4e, 0x85, 0x6b]).unwrap();
+        // The status bits are dropped before converting.
+        assert_eq!((temperature, humidity), (26680.0, 20100.0));
+        let reading = htu21d_reading(temperature, humidity);
+        assert_near(reading.temperature, 24.69);
+        assert_near(reading.humidity, 32.34);
+        assert_near(htu21d_reading(0.0, 0.0).humidity, 0.0);
+    }
+
+    #[test]
+    fn converts_aht20_measurements() {
+        let reading = aht20_reading(&[0x1c, 0x80, 0x00, 0x06, 0x00, 0x00, 0x4e]).unwrap();
+        assert_near(reading.humidity, 50.0);
+        assert_near(reading.temperature, 25.0);
+
+        let err = aht20_reading(&[0x9c, 0x80, 0x00, 0x06, 0x00, 0x00, 0x4e])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("busy"), "{err}");
+    }
+
+    #[test]
+    fn converts_left_aligned_temperatures() {
+        assert_eq!(lm75_temperature(&[0x19, 0x80], 5, 0.125), 25.5);
+        assert_eq!(lm75_temperature(&[0xe7, 0x00], 5, 0.125), -25.0);
+        assert_eq!(lm75_temperature(&[0x19, 0x00], 4, 0.0625), 25.0);
+        assert_eq!(lm75_temperature(&[0xff, 0xf0], 4, 0.0625), -0.0625);
+    }
+}
@@ -0,0 +1,71 @@
+//! Minimal access to Linux i2c-dev character devices.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+// From linux/i2c-dev.h
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+pub struct I2cDevice {
+    file: File,
+}
+
+impl I2cDevice {
+    /// Opens `/dev/i2c-<bus>` and selects the slave address for subsequent transfers.
+    pub fn open(bus: u8, address: u16) -> io::Result<I2cDevice> {
+        let path = format!("/dev/i2c-{bus}");
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut device = I2cDevice { file };
+        device.set_address(address)?;
+        Ok(device)
+    }
+
+    pub fn set_address(&mut self, address: u16) -> io::Result<()> {
+        // SAFETY: I2C_SLAVE takes the address by value and the fd is owned by `self.file`.
+        let result = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                I2C_SLAVE as _,
+                libc::c_ulong::from(address),
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)
+    }
+
+    pub fn read(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; length];
+        self.file.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Writes the register pointer then reads `length` bytes starting from it.
+    pub fn read_register(&mut self, register: u8, length: usize) -> io::Result<Vec<u8>> {
+        self.write(&[register])?;
+        self.read(length)
+    }
+}
+
+/// Probes every non-reserved 7-bit address with a one byte read, like `i2cdetect -r`.
+pub fn scan(bus: u8) -> io::Result<Vec<u16>> {
+    let mut device = I2cDevice::open(bus, 0x03)?;
+    let mut found = Vec::new();
+    for address in 0x03..=0x77 {
+        if device.set_address(address).is_err() {
+            // EBUSY: claimed by a kernel driver, which means something is there.
+            found.push(address);
+            continue;
+        }
+        if device.read(1).is_ok() {
+            found.push(address);
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,268 @@
+mod drivers;
+mod i2c;
+mod onewire;
+
+use clap::{Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::{OutputArgs, Record};
+use edge_core::shutdown::Summary;
+use edge_core::watch::Watch;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use drivers::Chip;
+use i2c::I2cDevice;
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    #[clap(subcommand)]
+    command: Option<Subcommands>,
+
+    /// Repeat the read every N seconds.
+    #[clap(short, long, action)]
+    interval: Option<u64>,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// List the addresses that answer on an I2C bus.
+    Scan {
+        #[clap(short, long, action)]
+        bus: Option<u8>,
+    },
+
+    /// Read raw bytes starting at a register.
+    ReadRegister {
+        #[clap(short, long, action)]
+        bus: Option<u8>,
+        #[clap(short, long, value_parser = parse_number)]
+        address: u16,
+        #[clap(short, long, value_parser = parse_number)]
+        register: u16,
+        #[clap(short, long, action)]
+        count: Option<usize>,
+    },
+
+    /// Read temperature/humidity from a supported chip.
+    Read {
+        #[clap(short, long, action)]
+        bus: Option<u8>,
+        #[clap(short, long, action)]
+        chip: Chip,
+        /// Defaults to the chip's usual address.
+        #[clap(short, long, value_parser = parse_number)]
+        address: Option<u16>,
+    },
+
+    /// Exit 0 if the bus has something on it, or if the given chip or address answers, for
+    /// container and systemd health probes.
+    Healthcheck {
+        #[clap(short, long, action)]
+        bus: Option<u8>,
+        #[clap(short, long, action)]
+        chip: Option<Chip>,
+        /// Defaults to the chip's usual address.
+        #[clap(short, long, value_parser = parse_number)]
+        address: Option<u16>,
+    },
+
+    /// Read every 1-Wire temperature sensor, or just the one with this id.
+    OneWire {
+        #[clap(value_parser)]
+        id: Option<String>,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+pub fn run(cli: Args) {
+    if let Some(Subcommands::Completions { shell }) = cli.command {
+        completions::print::<Args>(shell, "sensors");
+        return;
+    }
+    if let Some(Subcommands::GenMan { directory }) = &cli.command {
+        man::generate::<Args>("sensors", directory.as_deref());
+        return;
+    }
+
+    let command = if let Some(command) = cli.command {
+        command
+    } else {
+        exit::fatal_with(Code::Usage, "No subcommand specified.");
+    };
+
+    let out = cli.output.load();
+    match command {
+        Subcommands::Completions { .. } | Subcommands::GenMan { .. } => {
+            unreachable!("handled above")
+        }
+        Subcommands::Scan { bus } => {
+            let bus = bus.unwrap_or(1);
+            match i2c::scan(bus) {
+                Ok(addresses) => {
+                    let records: Vec<Record> = addresses
+                        .iter()
+                        .map(|address| Record::new().field("address", *address))
+                        .collect();
+                    out.records(&records, || {
+                        for address in &addresses {
+                            println!("{address:#04x}");
+                        }
+                    });
+                }
+                Err(err) => exit::fatal_error(format!("Unable to scan /dev/i2c-{bus}"), &err),
+            }
+        }
+        Subcommands::ReadRegister {
+            bus,
+            address,
+            register,
+            count,
+        } => {
+            let mut device = open(bus.unwrap_or(1), address);
+            let count = count.unwrap_or(1);
+            repeat(cli.interval, || {
+                match device.read_register(register as u8, count) {
+                    Ok(data) => {
+                        let record = Record::new()
+                            .field("address", address)
+                            .field("register", register)
+                            .field("data", data.clone());
+                        out.record(&record, || {
+                            let data: Vec<String> =
+                                data.iter().map(|byte| format!("{byte:#04x}")).collect();
+                            println!("{:?}", data);
+                        });
+                    }
+                    Err(err) => log::error!("Unable to read register {register:#04x}: {err}"),
+                }
+            });
+        }
+        Subcommands::Read { bus, chip, address } => {
+            let address = address.unwrap_or_else(|| chip.default_address());
+            let mut device = open(bus.unwrap_or(1), address);
+            repeat(cli.interval, || match drivers::read(&mut device, chip) {
+                Ok(reading) => {
+                    let record = Record::new()
+                        .field("address", address)
+                        .field("temperature_c", reading.temperature)
+                        .field("humidity_percent", reading.humidity);
+                    out.record(&record, || println!("{reading}"));
+                }
+                Err(err) => log::error!("Unable to read sensor at {address:#04x}: {err}"),
+            });
+        }
+        Subcommands::Healthcheck { bus, chip, address } => {
+            let bus = bus.unwrap_or(1);
+            health::check_blocking(
+                &out,
+                &format!("/dev/i2c-{bus}"),
+                || -> Result<String, Box<dyn std::error::Error>> {
+                    let address = address.or_else(|| chip.map(|chip| chip.default_address()));
+                    match (chip, address) {
+                        (Some(chip), Some(address)) => {
+                            let mut device = I2cDevice::open(bus, address)?;
+                            let reading = drivers::read(&mut device, chip)?;
+                            Ok(format!("read {reading} at {address:#04x}"))
+                        }
+                        (None, Some(address)) => {
+                            let mut device = I2cDevice::open(bus, address)?;
+                            device.read(1)?;
+                            Ok(format!("answered at {address:#04x}"))
+                        }
+                        _ => match i2c::scan(bus)?.len() {
+                            0 => Err(exit::Error::new(Code::Connection, "nothing answers").into()),
+                            count => Ok(format!("has {count} addresses answering")),
+                        },
+                    }
+                },
+            )
+        }
+        Subcommands::OneWire { id } => {
+            let devices = match onewire::devices() {
+                Ok(devices) => devices,
+                Err(err) => exit::fatal_error("Unable to list 1-Wire devices", &*err),
+            };
+            let devices: Vec<_> = devices
+                .into_iter()
+                .filter(|device| id.as_ref().map(|id| &device.id == id).unwrap_or(true))
+                .collect();
+            if devices.is_empty() {
+                exit::fatal_with(Code::Connection, "No 1-Wire devices found.");
+            }
+
+            repeat(cli.interval, || {
+                for device in &devices {
+                    match device.temperature() {
+                        Ok(temperature) => {
+                            let record = Record::new()
+                                .field("id", device.id.as_str())
+                                .field("family", device.family())
+                                .field("temperature_c", temperature);
+                            out.record(&record, || {
+                                println!(
+                                    "{} {} temperature={temperature:.3}C",
+                                    device.id,
+                                    device.family()
+                                )
+                            });
+                        }
+                        Err(err) => log::error!("Unable to read {}: {err}", device.id),
+                    }
+                }
+            });
+        }
+    }
+}
+
+fn open(bus: u8, address: u16) -> I2cDevice {
+    match I2cDevice::open(bus, address) {
+        Ok(device) => device,
+        Err(err) => exit::fatal_error(
+            format!("Unable to open /dev/i2c-{bus} address {address:#04x}"),
+            &err,
+        ),
+    }
+}
+
+fn repeat<F: FnMut()>(interval: Option<u64>, mut read: F) {
+    let summary = Summary::new(&["reads"]);
+    Watch::new(interval.is_some(), interval.map(Duration::from_secs)).run_blocking(|| {
+        read();
+        summary.count("reads");
+        true
+    });
+    if interval.is_some() {
+        summary.print();
+    }
+}
+
+/// Accepts decimal or 0x-prefixed hex, since datasheets give addresses in hex.
+fn parse_number(value: &str) -> Result<u16, String> {
+    let result = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    result.map_err(|err| format!("{value}: {err}"))
+}
@@ -0,0 +1,4 @@
+fn main() {
+    sensors::run(edge_core::logging::parse("sensors"));
+    edge_core::expect::finish();
+}
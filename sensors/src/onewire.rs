@@ -0,0 +1,101 @@
+//! 1-Wire temperature sensors exposed by the kernel w1 drivers under sysfs.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEVICES: &str = "/sys/bus/w1/devices";
+
+pub struct Device {
+    pub id: String,
+    path: PathBuf,
+}
+
+impl Device {
+    pub fn family(&self) -> &'static str {
+        match self.id.split('-').next().unwrap_or("") {
+            "10" => "DS18S20",
+            "22" => "DS1822",
+            "28" => "DS18B20",
+            "3b" => "MAX31850",
+            "42" => "DS28EA00",
+            _ => "unknown",
+        }
+    }
+
+    /// Reads the temperature in degrees Celsius from `w1_slave`.
+    pub fn temperature(&self) -> Result<f64, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(self.path.join("w1_slave"))?;
+        self.parse_temperature(&contents)
+    }
+
+    fn parse_temperature(&self, contents: &str) -> Result<f64, Box<dyn std::error::Error>> {
+        // Line one is the scratchpad ending in "crc=xx YES", line two ends in "t=<millidegrees>".
+        let mut lines = contents.lines();
+        let status = lines.next().unwrap_or("");
+        if !status.trim_end().ends_with("YES") {
+            return Err(format!("CRC check failed for {}", self.id).into());
+        }
+        let value = lines
+            .next()
+            .and_then(|line| line.split("t=").nth(1))
+            .ok_or_else(|| format!("no temperature in w1_slave for {}", self.id))?;
+        Ok(value.trim().parse::<i32>()? as f64 / 1000.0)
+    }
+}
+
+pub fn devices() -> Result<Vec<Device>, Box<dyn std::error::Error>> {
+    let root = Path::new(DEVICES);
+    if !root.exists() {
+        return Err(format!("{DEVICES} not found, is the w1-gpio overlay loaded?").into());
+    }
+
+    let mut devices = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let id = entry.file_name().to_string_lossy().to_string();
+        if id.starts_with("w1_bus_master") {
+            continue;
+        }
+        devices.push(Device {
+            id,
+            path: entry.path(),
+        });
+    }
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(id: &str) -> Device {
+        Device {
+            id: id.to_string(),
+            path: PathBuf::from(DEVICES).join(id),
+        }
+    }
+
+    #[test]
+    fn reads_millidegrees_from_w1_slave() {
+        let sensor = device("28-0316a2797cff");
+        assert_eq!(sensor.family(), "DS18B20");
+        let contents = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+                        72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(sensor.parse_temperature(contents).unwrap(), 23.125);
+        let contents = "5e ff 4b 46 7f ff 02 10 39 : crc=39 YES\n\
+                        5e ff 4b 46 7f ff 02 10 39 t=-10125\n";
+        assert_eq!(sensor.parse_temperature(contents).unwrap(), -10.125);
+    }
+
+    #[test]
+    fn refuses_readings_that_failed_their_crc() {
+        let sensor = device("10-000802b4ba0a");
+        assert_eq!(sensor.family(), "DS18S20");
+        let contents = "72 01 4b 46 7f ff 0e 10 57 : crc=00 NO\n\
+                        72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        let err = sensor.parse_temperature(contents).unwrap_err();
+        assert!(err.to_string().contains("CRC check failed"), "{err}");
+        assert!(sensor.parse_temperature("crc=57 YES\n").is_err());
+    }
+}
@@ -0,0 +1,18 @@
+//! In-process test servers that speak enough Modbus TCP and NATS for the tools' integration
+//! tests. Each one listens on a free local port, keeps its state in memory and can be told how
+//! to misbehave: exception answers, slow answers, dropped connections or refused logins.
+//!
+//! ```no_run
+//! # async fn example() -> std::io::Result<()> {
+//! let device = simulators::ModbusSimulator::start().await?;
+//! device.set_holding(100, &[215]);
+//! // ... run `modbus <device.address()> read-register -r 100 -k holding` ...
+//! # Ok(())
+//! # }
+//! ```
+
+mod modbus;
+mod nats;
+
+pub use modbus::{ModbusSimulator, Request};
+pub use nats::{Message, NatsSimulator};
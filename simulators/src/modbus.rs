@@ -0,0 +1,292 @@
+//! A Modbus TCP device: holding and input registers, coils and discrete inputs in memory,
+//! shared by every unit id. Registers that were never set read as 0.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+// Exception codes.
+const ILLEGAL_FUNCTION: u8 = 1;
+const ILLEGAL_DATA_VALUE: u8 = 3;
+
+/// A request as the device received it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub unit_id: u8,
+    pub function: u8,
+    pub address: u16,
+    // Registers or bits read or written.
+    pub count: u16,
+}
+
+#[derive(Default)]
+struct State {
+    holding: BTreeMap<u16, u16>,
+    input: BTreeMap<u16, u16>,
+    coils: BTreeMap<u16, bool>,
+    discrete: BTreeMap<u16, bool>,
+    // Exception code answered for any request touching the address.
+    exceptions: HashMap<u16, u8>,
+    delay: Duration,
+    // Connections still to close right after accepting them.
+    drops: usize,
+    accepted: usize,
+    requests: Vec<Request>,
+}
+
+pub struct ModbusSimulator {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl ModbusSimulator {
+    /// Listens on a free port on 127.0.0.1.
+    pub async fn start() -> io::Result<ModbusSimulator> {
+        ModbusSimulator::start_on("127.0.0.1:0".parse().expect("valid address")).await
+    }
+
+    /// Listens on `address`, e.g. one a client is already retrying. A simulator dropped there
+    /// just before lets go of it shortly after, so the address is waited for a moment.
+    pub async fn start_on(address: SocketAddr) -> io::Result<ModbusSimulator> {
+        let mut attempts = 0;
+        let listener = loop {
+            match TcpListener::bind(address).await {
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse && attempts < 100 => {
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                result => break result?,
+            }
+        };
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let task = tokio::spawn(accept(listener, state.clone()));
+        Ok(ModbusSimulator {
+            address,
+            state,
+            task,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn set_holding(&self, address: u16, values: &[u16]) {
+        set(&mut self.state().holding, address, values);
+    }
+
+    pub fn set_input(&self, address: u16, values: &[u16]) {
+        set(&mut self.state().input, address, values);
+    }
+
+    pub fn set_coils(&self, address: u16, values: &[bool]) {
+        set(&mut self.state().coils, address, values);
+    }
+
+    pub fn set_discrete(&self, address: u16, values: &[bool]) {
+        set(&mut self.state().discrete, address, values);
+    }
+
+    pub fn holding(&self, address: u16) -> u16 {
+        self.state().holding.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn coil(&self, address: u16) -> bool {
+        self.state().coils.get(&address).copied().unwrap_or(false)
+    }
+
+    /// Answers every request that touches `address` with exception `code`, e.g. 2 for an
+    /// illegal data address.
+    pub fn fail(&self, address: u16, code: u8) {
+        self.state().exceptions.insert(address, code);
+    }
+
+    /// Waits this long before each answer.
+    pub fn delay(&self, delay: Duration) {
+        self.state().delay = delay;
+    }
+
+    /// Closes the next `count` connections as soon as they're accepted.
+    pub fn drop_connections(&self, count: usize) {
+        self.state().drops = count;
+    }
+
+    /// Connections accepted so far, dropped ones included.
+    pub fn connections(&self) -> usize {
+        self.state().accepted
+    }
+
+    /// Every request answered or failed so far, oldest first.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state().requests.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("simulator state poisoned")
+    }
+}
+
+impl Drop for ModbusSimulator {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn set<T: Copy>(map: &mut BTreeMap<u16, T>, address: u16, values: &[T]) {
+    for (offset, value) in values.iter().enumerate() {
+        map.insert(address.wrapping_add(offset as u16), *value);
+    }
+}
+
+async fn accept(listener: TcpListener, state: Arc<Mutex<State>>) {
+    let mut connections = Vec::new();
+    while let Ok((stream, _)) = listener.accept().await {
+        {
+            let mut state = state.lock().expect("simulator state poisoned");
+            state.accepted += 1;
+            if state.drops > 0 {
+                state.drops -= 1;
+                continue;
+            }
+        }
+        connections.push(AbortOnDrop(tokio::spawn(serve(stream, state.clone()))));
+    }
+}
+
+// Connections end with the accept loop, so dropping the simulator closes them all.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    loop {
+        let mut header = [0; 7];
+        if stream.read_exact(&mut header).await.is_err() {
+            return;
+        }
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut pdu = vec![0; length.saturating_sub(1)];
+        if stream.read_exact(&mut pdu).await.is_err() {
+            return;
+        }
+        let unit_id = header[6];
+        let (answer, delay) = {
+            let mut state = state.lock().expect("simulator state poisoned");
+            (handle(&mut state, unit_id, &pdu), state.delay)
+        };
+        tokio::time::sleep(delay).await;
+
+        let mut frame = Vec::with_capacity(answer.len() + 7);
+        frame.extend_from_slice(&header[..4]);
+        frame.extend_from_slice(&(answer.len() as u16 + 1).to_be_bytes());
+        frame.push(unit_id);
+        frame.extend_from_slice(&answer);
+        if stream.write_all(&frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The answer PDU for a request PDU.
+fn handle(state: &mut State, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+    let Some(&function) = pdu.first() else {
+        return vec![0x80, ILLEGAL_FUNCTION];
+    };
+    let word = |index: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([*pdu.get(index)?, *pdu.get(index + 1)?]))
+    };
+    let exception = |code: u8| vec![function | 0x80, code];
+    let (Some(address), Some(argument)) = (word(1), word(3)) else {
+        return exception(ILLEGAL_DATA_VALUE);
+    };
+    let count = match function {
+        5 | 6 => 1,
+        1..=4 | 15 | 16 => argument,
+        _ => 0,
+    };
+    state.requests.push(Request {
+        unit_id,
+        function,
+        address,
+        count,
+    });
+    // Up to and including the last address, 65535.
+    let end = (u32::from(address) + u32::from(count.max(1))).min(0x10000);
+    let touched = (u32::from(address)..end).map(|address| address as u16);
+    if let Some(code) = touched
+        .clone()
+        .find_map(|address| state.exceptions.get(&address))
+    {
+        return exception(*code);
+    }
+
+    match function {
+        1 | 2 => {
+            let bits = if function == 1 {
+                &state.coils
+            } else {
+                &state.discrete
+            };
+            let mut bytes = vec![0u8; (count as usize).div_ceil(8)];
+            for (index, address) in touched.enumerate() {
+                if bits.get(&address).copied().unwrap_or(false) {
+                    bytes[index / 8] |= 1 << (index % 8);
+                }
+            }
+            let mut answer = vec![function, bytes.len() as u8];
+            answer.extend(bytes);
+            answer
+        }
+        3 | 4 => {
+            let registers = if function == 3 {
+                &state.holding
+            } else {
+                &state.input
+            };
+            let mut answer = vec![function, (count * 2) as u8];
+            for address in touched {
+                let value = registers.get(&address).copied().unwrap_or(0);
+                answer.extend(value.to_be_bytes());
+            }
+            answer
+        }
+        5 => {
+            state.coils.insert(address, argument == 0xff00);
+            pdu[..5].to_vec()
+        }
+        6 => {
+            state.holding.insert(address, argument);
+            pdu[..5].to_vec()
+        }
+        15 => {
+            for (index, address) in touched.enumerate() {
+                let Some(byte) = pdu.get(6 + index / 8) else {
+                    return exception(ILLEGAL_DATA_VALUE);
+                };
+                state.coils.insert(address, byte & (1 << (index % 8)) != 0);
+            }
+            pdu[..5].to_vec()
+        }
+        16 => {
+            for (index, address) in touched.enumerate() {
+                let Some(value) = word(6 + index * 2) else {
+                    return exception(ILLEGAL_DATA_VALUE);
+                };
+                state.holding.insert(address, value);
+            }
+            pdu[..5].to_vec()
+        }
+        _ => exception(ILLEGAL_FUNCTION),
+    }
+}
@@ -0,0 +1,617 @@
+//! A NATS server for one test: core publish and subscribe with wildcards and headers,
+//! optional user/password, token, NKey or JWT logins, subjects the client isn't permitted to
+//! publish or subscribe to, and a record of everything published.
+//! Requests are answered by subscribers, canned replies (see `respond`), or with the "no
+//! responders" status when nothing listens on their subject. Signatures of the server's nonce
+//! are expected but not checked. JetStream goes as far as streams read back through push and
+//! pull consumers, and the object stores built on them (see `jetstream`).
+
+mod jetstream;
+
+use edge_core::json;
+use edge_core::output::Value;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+/// A message a client published.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub subject: String,
+    pub reply: Option<String>,
+    // The raw `NATS/1.0` header block, for messages sent with headers.
+    pub headers: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+}
+
+enum Login {
+    Open,
+    User { user: String, password: String },
+    Token(String),
+    // The public key, e.g. UB43...
+    NKey(String),
+    Jwt(String),
+}
+
+struct Subscription {
+    connection: u64,
+    sid: String,
+    subject: String,
+}
+
+struct State {
+    login: Login,
+    subscriptions: Vec<Subscription>,
+    streams: Vec<jetstream::Stream>,
+    // Subject patterns answered with a fixed payload, as a service would.
+    responders: Vec<(String, Vec<u8>)>,
+    // Frames waiting to be written to each connection.
+    writers: Vec<(u64, mpsc::UnboundedSender<Vec<u8>>)>,
+    published: Vec<Message>,
+    next_connection: u64,
+    // The cluster named in INFO, for a server that is in one.
+    cluster: Option<String>,
+    max_payload: usize,
+    // Subject patterns publishing to, and subscribing to, are refused on, as a user's
+    // permissions would.
+    denied_publish: Vec<String>,
+    denied_subscribe: Vec<String>,
+}
+
+pub struct NatsSimulator {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+    changed: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl NatsSimulator {
+    /// Listens on a free port on 127.0.0.1 and lets anyone in.
+    pub async fn start() -> io::Result<NatsSimulator> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            login: Login::Open,
+            subscriptions: Vec::new(),
+            streams: Vec::new(),
+            responders: Vec::new(),
+            writers: Vec::new(),
+            published: Vec::new(),
+            next_connection: 0,
+            cluster: None,
+            max_payload: 1048576,
+            denied_publish: Vec::new(),
+            denied_subscribe: Vec::new(),
+        }));
+        let changed = Arc::new(Notify::new());
+        let task = tokio::spawn(accept(listener, state.clone(), changed.clone()));
+        Ok(NatsSimulator {
+            address,
+            state,
+            changed,
+            task,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// `nats://127.0.0.1:<port>`.
+    pub fn url(&self) -> String {
+        format!("nats://{}", self.address)
+    }
+
+    /// Only lets in clients connecting with this user and password.
+    pub fn require_user(&self, user: &str, password: &str) {
+        self.state().login = Login::User {
+            user: user.to_string(),
+            password: password.to_string(),
+        };
+    }
+
+    /// Only lets in clients connecting with this token.
+    pub fn require_token(&self, token: &str) {
+        self.state().login = Login::Token(token.to_string());
+    }
+
+    /// Only lets in clients connecting with the NKey whose public key this is.
+    pub fn require_nkey(&self, public_key: &str) {
+        self.state().login = Login::NKey(public_key.to_string());
+    }
+
+    /// Only lets in clients connecting with this user JWT, as from a .creds file.
+    pub fn require_jwt(&self, jwt: &str) {
+        self.state().login = Login::Jwt(jwt.to_string());
+    }
+
+    /// Names the cluster the server says it's in.
+    pub fn set_cluster(&self, name: &str) {
+        self.state().cluster = Some(name.to_string());
+    }
+
+    /// The largest payload the server says it takes, and takes; 1MiB by default. Bigger ones
+    /// end the connection, as nats-server does.
+    pub fn set_max_payload(&self, bytes: usize) {
+        self.state().max_payload = bytes;
+    }
+
+    /// Refuses publishing on subjects matching `pattern` with a permissions violation,
+    /// keeping the connection open.
+    pub fn deny_publish(&self, pattern: &str) {
+        self.state().denied_publish.push(pattern.to_string());
+    }
+
+    /// Refuses subscriptions to subjects matching `pattern` the same way.
+    pub fn deny_subscribe(&self, pattern: &str) {
+        self.state().denied_subscribe.push(pattern.to_string());
+    }
+
+    /// Adds a JetStream stream capturing `subjects`, which may have wildcards; its sequence
+    /// numbers start at 1.
+    pub fn add_stream(&self, name: &str, subjects: &[&str]) {
+        self.state()
+            .streams
+            .push(jetstream::Stream::new(name, subjects));
+    }
+
+    /// The stream sequence numbers of the messages clients acknowledged through the stream's
+    /// consumers, in the order they did.
+    pub fn acknowledged(&self, stream: &str) -> Vec<u64> {
+        self.state()
+            .streams
+            .iter()
+            .find(|candidate| candidate.name == stream)
+            .map(|stream| stream.acknowledged.clone())
+            .unwrap_or_default()
+    }
+
+    /// Waits until clients have acknowledged at least `count` messages of the stream; false
+    /// on timeout.
+    pub async fn wait_for_acks(&self, stream: &str, count: usize, timeout: Duration) -> bool {
+        self.wait_until(timeout, |state| {
+            state
+                .streams
+                .iter()
+                .any(|candidate| candidate.name == stream && candidate.acknowledged.len() >= count)
+        })
+        .await
+    }
+
+    /// Everything clients published so far, oldest first, JetStream API requests and
+    /// acknowledgements aside.
+    pub fn messages(&self) -> Vec<Message> {
+        self.state().published.clone()
+    }
+
+    /// The subjects clients are subscribed to.
+    pub fn subscriptions(&self) -> Vec<String> {
+        let state = self.state();
+        state
+            .subscriptions
+            .iter()
+            .map(|subscription| subscription.subject.clone())
+            .collect()
+    }
+
+    /// Waits until some client subscribes to exactly `subject`; false on timeout.
+    pub async fn wait_for_subscription(&self, subject: &str, timeout: Duration) -> bool {
+        self.wait_until(timeout, |state| {
+            state
+                .subscriptions
+                .iter()
+                .any(|subscription| subscription.subject == subject)
+        })
+        .await
+    }
+
+    /// Waits until clients have published at least `count` messages; false on timeout.
+    pub async fn wait_for_messages(&self, count: usize, timeout: Duration) -> bool {
+        self.wait_until(timeout, |state| state.published.len() >= count)
+            .await
+    }
+
+    /// Delivers a message to every matching subscriber and stores it in the stream that
+    /// captures its subject, as if another client published it.
+    pub fn publish(&self, subject: &str, payload: &[u8]) {
+        self.publish_with_headers(subject, &[], payload);
+    }
+
+    /// [`NatsSimulator::publish`] with headers, as an HMSG.
+    pub fn publish_with_headers(&self, subject: &str, headers: &[(&str, &str)], payload: &[u8]) {
+        let headers = (!headers.is_empty()).then(|| {
+            let mut block = b"NATS/1.0\r\n".to_vec();
+            for (key, value) in headers {
+                block.extend_from_slice(format!("{key}: {value}\r\n").as_bytes());
+            }
+            block.extend_from_slice(b"\r\n");
+            block
+        });
+        let mut state = self.state();
+        let state = &mut *state;
+        deliver(state, subject, None, headers.as_deref(), payload);
+        let message = Message {
+            subject: subject.to_string(),
+            reply: None,
+            headers,
+            payload: payload.to_vec(),
+        };
+        jetstream::store(&mut state.streams, &message);
+        pump(state);
+    }
+
+    /// Delivers a message asking for an answer on `reply`, as a requesting client would.
+    pub fn request(&self, subject: &str, reply: &str, payload: &[u8]) {
+        deliver(&self.state(), subject, Some(reply), None, payload);
+    }
+
+    /// Answers every request on subjects matching `subject` with `payload`.
+    pub fn respond(&self, subject: &str, payload: &[u8]) {
+        self.state()
+            .responders
+            .push((subject.to_string(), payload.to_vec()));
+    }
+
+    async fn wait_until(&self, timeout: Duration, done: impl Fn(&State) -> bool) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let changed = self.changed.notified();
+            if done(&self.state()) {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return done(&self.state());
+            }
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("simulator state poisoned")
+    }
+}
+
+impl Drop for NatsSimulator {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept(listener: TcpListener, state: Arc<Mutex<State>>, changed: Arc<Notify>) {
+    let mut connections = Vec::new();
+    while let Ok((stream, _)) = listener.accept().await {
+        connections.push(AbortOnDrop(tokio::spawn(serve(
+            stream,
+            state.clone(),
+            changed.clone(),
+        ))));
+    }
+}
+
+// Connections end with the accept loop, so dropping the simulator closes them all.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>, changed: Arc<Notify>) {
+    let (reader, mut writer) = stream.into_split();
+    let (sender, mut frames) = mpsc::unbounded_channel::<Vec<u8>>();
+    let (id, auth_required, cluster, max_payload) = {
+        let mut state = state.lock().expect("simulator state poisoned");
+        state.next_connection += 1;
+        let id = state.next_connection;
+        state.writers.push((id, sender.clone()));
+        let cluster = match &state.cluster {
+            Some(name) => format!(",\"cluster\":\"{name}\""),
+            None => String::new(),
+        };
+        let auth_required = !matches!(state.login, Login::Open);
+        (id, auth_required, cluster, state.max_payload)
+    };
+    let info = format!(
+        "INFO {{\"server_id\":\"simulator\",\"server_name\":\"simulator\",\"version\":\"2.9.0\",\
+         \"go\":\"go1.19\",\"host\":\"127.0.0.1\",\"port\":0,\"headers\":true,\
+         \"max_payload\":{max_payload},\"proto\":1,\"auth_required\":{auth_required},\
+         \"nonce\":\"simulator-nonce\"{cluster}}}\r\n"
+    );
+    let _ = sender.send(info.into_bytes());
+    let writes = tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            if frame.is_empty() || writer.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = read(reader, id, &state, &changed, &sender).await;
+    if let Err(err) = result {
+        log::debug!("Simulator connection {id} ended: {err}");
+    }
+    {
+        let mut state = state.lock().expect("simulator state poisoned");
+        state
+            .subscriptions
+            .retain(|subscription| subscription.connection != id);
+        state.writers.retain(|(connection, _)| *connection != id);
+    }
+    changed.notify_waiters();
+    // An empty frame closes the connection once everything before it is written.
+    let _ = sender.send(Vec::new());
+    let _ = writes.await;
+}
+
+async fn read(
+    reader: OwnedReadHalf,
+    id: u64,
+    state: &Mutex<State>,
+    changed: &Notify,
+    sender: &mpsc::UnboundedSender<Vec<u8>>,
+) -> io::Result<()> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let mut words = line.split_whitespace();
+        let Some(operation) = words.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = words.collect();
+        match operation.to_uppercase().as_str() {
+            "CONNECT" => {
+                let options = json::parse(line.trim()["CONNECT".len()..].trim())
+                    .map_err(|err| invalid(&err.to_string()))?;
+                if !logged_in(
+                    &state.lock().expect("simulator state poisoned").login,
+                    &options,
+                ) {
+                    let _ = sender.send(b"-ERR 'Authorization Violation'\r\n".to_vec());
+                    return Ok(());
+                }
+            }
+            "PING" => {
+                let _ = sender.send(b"PONG\r\n".to_vec());
+            }
+            "PONG" => {}
+            "SUB" => {
+                // SUB <subject> [queue group] <sid>
+                let (Some(subject), Some(sid)) = (arguments.first(), arguments.last()) else {
+                    return Err(invalid("SUB needs a subject and a sid"));
+                };
+                let mut state = state.lock().expect("simulator state poisoned");
+                if let Some(refused) = refused(&state.denied_subscribe, subject) {
+                    let _ = sender.send(
+                        format!(
+                            "-ERR 'Permissions Violation for Subscription to \"{refused}\"'\r\n"
+                        )
+                        .into_bytes(),
+                    );
+                    continue;
+                }
+                state.subscriptions.push(Subscription {
+                    connection: id,
+                    sid: sid.to_string(),
+                    subject: subject.to_string(),
+                });
+                // A push consumer starts delivering once someone listens.
+                pump(&mut state);
+                drop(state);
+                changed.notify_waiters();
+            }
+            "UNSUB" => {
+                let Some(sid) = arguments.first() else {
+                    return Err(invalid("UNSUB needs a sid"));
+                };
+                state
+                    .lock()
+                    .expect("simulator state poisoned")
+                    .subscriptions
+                    .retain(|subscription| {
+                        subscription.connection != id || subscription.sid != *sid
+                    });
+                changed.notify_waiters();
+            }
+            "PUB" | "HPUB" => {
+                // PUB <subject> [reply] <size>, HPUB <subject> [reply] <header size> <size>
+                let headers = operation.eq_ignore_ascii_case("HPUB");
+                let sizes = if headers { 2 } else { 1 };
+                if arguments.len() < 1 + sizes || arguments.len() > 2 + sizes {
+                    return Err(invalid("wrong number of arguments to PUB"));
+                }
+                let number = |word: &str| word.parse::<usize>().map_err(|_| invalid("bad size"));
+                let total = number(arguments[arguments.len() - 1])?;
+                let header_size = if headers {
+                    number(arguments[arguments.len() - 2])?
+                } else {
+                    0
+                };
+                let max_payload = state.lock().expect("simulator state poisoned").max_payload;
+                if total > max_payload {
+                    let _ = sender.send(b"-ERR 'Maximum Payload Violation'\r\n".to_vec());
+                    return Ok(());
+                }
+                let mut data = vec![0; total + 2];
+                reader.read_exact(&mut data).await?;
+                data.truncate(total);
+                let message = Message {
+                    subject: arguments[0].to_string(),
+                    reply: (arguments.len() == 2 + sizes).then(|| arguments[1].to_string()),
+                    headers: headers.then(|| data[..header_size.min(total)].to_vec()),
+                    payload: data[header_size.min(total)..].to_vec(),
+                };
+                {
+                    let mut state = state.lock().expect("simulator state poisoned");
+                    let state = &mut *state;
+                    if let Some(refused) = refused(&state.denied_publish, &message.subject) {
+                        let _ = sender.send(
+                            format!(
+                                "-ERR 'Permissions Violation for Publish to \"{refused}\"'\r\n"
+                            )
+                            .into_bytes(),
+                        );
+                        continue;
+                    }
+                    let reply = message.reply.as_deref();
+                    if let Some(request) = message.subject.strip_prefix("$JS.API.") {
+                        let reply = reply.unwrap_or_default();
+                        let response =
+                            jetstream::api(&mut state.streams, request, reply, &message.payload);
+                        if let Some(response) = response {
+                            let headers = response.headers.as_deref();
+                            deliver(state, reply, None, headers, &response.payload);
+                        }
+                    } else if message.subject.starts_with("$JS.ACK.") {
+                        jetstream::acknowledge(&mut state.streams, &message.subject);
+                    } else {
+                        deliver(
+                            state,
+                            &message.subject,
+                            reply,
+                            message.headers.as_deref(),
+                            &message.payload,
+                        );
+                        let ack = jetstream::store(&mut state.streams, &message);
+                        if let Some(reply) = reply {
+                            answer(state, &message.subject, reply, ack);
+                        }
+                        state.published.push(message);
+                    }
+                    pump(state);
+                }
+                changed.notify_waiters();
+            }
+            other => {
+                let _ = sender
+                    .send(format!("-ERR 'Unknown Protocol Operation {other}'\r\n").into_bytes());
+            }
+        }
+    }
+}
+
+fn logged_in(login: &Login, options: &Value) -> bool {
+    let Value::Record(options) = options else {
+        return false;
+    };
+    let field = |name: &str| match options.get(name) {
+        Some(Value::String(value)) => Some(value.as_str()),
+        _ => None,
+    };
+    match login {
+        Login::Open => true,
+        Login::User { user, password } => {
+            field("user") == Some(user.as_str()) && field("pass") == Some(password.as_str())
+        }
+        Login::Token(token) => field("auth_token") == Some(token.as_str()),
+        Login::NKey(key) => field("nkey") == Some(key.as_str()) && field("sig").is_some(),
+        Login::Jwt(jwt) => field("jwt") == Some(jwt.as_str()) && field("sig").is_some(),
+    }
+}
+
+/// Answers a request published on `subject`: with the stream's `ack`, a canned reply, or "no
+/// responders" when not even a subscriber got it.
+fn answer(state: &State, subject: &str, reply: &str, ack: Option<String>) {
+    let canned = state
+        .responders
+        .iter()
+        .find(|(pattern, _)| matches(pattern, subject))
+        .map(|(_, payload)| payload.clone());
+    let subscribed = state
+        .subscriptions
+        .iter()
+        .any(|subscription| matches(&subscription.subject, subject));
+    match ack.map(String::into_bytes).or(canned) {
+        Some(payload) => deliver(state, reply, None, None, &payload),
+        None if !subscribed => deliver(state, reply, None, Some(b"NATS/1.0 503\r\n\r\n"), &[]),
+        None => {}
+    }
+}
+
+/// Delivers what JetStream consumers have to send now.
+fn pump(state: &mut State) {
+    let subscriptions = &state.subscriptions;
+    let deliveries = jetstream::deliveries(&mut state.streams, |subject| {
+        subscriptions
+            .iter()
+            .any(|subscription| matches(&subscription.subject, subject))
+    });
+    for delivery in deliveries {
+        deliver(
+            state,
+            &delivery.subject,
+            Some(&delivery.reply),
+            delivery.headers.as_deref(),
+            &delivery.payload,
+        );
+    }
+}
+
+fn deliver(
+    state: &State,
+    subject: &str,
+    reply: Option<&str>,
+    headers: Option<&[u8]>,
+    payload: &[u8],
+) {
+    for subscription in &state.subscriptions {
+        if !matches(&subscription.subject, subject) {
+            continue;
+        }
+        let Some((_, writer)) = state
+            .writers
+            .iter()
+            .find(|(connection, _)| *connection == subscription.connection)
+        else {
+            continue;
+        };
+        let reply = reply.map(|reply| format!("{reply} ")).unwrap_or_default();
+        let sid = &subscription.sid;
+        let mut frame = match headers {
+            Some(headers) => format!(
+                "HMSG {subject} {sid} {reply}{} {}\r\n",
+                headers.len(),
+                headers.len() + payload.len()
+            )
+            .into_bytes(),
+            None => format!("MSG {subject} {sid} {reply}{}\r\n", payload.len()).into_bytes(),
+        };
+        frame.extend_from_slice(headers.unwrap_or_default());
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(b"\r\n");
+        let _ = writer.send(frame);
+    }
+}
+
+/// Whether `subject` matches the subscription `pattern`, with `*` for one token and `>` for
+/// the rest.
+/// `subject` if a pattern in `denied` matches it.
+fn refused<'a>(denied: &[String], subject: &'a str) -> Option<&'a str> {
+    denied
+        .iter()
+        .any(|pattern| matches(pattern, subject))
+        .then_some(subject)
+}
+
+fn matches(pattern: &str, subject: &str) -> bool {
+    let mut subject = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (token, Some(word)) if token == word => {}
+            _ => return false,
+        }
+    }
+    subject.next().is_none()
+}
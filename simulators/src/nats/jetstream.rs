@@ -0,0 +1,420 @@
+//! Enough of JetStream to publish to streams and read them back: in-memory streams that ack
+//! what's published on their subjects, and push or pull consumers delivering from the oldest
+//! message, each message once, whatever deliver policy they ask for. Streams can be created,
+//! purged and read by subject, consumers listed and deleted, and a `Nats-Rollup: sub` header
+//! replaces what came before on its subject, which is all object stores need.
+//! Acknowledgements are recorded but never waited for.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use edge_core::json;
+use edge_core::output::{self, Record, Value};
+
+use super::{matches, Message};
+
+// Every stream and consumer was created at the same moment, as far as clients can tell.
+const CREATED: &str = "2022-01-01T00:00:00Z";
+
+pub(super) struct Stream {
+    pub(super) name: String,
+    subjects: Vec<String>,
+    // By sequence number, from 1; None once purged or rolled up.
+    messages: Vec<Option<Message>>,
+    consumers: Vec<Consumer>,
+    // Stream sequence numbers acknowledged through any of its consumers.
+    pub(super) acknowledged: Vec<u64>,
+}
+
+struct Consumer {
+    name: String,
+    // As the client sent it, to give back in the consumer's info.
+    config: Value,
+    filter: Option<String>,
+    // Where a push consumer sends messages; pull consumers wait to be asked.
+    deliver_subject: Option<String>,
+    // Index of the next message of the stream to look at.
+    next: usize,
+    delivered: u64,
+    // Pull requests not yet answered in full: their inbox and how many messages they want.
+    waiting: Vec<(String, u64)>,
+}
+
+/// A message for the server to deliver to whoever subscribed to `subject`.
+pub(super) struct Delivery {
+    pub(super) subject: String,
+    pub(super) reply: String,
+    pub(super) headers: Option<Vec<u8>>,
+    pub(super) payload: Vec<u8>,
+}
+
+/// The server's answer to an API request: JSON, or a status such as "404 No Messages".
+pub(super) struct Answer {
+    pub(super) headers: Option<Vec<u8>>,
+    pub(super) payload: Vec<u8>,
+}
+
+impl Answer {
+    fn json(text: String) -> Option<Answer> {
+        Some(Answer {
+            headers: None,
+            payload: text.into_bytes(),
+        })
+    }
+}
+
+impl Stream {
+    pub(super) fn new(name: &str, subjects: &[&str]) -> Stream {
+        Stream {
+            name: name.to_string(),
+            subjects: subjects.iter().map(|subject| subject.to_string()).collect(),
+            messages: Vec::new(),
+            consumers: Vec::new(),
+            acknowledged: Vec::new(),
+        }
+    }
+
+    fn live(&self) -> impl Iterator<Item = (u64, &Message)> {
+        self.messages
+            .iter()
+            .enumerate()
+            .filter_map(|(index, message)| Some((index as u64 + 1, message.as_ref()?)))
+    }
+}
+
+impl Consumer {
+    /// Messages of `stream` this consumer has yet to deliver.
+    fn pending(&self, stream: &[Option<Message>]) -> usize {
+        stream[self.next.min(stream.len())..]
+            .iter()
+            .flatten()
+            .filter(|message| self.wants(&message.subject))
+            .count()
+    }
+
+    fn wants(&self, subject: &str) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| matches(filter, subject))
+    }
+}
+
+/// Keeps `message` in the stream that captures its subject, if any, returning the PubAck.
+pub(super) fn store(streams: &mut [Stream], message: &Message) -> Option<String> {
+    let stream = streams.iter_mut().find(|stream| {
+        stream
+            .subjects
+            .iter()
+            .any(|pattern| matches(pattern, &message.subject))
+    })?;
+    let rollup = message.headers.as_ref().is_some_and(|headers| {
+        String::from_utf8_lossy(headers)
+            .to_ascii_lowercase()
+            .contains("nats-rollup: sub")
+    });
+    if rollup {
+        for stored in &mut stream.messages {
+            if stored
+                .as_ref()
+                .is_some_and(|stored| stored.subject == message.subject)
+            {
+                *stored = None;
+            }
+        }
+    }
+    stream.messages.push(Some(message.clone()));
+    Some(format!(
+        "{{\"stream\":\"{}\",\"seq\":{}}}",
+        stream.name,
+        stream.messages.len()
+    ))
+}
+
+/// Answers a `$JS.API.<request>` request, or None for a pull request, which is answered with
+/// messages as they come.
+pub(super) fn api(
+    streams: &mut Vec<Stream>,
+    request: &str,
+    reply: &str,
+    body: &[u8],
+) -> Option<Answer> {
+    let body = match json::parse(&String::from_utf8_lossy(body)) {
+        Ok(Value::Record(body)) => body,
+        _ => Record::new(),
+    };
+    let tokens: Vec<&str> = request.split('.').collect();
+    let (operation, name) = match tokens.as_slice() {
+        ["STREAM", "CREATE", stream] => ("create stream", stream),
+        ["STREAM", "INFO", stream] => ("stream info", stream),
+        ["STREAM", "MSG", "GET", stream] => ("get", stream),
+        ["STREAM", "PURGE", stream] => ("purge", stream),
+        ["CONSUMER", "CREATE", stream, ..] | ["CONSUMER", "DURABLE", "CREATE", stream, _] => {
+            ("create", stream)
+        }
+        ["CONSUMER", "INFO", stream, _] => ("consumer info", stream),
+        ["CONSUMER", "LIST", stream] => ("list", stream),
+        ["CONSUMER", "DELETE", stream, _] => ("delete", stream),
+        ["CONSUMER", "MSG", "NEXT", stream, _] => ("next", stream),
+        _ => return Answer::json(error(400, 10003, "unsupported by the simulator")),
+    };
+    if operation == "create stream" && !streams.iter().any(|stream| stream.name == *name) {
+        let subjects = match body.get("subjects") {
+            Some(Value::List(subjects)) => subjects
+                .iter()
+                .filter_map(|subject| match subject {
+                    Value::String(subject) => Some(subject.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![*name],
+        };
+        streams.push(Stream::new(name, &subjects));
+    }
+    let Some(stream) = streams.iter_mut().find(|stream| stream.name == *name) else {
+        return Answer::json(error(404, 10059, "stream not found"));
+    };
+    let consumer_name = tokens.last().copied().unwrap_or_default();
+    let text = |record: &Record, name: &str| match record.get(name) {
+        Some(Value::String(text)) => Some(text.clone()),
+        _ => None,
+    };
+    let number = |record: &Record, name: &str| match record.get(name) {
+        Some(Value::Integer(number)) if *number > 0 => Some(*number as u64),
+        _ => None,
+    };
+    match operation {
+        "create stream" | "stream info" => Answer::json(stream_info(stream)),
+        "get" => {
+            let found = match (text(&body, "last_by_subj"), number(&body, "seq")) {
+                (Some(subject), _) => stream
+                    .live()
+                    .filter(|(_, message)| message.subject == subject)
+                    .last(),
+                (None, Some(sequence)) => stream.live().find(|(seq, _)| *seq == sequence),
+                (None, None) => None,
+            };
+            let Some((sequence, message)) = found else {
+                return Answer::json(error(404, 10037, "no message found"));
+            };
+            let mut record = Record::new()
+                .field("subject", message.subject.as_str())
+                .field("seq", sequence)
+                .field("data", BASE64.encode(&message.payload))
+                .field("time", CREATED);
+            if let Some(headers) = &message.headers {
+                record.push("hdrs", BASE64.encode(headers));
+            }
+            let response = Record::new()
+                .field("type", "io.nats.jetstream.api.v1.stream_msg_get_response")
+                .field("message", record);
+            Answer::json(output::json(&Value::Record(response)))
+        }
+        "purge" => {
+            let filter = text(&body, "filter");
+            let mut purged = 0;
+            for stored in &mut stream.messages {
+                let purge = stored.as_ref().is_some_and(|message| {
+                    filter
+                        .as_ref()
+                        .is_none_or(|filter| matches(filter, &message.subject))
+                });
+                if purge {
+                    *stored = None;
+                    purged += 1;
+                }
+            }
+            Answer::json(format!("{{\"success\":true,\"purged\":{purged}}}"))
+        }
+        "create" => {
+            let config = match body.get("config") {
+                Some(Value::Record(config)) => config.clone(),
+                _ => Record::new(),
+            };
+            let name = text(&config, "name")
+                .or_else(|| text(&config, "durable_name"))
+                .unwrap_or_else(|| format!("simulator-{}", stream.consumers.len() + 1));
+            if !stream
+                .consumers
+                .iter()
+                .any(|consumer| consumer.name == name)
+            {
+                stream.consumers.push(Consumer {
+                    name: name.clone(),
+                    filter: text(&config, "filter_subject"),
+                    deliver_subject: text(&config, "deliver_subject"),
+                    config: Value::Record(config),
+                    next: 0,
+                    delivered: 0,
+                    waiting: Vec::new(),
+                });
+            }
+            Answer::json(consumer_info(stream, &name))
+        }
+        "consumer info" => Answer::json(consumer_info(stream, consumer_name)),
+        "list" => {
+            let consumers: Vec<String> = stream
+                .consumers
+                .iter()
+                .map(|consumer| consumer_info(stream, &consumer.name))
+                .collect();
+            Answer::json(format!(
+                "{{\"type\":\"io.nats.jetstream.api.v1.consumer_list_response\",\
+                 \"total\":{},\"offset\":0,\"limit\":256,\"consumers\":[{}]}}",
+                consumers.len(),
+                consumers.join(",")
+            ))
+        }
+        _ => {
+            let Some(index) = stream
+                .consumers
+                .iter()
+                .position(|consumer| consumer.name == consumer_name)
+            else {
+                return Answer::json(error(404, 10014, "consumer not found"));
+            };
+            if operation == "delete" {
+                stream.consumers.remove(index);
+                return Answer::json("{\"success\":true}".to_string());
+            }
+            let consumer = &mut stream.consumers[index];
+            let no_wait = matches!(body.get("no_wait"), Some(Value::Bool(true)));
+            if no_wait && consumer.pending(&stream.messages) == 0 {
+                return Some(Answer {
+                    headers: Some(b"NATS/1.0 404 No Messages\r\n\r\n".to_vec()),
+                    payload: Vec::new(),
+                });
+            }
+            let batch = number(&body, "batch").unwrap_or(1);
+            consumer.waiting.push((reply.to_string(), batch));
+            None
+        }
+    }
+}
+
+/// Notes the acknowledgement published on `subject`, a message's
+/// `$JS.ACK.<stream>.<consumer>.<delivered>.<stream sequence>...` reply subject.
+pub(super) fn acknowledge(streams: &mut [Stream], subject: &str) {
+    let tokens: Vec<&str> = subject.split('.').collect();
+    let (Some(name), Some(Ok(sequence))) = (tokens.get(2), tokens.get(5).map(|s| s.parse())) else {
+        return;
+    };
+    if let Some(stream) = streams.iter_mut().find(|stream| stream.name == *name) {
+        stream.acknowledged.push(sequence);
+    }
+}
+
+/// What consumers have to deliver now: push consumers whatever they haven't sent once
+/// someone subscribes to their deliver subject, pull consumers as much as they were asked for.
+pub(super) fn deliveries(
+    streams: &mut [Stream],
+    subscribed: impl Fn(&str) -> bool,
+) -> Vec<Delivery> {
+    let mut deliveries = Vec::new();
+    for stream in streams {
+        let messages = &stream.messages;
+        for consumer in &mut stream.consumers {
+            loop {
+                let inbox = match &consumer.deliver_subject {
+                    Some(subject) if subscribed(subject) => subject.clone(),
+                    Some(_) => break,
+                    None => match consumer.waiting.first() {
+                        Some((inbox, _)) => inbox.clone(),
+                        None => break,
+                    },
+                };
+                let Some(index) = (consumer.next..messages.len()).find(|index| {
+                    messages[*index]
+                        .as_ref()
+                        .is_some_and(|message| consumer.wants(&message.subject))
+                }) else {
+                    consumer.next = messages.len();
+                    break;
+                };
+                consumer.next = index + 1;
+                consumer.delivered += 1;
+                if let Some((_, wanted)) = consumer.waiting.first_mut() {
+                    if consumer.deliver_subject.is_none() {
+                        *wanted -= 1;
+                        if *wanted == 0 {
+                            consumer.waiting.remove(0);
+                        }
+                    }
+                }
+                let Some(message) = &messages[index] else {
+                    continue;
+                };
+                let pending = consumer.pending(messages);
+                deliveries.push(Delivery {
+                    subject: inbox,
+                    reply: format!(
+                        "$JS.ACK.{}.{}.1.{}.{}.0.{pending}",
+                        stream.name,
+                        consumer.name,
+                        index + 1,
+                        consumer.delivered
+                    ),
+                    headers: message.headers.clone(),
+                    payload: message.payload.clone(),
+                });
+            }
+        }
+    }
+    deliveries
+}
+
+fn stream_info(stream: &Stream) -> String {
+    let subjects: Vec<Value> = stream
+        .subjects
+        .iter()
+        .map(|subject| Value::String(subject.clone()))
+        .collect();
+    let bytes: usize = stream
+        .live()
+        .map(|(_, message)| message.payload.len())
+        .sum();
+    let count = stream.live().count();
+    let first = stream.live().next().map_or(0, |(sequence, _)| sequence);
+    format!(
+        "{{\"type\":\"io.nats.jetstream.api.v1.stream_info_response\",\
+         \"config\":{{\"name\":\"{}\",\"subjects\":{},\"retention\":\"limits\",\
+         \"max_consumers\":-1,\"max_msgs\":-1,\"max_bytes\":-1,\"max_age\":0,\
+         \"max_msgs_per_subject\":-1,\"discard\":\"old\",\"storage\":\"memory\",\
+         \"num_replicas\":1}},\"created\":\"{CREATED}\",\
+         \"state\":{{\"messages\":{count},\"bytes\":{bytes},\"first_seq\":{first},\
+         \"first_ts\":\"{CREATED}\",\"last_seq\":{},\"last_ts\":\"{CREATED}\",\
+         \"consumer_count\":{}}}}}",
+        stream.name,
+        output::json(&Value::List(subjects)),
+        stream.messages.len(),
+        stream.consumers.len()
+    )
+}
+
+fn consumer_info(stream: &Stream, name: &str) -> String {
+    let Some(consumer) = stream
+        .consumers
+        .iter()
+        .find(|consumer| consumer.name == name)
+    else {
+        return error(404, 10014, "consumer not found");
+    };
+    let waiting = consumer.waiting.len();
+    let pending = consumer.pending(&stream.messages);
+    format!(
+        "{{\"type\":\"io.nats.jetstream.api.v1.consumer_info_response\",\
+         \"stream_name\":\"{}\",\"name\":\"{name}\",\"created\":\"{CREATED}\",\"config\":{},\
+         \"delivered\":{{\"consumer_seq\":{},\"stream_seq\":{}}},\
+         \"ack_floor\":{{\"consumer_seq\":0,\"stream_seq\":0}},\"num_ack_pending\":0,\
+         \"num_redelivered\":0,\"num_waiting\":{waiting},\"num_pending\":{pending},\
+         \"cluster\":{{}}}}",
+        stream.name,
+        output::json(&consumer.config),
+        consumer.delivered,
+        consumer.next
+    )
+}
+
+fn error(code: u16, err_code: u32, description: &str) -> String {
+    format!(
+        "{{\"error\":{{\"code\":{code},\"err_code\":{err_code},\"description\":\"{description}\"}}}}"
+    )
+}
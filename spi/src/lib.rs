@@ -0,0 +1,215 @@
+mod spidev;
+
+use clap::{Parser, Subcommand};
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::output::{OutputArgs, Record};
+use edge_core::shutdown::{self, Summary};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use spidev::Spidev;
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Defaults to /dev/spidev0.0.
+    #[clap(short, long, env = "EDGE_SPI_DEVICE", action)]
+    device: Option<String>,
+
+    /// Clock polarity and phase, 0-3.
+    #[clap(short, long, value_parser = clap::value_parser!(u8).range(0..=3))]
+    mode: Option<u8>,
+    /// Clock speed in Hz.
+    #[clap(short, long, action)]
+    speed: Option<u32>,
+    #[clap(short, long, action)]
+    bits_per_word: Option<u8>,
+    #[clap(long, action)]
+    lsb_first: bool,
+    #[clap(long, action)]
+    cs_high: bool,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+
+    #[clap(subcommand)]
+    command: Option<Subcommands>,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Send bytes and print what was clocked back, e.g. `transfer 9f 00 00 00`.
+    Transfer {
+        #[clap(value_parser)]
+        data: Vec<String>,
+        /// Append this many 0x00 bytes to clock out a response.
+        #[clap(short, long, action)]
+        read: Option<usize>,
+        /// Repeat the transfer every N milliseconds.
+        #[clap(short, long, action)]
+        interval: Option<u64>,
+    },
+
+    /// Open and configure the device without clocking anything out, exiting 0 if that works,
+    /// for container and systemd health probes.
+    Healthcheck,
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+pub fn run(cli: Args) {
+    if let Some(Subcommands::Completions { shell }) = cli.command {
+        completions::print::<Args>(shell, "spi");
+        return;
+    }
+    if let Some(Subcommands::GenMan { directory }) = &cli.command {
+        man::generate::<Args>("spi", directory.as_deref());
+        return;
+    }
+
+    let command = if let Some(command) = cli.command {
+        command
+    } else {
+        exit::fatal_with(Code::Usage, "No subcommand specified.");
+    };
+
+    let path = cli.device.as_deref().unwrap_or("/dev/spidev0.0");
+    let mut mode = cli.mode.unwrap_or(0);
+    if cli.lsb_first {
+        mode |= spidev::MODE_LSB_FIRST;
+    }
+    if cli.cs_high {
+        mode |= spidev::MODE_CS_HIGH;
+    }
+    let speed = cli.speed.unwrap_or(1_000_000);
+    let bits_per_word = cli.bits_per_word.unwrap_or(8);
+    let out = cli.output.load();
+    if let Subcommands::Healthcheck = command {
+        health::check_blocking(&out, path, || {
+            Spidev::open(path, mode, speed, bits_per_word)
+                .map(|_| format!("configured mode {mode:#04x} at {speed} Hz"))
+        });
+    }
+    let device = match Spidev::open(path, mode, speed, bits_per_word) {
+        Ok(device) => device,
+        Err(err) => exit::fatal_error(format!("Unable to open {path}"), &err),
+    };
+
+    match command {
+        Subcommands::Completions { .. } | Subcommands::GenMan { .. } => {
+            unreachable!("handled above")
+        }
+        Subcommands::Healthcheck => unreachable!("handled before opening the device"),
+        Subcommands::Transfer {
+            data,
+            read,
+            interval,
+        } => {
+            let mut tx = match parse_hex(&data) {
+                Ok(tx) => tx,
+                Err(err) => exit::fatal_with(Code::Usage, format!("Invalid transfer data: {err}")),
+            };
+            tx.resize(tx.len() + read.unwrap_or(0), 0);
+            if tx.is_empty() {
+                exit::fatal_with(Code::Usage, "Nothing to transfer.");
+            }
+
+            let summary = Summary::new(&["transfers"]);
+            if interval.is_some() {
+                shutdown::listen_blocking();
+            }
+            loop {
+                match device.transfer(&tx) {
+                    Ok(rx) => {
+                        summary.count("transfers");
+                        let record = Record::new().field("tx", hex(&tx)).field("rx", hex(&rx));
+                        out.record(&record, || {
+                            println!("TX {}", hex(&tx));
+                            println!("RX {}", hex(&rx));
+                        });
+                    }
+                    Err(err) => exit::fatal_error("Transfer failed", &err),
+                }
+                match interval {
+                    Some(ms) if shutdown::sleep(Duration::from_millis(ms)) => {}
+                    Some(_) => {
+                        summary.print();
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+/// Accepts bytes as separate arguments or run together, with optional 0x prefixes and
+/// `:`/`,` separators: `9f 00`, `0x9f,0x00` and `9f00` are all the same.
+fn parse_hex(args: &[String]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for arg in args {
+        for token in arg.split([':', ',', ' ']).filter(|token| !token.is_empty()) {
+            let digits = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            if !digits.is_ascii() {
+                return Err(format!("{token}: not hex digits"));
+            }
+            if digits.len() % 2 != 0 {
+                return Err(format!("{token}: odd number of hex digits"));
+            }
+            for i in (0..digits.len()).step_by(2) {
+                let byte = u8::from_str_radix(&digits[i..i + 2], 16)
+                    .map_err(|err| format!("{token}: {err}"))?;
+                bytes.push(byte);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+fn hex(data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|byte| format!("{byte:02x}")).collect();
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Vec<u8>, String> {
+        parse_hex(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_bytes_however_they_are_written() {
+        assert_eq!(parse(&["9f", "00"]).unwrap(), [0x9f, 0x00]);
+        assert_eq!(parse(&["0x9f,0x00"]).unwrap(), [0x9f, 0x00]);
+        assert_eq!(parse(&["9f00"]).unwrap(), [0x9f, 0x00]);
+        assert_eq!(
+            parse(&["de:ad", "0XBEEF"]).unwrap(),
+            [0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(parse(&[]).unwrap(), []);
+
+        assert!(parse(&["9f0"]).is_err());
+        assert!(parse(&["zz"]).is_err());
+        assert!(parse(&["a\u{e9}a"]).is_err());
+        assert_eq!(hex(&[0x9f, 0x00]), "9f 00");
+    }
+}
@@ -0,0 +1,4 @@
+fn main() {
+    spi::run(edge_core::logging::parse("spi"));
+    edge_core::expect::finish();
+}
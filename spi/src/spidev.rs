@@ -0,0 +1,96 @@
+//! Full-duplex transfers through the Linux spidev driver (linux/spi/spidev.h).
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+
+pub const MODE_CS_HIGH: u8 = 0x04;
+pub const MODE_LSB_FIRST: u8 = 0x08;
+
+#[repr(C)]
+#[derive(Default)]
+struct Transfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+const fn iow(nr: u64, size: usize) -> u64 {
+    (1 << 30) | ((size as u64) << 16) | ((b'k' as u64) << 8) | nr
+}
+
+const WR_MODE: u64 = iow(1, size_of::<u8>());
+const WR_BITS_PER_WORD: u64 = iow(3, size_of::<u8>());
+const WR_MAX_SPEED_HZ: u64 = iow(4, size_of::<u32>());
+const MESSAGE_1: u64 = iow(0, size_of::<Transfer>());
+
+pub struct Spidev {
+    file: File,
+    speed_hz: u32,
+    bits_per_word: u8,
+}
+
+impl Spidev {
+    pub fn open(path: &str, mode: u8, speed_hz: u32, bits_per_word: u8) -> io::Result<Spidev> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let device = Spidev {
+            file,
+            speed_hz,
+            bits_per_word,
+        };
+        device.ioctl(WR_MODE, &mode)?;
+        device.ioctl(WR_BITS_PER_WORD, &bits_per_word)?;
+        device.ioctl(WR_MAX_SPEED_HZ, &speed_hz)?;
+        Ok(device)
+    }
+
+    fn ioctl<T>(&self, request: u64, argument: &T) -> io::Result<()> {
+        // SAFETY: each request number encodes the size of the value passed with it.
+        let result =
+            unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, argument as *const T) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Clocks `tx` out while clocking the same number of bytes in, in one chip select cycle.
+    pub fn transfer(&self, tx: &[u8]) -> io::Result<Vec<u8>> {
+        let mut rx = vec![0u8; tx.len()];
+        let transfer = Transfer {
+            tx_buf: tx.as_ptr() as u64,
+            rx_buf: rx.as_mut_ptr() as u64,
+            len: tx.len() as u32,
+            speed_hz: self.speed_hz,
+            bits_per_word: self.bits_per_word,
+            ..Default::default()
+        };
+        self.ioctl(MESSAGE_1, &transfer)?;
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_spidev_uapi_layout() {
+        assert_eq!(size_of::<Transfer>(), 32);
+        // SPI_IOC_WR_MODE, SPI_IOC_WR_BITS_PER_WORD, SPI_IOC_WR_MAX_SPEED_HZ and
+        // SPI_IOC_MESSAGE(1) from linux/spi/spidev.h.
+        assert_eq!(WR_MODE, 0x4001_6b01);
+        assert_eq!(WR_BITS_PER_WORD, 0x4001_6b03);
+        assert_eq!(WR_MAX_SPEED_HZ, 0x4004_6b04);
+        assert_eq!(MESSAGE_1, 0x4020_6b00);
+    }
+}
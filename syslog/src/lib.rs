@@ -0,0 +1,434 @@
+mod message;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use edge_core::audit;
+use edge_core::completions::{self, Shell};
+use edge_core::exit::{self, Code};
+use edge_core::health;
+use edge_core::man;
+use edge_core::mqtt::{MqttClient, MqttOptions};
+use edge_core::output::OutputArgs;
+use edge_core::secret;
+use edge_core::shutdown::{self, Summary};
+use edge_core::writes::{self, Write};
+use edge_core::OrExit;
+use regex::Regex;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+use message::{Severity, SyslogMessage};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = edge_core::exit::EXIT_CODES)]
+pub struct Args {
+    /// Address to listen on.
+    #[clap(short, long, env = "EDGE_SYSLOG_LISTEN", action)]
+    listen: Option<String>,
+
+    #[clap(short, long, action)]
+    protocol: Option<ListenProtocol>,
+
+    // Filters
+    /// Only show messages at this severity or more severe.
+    #[clap(short = 's', long, action)]
+    min_severity: Option<Severity>,
+    /// Only show these facilities (kern, daemon, local0, ...). Repeatable.
+    #[clap(short, long, action)]
+    facility: Vec<String>,
+    /// Only show messages from hosts containing this string.
+    #[clap(long, action)]
+    host: Option<String>,
+    /// Only show messages matching this regular expression.
+    #[clap(short, long, action)]
+    grep: Option<String>,
+
+    /// Print messages as received instead of reformatting them.
+    #[clap(short, long, action)]
+    raw: bool,
+    #[clap(flatten)]
+    output: OutputArgs,
+
+    // Forwarding
+    #[clap(long, env = "EDGE_NATS_URL", action)]
+    nats_url: Option<String>,
+    /// Subject for forwarded messages; {host}, {facility}, {severity} and {app} are substituted.
+    #[clap(long, action)]
+    nats_subject: Option<String>,
+    #[clap(long, env = "EDGE_MQTT_BROKER", action)]
+    mqtt_broker: Option<String>,
+    /// Topic for forwarded messages, same substitutions as the NATS subject.
+    #[clap(long, action)]
+    mqtt_topic: Option<String>,
+    #[clap(long, env = "EDGE_MQTT_USER", action)]
+    mqtt_username: Option<String>,
+    #[clap(long, env = "EDGE_MQTT_PASSWORD", hide_env_values = true, action)]
+    mqtt_password: Option<String>,
+    #[clap(
+        long,
+        env = "EDGE_MQTT_PASSWORD_FILE",
+        conflicts_with = "mqtt-password",
+        action
+    )]
+    mqtt_password_file: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Subcommands>,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Check that the listener running on --listen takes connections (TCP) or datagrams (UDP)
+    /// and exit 0 if it does, for container and systemd health probes.
+    Healthcheck {
+        /// Give up after this long, e.g. 2s; 5s by default.
+        #[clap(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+
+    /// Print a completion script for bash, zsh or fish.
+    Completions {
+        #[clap(value_parser)]
+        shell: Shell,
+    },
+
+    /// Write man pages for this tool and its subcommands into a directory.
+    #[clap(hide = true)]
+    GenMan {
+        #[clap(value_parser)]
+        directory: Option<PathBuf>,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ListenProtocol {
+    Udp,
+    Tcp,
+    Both,
+}
+
+struct Filter {
+    min_severity: Option<Severity>,
+    facilities: Vec<String>,
+    host: Option<String>,
+    grep: Option<Regex>,
+}
+
+impl Filter {
+    fn matches(&self, message: &SyslogMessage) -> bool {
+        if let Some(min) = self.min_severity {
+            // Severities are ordered from emerg down to debug.
+            if message.severity > min {
+                return false;
+            }
+        }
+        if !self.facilities.is_empty()
+            && !self
+                .facilities
+                .iter()
+                .any(|f| f.eq_ignore_ascii_case(message.facility_name()))
+        {
+            return false;
+        }
+        if let Some(host) = &self.host {
+            if !message.host().contains(host.as_str()) {
+                return false;
+            }
+        }
+        if let Some(grep) = &self.grep {
+            if !grep.is_match(&message.raw) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub async fn run(cli: Args) {
+    if let Some(Subcommands::Completions { shell }) = cli.command {
+        completions::print::<Args>(shell, "syslog");
+        return;
+    }
+    if let Some(Subcommands::GenMan { directory }) = &cli.command {
+        man::generate::<Args>("syslog", directory.as_deref());
+        return;
+    }
+
+    let listen = cli.listen.as_deref().unwrap_or("0.0.0.0:514");
+    let listen = match listen.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(err) => exit::fatal_with(
+            Code::Usage,
+            format!("Unable to parse listen address {listen}: {err}"),
+        ),
+    };
+    let protocol = cli.protocol.unwrap_or(ListenProtocol::Both);
+    let out = cli.output.load();
+    if let Some(Subcommands::Healthcheck { timeout }) = cli.command {
+        let mut target = listen;
+        if target.ip().is_unspecified() {
+            target.set_ip(match target {
+                SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        health::check(&out, &target.to_string(), timeout, probe(target, protocol)).await;
+    }
+
+    let grep = match cli.grep.as_deref().map(Regex::new).transpose() {
+        Ok(grep) => grep,
+        Err(err) => exit::fatal_with(Code::Usage, format!("Invalid --grep expression: {err}")),
+    };
+    let filter = Filter {
+        min_severity: cli.min_severity,
+        facilities: cli.facility,
+        host: cli.host,
+        grep,
+    };
+
+    let nats = match &cli.nats_url {
+        Some(url) => match async_nats::connect(url.as_str()).await {
+            Ok(client) => Some(client),
+            Err(err) => exit::fatal_error("Unable to connect to nats", &err),
+        },
+        None => None,
+    };
+    let nats_subject = cli
+        .nats_subject
+        .unwrap_or_else(|| "syslog.{host}.{severity}".to_string());
+
+    let mqtt_password = match &cli.mqtt_password_file {
+        Some(path) => Some(
+            secret::read_file(path).or_exit_with(Code::Usage, "Unable to read the MQTT password"),
+        ),
+        None => cli.mqtt_password,
+    };
+    let mut mqtt = cli.mqtt_broker.map(|address| {
+        MqttClient::new(MqttOptions {
+            address,
+            client_id: format!("edge-syslog-{}", std::process::id()),
+            username: cli.mqtt_username,
+            password: mqtt_password,
+            keep_alive: Duration::from_secs(60),
+        })
+    });
+    let mqtt_topic = cli
+        .mqtt_topic
+        .unwrap_or_else(|| "syslog/{host}/{severity}".to_string());
+
+    let (sender, mut receiver) = mpsc::channel(1024);
+    if protocol != ListenProtocol::Tcp {
+        let sender = sender.clone();
+        let socket = match UdpSocket::bind(listen).await {
+            Ok(socket) => socket,
+            Err(err) => exit::fatal_error(format!("Unable to bind UDP {listen}"), &err),
+        };
+        tokio::spawn(listen_udp(socket, sender));
+    }
+    if protocol != ListenProtocol::Udp {
+        let listener = match TcpListener::bind(listen).await {
+            Ok(listener) => listener,
+            Err(err) => exit::fatal_error(format!("Unable to bind TCP {listen}"), &err),
+        };
+        tokio::spawn(listen_tcp(listener, sender.clone()));
+    }
+    drop(sender);
+    log::info!("Listening for syslog on {listen}");
+
+    let keep_alive = mqtt
+        .as_ref()
+        .map(|m| m.keep_alive() / 2)
+        .unwrap_or(Duration::from_secs(30));
+    let mut ping = tokio::time::interval(keep_alive);
+
+    let summary = Summary::new(&["messages", "filtered"]);
+    shutdown::listen();
+    loop {
+        let message = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = shutdown::requested() => break,
+            _ = ping.tick() => {
+                if let Some(mqtt) = mqtt.as_mut() {
+                    if let Err(err) = mqtt.ping().await {
+                        log::warn!("MQTT ping failed: {err}");
+                    }
+                }
+                continue;
+            }
+        };
+
+        summary.count("messages");
+        if !filter.matches(&message) {
+            summary.count("filtered");
+            continue;
+        }
+
+        out.record(&message.to_record(), || {
+            if cli.raw {
+                println!("{}", message.raw);
+            } else {
+                println!("{message}");
+            }
+        });
+
+        if let Some(nats) = &nats {
+            let subject = expand(&nats_subject, &message, &['.', ' ', '*', '>']);
+            let (write, value) = (
+                Write::Nats(&subject),
+                audit::payload(message.raw.as_bytes()),
+            );
+            let published = match writes::check(&write, &value) {
+                Ok(()) => {
+                    let result = nats
+                        .publish(subject.clone(), message.raw.clone().into())
+                        .await;
+                    audit::record(&write, &value, &result);
+                    result.map_err(|err| err.to_string())
+                }
+                Err(err) => Err(err.to_string()),
+            };
+            if let Err(err) = published {
+                summary.error();
+                log::warn!("Unable to forward to nats: {err}");
+            }
+        }
+        if let Some(mqtt) = mqtt.as_mut() {
+            let topic = expand(&mqtt_topic, &message, &['/', '+', '#']);
+            if let Err(err) = mqtt.publish(&topic, message.raw.as_bytes()).await {
+                summary.error();
+                log::warn!("Unable to forward to mqtt: {err}");
+            }
+        }
+    }
+    // Forwarded messages may still be buffered.
+    if let Some(nats) = &nats {
+        if let Err(err) = nats.flush().await {
+            log::warn!("Unable to flush nats: {err}");
+        }
+    }
+    summary.print();
+}
+
+/// Connects to the listener at `address`, or for UDP alone sends it an empty datagram. UDP
+/// has no handshake, so a datagram counts as taken unless the host answers that nothing
+/// listens on the port.
+async fn probe(address: SocketAddr, protocol: ListenProtocol) -> std::io::Result<String> {
+    if protocol != ListenProtocol::Udp {
+        TcpStream::connect(address).await?;
+        return Ok("accepted a TCP connection".to_string());
+    }
+    let local: SocketAddr = match address {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(address).await?;
+    socket.send(&[]).await?;
+    let mut answer = [0; 1];
+    match tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut answer)).await {
+        Ok(Err(err)) => Err(err),
+        _ => Ok("took a UDP datagram".to_string()),
+    }
+}
+
+async fn listen_udp(socket: UdpSocket, sender: mpsc::Sender<SyslogMessage>) {
+    let mut buffer = vec![0u8; 65535];
+    loop {
+        let (len, source) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(err) => {
+                log::warn!("UDP receive failed: {err}");
+                continue;
+            }
+        };
+        // Empty datagrams are healthcheck probes, not messages.
+        if len == 0 {
+            continue;
+        }
+        let raw = String::from_utf8_lossy(&buffer[..len]);
+        if sender
+            .send(SyslogMessage::parse(&raw, source))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+async fn listen_tcp(listener: TcpListener, sender: mpsc::Sender<SyslogMessage>) {
+    loop {
+        let (stream, source) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log::warn!("TCP accept failed: {err}");
+                continue;
+            }
+        };
+        log::debug!("Accepted syslog connection from {source}");
+
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(err) = read_tcp_stream(stream, source, sender).await {
+                log::warn!("Syslog connection from {source} failed: {err}");
+            }
+        });
+    }
+}
+
+/// Reads both RFC 6587 framings: octet counting (`LEN SP MSG`) and newline delimited.
+async fn read_tcp_stream(
+    stream: tokio::net::TcpStream,
+    source: SocketAddr,
+    sender: mpsc::Sender<SyslogMessage>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let buffer = reader.fill_buf().await?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let raw = if buffer[0].is_ascii_digit() {
+            let mut length = Vec::new();
+            reader.read_until(b' ', &mut length).await?;
+            let length: usize = String::from_utf8_lossy(&length).trim().parse()?;
+            let mut message = vec![0u8; length];
+            reader.read_exact(&mut message).await?;
+            message
+        } else {
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line).await?;
+            line
+        };
+
+        let raw = String::from_utf8_lossy(&raw);
+        if raw.trim().is_empty() {
+            continue;
+        }
+        if sender
+            .send(SyslogMessage::parse(&raw, source))
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Substitutes message fields into a subject/topic template, replacing separator characters
+/// so a dotted hostname doesn't add subject tokens.
+fn expand(template: &str, message: &SyslogMessage, separators: &[char]) -> String {
+    let clean = |value: &str| value.replace(separators, "_");
+    template
+        .replace("{host}", &clean(&message.host()))
+        .replace("{facility}", message.facility_name())
+        .replace("{severity}", message.severity.name())
+        .replace("{app}", &clean(message.app_name.as_deref().unwrap_or("-")))
+}
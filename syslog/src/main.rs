@@ -0,0 +1,5 @@
+#[tokio::main]
+async fn main() {
+    syslog::run(edge_core::logging::parse("syslog")).await;
+    edge_core::expect::finish();
+}
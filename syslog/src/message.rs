@@ -0,0 +1,384 @@
+use clap::ValueEnum;
+use edge_core::output::Record;
+use std::fmt;
+use std::net::SocketAddr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Severity {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl Severity {
+    fn from_code(code: u8) -> Severity {
+        match code {
+            0 => Severity::Emerg,
+            1 => Severity::Alert,
+            2 => Severity::Crit,
+            3 => Severity::Err,
+            4 => Severity::Warning,
+            5 => Severity::Notice,
+            6 => Severity::Info,
+            _ => Severity::Debug,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Emerg => "emerg",
+            Severity::Alert => "alert",
+            Severity::Crit => "crit",
+            Severity::Err => "err",
+            Severity::Warning => "warning",
+            Severity::Notice => "notice",
+            Severity::Info => "info",
+            Severity::Debug => "debug",
+        }
+    }
+}
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+pub fn facility_name(facility: u8) -> &'static str {
+    FACILITIES
+        .get(facility as usize)
+        .copied()
+        .unwrap_or("unknown")
+}
+
+/// A syslog message in either RFC 3164 (BSD) or RFC 5424 format.
+///
+/// Network gear is notoriously loose with both, so every header field is optional and whatever
+/// can't be parsed ends up in `message`.
+pub struct SyslogMessage {
+    pub source: SocketAddr,
+    pub raw: String,
+    pub facility: u8,
+    pub severity: Severity,
+    pub timestamp: Option<String>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub msg_id: Option<String>,
+    pub structured_data: Option<String>,
+    pub message: String,
+}
+
+impl SyslogMessage {
+    pub fn parse(raw: &str, source: SocketAddr) -> SyslogMessage {
+        let raw = raw.trim_end_matches(['\r', '\n', '\0']).to_string();
+
+        // Messages without a PRI are user.notice according to RFC 3164.
+        let (pri, rest) = parse_pri(&raw).unwrap_or((13, raw.as_str()));
+        let mut message = SyslogMessage {
+            source,
+            raw: raw.clone(),
+            facility: pri / 8,
+            severity: Severity::from_code(pri % 8),
+            timestamp: None,
+            hostname: None,
+            app_name: None,
+            proc_id: None,
+            msg_id: None,
+            structured_data: None,
+            message: String::new(),
+        };
+
+        match rest.strip_prefix("1 ") {
+            Some(rest) => message.parse_rfc5424(rest),
+            None => message.parse_rfc3164(rest),
+        }
+        message
+    }
+
+    fn parse_rfc5424(&mut self, rest: &str) {
+        let mut fields = rest.splitn(6, ' ');
+        self.timestamp = nil_value(fields.next());
+        self.hostname = nil_value(fields.next());
+        self.app_name = nil_value(fields.next());
+        self.proc_id = nil_value(fields.next());
+        self.msg_id = nil_value(fields.next());
+
+        let rest = fields.next().unwrap_or("");
+        let (structured_data, msg) = split_structured_data(rest);
+        self.structured_data = structured_data;
+        // Strip the UTF-8 BOM the RFC allows in front of the message.
+        self.message = msg.trim_start_matches('\u{feff}').to_string();
+    }
+
+    fn parse_rfc3164(&mut self, rest: &str) {
+        let mut rest = rest;
+
+        // "Mmm dd hh:mm:ss" is always 15 characters, with the day padded by a space.
+        if rest.len() >= 16 && rest.is_char_boundary(15) && looks_like_bsd_timestamp(&rest[..15]) {
+            self.timestamp = Some(rest[..15].to_string());
+            rest = rest[15..].trim_start();
+
+            if let Some((hostname, after)) = rest.split_once(' ') {
+                // A bare tag ("sshd[12]:") in the hostname position means no hostname was sent.
+                if !hostname.ends_with(':') {
+                    self.hostname = Some(hostname.to_string());
+                    rest = after;
+                }
+            }
+        }
+
+        // TAG is alphanumeric up to 32 chars, optionally followed by [pid], then a colon.
+        if let Some((tag, msg)) = rest.split_once(':') {
+            if !tag.is_empty() && tag.len() <= 48 && !tag.contains(' ') {
+                match tag.split_once('[') {
+                    Some((app, pid)) => {
+                        self.app_name = Some(app.to_string());
+                        self.proc_id = Some(pid.trim_end_matches(']').to_string());
+                    }
+                    None => self.app_name = Some(tag.to_string()),
+                }
+                self.message = msg.trim_start().to_string();
+                return;
+            }
+        }
+        self.message = rest.to_string();
+    }
+
+    pub fn facility_name(&self) -> &'static str {
+        facility_name(self.facility)
+    }
+
+    /// Hostname from the header, or the sender's IP when the device didn't include one. The
+    /// header's timestamp is the `source_timestamp`, apart from the time received that
+    /// `--timestamps` adds.
+    pub fn to_record(&self) -> Record {
+        Record::new()
+            .field("source", self.source.to_string())
+            .field("facility", self.facility_name())
+            .field("severity", self.severity.name())
+            .field("source_timestamp", self.timestamp.as_deref())
+            .field("hostname", self.hostname.as_deref())
+            .field("app_name", self.app_name.as_deref())
+            .field("proc_id", self.proc_id.as_deref())
+            .field("msg_id", self.msg_id.as_deref())
+            .field("structured_data", self.structured_data.as_deref())
+            .field("message", self.message.as_str())
+            .field("raw", self.raw.as_str())
+    }
+
+    pub fn host(&self) -> String {
+        match &self.hostname {
+            Some(hostname) => hostname.clone(),
+            None => self.source.ip().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SyslogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timestamp = self.timestamp.as_deref().unwrap_or("-");
+        write!(
+            f,
+            "{} {} {}.{}",
+            timestamp,
+            self.host(),
+            self.facility_name(),
+            self.severity.name()
+        )?;
+        if let Some(app) = &self.app_name {
+            write!(f, " {app}")?;
+            if let Some(pid) = &self.proc_id {
+                write!(f, "[{pid}]")?;
+            }
+        }
+        if let Some(msg_id) = &self.msg_id {
+            write!(f, " ({msg_id})")?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(sd) = &self.structured_data {
+            write!(f, " {sd}")?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_pri(raw: &str) -> Option<(u8, &str)> {
+    let rest = raw.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    if end == 0 || end > 3 {
+        return None;
+    }
+    let pri: u8 = rest[..end].parse().ok()?;
+    if pri > 191 {
+        return None;
+    }
+    Some((pri, &rest[end + 1..]))
+}
+
+fn nil_value(field: Option<&str>) -> Option<String> {
+    match field {
+        Some("-") | None => None,
+        Some(value) => Some(value.to_string()),
+    }
+}
+
+fn looks_like_bsd_timestamp(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes[3] == b' ' && bytes[6] == b' ' && bytes[9] == b':' && bytes[12] == b':'
+}
+
+/// Splits `[id k="v"][id2 ...] message` into the structured data and the message.
+fn split_structured_data(rest: &str) -> (Option<String>, &str) {
+    if let Some(msg) = rest.strip_prefix("- ") {
+        return (None, msg);
+    }
+    if rest == "-" {
+        return (None, "");
+    }
+    if !rest.starts_with('[') {
+        return (None, rest);
+    }
+
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut in_quotes = false;
+    for (i, c) in rest.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '[' if !in_quotes => depth += 1,
+            ']' if !in_quotes => depth -= 1,
+            ' ' if depth == 0 && !in_quotes => {
+                return (Some(rest[..i].to_string()), &rest[i + 1..]);
+            }
+            _ => {}
+        }
+    }
+    (Some(rest.to_string()), "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> SyslogMessage {
+        SyslogMessage::parse(raw, "192.0.2.1:514".parse().unwrap())
+    }
+
+    #[test]
+    fn parses_rfc_3164_messages() {
+        let message = parse("<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick\n");
+        assert_eq!(message.facility_name(), "auth");
+        assert_eq!(message.severity, Severity::Crit);
+        assert_eq!(message.timestamp.as_deref(), Some("Oct 11 22:14:15"));
+        assert_eq!(message.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.proc_id, None);
+        assert_eq!(message.message, "'su root' failed for lonvick");
+        assert_eq!(
+            message.raw,
+            "<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick"
+        );
+
+        let message = parse("<13>Feb  5 17:32:18 sshd[1234]: Accepted publickey");
+        assert_eq!(message.timestamp.as_deref(), Some("Feb  5 17:32:18"));
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name.as_deref(), Some("sshd"));
+        assert_eq!(message.proc_id.as_deref(), Some("1234"));
+        assert_eq!(message.host(), "192.0.2.1");
+    }
+
+    #[test]
+    fn keeps_what_it_cant_parse_in_the_message() {
+        // No PRI: user.notice.
+        let message = parse("link down on port 3");
+        assert_eq!((message.facility, message.severity), (1, Severity::Notice));
+        assert_eq!(message.message, "link down on port 3");
+        assert_eq!(message.app_name, None);
+
+        // Out of range PRI.
+        let message = parse("<192>hello");
+        assert_eq!(message.message, "<192>hello");
+
+        let message = parse("<14>Oct 11 22:14:15 host a message: with a colon");
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.message, "a message: with a colon");
+    }
+
+    #[test]
+    fn parses_rfc_5424_messages() {
+        let message = parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"] \u{feff}An application event",
+        );
+        assert_eq!(message.facility_name(), "local4");
+        assert_eq!(message.severity, Severity::Notice);
+        assert_eq!(
+            message.timestamp.as_deref(),
+            Some("2003-10-11T22:14:15.003Z")
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.proc_id, None);
+        assert_eq!(message.msg_id.as_deref(), Some("ID47"));
+        assert_eq!(
+            message.structured_data.as_deref(),
+            Some("[exampleSDID@32473 iut=\"3\" eventSource=\"Application\"]")
+        );
+        assert_eq!(message.message, "An application event");
+
+        let message = parse("<34>1 2003-10-11T22:14:15.003Z host su - ID47 - 'su root' failed");
+        assert_eq!(message.structured_data, None);
+        assert_eq!(message.message, "'su root' failed");
+    }
+
+    #[test]
+    fn splits_structured_data_with_escapes_and_spaces() {
+        let (sd, message) = split_structured_data(r#"[a x="] [\"y"][b] rest of it"#);
+        assert_eq!(sd.as_deref(), Some(r#"[a x="] [\"y"][b]"#));
+        assert_eq!(message, "rest of it");
+
+        assert_eq!(split_structured_data("-"), (None, ""));
+        assert_eq!(split_structured_data("[a]"), (Some("[a]".to_string()), ""));
+    }
+
+    #[test]
+    fn prints_messages_on_one_line() {
+        let message = parse("<11>1 - - app 42 - - it broke");
+        assert_eq!(
+            message.to_string(),
+            "- 192.0.2.1 user.err app[42]: it broke"
+        );
+    }
+}